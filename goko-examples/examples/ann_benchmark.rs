@@ -0,0 +1,96 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! Downloads a benchmark dataset, builds a tree on it and measures the recall of its queries
+//! against the ground truth. The dataset is the first argument, `siftsmall` by default:
+//! `cargo run --release -p goko-examples --features datasets --example ann_benchmark -- sift`.
+
+use goko::CoverTreeBuilder;
+use pointcloud::loaders::*;
+use pointcloud::*;
+use std::env;
+use std::sync::Arc;
+use std::time;
+
+fn main() {
+    let name = env::args()
+        .nth(1)
+        .unwrap_or_else(|| "siftsmall".to_string());
+    let dataset = BenchmarkDataset::from_name(&name).unwrap_or_else(|| {
+        panic!(
+            "unknown dataset {:?}, expected one of {:?}",
+            name,
+            BenchmarkDataset::ALL
+                .iter()
+                .map(|d| d.name())
+                .collect::<Vec<&str>>()
+        )
+    });
+    let start = time::Instant::now();
+    let prepared = prepare_dataset(dataset).unwrap();
+    println!(
+        "Prepared {} points of dimension {} in {:?}, config at {:?}",
+        prepared.count,
+        prepared.dim,
+        start.elapsed(),
+        prepared.config
+    );
+
+    let point_cloud = ram_from_yaml::<_, L2>(&prepared.config).unwrap();
+    let start = time::Instant::now();
+    let mut builder = CoverTreeBuilder::new();
+    builder.set_leaf_cutoff(10).set_min_res_index(-20);
+    let writer = builder.build(Arc::new(point_cloud)).unwrap();
+    let reader = writer.reader();
+    println!(
+        "Built a tree with {} nodes in {:?}",
+        reader.node_count(),
+        start.elapsed()
+    );
+
+    let (queries, ground_truth) = match (&prepared.queries, &prepared.ground_truth) {
+        (Some(queries), Some(ground_truth)) => (queries, ground_truth),
+        _ => {
+            println!("{} has no queries to measure the recall with", name);
+            return;
+        }
+    };
+    let queries = ram_from_f32_file::<_, L2>(queries, prepared.dim).unwrap();
+    let ground_truth = ground_truth_from_ivecs(ground_truth, None).unwrap();
+    let k = 10;
+    let start = time::Instant::now();
+    let results: Vec<Vec<usize>> = (0..queries.len())
+        .map(|i| {
+            let query = queries.point(i).unwrap();
+            reader
+                .knn(&query, k)
+                .unwrap()
+                .iter()
+                .map(|(_, pi)| *pi)
+                .collect()
+        })
+        .collect();
+    println!(
+        "Ran {} queries in {:?}, recall at {} is {:.4}",
+        queries.len(),
+        start.elapsed(),
+        k,
+        recall_at_k(&results, &ground_truth, k)
+    );
+}
@@ -0,0 +1,74 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! Trains on a day of made up logs from three services, then streams more of their logs through
+//! the trackers. Halfway through the stream the `db` service has an incident.
+
+use goko_examples::log_anomaly::*;
+use goko_examples::synthetic::LogGenerator;
+use std::sync::Arc;
+use std::time;
+
+fn main() {
+    let config = PipelineConfig::default();
+    let mut generator = LogGenerator::new(0);
+    let training: Vec<String> = (0..20_000).map(|_| generator.normal()).collect();
+
+    let start = time::Instant::now();
+    let model = LogModel::train(training.iter().map(|l| l.as_str()), &config).unwrap();
+    println!(
+        "Trained on {} lines in {:?}, the tree has {} nodes",
+        training.len(),
+        start.elapsed(),
+        model.reader().node_count()
+    );
+
+    let mut registry = TrackerRegistry::new(Arc::new(model), config.alert_threshold);
+    let sources = ["web", "worker", "db"];
+    let stream_len = 3000;
+    let start = time::Instant::now();
+    for i in 0..stream_len {
+        for source in &sources {
+            let incident = *source == "db" && i >= stream_len / 2;
+            let line = generator.line(if incident { 0.3 } else { 0.0 });
+            if let Some(alert) = registry.push(source, &line, Some(i as u64)).unwrap() {
+                println!(
+                    "ALERT {} at event {}: {:.1} standard deviations above the baseline, latest line `{}`",
+                    alert.source,
+                    i,
+                    alert.score,
+                    line
+                );
+            }
+        }
+    }
+    println!(
+        "Streamed {} lines in {:?}",
+        stream_len * sources.len(),
+        start.elapsed()
+    );
+    for source in &sources {
+        println!(
+            "{}: score {:.2}, stats {:?}",
+            source,
+            registry.score(source).unwrap(),
+            registry.stats(source).unwrap()
+        );
+    }
+}
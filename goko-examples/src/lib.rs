@@ -0,0 +1,37 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! # Goko examples
+//!
+//! Complete pipelines built out of goko and pointcloud, kept in a crate of their own so they're
+//! compiled and tested with the rest of the workspace rather than rotting in a notebook.
+//!
+//! * [`log_anomaly`]: hashes log lines into sparse vectors, builds a tree on a sample of normal
+//!   logs, trains a KL divergence baseline and then tracks a stream of logs per source, raising
+//!   an alert when a source's recent logs stop looking like the training set. Run it with
+//!   `cargo run -p goko-examples --example log_anomaly`.
+//! * `ann_benchmark`: downloads SIFT, GloVe or covertype with pointcloud's `datasets` feature,
+//!   builds a tree on it and measures the recall of the dataset's queries. Run it with
+//!   `cargo run --release -p goko-examples --features datasets --example ann_benchmark -- siftsmall`.
+
+#![deny(warnings)]
+#![warn(missing_docs)]
+
+pub mod log_anomaly;
+pub mod synthetic;
@@ -0,0 +1,268 @@
+//! # Log anomaly detection
+//!
+//! The pipeline has two halves. Training, [`LogModel::train`]:
+//!
+//! 1. Each log line is hashed into a sparse vector with a
+//!    [`HashingVectorizer`](pointcloud::hashing::HashingVectorizer). Numbers are masked, so lines
+//!    that differ only by a timestamp or an id land on the same point.
+//! 2. A cover tree is built on the vectors, with the Dirichlet plugin attached so every node
+//!    knows how often the training set passes through each of its children.
+//! 3. A [`KLDivergenceBaseline`] is trained by tracking windows of the training set. This is
+//!    how much KL divergence a window of normal logs racks up.
+//!
+//! Streaming, [`TrackerRegistry::push`]: each source, like a host or a service, gets its own
+//! [`BayesCategoricalTracker`] over its most recent lines. After every line the tracker's total
+//! KL divergence is compared to the baseline for a window of the same length. When it's more
+//! than the threshold's worth of standard deviations above the baseline's mean an [`Alert`] is
+//! raised. Sources are alerted on once per excursion above the threshold, not on every line.
+
+use goko::plugins::discrete::prelude::*;
+use goko::*;
+use pointcloud::data_sources::SparseDataRam;
+use pointcloud::hashing::HashingVectorizer;
+use pointcloud::points::SparseRef;
+use pointcloud::L2;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// The sparse cloud the log lines are hashed into.
+pub type LogCloud = SparseDataRam<f32, u32, L2>;
+
+/// The settings of the whole pipeline.
+#[derive(Debug, Clone)]
+pub struct PipelineConfig {
+    /// The number of columns lines are hashed into, default `2^16`
+    pub dim: usize,
+    /// Hashes runs of up to this many tokens, default 2
+    pub ngrams: usize,
+    /// The tree's settings, the default is a scale base of 1.5 and a leaf cutoff of 10
+    pub tree: BuilderConfig,
+    /// The number of recent lines each source's tracker looks at, default 200
+    pub window_size: usize,
+    /// The number of training windows the baseline is made of, default 16
+    pub num_sequences: usize,
+    /// How many standard deviations above the baseline a source has to be to alert, default 4
+    pub alert_threshold: f64,
+}
+
+impl Default for PipelineConfig {
+    fn default() -> PipelineConfig {
+        PipelineConfig {
+            dim: 1 << 16,
+            ngrams: 2,
+            tree: BuilderConfig {
+                scale_base: Some(1.5),
+                leaf_cutoff: Some(10),
+                rng_seed: Some(0),
+                ..Default::default()
+            },
+            window_size: 200,
+            num_sequences: 16,
+            alert_threshold: 4.0,
+        }
+    }
+}
+
+/// A tree of normal log lines and the baseline the trackers are compared to.
+pub struct LogModel {
+    vectorizer: HashingVectorizer,
+    tree: CoverTreeWriter<LogCloud>,
+    baseline: KLDivergenceBaseline,
+    window_size: usize,
+}
+
+impl LogModel {
+    /// Hashes the lines, builds the tree and trains the baseline, see the [module docs](self).
+    pub fn train<'a, I>(lines: I, config: &PipelineConfig) -> GokoResult<LogModel>
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let mut vectorizer = HashingVectorizer::new(config.dim);
+        vectorizer.set_ngrams(config.ngrams);
+        let point_cloud = vectorizer.transform_all(lines, L2 {})?;
+
+        let builder = CoverTreeBuilder::from_config(&config.tree);
+        let mut tree = builder.build(Arc::new(point_cloud))?;
+        tree.add_plugin::<GokoDirichlet>(GokoDirichlet::default());
+        tree.refresh();
+
+        let mut baseline = DirichletBaseline::default();
+        baseline.set_sequence_len(config.window_size);
+        baseline.set_num_sequences(config.num_sequences);
+        baseline.set_sample_rate((config.window_size / 20).max(1));
+        let baseline = baseline.train(tree.reader())?;
+
+        Ok(LogModel {
+            vectorizer,
+            tree,
+            baseline,
+            window_size: config.window_size,
+        })
+    }
+
+    /// A reader of the tree.
+    pub fn reader(&self) -> CoverTreeReader<LogCloud> {
+        self.tree.reader()
+    }
+
+    /// The vectorizer the lines are hashed with.
+    pub fn vectorizer(&self) -> &HashingVectorizer {
+        &self.vectorizer
+    }
+
+    /// The baseline the trackers are compared to.
+    pub fn baseline(&self) -> &KLDivergenceBaseline {
+        &self.baseline
+    }
+
+    /// The path of a log line down the tree.
+    pub fn path(
+        &self,
+        reader: &CoverTreeReader<LogCloud>,
+        line: &str,
+    ) -> GokoResult<Vec<(f32, NodeAddress)>> {
+        let (indexes, values) = self.vectorizer.transform(line);
+        let point = SparseRef::new(self.vectorizer.dim(), &values, &indexes);
+        reader.path(&point)
+    }
+
+    /// How unusual a window is, the number of standard deviations its total KL divergence is
+    /// above the baseline's mean for windows of the same length.
+    pub fn score(&self, stats: &KLDivergenceStats) -> f64 {
+        let (mean, var) = self.baseline.stats(stats.sequence_len).moment1_nz;
+        (stats.moment1_nz - mean) / var.max(f64::EPSILON).sqrt()
+    }
+}
+
+/// A source whose recent lines stopped looking like the training set.
+#[derive(Debug)]
+pub struct Alert {
+    /// The source that alerted
+    pub source: String,
+    /// See [`LogModel::score`]
+    pub score: f64,
+    /// The stats of the source's window when it alerted
+    pub stats: KLDivergenceStats,
+}
+
+struct SourceTracker {
+    tracker: BayesCategoricalTracker<LogCloud>,
+    alerting: bool,
+}
+
+/// The trackers of each log source, by name. A source's tracker is made on its first line.
+pub struct TrackerRegistry {
+    model: Arc<LogModel>,
+    reader: CoverTreeReader<LogCloud>,
+    trackers: HashMap<String, SourceTracker>,
+    alert_threshold: f64,
+    warmup: usize,
+}
+
+impl TrackerRegistry {
+    /// An empty registry, alerting at `alert_threshold` standard deviations.
+    pub fn new(model: Arc<LogModel>, alert_threshold: f64) -> TrackerRegistry {
+        let reader = model.reader();
+        let warmup = model.window_size / 4;
+        TrackerRegistry {
+            model,
+            reader,
+            trackers: HashMap::new(),
+            alert_threshold,
+            warmup,
+        }
+    }
+
+    /// The number of lines a source has to send before it can alert, default a quarter of the
+    /// window. The baseline is noisy for very short windows.
+    pub fn set_warmup(&mut self, warmup: usize) {
+        self.warmup = warmup;
+    }
+
+    /// Adds a line to the source's tracker. Returns an alert if this line pushed the source
+    /// above the threshold.
+    pub fn push(
+        &mut self,
+        source: &str,
+        line: &str,
+        id: Option<EventId>,
+    ) -> GokoResult<Option<Alert>> {
+        let path = self.model.path(&self.reader, line)?;
+        let (reader, window_size) = (&self.reader, self.model.window_size);
+        let source_tracker =
+            self.trackers
+                .entry(source.to_string())
+                .or_insert_with(|| SourceTracker {
+                    tracker: BayesCategoricalTracker::new(window_size, reader.clone()),
+                    alerting: false,
+                });
+        source_tracker.tracker.add_path_with_id(path, id);
+
+        let stats = source_tracker.tracker.kl_div_stats();
+        let score = self.model.score(&stats);
+        let above = stats.sequence_len >= self.warmup && score > self.alert_threshold;
+        let was_alerting = std::mem::replace(&mut source_tracker.alerting, above);
+        if above && !was_alerting {
+            Ok(Some(Alert {
+                source: source.to_string(),
+                score,
+                stats,
+            }))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// The sources that have sent a line.
+    pub fn sources(&self) -> impl Iterator<Item = &str> {
+        self.trackers.keys().map(|source| source.as_str())
+    }
+
+    /// The current score of a source, see [`LogModel::score`].
+    pub fn score(&self, source: &str) -> Option<f64> {
+        self.trackers
+            .get(source)
+            .map(|t| self.model.score(&t.tracker.kl_div_stats()))
+    }
+
+    /// The current stats of a source's window.
+    pub fn stats(&self, source: &str) -> Option<KLDivergenceStats> {
+        self.trackers.get(source).map(|t| t.tracker.kl_div_stats())
+    }
+
+    /// Drops a source's tracker, returns false if there wasn't one.
+    pub fn remove(&mut self, source: &str) -> bool {
+        self.trackers.remove(source).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::synthetic::LogGenerator;
+
+    #[test]
+    fn incidents_raise_alerts() {
+        let mut generator = LogGenerator::new(0);
+        let training: Vec<String> = (0..2000).map(|_| generator.normal()).collect();
+        let config = PipelineConfig {
+            window_size: 100,
+            ..Default::default()
+        };
+        let model = LogModel::train(training.iter().map(|l| l.as_str()), &config).unwrap();
+        let mut registry = TrackerRegistry::new(Arc::new(model), config.alert_threshold);
+
+        let mut alerts = Vec::new();
+        for i in 0..300 {
+            let quiet = generator.normal();
+            let noisy = generator.line(0.5);
+            alerts.extend(registry.push("web", &quiet, Some(i)).unwrap());
+            alerts.extend(registry.push("db", &noisy, Some(i)).unwrap());
+        }
+        assert!(alerts.iter().any(|alert| alert.source == "db"));
+        assert!(registry.score("db").unwrap() > registry.score("web").unwrap());
+        assert_eq!(registry.sources().count(), 2);
+        assert!(registry.remove("web"));
+        assert!(registry.stats("web").is_none());
+    }
+}
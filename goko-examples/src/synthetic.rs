@@ -0,0 +1,102 @@
+//! Made up logs for the examples and tests. Each line comes from a template whose `{}`s are
+//! filled with random numbers and whose `{w}`s are filled with one of the template's words, so
+//! there's some variety within each kind of line.
+
+use rand::prelude::*;
+use rand::rngs::SmallRng;
+
+type Template = (&'static str, &'static [&'static str]);
+
+/// The routine lines of a few services.
+const NORMAL_TEMPLATES: &[Template] = &[
+    (
+        "INFO http GET /api/{w}/{} 200 in {}ms",
+        &["users", "orders", "items", "carts"],
+    ),
+    (
+        "INFO http POST /api/{w} 201 in {}ms",
+        &["orders", "sessions", "reviews"],
+    ),
+    ("DEBUG cache {w} for key session:{}", &["hit", "miss"]),
+    (
+        "INFO worker {} finished {w} job {} in {}ms",
+        &["email", "billing", "thumbnail", "export"],
+    ),
+    (
+        "WARN db slow query took {}ms on table {w}",
+        &["orders", "users", "events"],
+    ),
+    (
+        "INFO auth user {} logged in from 10.0.{}.{} with {w}",
+        &["password", "sso", "token"],
+    ),
+];
+
+/// Lines that should be rare, an incident's worth of errors.
+const INCIDENT_TEMPLATES: &[Template] = &[
+    (
+        "ERROR db connection refused by {w} replica {} retrying in {}s",
+        &["primary", "secondary"],
+    ),
+    (
+        "ERROR kernel out of memory killed process {} ({w})",
+        &["java", "postgres", "nginx"],
+    ),
+    (
+        "CRIT disk /dev/sda{} failing smart check with {} reallocated sectors on {w}",
+        &["storage", "backup"],
+    ),
+];
+
+/// Generates log lines from a seeded rng, so runs are repeatable.
+#[derive(Debug, Clone)]
+pub struct LogGenerator {
+    rng: SmallRng,
+}
+
+impl LogGenerator {
+    /// A generator seeded with `seed`.
+    pub fn new(seed: u64) -> LogGenerator {
+        LogGenerator {
+            rng: SmallRng::seed_from_u64(seed),
+        }
+    }
+
+    /// A routine line.
+    pub fn normal(&mut self) -> String {
+        let template = NORMAL_TEMPLATES[self.rng.gen_range(0..NORMAL_TEMPLATES.len())];
+        self.fill(template)
+    }
+
+    /// An error from an incident.
+    pub fn incident(&mut self) -> String {
+        let template = INCIDENT_TEMPLATES[self.rng.gen_range(0..INCIDENT_TEMPLATES.len())];
+        self.fill(template)
+    }
+
+    /// An incident line with probability `incident_rate`, a routine line otherwise.
+    pub fn line(&mut self, incident_rate: f64) -> String {
+        if self.rng.gen_bool(incident_rate) {
+            self.incident()
+        } else {
+            self.normal()
+        }
+    }
+
+    fn fill(&mut self, (template, words): Template) -> String {
+        let mut line = String::with_capacity(template.len() + 16);
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            line.push_str(&rest[..start]);
+            if rest[start..].starts_with("{w}") {
+                line.push_str(words[self.rng.gen_range(0..words.len())]);
+                rest = &rest[start + 3..];
+            } else {
+                line.push_str(&self.rng.gen_range(0..10_000u32).to_string());
+                rest = &rest[start + 2..];
+            }
+        }
+        line.push_str(rest);
+        line
+    }
+}
@@ -0,0 +1,98 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! Progress reports and cancellation for long builds.
+//!
+//! A build on a large point cloud can run for hours. Give the [`crate::CoverTreeBuilder`] a
+//! callback with `set_progress_callback` and it's called from the thread running the build with
+//! a [`BuildProgress`] as nodes are written into the layers, at most once per progress interval
+//! and once more when the last node is in. A [`CancelToken`] passed with `set_cancel_token` can
+//! be cancelled from any thread, the build then stops its workers and returns
+//! `GokoError::BuildCancelled`.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The least time between two progress reports, unless the builder is given another.
+pub(crate) const DEFAULT_PROGRESS_INTERVAL: Duration = Duration::from_millis(200);
+
+/// How far along a build is.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BuildProgress {
+    /// The number of nodes written into the layers so far
+    pub inserted_nodes: usize,
+    /// The number of nodes created so far. This grows as the workers split nodes, so it's only
+    /// the final count once it equals `inserted_nodes`.
+    pub total_nodes: usize,
+    /// The number of points with a final address so far
+    pub indexed_points: usize,
+    /// The number of points in the point cloud
+    pub point_count: usize,
+    /// The scale index and the number of nodes inserted into each layer, from the bottom layer up
+    pub layer_counts: Vec<(i32, usize)>,
+    /// The time since the build started
+    pub elapsed: Duration,
+}
+
+impl BuildProgress {
+    /// The fraction of the points that have a final address, between 0 and 1.
+    pub fn fraction_indexed(&self) -> f32 {
+        if self.point_count == 0 {
+            1.0
+        } else {
+            self.indexed_points as f32 / self.point_count as f32
+        }
+    }
+}
+
+/// The callback a builder reports its progress to.
+#[derive(Clone)]
+pub(crate) struct ProgressCallback(pub(crate) Arc<dyn Fn(&BuildProgress) + Send + Sync>);
+
+impl fmt::Debug for ProgressCallback {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ProgressCallback")
+    }
+}
+
+/// Stops a running build. Clones share the same flag, keep one and hand one to the builder.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancelToken {
+    /// A token that isn't cancelled yet.
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    /// Asks the builds using this token to stop.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    /// If `cancel` was called on this token or one of its clones.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
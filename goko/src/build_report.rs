@@ -0,0 +1,90 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! Where the time of a build went.
+//!
+//! Every tree built with a [`crate::CoverTreeBuilder`] keeps a [`BuildReport`], reachable with
+//! [`CoverTreeWriter::build_report`](crate::CoverTreeWriter::build_report). The build is split
+//! into nesting, the worker threads splitting nodes into their children and singletons, and
+//! insertion, the finished nodes being written into the layers as they come in. Plugins attached
+//! afterwards add their own times, with the label and metadata summaries kept apart from the
+//! rest. Reports of builds with different parameters can be compared directly.
+
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// The time a plugin took to build the node components of the whole tree.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PluginTiming {
+    /// The type name of the plugin
+    pub name: String,
+    /// How long attaching it took
+    pub time: Duration,
+}
+
+/// The timings and size of a build.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BuildReport {
+    /// Creating the root and waiting on the worker threads to split nodes. Splitting runs in
+    /// parallel with the insertion, so this is the time the insertion was held up by it.
+    pub nesting: Duration,
+    /// Writing the finished nodes into the layers and recording the final addresses of the points
+    pub insertion: Duration,
+    /// Refreshing the layers once every node is in, and recounting the coverage or trimming a
+    /// tree that reached the memory limit
+    pub finishing: Duration,
+    /// Generating the label and metadata summaries
+    pub summaries: Duration,
+    /// Attaching the other plugins, in the order they were added
+    pub plugins: Vec<PluginTiming>,
+    /// The number of nodes the build created
+    pub nodes: usize,
+    /// The largest estimate of the tree's memory during the build, in bytes. This counts the
+    /// nodes and the final addresses of the points, not the point cloud.
+    pub peak_memory: usize,
+}
+
+impl BuildReport {
+    /// The time from the start of the build until the tree was ready, without the plugins.
+    pub fn build_time(&self) -> Duration {
+        self.nesting + self.insertion + self.finishing
+    }
+
+    /// The time spent attaching plugins, the summaries included.
+    pub fn plugin_time(&self) -> Duration {
+        self.plugins
+            .iter()
+            .fold(self.summaries, |total, plugin| total + plugin.time)
+    }
+
+    /// The build and plugin times together.
+    pub fn total_time(&self) -> Duration {
+        self.build_time() + self.plugin_time()
+    }
+
+    /// The nodes created per second of build time, 0 if the build took no measurable time.
+    pub fn nodes_per_second(&self) -> f64 {
+        let seconds = self.build_time().as_secs_f64();
+        if seconds > 0.0 {
+            self.nodes as f64 / seconds
+        } else {
+            0.0
+        }
+    }
+}
@@ -0,0 +1,201 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! Scores for how well the tree's partition at a scale matches an external clustering.
+//!
+//! The tree's partition comes from [`CoverTreeReader::assignments`]. The scores follow the usual
+//! definitions, the same ones scikit-learn uses, with the external labels as the classes and the
+//! tree's nodes as the clusters.
+
+use crate::errors::{GokoError, GokoResult};
+use crate::*;
+use std::collections::HashMap;
+
+/// How closely two clusterings of the same points agree.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClusterComparison {
+    /// The number of points that were compared
+    pub count: usize,
+    /// The adjusted Rand index, 1 for identical clusterings and around 0 for random ones
+    pub adjusted_rand_index: f32,
+    /// The mutual information normalized by the mean of the two entropies, between 0 and 1
+    pub normalized_mutual_information: f32,
+    /// 1 when every cluster only holds points of one class
+    pub homogeneity: f32,
+    /// 1 when all points of each class are in the same cluster
+    pub completeness: f32,
+}
+
+fn pairs(n: usize) -> f64 {
+    let n = n as f64;
+    n * (n - 1.0) / 2.0
+}
+
+fn entropy(counts: &HashMap<i64, usize>, total: f64) -> f64 {
+    counts
+        .values()
+        .map(|c| {
+            let p = *c as f64 / total;
+            -p * p.ln()
+        })
+        .sum()
+}
+
+/// Compares two clusterings of the same points, given as one cluster id per point. Points where
+/// either id is negative are left out. Both slices need to be the same length.
+pub fn compare_clusterings(classes: &[i64], clusters: &[i64]) -> ClusterComparison {
+    assert_eq!(classes.len(), clusters.len());
+    let mut contingency: HashMap<(i64, i64), usize> = HashMap::new();
+    let mut class_counts: HashMap<i64, usize> = HashMap::new();
+    let mut cluster_counts: HashMap<i64, usize> = HashMap::new();
+    let mut count = 0;
+    for (class, cluster) in classes.iter().zip(clusters) {
+        if *class < 0 || *cluster < 0 {
+            continue;
+        }
+        *contingency.entry((*class, *cluster)).or_insert(0) += 1;
+        *class_counts.entry(*class).or_insert(0) += 1;
+        *cluster_counts.entry(*cluster).or_insert(0) += 1;
+        count += 1;
+    }
+    // Comparing nothing, or one cluster against one class, is a perfect match
+    if class_counts.len() <= 1 && cluster_counts.len() <= 1 {
+        return ClusterComparison {
+            count,
+            adjusted_rand_index: 1.0,
+            normalized_mutual_information: 1.0,
+            homogeneity: 1.0,
+            completeness: 1.0,
+        };
+    }
+    let total = count as f64;
+
+    let index: f64 = contingency.values().map(|c| pairs(*c)).sum();
+    let class_pairs: f64 = class_counts.values().map(|c| pairs(*c)).sum();
+    let cluster_pairs: f64 = cluster_counts.values().map(|c| pairs(*c)).sum();
+    let expected_index = class_pairs * cluster_pairs / pairs(count);
+    let max_index = (class_pairs + cluster_pairs) / 2.0;
+    let adjusted_rand_index = if max_index == expected_index {
+        1.0
+    } else {
+        (index - expected_index) / (max_index - expected_index)
+    };
+
+    let class_entropy = entropy(&class_counts, total);
+    let cluster_entropy = entropy(&cluster_counts, total);
+    let mutual_information: f64 = contingency
+        .iter()
+        .map(|((class, cluster), c)| {
+            let c = *c as f64;
+            let outer = (class_counts[class] * cluster_counts[cluster]) as f64;
+            (c / total) * (c * total / outer).ln()
+        })
+        .sum::<f64>()
+        .max(0.0);
+    let ratio = |entropy: f64| {
+        if entropy == 0.0 {
+            1.0
+        } else {
+            (mutual_information / entropy).min(1.0)
+        }
+    };
+
+    ClusterComparison {
+        count,
+        adjusted_rand_index: adjusted_rand_index as f32,
+        normalized_mutual_information: ratio((class_entropy + cluster_entropy) / 2.0) as f32,
+        homogeneity: ratio(class_entropy) as f32,
+        completeness: ratio(cluster_entropy) as f32,
+    }
+}
+
+/// Compares the partition of the tree at a scale index to one label per point in the tree's point
+/// cloud. Negative labels are treated as unlabeled and are left out.
+pub fn compare_layer<D: PointCloud>(
+    reader: &CoverTreeReader<D>,
+    scale_index: i32,
+    labels: &[i64],
+) -> GokoResult<ClusterComparison> {
+    let points = reader.parameters().point_cloud.len();
+    if labels.len() != points {
+        return Err(GokoError::LabelCountMismatch {
+            points,
+            labels: labels.len(),
+        });
+    }
+    Ok(compare_clusterings(
+        labels,
+        &reader.assignments(scale_index),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::covertree::tests::build_basic_tree;
+
+    #[test]
+    fn identical_clusterings_score_one() {
+        // The cluster ids don't have to match, just the grouping
+        let scores = compare_clusterings(&[0, 0, 1, 1, 2, 2], &[5, 5, 3, 3, 9, 9]);
+        assert_eq!(scores.count, 6);
+        assert_approx_eq!(scores.adjusted_rand_index, 1.0);
+        assert_approx_eq!(scores.normalized_mutual_information, 1.0);
+        assert_approx_eq!(scores.homogeneity, 1.0);
+        assert_approx_eq!(scores.completeness, 1.0);
+    }
+
+    #[test]
+    fn matches_known_scores() {
+        // These match scikit-learn's scores for the same labels
+        let classes = [0, 0, 0, 1, 1, 1];
+        let clusters = [0, 0, 1, 1, 2, 2];
+        let scores = compare_clusterings(&classes, &clusters);
+        assert_approx_eq!(scores.adjusted_rand_index, 0.24242424, 1e-5);
+        assert_approx_eq!(scores.normalized_mutual_information, 0.51580374, 1e-5);
+        assert_approx_eq!(scores.homogeneity, 0.66666667, 1e-5);
+        assert_approx_eq!(scores.completeness, 0.42061983, 1e-5);
+    }
+
+    #[test]
+    fn splitting_classes_is_homogeneous() {
+        let scores = compare_clusterings(&[0, 0, 1, 1, -1], &[0, 1, 2, 3, 4]);
+        assert_eq!(scores.count, 4);
+        assert_approx_eq!(scores.homogeneity, 1.0);
+        assert!(scores.completeness < 1.0);
+    }
+
+    #[test]
+    fn compares_tree_layers() {
+        let tree = build_basic_tree();
+        let reader = tree.reader();
+        let labels = [0, 0, 0, 1, 1];
+        let root_scores = compare_layer(&reader, reader.root_address().0, &labels).unwrap();
+        assert_approx_eq!(root_scores.completeness, 1.0);
+        assert_approx_eq!(root_scores.adjusted_rand_index, 0.0);
+
+        // Lower layers split the points up, which can only make the nodes more homogeneous
+        let (bottom_scale, _) = reader.layers().last().unwrap();
+        let bottom_scores = compare_layer(&reader, bottom_scale, &labels).unwrap();
+        assert_eq!(bottom_scores.count, 5);
+        assert!(bottom_scores.homogeneity >= root_scores.homogeneity);
+
+        assert!(compare_layer(&reader, bottom_scale, &labels[..4]).is_err());
+    }
+}
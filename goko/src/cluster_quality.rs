@@ -0,0 +1,316 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! Internal clustering scores for each layer, to help pick the scale to cut the tree at.
+//!
+//! Cutting the tree at a scale index clusters the points by the node that covers them, see
+//! [`CoverTreeReader::assignments`]. Both scores measure clusters by their node's center, not their
+//! mean, so they work for any metric:
+//!
+//! * The silhouette of a point is `(b - a) / max(a, b)`, where `a` is the distance to its own
+//!   cluster's center and `b` is the distance to the nearest other center. This is the simplified
+//!   silhouette, the full one needs the distance between every pair of points. Points alone in
+//!   their cluster score 0.
+//! * The Davies-Bouldin index is the mean over clusters of `max_j (s_i + s_j) / d(c_i, c_j)`,
+//!   where `s_i` is the mean distance of cluster `i`'s points to its center. Lower is better.
+//!
+//! The nearest centers are found by walking down the tree and skipping every subtree whose radius
+//! rules it out, rather than by comparing against every other cluster.
+
+use crate::*;
+use rayon::iter::repeatn;
+use std::cell::Cell;
+use std::collections::HashMap;
+
+/// Clustering scores for the partition at one scale index.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClusterQuality {
+    /// The scale index the tree was cut at
+    pub scale_index: i32,
+    /// The number of clusters, nodes, at that cut
+    pub cluster_count: usize,
+    /// The mean simplified silhouette over all points, between -1 and 1. Higher is better. This
+    /// is 0 if there's only one cluster.
+    pub silhouette: f32,
+    /// The Davies-Bouldin index, lower is better. This is 0 if there's only one cluster.
+    pub davies_bouldin: f32,
+}
+
+/// The children of a node that are still at or above the scale index, and the node's radius.
+fn expand<D: PointCloud>(
+    reader: &CoverTreeReader<D>,
+    address: NodeAddress,
+    scale_index: i32,
+) -> (f32, Vec<NodeAddress>) {
+    reader
+        .get_node_and(address, |n| {
+            let children = match n.children() {
+                Some((nested_scale, children)) if nested_scale >= scale_index => {
+                    let mut children = children.to_vec();
+                    children.push((nested_scale, address.1));
+                    children
+                }
+                _ => Vec::new(),
+            };
+            (n.radius(), children)
+        })
+        .unwrap_or((0.0, Vec::new()))
+}
+
+/// Walks the nodes at or above the scale index, calling `visit` with the center and distance to
+/// the point of every node that isn't centered on `own`. Every one of those nodes is centered on
+/// a cluster's center. `bound` is given a subtree's distance and radius and returns `false` when
+/// nothing in it can matter anymore.
+fn walk_cut<D, V, B>(
+    reader: &CoverTreeReader<D>,
+    scale_index: i32,
+    point_index: usize,
+    own: usize,
+    mut visit: V,
+    bound: B,
+) -> GokoResult<()>
+where
+    D: PointCloud,
+    V: FnMut(usize, f32),
+    B: Fn(f32, f32) -> bool,
+{
+    let point_cloud = &reader.parameters().point_cloud;
+    let root = reader.root_address();
+    let root_dist = point_cloud.distances_to_point_index(point_index, &[root.1])?[0];
+    let mut stack = vec![(root, root_dist)];
+    while let Some((address, dist)) = stack.pop() {
+        if address.1 != own {
+            visit(address.1, dist);
+        }
+        let (radius, children) = expand(reader, address, scale_index);
+        if !children.is_empty() && bound(dist, radius) {
+            let centers: Vec<usize> = children.iter().map(|c| c.1).collect();
+            let dists = point_cloud.distances_to_point_index(point_index, &centers)?;
+            stack.extend(children.into_iter().zip(dists));
+        }
+    }
+    Ok(())
+}
+
+/// Scores the partition of the tree at a scale index.
+pub fn layer_quality<D: PointCloud>(
+    reader: &CoverTreeReader<D>,
+    scale_index: i32,
+) -> GokoResult<ClusterQuality> {
+    let point_cloud = reader.parameters().point_cloud.clone();
+    let point_indexes: Vec<usize> = (0..point_cloud.len()).collect();
+
+    // The cluster center of each point and its distance to it. Clusters are keyed by their
+    // center, like the assignments, as a node and the singletons of its parent can share one.
+    let indexes_iter = point_indexes.par_chunks(1000);
+    let reader_copies = indexes_iter.len();
+    let chunked: GokoResult<Vec<Vec<(usize, usize, f32)>>> = indexes_iter
+        .zip(repeatn(reader.clone(), reader_copies))
+        .map(|(chunk_indexes, reader)| {
+            let mut chunk = Vec::with_capacity(chunk_indexes.len());
+            for pi in chunk_indexes {
+                if let Some((_, center)) = reader.covering_node(*pi, scale_index) {
+                    let dist = point_cloud.distances_to_point_index(*pi, &[center])?[0];
+                    chunk.push((*pi, center, dist));
+                }
+            }
+            Ok(chunk)
+        })
+        .collect();
+    let members: Vec<(usize, usize, f32)> = chunked?.concat();
+
+    let mut clusters: HashMap<usize, (usize, f32)> = HashMap::new();
+    for (_, center, dist) in &members {
+        let entry = clusters.entry(*center).or_insert((0, 0.0));
+        entry.0 += 1;
+        entry.1 += dist;
+    }
+    let scatter: HashMap<usize, f32> = clusters
+        .iter()
+        .map(|(center, (count, total))| (*center, total / *count as f32))
+        .collect();
+    if clusters.len() < 2 {
+        return Ok(ClusterQuality {
+            scale_index,
+            cluster_count: clusters.len(),
+            silhouette: 0.0,
+            davies_bouldin: 0.0,
+        });
+    }
+
+    let members_iter = members.par_chunks(1000);
+    let reader_copies = members_iter.len();
+    let silhouettes: GokoResult<Vec<f32>> = members_iter
+        .zip(repeatn(reader.clone(), reader_copies))
+        .map(|(chunk, reader)| {
+            let mut chunk_sum = 0.0;
+            for (pi, own, a) in chunk {
+                if clusters[own].0 < 2 {
+                    continue;
+                }
+                let b = Cell::new(f32::INFINITY);
+                walk_cut(
+                    &reader,
+                    scale_index,
+                    *pi,
+                    *own,
+                    |_, dist| b.set(b.get().min(dist)),
+                    |dist, radius| dist - radius < b.get(),
+                )?;
+                let b = b.get();
+                let s = (b - a) / a.max(b);
+                if s.is_finite() {
+                    chunk_sum += s;
+                }
+            }
+            Ok(chunk_sum)
+        })
+        .collect();
+    let silhouette = silhouettes?.iter().sum::<f32>() / members.len() as f32;
+
+    let centers: Vec<usize> = clusters.keys().copied().collect();
+    let clusters_iter = centers.par_chunks(100);
+    let reader_copies = clusters_iter.len();
+    let worst_ratios: GokoResult<Vec<f32>> = clusters_iter
+        .zip(repeatn(reader.clone(), reader_copies))
+        .map(|(chunk, reader)| {
+            let mut chunk_sum = 0.0;
+            for own in chunk {
+                let own_scatter = scatter[own];
+                let worst = Cell::new(0.0f32);
+                // A cluster in a subtree is at least `dist - radius` away and its scatter is at
+                // most twice the subtree's radius
+                walk_cut(
+                    &reader,
+                    scale_index,
+                    *own,
+                    *own,
+                    |center, dist| {
+                        let ratio = (own_scatter + scatter.get(&center).unwrap_or(&0.0)) / dist;
+                        worst.set(worst.get().max(ratio));
+                    },
+                    |dist, radius| {
+                        dist <= radius
+                            || (own_scatter + 2.0 * radius) / (dist - radius) > worst.get()
+                    },
+                )?;
+                chunk_sum += worst.get();
+            }
+            Ok(chunk_sum)
+        })
+        .collect();
+    let davies_bouldin = worst_ratios?.iter().sum::<f32>() / clusters.len() as f32;
+
+    Ok(ClusterQuality {
+        scale_index,
+        cluster_count: clusters.len(),
+        silhouette,
+        davies_bouldin,
+    })
+}
+
+/// Scores the partition at every layer, from the root down.
+pub fn quality_by_layer<D: PointCloud>(
+    reader: &CoverTreeReader<D>,
+) -> GokoResult<Vec<ClusterQuality>> {
+    reader
+        .layers()
+        .map(|(scale_index, _)| layer_quality(reader, scale_index))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::covertree::tests::build_basic_tree;
+
+    /// Scores the cut by comparing every point against every cluster center
+    fn brute_force(
+        reader: &CoverTreeReader<DefaultLabeledCloud<L2>>,
+        scale_index: i32,
+    ) -> (f32, f32) {
+        let point_cloud = &reader.parameters().point_cloud;
+        let assignments: Vec<usize> = reader
+            .assignments(scale_index)
+            .iter()
+            .map(|c| *c as usize)
+            .collect();
+        let mut centers = assignments.clone();
+        centers.sort_unstable();
+        centers.dedup();
+        let dist = |i: usize, j: usize| point_cloud.distances_to_point_index(i, &[j]).unwrap()[0];
+        let scatter = |c: usize| {
+            let members: Vec<usize> = (0..point_cloud.len())
+                .filter(|pi| assignments[*pi] == c)
+                .collect();
+            members.iter().map(|pi| dist(*pi, c)).sum::<f32>() / members.len() as f32
+        };
+        let mut silhouette = 0.0;
+        for (pi, own) in assignments.iter().enumerate() {
+            if assignments.iter().filter(|a| *a == own).count() < 2 {
+                continue;
+            }
+            let a = dist(pi, *own);
+            let b = centers
+                .iter()
+                .filter(|c| *c != own)
+                .map(|c| dist(pi, *c))
+                .fold(f32::INFINITY, f32::min);
+            silhouette += (b - a) / a.max(b);
+        }
+        let mut davies_bouldin = 0.0;
+        for c in &centers {
+            davies_bouldin += centers
+                .iter()
+                .filter(|o| *o != c)
+                .map(|o| (scatter(*c) + scatter(*o)) / dist(*c, *o))
+                .fold(0.0, f32::max);
+        }
+        (
+            silhouette / point_cloud.len() as f32,
+            davies_bouldin / centers.len() as f32,
+        )
+    }
+
+    #[test]
+    fn matches_brute_force() {
+        let tree = build_basic_tree();
+        let reader = tree.reader();
+        let qualities = quality_by_layer(&reader).unwrap();
+        assert_eq!(qualities.len(), reader.layers().count());
+        for quality in qualities {
+            if quality.cluster_count < 2 {
+                assert_eq!(quality.silhouette, 0.0);
+                continue;
+            }
+            let (silhouette, davies_bouldin) = brute_force(&reader, quality.scale_index);
+            assert_approx_eq!(quality.silhouette, silhouette, 1e-4);
+            assert_approx_eq!(quality.davies_bouldin, davies_bouldin, 1e-4);
+        }
+    }
+
+    #[test]
+    fn root_is_one_cluster() {
+        let tree = build_basic_tree();
+        let reader = tree.reader();
+        let quality = layer_quality(&reader, reader.root_address().0).unwrap();
+        assert_eq!(quality.cluster_count, 1);
+        assert_eq!(quality.davies_bouldin, 0.0);
+    }
+}
@@ -20,7 +20,12 @@ use super::data_caches::*;
 use super::layer::*;
 use super::node::*;
 use super::*;
+use crate::build_progress::*;
+use crate::build_report::BuildReport;
+use crate::health::PublishLog;
+use crate::latency::LatencyStats;
 use crate::plugins::TreePluginSet;
+use crate::scheduler::PoolHandle;
 use crate::*;
 use pbr::ProgressBar;
 use rand::rngs::SmallRng;
@@ -32,12 +37,14 @@ use std::sync::{atomic, Arc, RwLock};
 use yaml_rust::YamlLoader;
 
 use crossbeam_channel::{unbounded, Receiver, Sender};
-use errors::GokoResult;
+use errors::{GokoError, GokoResult};
+use pointcloud::loaders::{read_config, ConfigFormat};
+use serde::{Deserialize, Serialize};
 
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 #[derive(Debug)]
-struct BuilderNode {
+pub(crate) struct BuilderNode {
     parent_address: Option<NodeAddress>,
     scale_index: i32,
     covered: CoveredData,
@@ -58,6 +65,14 @@ impl BuilderNode {
                 CoveredData::FirstCoveredData(FirstCoveredData::new::<D>(&parameters.point_cloud)?)
             }
         };
+        // Every point is under the root, so this catches missing values before they scramble the
+        // scales
+        if let Some(point) = covered.nan_distance() {
+            return Err(GokoError::NanDistance {
+                center: covered.center_index(),
+                point,
+            });
+        }
         let scale_index = (covered.max_distance()).log(parameters.scale_base).ceil() as i32;
         Ok(BuilderNode {
             parent_address: None,
@@ -66,6 +81,42 @@ impl BuilderNode {
         })
     }
 
+    /// A builder node that covers just the given points, for rebuilding part of an existing tree.
+    pub(crate) fn from_indexes<D: PointCloud>(
+        parameters: &CoverTreeParameters<D>,
+        parent_address: Option<NodeAddress>,
+        address: NodeAddress,
+        indexes: Vec<usize>,
+    ) -> GokoResult<BuilderNode> {
+        let covered = CoveredData::from_indexes(
+            parameters.partition_type,
+            address.1,
+            indexes,
+            &parameters.point_cloud,
+        )?;
+        Ok(BuilderNode {
+            parent_address,
+            scale_index: address.0,
+            covered,
+        })
+    }
+
+    /// Splits this node and all of its decendents on the current thread. Used when editing a
+    /// tree, where the subtrees are small and we want the nodes back in one piece.
+    pub(crate) fn split_subtree<D: PointCloud>(
+        self,
+        parameters: &Arc<CoverTreeParameters<D>>,
+    ) -> GokoResult<Vec<CoverNode<D>>> {
+        let mut unsplit_nodes = vec![self];
+        let mut nodes = Vec::new();
+        while let Some(builder_node) = unsplit_nodes.pop() {
+            let (new_node, new_nodes) = builder_node.split(parameters)?;
+            nodes.push(new_node);
+            unsplit_nodes.extend(new_nodes);
+        }
+        Ok(nodes)
+    }
+
     #[inline]
     fn address(&self) -> NodeAddress {
         (self.scale_index, self.covered.center_index())
@@ -75,19 +126,29 @@ impl BuilderNode {
         self,
         parameters: &Arc<CoverTreeParameters<D>>,
         node_sender: &Arc<Sender<NodeSplitResult<D>>>,
+        halt: &Arc<atomic::AtomicBool>,
     ) {
         let parameters = Arc::clone(parameters);
         let node_sender = Arc::clone(node_sender);
+        let halt = Arc::clone(halt);
         rayon::spawn(move || {
+            // The build was stopped, and the receiver may already be gone
+            if halt.load(atomic::Ordering::SeqCst) {
+                return;
+            }
             let (si, pi) = self.address();
             match self.split(&parameters) {
                 Ok((new_node, mut new_nodes)) => {
-                    node_sender.send(Ok((si, pi, new_node))).unwrap();
+                    if node_sender.send(Ok((si, pi, new_node))).is_err() {
+                        return;
+                    }
                     while let Some(node) = new_nodes.pop() {
-                        node.split_parallel(&parameters, &node_sender);
+                        node.split_parallel(&parameters, &node_sender, &halt);
                     }
                 }
-                Err(e) => node_sender.send(Err(e)).unwrap(),
+                Err(e) => {
+                    let _ = node_sender.send(Err(e));
+                }
             };
         });
     }
@@ -288,8 +349,51 @@ impl BuilderNode {
     }
 }
 
+/// The builder's settings as a serde struct, so they can be read from JSON, TOML or YAML. Unset
+/// fields keep the builder's defaults, and fields that aren't the builder's are ignored so the
+/// point cloud's config can share the file. See [`CoverTreeBuilder::from_config`].
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BuilderConfig {
+    /// See [`crate::covertree::CoverTreeParameters`] for docs
+    pub scale_base: Option<f32>,
+    /// See [`crate::covertree::CoverTreeParameters`] for docs
+    pub leaf_cutoff: Option<usize>,
+    /// See [`crate::covertree::CoverTreeParameters`] for docs
+    pub min_res_index: Option<i32>,
+    /// See [`crate::covertree::CoverTreeParameters`] for docs
+    pub use_singletons: Option<bool>,
+    /// `nearest` or `first`
+    pub partition_type: Option<PartitionType>,
+    /// See [`crate::covertree::CoverTreeParameters`] for docs
+    pub verbosity: Option<u32>,
+    /// See [`crate::covertree::CoverTreeParameters`] for docs
+    pub rng_seed: Option<u64>,
+    /// See [`CoverTreeBuilder::set_memory_limit`]
+    pub memory_limit: Option<usize>,
+    /// See [`CoverTreeBuilder::set_prune_chains`]
+    pub prune_chains: Option<bool>,
+}
+
+impl BuilderConfig {
+    /// Reads a JSON, TOML or YAML config file, picking the format from the extension.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> GokoResult<BuilderConfig> {
+        Ok(read_config(path)?)
+    }
+
+    /// Parses a JSON config.
+    pub fn from_json_str(contents: &str) -> GokoResult<BuilderConfig> {
+        Ok(ConfigFormat::Json.parse(contents, "<json>")?)
+    }
+
+    /// Parses a TOML config.
+    pub fn from_toml_str(contents: &str) -> GokoResult<BuilderConfig> {
+        Ok(ConfigFormat::Toml.parse(contents, "<toml>")?)
+    }
+}
+
 /// A construction object for a covertree. See [`crate::covertree::CoverTreeParameters`] for docs
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct CoverTreeBuilder {
     pub(crate) scale_base: f32,
     pub(crate) leaf_cutoff: usize,
@@ -298,6 +402,12 @@ pub struct CoverTreeBuilder {
     pub(crate) partition_type: PartitionType,
     pub(crate) verbosity: u32,
     pub(crate) rng_seed: Option<u64>,
+    pub(crate) memory_limit: Option<usize>,
+    pub(crate) prune_chains: bool,
+    pub(crate) thread_pool: Option<PoolHandle>,
+    pub(crate) progress_callback: Option<ProgressCallback>,
+    pub(crate) progress_interval: Option<Duration>,
+    pub(crate) cancel_token: Option<CancelToken>,
 }
 
 impl Default for CoverTreeBuilder {
@@ -310,6 +420,12 @@ impl Default for CoverTreeBuilder {
             partition_type: PartitionType::Nearest,
             verbosity: 0,
             rng_seed: None,
+            memory_limit: None,
+            prune_chains: false,
+            thread_pool: None,
+            progress_callback: None,
+            progress_interval: None,
+            cancel_token: None,
         }
     }
 }
@@ -325,6 +441,12 @@ impl CoverTreeBuilder {
             partition_type: PartitionType::Nearest,
             verbosity: 0,
             rng_seed: None,
+            memory_limit: None,
+            prune_chains: false,
+            thread_pool: None,
+            progress_callback: None,
+            progress_interval: None,
+            cancel_token: None,
         }
     }
 
@@ -345,10 +467,46 @@ impl CoverTreeBuilder {
             use_singletons: params["use_singletons"].as_bool().unwrap_or(true),
             partition_type,
             verbosity: params["verbosity"].as_i64().unwrap_or(2) as u32,
-            rng_seed: params["verbosity"].as_i64().map(|i| i as u64),
+            rng_seed: params["rng_seed"].as_i64().map(|i| i as u64),
+            memory_limit: params["memory_limit"].as_i64().map(|i| i as usize),
+            prune_chains: params["prune_chains"].as_bool().unwrap_or(false),
+            thread_pool: None,
+            progress_callback: None,
+            progress_interval: None,
+            cancel_token: None,
         }
     }
 
+    /// Creates a builder from a config, without touching the filesystem. Unset fields keep the
+    /// defaults of [`CoverTreeBuilder::new`].
+    pub fn from_config(config: &BuilderConfig) -> Self {
+        let mut builder = CoverTreeBuilder::new();
+        if let Some(x) = config.scale_base {
+            builder.scale_base = x;
+        }
+        if let Some(x) = config.leaf_cutoff {
+            builder.leaf_cutoff = x;
+        }
+        if let Some(x) = config.min_res_index {
+            builder.min_res_index = x;
+        }
+        if let Some(x) = config.use_singletons {
+            builder.use_singletons = x;
+        }
+        if let Some(x) = config.partition_type {
+            builder.partition_type = x;
+        }
+        if let Some(x) = config.verbosity {
+            builder.verbosity = x;
+        }
+        builder.rng_seed = config.rng_seed;
+        builder.memory_limit = config.memory_limit;
+        if let Some(x) = config.prune_chains {
+            builder.prune_chains = x;
+        }
+        builder
+    }
+
     /// See [`crate::covertree::CoverTreeParameters`] for docs
     pub fn set_scale_base(&mut self, x: f32) -> &mut Self {
         self.scale_base = x;
@@ -374,14 +532,76 @@ impl CoverTreeBuilder {
         self.verbosity = x;
         self
     }
-    /// See [`crate::covertree::CoverTreeParameters`] for docs
-    pub fn set_rng_seed(&mut self, x: u64) -> &mut Self {
+    /// Seeds every random choice of the build, so that two builds on the same data make the same
+    /// tree, whatever the thread pool and the order the nodes are split in. Trees saved from them
+    /// are byte for byte identical. Without a seed each build draws from the OS's entropy.
+    pub fn set_seed(&mut self, x: u64) -> &mut Self {
         self.rng_seed = Some(x);
         self
     }
+    /// Same as [`CoverTreeBuilder::set_seed`].
+    pub fn set_rng_seed(&mut self, x: u64) -> &mut Self {
+        self.set_seed(x)
+    }
+    /// Caps the estimated memory, in bytes, that the tree's nodes and point addresses may use
+    /// during the build. When the estimate passes this the build stops and the tree is trimmed
+    /// down to the nodes that were finished. See [`CoverTreeBuilder::build_partial`].
+    pub fn set_memory_limit(&mut self, x: usize) -> &mut Self {
+        self.memory_limit = Some(x);
+        self
+    }
+    /// Removes the nodes in the middle of chains of single children once the build is done, see
+    /// [`CoverTreeWriter::prune_chains`]. This cuts the depth and memory of trees on data with
+    /// large empty regions. Off by default, as the pruned tree skips scales along those chains.
+    pub fn set_prune_chains(&mut self, x: bool) -> &mut Self {
+        self.prune_chains = x;
+        self
+    }
+    /// Builds on a [`crate::scheduler::SharedPool`] instead of rayon's global pool. The build waits
+    /// for a slot on the shared pool and holds it until it's done.
+    pub fn set_thread_pool(&mut self, x: PoolHandle) -> &mut Self {
+        self.thread_pool = Some(x);
+        self
+    }
+    /// Calls `callback` with the progress of the build from the thread running it, at most once per
+    /// progress interval and once more when every node is in. See [`crate::build_progress`].
+    pub fn set_progress_callback<F>(&mut self, callback: F) -> &mut Self
+    where
+        F: Fn(&BuildProgress) + Send + Sync + 'static,
+    {
+        self.progress_callback = Some(ProgressCallback(Arc::new(callback)));
+        self
+    }
+    /// The least time between two calls of the progress callback, 200 milliseconds by default.
+    pub fn set_progress_interval(&mut self, x: Duration) -> &mut Self {
+        self.progress_interval = Some(x);
+        self
+    }
+    /// Stops the build with `GokoError::BuildCancelled` once the token is cancelled. The workers
+    /// finish the nodes they're splitting and no partial tree is kept.
+    pub fn set_cancel_token(&mut self, x: CancelToken) -> &mut Self {
+        self.cancel_token = Some(x);
+        self
+    }
     /// Pass a point cloud object when ready.
     /// To do, make this point cloud an Arc
+    ///
+    /// If a memory limit is set and reached this returns `GokoError::MemoryLimitReached`, use
+    /// [`CoverTreeBuilder::build_partial`] to keep the partial tree.
     pub fn build<D: PointCloud>(&self, point_cloud: Arc<D>) -> GokoResult<CoverTreeWriter<D>> {
+        match self.build_partial(point_cloud)? {
+            (cover_tree, None) => Ok(cover_tree),
+            (_, Some(e)) => Err(e),
+        }
+    }
+
+    /// Builds the tree, but if the memory limit is reached the usable partial tree is returned along
+    /// with a `GokoError::MemoryLimitReached` that reports how many points made it in. Points that were
+    /// not indexed have no final address in the partial tree.
+    pub fn build_partial<D: PointCloud>(
+        &self,
+        point_cloud: Arc<D>,
+    ) -> GokoResult<(CoverTreeWriter<D>, Option<GokoError>)> {
         let parameters = CoverTreeParameters {
             total_nodes: atomic::AtomicUsize::new(1),
             scale_base: self.scale_base,
@@ -393,8 +613,12 @@ impl CoverTreeBuilder {
             verbosity: self.verbosity,
             rng_seed: self.rng_seed,
             plugins: RwLock::new(TreePluginSet::new()),
+            latencies: LatencyStats::new(),
+            publishes: PublishLog::new(),
         };
 
+        let mut build_report = BuildReport::default();
+        let started = Instant::now();
         let root = BuilderNode::new(&parameters, self.partition_type)?;
         let root_address = root.address();
         let scale_range = root_address.0 - parameters.min_res_index;
@@ -411,7 +635,16 @@ impl CoverTreeBuilder {
 
         let node_sender = Arc::new(node_sender);
         let parameters = Arc::new(parameters);
-        root.split_parallel(&parameters, &node_sender);
+        let halt = Arc::new(atomic::AtomicBool::new(false));
+        // The slot is held until the build returns. Spawns from inside the shared pool stay on it,
+        // so only the root split needs to be started there.
+        let _slot = self.thread_pool.as_ref().map(|handle| handle.slot());
+        match &self.thread_pool {
+            Some(handle) => {
+                handle.install_unscheduled(|| root.split_parallel(&parameters, &node_sender, &halt))
+            }
+            None => root.split_parallel(&parameters, &node_sender, &halt),
+        }
         let mut pb = ProgressBar::new(1u64);
         if parameters.verbosity > 1 {
             pb.format("╢▌▌░╟");
@@ -424,17 +657,73 @@ impl CoverTreeBuilder {
             layers,
             root_address,
             final_addresses,
+            plugin_updaters: Vec::new(),
+            saved_plugins: Vec::new(),
+            maintenance_pool: self.thread_pool.clone(),
+            build_report: BuildReport::default(),
         };
 
+        // Both copies of the double buffered maps are counted
+        let address_size = 2 * (std::mem::size_of::<usize>() + std::mem::size_of::<NodeAddress>());
+        let mut estimated_memory: usize = 0;
+        let mut limit_reached = false;
         let mut inserted_nodes: usize = 0;
+        let mut indexed_points: usize = 0;
+        let mut layer_counts = vec![0; cover_tree.layers.len()];
+        let report_progress =
+            |inserted_nodes: usize, indexed_points: usize, layer_counts: &[usize]| {
+                if let Some(callback) = &self.progress_callback {
+                    (callback.0)(&BuildProgress {
+                        inserted_nodes,
+                        total_nodes: parameters.total_nodes.load(atomic::Ordering::SeqCst),
+                        indexed_points,
+                        point_count: parameters.point_cloud.len(),
+                        layer_counts: layer_counts
+                            .iter()
+                            .enumerate()
+                            .map(|(i, count)| (i as i32 + parameters.min_res_index - 1, *count))
+                            .collect(),
+                        elapsed: started.elapsed(),
+                    });
+                }
+            };
+        let progress_interval = self.progress_interval.unwrap_or(DEFAULT_PROGRESS_INTERVAL);
+        let mut last_report = Instant::now();
         let now = Instant::now();
+        build_report.nesting += started.elapsed();
         loop {
-            if let Ok(res) = node_receiver.recv() {
+            if self
+                .cancel_token
+                .as_ref()
+                .map_or(false, |t| t.is_cancelled())
+            {
+                halt.store(true, atomic::Ordering::SeqCst);
+                if parameters.verbosity > 0 {
+                    println!("The build was cancelled after {} nodes", inserted_nodes);
+                }
+                return Err(GokoError::BuildCancelled);
+            }
+            let waiting = Instant::now();
+            // Wake up now and then so that a cancelled build doesn't wait on the next node
+            let res = node_receiver.recv_timeout(Duration::from_millis(50));
+            build_report.nesting += waiting.elapsed();
+            if let Ok(res) = res {
+                let inserting = Instant::now();
                 let (scale_index, point_index, new_node) = res.unwrap();
-                for singleton in new_node.singletons() {
+                let leaf_address = if new_node.is_leaf() { 1 } else { 0 };
+                let new_node_points = new_node.singletons_len() + leaf_address;
+                estimated_memory += 2 * new_node.memory_estimate()
+                    + (new_node.singletons_len() + leaf_address) * address_size;
+                if let Some(limit) = self.memory_limit {
+                    if estimated_memory > limit {
+                        halt.store(true, atomic::Ordering::SeqCst);
+                        limit_reached = true;
+                    }
+                }
+                for singleton in new_node.singletons_iter() {
                     cover_tree
                         .final_addresses
-                        .insert(*singleton, (scale_index, point_index));
+                        .insert(singleton, (scale_index, point_index));
                 }
                 if new_node.is_leaf() {
                     cover_tree
@@ -445,22 +734,59 @@ impl CoverTreeBuilder {
                     cover_tree.insert_raw(scale_index, point_index, new_node);
                 }
                 inserted_nodes += 1;
+                indexed_points += new_node_points;
+                layer_counts[parameters.internal_index(scale_index)] += 1;
                 if parameters.verbosity > 1 {
                     pb.total = parameters.total_nodes.load(atomic::Ordering::SeqCst) as u64;
                     pb.inc();
                 }
+                build_report.insertion += inserting.elapsed();
+            }
+            if limit_reached {
+                break;
             }
             // Stop if there are enough done, and there are no more outstanding parameter references
             if inserted_nodes == parameters.total_nodes.load(atomic::Ordering::SeqCst) {
                 break;
             }
+            if last_report.elapsed() >= progress_interval {
+                report_progress(inserted_nodes, indexed_points, &layer_counts);
+                last_report = Instant::now();
+            }
         }
+        report_progress(inserted_nodes, indexed_points, &layer_counts);
         if parameters.verbosity > 1 {
             println!("\nWriting layers...");
         }
+        let finishing = Instant::now();
         cover_tree.refresh();
         cover_tree.final_addresses.refresh();
         cover_tree.final_addresses.refresh();
+        let mut error = None;
+        if limit_reached {
+            cover_tree.trim_unfinished();
+            let indexed_points = cover_tree.final_addresses.len();
+            if parameters.verbosity > 0 {
+                println!(
+                    "Reached the memory limit, stopped after indexing {} of {} points",
+                    indexed_points,
+                    parameters.point_cloud.len()
+                );
+            }
+            error = Some(GokoError::MemoryLimitReached {
+                limit: self.memory_limit.unwrap_or(0),
+                indexed_points,
+            });
+        } else {
+            cover_tree.recount_coverage();
+            if self.prune_chains {
+                cover_tree.prune_chains()?;
+            }
+        }
+        build_report.finishing = finishing.elapsed();
+        build_report.nodes = inserted_nodes;
+        build_report.peak_memory = estimated_memory;
+        cover_tree.build_report = build_report;
         if parameters.verbosity > 1 {
             println!(
                 "Finished building, took {:?} with {} per second",
@@ -468,13 +794,16 @@ impl CoverTreeBuilder {
                 (inserted_nodes as f32) / now.elapsed().as_secs_f32()
             );
         }
-        Ok(cover_tree)
+        Ok((cover_tree, error))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::scheduler::{SharedPool, TenantSettings};
+    use pointcloud::metrics::NanEuclidean;
+    use pointcloud::missing_values::NanPolicy;
     use std::{thread, time};
 
     pub fn create_test_parameters(
@@ -493,6 +822,8 @@ mod tests {
             verbosity: 0,
             rng_seed: Some(0),
             plugins: RwLock::new(TreePluginSet::new()),
+            latencies: LatencyStats::new(),
+            publishes: PublishLog::new(),
         })
     }
 
@@ -583,8 +914,9 @@ mod tests {
             Receiver<GokoResult<(i32, usize, CoverNode<DefaultCloud<L2>>)>>,
         ) = unbounded();
         let node_sender = Arc::new(node_sender);
+        let halt = Arc::new(atomic::AtomicBool::new(false));
 
-        build_node.split_parallel(&test_parameters, &node_sender);
+        build_node.split_parallel(&test_parameters, &node_sender, &halt);
         thread::sleep(time::Duration::from_millis(100));
         let split_count = test_parameters.total_nodes.load(atomic::Ordering::SeqCst) - 1;
         println!(
@@ -620,8 +952,9 @@ mod tests {
             Receiver<GokoResult<(i32, usize, CoverNode<DefaultCloud<L2>>)>>,
         ) = unbounded();
         let node_sender = Arc::new(node_sender);
+        let halt = Arc::new(atomic::AtomicBool::new(false));
 
-        build_node.split_parallel(&test_parameters, &node_sender);
+        build_node.split_parallel(&test_parameters, &node_sender, &halt);
         thread::sleep(time::Duration::from_millis(100));
         let split_count = test_parameters.total_nodes.load(atomic::Ordering::SeqCst) - 1;
         println!(
@@ -659,6 +992,12 @@ mod tests {
             verbosity: 0,
             partition_type: PartitionType::First,
             rng_seed: Some(0),
+            memory_limit: None,
+            prune_chains: false,
+            thread_pool: None,
+            progress_callback: None,
+            progress_interval: None,
+            cancel_token: None,
         };
         let tree = builder.build(point_cloud).unwrap();
         let reader = tree.reader();
@@ -703,6 +1042,12 @@ mod tests {
             verbosity: 0,
             partition_type: PartitionType::First,
             rng_seed: Some(0),
+            memory_limit: None,
+            prune_chains: false,
+            thread_pool: None,
+            progress_callback: None,
+            progress_interval: None,
+            cancel_token: None,
         };
         let tree = builder.build(point_cloud).unwrap();
         let reader = tree.reader();
@@ -711,4 +1056,192 @@ mod tests {
         assert!(reader.get_node_and((-2, 2), |n| n.is_leaf()).is_some());
         assert!(reader.no_dangling_refs());
     }
+
+    #[test]
+    fn memory_limit_leaves_usable_partial_tree() {
+        let data: Vec<f32> = (0..500).map(|_| rand::random::<f32>()).collect();
+        let point_cloud = Arc::new(DefaultCloud::<L2>::new(data, 1).unwrap());
+
+        let mut builder = CoverTreeBuilder::new();
+        builder
+            .set_min_res_index(-9)
+            .set_rng_seed(0)
+            .set_memory_limit(4096);
+        let (tree, error) = builder.build_partial(point_cloud).unwrap();
+        let reader = tree.reader();
+
+        let indexed_points = match error {
+            Some(GokoError::MemoryLimitReached {
+                limit,
+                indexed_points,
+            }) => {
+                assert_eq!(limit, 4096);
+                indexed_points
+            }
+            e => panic!("Expected to hit the memory limit, got {:?}", e),
+        };
+        assert!(indexed_points < 500);
+        assert!(reader.no_dangling_refs());
+        let root_coverage = reader
+            .get_node_and(reader.root_address(), |n| n.coverage_count())
+            .unwrap();
+        assert_eq!(root_coverage, indexed_points);
+        assert_eq!(reader.knn(&&[0.5f32][..], 1).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn build_report_accounts_for_the_build() {
+        use crate::plugins::discrete::prelude::GokoDirichlet;
+        let data: Vec<f32> = (0..500).map(|_| rand::random::<f32>()).collect();
+        let point_cloud = Arc::new(DefaultCloud::<L2>::new(data, 1).unwrap());
+
+        let mut builder = CoverTreeBuilder::new();
+        builder.set_min_res_index(-9).set_rng_seed(0);
+        let mut tree = builder.build(point_cloud).unwrap();
+        let report = tree.build_report().clone();
+        assert_eq!(report.nodes, tree.reader().node_count());
+        assert!(report.peak_memory > 0);
+        assert!(report.plugins.is_empty());
+        assert_eq!(report.total_time(), report.build_time());
+
+        tree.generate_summaries();
+        tree.add_plugin::<GokoDirichlet>(GokoDirichlet {});
+        let report = tree.build_report();
+        assert_eq!(report.plugins.len(), 1);
+        assert!(report.plugins[0].name.ends_with("GokoDirichlet"));
+        assert_eq!(
+            report.plugin_time(),
+            report.summaries + report.plugins[0].time
+        );
+    }
+
+    #[test]
+    fn missing_values_are_an_error_without_a_policy() {
+        let data = vec![0.0, 1.0, 0.5, f32::NAN, 0.25, 0.75];
+        let builder = CoverTreeBuilder::new();
+        let point_cloud = Arc::new(DefaultCloud::<L2>::new(data.clone(), 2).unwrap());
+        match builder.build(point_cloud) {
+            Err(GokoError::NanDistance { center, point }) => {
+                assert_eq!(center, 2);
+                assert_eq!(point, 1);
+            }
+            e => panic!("Expected a NaN distance error, got {:?}", e),
+        }
+
+        let point_cloud = DefaultCloud::<L2>::new(data.clone(), 2)
+            .unwrap()
+            .with_nan_policy(NanPolicy::ImputeMean)
+            .unwrap();
+        assert!(builder.build(Arc::new(point_cloud)).is_ok());
+
+        let point_cloud = DefaultCloud::<NanEuclidean>::new(data, 2)
+            .unwrap()
+            .with_nan_policy(NanPolicy::IgnorePairwise)
+            .unwrap();
+        let tree = builder.build(Arc::new(point_cloud)).unwrap();
+        assert_eq!(tree.reader().knn(&&[0.5, f32::NAN][..], 1).unwrap()[0].1, 1);
+    }
+
+    #[test]
+    fn builds_on_shared_pool() {
+        let data: Vec<f32> = (0..500).map(|_| rand::random::<f32>()).collect();
+        let point_cloud = Arc::new(DefaultCloud::<L2>::new(data, 1).unwrap());
+
+        let pool = SharedPool::new(2, 1).unwrap();
+        let mut builder = CoverTreeBuilder::new();
+        builder
+            .set_min_res_index(-9)
+            .set_rng_seed(0)
+            .set_thread_pool(PoolHandle::new(&pool, TenantSettings::default()));
+        let mut tree = builder.build(point_cloud).unwrap();
+        assert_eq!(pool.running_jobs(), 0);
+        // Maintenance runs on the same pool as background work
+        tree.generate_summaries();
+        assert_eq!(pool.running_jobs(), 0);
+        let reader = tree.reader();
+
+        assert!(reader.no_dangling_refs());
+        let root_coverage = reader
+            .get_node_and(reader.root_address(), |n| n.coverage_count())
+            .unwrap();
+        assert_eq!(root_coverage, 500);
+    }
+
+    #[test]
+    fn builder_from_config() {
+        let json = r#"{"scale_base": 1.5, "leaf_cutoff": 5, "partition_type": "first", "rng_seed": 3, "data_path": "a.dat"}"#;
+        let toml = "scale_base = 1.5\nleaf_cutoff = 5\npartition_type = \"first\"\nrng_seed = 3\n";
+        let config = BuilderConfig::from_json_str(json).unwrap();
+        assert_eq!(config, BuilderConfig::from_toml_str(toml).unwrap());
+        assert!(BuilderConfig::from_json_str(r#"{"leaf_cutoff": "five"}"#).is_err());
+
+        let builder = CoverTreeBuilder::from_config(&config);
+        assert_eq!(builder.scale_base, 1.5);
+        assert_eq!(builder.leaf_cutoff, 5);
+        assert_eq!(builder.min_res_index, -10);
+        assert_eq!(builder.partition_type, PartitionType::First);
+        assert_eq!(builder.rng_seed, Some(3));
+
+        let data: Vec<f32> = (0..100).map(|_| rand::random::<f32>()).collect();
+        let point_cloud = Arc::new(DefaultCloud::<L2>::new(data, 1).unwrap());
+        let tree = builder.build(point_cloud).unwrap();
+        assert!(tree.reader().no_dangling_refs());
+    }
+
+    #[test]
+    fn progress_is_reported_and_builds_can_be_cancelled() {
+        use std::sync::Mutex;
+
+        let data: Vec<f32> = (0..500).map(|_| rand::random::<f32>()).collect();
+        let point_cloud = Arc::new(DefaultCloud::<L2>::new(data, 1).unwrap());
+
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let reports_clone = Arc::clone(&reports);
+        let mut builder = CoverTreeBuilder::new();
+        builder
+            .set_min_res_index(-9)
+            .set_rng_seed(0)
+            .set_progress_callback(move |p| reports_clone.lock().unwrap().push(p.clone()));
+        let tree = builder.build(Arc::clone(&point_cloud)).unwrap();
+        let last = reports.lock().unwrap().last().cloned().unwrap();
+        assert_eq!(last.inserted_nodes, tree.build_report().nodes);
+        assert_eq!(last.total_nodes, last.inserted_nodes);
+        assert_eq!(last.point_count, 500);
+        assert_eq!(last.indexed_points, 500);
+        assert_eq!(last.fraction_indexed(), 1.0);
+        let layer_total: usize = last.layer_counts.iter().map(|(_, c)| c).sum();
+        assert_eq!(layer_total, last.inserted_nodes);
+
+        let token = CancelToken::new();
+        let callback_token = token.clone();
+        builder
+            .set_progress_interval(Duration::from_millis(0))
+            .set_progress_callback(move |_| callback_token.cancel())
+            .set_cancel_token(token.clone());
+        match builder.build(point_cloud) {
+            Err(GokoError::BuildCancelled) => (),
+            Err(e) => panic!("Expected the build to be cancelled, got {:?}", e),
+            Ok(_) => panic!("Expected the build to be cancelled"),
+        }
+        assert!(token.is_cancelled());
+    }
+
+    #[test]
+    fn seeded_builds_save_identical_trees() {
+        use protobuf::Message;
+
+        let data: Vec<f32> = (0..1000).map(|_| rand::random::<f32>()).collect();
+        let point_cloud = Arc::new(DefaultCloud::<L2>::new(data, 2).unwrap());
+
+        let mut builder = CoverTreeBuilder::new();
+        builder.set_min_res_index(-9).set_seed(7);
+        let first = builder.build(Arc::clone(&point_cloud)).unwrap();
+        let pool = SharedPool::new(4, 1).unwrap();
+        builder.set_thread_pool(PoolHandle::new(&pool, TenantSettings::default()));
+        let second = builder.build(point_cloud).unwrap();
+
+        let first_bytes = first.to_proto().write_to_bytes().unwrap();
+        let second_bytes = second.to_proto().write_to_bytes().unwrap();
+        assert_eq!(first_bytes, second_bytes);
+    }
 }
@@ -16,6 +16,7 @@
 * specific language governing permissions and limitations
 * under the License.
 */
+use super::PartitionType;
 use crate::errors::GokoResult;
 use pointcloud::*;
 use rand::rngs::SmallRng;
@@ -44,6 +45,19 @@ impl CoveredData {
         }
     }
 
+    /// The first covered point whose distance to the center is NaN.
+    pub(crate) fn nan_distance(&self) -> Option<usize> {
+        let (indexes, dists) = match &self {
+            Self::FirstCoveredData(a) => (&a.coverage, &a.dists),
+            Self::NearestCoveredData(a) => (&a.point_indexes, &a.center_dists),
+        };
+        indexes
+            .iter()
+            .zip(dists.iter())
+            .find(|(_, d)| d.is_nan())
+            .map(|(pi, _)| *pi)
+    }
+
     pub(crate) fn len(&self) -> usize {
         match self {
             Self::FirstCoveredData(a) => a.len(),
@@ -57,6 +71,25 @@ impl CoveredData {
             Self::NearestCoveredData(a) => a.center_index,
         }
     }
+
+    /// Covered data for a subset of the point cloud, used when rebuilding part of a tree.
+    pub(crate) fn from_indexes<D: PointCloud>(
+        partition_type: PartitionType,
+        center_index: usize,
+        indexes: Vec<usize>,
+        point_cloud: &Arc<D>,
+    ) -> GokoResult<CoveredData> {
+        Ok(match partition_type {
+            PartitionType::Nearest => CoveredData::NearestCoveredData(
+                NearestCoveredData::from_indexes(center_index, indexes, point_cloud)?,
+            ),
+            PartitionType::First => CoveredData::FirstCoveredData(FirstCoveredData::from_indexes(
+                center_index,
+                indexes,
+                point_cloud,
+            )?),
+        })
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -131,6 +164,14 @@ impl FirstCoveredData {
     pub(crate) fn new<D: PointCloud>(point_cloud: &Arc<D>) -> GokoResult<FirstCoveredData> {
         let mut coverage = point_cloud.reference_indexes();
         let center_index = coverage.pop().unwrap();
+        FirstCoveredData::from_indexes(center_index, coverage, point_cloud)
+    }
+
+    pub(crate) fn from_indexes<D: PointCloud>(
+        center_index: usize,
+        coverage: Vec<usize>,
+        point_cloud: &Arc<D>,
+    ) -> GokoResult<FirstCoveredData> {
         let dists = point_cloud.distances_to_point_index(center_index, &coverage)?;
         Ok(FirstCoveredData {
             dists,
@@ -189,6 +230,14 @@ impl NearestCoveredData {
     pub(crate) fn new<D: PointCloud>(point_cloud: &Arc<D>) -> GokoResult<NearestCoveredData> {
         let mut point_indexes = point_cloud.reference_indexes();
         let center_index = point_indexes.pop().unwrap();
+        NearestCoveredData::from_indexes(center_index, point_indexes, point_cloud)
+    }
+
+    pub(crate) fn from_indexes<D: PointCloud>(
+        center_index: usize,
+        point_indexes: Vec<usize>,
+        point_cloud: &Arc<D>,
+    ) -> GokoResult<NearestCoveredData> {
         let center_dists = point_cloud.distances_to_point_index(center_index, &point_indexes)?;
         let dists = vec![];
         let centers = vec![];
@@ -0,0 +1,1495 @@
+//! # Tree Editing
+//! Local edits to a built tree, so that a pathological region can be cleaned up without a full rebuild.
+//!
+//! Each edit queues its changes on the writer, refreshes the readers once the tree is valid again,
+//! then rebuilds the plugins of the edited nodes and their ancestors.
+
+use super::builders::BuilderNode;
+use super::layer::CoverLayerWriter;
+use super::node::*;
+use super::*;
+use crate::errors::{GokoError, GokoResult};
+use crate::health::PublishLog;
+use crate::latency::{LatencyStats, Operation};
+use crate::plugins::TreePluginSet;
+use crate::*;
+use pointcloud::partitions::PartitionedCloudMut;
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::iter;
+use std::mem;
+use std::sync::{atomic, Arc, RwLock};
+
+/// The parts of a node we need to plan an edit.
+struct NodeSnapshot {
+    parent_address: Option<NodeAddress>,
+    radius: f32,
+    coverage_count: usize,
+    children: Option<(i32, Vec<NodeAddress>)>,
+    singletons: Vec<usize>,
+}
+
+impl NodeSnapshot {
+    fn new<D: PointCloud>(
+        reader: &CoverTreeReader<D>,
+        address: NodeAddress,
+    ) -> GokoResult<NodeSnapshot> {
+        reader
+            .get_node_and(address, |n| NodeSnapshot {
+                parent_address: n.parent_address(),
+                radius: n.radius(),
+                coverage_count: n.coverage_count(),
+                children: n.children().map(|(ns, c)| (ns, Vec::from(c))),
+                singletons: n.singletons_decoded().into_owned(),
+            })
+            .ok_or(GokoError::NodeNotInTree(address))
+    }
+}
+
+/// The points covered by a node and the nodes of its subtree, the node itself included.
+fn subtree<D: PointCloud>(
+    reader: &CoverTreeReader<D>,
+    address: NodeAddress,
+) -> GokoResult<(Vec<usize>, Vec<NodeAddress>)> {
+    let mut covered = Vec::new();
+    let mut nodes = Vec::new();
+    let mut unvisited = vec![address];
+    while let Some(na) = unvisited.pop() {
+        reader
+            .get_node_and(na, |n| {
+                covered.extend(n.singletons_iter());
+                match n.children() {
+                    Some((nested_scale, children)) => {
+                        unvisited.push((nested_scale, na.1));
+                        unvisited.extend_from_slice(children);
+                    }
+                    None => covered.push(na.1),
+                }
+            })
+            .ok_or(GokoError::NodeNotInTree(na))?;
+        nodes.push(na);
+    }
+    Ok((covered, nodes))
+}
+
+/// The node and all of its ancestors, up to the root.
+fn ancestors<D: PointCloud>(reader: &CoverTreeReader<D>, address: NodeAddress) -> Vec<NodeAddress> {
+    let mut ancestors = vec![address];
+    let mut current = address;
+    while let Some(Some(parent)) = reader.get_node_and(current, |n| n.parent_address()) {
+        ancestors.push(parent);
+        current = parent;
+    }
+    ancestors
+}
+
+impl<D: PointCloud> CoverTreeWriter<D> {
+    /// Merges two sibling nodes into one. The routing children and singletons of the absorbed node are
+    /// handed to the kept node, and the kept node's radius is grown to cover them. Points past the kept
+    /// node's scale can't be covered by it, those are put back in the tree from the root, so a routing
+    /// child holding any is broken up. If one of the two is the nested child of their parent that one
+    /// is kept, otherwise `keep` is. Returns the address of the merged node.
+    pub fn merge_nodes(
+        &mut self,
+        keep: NodeAddress,
+        absorb: NodeAddress,
+    ) -> GokoResult<NodeAddress> {
+        if keep == absorb || keep.0 != absorb.0 {
+            return Err(GokoError::InvalidTreeEdit(
+                "only two distinct nodes on the same layer can be merged",
+            ));
+        }
+        let reader = self.reader();
+        let (mut keep, mut absorb) = (keep, absorb);
+        let mut keep_node = NodeSnapshot::new(&reader, keep)?;
+        let mut absorb_node = NodeSnapshot::new(&reader, absorb)?;
+        let parent_address = match (keep_node.parent_address, absorb_node.parent_address) {
+            (Some(a), Some(b)) if a == b => a,
+            _ => {
+                return Err(GokoError::InvalidTreeEdit(
+                    "only nodes with the same parent can be merged",
+                ))
+            }
+        };
+        // The nested child has to stay where it is, or the parent loses its center.
+        if absorb.1 == parent_address.1 {
+            mem::swap(&mut keep, &mut absorb);
+            mem::swap(&mut keep_node, &mut absorb_node);
+        }
+
+        // Everything handed over has to be within the kept node's scale, the kNN queries prune on it. A routing
+        // child with points past it is broken up, the points that fit become singletons of the kept node and the
+        // rest are put back in the tree from the root.
+        let point_cloud = Arc::clone(&self.parameters.point_cloud);
+        let scale = self.parameters.scale_base.powi(keep.0);
+        let mut radius = keep_node.radius;
+        let mut loose = absorb_node.singletons.clone();
+        let mut new_children: Vec<(NodeAddress, usize)> = Vec::new();
+        let mut dissolved = vec![absorb];
+        let mut nested_scale = None;
+        match &absorb_node.children {
+            None => loose.push(absorb.1),
+            Some((absorb_nested_scale, children)) => {
+                nested_scale = Some(*absorb_nested_scale);
+                for ca in iter::once(&(*absorb_nested_scale, absorb.1)).chain(children.iter()) {
+                    let (points, nodes) = subtree(&reader, *ca)?;
+                    let farthest = point_cloud
+                        .distances_to_point_index(keep.1, &points)?
+                        .into_iter()
+                        .fold(0.0, f32::max);
+                    if farthest <= scale {
+                        let coverage = reader
+                            .get_node_and(*ca, |n| n.coverage_count())
+                            .ok_or(GokoError::NodeNotInTree(*ca))?;
+                        radius = radius.max(farthest);
+                        new_children.push((*ca, coverage));
+                    } else {
+                        loose.extend(points);
+                        dissolved.extend(nodes);
+                    }
+                }
+            }
+        }
+        drop(reader);
+        let distances = point_cloud.distances_to_point_index(keep.1, &loose)?;
+        let mut new_singletons = Vec::new();
+        let mut misfits = Vec::new();
+        for (pi, d) in loose.into_iter().zip(distances) {
+            if d <= scale {
+                radius = radius.max(d);
+                new_singletons.push(pi);
+            } else {
+                misfits.push(pi);
+            }
+        }
+        let coverage = keep_node.coverage_count
+            + new_children.iter().map(|(_, c)| c).sum::<usize>()
+            + new_singletons
+                .iter()
+                .map(|pi| point_cloud.multiplicity(*pi))
+                .sum::<usize>();
+        let removed = misfits
+            .iter()
+            .map(|pi| point_cloud.multiplicity(*pi))
+            .sum::<usize>();
+
+        // A leaf needs a nested child before it can hold routing children.
+        let new_nested_scale = match (&keep_node.children, nested_scale) {
+            (None, Some(nested_scale)) if !new_children.is_empty() => Some(nested_scale),
+            _ => None,
+        };
+        if let Some(nested_scale) = new_nested_scale {
+            let mut nested_node = CoverNode::new(Some(keep), (nested_scale, keep.1));
+            nested_node.set_coverage_count(point_cloud.multiplicity(keep.1));
+            unsafe {
+                self.insert_raw(nested_scale, keep.1, nested_node);
+            }
+            self.final_addresses.insert(keep.1, (nested_scale, keep.1));
+            self.parameters
+                .total_nodes
+                .fetch_add(1, atomic::Ordering::SeqCst);
+        }
+        let update_singletons = new_singletons.clone();
+        let update_children = new_children.clone();
+        let touched = ancestors(&self.reader(), parent_address);
+        unsafe {
+            self.update_node(keep, move |n| {
+                if let Some(nested_scale) = new_nested_scale {
+                    n.insert_nested_child(nested_scale, 1).unwrap();
+                }
+                for (ca, c) in &update_children {
+                    n.insert_child(*ca, *c).unwrap();
+                }
+                n.insert_singletons(update_singletons.clone());
+                n.set_coverage_count(coverage);
+                n.set_radius(radius);
+            });
+            for (ca, _) in &new_children {
+                self.update_node(*ca, move |n| n.set_parent_address(Some(keep)));
+            }
+            self.update_node(parent_address, move |n| {
+                n.remove_child(absorb);
+            });
+            if removed > 0 {
+                for address in &touched {
+                    self.update_node(*address, move |n| {
+                        n.set_coverage_count(n.coverage_count() - removed)
+                    });
+                }
+            }
+            for na in &dissolved {
+                self.layer(na.0).remove_raw(na.1);
+            }
+        }
+        for pi in &new_singletons {
+            self.final_addresses.insert(*pi, keep);
+        }
+        self.parameters
+            .total_nodes
+            .fetch_sub(dissolved.len(), atomic::Ordering::SeqCst);
+
+        self.refresh();
+        self.final_addresses.refresh();
+
+        let mut touched = iter::once(keep).chain(touched).collect::<Vec<_>>();
+        if let Some(nested_scale) = new_nested_scale {
+            touched.push((nested_scale, keep.1));
+        }
+        touched.extend(self.attach_points(&misfits)?);
+        self.recompute_node_plugins(touched);
+        Ok(keep)
+    }
+
+    /// Re-clusters all the points covered by a node, replacing its subtree. Nodes that have collected too
+    /// many singletons or points can be broken up by passing a `leaf_cutoff` lower than the tree's. The
+    /// node keeps its address, so its parent is unchanged.
+    pub fn split_node(&mut self, address: NodeAddress, leaf_cutoff: usize) -> GokoResult<()> {
+        let reader = self.reader();
+        let node = NodeSnapshot::new(&reader, address)?;
+        if node.coverage_count < 2 {
+            return Err(GokoError::InvalidTreeEdit(
+                "a node covering a single point cannot be split",
+            ));
+        }
+
+        let (mut covered, mut old_nodes) = subtree(&reader, address)?;
+        old_nodes.retain(|na| *na != address);
+        covered.retain(|pi| *pi != address.1);
+
+        let parameters = self.subtree_parameters(leaf_cutoff);
+        let new_nodes =
+            BuilderNode::from_indexes(&parameters, node.parent_address, address, covered)?
+                .split_subtree(&parameters)?;
+
+        unsafe {
+            for na in &old_nodes {
+                self.layer(na.0).remove_raw(na.1);
+            }
+        }
+        let mut new_addresses = self.insert_subtree(new_nodes);
+        self.parameters
+            .total_nodes
+            .fetch_sub(old_nodes.len() + 1, atomic::Ordering::SeqCst);
+
+        self.refresh();
+        self.final_addresses.refresh();
+        self.recount_coverage();
+
+        new_addresses.extend(ancestors(&self.reader(), address));
+        self.recompute_node_plugins(new_addresses);
+        Ok(())
+    }
+
+    /// Removes a point from the tree. The point stays in the point cloud, but no node covers it
+    /// and it no longer has a final address. A singleton is simply dropped from its node. A point
+    /// that is the center of nodes takes their subtree with it, and the other points in that
+    /// subtree are re-clustered under the one closest to the removed point, which takes the
+    /// subtree's place in its parent. Points that are past the new center's scale are put back in
+    /// the tree from the root, as [`CoverTreeWriter::insert`] would. The coverage counts of the
+    /// ancestors are lowered, and the plugins of the new nodes and the ancestors are rebuilt.
+    ///
+    /// Together with [`CoverTreeWriter::insert`] this keeps a sliding window of a stream indexed.
+    /// Removing the center of the root moves the root, so get a new reader after that. A point
+    /// that stands for collapsed copies removes them with it, and the last point of a tree cannot
+    /// be removed.
+    pub fn remove(&mut self, point_index: usize) -> GokoResult<()> {
+        let final_address = self
+            .final_addresses
+            .get_and(&point_index, |a| *a)
+            .ok_or(GokoError::IndexNotInTree(point_index))?;
+        let multiplicity = self.parameters.point_cloud.multiplicity(point_index);
+        let reader = self.reader();
+
+        if final_address.1 != point_index {
+            let touched = ancestors(&reader, final_address);
+            drop(reader);
+            unsafe {
+                self.update_node(final_address, move |n| {
+                    n.remove_singleton(point_index);
+                });
+                for address in &touched {
+                    self.update_node(*address, move |n| {
+                        n.set_coverage_count(n.coverage_count() - multiplicity)
+                    });
+                }
+            }
+            self.final_addresses.remove(point_index);
+            self.refresh();
+            self.final_addresses.refresh();
+            self.recompute_node_plugins(touched);
+            return Ok(());
+        }
+
+        // The point is a center, find the top of its chain of nested nodes
+        let mut top = final_address;
+        let parent = loop {
+            let parent = reader
+                .get_node_and(top, |n| n.parent_address())
+                .ok_or(GokoError::NodeNotInTree(top))?;
+            match parent {
+                Some(parent) if parent.1 == point_index => top = parent,
+                _ => break parent,
+            }
+        };
+        let (mut covered, old_nodes) = subtree(&reader, top)?;
+        covered.retain(|pi| *pi != point_index);
+        let touched = match parent {
+            Some(parent) => ancestors(&reader, parent),
+            None => Vec::new(),
+        };
+        drop(reader);
+        if covered.is_empty() && parent.is_none() {
+            return Err(GokoError::InvalidTreeEdit(
+                "the last point of a tree cannot be removed",
+            ));
+        }
+
+        let mut misfits = Vec::new();
+        let new_top = if covered.is_empty() {
+            None
+        } else {
+            let distances = self
+                .parameters
+                .point_cloud
+                .distances_to_point_index(point_index, &covered)?;
+            let closest = distances
+                .iter()
+                .enumerate()
+                .min_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))
+                .map(|(i, _)| i)
+                .unwrap();
+            let center = covered.swap_remove(closest);
+            // Points that were within the old center's scale can be past the new center's, those are put back
+            // in the tree from the root once the subtree is rebuilt
+            let scale = self.parameters.scale_base.powi(top.0);
+            let distances = self
+                .parameters
+                .point_cloud
+                .distances_to_point_index(center, &covered)?;
+            let (fits, far): (Vec<(usize, f32)>, Vec<(usize, f32)>) = covered
+                .iter()
+                .copied()
+                .zip(distances)
+                .partition(|(_, d)| *d <= scale);
+            covered = fits.into_iter().map(|(pi, _)| pi).collect();
+            misfits = far.into_iter().map(|(pi, _)| pi).collect();
+            Some((top.0, center))
+        };
+        let removed = multiplicity
+            + misfits
+                .iter()
+                .map(|pi| self.parameters.point_cloud.multiplicity(*pi))
+                .sum::<usize>();
+
+        unsafe {
+            for na in &old_nodes {
+                self.layer(na.0).remove_raw(na.1);
+            }
+        }
+        let mut new_addresses = Vec::new();
+        if let Some(new_top) = new_top {
+            let parameters = self.subtree_parameters(self.parameters.leaf_cutoff);
+            let new_nodes = BuilderNode::from_indexes(&parameters, parent, new_top, covered)?
+                .split_subtree(&parameters)?;
+            new_addresses = self.insert_subtree(new_nodes);
+        }
+        self.parameters
+            .total_nodes
+            .fetch_sub(old_nodes.len(), atomic::Ordering::SeqCst);
+        match parent {
+            Some(parent) => unsafe {
+                self.update_node(parent, move |n| {
+                    n.remove_child(top);
+                    if let Some(new_top) = new_top {
+                        n.insert_child(new_top, 0).unwrap();
+                    }
+                });
+                for address in &touched {
+                    self.update_node(*address, move |n| {
+                        n.set_coverage_count(n.coverage_count() - removed)
+                    });
+                }
+            },
+            None => self.root_address = new_top.unwrap(),
+        }
+        self.final_addresses.remove(point_index);
+
+        self.refresh();
+        self.final_addresses.refresh();
+        new_addresses.extend(self.attach_points(&misfits)?);
+        self.recount_coverage();
+
+        new_addresses.extend(touched);
+        self.recompute_node_plugins(new_addresses);
+        Ok(())
+    }
+
+    /// Routes points of the point cloud that aren't in the tree down the path a query would take,
+    /// and attaches each as a singleton of the last node on it. If one is past the root's scale the
+    /// root is raised first. The coverage counts and radii of the nodes on the paths are updated.
+    /// Returns the nodes whose plugins have to be rebuilt.
+    fn attach_points(&mut self, indexes: &[usize]) -> GokoResult<Vec<NodeAddress>> {
+        let farthest = self
+            .parameters
+            .point_cloud
+            .distances_to_point_index(self.root_address.1, indexes)?
+            .into_iter()
+            .fold(0.0, f32::max);
+        let mut touched = self.raise_root(farthest)?;
+
+        let reader = self.reader();
+        let mut paths = Vec::with_capacity(indexes.len());
+        for pi in indexes {
+            let point = reader.parameters().point_cloud.point(*pi)?;
+            paths.push(reader.untimed_path(&point)?);
+        }
+        drop(reader);
+
+        for (pi, path) in indexes.iter().zip(paths) {
+            let (_, final_address) = *path.last().unwrap();
+            let pi = *pi;
+            let multiplicity = self.parameters.point_cloud.multiplicity(pi);
+            for (dist, address) in path {
+                unsafe {
+                    self.update_node(address, move |n| {
+                        if address == final_address {
+                            n.insert_singleton(pi);
+                            n.set_coverage_count(n.coverage_count() + multiplicity - 1);
+                        } else {
+                            n.set_coverage_count(n.coverage_count() + multiplicity);
+                        }
+                        if n.radius() < dist {
+                            n.set_radius(dist);
+                        }
+                    });
+                }
+                touched.push(address);
+            }
+            self.final_addresses.insert(pi, final_address);
+        }
+        self.refresh();
+        self.final_addresses.refresh();
+        Ok(touched)
+    }
+
+    /// Puts nodes centered on the root's center on top of the root, until the root's scale is at
+    /// least `dist`. The old root hangs off the new one through a chain of nested nodes, like the
+    /// roots in [`CoverTreeWriter::merge`]. Returns the new nodes.
+    fn raise_root(&mut self, dist: f32) -> GokoResult<Vec<NodeAddress>> {
+        let old_root = self.root_address;
+        let scale_base = self.parameters.scale_base;
+        let mut root_scale = old_root.0;
+        while scale_base.powi(root_scale) < dist {
+            root_scale += 1;
+        }
+        if root_scale == old_root.0 {
+            return Ok(Vec::new());
+        }
+        let (coverage, radius) = self
+            .reader()
+            .get_node_and(old_root, |n| (n.coverage_count(), n.radius()))
+            .ok_or(GokoError::NodeNotInTree(old_root))?;
+
+        while self.layers.len() as i32 + self.parameters.min_res_index - 1 <= root_scale {
+            let scale_index = self.layers.len() as i32 + self.parameters.min_res_index - 1;
+            self.layers.push(CoverLayerWriter::new(scale_index));
+        }
+        let mut chain = Vec::with_capacity((root_scale - old_root.0) as usize);
+        for scale_index in (old_root.0 + 1)..=root_scale {
+            let parent = if scale_index < root_scale {
+                Some((scale_index + 1, old_root.1))
+            } else {
+                None
+            };
+            let mut node = CoverNode::new(parent, (scale_index, old_root.1));
+            node.insert_nested_child(scale_index - 1, coverage)?;
+            node.set_radius(radius);
+            chain.push(node);
+        }
+        let parent = (old_root.0 + 1, old_root.1);
+        unsafe { self.update_node(old_root, move |n| n.set_parent_address(Some(parent))) };
+
+        let new_addresses = self.insert_subtree(chain);
+        self.root_address = (root_scale, old_root.1);
+        self.refresh();
+        Ok(new_addresses)
+    }
+
+    /// The parameters for rebuilding part of the tree with a different `leaf_cutoff`.
+    fn subtree_parameters(&self, leaf_cutoff: usize) -> Arc<CoverTreeParameters<D>> {
+        Arc::new(CoverTreeParameters {
+            total_nodes: atomic::AtomicUsize::new(1),
+            scale_base: self.parameters.scale_base,
+            leaf_cutoff,
+            min_res_index: self.parameters.min_res_index,
+            use_singletons: self.parameters.use_singletons,
+            partition_type: self.parameters.partition_type,
+            verbosity: self.parameters.verbosity,
+            rng_seed: self.parameters.rng_seed,
+            point_cloud: Arc::clone(&self.parameters.point_cloud),
+            plugins: RwLock::new(TreePluginSet::new()),
+            latencies: LatencyStats::new(),
+            publishes: PublishLog::new(),
+        })
+    }
+
+    /// Writes the nodes of a rebuilt subtree into the layers and points the final addresses of
+    /// their points at them. Returns their addresses.
+    fn insert_subtree(&mut self, new_nodes: Vec<CoverNode<D>>) -> Vec<NodeAddress> {
+        let mut new_addresses = Vec::with_capacity(new_nodes.len());
+        for new_node in new_nodes {
+            let na = new_node.address();
+            for singleton in new_node.singletons_iter() {
+                self.final_addresses.insert(singleton, na);
+            }
+            if new_node.is_leaf() {
+                self.final_addresses.insert(na.1, na);
+            }
+            unsafe {
+                self.insert_raw(na.0, na.1, new_node);
+            }
+            new_addresses.push(na);
+        }
+        self.parameters
+            .total_nodes
+            .fetch_add(new_addresses.len(), atomic::Ordering::SeqCst);
+        new_addresses
+    }
+
+    /// Repairs a tree whose build was stopped before every node was split. References to children
+    /// that never made it into the tree are dropped, a routing node that lost its nested child gets
+    /// a fresh nested leaf (or becomes a leaf if it lost all of its children), and the coverage
+    /// counts are recomputed from the bottom layer up. The points that were in the missing subtrees
+    /// are left out of the tree.
+    pub(crate) fn trim_unfinished(&mut self) {
+        let point_cloud = Arc::clone(&self.parameters.point_cloud);
+        let mut coverages: HashMap<NodeAddress, usize> = HashMap::new();
+        let mut nested_leaves = Vec::new();
+        let mut new_leaves = Vec::new();
+        for layer_index in 0..self.layers.len() {
+            let layer_reader = self.layers[layer_index].reader();
+            let scale_index = layer_reader.scale_index();
+            let mut layer_coverages = Vec::with_capacity(layer_reader.len());
+            let mut updates = Vec::new();
+            layer_reader.for_each_node(|pi, n| {
+                let address = (scale_index, *pi);
+                let mut coverage = n.singletons_multiplicity(&point_cloud);
+                let mut missing = Vec::new();
+                let mut becomes_leaf = false;
+                match n.children() {
+                    None => coverage += point_cloud.multiplicity(*pi),
+                    Some((nested_scale, children)) => {
+                        for ca in children {
+                            match coverages.get(ca) {
+                                Some(c) => coverage += c,
+                                None => missing.push(*ca),
+                            }
+                        }
+                        match coverages.get(&(nested_scale, *pi)) {
+                            Some(c) => coverage += c,
+                            None => {
+                                coverage += point_cloud.multiplicity(*pi);
+                                if missing.len() == children.len() {
+                                    becomes_leaf = true;
+                                } else {
+                                    nested_leaves.push((address, (nested_scale, *pi)));
+                                }
+                            }
+                        }
+                    }
+                }
+                if becomes_leaf {
+                    new_leaves.push(address);
+                }
+                if becomes_leaf || !missing.is_empty() || coverage != n.coverage_count() {
+                    updates.push((*pi, missing, becomes_leaf, coverage));
+                }
+                layer_coverages.push((address, coverage));
+            });
+            coverages.extend(layer_coverages);
+            for (pi, missing, becomes_leaf, coverage) in updates {
+                unsafe {
+                    self.layers[layer_index].update_node(pi, move |n| {
+                        if becomes_leaf {
+                            n.remove_children();
+                        }
+                        for ca in &missing {
+                            n.remove_child(*ca);
+                        }
+                        n.set_coverage_count(coverage);
+                    });
+                }
+            }
+        }
+        for (parent_address, address) in nested_leaves {
+            unsafe {
+                self.insert_raw(
+                    address.0,
+                    address.1,
+                    CoverNode::new(Some(parent_address), address),
+                );
+            }
+            self.final_addresses.insert(address.1, address);
+        }
+        for address in new_leaves {
+            self.final_addresses.insert(address.1, address);
+        }
+        let total_nodes = self.layers.iter().map(|l| l.reader().len()).sum();
+        self.parameters
+            .total_nodes
+            .store(total_nodes, atomic::Ordering::SeqCst);
+        self.refresh();
+        self.final_addresses.refresh();
+        self.final_addresses.refresh();
+    }
+
+    /// The builder counts every point once. When the point cloud collapsed its duplicates this
+    /// recounts the coverage of every node with the points' multiplicities, see
+    /// [`PointCloud::multiplicity`]. A finished tree has nothing to trim, so this is the same
+    /// bottom up pass as `trim_unfinished`. It does nothing if every point counts once.
+    pub(crate) fn recount_coverage(&mut self) {
+        let point_cloud = &self.parameters.point_cloud;
+        if (0..point_cloud.len()).any(|pi| point_cloud.multiplicity(pi) != 1) {
+            self.trim_unfinished();
+        }
+    }
+
+    /// Removes the nodes in the middle of chains of single children. A node whose only child is
+    /// its nested child, with no routing children or singletons, covers exactly what that child
+    /// does. On data with large empty regions these chains can span many scales. Each chain is cut
+    /// down to its top and bottom node, the top's nested child pointing straight at the bottom, so
+    /// the scale of every remaining node is unchanged. Coverage counts, radii and the final
+    /// addresses of the points are untouched. Returns the number of nodes removed.
+    ///
+    /// The pruned tree answers queries the same way, but it no longer has a node at every scale
+    /// along a chain. Code that walks the tree a layer at a time should not prune.
+    pub fn prune_chains(&mut self) -> GokoResult<usize> {
+        let reader = self.reader();
+        let mut pruned = Vec::new();
+        let mut shortcuts = Vec::new();
+        let mut unvisited = vec![reader.root_address()];
+        while let Some(address) = unvisited.pop() {
+            let mut bottom = address;
+            let mut skipped = Vec::new();
+            while let Some(nested_scale) = reader
+                .get_node_and(bottom, |n| match n.children() {
+                    Some((nested_scale, children))
+                        if children.is_empty() && n.singletons_len() == 0 =>
+                    {
+                        Some(nested_scale)
+                    }
+                    _ => None,
+                })
+                .ok_or(GokoError::NodeNotInTree(bottom))?
+            {
+                if bottom != address {
+                    skipped.push(bottom);
+                }
+                bottom = (nested_scale, bottom.1);
+            }
+            if !skipped.is_empty() {
+                shortcuts.push((address, bottom));
+                pruned.extend(skipped);
+            }
+            reader.get_node_and(bottom, |n| {
+                if let Some((nested_scale, children)) = n.children() {
+                    unvisited.push((nested_scale, bottom.1));
+                    unvisited.extend_from_slice(children);
+                }
+            });
+        }
+        drop(reader);
+
+        for (top, bottom) in shortcuts.iter().copied() {
+            unsafe {
+                self.update_node(top, move |n| n.set_nested_scale(bottom.0));
+                self.update_node(bottom, move |n| n.set_parent_address(Some(top)));
+            }
+        }
+        for address in &pruned {
+            unsafe {
+                self.layer(address.0).remove_raw(address.1);
+            }
+        }
+        self.parameters
+            .total_nodes
+            .fetch_sub(pruned.len(), atomic::Ordering::SeqCst);
+        self.refresh();
+
+        self.recompute_node_plugins(shortcuts.iter().map(|(top, _)| *top).collect());
+        Ok(pruned.len())
+    }
+}
+
+impl<D: LabeledCloudMut> CoverTreeWriter<D> {
+    /// Replaces the labels of some points, then rebuilds the plugins (label summaries, dirichlet
+    /// evidence, etc.) of the nodes on the paths to those points. The rest of the tree is untouched.
+    ///
+    /// The point cloud is edited in place, so this writer has to be its only owner. Drop all
+    /// readers and any other references to the point cloud first.
+    pub fn update_labels<L: Borrow<D::Label>>(
+        &mut self,
+        indexes: &[usize],
+        labels: &[L],
+    ) -> GokoResult<()> {
+        if indexes.len() != labels.len() {
+            return Err(GokoError::InvalidTreeEdit(
+                "there must be exactly one label for each index",
+            ));
+        }
+        let point_cloud = Arc::get_mut(&mut self.parameters)
+            .and_then(|parameters| Arc::get_mut(&mut parameters.point_cloud))
+            .ok_or(GokoError::InvalidTreeEdit(
+                "the point cloud is shared, drop all readers of the tree before updating labels",
+            ))?;
+        for (pi, label) in indexes.iter().zip(labels) {
+            point_cloud.set_label(*pi, Some(label.borrow()))?;
+        }
+
+        let reader = self.reader();
+        let mut touched = Vec::new();
+        for pi in indexes {
+            let final_address = self
+                .final_addresses
+                .get_and(pi, |address| *address)
+                .ok_or(GokoError::IndexNotInTree(*pi))?;
+            touched.extend(ancestors(&reader, final_address));
+        }
+        drop(reader);
+        self.recompute_node_plugins(touched);
+        Ok(())
+    }
+}
+
+impl<D: DeletableCloud> CoverTreeWriter<D> {
+    /// Marks points as deleted in the point cloud. They're left out of every kNN query from then on, and the
+    /// plugins (label summaries, etc.) of the nodes on the paths to them are rebuilt without them.
+    ///
+    /// The tree's structure isn't touched, a deleted point that's a node's center still routes queries to the points
+    /// under it and the coverage counts still include the deleted points. A rebuild, see [`crate::RebuildingTree`],
+    /// leaves them out. As with [`CoverTreeWriter::update_labels`] this writer has to be the point cloud's only owner.
+    pub fn delete_points(&mut self, indexes: &[usize]) -> GokoResult<()> {
+        self.set_deleted(indexes, true)
+    }
+
+    /// Brings back points removed with [`CoverTreeWriter::delete_points`].
+    pub fn restore_points(&mut self, indexes: &[usize]) -> GokoResult<()> {
+        self.set_deleted(indexes, false)
+    }
+
+    fn set_deleted(&mut self, indexes: &[usize], deleted: bool) -> GokoResult<()> {
+        let point_cloud = Arc::get_mut(&mut self.parameters)
+            .and_then(|parameters| Arc::get_mut(&mut parameters.point_cloud))
+            .ok_or(GokoError::InvalidTreeEdit(
+                "the point cloud is shared, drop all readers of the tree before deleting points",
+            ))?;
+        for pi in indexes {
+            if deleted {
+                point_cloud.delete_point(*pi)?;
+            } else {
+                point_cloud.restore_point(*pi)?;
+            }
+        }
+
+        let reader = self.reader();
+        let mut touched = Vec::new();
+        for pi in indexes {
+            let final_address = self
+                .final_addresses
+                .get_and(pi, |address| *address)
+                .ok_or(GokoError::IndexNotInTree(*pi))?;
+            touched.extend(ancestors(&reader, final_address));
+        }
+        drop(reader);
+        self.recompute_node_plugins(touched);
+        Ok(())
+    }
+}
+
+impl<D: ExtendableCloud> CoverTreeWriter<D> {
+    /// Appends points, given as consecutive rows of `dim` floats, to the point cloud and inserts
+    /// them into the tree. Each point is routed down the same path a query would take and attached
+    /// as a singleton of the last node on it, and that node and its ancestors have their coverage
+    /// counts bumped and their radii grown to cover the point. Their plugins are then rebuilt.
+    /// Returns the indexes of the new points.
+    ///
+    /// Every node on the path but the root is within its scale of the point. If a point is farther
+    /// from the root's center than the root's scale, new roots are put on top of the old one until
+    /// the scale covers it, so the queries can keep bounding each subtree by its scale. Readers made
+    /// before that don't see the new roots, get a new reader after it.
+    ///
+    /// The tree is not re-clustered, so many extensions can leave overloaded nodes. Use
+    /// [`CoverTreeWriter::split_node`] on those. As with [`CoverTreeWriter::update_labels`] this
+    /// writer has to be the point cloud's only owner.
+    pub fn extend(
+        &mut self,
+        points: &[f32],
+        labels: &[Option<&D::Label>],
+    ) -> GokoResult<Vec<usize>> {
+        self.extend_with(|point_cloud| point_cloud.extend_points(points, labels))
+    }
+
+    /// Inserts a single point into the tree and returns its new index. Unlike
+    /// [`CoverTreeWriter::extend`] this keeps the nesting a build would have produced: if the point
+    /// lands in a leaf that now holds more singletons than the tree's `leaf_cutoff`, that leaf is
+    /// split with [`CoverTreeWriter::split_node`]. A point past the root's scale raises the root,
+    /// as in [`CoverTreeWriter::extend`], so it's never outside the scale of a node above it. The
+    /// coverage counts and plugins of the nodes involved are updated along the way.
+    pub fn insert(&mut self, point: &[f32], label: Option<&D::Label>) -> GokoResult<usize> {
+        let pi = self.extend(point, &[label])?[0];
+        let final_address = self
+            .final_addresses
+            .get_and(&pi, |a| *a)
+            .ok_or(GokoError::IndexNotInTree(pi))?;
+        let leaf_cutoff = self.parameters.leaf_cutoff;
+        let overloaded = final_address.0 >= self.parameters.min_res_index
+            && self
+                .reader()
+                .get_node_and(final_address, |n| {
+                    n.is_leaf() && n.singletons_len() > leaf_cutoff
+                })
+                .unwrap_or(false);
+        if overloaded {
+            self.split_node(final_address, leaf_cutoff)?;
+        }
+        Ok(pi)
+    }
+
+    /// Appends points to the point cloud with `add_points`, then inserts everything it appended.
+    fn extend_with<F>(&mut self, add_points: F) -> GokoResult<Vec<usize>>
+    where
+        F: FnOnce(&mut D) -> PointCloudResult<()>,
+    {
+        let timer = self.parameters.latencies.start();
+        let new_indexes = self.extend_point_cloud(add_points)?;
+        let touched = self.attach_points(&new_indexes)?;
+        self.recompute_node_plugins(touched);
+        self.parameters.latencies.record(Operation::Insert, timer);
+        Ok(new_indexes)
+    }
+
+    /// Appends points to the point cloud with `add_points` and returns the indexes it appended.
+    fn extend_point_cloud<F>(&mut self, add_points: F) -> GokoResult<Vec<usize>>
+    where
+        F: FnOnce(&mut D) -> PointCloudResult<()>,
+    {
+        let point_cloud = Arc::get_mut(&mut self.parameters)
+            .and_then(|parameters| Arc::get_mut(&mut parameters.point_cloud))
+            .ok_or(GokoError::InvalidTreeEdit(
+                "the point cloud is shared, drop all readers of the tree before extending it",
+            ))?;
+        let start = point_cloud.len();
+        add_points(point_cloud)?;
+        Ok((start..point_cloud.len()).collect())
+    }
+
+    /// Merges a tree built over other points into this one, so that a single tree covers both.
+    /// The other tree's points are appended to this tree's point cloud with their labels, and its
+    /// nodes are moved over with their indexes shifted to match. Both roots are put under a new
+    /// root, centered on the root at the higher scale, one scale above both roots and high enough
+    /// to cover every point of both trees. Each old root hangs off the new one through a chain of
+    /// nested nodes, so the children of every node stay one scale down. Returns the indexes the
+    /// other tree's points got, in the order of its point cloud.
+    ///
+    /// The other tree's collapsed copies stay collapsed into the same points, and the coverage
+    /// counts are recounted with their multiplicities.
+    ///
+    /// The trees have to share their `scale_base` and `min_res_index`, and the other tree can't
+    /// have deleted points. This tree's plugins are computed for the moved nodes, the other tree's
+    /// plugins are dropped. As with [`CoverTreeWriter::extend`] this writer has to be the point
+    /// cloud's only owner. Readers made before the merge don't see the new top layers, get a new
+    /// reader after it.
+    pub fn merge(&mut self, other: CoverTreeWriter<D>) -> GokoResult<Vec<usize>> {
+        if self.parameters.scale_base != other.parameters.scale_base
+            || self.parameters.min_res_index != other.parameters.min_res_index
+        {
+            return Err(GokoError::InvalidTreeEdit(
+                "merged trees need the same scale base and minimum resolution index",
+            ));
+        }
+        let other_reader = other.reader();
+        let other_cloud = other_reader.point_cloud();
+        if !other_cloud.deleted_indexes().is_empty() {
+            return Err(GokoError::InvalidTreeEdit(
+                "remove the deleted points of a tree before merging it",
+            ));
+        }
+        let mut points = Vec::with_capacity(other_cloud.len() * other_cloud.dim());
+        let mut labels = Vec::with_capacity(other_cloud.len());
+        for pi in 0..other_cloud.len() {
+            points.extend(other_cloud.point(pi)?.dense_iter());
+            labels.push(other_cloud.label(pi)?);
+        }
+        // The copies the other tree collapsed stay collapsed, so its points keep their multiplicity
+        let copies: Vec<(usize, usize)> = (0..other_cloud.len())
+            .filter(|pi| other_cloud.multiplicity(*pi) == 0)
+            .map(|pi| (pi, other_cloud.representative(pi)))
+            .collect();
+        let new_indexes = self.extend_point_cloud(|point_cloud| {
+            let offset = point_cloud.len();
+            point_cloud.extend_points(&points, &labels)?;
+            for (pi, into) in &copies {
+                point_cloud.collapse_point(offset + pi, offset + into)?;
+            }
+            Ok(())
+        })?;
+        let offset = new_indexes.first().copied().unwrap_or(0);
+
+        let mut moved: Vec<CoverNode<D>> = Vec::new();
+        for (_, layer) in other_reader.layers() {
+            layer.for_each_node(|_, n| moved.push(n.shifted(offset)));
+        }
+        let other_root = other_reader.root_address();
+        let other_root = (other_root.0, other_root.1 + offset);
+        let (other_coverage, other_radius) = moved
+            .iter()
+            .find(|n| n.address() == other_root)
+            .map(|n| (n.coverage_count(), n.radius()))
+            .ok_or(GokoError::NodeNotInTree(other_root))?;
+        let my_root = self.root_address;
+        let (my_coverage, my_radius) = self
+            .reader()
+            .get_node_and(my_root, |n| (n.coverage_count(), n.radius()))
+            .ok_or(GokoError::NodeNotInTree(my_root))?;
+
+        // The new root is centered on the old root at the higher scale, which becomes its nested child
+        let mut roots = [
+            (my_root, my_coverage, my_radius),
+            (other_root, other_coverage, other_radius),
+        ];
+        if other_root.0 > my_root.0 {
+            roots.swap(0, 1);
+        }
+        let (top_root, top_radius) = (roots[0].0, roots[0].2);
+        let (bottom_root, bottom_radius) = (roots[1].0, roots[1].2);
+        let dist = self
+            .parameters
+            .point_cloud
+            .distances_to_point_index(top_root.1, &[bottom_root.1])?[0];
+        // The new root's scale has to reach the far side of the bottom tree, not just its center
+        let root_radius = top_radius.max(dist + bottom_radius);
+        let scale_base = self.parameters.scale_base;
+        let mut root_scale = top_root.0 + 1;
+        while scale_base.powi(root_scale) < root_radius {
+            root_scale += 1;
+        }
+        let root = (root_scale, top_root.1);
+
+        while self.layers.len() as i32 + self.parameters.min_res_index - 1 <= root_scale {
+            let scale_index = self.layers.len() as i32 + self.parameters.min_res_index - 1;
+            self.layers.push(CoverLayerWriter::new(scale_index));
+        }
+
+        let mut root_node = CoverNode::new(None, root);
+        root_node.set_radius(root_radius);
+        let mut parents = Vec::with_capacity(2);
+        for (i, (old_root, coverage, radius)) in roots.iter().copied().enumerate() {
+            let mut parent = root;
+            for scale_index in ((old_root.0 + 1)..root_scale).rev() {
+                let address = (scale_index, old_root.1);
+                let mut node = CoverNode::new(Some(parent), address);
+                node.insert_nested_child(scale_index - 1, coverage)?;
+                node.set_radius(radius);
+                moved.push(node);
+                parent = address;
+            }
+            let child = (root_scale - 1, old_root.1);
+            if i == 0 {
+                root_node.insert_nested_child(child.0, coverage)?;
+            } else {
+                root_node.insert_child(child, coverage)?;
+            }
+            parents.push((old_root, parent));
+        }
+        moved.push(root_node);
+        for node in moved.iter_mut() {
+            if let Some((_, parent)) = parents.iter().find(|(a, _)| *a == node.address()) {
+                node.set_parent_address(Some(*parent));
+            }
+        }
+        if let Some((_, parent)) = parents.iter().find(|(a, _)| *a == my_root) {
+            let parent = *parent;
+            unsafe { self.update_node(my_root, move |n| n.set_parent_address(Some(parent))) };
+        }
+
+        let new_addresses = self.insert_subtree(moved);
+        self.root_address = root;
+        self.refresh();
+        self.final_addresses.refresh();
+        self.recount_coverage();
+
+        self.recompute_node_plugins(new_addresses);
+        Ok(new_indexes)
+    }
+}
+
+impl<D: PartitionedCloudMut> CoverTreeWriter<D> {
+    /// [`CoverTreeWriter::extend`] with all the new points in one partition, see [`pointcloud::partitions`]. `None`
+    /// leaves them in no partition.
+    pub fn extend_in_partition(
+        &mut self,
+        points: &[f32],
+        labels: &[Option<&D::Label>],
+        partition: Option<&str>,
+    ) -> GokoResult<Vec<usize>> {
+        self.extend_with(|point_cloud| {
+            point_cloud.extend_points_in_partition(points, labels, partition)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::covertree::tests::build_basic_tree;
+
+    fn coverage<D: PointCloud>(reader: &CoverTreeReader<D>, address: NodeAddress) -> usize {
+        reader
+            .get_node_and(address, |n| n.coverage_count())
+            .unwrap()
+    }
+
+    #[test]
+    fn merge_keeps_nested_child() {
+        let mut tree = build_basic_tree();
+        tree.generate_summaries();
+        let reader = tree.reader();
+        let root = reader.root_address();
+        let (nested_scale, children) = reader
+            .get_node_and(root, |n| n.children().map(|(ns, c)| (ns, Vec::from(c))))
+            .flatten()
+            .unwrap();
+        assert!(!children.is_empty());
+        let nested = (nested_scale, root.1);
+        let merged_coverage = coverage(&reader, nested) + coverage(&reader, children[0]);
+        // The points of the absorbed child past the nested child's scale go back to the root
+        let (absorbed, _) = subtree(&reader, children[0]).unwrap();
+        let center = reader.point_cloud().point(root.1).unwrap()[0];
+        let scale = reader.parameters().scale_base.powi(nested_scale);
+        let misfits = absorbed
+            .iter()
+            .filter(|pi| (reader.point_cloud().point(**pi).unwrap()[0] - center).abs() > scale)
+            .count();
+
+        let merged = tree.merge_nodes(children[0], nested).unwrap();
+        assert_eq!(merged, nested);
+
+        let reader = tree.reader();
+        assert!(reader.no_dangling_refs());
+        assert!(reader.get_node_and(children[0], |_| ()).is_none());
+        assert_eq!(coverage(&reader, reader.root_address()), 5);
+        assert_eq!(coverage(&reader, merged), merged_coverage - misfits);
+        let summary = reader.get_node_label_summary(merged).unwrap();
+        assert_eq!(summary.count(), merged_coverage - misfits);
+        for pi in 0..5 {
+            assert!(reader.known_path(pi).is_ok());
+        }
+        let report = reader.validate().unwrap();
+        assert_eq!(report.violations_of(Invariant::Covering).count(), 0);
+
+        let data: Vec<f32> = (0..5)
+            .map(|pi| reader.point_cloud().point(pi).unwrap()[0])
+            .collect();
+        for x in &[-0.6, -0.3, 0.0, 0.25, 0.485, 0.7] {
+            let mut brute_force: Vec<f32> = data.iter().map(|y| (x - y).abs()).collect();
+            brute_force.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            let knn = reader.knn(&[*x].as_ref(), 5).unwrap();
+            assert_eq!(knn.len(), 5);
+            for ((d, _), e) in knn.iter().zip(&brute_force) {
+                assert_approx_eq!(*d, *e);
+            }
+        }
+    }
+
+    #[test]
+    fn merge_rejects_non_siblings() {
+        let mut tree = build_basic_tree();
+        let root = tree.reader().root_address();
+        let nested = tree
+            .reader()
+            .get_node_and(root, |n| n.children().map(|(ns, _)| (ns, root.1)))
+            .flatten()
+            .unwrap();
+        assert!(tree.merge_nodes(root, nested).is_err());
+        assert!(tree.merge_nodes(nested, nested).is_err());
+    }
+
+    #[test]
+    fn update_labels_fixes_summaries() {
+        let mut tree = build_basic_tree();
+        tree.generate_summaries();
+        tree.update_labels(&[3, 4], &[0i64, 0]).unwrap();
+
+        let reader = tree.reader();
+        let summary = reader
+            .get_node_label_summary(reader.root_address())
+            .unwrap();
+        assert_eq!(summary.count(), 5);
+        assert_eq!(summary.summary.items.len(), 1);
+        let summary = reader
+            .get_node_label_summary(reader.known_path(3).unwrap().last().unwrap().1)
+            .unwrap();
+        assert!(summary.summary.items.iter().all(|(label, _)| *label == 0));
+    }
+
+    #[test]
+    fn update_labels_needs_unique_point_cloud() {
+        let mut tree = build_basic_tree();
+        let _reader = tree.reader();
+        assert!(tree.update_labels(&[3], &[0i64]).is_err());
+    }
+
+    #[test]
+    fn split_overloaded_leaf() {
+        let data = vec![0.499, 0.49, 0.48, -0.49, 0.0];
+        let labels = vec![0, 0, 0, 1, 1];
+        let point_cloud = DefaultLabeledCloud::<L2>::new_simple(data, 1, labels);
+        let mut builder = CoverTreeBuilder::new();
+        builder.set_leaf_cutoff(5).set_rng_seed(0);
+        let mut tree = builder.build(Arc::new(point_cloud)).unwrap();
+        tree.generate_summaries();
+        let root = tree.reader().root_address();
+        assert!(tree.reader().get_node_and(root, |n| n.is_leaf()).unwrap());
+
+        tree.split_node(root, 1).unwrap();
+
+        let reader = tree.reader();
+        assert!(reader.no_dangling_refs());
+        assert!(!reader.get_node_and(root, |n| n.is_leaf()).unwrap());
+        assert_eq!(coverage(&reader, root), 5);
+        assert_eq!(reader.get_node_label_summary(root).unwrap().count(), 5);
+        for pi in 0..5 {
+            assert!(reader.known_path(pi).is_ok());
+        }
+    }
+
+    #[test]
+    fn extend_inserts_points() {
+        let mut tree = build_basic_tree();
+        tree.generate_summaries();
+        let new_indexes = tree
+            .extend(&[0.495, -0.5, 3.0], &[Some(&0), None, Some(&1)])
+            .unwrap();
+        assert_eq!(new_indexes, vec![5, 6, 7]);
+
+        let reader = tree.reader();
+        assert!(reader.no_dangling_refs());
+        let root = reader.root_address();
+        assert_eq!(coverage(&reader, root), 8);
+        assert_eq!(reader.get_node_label_summary(root).unwrap().count(), 8);
+        assert!(reader.get_node_and(root, |n| n.radius()).unwrap() >= 3.0);
+        // The far point raised the root, so the root's scale still bounds everything under it
+        assert!(reader.scale(root.0) >= 3.0);
+        assert_eq!(reader.knn(&[3.0f32].as_ref(), 1).unwrap()[0], (0.0, 7));
+        for pi in new_indexes {
+            let path = reader.known_path(pi).unwrap();
+            assert_eq!(path[0].1, root);
+            let final_address = path.last().unwrap().1;
+            assert!(reader
+                .get_node_and(final_address, |n| n.singletons().contains(&pi))
+                .unwrap());
+            for (dist, address) in path {
+                assert!(dist <= reader.get_node_and(address, |n| n.radius()).unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn insert_keeps_leaves_under_the_cutoff() {
+        let mut tree = build_basic_tree();
+        tree.generate_summaries();
+        let points = [0.01f32, 0.02, 0.03, -0.01, 0.495];
+        for (i, x) in points.iter().enumerate() {
+            assert_eq!(tree.insert(&[*x], Some(&1)).unwrap(), 5 + i);
+        }
+
+        let reader = tree.reader();
+        assert!(reader.no_dangling_refs());
+        let root = reader.root_address();
+        assert_eq!(coverage(&reader, root), 10);
+        assert_eq!(reader.get_node_label_summary(root).unwrap().count(), 10);
+        let leaf_cutoff = reader.parameters().leaf_cutoff;
+        let min_res_index = reader.parameters().min_res_index;
+        for (scale_index, layer) in reader.layers() {
+            if scale_index < min_res_index {
+                continue;
+            }
+            layer.for_each_node(|_, n| {
+                if n.is_leaf() {
+                    assert!(n.singletons_len() <= leaf_cutoff);
+                }
+            });
+        }
+        for (i, x) in points.iter().enumerate() {
+            let nearest = reader.knn(&[*x].as_ref(), 1).unwrap()[0];
+            assert_eq!(nearest, (0.0, 5 + i));
+        }
+    }
+
+    #[test]
+    fn insert_past_the_root_scale() {
+        let mut tree = build_basic_tree();
+        tree.generate_summaries();
+        let far = [5.0f32, 5.5, -7.0];
+        for (i, x) in far.iter().enumerate() {
+            assert_eq!(tree.insert(&[*x], Some(&0)).unwrap(), 5 + i);
+        }
+
+        let reader = tree.reader();
+        assert!(reader.no_dangling_refs());
+        let root = reader.root_address();
+        assert_eq!(coverage(&reader, root), 8);
+        for (i, x) in far.iter().enumerate() {
+            let path = reader.known_path(5 + i).unwrap();
+            for (dist, address) in path {
+                assert!(dist <= reader.scale(address.0));
+            }
+            let nearest = reader.knn(&[*x].as_ref(), 1).unwrap()[0];
+            assert_eq!(nearest, (0.0, 5 + i));
+        }
+        let nearest: Vec<usize> = reader
+            .knn(&[5.2f32].as_ref(), 2)
+            .unwrap()
+            .iter()
+            .map(|(_, pi)| *pi)
+            .collect();
+        assert_eq!(nearest, vec![5, 6]);
+    }
+
+    #[test]
+    fn remove_center_keeps_covering() {
+        // The root is centered on 0.0 at scale 1, re-centering on 0.2 leaves -0.95 and -0.9 past its scale
+        let data = vec![0.95, -0.95, -0.9, 0.2, 0.0];
+        let labels = vec![0, 1, 1, 0, 0];
+        let point_cloud = DefaultLabeledCloud::<L2>::new_simple(data, 1, labels);
+        let mut builder = CoverTreeBuilder::new();
+        builder
+            .set_leaf_cutoff(1)
+            .set_min_res_index(-9)
+            .set_rng_seed(0);
+        let mut tree = builder.build(Arc::new(point_cloud)).unwrap();
+        tree.generate_summaries();
+        let root_center = tree.reader().root_address().1;
+        tree.remove(root_center).unwrap();
+
+        let reader = tree.reader();
+        assert!(reader.no_dangling_refs());
+        let report = reader.validate().unwrap();
+        assert_eq!(report.violations_of(Invariant::Covering).count(), 0);
+        assert_eq!(report.violations_of(Invariant::Nesting).count(), 0);
+        assert_eq!(coverage(&reader, reader.root_address()), 4);
+        for pi in (0..5).filter(|pi| *pi != root_center) {
+            let x = reader.point_cloud().point(pi).unwrap()[0];
+            assert_eq!(reader.knn(&[x].as_ref(), 1).unwrap()[0], (0.0, pi));
+        }
+    }
+
+    #[test]
+    fn remove_detaches_points() {
+        let mut tree = build_basic_tree();
+        tree.generate_summaries();
+        let mut remaining: Vec<usize> = (0..5).collect();
+        let root_center = tree.reader().root_address().1;
+        let mut order = vec![root_center];
+        order.extend((0..5).filter(|pi| *pi != root_center).take(3));
+        for pi in order {
+            tree.remove(pi).unwrap();
+            remaining.retain(|i| *i != pi);
+
+            let reader = tree.reader();
+            assert!(reader.no_dangling_refs());
+            assert!(reader.known_path(pi).is_err());
+            let root = reader.root_address();
+            assert_ne!(root.1, pi);
+            assert_eq!(coverage(&reader, root), remaining.len());
+            assert_eq!(
+                reader.get_node_label_summary(root).unwrap().count(),
+                remaining.len()
+            );
+            let mut found: Vec<usize> = reader
+                .knn(&[0.0f32].as_ref(), 5)
+                .unwrap()
+                .iter()
+                .map(|(_, i)| *i)
+                .collect();
+            found.sort_unstable();
+            assert_eq!(found, remaining);
+        }
+        assert!(tree.remove(remaining[0]).is_err());
+
+        let pi = tree.insert(&[0.25], Some(&0)).unwrap();
+        let reader = tree.reader();
+        assert_eq!(reader.knn(&[0.25f32].as_ref(), 1).unwrap()[0], (0.0, pi));
+        assert_eq!(coverage(&reader, reader.root_address()), 2);
+    }
+
+    #[test]
+    fn prune_chains_keeps_queries() {
+        let mut tree = build_basic_tree();
+        tree.generate_summaries();
+        let queries = [0.495f32, 0.485, -0.3, 0.1];
+        let reader = tree.reader();
+        let node_count = reader.node_count();
+        let before: Vec<Vec<(f32, usize)>> = queries
+            .iter()
+            .map(|x| reader.knn(&[*x].as_ref(), 3).unwrap())
+            .collect();
+        drop(reader);
+
+        let pruned = tree.prune_chains().unwrap();
+        assert!(pruned > 0);
+        let reader = tree.reader();
+        assert!(reader.no_dangling_refs());
+        assert_eq!(reader.node_count(), node_count - pruned);
+        let root = reader.root_address();
+        assert_eq!(coverage(&reader, root), 5);
+        assert_eq!(reader.get_node_label_summary(root).unwrap().count(), 5);
+        for (x, nbrs) in queries.iter().zip(before) {
+            assert_eq!(reader.knn(&[*x].as_ref(), 3).unwrap(), nbrs);
+        }
+        for pi in 0..5 {
+            let path = reader.known_path(pi).unwrap();
+            assert_eq!(path[0].1, root);
+            let final_address = path.last().unwrap().1;
+            assert!(reader
+                .get_node_and(final_address, |n| final_address.1 == pi
+                    || n.singletons().contains(&pi))
+                .unwrap());
+        }
+        drop(reader);
+        assert_eq!(tree.prune_chains().unwrap(), 0);
+    }
+
+    #[test]
+    fn extend_checks_dimensions() {
+        let mut tree = build_basic_tree();
+        assert!(tree.extend(&[0.1, 0.2], &[Some(&0)]).is_err());
+    }
+
+    #[test]
+    fn deleted_points_leave_queries() {
+        let mut tree = build_basic_tree();
+        tree.generate_summaries();
+        let reader = tree.reader();
+        let root = reader.root_address();
+        let nearest = reader.knn(&[0.499f32].as_ref(), 1).unwrap()[0].1;
+        assert_eq!(nearest, 0);
+        drop(reader);
+
+        tree.delete_points(&[0, 3]).unwrap();
+        let reader = tree.reader();
+        let nbrs = reader.knn(&[0.499f32].as_ref(), 5).unwrap();
+        assert_eq!(nbrs.len(), 3);
+        assert!(nbrs.iter().all(|(_, pi)| *pi != 0 && *pi != 3));
+        assert_eq!(nbrs[0].1, 1);
+        assert_eq!(reader.get_node_label_summary(root).unwrap().count(), 3);
+        // The structure is untouched
+        assert_eq!(coverage(&reader, root), 5);
+        drop(reader);
+
+        tree.restore_points(&[0]).unwrap();
+        let reader = tree.reader();
+        assert_eq!(reader.knn(&[0.499f32].as_ref(), 1).unwrap()[0].1, 0);
+        assert_eq!(reader.get_node_label_summary(root).unwrap().count(), 4);
+    }
+
+    #[test]
+    fn merge_covers_both_trees() {
+        let mut tree = build_basic_tree();
+        tree.generate_summaries();
+        let shard =
+            DefaultLabeledCloud::<L2>::new_simple(vec![10.0, 10.2, 9.9, 10.5], 1, vec![1, 0, 1, 1]);
+        let builder = CoverTreeBuilder {
+            scale_base: 2.0,
+            leaf_cutoff: 1,
+            min_res_index: -9,
+            use_singletons: true,
+            partition_type: PartitionType::Nearest,
+            verbosity: 0,
+            rng_seed: Some(0),
+            memory_limit: None,
+            prune_chains: false,
+            thread_pool: None,
+            progress_callback: None,
+            progress_interval: None,
+            cancel_token: None,
+        };
+        let other = builder.build(Arc::new(shard)).unwrap();
+        let new_indexes = tree.merge(other).unwrap();
+        assert_eq!(new_indexes, vec![5, 6, 7, 8]);
+
+        let reader = tree.reader();
+        assert!(reader.no_dangling_refs());
+        let root = reader.root_address();
+        assert_eq!(coverage(&reader, root), 9);
+        assert_eq!(reader.get_node_label_summary(root).unwrap().count(), 9);
+        for pi in 0..9 {
+            let path = reader.known_path(pi).unwrap();
+            assert_eq!(path[0].1, root);
+            for (dist, address) in path {
+                assert!(dist <= reader.get_node_and(address, |n| n.radius()).unwrap());
+                assert!(dist <= reader.scale(address.0));
+            }
+        }
+        let mut found: Vec<usize> = reader
+            .knn(&[10.1f32].as_ref(), 3)
+            .unwrap()
+            .iter()
+            .map(|(_, i)| *i)
+            .collect();
+        found.sort_unstable();
+        assert_eq!(found, vec![5, 6, 7]);
+        assert_eq!(reader.knn(&[0.499f32].as_ref(), 1).unwrap()[0].1, 0);
+
+        let mismatched = CoverTreeBuilder {
+            scale_base: 1.5,
+            ..builder
+        }
+        .build(Arc::new(DefaultLabeledCloud::<L2>::new_simple(
+            vec![3.0],
+            1,
+            vec![0],
+        )))
+        .unwrap();
+        drop(reader);
+        assert!(tree.merge(mismatched).is_err());
+    }
+
+    #[test]
+    fn merge_keeps_collapsed_duplicates() {
+        use pointcloud::data_sources::DataRam;
+        use pointcloud::label_sources::SmallIntLabels;
+        let mut tree = build_basic_tree();
+        let data = vec![10.0, 10.0, 10.2, 9.9, 10.0, 10.5];
+        let labels = SmallIntLabels::new(vec![1, 1, 0, 1, 1, 1], None);
+        let mut data = DataRam::<L2>::new(data, 1).unwrap();
+        data.collapse_duplicates_by(|pi| labels.label(pi).ok().map(|l| l.copied()));
+        let mut builder = CoverTreeBuilder::new();
+        builder
+            .set_leaf_cutoff(1)
+            .set_min_res_index(-9)
+            .set_rng_seed(0);
+        let other = builder
+            .build(Arc::new(SimpleLabeledCloud::new(data, labels)))
+            .unwrap();
+        assert_eq!(tree.merge(other).unwrap(), vec![5, 6, 7, 8, 9, 10]);
+
+        let reader = tree.reader();
+        let point_cloud = reader.point_cloud();
+        assert_eq!(point_cloud.multiplicity(5), 3);
+        assert_eq!(point_cloud.multiplicity(6), 0);
+        assert_eq!(point_cloud.representative(9), 5);
+        assert_eq!(
+            point_cloud.reference_indexes(),
+            vec![0, 1, 2, 3, 4, 5, 7, 8, 10]
+        );
+        assert_eq!(coverage(&reader, reader.root_address()), 11);
+        let report = reader.validate().unwrap();
+        assert!(!report
+            .violations
+            .iter()
+            .any(|v| matches!(v, TreeViolation::CoverageCount { .. })));
+        assert_eq!(report.violations_of(Invariant::Covering).count(), 0);
+        assert_eq!(reader.knn(&[10.0f32].as_ref(), 1).unwrap()[0].1, 5);
+    }
+}
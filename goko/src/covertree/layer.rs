@@ -193,9 +193,14 @@ impl<D: PointCloud> CoverLayerWriter<D> {
     pub(crate) fn save(&self) -> LayerProto {
         let mut layer_proto = LayerProto::new();
         let mut node_protos = layer_proto.take_nodes();
-        self.node_writer.for_each(|_pi, node| {
-            node_protos.push(node.save());
+        let mut nodes = Vec::new();
+        self.node_writer.for_each(|pi, node| {
+            nodes.push((*pi, node.save()));
         });
+        // The map's order depends on the order the nodes were inserted in, which isn't the same
+        // between builds. Sorting keeps saves of the same tree identical.
+        nodes.sort_unstable_by_key(|(pi, _)| *pi);
+        node_protos.extend(nodes.into_iter().map(|(_, proto)| proto));
         layer_proto.set_nodes(node_protos);
         layer_proto.set_scale_index(self.scale_index);
         layer_proto
@@ -205,6 +210,10 @@ impl<D: PointCloud> CoverLayerWriter<D> {
         self.node_writer.insert(index, node);
     }
 
+    pub(crate) fn remove_raw(&mut self, index: usize) {
+        self.node_writer.remove(index);
+    }
+
     pub(crate) fn refresh(&mut self) {
         self.node_writer.refresh();
     }
@@ -1,10 +1,18 @@
 pub(crate) mod builders;
 pub(crate) mod data_caches;
+mod editing;
 pub mod layer;
 pub mod node;
 pub mod query_tools;
+mod rebuild;
+mod saved_tree;
+pub mod singletons;
 
 mod tree;
+mod validation;
 
-pub use builders::CoverTreeBuilder;
+pub use builders::{BuilderConfig, CoverTreeBuilder};
+pub use rebuild::*;
+pub use saved_tree::*;
 pub use tree::*;
+pub use validation::*;
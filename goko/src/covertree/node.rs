@@ -21,6 +21,7 @@
 //! This is the workhorse of the library. Each node
 //!
 use super::query_tools::{RoutingQueryHeap, SingletonQueryHeap};
+use super::singletons::{PackedIndexes, SingletonIndexes, SingletonIter};
 use crate::errors::{GokoError, GokoResult};
 use crate::plugins::{
     labels::{NodeLabelSummary, NodeMetaSummary},
@@ -32,6 +33,7 @@ use std::ops::Deref;
 
 use pointcloud::*;
 use smallvec::SmallVec;
+use std::borrow::Cow;
 use std::marker::PhantomData;
 use std::sync::Arc;
 /// The node children. This is a separate struct from the `CoverNode` to use the rust compile time type checking and
@@ -44,8 +46,8 @@ pub(crate) struct NodeChildren {
 
 /// The actual cover node. The fields can be separated into three piles. The first two consist of node `address` for testing and reference
 /// when working and the `radius`, `coverage_count`, and `singles_summary` for a query various properties of the node.
-/// Finally we have the children and singleton pile. The singletons are saved in a `SmallVec` directly attached to the node. This saves a
-/// memory redirect for the first 20 singleton children. The children are saved in a separate struct also consisting of a `SmallVec`
+/// Finally we have the children and singleton pile. The first 20 singletons are saved in a `SmallVec` directly attached to the node, which
+/// saves a memory redirect. Longer lists are bitpacked, see [`crate::covertree::singletons`]. The children are saved in a separate struct also consisting of a `SmallVec`
 /// (though, this is only 10 wide before we allocate on the heap), and the scale index of the nested child.
 #[derive(Debug)]
 pub struct CoverNode<D: PointCloud> {
@@ -58,7 +60,7 @@ pub struct CoverNode<D: PointCloud> {
     coverage_count: usize,
     /// Children
     children: Option<NodeChildren>,
-    singles_indexes: SmallVec<[usize; 20]>,
+    singles_indexes: SingletonIndexes,
     plugins: NodePluginSet,
     metic: PhantomData<D>,
 }
@@ -87,7 +89,7 @@ impl<D: PointCloud> CoverNode<D> {
             radius: 0.0,
             coverage_count: 1,
             children: None,
-            singles_indexes: SmallVec::new(),
+            singles_indexes: SingletonIndexes::default(),
             plugins: NodePluginSet::new(),
             metic: PhantomData,
         }
@@ -141,9 +143,41 @@ impl<D: PointCloud> CoverNode<D> {
         self.singles_indexes.len()
     }
 
-    ///
+    /// The number of points the singletons stand for, each counted with its multiplicity. This is
+    /// the number of singletons unless the point cloud collapsed its duplicates, see
+    /// [`PointCloud::multiplicity`].
+    pub fn singletons_multiplicity(&self, point_cloud: &D) -> usize {
+        self.singles_indexes
+            .iter()
+            .map(|pi| point_cloud.multiplicity(pi))
+            .sum()
+    }
+
+    /// The singletons as a slice. Long lists are stored packed, the first call on one decodes it
+    /// and keeps the decoded copy with the node. Use [`CoverNode::singletons_iter`] or
+    /// [`CoverNode::packed_singletons`] to read them without that copy.
     pub fn singletons(&self) -> &[usize] {
-        &self.singles_indexes
+        self.singles_indexes.as_slice()
+    }
+
+    /// The packed singletons, see [`crate::covertree::singletons`]. `None` if the list is short
+    /// enough to be kept inline, then [`CoverNode::singletons`] doesn't copy anything.
+    pub fn packed_singletons(&self) -> Option<&PackedIndexes> {
+        self.singles_indexes.packed()
+    }
+
+    /// The singletons as a slice, decoded for this call only if they're packed.
+    pub(crate) fn singletons_decoded(&self) -> Cow<'_, [usize]> {
+        match self.packed_singletons() {
+            Some(packed) => Cow::Owned(packed.iter().collect()),
+            None => Cow::Borrowed(self.singletons()),
+        }
+    }
+
+    /// Iterates over the singletons without decoding them into a list. Packed lists come out in
+    /// ascending order.
+    pub fn singletons_iter(&self) -> SingletonIter<'_> {
+        self.singles_indexes.iter()
     }
 
     ///
@@ -212,8 +246,9 @@ impl<D: PointCloud> CoverNode<D> {
         point_cloud: &D,
         query_heap: &mut T,
     ) -> GokoResult<()> {
-        let distances = point_cloud.distances_to_point(point, &self.singles_indexes[..])?;
-        query_heap.push_outliers(&self.singles_indexes[..], &distances[..]);
+        let singletons = self.singletons_decoded();
+        let distances = point_cloud.distances_to_point(point, &singletons)?;
+        query_heap.push_outliers(&singletons, &distances[..]);
         Ok(())
     }
 
@@ -331,6 +366,14 @@ impl<D: PointCloud> CoverNode<D> {
         }
     }
 
+    /// Points the nested child at another scale, for when the nodes between are removed. Does nothing
+    /// to a leaf.
+    pub(crate) fn set_nested_scale(&mut self, scale_index: i32) {
+        if let Some(children) = &mut self.children {
+            children.nested_scale = scale_index;
+        }
+    }
+
     /// Inserts a routing child into the node. Make sure the child node is also in the tree or you get a dangling reference
     pub(crate) fn insert_child(&mut self, address: NodeAddress, coverage: usize) -> GokoResult<()> {
         self.coverage_count += coverage;
@@ -353,6 +396,12 @@ impl<D: PointCloud> CoverNode<D> {
         self.singles_indexes.push(pi);
     }
 
+    /// Removes a singleton from the node and returns false if it wasn't one. The coverage count is
+    /// left alone, as the point may stand for several, see [`PointCloud::multiplicity`].
+    pub(crate) fn remove_singleton(&mut self, pi: usize) -> bool {
+        self.singles_indexes.remove(pi)
+    }
+
     /// Inserts a single singleton child into the node.
     pub(crate) fn insert_plugin<T: NodePlugin<D> + 'static>(&mut self, plugin: T) {
         self.plugins.insert(plugin);
@@ -363,6 +412,63 @@ impl<D: PointCloud> CoverNode<D> {
         self.radius = radius;
     }
 
+    /// Overwrites the coverage count, for when the node's decendents are changed wholesale.
+    pub(crate) fn set_coverage_count(&mut self, coverage_count: usize) {
+        self.coverage_count = coverage_count;
+    }
+
+    /// Rough estimate of the bytes this node occupies, including any spilled `SmallVec` storage
+    /// and packed singletons. Plugins are not counted.
+    pub(crate) fn memory_estimate(&self) -> usize {
+        let mut size = std::mem::size_of::<Self>() + self.singles_indexes.memory_estimate();
+        if let Some(children) = &self.children {
+            size += std::mem::size_of::<NodeChildren>();
+            if children.addresses.spilled() {
+                size += children.addresses.capacity() * std::mem::size_of::<NodeAddress>();
+            }
+        }
+        size
+    }
+
+    /// A copy of the node for a point cloud with `offset` more points in front of its own, with the
+    /// center, parent, children and singletons shifted to match. The copy can be for another type of
+    /// point cloud over the same points. The plugins are not copied.
+    pub(crate) fn shifted<E: PointCloud>(&self, offset: usize) -> CoverNode<E> {
+        let shift = |(si, pi): NodeAddress| (si, pi + offset);
+        let mut singles_indexes = SingletonIndexes::default();
+        singles_indexes.extend(self.singletons_iter().map(|pi| pi + offset));
+        CoverNode {
+            parent_address: self.parent_address.map(shift),
+            address: shift(self.address),
+            radius: self.radius,
+            coverage_count: self.coverage_count,
+            children: self.children.as_ref().map(|children| NodeChildren {
+                nested_scale: children.nested_scale,
+                addresses: children.addresses.iter().copied().map(shift).collect(),
+            }),
+            singles_indexes,
+            plugins: NodePluginSet::new(),
+            metic: PhantomData,
+        }
+    }
+
+    /// Moves the node under a new parent. This does not touch the parent's child list.
+    pub(crate) fn set_parent_address(&mut self, parent_address: Option<NodeAddress>) {
+        self.parent_address = parent_address;
+    }
+
+    /// Removes a routing child from the child list. This does not change the coverage count, and cannot
+    /// remove the nested child. Returns false if the address wasn't a child of this node.
+    pub(crate) fn remove_child(&mut self, address: NodeAddress) -> bool {
+        if let Some(children) = &mut self.children {
+            if let Some(i) = children.addresses.iter().position(|a| *a == address) {
+                children.addresses.remove(i);
+                return true;
+            }
+        }
+        false
+    }
+
     pub(crate) fn load(node_proto: &NodeProto) -> CoverNode<D> {
         let singles_indexes = node_proto
             .outlier_point_indexes
@@ -428,7 +534,7 @@ impl<D: PointCloud> CoverNode<D> {
         }
 
         proto.set_radius(self.radius);
-        proto.set_outlier_point_indexes(self.singles_indexes.iter().map(|pi| *pi as u64).collect());
+        proto.set_outlier_point_indexes(self.singles_indexes.iter().map(|pi| pi as u64).collect());
 
         match &self.children {
             Some(children) => {
@@ -454,7 +560,7 @@ impl<D: PointCloud> CoverNode<D> {
     /// Brute force verifies that the children are separated by at least the scale provided.
     /// The scale provided should be b^(s-1) where s is this node's scale index.
     pub fn check_seperation(&self, scale: f32, point_cloud: &D) -> GokoResult<bool> {
-        let mut nodes = self.singles_indexes.clone();
+        let mut nodes = self.singletons().to_vec();
         nodes.push(self.address.1);
         if let Some(children) = &self.children {
             nodes.extend(children.addresses.iter().map(|(_si, pi)| *pi));
@@ -487,7 +593,7 @@ mod tests {
             radius: 1.0,
             coverage_count: 8,
             children,
-            singles_indexes: smallvec![4, 5, 6],
+            singles_indexes: vec![4, 5, 6].into_iter().collect(),
             plugins: NodePluginSet::new(),
             metic: PhantomData,
         }
@@ -500,7 +606,7 @@ mod tests {
             radius: 1.0,
             coverage_count: 8,
             children: None,
-            singles_indexes: smallvec![1, 2, 3, 4, 5, 6],
+            singles_indexes: vec![1, 2, 3, 4, 5, 6].into_iter().collect(),
             plugins: NodePluginSet::new(),
             metic: PhantomData,
         }
@@ -731,7 +837,7 @@ mod tests {
         assert_eq!(reconstructed_node.address, (0, 0));
         assert_eq!(reconstructed_node.radius, 1.0);
         assert_eq!(reconstructed_node.coverage_count, 8);
-        assert_eq!(&reconstructed_node.singles_indexes[..], &[4, 5, 6]);
+        assert_eq!(&reconstructed_node.singletons()[..], &[4, 5, 6]);
 
         let reconstructed_children = reconstructed_node.children.unwrap();
         assert_eq!(reconstructed_children.nested_scale, 0);
@@ -751,7 +857,7 @@ mod tests {
         assert_eq!(reconstructed_node.address, (0, 0));
         assert_eq!(reconstructed_node.radius, 1.0);
         assert_eq!(reconstructed_node.coverage_count, 8);
-        assert_eq!(&reconstructed_node.singles_indexes[..], &[1, 2, 3, 4, 5, 6]);
+        assert_eq!(&reconstructed_node.singletons()[..], &[1, 2, 3, 4, 5, 6]);
         assert!(reconstructed_node.children.is_none());
     }
 }
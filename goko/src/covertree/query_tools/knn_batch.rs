@@ -0,0 +1,101 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+use std::iter::FromIterator;
+
+/// The neighbors of a batch of queries, see
+/// [`CoverTreeReader::knn_batch`](crate::covertree::CoverTreeReader::knn_batch). All of them are
+/// kept in two flat arrays, closest first within each query, rather than a vector per query. The
+/// neighbors of query `i` are at `offsets[i]..offsets[i + 1]`. A query has fewer than `k` neighbors
+/// only when the tree has fewer than `k` points.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KnnBatch {
+    distances: Vec<f32>,
+    indexes: Vec<usize>,
+    offsets: Vec<usize>,
+}
+
+impl Default for KnnBatch {
+    fn default() -> Self {
+        KnnBatch {
+            distances: Vec::new(),
+            indexes: Vec::new(),
+            offsets: vec![0],
+        }
+    }
+}
+
+impl KnnBatch {
+    /// The number of queries.
+    pub fn len(&self) -> usize {
+        self.offsets.len() - 1
+    }
+
+    /// If there were no queries.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The distances and point indexes of the neighbors of query `i`.
+    pub fn get(&self, i: usize) -> Option<(&[f32], &[usize])> {
+        let start = *self.offsets.get(i)?;
+        let end = *self.offsets.get(i + 1)?;
+        Some((&self.distances[start..end], &self.indexes[start..end]))
+    }
+
+    /// The neighbors of each query, in order.
+    pub fn iter(&self) -> impl Iterator<Item = (&[f32], &[usize])> + '_ {
+        self.offsets.windows(2).map(move |bounds| {
+            (
+                &self.distances[bounds[0]..bounds[1]],
+                &self.indexes[bounds[0]..bounds[1]],
+            )
+        })
+    }
+
+    /// The distances of all the neighbors.
+    pub fn distances(&self) -> &[f32] {
+        &self.distances
+    }
+
+    /// The point indexes of all the neighbors.
+    pub fn indexes(&self) -> &[usize] {
+        &self.indexes
+    }
+
+    /// Where each query's neighbors start in `distances` and `indexes`, with one more entry at the
+    /// end for where the last query's stop.
+    pub fn offsets(&self) -> &[usize] {
+        &self.offsets
+    }
+}
+
+impl FromIterator<Vec<(f32, usize)>> for KnnBatch {
+    fn from_iter<I: IntoIterator<Item = Vec<(f32, usize)>>>(iter: I) -> Self {
+        let mut batch = KnnBatch::default();
+        for neighbors in iter {
+            for (dist, pi) in neighbors {
+                batch.distances.push(dist);
+                batch.indexes.push(pi);
+            }
+            batch.offsets.push(batch.indexes.len());
+        }
+        batch
+    }
+}
@@ -20,6 +20,7 @@
 //! Tools and data structures for assisting cover tree queries.
 
 use crate::NodeAddress;
+use roaring::RoaringBitmap;
 use std::collections::{BinaryHeap, HashMap, HashSet};
 use std::f32;
 
@@ -39,15 +40,31 @@ use super::query_items::{QueryAddress, QuerySingleton};
 /// To help with double inserts (easy due to a node's central point's index being repeated througout the tree), we also have a HashSet of visited points.
 /// We reject a node insert if it's central point index is in this hashset.
 ///
+/// Nodes can also be marked as visited, which skips them and so their whole subtree. This is for queries that search
+/// part of the tree first and then start over from the root with the distance bound they already have.
+///
+/// The heap can also have a radius, then only points no farther than that are kept and nodes that can't cover such a
+/// point are never pushed. The two bounds prune together, whichever is tighter.
+///
+/// The result can be restricted to a set of allowed points, for queries scoped to some partitions of the data. Nodes
+/// are still searched, only the points outside the set are left out.
+///
+/// Finally the heap can allow an error `epsilon`, for approximate queries. A node is then dropped once nothing it
+/// covers could be closer than the current `k`th distance divided by `1 + epsilon`.
+///
 #[derive(Debug)]
 pub struct KnnQueryHeap {
     child_heap: BinaryHeap<QueryAddress>,
     singleton_heap: BinaryHeap<QueryAddress>,
 
     known_indexes: HashSet<usize>,
+    allowed: Option<RoaringBitmap>,
+    visited: HashSet<NodeAddress>,
     est_min_dist: HashMap<NodeAddress, f32>,
     dist_heap: BinaryHeap<QuerySingleton>,
     k: usize,
+    radius: f32,
+    error: f32,
     scale_base: f32,
 }
 
@@ -59,27 +76,99 @@ impl RoutingQueryHeap for KnnQueryHeap {
         dists: &[f32],
         parent_address: Option<NodeAddress>,
     ) {
+        let scale_base = self.scale_base;
+        self.push_scored_nodes(
+            indexes,
+            dists,
+            |(si, _)| scale_base.powi(si),
+            |_, d| d,
+            parent_address,
+        );
+    }
+}
+
+impl SingletonQueryHeap for KnnQueryHeap {
+    /// Shove a bunch of single points onto the heap
+    fn push_outliers(&mut self, indexes: &[usize], dists: &[f32]) {
+        self.push_scored_outliers(indexes, dists, |_, d| d);
+    }
+}
+
+impl KnnQueryHeap {
+    /// Creates a new KNN heap. The K is obvious, but the `scale_base` is for the
+    /// minimum distance from our query point to potential covered points of a node.
+    pub fn new(k: usize, scale_base: f32) -> KnnQueryHeap {
+        KnnQueryHeap::with_radius(k, std::f32::MAX, scale_base)
+    }
+
+    /// Creates a KNN heap that only keeps points no farther than `radius` from the query point.
+    pub fn with_radius(k: usize, radius: f32, scale_base: f32) -> KnnQueryHeap {
+        KnnQueryHeap {
+            child_heap: BinaryHeap::new(),
+            singleton_heap: BinaryHeap::new(),
+            est_min_dist: HashMap::new(),
+            dist_heap: BinaryHeap::new(),
+            known_indexes: HashSet::new(),
+            allowed: None,
+            visited: HashSet::new(),
+            k,
+            radius,
+            error: 0.0,
+            scale_base,
+        }
+    }
+
+    /// `push_nodes` with the nodes' radii given by `radius` instead of `scale_base^scale_index`. This is for queries
+    /// under a distance that has tighter bounds on a node than the tree's metric, like a masked one. The radius has to
+    /// bound the distance from the node's center to everything it covers.
+    pub fn push_nodes_with_radius<F: Fn(NodeAddress) -> f32>(
+        &mut self,
+        indexes: &[NodeAddress],
+        dists: &[f32],
+        radius: F,
+        parent_address: Option<NodeAddress>,
+    ) {
+        self.push_scored_nodes(indexes, dists, radius, |_, d| d, parent_address);
+    }
+
+    /// `push_nodes_with_radius` where the points are ranked by a score, a function of the point's index and its
+    /// distance to the query, instead of by the distance. The score can't be less than the distance, so that the
+    /// distance to a node bounds the scores of everything it covers. The kept scores are what `unpack` returns.
+    pub fn push_scored_nodes<F, S>(
+        &mut self,
+        indexes: &[NodeAddress],
+        dists: &[f32],
+        radius: F,
+        score: S,
+        parent_address: Option<NodeAddress>,
+    ) where
+        F: Fn(NodeAddress) -> f32,
+        S: Fn(usize, f32) -> f32,
+    {
         let mut max_dist = self.max_dist();
         let mut parent_est_dist_update = 0.0;
         for ((si, pi), d) in indexes.iter().zip(dists) {
-            let emd = (d - self.scale_base.powi(*si)).max(0.0);
+            let emd = (d - radius((*si, *pi))).max(0.0);
             parent_est_dist_update = emd.max(parent_est_dist_update);
-            if emd < max_dist {
+            if emd * (1.0 + self.error) < max_dist
+                || (emd <= self.radius && self.dist_heap.len() < self.k)
+            {
                 self.child_heap.push(QueryAddress {
                     address: (*si, *pi),
                     dist_to_center: *d,
                     min_dist: emd,
                 });
             }
-            if !self.known_indexes.contains(pi) {
+            let d = score(*pi, *d);
+            if d <= self.radius && !self.known_indexes.contains(pi) && self.is_allowed(*pi) {
                 self.known_indexes.insert(*pi);
                 match self.dist_heap.peek() {
                     Some(my_dist) => {
-                        if !(my_dist.dist < *d && self.dist_heap.len() >= self.k) {
-                            self.dist_heap.push(QuerySingleton::new(*pi, *d));
+                        if !(my_dist.dist < d && self.dist_heap.len() >= self.k) {
+                            self.dist_heap.push(QuerySingleton::new(*pi, d));
                         }
                     }
-                    None => self.dist_heap.push(QuerySingleton::new(*pi, *d)),
+                    None => self.dist_heap.push(QuerySingleton::new(*pi, d)),
                 };
             }
             while self.dist_heap.len() > self.k {
@@ -92,21 +181,25 @@ impl RoutingQueryHeap for KnnQueryHeap {
             self.increase_estimated_distance(a, parent_est_dist_update);
         }
     }
-}
 
-impl SingletonQueryHeap for KnnQueryHeap {
-    /// Shove a bunch of single points onto the heap
-    fn push_outliers(&mut self, indexes: &[usize], dists: &[f32]) {
+    /// `push_outliers` where the points are ranked by a score instead of by their distance, see `push_scored_nodes`.
+    pub fn push_scored_outliers<S: Fn(usize, f32) -> f32>(
+        &mut self,
+        indexes: &[usize],
+        dists: &[f32],
+        score: S,
+    ) {
         for (i, d) in indexes.iter().zip(dists) {
-            if !self.known_indexes.contains(i) {
+            let d = score(*i, *d);
+            if d <= self.radius && !self.known_indexes.contains(i) && self.is_allowed(*i) {
                 self.known_indexes.insert(*i);
                 match self.dist_heap.peek() {
                     Some(my_dist) => {
-                        if !(my_dist.dist < *d && self.dist_heap.len() >= self.k) {
-                            self.dist_heap.push(QuerySingleton::new(*i, *d));
+                        if !(my_dist.dist < d && self.dist_heap.len() >= self.k) {
+                            self.dist_heap.push(QuerySingleton::new(*i, d));
                         }
                     }
-                    None => self.dist_heap.push(QuerySingleton::new(*i, *d)),
+                    None => self.dist_heap.push(QuerySingleton::new(*i, d)),
                 };
                 while self.dist_heap.len() > self.k {
                     self.dist_heap.pop();
@@ -114,27 +207,15 @@ impl SingletonQueryHeap for KnnQueryHeap {
             }
         }
     }
-}
-
-impl KnnQueryHeap {
-    /// Creates a new KNN heap. The K is obvious, but the `scale_base` is for the
-    /// minimum distance from our query point to potential covered points of a node.
-    pub fn new(k: usize, scale_base: f32) -> KnnQueryHeap {
-        KnnQueryHeap {
-            child_heap: BinaryHeap::new(),
-            singleton_heap: BinaryHeap::new(),
-            est_min_dist: HashMap::new(),
-            dist_heap: BinaryHeap::new(),
-            known_indexes: HashSet::new(),
-            k,
-            scale_base,
-        }
-    }
 
     /// Finds the closest node who could have a child node at least the current kth furthest distance away from the query point.
     /// This pops that node and pushes it onto the singleton heap.
     pub fn closest_unvisited_child_covering_address(&mut self) -> Option<(f32, NodeAddress)> {
         while let Some(mut node_to_visit) = self.child_heap.pop() {
+            if self.was_visited(node_to_visit.address) || self.out_of_reach(node_to_visit.min_dist)
+            {
+                continue;
+            }
             if let Some(min_dist_update) = self.est_min_dist.remove(&node_to_visit.address) {
                 if min_dist_update > node_to_visit.min_dist {
                     node_to_visit.min_dist = min_dist_update;
@@ -155,6 +236,10 @@ impl KnnQueryHeap {
     /// This pops the node and sends it to oblivion.
     pub fn closest_unvisited_singleton_covering_address(&mut self) -> Option<(f32, NodeAddress)> {
         while let Some(mut node_to_visit) = self.singleton_heap.pop() {
+            if self.was_visited(node_to_visit.address) || self.out_of_reach(node_to_visit.min_dist)
+            {
+                continue;
+            }
             if let Some(min_dist_update) = self.est_min_dist.remove(&node_to_visit.address) {
                 if min_dist_update > node_to_visit.min_dist {
                     node_to_visit.min_dist = min_dist_update;
@@ -169,6 +254,49 @@ impl KnnQueryHeap {
         None
     }
 
+    /// Marks a node as already searched. It won't be returned by either `closest_unvisited_*` query, so neither its
+    /// singletons nor anything under it is searched again.
+    pub fn mark_visited(&mut self, address: NodeAddress) {
+        self.visited.insert(address);
+    }
+
+    /// Leaves these points out of the result. Nodes centered on them are still searched, so the points they cover
+    /// can still be found. For deleted points.
+    pub fn exclude<I: IntoIterator<Item = usize>>(&mut self, indexes: I) {
+        self.known_indexes.extend(indexes);
+    }
+
+    /// Allows the result to be off by a factor of `1 + epsilon`: the `k`th distance found is at most that many times the
+    /// true `k`th distance. Nodes are pruned by the current `k`th distance divided by `1 + epsilon`, so fewer are
+    /// searched. Negative values are treated as 0, an exact query.
+    pub fn allow_error(&mut self, epsilon: f32) {
+        self.error = epsilon.max(0.0);
+    }
+
+    /// Leaves every point that isn't in `allowed` out of the result.
+    pub fn restrict_to(&mut self, allowed: RoaringBitmap) {
+        self.allowed = Some(allowed);
+    }
+
+    fn is_allowed(&self, pi: usize) -> bool {
+        match &self.allowed {
+            Some(allowed) => allowed.contains(pi as u32),
+            None => true,
+        }
+    }
+
+    /// If a node this far away can be dropped under the allowed error. Exact queries never drop a node once it's on
+    /// the heap.
+    fn out_of_reach(&self, min_dist: f32) -> bool {
+        self.error > 0.0
+            && self.dist_heap.len() >= self.k
+            && min_dist * (1.0 + self.error) >= self.max_dist()
+    }
+
+    fn was_visited(&self, address: NodeAddress) -> bool {
+        !self.visited.is_empty() && self.visited.contains(&address)
+    }
+
     /// The current number of points on the distance heap
     pub fn len(&self) -> usize {
         self.dist_heap.len()
@@ -184,12 +312,13 @@ impl KnnQueryHeap {
         self.child_heap.len() + self.singleton_heap.len()
     }
 
-    /// The current maximum distance to the query point. If the distance heap isn't full it returns the maximum float value.
+    /// The current maximum distance to the query point. If the distance heap isn't full it returns the radius, which is
+    /// the maximum float value unless the heap was made with one.
     pub fn max_dist(&self) -> f32 {
         if self.len() < self.k {
-            std::f32::MAX
+            self.radius
         } else {
-            self.dist_heap.peek().map(|x| x.dist).unwrap_or(f32::MAX)
+            self.dist_heap.peek().map(|x| x.dist).unwrap_or(self.radius)
         }
     }
 
@@ -233,6 +362,19 @@ pub(crate) mod tests {
         }
     }
 
+    #[test]
+    fn ties_are_broken_by_index() {
+        let mut forward = KnnQueryHeap::new(3, 2.0);
+        forward.push_outliers(&[1, 2, 3, 4, 5], &[0.5, 0.5, 0.5, 0.5, 0.1]);
+        let mut backward = KnnQueryHeap::new(3, 2.0);
+        backward.push_outliers(&[5, 4, 3, 2, 1], &[0.1, 0.5, 0.5, 0.5, 0.5]);
+        let forward = forward.unpack();
+        let backward = backward.unpack();
+        assert_eq!(forward, backward);
+        let indexes: Vec<usize> = forward.iter().map(|(_, i)| *i).collect();
+        assert_eq!(indexes, vec![5, 1, 2]);
+    }
+
     pub fn clone_unvisited_nodes(heap: &KnnQueryHeap) -> Vec<(f32, NodeAddress)> {
         let mut all_nodes: Vec<QueryAddress> = heap.child_heap.iter().cloned().collect();
         all_nodes.extend(heap.singleton_heap.iter().cloned());
@@ -240,6 +382,26 @@ pub(crate) mod tests {
         all_nodes.sort();
         all_nodes.iter().map(|n| (n.min_dist, n.address)).collect()
     }
+    #[test]
+    fn excluded_points_are_skipped() {
+        let mut heap = KnnQueryHeap::new(2, 2.0);
+        heap.exclude(vec![2, 5]);
+        heap.push_outliers(&[2, 4, 6], &[0.2, 0.4, 0.6]);
+        heap.push_nodes(&[(0, 5)], &[0.1], None);
+        let unpack = heap.unpack();
+        assert_eq!(unpack, vec![(0.4, 4), (0.6, 6)]);
+    }
+
+    #[test]
+    fn points_outside_the_allowed_set_are_skipped() {
+        let mut heap = KnnQueryHeap::new(2, 2.0);
+        heap.restrict_to([4u32, 5, 8].iter().copied().collect());
+        heap.push_outliers(&[2, 4, 6, 8], &[0.2, 0.4, 0.6, 0.8]);
+        heap.push_nodes(&[(0, 5)], &[0.5], None);
+        let unpack = heap.unpack();
+        assert_eq!(unpack, vec![(0.4, 4), (0.5, 5)]);
+    }
+
     /*
         #[test]
         fn level_grab_is_correct() {
@@ -23,6 +23,9 @@ use crate::NodeAddress;
 
 pub(crate) mod query_items;
 
+pub(crate) mod knn_batch;
+pub use knn_batch::KnnBatch;
+
 pub(crate) mod knn_query_heap;
 pub use knn_query_heap::KnnQueryHeap;
 pub(crate) mod trace_query_heap;
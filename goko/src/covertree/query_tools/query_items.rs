@@ -55,7 +55,11 @@ impl PartialOrd for QueryAddress {
             Ordering::Equal => match other.address.0.cmp(&self.address.0) {
                 Ordering::Greater => Some(Ordering::Greater),
                 Ordering::Less => Some(Ordering::Less),
-                Ordering::Equal => other.dist_to_center.partial_cmp(&self.dist_to_center),
+                // Break exact ties by the center index so traversal order is reproducible
+                Ordering::Equal => match other.dist_to_center.partial_cmp(&self.dist_to_center) {
+                    Some(Ordering::Equal) => Some(other.address.1.cmp(&self.address.1)),
+                    ord => ord,
+                },
             },
         }
     }
@@ -95,7 +99,10 @@ impl PartialOrd for QueryAddressRev {
             Ordering::Equal => match self.address.0.cmp(&other.address.0) {
                 Ordering::Greater => Some(Ordering::Greater),
                 Ordering::Less => Some(Ordering::Less),
-                Ordering::Equal => self.dist_to_center.partial_cmp(&other.dist_to_center),
+                Ordering::Equal => match self.dist_to_center.partial_cmp(&other.dist_to_center) {
+                    Some(Ordering::Equal) => Some(self.address.1.cmp(&other.address.1)),
+                    ord => ord,
+                },
             },
         }
     }
@@ -128,7 +135,12 @@ impl Ord for QuerySingleton {
 }
 
 impl PartialOrd for QuerySingleton {
+    /// Ordered by distance, then by index. Points at the same distance are kept or dropped by
+    /// their index so that knn results don't depend on the order they were found in.
     fn partial_cmp(&self, other: &QuerySingleton) -> Option<Ordering> {
-        self.dist.partial_cmp(&other.dist)
+        match self.dist.partial_cmp(&other.dist) {
+            Some(Ordering::Equal) => Some(self.index.cmp(&other.index)),
+            ord => ord,
+        }
     }
 }
@@ -0,0 +1,608 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! Deciding when an edited tree should be rebuilt, and rebuilding it in the background.
+//!
+//! Incremental edits keep a tree valid, but it drifts away from what a fresh build over the same
+//! points would produce. [`TreeQuality`] measures how far, [`RebuildPolicy`] compares that to the
+//! quality right after the last build, and [`RebuildingTree`] rebuilds on another thread when the
+//! policy says so and swaps the new tree in once it's done. [`CoverTreeWriter::refine`] is the
+//! local version, it only rebuilds the subtrees that a [`RefinePolicy`] flags.
+
+use crate::errors::{GokoError, GokoResult};
+use crate::plugins::coverage_drift::{CoverageDriftAlarm, CoverageDriftPlugin};
+use crate::*;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// Measurements of a tree's structure that degrade as it's edited.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TreeQuality {
+    /// The number of nodes on the longest path from the root to a leaf
+    pub depth: usize,
+    /// The number of nodes in the tree
+    pub node_count: usize,
+    /// The mean, over routing nodes, of the fraction of the node's coverage held by its most
+    /// populated child. 1 means every routing node hands almost everything to one child.
+    pub imbalance: f32,
+    /// The fraction of nodes whose radius is larger than their scale
+    pub stale_radius_fraction: f32,
+    /// The fraction of nodes flagged by the coverage drift alarm. This is 0 if the
+    /// [`CoverageDriftPlugin`] isn't attached.
+    pub drifted_fraction: f32,
+}
+
+impl TreeQuality {
+    /// Measures a tree. The drift alarm is used to count the drifted nodes.
+    pub fn measure<D: PointCloud>(
+        reader: &CoverTreeReader<D>,
+        drift_alarm: &CoverageDriftAlarm,
+    ) -> TreeQuality {
+        let mut depths: HashMap<NodeAddress, usize> = HashMap::new();
+        depths.insert(reader.root_address(), 1);
+        let mut depth = 0;
+        let mut node_count = 0;
+        let mut imbalance_sum = 0.0;
+        let mut routing_count = 0;
+        let mut stale_count = 0;
+        let mut drifted_count = 0;
+        // Layers come from the root down, so parents are seen before their children
+        for (scale_index, layer) in reader.layers() {
+            let scale = reader.scale(scale_index);
+            layer.for_each_node(|pi, n| {
+                let node_depth = depths.get(&(scale_index, *pi)).copied().unwrap_or(1);
+                node_count += 1;
+                depth = depth.max(node_depth);
+                if n.radius() > scale {
+                    stale_count += 1;
+                }
+                if drift_alarm.check_node(n).is_some() {
+                    drifted_count += 1;
+                }
+                if let Some((nested_scale, children)) = n.children() {
+                    let mut largest = 0;
+                    for child in children.iter().chain(Some(&(nested_scale, *pi))) {
+                        depths.insert(*child, node_depth + 1);
+                        let coverage = reader
+                            .get_node_and(*child, |c| c.coverage_count())
+                            .unwrap_or(0);
+                        largest = largest.max(coverage);
+                    }
+                    if n.coverage_count() > 0 {
+                        imbalance_sum += largest as f32 / n.coverage_count() as f32;
+                        routing_count += 1;
+                    }
+                }
+            });
+        }
+        let fraction = |count: usize| {
+            if node_count > 0 {
+                count as f32 / node_count as f32
+            } else {
+                0.0
+            }
+        };
+        TreeQuality {
+            depth,
+            node_count,
+            imbalance: if routing_count > 0 {
+                imbalance_sum / routing_count as f32
+            } else {
+                0.0
+            },
+            stale_radius_fraction: fraction(stale_count),
+            drifted_fraction: fraction(drifted_count),
+        }
+    }
+}
+
+/// Why a [`RebuildPolicy`] asked for a rebuild.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RebuildReason {
+    /// The tree got deeper than allowed
+    DepthGrowth {
+        /// The depth after the last build
+        baseline: usize,
+        /// The depth now
+        current: usize,
+    },
+    /// The tree got more unbalanced than allowed
+    Imbalance {
+        /// The imbalance after the last build
+        baseline: f32,
+        /// The imbalance now
+        current: f32,
+    },
+    /// Too many nodes have a radius larger than their scale
+    StaleRadii(f32),
+    /// Too many nodes drifted away from their baseline population
+    CoverageDrift(f32),
+}
+
+/// Decides when a tree has degraded enough to be rebuilt, by comparing its quality to the quality
+/// right after it was built.
+#[derive(Debug, Clone)]
+pub struct RebuildPolicy {
+    /// Rebuild once the tree is this many levels deeper than it was
+    pub max_depth_growth: usize,
+    /// Rebuild once the imbalance has grown by this much
+    pub max_imbalance_growth: f32,
+    /// Rebuild once more than this fraction of nodes have stale radii
+    pub max_stale_radius_fraction: f32,
+    /// Rebuild once more than this fraction of nodes have drifted
+    pub max_drifted_fraction: f32,
+    /// The alarm that decides which nodes drifted
+    pub drift_alarm: CoverageDriftAlarm,
+}
+
+impl Default for RebuildPolicy {
+    fn default() -> Self {
+        RebuildPolicy {
+            max_depth_growth: 4,
+            max_imbalance_growth: 0.1,
+            max_stale_radius_fraction: 0.05,
+            max_drifted_fraction: 0.05,
+            drift_alarm: CoverageDriftAlarm::default(),
+        }
+    }
+}
+
+impl RebuildPolicy {
+    /// Measures the tree with this policy's drift alarm.
+    pub fn measure<D: PointCloud>(&self, reader: &CoverTreeReader<D>) -> TreeQuality {
+        TreeQuality::measure(reader, &self.drift_alarm)
+    }
+
+    /// Returns the first reason to rebuild, or `None` if the tree is still good.
+    pub fn check(&self, baseline: &TreeQuality, current: &TreeQuality) -> Option<RebuildReason> {
+        if current.depth > baseline.depth + self.max_depth_growth {
+            return Some(RebuildReason::DepthGrowth {
+                baseline: baseline.depth,
+                current: current.depth,
+            });
+        }
+        if current.imbalance > baseline.imbalance + self.max_imbalance_growth {
+            return Some(RebuildReason::Imbalance {
+                baseline: baseline.imbalance,
+                current: current.imbalance,
+            });
+        }
+        if current.stale_radius_fraction > self.max_stale_radius_fraction {
+            return Some(RebuildReason::StaleRadii(current.stale_radius_fraction));
+        }
+        if current.drifted_fraction > self.max_drifted_fraction {
+            return Some(RebuildReason::CoverageDrift(current.drifted_fraction));
+        }
+        None
+    }
+}
+
+/// Why [`CoverTreeWriter::refine`] picked a subtree.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RefineReason {
+    /// One of the node's children has a radius larger than its scale allows
+    StaleRadius,
+    /// The node is a leaf holding this many singletons
+    Overloaded(usize),
+    /// The node is the top of a chain of this many nodes whose only child is their nested child
+    Chain(usize),
+}
+
+/// Decides which subtrees [`CoverTreeWriter::refine`] rebuilds. Unlike the [`RebuildPolicy`] this
+/// looks at single nodes rather than the whole tree.
+#[derive(Debug, Clone)]
+pub struct RefinePolicy {
+    /// Rebuild a node when one of its children has a radius more than this many times the child's scale
+    pub max_radius_ratio: f32,
+    /// Rebuild a leaf when it holds more than this many times the tree's `leaf_cutoff` in singletons
+    pub max_leaf_overload: f32,
+    /// Rebuild the top of a chain of more than this many nodes that only have a nested child
+    pub max_chain_length: usize,
+    /// Leave subtrees covering more than this fraction of the tree alone, they're better off with
+    /// a full rebuild
+    pub max_rebuild_fraction: f32,
+}
+
+impl Default for RefinePolicy {
+    fn default() -> Self {
+        RefinePolicy {
+            max_radius_ratio: 1.0,
+            max_leaf_overload: 2.0,
+            max_chain_length: 8,
+            max_rebuild_fraction: 0.25,
+        }
+    }
+}
+
+/// What [`CoverTreeWriter::refine`] did.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RefineReport {
+    /// The roots of the subtrees that were rebuilt, and why
+    pub rebuilt: Vec<(NodeAddress, RefineReason)>,
+    /// The roots of the subtrees that needed a rebuild but covered too much of the tree
+    pub skipped: Vec<(NodeAddress, RefineReason)>,
+}
+
+impl<D: PointCloud> CoverTreeWriter<D> {
+    /// Rebuilds the subtrees that incremental edits have degraded, like compaction in an LSM tree.
+    /// The tree is walked from the root and the first node on each path that the policy flags is
+    /// re-clustered with [`CoverTreeWriter::split_node`], so the rebuilt subtrees never overlap.
+    /// Each rebuild is published on its own, readers keep serving from the rest of the tree while
+    /// the pass runs.
+    ///
+    /// A node with a stale child is rebuilt rather than the child, as a node keeps its scale when
+    /// it's rebuilt and only its parent can spread the child's points out over new nodes. The root
+    /// can't be fixed that way, a root with a stale radius needs a full rebuild.
+    pub fn refine(&mut self, policy: &RefinePolicy) -> GokoResult<RefineReport> {
+        let reader = self.reader();
+        let root = reader.root_address();
+        let total_coverage = reader
+            .get_node_and(root, |n| n.coverage_count())
+            .ok_or(GokoError::NodeNotInTree(root))?;
+        let leaf_cutoff = self.parameters.leaf_cutoff;
+        let min_res_index = self.parameters.min_res_index;
+        let max_singletons = (policy.max_leaf_overload * leaf_cutoff.max(1) as f32) as usize;
+        let is_stale = |address: NodeAddress| -> GokoResult<bool> {
+            reader
+                .get_node_and(address, |n| {
+                    n.radius() > policy.max_radius_ratio * reader.scale(address.0)
+                })
+                .ok_or(GokoError::NodeNotInTree(address))
+        };
+
+        let mut report = RefineReport::default();
+        // Each node is visited with whether it's the nested child of a node that starts or
+        // continues a chain
+        let mut unvisited = vec![(root, false)];
+        while let Some((address, in_chain)) = unvisited.pop() {
+            let (coverage, singletons, children) = reader
+                .get_node_and(address, |n| {
+                    (
+                        n.coverage_count(),
+                        n.singletons_len(),
+                        n.children().map(|(ns, c)| (ns, Vec::from(c))),
+                    )
+                })
+                .ok_or(GokoError::NodeNotInTree(address))?;
+            let chained = matches!(&children, Some((_, c)) if c.is_empty());
+            let mut reason = None;
+            match &children {
+                None => {
+                    if address.0 >= min_res_index && singletons > max_singletons {
+                        reason = Some(RefineReason::Overloaded(singletons));
+                    }
+                }
+                Some((nested_scale, routing)) => {
+                    for child in routing.iter().chain(Some(&(*nested_scale, address.1))) {
+                        if is_stale(*child)? {
+                            reason = Some(RefineReason::StaleRadius);
+                            break;
+                        }
+                    }
+                }
+            }
+            if reason.is_none() && chained && !in_chain {
+                let mut length = 1;
+                let mut bottom = address;
+                while let Some(Some(nested_scale)) = reader.get_node_and(bottom, |n| {
+                    n.children().filter(|(_, c)| c.is_empty()).map(|(ns, _)| ns)
+                }) {
+                    bottom = (nested_scale, bottom.1);
+                    length += 1;
+                }
+                if length > policy.max_chain_length {
+                    reason = Some(RefineReason::Chain(length));
+                }
+            }
+
+            match reason {
+                Some(reason)
+                    if coverage >= 2
+                        && coverage as f32
+                            <= policy.max_rebuild_fraction * total_coverage as f32 =>
+                {
+                    report.rebuilt.push((address, reason));
+                }
+                reason => {
+                    if let Some(reason) = reason {
+                        report.skipped.push((address, reason));
+                    }
+                    if let Some((nested_scale, routing)) = children {
+                        unvisited.push(((nested_scale, address.1), chained));
+                        unvisited.extend(routing.into_iter().map(|ca| (ca, false)));
+                    }
+                }
+            }
+        }
+        drop(reader);
+
+        for (address, _) in &report.rebuilt {
+            self.split_node(*address, leaf_cutoff)?;
+        }
+        Ok(report)
+    }
+}
+
+type PrepareFn<D> = Box<dyn Fn(&mut CoverTreeWriter<D>) + Send + Sync>;
+
+struct CurrentTree<D: PointCloud> {
+    writer: CoverTreeWriter<D>,
+    baseline: TreeQuality,
+}
+
+/// A tree that's rebuilt in the background when its [`RebuildPolicy`] says so. Readers are handed
+/// out from whichever tree is current, and the new tree is swapped in all at once when its build
+/// is done. Readers handed out before the swap keep reading the old tree.
+///
+/// Edits made to the old tree while a rebuild is running are not carried over to the new tree.
+/// Changes to the point cloud are, as the new tree is built over the old tree's point cloud.
+pub struct RebuildingTree<D: PointCloud> {
+    builder: CoverTreeBuilder,
+    policy: RebuildPolicy,
+    prepare: PrepareFn<D>,
+    current: Mutex<CurrentTree<D>>,
+    rebuilding: AtomicBool,
+    generation: AtomicUsize,
+}
+
+impl<D: PointCloud> RebuildingTree<D> {
+    /// Builds the first tree. `prepare` is run on every new tree before it's swapped in, use it to
+    /// attach plugins. The [`CoverageDriftPlugin`] is attached after `prepare`.
+    pub fn new<F>(
+        builder: CoverTreeBuilder,
+        policy: RebuildPolicy,
+        point_cloud: Arc<D>,
+        prepare: F,
+    ) -> GokoResult<Arc<RebuildingTree<D>>>
+    where
+        F: Fn(&mut CoverTreeWriter<D>) + Send + Sync + 'static,
+    {
+        let prepare: PrepareFn<D> = Box::new(prepare);
+        let current = Self::build(&builder, &policy, &prepare, point_cloud)?;
+        Ok(Arc::new(RebuildingTree {
+            builder,
+            policy,
+            prepare,
+            current: Mutex::new(current),
+            rebuilding: AtomicBool::new(false),
+            generation: AtomicUsize::new(0),
+        }))
+    }
+
+    fn build(
+        builder: &CoverTreeBuilder,
+        policy: &RebuildPolicy,
+        prepare: &PrepareFn<D>,
+        point_cloud: Arc<D>,
+    ) -> GokoResult<CurrentTree<D>> {
+        let mut writer = builder.build(point_cloud)?;
+        prepare(&mut writer);
+        writer.add_plugin::<CoverageDriftPlugin>(CoverageDriftPlugin::default());
+        let baseline = policy.measure(&writer.reader());
+        Ok(CurrentTree { writer, baseline })
+    }
+
+    /// A reader for the current tree.
+    pub fn reader(&self) -> CoverTreeReader<D> {
+        self.current.lock().unwrap().writer.reader()
+    }
+
+    /// Edits the current tree.
+    pub fn with_writer<F, T>(&self, f: F) -> T
+    where
+        F: FnOnce(&mut CoverTreeWriter<D>) -> T,
+    {
+        f(&mut self.current.lock().unwrap().writer)
+    }
+
+    /// The number of times the tree has been swapped out.
+    pub fn generation(&self) -> usize {
+        self.generation.load(Ordering::SeqCst)
+    }
+
+    /// If a rebuild is running.
+    pub fn is_rebuilding(&self) -> bool {
+        self.rebuilding.load(Ordering::SeqCst)
+    }
+
+    /// The quality of the current tree, and the quality it had right after it was built.
+    pub fn quality(&self) -> (TreeQuality, TreeQuality) {
+        let current = self.current.lock().unwrap();
+        (
+            self.policy.measure(&current.writer.reader()),
+            current.baseline.clone(),
+        )
+    }
+
+    /// Checks the current tree against the policy.
+    pub fn check(&self) -> Option<RebuildReason> {
+        let (quality, baseline) = self.quality();
+        self.policy.check(&baseline, &quality)
+    }
+
+    /// Builds a new tree on this thread and swaps it in.
+    pub fn rebuild_now(&self) -> GokoResult<()> {
+        let point_cloud = Arc::clone(&self.current.lock().unwrap().writer.parameters.point_cloud);
+        let new_tree = Self::build(&self.builder, &self.policy, &self.prepare, point_cloud)?;
+        *self.current.lock().unwrap() = new_tree;
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Checks the current tree against the policy and, if it needs it and no rebuild is running,
+    /// starts one on a background thread. Returns the reason a rebuild was started.
+    pub fn check_and_rebuild(self: &Arc<Self>) -> Option<RebuildReason> {
+        let reason = self.check()?;
+        if self
+            .rebuilding
+            .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+            .is_err()
+        {
+            return None;
+        }
+        let tree = Arc::clone(self);
+        thread::spawn(move || {
+            if let Err(e) = tree.rebuild_now() {
+                if tree.builder.verbosity > 0 {
+                    println!("Background rebuild failed: {}", e);
+                }
+            }
+            tree.rebuilding.store(false, Ordering::SeqCst);
+        });
+        Some(reason)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::covertree::tests::build_basic_tree;
+    use std::time::Duration;
+
+    #[test]
+    fn fresh_tree_needs_no_rebuild() {
+        let tree = build_basic_tree();
+        let policy = RebuildPolicy::default();
+        let quality = policy.measure(&tree.reader());
+        assert!(quality.depth > 1);
+        assert_eq!(quality.node_count, tree.reader().node_count());
+        assert!(quality.imbalance > 0.0 && quality.imbalance <= 1.0);
+        assert_eq!(quality.stale_radius_fraction, 0.0);
+        assert!(policy.check(&quality, &quality).is_none());
+    }
+
+    #[test]
+    fn policy_flags_degraded_trees() {
+        let policy = RebuildPolicy::default();
+        let baseline = TreeQuality {
+            depth: 5,
+            node_count: 100,
+            imbalance: 0.5,
+            stale_radius_fraction: 0.0,
+            drifted_fraction: 0.0,
+        };
+        let mut current = baseline.clone();
+        current.depth = 10;
+        assert_eq!(
+            policy.check(&baseline, &current),
+            Some(RebuildReason::DepthGrowth {
+                baseline: 5,
+                current: 10
+            })
+        );
+        let mut current = baseline.clone();
+        current.stale_radius_fraction = 0.2;
+        assert_eq!(
+            policy.check(&baseline, &current),
+            Some(RebuildReason::StaleRadii(0.2))
+        );
+        let mut current = baseline.clone();
+        current.drifted_fraction = 0.2;
+        assert_eq!(
+            policy.check(&baseline, &current),
+            Some(RebuildReason::CoverageDrift(0.2))
+        );
+    }
+
+    #[test]
+    fn refine_splits_overloaded_leaves() {
+        let data = vec![0.499, 0.49, 0.48, -0.49, 0.0];
+        let labels = vec![0, 0, 0, 1, 1];
+        let point_cloud = DefaultLabeledCloud::<L2>::new_simple(data, 1, labels);
+        let mut builder = CoverTreeBuilder::new();
+        builder.set_leaf_cutoff(5).set_rng_seed(0);
+        let mut tree = builder.build(Arc::new(point_cloud)).unwrap();
+        tree.generate_summaries();
+        let root = tree.reader().root_address();
+        assert!(tree.reader().get_node_and(root, |n| n.is_leaf()).unwrap());
+
+        let points: Vec<f32> = (0..10).map(|i| -0.44 + 0.09 * i as f32).collect();
+        let label = 1i64;
+        tree.extend(&points, &vec![Some(&label); 10]).unwrap();
+
+        // The root covers the whole tree, so the default policy leaves it to a full rebuild
+        let report = tree.refine(&RefinePolicy::default()).unwrap();
+        assert!(report.rebuilt.is_empty());
+        assert_eq!(report.skipped.len(), 1);
+        assert_eq!(report.skipped[0].0, root);
+        assert!(matches!(report.skipped[0].1, RefineReason::Overloaded(_)));
+
+        let policy = RefinePolicy {
+            max_rebuild_fraction: 1.0,
+            ..RefinePolicy::default()
+        };
+        let report = tree.refine(&policy).unwrap();
+        assert_eq!(report.rebuilt.len(), 1);
+        let reader = tree.reader();
+        assert!(reader.no_dangling_refs());
+        assert!(!reader.get_node_and(root, |n| n.is_leaf()).unwrap());
+        assert_eq!(reader.get_node_and(root, |n| n.coverage_count()), Some(15));
+        assert_eq!(reader.get_node_label_summary(root).unwrap().count(), 15);
+        for pi in 0..15 {
+            assert!(reader.known_path(pi).is_ok());
+        }
+        for (i, x) in points.iter().enumerate() {
+            assert_eq!(reader.knn(&[*x].as_ref(), 1).unwrap()[0], (0.0, 5 + i));
+        }
+        drop(reader);
+        assert_eq!(tree.refine(&policy).unwrap(), RefineReport::default());
+    }
+
+    #[test]
+    fn rebuilds_in_the_background() {
+        let point_cloud = Arc::clone(&build_basic_tree().parameters.point_cloud);
+        let mut builder = CoverTreeBuilder::new();
+        builder
+            .set_scale_base(2.0)
+            .set_leaf_cutoff(1)
+            .set_min_res_index(-9)
+            .set_rng_seed(0);
+        let mut policy = RebuildPolicy::default();
+        policy.drift_alarm.set_min_coverage(0);
+        let tree = RebuildingTree::new(builder, policy, point_cloud, |writer| {
+            writer.generate_summaries()
+        })
+        .unwrap();
+        assert!(tree.check_and_rebuild().is_none());
+
+        // Drift the root's population far from its baseline
+        tree.with_writer(|writer| {
+            let root_address = writer.reader().root_address();
+            unsafe {
+                writer.update_node(root_address, |n| n.set_coverage_count(1000));
+            }
+            writer.refresh();
+        });
+        assert!(matches!(
+            tree.check_and_rebuild(),
+            Some(RebuildReason::CoverageDrift(_))
+        ));
+        while tree.is_rebuilding() {
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(tree.generation(), 1);
+        assert!(tree.check().is_none());
+        let reader = tree.reader();
+        assert!(reader
+            .get_node_label_summary(reader.root_address())
+            .is_some());
+    }
+}
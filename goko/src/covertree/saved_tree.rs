@@ -0,0 +1,336 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! A serde form of a saved tree, for bincode, MessagePack or any other serde format.
+//!
+//! [`SavedTree`] holds exactly what the protobuf file format does: the parameters, every node of
+//! every layer and the parameters of the persistent plugins, as JSON like in the protobuf. It's
+//! converted to and from the protobuf, so both formats load through the same checks and the
+//! plugins are attached again from a [`PluginRegistry`] the same way. Like the protobuf, the point
+//! cloud isn't included, only its dimension and number of points.
+//!
+//! ```rust,ignore
+//! let saved = tree.to_saved();
+//! let bytes = bincode::serialize(&saved)?;
+//! let saved: SavedTree = bincode::deserialize(&bytes)?;
+//! let tree = CoverTreeWriter::from_saved(&saved, point_cloud, &PluginRegistry::default())?;
+//! ```
+
+use crate::plugins::PluginRegistry;
+use crate::tree_file_format::*;
+use crate::*;
+use errors::GokoResult;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// A node of a [`SavedTree`], the fields of a `NodeProto`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SavedNode {
+    /// The number of points the node covers
+    pub coverage_count: u64,
+    /// The index of the node's center
+    pub center_index: u64,
+    /// The name of the node's center
+    pub name: String,
+    /// The node's scale index
+    pub scale_index: i32,
+    /// The center index of the node's parent
+    pub parent_center_index: u64,
+    /// The scale index of the node's parent
+    pub parent_scale_index: i32,
+    /// If the node has no children
+    pub is_leaf: bool,
+    /// The center indexes of the children
+    pub children_point_indexes: Vec<u64>,
+    /// The scale indexes of the children
+    pub children_scale_indexes: Vec<i32>,
+    /// The scale index of the nested child
+    pub nested_scale_index: i32,
+    /// The points the node holds as singletons
+    pub outlier_point_indexes: Vec<u64>,
+    /// The label summary of the singletons, as JSON
+    pub outlier_summary_json: String,
+    /// The distance to the farthest point the node covers
+    pub radius: f32,
+}
+
+/// A layer of a [`SavedTree`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SavedLayer {
+    /// The layer's scale index
+    pub scale_index: i32,
+    /// The nodes of the layer
+    pub nodes: Vec<SavedNode>,
+}
+
+/// A persistent plugin of a [`SavedTree`], its name on the [`PluginRegistry`] and its parameters.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SavedPlugin {
+    /// The name the plugin is registered under
+    pub name: String,
+    /// The plugin's parameters, as JSON
+    pub parameters_json: String,
+}
+
+/// A whole tree, without its point cloud, in a form any serde format can write. It holds what the protobuf
+/// file format does, see [`CoverTreeWriter::to_saved`] and [`CoverTreeWriter::from_saved`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SavedTree {
+    /// If the tree holds singletons
+    pub use_singletons: bool,
+    /// The base of the scales
+    pub scale_base: f32,
+    /// The leaf cutoff
+    pub leaf_cutoff: u64,
+    /// The minimum resolution index
+    pub min_res_index: i32,
+    /// The partition type, `first` or `nearest`
+    pub partition_type: String,
+    /// The dimension of the point cloud the tree was built on
+    pub dim: u64,
+    /// The number of points in the point cloud the tree was built on
+    pub count: u64,
+    /// The root's address
+    pub root_address: NodeAddress,
+    /// The layers, top first
+    pub layers: Vec<SavedLayer>,
+    /// The name of every point in the tree mapped to its index
+    pub name_map: BTreeMap<String, u64>,
+    /// The seed the tree was built with
+    pub rng_seed: Option<u64>,
+    /// The verbosity
+    pub verbosity: u32,
+    /// The persistent plugins
+    pub plugins: Vec<SavedPlugin>,
+}
+
+impl From<&NodeProto> for SavedNode {
+    fn from(proto: &NodeProto) -> SavedNode {
+        SavedNode {
+            coverage_count: proto.get_coverage_count(),
+            center_index: proto.get_center_index(),
+            name: proto.get_name().to_string(),
+            scale_index: proto.get_scale_index(),
+            parent_center_index: proto.get_parent_center_index(),
+            parent_scale_index: proto.get_parent_scale_index(),
+            is_leaf: proto.get_is_leaf(),
+            children_point_indexes: proto.get_children_point_indexes().to_vec(),
+            children_scale_indexes: proto.get_children_scale_indexes().to_vec(),
+            nested_scale_index: proto.get_nested_scale_index(),
+            outlier_point_indexes: proto.get_outlier_point_indexes().to_vec(),
+            outlier_summary_json: proto.get_outlier_summary_json().to_string(),
+            radius: proto.get_radius(),
+        }
+    }
+}
+
+impl From<&SavedNode> for NodeProto {
+    fn from(saved: &SavedNode) -> NodeProto {
+        let mut proto = NodeProto::new();
+        proto.set_coverage_count(saved.coverage_count);
+        proto.set_center_index(saved.center_index);
+        proto.set_name(saved.name.clone());
+        proto.set_scale_index(saved.scale_index);
+        proto.set_parent_center_index(saved.parent_center_index);
+        proto.set_parent_scale_index(saved.parent_scale_index);
+        proto.set_is_leaf(saved.is_leaf);
+        proto.set_children_point_indexes(saved.children_point_indexes.clone());
+        proto.set_children_scale_indexes(saved.children_scale_indexes.clone());
+        proto.set_nested_scale_index(saved.nested_scale_index);
+        proto.set_outlier_point_indexes(saved.outlier_point_indexes.clone());
+        proto.set_outlier_summary_json(saved.outlier_summary_json.clone());
+        proto.set_radius(saved.radius);
+        proto
+    }
+}
+
+impl From<&CoreProto> for SavedTree {
+    fn from(proto: &CoreProto) -> SavedTree {
+        SavedTree {
+            use_singletons: proto.get_use_singletons(),
+            scale_base: proto.get_scale_base(),
+            leaf_cutoff: proto.get_cutoff(),
+            min_res_index: proto.get_resolution(),
+            partition_type: proto.get_partition_type().to_string(),
+            dim: proto.get_dim(),
+            count: proto.get_count(),
+            root_address: (proto.get_root_scale(), proto.get_root_index() as usize),
+            layers: proto
+                .get_layers()
+                .iter()
+                .map(|layer| SavedLayer {
+                    scale_index: layer.get_scale_index(),
+                    nodes: layer.get_nodes().iter().map(SavedNode::from).collect(),
+                })
+                .collect(),
+            name_map: proto
+                .get_name_map()
+                .iter()
+                .map(|(name, index)| (name.clone(), *index))
+                .collect(),
+            rng_seed: if proto.get_has_rng_seed() {
+                Some(proto.get_rng_seed())
+            } else {
+                None
+            },
+            verbosity: proto.get_verbosity(),
+            plugins: proto
+                .get_plugins()
+                .iter()
+                .map(|plugin| SavedPlugin {
+                    name: plugin.get_name().to_string(),
+                    parameters_json: plugin.get_parameters_json().to_string(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<&SavedTree> for CoreProto {
+    fn from(saved: &SavedTree) -> CoreProto {
+        let mut proto = CoreProto::new();
+        proto.set_use_singletons(saved.use_singletons);
+        proto.set_scale_base(saved.scale_base);
+        proto.set_cutoff(saved.leaf_cutoff);
+        proto.set_resolution(saved.min_res_index);
+        proto.set_partition_type(saved.partition_type.clone());
+        proto.set_dim(saved.dim);
+        proto.set_count(saved.count);
+        proto.set_root_scale(saved.root_address.0);
+        proto.set_root_index(saved.root_address.1 as u64);
+        proto.set_layers(
+            saved
+                .layers
+                .iter()
+                .map(|layer| {
+                    let mut layer_proto = LayerProto::new();
+                    layer_proto.set_scale_index(layer.scale_index);
+                    layer_proto.set_nodes(layer.nodes.iter().map(NodeProto::from).collect());
+                    layer_proto
+                })
+                .collect(),
+        );
+        proto.set_name_map(
+            saved
+                .name_map
+                .iter()
+                .map(|(name, index)| (name.clone(), *index))
+                .collect(),
+        );
+        if let Some(seed) = saved.rng_seed {
+            proto.set_has_rng_seed(true);
+            proto.set_rng_seed(seed);
+        }
+        proto.set_verbosity(saved.verbosity);
+        proto.set_plugins(
+            saved
+                .plugins
+                .iter()
+                .map(|plugin| {
+                    let mut plugin_proto = PluginProto::new();
+                    plugin_proto.set_name(plugin.name.clone());
+                    plugin_proto.set_parameters_json(plugin.parameters_json.clone());
+                    plugin_proto
+                })
+                .collect(),
+        );
+        proto
+    }
+}
+
+impl<D: PointCloud> CoverTreeWriter<D> {
+    /// The tree in its serde form, see [`SavedTree`].
+    pub fn to_saved(&self) -> SavedTree {
+        SavedTree::from(&self.to_proto())
+    }
+
+    /// Loads a tree from its serde form, with the point cloud it was built on. The saved plugins are attached again,
+    /// so they have to be on the registry.
+    pub fn from_saved(
+        saved: &SavedTree,
+        point_cloud: Arc<D>,
+        plugins: &PluginRegistry<D>,
+    ) -> GokoResult<CoverTreeWriter<D>> {
+        CoverTreeWriter::from_proto(&CoreProto::from(saved), point_cloud, plugins)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tree::tests::build_basic_tree;
+    use super::*;
+    use crate::plugins::discrete::prelude::*;
+
+    fn assert_same_tree<D: PointCloud>(a: &CoverTreeReader<D>, b: &CoverTreeReader<D>) {
+        assert_eq!(a.root_address(), b.root_address());
+        assert_eq!(a.node_count(), b.node_count());
+        for (si, layer) in a.layers() {
+            layer.for_each_node(|pi, n| {
+                b.get_node_and((si, *pi), |m| {
+                    assert_eq!(n.parent_address(), m.parent_address());
+                    assert_eq!(n.children(), m.children());
+                    assert_eq!(n.singletons(), m.singletons());
+                    assert_eq!(n.coverage_count(), m.coverage_count());
+                    assert_eq!(n.radius(), m.radius());
+                })
+                .unwrap();
+            });
+        }
+    }
+
+    #[test]
+    fn saved_tree_round_trips_through_bincode() {
+        let mut tree = build_basic_tree();
+        tree.generate_summaries();
+        tree.add_persistent_plugin(GokoDirichlet::default())
+            .unwrap();
+        let reader = tree.reader();
+        let saved = tree.to_saved();
+        let bytes = bincode::serialize(&saved).unwrap();
+        let decoded: SavedTree = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(saved, decoded);
+
+        let loaded = CoverTreeWriter::from_saved(
+            &decoded,
+            Arc::clone(reader.point_cloud()),
+            &PluginRegistry::default(),
+        )
+        .unwrap();
+        let loaded_reader = loaded.reader();
+        assert_same_tree(&reader, &loaded_reader);
+        assert_eq!(loaded.to_saved(), saved);
+    }
+
+    #[test]
+    fn saved_tree_round_trips_through_msgpack() {
+        let tree = build_basic_tree();
+        let saved = tree.to_saved();
+        let bytes = rmp_serde::to_vec(&saved).unwrap();
+        let decoded: SavedTree = rmp_serde::from_slice(&bytes).unwrap();
+        assert_eq!(saved, decoded);
+        let loaded = CoverTreeWriter::from_saved(
+            &decoded,
+            Arc::clone(tree.reader().point_cloud()),
+            &PluginRegistry::default(),
+        )
+        .unwrap();
+        assert_same_tree(&tree.reader(), &loaded.reader());
+    }
+}
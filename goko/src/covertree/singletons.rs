@@ -0,0 +1,356 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! # Singleton storage
+//! On large trees the singleton lists of the leaves hold most of the point indexes. Short lists
+//! are kept inline in the node, as they were. Once a list outgrows the inline space it's sorted,
+//! delta encoded and bitpacked, so a leaf of a few thousand nearby indexes takes a couple of bytes
+//! per index instead of eight. Iterating a packed list decodes it on the fly. Asking for a packed
+//! list as a slice decodes it once and keeps the decoded copy with the node, which gives up the
+//! savings on that node.
+
+use once_cell::sync::OnceCell;
+use smallvec::SmallVec;
+use std::iter::FromIterator;
+
+/// The number of singletons kept inline before a list is packed.
+pub(crate) const INLINE_SINGLETONS: usize = 20;
+
+/// A sorted list of point indexes stored as the bitpacked differences between consecutive
+/// indexes. Each difference takes as many bits as the largest one needs.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PackedIndexes {
+    len: usize,
+    width: u32,
+    words: Box<[u64]>,
+}
+
+impl PackedIndexes {
+    /// Packs the indexes, in ascending order.
+    pub fn new(indexes: &[usize]) -> PackedIndexes {
+        let mut sorted = indexes.to_vec();
+        sorted.sort_unstable();
+        let mut previous = 0;
+        let deltas: Vec<u64> = sorted
+            .iter()
+            .map(|pi| {
+                let delta = (pi - previous) as u64;
+                previous = *pi;
+                delta
+            })
+            .collect();
+        let width = deltas
+            .iter()
+            .map(|delta| 64 - delta.leading_zeros())
+            .max()
+            .unwrap_or(0);
+        let bits = deltas.len() * width as usize;
+        let mut words = vec![0u64; (bits + 63) / 64];
+        for (i, delta) in deltas.iter().enumerate() {
+            let bit = i * width as usize;
+            let (word, offset) = (bit / 64, bit % 64);
+            words[word] |= delta << offset;
+            if offset + width as usize > 64 {
+                words[word + 1] |= delta >> (64 - offset);
+            }
+        }
+        PackedIndexes {
+            len: sorted.len(),
+            width,
+            words: words.into_boxed_slice(),
+        }
+    }
+
+    /// The number of indexes.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// If there are no indexes.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The number of bits each index takes.
+    pub fn bits_per_index(&self) -> u32 {
+        self.width
+    }
+
+    /// Iterates over the indexes in ascending order.
+    pub fn iter(&self) -> PackedIter<'_> {
+        PackedIter {
+            words: &self.words,
+            width: self.width,
+            remaining: self.len,
+            bit: 0,
+            current: 0,
+        }
+    }
+
+    /// If the index is in the list. This stops at the first larger index.
+    pub fn contains(&self, pi: usize) -> bool {
+        self.iter().take_while(|i| *i <= pi).any(|i| i == pi)
+    }
+
+    /// The bytes of the packed words.
+    pub fn memory_estimate(&self) -> usize {
+        self.words.len() * std::mem::size_of::<u64>()
+    }
+}
+
+impl FromIterator<usize> for PackedIndexes {
+    fn from_iter<I: IntoIterator<Item = usize>>(iter: I) -> Self {
+        let indexes: Vec<usize> = iter.into_iter().collect();
+        PackedIndexes::new(&indexes)
+    }
+}
+
+/// Decodes a [`PackedIndexes`], see [`PackedIndexes::iter`].
+#[derive(Debug, Clone)]
+pub struct PackedIter<'a> {
+    words: &'a [u64],
+    width: u32,
+    remaining: usize,
+    bit: usize,
+    current: usize,
+}
+
+impl<'a> Iterator for PackedIter<'a> {
+    type Item = usize;
+    fn next(&mut self) -> Option<usize> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        if self.width > 0 {
+            let width = self.width as usize;
+            let (word, offset) = (self.bit / 64, self.bit % 64);
+            let mut delta = self.words[word] >> offset;
+            if offset + width > 64 {
+                delta |= self.words[word + 1] << (64 - offset);
+            }
+            if width < 64 {
+                delta &= (1u64 << width) - 1;
+            }
+            self.bit += width;
+            self.current += delta as usize;
+        }
+        Some(self.current)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a> ExactSizeIterator for PackedIter<'a> {}
+
+/// Iterates over the singletons of a node, see
+/// [`CoverNode::singletons_iter`](crate::covertree::node::CoverNode::singletons_iter).
+#[derive(Debug, Clone)]
+pub enum SingletonIter<'a> {
+    /// A list kept inline, in insertion order
+    Inline(std::slice::Iter<'a, usize>),
+    /// A packed list, in ascending order
+    Packed(PackedIter<'a>),
+}
+
+impl<'a> Iterator for SingletonIter<'a> {
+    type Item = usize;
+    fn next(&mut self) -> Option<usize> {
+        match self {
+            SingletonIter::Inline(iter) => iter.next().copied(),
+            SingletonIter::Packed(iter) => iter.next(),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self {
+            SingletonIter::Inline(iter) => iter.size_hint(),
+            SingletonIter::Packed(iter) => iter.size_hint(),
+        }
+    }
+}
+
+impl<'a> ExactSizeIterator for SingletonIter<'a> {}
+
+/// The singletons of a node, inline until they outgrow [`INLINE_SINGLETONS`] and packed after.
+/// A packed list carries the decoded copy once it's been asked for as a slice.
+#[derive(Debug, Clone)]
+pub(crate) enum SingletonIndexes {
+    Inline(SmallVec<[usize; INLINE_SINGLETONS]>),
+    Packed(PackedIndexes, OnceCell<Box<[usize]>>),
+}
+
+impl Default for SingletonIndexes {
+    fn default() -> Self {
+        SingletonIndexes::Inline(SmallVec::new())
+    }
+}
+
+impl SingletonIndexes {
+    pub(crate) fn len(&self) -> usize {
+        match self {
+            SingletonIndexes::Inline(indexes) => indexes.len(),
+            SingletonIndexes::Packed(indexes, _) => indexes.len(),
+        }
+    }
+
+    pub(crate) fn iter(&self) -> SingletonIter<'_> {
+        match self {
+            SingletonIndexes::Inline(indexes) => SingletonIter::Inline(indexes.iter()),
+            SingletonIndexes::Packed(indexes, _) => SingletonIter::Packed(indexes.iter()),
+        }
+    }
+
+    /// The indexes as a slice. A packed list is decoded the first time and the copy is kept.
+    pub(crate) fn as_slice(&self) -> &[usize] {
+        match self {
+            SingletonIndexes::Inline(indexes) => &indexes[..],
+            SingletonIndexes::Packed(indexes, decoded) => {
+                decoded.get_or_init(|| indexes.iter().collect())
+            }
+        }
+    }
+
+    /// The packed list, `None` while the list is inline.
+    pub(crate) fn packed(&self) -> Option<&PackedIndexes> {
+        match self {
+            SingletonIndexes::Inline(_) => None,
+            SingletonIndexes::Packed(indexes, _) => Some(indexes),
+        }
+    }
+
+    pub(crate) fn push(&mut self, pi: usize) {
+        self.extend(std::iter::once(pi));
+    }
+
+    /// Adds the indexes, packing the list if it no longer fits inline. Adding to a packed list
+    /// repacks it.
+    pub(crate) fn extend<I: IntoIterator<Item = usize>>(&mut self, indexes: I) {
+        match self {
+            SingletonIndexes::Inline(inline) => {
+                inline.extend(indexes);
+                if inline.spilled() {
+                    *self = SingletonIndexes::Packed(PackedIndexes::new(inline), OnceCell::new());
+                }
+            }
+            SingletonIndexes::Packed(packed, decoded) => {
+                *packed = packed.iter().chain(indexes).collect();
+                *decoded = OnceCell::new();
+            }
+        }
+    }
+
+    /// Removes an index and returns false if it wasn't there. A packed list that fits inline again
+    /// is unpacked.
+    pub(crate) fn remove(&mut self, pi: usize) -> bool {
+        match self {
+            SingletonIndexes::Inline(indexes) => match indexes.iter().position(|i| *i == pi) {
+                Some(i) => {
+                    indexes.remove(i);
+                    true
+                }
+                None => false,
+            },
+            SingletonIndexes::Packed(packed, _) => {
+                let mut indexes: Vec<usize> = packed.iter().collect();
+                match indexes.iter().position(|i| *i == pi) {
+                    Some(i) => {
+                        indexes.remove(i);
+                        *self = indexes.into_iter().collect();
+                        true
+                    }
+                    None => false,
+                }
+            }
+        }
+    }
+
+    /// The heap bytes of the list, with the decoded copy of a packed list. 0 while it's inline.
+    pub(crate) fn memory_estimate(&self) -> usize {
+        match self {
+            SingletonIndexes::Inline(indexes) if indexes.spilled() => {
+                indexes.capacity() * std::mem::size_of::<usize>()
+            }
+            SingletonIndexes::Inline(_) => 0,
+            SingletonIndexes::Packed(indexes, decoded) => {
+                indexes.memory_estimate()
+                    + decoded
+                        .get()
+                        .map_or(0, |d| d.len() * std::mem::size_of::<usize>())
+            }
+        }
+    }
+}
+
+impl FromIterator<usize> for SingletonIndexes {
+    fn from_iter<I: IntoIterator<Item = usize>>(iter: I) -> Self {
+        let mut indexes = SingletonIndexes::default();
+        indexes.extend(iter);
+        indexes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packed_indexes_round_trip() {
+        let indexes = vec![1_000_000, 3, 17, 17, 0, 64, 1 << 40, 5];
+        let packed = PackedIndexes::new(&indexes);
+        let mut sorted = indexes.clone();
+        sorted.sort_unstable();
+        assert_eq!(packed.len(), indexes.len());
+        assert_eq!(packed.iter().collect::<Vec<usize>>(), sorted);
+        assert!(packed.contains(64));
+        assert!(packed.contains(1 << 40));
+        assert!(!packed.contains(4));
+        assert!(PackedIndexes::new(&[]).iter().next().is_none());
+        assert_eq!(
+            PackedIndexes::new(&[0, 0]).iter().collect::<Vec<usize>>(),
+            vec![0, 0]
+        );
+    }
+
+    #[test]
+    fn long_lists_are_packed() {
+        let mut singletons: SingletonIndexes = (0..INLINE_SINGLETONS).rev().collect();
+        assert!(matches!(singletons, SingletonIndexes::Inline(_)));
+        assert_eq!(singletons.memory_estimate(), 0);
+
+        singletons.push(100);
+        singletons.extend(vec![50, 30]);
+        assert!(matches!(singletons, SingletonIndexes::Packed(..)));
+        let expected: Vec<usize> = (0..INLINE_SINGLETONS).chain(vec![30, 50, 100]).collect();
+        assert_eq!(singletons.iter().collect::<Vec<usize>>(), expected);
+        assert_eq!(singletons.len(), expected.len());
+        let packed_size = singletons.memory_estimate();
+        assert!(packed_size < expected.len() * std::mem::size_of::<usize>());
+        assert_eq!(singletons.packed().unwrap().len(), expected.len());
+
+        // The slice is decoded once and dropped when the list changes
+        assert_eq!(singletons.as_slice(), &expected[..]);
+        assert!(singletons.memory_estimate() > packed_size);
+        singletons.push(200);
+        assert!(singletons.memory_estimate() <= packed_size + std::mem::size_of::<u64>());
+        assert_eq!(singletons.as_slice().last(), Some(&200));
+    }
+}
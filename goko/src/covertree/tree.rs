@@ -38,30 +38,52 @@ use super::node::*;
 use crate::*;
 //use pointcloud::*;
 
+use crate::build_report::{BuildReport, PluginTiming};
+use crate::health::{PublishLog, TreeHealth};
+use crate::latency::{LatencyStats, Operation};
+use crate::middleware::QueryMiddleware;
 use crate::monomap::{MonoReadHandle, MonoWriteHandle};
 use crate::tree_file_format::*;
-use std::sync::{atomic, Arc, RwLock};
+use std::sync::{atomic, Arc, Mutex, RwLock};
 
-use super::query_tools::{KnnQueryHeap, RoutingQueryHeap};
-use crate::plugins::{GokoPlugin, TreePluginSet};
+use super::query_tools::query_items::QueryAddress;
+use super::query_tools::{KnnBatch, KnnQueryHeap, RoutingQueryHeap};
+use crate::plugins::{GokoPlugin, PersistentPlugin, PluginRegistry, TreePluginSet};
+use crate::scheduler::PoolHandle;
 use errors::{GokoError, GokoResult};
+use hashbrown::{HashMap, HashSet};
+use protobuf::{CodedInputStream, CodedOutputStream, Message};
+use rayon::iter::repeatn;
+use roaring::RoaringBitmap;
 use serde::{Deserialize, Serialize};
+use std::any::TypeId;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
 use std::iter::Iterator;
 use std::iter::Rev;
 use std::ops::Deref;
 use std::ops::Range;
+use std::path::Path;
 use std::slice::Iter;
+use std::time::Instant;
 
 use plugins::labels::*;
+use plugins::masking::NodeDeviations;
+use plugins::partitions::NodePartitionCounts;
+use pointcloud::metrics::{DimMask, MaskedMetric};
+use pointcloud::subset_cloud::SubsetCloud;
+use pointcloud::summaries::CategorySummary;
 
 /// When 2 spheres overlap under a node, and there is a point in the overlap we have to decide
 /// to which sphere it belongs. As we create the nodes in a particular sequence, we can assign them
 /// to the first to be created or we can assign it to the nearest.
-#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum PartitionType {
     /// Conflicts assigning a point to several eligible nodes are assigned to the nearest node.
+    #[serde(alias = "nearest")]
     Nearest,
     /// Conflicts assigning a point to several eligible nodes are assigned to the first node to be created.
+    #[serde(alias = "first")]
     First,
 }
 
@@ -91,6 +113,10 @@ pub struct CoverTreeParameters<D: PointCloud> {
     pub point_cloud: Arc<D>,
     /// This is where the base plugins are are stored.
     pub plugins: RwLock<TreePluginSet>,
+    /// Latency histograms of the tree's operations, off until they're enabled.
+    pub latencies: LatencyStats,
+    /// The generations the tree was published at, and those its plugins were computed at.
+    pub publishes: PublishLog,
 }
 
 impl<D: PointCloud> CoverTreeParameters<D> {
@@ -105,6 +131,83 @@ impl<D: PointCloud> CoverTreeParameters<D> {
     }
 }
 
+/// A query point after the point cloud's preprocessing, or the point as it was given if the cloud has none.
+enum QueryPoint<'a, T: ?Sized> {
+    Given(&'a T),
+    Preprocessed(Box<T>),
+}
+
+impl<'a, T: ?Sized> Deref for QueryPoint<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        match self {
+            QueryPoint::Given(point) => point,
+            QueryPoint::Preprocessed(point) => point,
+        }
+    }
+}
+
+/// The number of layers `knn_batch` routes its queries down before grouping them.
+const BATCH_ROUTING_DEPTH: usize = 3;
+
+/// A reference node, or a single reference point, that could hold a neighbor of something a query node covers, for
+/// `all_knn`. The distance is from the query node's center to the candidate's center.
+#[derive(Debug, Clone, Copy)]
+struct DualCandidate {
+    address: Option<NodeAddress>,
+    center: usize,
+    radius: f32,
+    dist: f32,
+}
+
+impl DualCandidate {
+    fn point(center: usize, dist: f32) -> DualCandidate {
+        DualCandidate {
+            address: None,
+            center,
+            radius: 0.0,
+            dist,
+        }
+    }
+}
+
+/// One node on a dry insert's path, see [`CoverTreeReader::path_detailed`].
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct PathStep {
+    /// The distance from the query to the node's center
+    pub distance: f32,
+    /// The node
+    pub address: NodeAddress,
+    /// The node's covering radius, the scale of its scale index
+    pub cover_radius: f32,
+    /// The distance from the node's center to the farthest point it covers
+    pub radius: f32,
+    /// The number of points the node covers
+    pub coverage_count: usize,
+    /// If the query is within the covering radius. Every node after the root is, the root can be
+    /// passed a point outside of it.
+    pub inside: bool,
+}
+
+/// Statistics of a single node, see [`CoverTreeReader::node_stats`].
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct NodeStats {
+    /// The node
+    pub address: NodeAddress,
+    /// The node's covering radius, the scale of its scale index
+    pub cover_radius: f32,
+    /// The distance from the node's center to the farthest point it covers, measured over those points
+    pub covering_radius: f32,
+    /// The radius stored on the node. Edits only ever grow it, so it can be larger than `covering_radius`.
+    pub radius: f32,
+    /// The number of points the node covers
+    pub coverage_count: usize,
+    /// The number of singletons attached to the node
+    pub singleton_count: usize,
+    /// The number of children, the nested child included. 0 for a leaf.
+    pub child_count: usize,
+}
+
 /// Helper struct for iterating thru the reader's of the the layers.
 pub type LayerIter<'a, D> = Rev<std::iter::Zip<Range<i32>, Iter<'a, CoverLayerReader<D>>>>;
 
@@ -116,12 +219,16 @@ pub type LayerIter<'a, D> = Rev<std::iter::Zip<Range<i32>, Iter<'a, CoverLayerRe
 /// There are no thread locks anywhere in the code below the reader head, so it's fast.
 ///
 /// The data structure is just a list of `CoverLayerReader`s, the parameter's object and the root address. Copies are relatively
-/// expensive as each `CoverLayerReader` contains several Arcs that need to be cloned.
+/// expensive as each `CoverLayerReader` contains several Arcs that need to be cloned. A reader can also carry query
+/// middleware, see [`crate::middleware`], which its copies share.
 pub struct CoverTreeReader<D: PointCloud> {
     parameters: Arc<CoverTreeParameters<D>>,
     layers: Vec<CoverLayerReader<D>>,
     root_address: NodeAddress,
     final_addresses: MonoReadHandle<usize, NodeAddress>,
+    middleware: Vec<Arc<dyn QueryMiddleware<D>>>,
+    // A reader from `load` has no writer elsewhere, it keeps its own so the maps aren't emptied.
+    writer: Option<Arc<Mutex<CoverTreeWriter<D>>>>,
 }
 
 impl<D: PointCloud> Clone for CoverTreeReader<D> {
@@ -131,16 +238,95 @@ impl<D: PointCloud> Clone for CoverTreeReader<D> {
             layers: self.layers.clone(),
             root_address: self.root_address,
             final_addresses: self.final_addresses.clone(),
+            middleware: self.middleware.clone(),
+            writer: self.writer.clone(),
         }
     }
 }
 
 impl<D: PointCloud> CoverTreeReader<D> {
+    /// Reads a tree that was written by [`CoverTreeWriter::save`], with the point cloud it was built on, for querying
+    /// only. The persistent plugins are attached again from the default [`PluginRegistry`]. Use
+    /// [`CoverTreeWriter::load_file`] if you need to keep updating the tree.
+    pub fn load<P: AsRef<Path>>(path: P, point_cloud: Arc<D>) -> GokoResult<CoverTreeReader<D>> {
+        CoverTreeReader::load_with_plugins(path, point_cloud, &PluginRegistry::default())
+    }
+
+    /// `load` with your own plugin registry, for trees with plugins that aren't built in.
+    pub fn load_with_plugins<P: AsRef<Path>>(
+        path: P,
+        point_cloud: Arc<D>,
+        plugins: &PluginRegistry<D>,
+    ) -> GokoResult<CoverTreeReader<D>> {
+        let writer = CoverTreeWriter::load_file(path, point_cloud, plugins)?;
+        let mut reader = writer.reader();
+        reader.writer = Some(Arc::new(Mutex::new(writer)));
+        Ok(reader)
+    }
+
     /// A reference to the point cloud the tree was built on.
     pub fn point_cloud(&self) -> &Arc<D> {
         &self.parameters.point_cloud
     }
 
+    /// Adds a hook to this reader's `knn` and `range` queries, after the ones it already has. See
+    /// [`crate::middleware`].
+    pub fn add_middleware<M: QueryMiddleware<D>>(&mut self, middleware: M) {
+        self.middleware.push(Arc::new(middleware));
+    }
+
+    /// Removes all of this reader's query middleware.
+    pub fn clear_middleware(&mut self) {
+        self.middleware.clear();
+    }
+
+    /// Runs a query through the middleware: the point through the transforms, then the results through the
+    /// processing and audits.
+    fn with_middleware<F>(
+        &self,
+        operation: Operation,
+        point: QueryPoint<'_, D::Point>,
+        query: F,
+    ) -> GokoResult<Vec<(f32, usize)>>
+    where
+        F: FnOnce(&D::Point) -> GokoResult<Vec<(f32, usize)>>,
+    {
+        let point = self.transform_query(operation, point);
+        let results = query(&point)?;
+        Ok(self.process_results(operation, &point, results))
+    }
+
+    /// Puts a query point through the middleware's transforms, in the order they were added.
+    fn transform_query<'a>(
+        &self,
+        operation: Operation,
+        point: QueryPoint<'a, D::Point>,
+    ) -> QueryPoint<'a, D::Point> {
+        let mut point = point;
+        for middleware in &self.middleware {
+            if let Some(next) = middleware.transform_query(operation, &point) {
+                point = QueryPoint::Preprocessed(next);
+            }
+        }
+        point
+    }
+
+    /// Puts the results of a query on `point`, after the transforms, through the middleware's processing and audits.
+    fn process_results(
+        &self,
+        operation: Operation,
+        point: &D::Point,
+        mut results: Vec<(f32, usize)>,
+    ) -> Vec<(f32, usize)> {
+        for middleware in &self.middleware {
+            results = middleware.process_results(operation, point, results);
+        }
+        for middleware in &self.middleware {
+            middleware.audit(operation, point, &results);
+        }
+        results
+    }
+
     /// Reads the contents of a plugin, due to the nature of the plugin map we have to access it with a
     /// closure.
     pub fn get_node_label_summary(
@@ -221,6 +407,111 @@ impl<D: PointCloud> CoverTreeReader<D> {
         &self.parameters
     }
 
+    /// The latency histograms of this tree's operations, see [`crate::latency`].
+    pub fn latency_stats(&self) -> &LatencyStats {
+        &self.parameters.latencies
+    }
+
+    /// The generation, size and plugin freshness of the tree, for health and readiness checks. See
+    /// [`crate::health`]. This walks every node to estimate the memory, so poll it every few seconds
+    /// rather than with each query.
+    pub fn health(&self) -> TreeHealth {
+        // Both copies of the double buffered maps are counted, as in the build's memory limit
+        let address_size = 2 * (std::mem::size_of::<usize>() + std::mem::size_of::<NodeAddress>());
+        let mut memory_estimate = 0;
+        for (_, layer) in self.layers() {
+            layer.for_each_node(|_, n| {
+                let leaf_address = if n.is_leaf() { 1 } else { 0 };
+                memory_estimate +=
+                    2 * n.memory_estimate() + (n.singletons_len() + leaf_address) * address_size;
+            });
+        }
+        let point_count = self
+            .get_node_and(self.root_address, |n| n.coverage_count())
+            .unwrap_or(0);
+        TreeHealth::new(&self.parameters.publishes, point_count, memory_estimate)
+    }
+
+    /// Copies the subtree under a node into a tree of its own, so that a cluster can be queried and
+    /// given plugins without the rest of the tree. The new tree has this tree's parameters and is over
+    /// a [`SubsetCloud`] view of the points the node covers, which keeps their indexes. The node
+    /// becomes the root. Plugins are not copied, attach them to the new tree.
+    pub fn subtree(&self, address: NodeAddress) -> GokoResult<CoverTreeWriter<SubsetCloud<D>>> {
+        let root_layer = self.parameters.internal_index(address.0);
+        if root_layer >= self.layers.len() {
+            return Err(GokoError::NodeNotInTree(address));
+        }
+        let mut nodes: Vec<CoverNode<SubsetCloud<D>>> = Vec::new();
+        let mut covered = Vec::new();
+        let mut unvisited = vec![address];
+        while let Some(na) = unvisited.pop() {
+            let node = self
+                .get_node_and(na, |n| n.shifted(0))
+                .ok_or(GokoError::NodeNotInTree(na))?;
+            covered.extend(node.singletons_iter());
+            match node.children() {
+                Some((nested_scale, children)) => {
+                    unvisited.push((nested_scale, na.1));
+                    unvisited.extend_from_slice(children);
+                }
+                None => covered.push(na.1),
+            }
+            nodes.push(node);
+        }
+        nodes[0].set_parent_address(None);
+
+        let point_cloud = SubsetCloud::new(Arc::clone(&self.parameters.point_cloud), covered)?;
+        let parameters = Arc::new(CoverTreeParameters {
+            total_nodes: atomic::AtomicUsize::new(nodes.len()),
+            scale_base: self.parameters.scale_base,
+            leaf_cutoff: self.parameters.leaf_cutoff,
+            min_res_index: self.parameters.min_res_index,
+            use_singletons: self.parameters.use_singletons,
+            partition_type: self.parameters.partition_type,
+            verbosity: self.parameters.verbosity,
+            rng_seed: self.parameters.rng_seed,
+            point_cloud: Arc::new(point_cloud),
+            plugins: RwLock::new(TreePluginSet::new()),
+            latencies: LatencyStats::new(),
+            publishes: PublishLog::new(),
+        });
+        let layers = (0..=root_layer)
+            .map(|i| CoverLayerWriter::new(i as i32 + parameters.min_res_index - 1))
+            .collect();
+        let (_final_addresses_reader, final_addresses) = monomap::new();
+        let mut tree = CoverTreeWriter {
+            parameters,
+            layers,
+            root_address: address,
+            final_addresses,
+            plugin_updaters: Vec::new(),
+            saved_plugins: Vec::new(),
+            maintenance_pool: None,
+            build_report: BuildReport::default(),
+        };
+        for node in nodes {
+            let na = node.address();
+            unsafe {
+                tree.insert_raw(na.0, na.1, node);
+            }
+        }
+        tree.refresh();
+        tree.refresh_final_indexes();
+        Ok(tree)
+    }
+
+    /// Puts the query through the point cloud's preprocessing, like a normalization fitted when the data was loaded.
+    /// Every query that takes an outside point goes through this first.
+    fn preprocess<'a, P: Deref<Target = D::Point>>(
+        &self,
+        point: &'a P,
+    ) -> QueryPoint<'a, D::Point> {
+        match self.parameters.point_cloud.preprocess_query(point) {
+            Some(point) => QueryPoint::Preprocessed(point),
+            None => QueryPoint::Given(&**point),
+        }
+    }
+
     /// This is the total number of nodes in the tree. This queries each layer, so it's not a simple return int.
     pub fn node_count(&self) -> usize {
         self.layers().fold(0, |a, (_si, l)| a + l.len())
@@ -282,434 +573,2737 @@ impl<D: PointCloud> CoverTreeReader<D> {
         point: &P,
         k: usize,
     ) -> GokoResult<Vec<(f32, usize)>> {
-        let mut query_heap = KnnQueryHeap::new(k, self.parameters.scale_base);
-
-        let root_center = self.parameters.point_cloud.point(self.root_address.1)?;
-        let dist_to_root = D::Metric::dist(&root_center, &point);
-        query_heap.push_nodes(&[self.root_address], &[dist_to_root], None);
-        self.greedy_knn_nodes(point, &mut query_heap);
-
-        while let Some((_dist, address)) = query_heap.closest_unvisited_singleton_covering_address()
-        {
-            self.get_node_and(address, |n| {
-                n.singleton_knn(point, &self.parameters.point_cloud, &mut query_heap)
-            });
-            self.greedy_knn_nodes(point, &mut query_heap);
-        }
-
-        Ok(query_heap.unpack())
+        let timer = self.parameters.latencies.start();
+        let knn = self.with_middleware(Operation::Knn, self.preprocess(point), |point| {
+            self.knn_preprocessed(&point, k)
+        });
+        self.parameters.latencies.record(Operation::Knn, timer);
+        knn
     }
 
-    /// Same as knn, but only deals with non-singleton points
-    pub fn routing_knn<P: Deref<Target = D::Point> + Send + Sync>(
+    /// `knn` for a point that already went through the point cloud's preprocessing, like a point of the cloud itself.
+    /// This doesn't record a latency.
+    pub fn knn_preprocessed<P: Deref<Target = D::Point> + Send + Sync>(
         &self,
         point: &P,
         k: usize,
     ) -> GokoResult<Vec<(f32, usize)>> {
         let mut query_heap = KnnQueryHeap::new(k, self.parameters.scale_base);
+        query_heap.exclude(self.parameters.point_cloud.deleted_indexes());
 
         let root_center = self.parameters.point_cloud.point(self.root_address.1)?;
-        let dist_to_root = D::Metric::dist(&root_center, &point);
+        let dist_to_root = self
+            .parameters
+            .point_cloud
+            .metric()
+            .dist(&root_center, &point);
         query_heap.push_nodes(&[self.root_address], &[dist_to_root], None);
-        self.greedy_knn_nodes(point, &mut query_heap);
+        self.search_knn_heap(point, &mut query_heap);
 
-        while self.greedy_knn_nodes(point, &mut query_heap) {}
         Ok(query_heap.unpack())
     }
 
-    fn greedy_knn_nodes<P: Deref<Target = D::Point> + Send + Sync>(
+    /// All the points within `radius` of the query, closest first, as `(distance, index)`. A node is only opened if
+    /// the ball around the query reaches into its covering radius, so this reads the part of the tree near the query.
+    /// Deleted points are left out.
+    pub fn range<P: Deref<Target = D::Point> + Send + Sync>(
         &self,
         point: &P,
-        query_heap: &mut KnnQueryHeap,
-    ) -> bool {
-        let mut did_something = false;
-        while let Some((dist, nearest_address)) =
-            query_heap.closest_unvisited_child_covering_address()
-        {
-            if self
-                .get_node_and(nearest_address, |n| n.is_leaf())
-                .unwrap_or(true)
-            {
-                break;
-            } else {
-                self.get_node_and(nearest_address, |n| {
-                    n.child_knn(Some(dist), point, &self.parameters.point_cloud, query_heap)
-                });
+        radius: f32,
+    ) -> GokoResult<Vec<(f32, usize)>> {
+        let timer = self.parameters.latencies.start();
+        let range = self.with_middleware(Operation::Range, self.preprocess(point), |point| {
+            self.range_preprocessed(&point, radius)
+        });
+        self.parameters.latencies.record(Operation::Range, timer);
+        range
+    }
+
+    /// `range` for a point that already went through the point cloud's preprocessing, like a point of the cloud itself.
+    /// This doesn't record a latency.
+    pub fn range_preprocessed<P: Deref<Target = D::Point> + Send + Sync>(
+        &self,
+        point: &P,
+        radius: f32,
+    ) -> GokoResult<Vec<(f32, usize)>> {
+        let point_cloud = &self.parameters.point_cloud;
+        let root_center = point_cloud.point(self.root_address.1)?;
+        let dist_to_root = point_cloud.metric().dist(&root_center, &point);
+        let mut found = Vec::new();
+        let mut unvisited = vec![(dist_to_root, self.root_address)];
+        while let Some((dist, address)) = unvisited.pop() {
+            let contents = self
+                .get_node_and(address, |n| {
+                    if dist - n.radius() > radius {
+                        None
+                    } else {
+                        let children = n.children().map(|(ns, c)| (ns, c.to_vec()));
+                        Some((n.singletons_decoded().into_owned(), children))
+                    }
+                })
+                .ok_or(GokoError::NodeNotInTree(address))?;
+            let (singletons, children) = match contents {
+                Some(contents) => contents,
+                None => continue,
+            };
+            if !singletons.is_empty() {
+                let distances = point_cloud.distances_to_point(point, &singletons)?;
+                found.extend(
+                    distances
+                        .into_iter()
+                        .zip(singletons)
+                        .filter(|(d, _)| *d <= radius),
+                );
+            }
+            match children {
+                None => {
+                    if dist <= radius {
+                        found.push((dist, address.1));
+                    }
+                }
+                Some((nested_scale, children)) => {
+                    unvisited.push((dist, (nested_scale, address.1)));
+                    let centers: Vec<usize> = children.iter().map(|(_, pi)| *pi).collect();
+                    let distances = point_cloud.distances_to_point(point, &centers)?;
+                    unvisited.extend(distances.into_iter().zip(children));
+                }
             }
-            did_something = true;
         }
-        did_something
+        let deleted: HashSet<usize> = point_cloud.deleted_indexes().into_iter().collect();
+        if !deleted.is_empty() {
+            found.retain(|(_, pi)| !deleted.contains(pi));
+        }
+        found.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        Ok(found)
     }
 
-    /// # Dry Insert Query
-    pub fn path<P: Deref<Target = D::Point> + Send + Sync>(
+    /// `knn` for many queries at once, in the order they're given. The queries are first routed a few layers down from
+    /// the root and grouped by the node they reach. Each group then descends the tree together: a node is read and
+    /// the points of its children and singletons are fetched once for the whole group, and each query keeps its own
+    /// `k` nearest and only follows the nodes that could still improve them. The groups run in parallel. The results
+    /// are exact, the same as calling `knn` on each query, but bulk scoring of clustered queries reads far fewer nodes.
+    /// Each rayon worker gets its own copy of the reader.
+    pub fn knn_batch<P: Deref<Target = D::Point> + Send + Sync>(
         &self,
-        point: &P,
-    ) -> GokoResult<Vec<(f32, NodeAddress)>> {
-        let root_center = self.parameters.point_cloud.point(self.root_address.1)?;
-        let mut current_distance = D::Metric::dist(&root_center, &point);
-        let mut current_address = self.root_address;
-        let mut trace = vec![(current_distance, current_address)];
-        while let Some(nearest) =
-            self.get_node_and(current_address, |n| match self.parameters.partition_type {
-                PartitionType::Nearest => n.nearest_covering_child(
-                    self.parameters.scale_base,
-                    current_distance,
-                    point,
-                    &self.parameters.point_cloud,
-                ),
-                PartitionType::First => n.first_covering_child(
-                    self.parameters.scale_base,
-                    current_distance,
-                    point,
-                    &self.parameters.point_cloud,
-                ),
+        points: &[P],
+        k: usize,
+    ) -> GokoResult<KnnBatch> {
+        let points: Vec<QueryPoint<D::Point>> = points
+            .iter()
+            .map(|p| self.transform_query(Operation::Knn, self.preprocess(p)))
+            .collect();
+        let mut groups: HashMap<NodeAddress, Vec<usize>> = HashMap::new();
+        for (i, point) in points.iter().enumerate() {
+            let address = self.coarse_address(point, BATCH_ROUTING_DEPTH)?;
+            groups.entry(address).or_insert_with(Vec::new).push(i);
+        }
+        let groups: Vec<Vec<usize>> = groups.into_iter().map(|(_, group)| group).collect();
+        let grouped_knn: Vec<GokoResult<Vec<Vec<(f32, usize)>>>> = groups
+            .par_iter()
+            .map_with(self.clone(), |reader, group| {
+                let group_points: Vec<&QueryPoint<D::Point>> =
+                    group.iter().map(|i| &points[*i]).collect();
+                reader.knn_group(&group_points, k)
             })
-        {
-            if let Some(nearest) = nearest? {
-                trace.push(nearest);
-                current_distance = nearest.0;
-                current_address = nearest.1;
-            } else {
-                break;
+            .collect();
+
+        let mut knn = vec![Vec::new(); points.len()];
+        for (group, group_knn) in groups.iter().zip(grouped_knn) {
+            for (i, nbrs) in group.iter().zip(group_knn?) {
+                knn[*i] = nbrs;
             }
         }
-        Ok(trace)
+        Ok(knn
+            .into_iter()
+            .zip(&points)
+            .map(|(nbrs, point)| self.process_results(Operation::Knn, point, nbrs))
+            .collect())
     }
 
-    ///
-    pub fn known_path(&self, point_index: usize) -> GokoResult<Vec<(f32, NodeAddress)>> {
-        self.final_addresses
-            .get_and(&point_index, |addr| {
-                let mut path = Vec::with_capacity((self.root_address().0 - addr.0) as usize);
-                let mut parent = Some(*addr);
-                while let Some(addr) = parent {
-                    path.push(addr);
-                    parent = self.get_node_and(addr, |n| n.parent_address()).flatten();
-                }
-                (&mut path[..]).reverse();
-                let point_indexes: Vec<usize> = path.iter().map(|na| na.1).collect();
-                let dists = self
-                    .parameters
-                    .point_cloud
-                    .distances_to_point_index(point_index, &point_indexes[..])
-                    .unwrap();
-                dists.iter().zip(path).map(|(d, a)| (*d, a)).collect()
-            })
-            .ok_or(GokoError::IndexNotInTree(point_index))
+    /// The node a query reaches by stepping `depth` times to the closest child, the coarse location `knn_batch` groups
+    /// queries by.
+    fn coarse_address<P: Deref<Target = D::Point> + Send + Sync>(
+        &self,
+        point: &P,
+        depth: usize,
+    ) -> GokoResult<NodeAddress> {
+        let mut address = self.root_address;
+        for _ in 0..depth {
+            let children = self
+                .get_node_and(address, |n| {
+                    n.children().map(|(nested_scale, children)| {
+                        let mut children = children.to_vec();
+                        children.push((nested_scale, address.1));
+                        children
+                    })
+                })
+                .ok_or(GokoError::NodeNotInTree(address))?;
+            let children = match children {
+                Some(children) => children,
+                None => break,
+            };
+            let centers: Vec<usize> = children.iter().map(|(_, pi)| *pi).collect();
+            let distances = self
+                .parameters
+                .point_cloud
+                .distances_to_point(point, &centers)?;
+            let closest = distances
+                .iter()
+                .enumerate()
+                .min_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))
+                .map(|(i, _)| i)
+                .unwrap_or(0);
+            address = children[closest];
+        }
+        Ok(address)
     }
 
-    ///Computes the fractal dimension of a node
-    pub fn node_fractal_dim(&self, node_address: NodeAddress) -> f32 {
-        let count: f32 = self
-            .get_node_and(node_address, |n| {
-                (n.singletons_len() + n.children_len()) as f32
+    /// The shared descent of a group of queries for `knn_batch`. Nodes are visited depth first, closest to the group
+    /// first. A query drops out of a subtree once the node's covering radius can't reach its current `k`th distance,
+    /// and the subtree is skipped when every query in the group has dropped out.
+    fn knn_group<P: Deref<Target = D::Point> + Send + Sync>(
+        &self,
+        points: &[&P],
+        k: usize,
+    ) -> GokoResult<Vec<Vec<(f32, usize)>>> {
+        let point_cloud = &self.parameters.point_cloud;
+        let metric = point_cloud.metric();
+        let deleted = point_cloud.deleted_indexes();
+        let mut heaps: Vec<KnnQueryHeap> = points
+            .iter()
+            .map(|_| {
+                let mut heap = KnnQueryHeap::new(k, self.parameters.scale_base);
+                heap.exclude(deleted.iter().copied());
+                heap
             })
-            .unwrap() as f32;
-        count.log(self.parameters.scale_base)
-    }
+            .collect();
 
-    ///Computes the weighted fractal dimension of a node
-    pub fn node_weighted_fractal_dim(&self, node_address: NodeAddress) -> f32 {
-        let weighted_count: f32 = self
-            .get_node_and(node_address, |n| {
-                let singleton_count = n.singletons().len() as f32;
-                let mut max_pop: usize = 1;
-                let mut weighted_count: f32 = 0.0;
-                if let Some((nested_scale, children)) = n.children() {
-                    let mut pops: Vec<usize> = children
+        let root_center = point_cloud.point(self.root_address.1)?;
+        let root_dists: Vec<f32> = points
+            .iter()
+            .map(|point| metric.dist(&root_center, point))
+            .collect();
+        let mut unvisited = vec![(self.root_address, root_dists)];
+        while let Some((address, mut dists)) = unvisited.pop() {
+            let (singletons, children) = self
+                .get_node_and(address, |n| {
+                    for (dist, heap) in dists.iter_mut().zip(&heaps) {
+                        if *dist - n.radius() > heap.max_dist() {
+                            *dist = std::f32::INFINITY;
+                        }
+                    }
+                    let children = n.children().map(|(ns, c)| (ns, c.to_vec()));
+                    (n.singletons_decoded().into_owned(), children)
+                })
+                .ok_or(GokoError::NodeNotInTree(address))?;
+            let active: Vec<usize> = (0..points.len())
+                .filter(|i| dists[*i].is_finite())
+                .collect();
+            if active.is_empty() {
+                continue;
+            }
+
+            if !singletons.is_empty() {
+                let singleton_points = singletons
+                    .iter()
+                    .map(|pi| point_cloud.point(*pi))
+                    .collect::<Result<Vec<_>, _>>()?;
+                for i in &active {
+                    let singleton_dists: Vec<f32> = singleton_points
                         .iter()
-                        .map(|child_addr| {
-                            self.get_node_and(*child_addr, |child| child.coverage_count())
-                                .unwrap()
-                        })
+                        .map(|x| metric.dist(x, points[*i]))
                         .collect();
-                    pops.push(
-                        self.get_node_and((nested_scale, node_address.1), |child| {
-                            child.coverage_count()
+                    heaps[*i].push_outliers(&singletons, &singleton_dists);
+                }
+            }
+            match children {
+                None => {
+                    for i in &active {
+                        heaps[*i].push_outliers(&[address.1], &[dists[*i]]);
+                    }
+                }
+                Some((nested_scale, children)) => {
+                    let centers = children
+                        .iter()
+                        .map(|(_, pi)| point_cloud.point(*pi))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    let mut next: Vec<(f32, NodeAddress, Vec<f32>)> = children
+                        .iter()
+                        .zip(&centers)
+                        .map(|(child, center)| {
+                            let mut child_dists = vec![std::f32::INFINITY; points.len()];
+                            for i in &active {
+                                child_dists[*i] = metric.dist(center, points[*i]);
+                            }
+                            let closest = active
+                                .iter()
+                                .map(|i| child_dists[*i])
+                                .fold(std::f32::INFINITY, f32::min);
+                            (closest, *child, child_dists)
                         })
-                        .unwrap(),
-                    );
-                    max_pop = *pops.iter().max().unwrap();
-                    pops.iter()
-                        .for_each(|p| weighted_count += (*p as f32) / (max_pop as f32));
+                        .collect();
+                    let closest = active
+                        .iter()
+                        .map(|i| dists[*i])
+                        .fold(std::f32::INFINITY, f32::min);
+                    next.push((closest, (nested_scale, address.1), dists));
+                    // The stack pops the closest child first
+                    next.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+                    unvisited.extend(next.into_iter().map(|(_, child, dists)| (child, dists)));
                 }
-                weighted_count + singleton_count / (max_pop as f32)
-            })
-            .unwrap();
-        weighted_count.log(self.parameters.scale_base)
+            }
+        }
+        Ok(heaps.into_iter().map(|heap| heap.unpack()).collect())
     }
 
-    ///Computes the fractal dimension of a layer
-    pub fn layer_fractal_dim(&self, scale_index: i32) -> f32 {
-        let parent_layer = self.layer(scale_index);
-        let parent_count = parent_layer.len() as f32;
-        let mut child_count: f32 = 0.0;
-        parent_layer
-            .for_each_node(|_, n| child_count += (n.singletons_len() + n.children_len()) as f32);
-        child_count.log(self.parameters.scale_base) - parent_count.log(self.parameters.scale_base)
+    /// The `k` nearest neighbors of every point in the tree, not counting the point itself, in the order of the point
+    /// cloud's `reference_indexes`. This is the kNN graph of the training set.
+    ///
+    /// It's a dual-tree traversal of the tree against itself rather than a query per point. Every node is a query for
+    /// all the points it covers at once, and carries a list of candidate reference nodes and points. The candidates
+    /// are opened until none is bigger than the query node, then a candidate is dropped if nothing it covers can be
+    /// closer to the query node than the `k+1`th closest candidate center, padded by the query node's radius on both
+    /// sides. The children of the query node start from its pruned list, so a far away part of the tree is ruled out
+    /// once for a whole subtree. The singletons of a node, and the center of a leaf, finish with a `knn` seeded by the
+    /// node's candidates. Sibling subtrees run in parallel, each rayon worker with its own copy of the reader. The
+    /// results are exact, the same as `knn` on each point asking for one more and dropping the point itself.
+    pub fn all_knn(&self, k: usize) -> GokoResult<KnnBatch> {
+        let root_radius = self
+            .get_node_and(self.root_address, |n| n.radius())
+            .ok_or(GokoError::NodeNotInTree(self.root_address))?;
+        let root_candidate = DualCandidate {
+            address: Some(self.root_address),
+            center: self.root_address.1,
+            radius: root_radius,
+            dist: 0.0,
+        };
+        let neighbors: HashMap<usize, Vec<(f32, usize)>> = self
+            .all_knn_node(self.root_address, vec![root_candidate], k)?
+            .into_iter()
+            .collect();
+        Ok(self
+            .parameters
+            .point_cloud
+            .reference_indexes()
+            .iter()
+            .map(|pi| neighbors.get(pi).cloned().unwrap_or_default())
+            .collect())
     }
 
-    ///Computes the weighted fractal dimension of a node
-    pub fn layer_weighted_fractal_dim(&self, scale_index: i32) -> f32 {
-        // gather the coverages of every node on the layer and their's children
-        let parent_layer = self.layer(scale_index);
-        let mut parent_coverage_counts: Vec<usize> = Vec::new();
-        let mut child_coverage_counts: Vec<usize> = Vec::new();
-        let mut singletons_count: f32 = 0.0;
-        parent_layer.for_each_node(|center_index, n| {
-            parent_coverage_counts.push(n.coverage_count());
-
-            singletons_count += n.singletons().len() as f32;
-            if let Some((nested_scale, children)) = n.children() {
-                child_coverage_counts.extend(children.iter().map(|child_addr| {
-                    self.get_node_and(*child_addr, |child| child.coverage_count())
-                        .unwrap()
-                }));
-                child_coverage_counts.push(
-                    self.get_node_and((nested_scale, *center_index), |child| {
-                        child.coverage_count()
+    /// The dual-tree step of `all_knn` for one query node, returns the neighbors of everything the node covers.
+    fn all_knn_node(
+        &self,
+        address: NodeAddress,
+        mut candidates: Vec<DualCandidate>,
+        k: usize,
+    ) -> GokoResult<Vec<(usize, Vec<(f32, usize)>)>> {
+        let point_cloud = &self.parameters.point_cloud;
+        let (radius, singletons, children) = self
+            .get_node_and(address, |n| {
+                let children = n.children().map(|(ns, c)| (ns, c.to_vec()));
+                (n.radius(), n.singletons_decoded().into_owned(), children)
+            })
+            .ok_or(GokoError::NodeNotInTree(address))?;
+
+        loop {
+            // Each point is within this of k + 1 distinct centers, one of which may be the point itself
+            let bound = self.dual_kth_dist(&candidates, k + 1) + 2.0 * radius;
+            candidates.retain(|c| c.dist - c.radius <= bound);
+            let (to_open, kept): (Vec<DualCandidate>, Vec<DualCandidate>) = candidates
+                .into_iter()
+                .partition(|c| c.address.is_some() && c.radius > radius);
+            candidates = kept;
+            if to_open.is_empty() {
+                break;
+            }
+            for candidate in to_open {
+                let candidate_address = candidate.address.unwrap();
+                let (candidate_singletons, candidate_children) = self
+                    .get_node_and(candidate_address, |n| {
+                        let children = n.children().map(|(ns, c)| {
+                            let mut children = c.to_vec();
+                            children.push((ns, candidate_address.1));
+                            children
+                        });
+                        (n.singletons_decoded().into_owned(), children)
                     })
-                    .unwrap(),
+                    .ok_or(GokoError::NodeNotInTree(candidate_address))?;
+                let singleton_dists =
+                    point_cloud.distances_to_point_index(address.1, &candidate_singletons)?;
+                candidates.extend(
+                    candidate_singletons
+                        .iter()
+                        .zip(singleton_dists)
+                        .map(|(pi, d)| DualCandidate::point(*pi, d)),
                 );
+                match candidate_children {
+                    None => candidates.push(DualCandidate::point(candidate.center, candidate.dist)),
+                    Some(candidate_children) => {
+                        let centers: Vec<usize> =
+                            candidate_children.iter().map(|(_, pi)| *pi).collect();
+                        let dists = point_cloud.distances_to_point_index(address.1, &centers)?;
+                        for (child_address, d) in candidate_children.into_iter().zip(dists) {
+                            let child_radius = self
+                                .get_node_and(child_address, |n| n.radius())
+                                .ok_or(GokoError::NodeNotInTree(child_address))?;
+                            candidates.push(DualCandidate {
+                                address: Some(child_address),
+                                center: child_address.1,
+                                radius: child_radius,
+                                dist: d,
+                            });
+                        }
+                    }
+                }
             }
-        });
-        // Get the maximum count
-        let max_parent_pop: f32 = *parent_coverage_counts.iter().max().unwrap_or(&1) as f32;
-        let max_child_pop: f32 = *child_coverage_counts.iter().max().unwrap_or(&1) as f32;
-
-        // Normalize the counts by the maximum
-        let weighted_child_sum: f32 = singletons_count / max_child_pop
-            + child_coverage_counts
-                .iter()
-                .fold(0.0, |a, c| a + (*c as f32) / max_child_pop);
-        let weighted_parent_sum: f32 = parent_coverage_counts
-            .iter()
-            .fold(0.0, |a, c| a + (*c as f32) / max_parent_pop);
-
-        // take the log and return
-        weighted_child_sum.log(self.parameters.scale_base)
-            - weighted_parent_sum.log(self.parameters.scale_base)
-    }
+        }
 
-    /// Checks that there are no node addresses in the child list of any node that don't reference a node in the tree.
-    /// Please calmly panic if there are, the tree is very invalid.
-    pub(crate) fn no_dangling_refs(&self) -> bool {
-        let mut refs_to_check = vec![self.root_address];
-        while let Some(node_addr) = refs_to_check.pop() {
-            println!("checking {:?}", node_addr);
-            println!("refs_to_check: {:?}", refs_to_check);
-            let node_exists = self.get_node_and(node_addr, |n| {
-                if let Some((nested_scale, other_children)) = n.children() {
-                    println!(
-                        "Pushing: {:?}, {:?}",
-                        (nested_scale, other_children),
-                        other_children
-                    );
-                    refs_to_check.push((nested_scale, node_addr.1));
-                    refs_to_check.extend(&other_children[..]);
+        let mut query_points = singletons;
+        if children.is_none() {
+            query_points.push(address.1);
+        }
+        let mut neighbors = Vec::with_capacity(query_points.len());
+        for pi in query_points {
+            if point_cloud.is_deleted(pi) {
+                continue;
+            }
+            let point = point_cloud.point(pi)?;
+            let centers: Vec<usize> = candidates.iter().map(|c| c.center).collect();
+            let dists = point_cloud.distances_to_point(&point, &centers)?;
+            let mut query_heap = KnnQueryHeap::new(k, self.parameters.scale_base);
+            query_heap.exclude(point_cloud.deleted_indexes());
+            query_heap.exclude(std::iter::once(pi));
+            for (candidate, d) in candidates.iter().zip(&dists) {
+                match candidate.address {
+                    Some(candidate_address) => {
+                        query_heap.push_nodes(&[candidate_address], &[*d], None)
+                    }
+                    None => query_heap.push_outliers(&[candidate.center], &[*d]),
                 }
-            });
-            if node_exists.is_none() {
-                return false;
             }
+            self.search_knn_heap(&point, &mut query_heap);
+            neighbors.push((pi, query_heap.unpack()));
         }
-        true
-    }
-}
-
-///
-pub struct CoverTreeWriter<D: PointCloud> {
-    pub(crate) parameters: Arc<CoverTreeParameters<D>>,
-    pub(crate) layers: Vec<CoverLayerWriter<D>>,
-    pub(crate) root_address: NodeAddress,
-    pub(crate) final_addresses: MonoWriteHandle<usize, NodeAddress>,
-}
-
-impl<D: PointCloud> CoverTreeWriter<D> {
-    ///
-    pub fn generate_meta_summaries(&mut self) {
-        self.add_plugin::<MetaSummaryPlugin>(MetaSummaryPlugin::default())
-    }
 
-    ///
-    pub fn generate_summaries(&mut self) {
-        self.add_plugin::<LabelSummaryPlugin>(LabelSummaryPlugin::default())
+        if let Some((nested_scale, mut children)) = children {
+            children.push((nested_scale, address.1));
+            let child_neighbors: Vec<GokoResult<Vec<(usize, Vec<(f32, usize)>)>>> = children
+                .par_iter()
+                .map_with(self.clone(), |reader, child| {
+                    let mut child_candidates = candidates.clone();
+                    if child.1 != address.1 {
+                        let centers: Vec<usize> =
+                            child_candidates.iter().map(|c| c.center).collect();
+                        let dists = reader
+                            .parameters
+                            .point_cloud
+                            .distances_to_point_index(child.1, &centers)?;
+                        for (candidate, d) in child_candidates.iter_mut().zip(dists) {
+                            candidate.dist = d;
+                        }
+                    }
+                    reader.all_knn_node(*child, child_candidates, k)
+                })
+                .collect();
+            for child_neighbors in child_neighbors {
+                neighbors.extend(child_neighbors?);
+            }
+        }
+        Ok(neighbors)
     }
 
-    ///
-    pub fn add_plugin<P: GokoPlugin<D>>(&mut self, plug_in: P) {
-        P::prepare_tree(&plug_in, self);
-        let reader = self.reader();
-        for layer in self.layers.iter_mut() {
-            layer.reader().for_each_node(|pi, n| {
-                if let Some(node_component) = P::node_component(&plug_in, n, &reader) {
-                    unsafe {
-                        layer.update_node(*pi, move |n| n.insert_plugin(node_component.clone()))
-                    }
-                }
-            });
-            layer.refresh()
+    /// The `n`th smallest distance to a distinct candidate center, infinite if there are fewer than `n`. Deleted
+    /// centers don't count, they can't be neighbors.
+    fn dual_kth_dist(&self, candidates: &[DualCandidate], n: usize) -> f32 {
+        let point_cloud = &self.parameters.point_cloud;
+        let mut seen = HashSet::new();
+        let mut dists: Vec<f32> = candidates
+            .iter()
+            .filter(|c| !point_cloud.is_deleted(c.center) && seen.insert(c.center))
+            .map(|c| c.dist)
+            .collect();
+        if dists.len() < n {
+            return std::f32::INFINITY;
         }
-        self.parameters.plugins.write().unwrap().insert(plug_in);
+        dists.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        dists[n - 1]
     }
 
-    /// Provides a reference to a `CoverLayerWriter`. Do not use, unless you're going to leave the tree in a *valid* state.
-    pub(crate) unsafe fn layer(&mut self, scale_index: i32) -> &mut CoverLayerWriter<D> {
-        &mut self.layers[self.parameters.internal_index(scale_index)]
+    /// `knn` that trades recall for speed. Nodes are pruned by the current `k`th distance divided by `1 + epsilon`, so
+    /// the `k`th neighbor found is at most `1 + epsilon` times farther than the true one. `epsilon = 0` is exact `knn`.
+    /// At high dimension exact search ends up reading most of the tree, and a small `epsilon` cuts that down a lot.
+    pub fn knn_approx<P: Deref<Target = D::Point> + Send + Sync>(
+        &self,
+        point: &P,
+        k: usize,
+        epsilon: f32,
+    ) -> GokoResult<Vec<(f32, usize)>> {
+        let timer = self.parameters.latencies.start();
+        let knn = self.with_middleware(Operation::Knn, self.preprocess(point), |point| {
+            let point = &point;
+            let mut query_heap = KnnQueryHeap::new(k, self.parameters.scale_base);
+            query_heap.exclude(self.parameters.point_cloud.deleted_indexes());
+            query_heap.allow_error(epsilon);
+
+            let root_center = self.parameters.point_cloud.point(self.root_address.1)?;
+            let dist_to_root = self
+                .parameters
+                .point_cloud
+                .metric()
+                .dist(&root_center, &point);
+            query_heap.push_nodes(&[self.root_address], &[dist_to_root], None);
+            self.search_knn_heap(point, &mut query_heap);
+
+            Ok(query_heap.unpack())
+        });
+        self.parameters.latencies.record(Operation::Knn, timer);
+        knn
     }
 
-    pub(crate) unsafe fn update_node<F>(&mut self, address: NodeAddress, update_fn: F)
+    /// `knn_approx` with an exact rerank. The approximate search gathers `candidate_multiplier * k` candidates, which
+    /// are rescored by their distance to `original_point` in `original`, after `original`'s preprocessing, and cut down
+    /// to the closest `k`. `original` has
+    /// to have the same indexes as the tree's point cloud. For a tree of quantized points, like a `DataRamF16`, it's the
+    /// full precision data, so the returned distances are exact. For any other tree pass the tree's own point cloud,
+    /// the extra candidates then win back most of the recall `epsilon` gives up.
+    pub fn knn_reranked<P, C, Q>(
+        &self,
+        point: &P,
+        original: &C,
+        original_point: &Q,
+        k: usize,
+        epsilon: f32,
+        candidate_multiplier: usize,
+    ) -> GokoResult<Vec<(f32, usize)>>
     where
-        F: Fn(&mut CoverNode<D>) + 'static + Send + Sync,
+        P: Deref<Target = D::Point> + Send + Sync,
+        C: PointCloud,
+        Q: Deref<Target = C::Point> + Send + Sync,
     {
-        self.layers[self.parameters.internal_index(address.0)].update_node(address.1, update_fn);
+        let candidates = self.knn_approx(point, k * candidate_multiplier.max(1), epsilon)?;
+        let indexes: Vec<usize> = candidates.iter().map(|(_, pi)| *pi).collect();
+        let original_point = match original.preprocess_query(original_point) {
+            Some(point) => QueryPoint::Preprocessed(point),
+            None => QueryPoint::Given(&**original_point),
+        };
+        let dists = original.distances_to_point(&original_point, &indexes)?;
+        let mut reranked: Vec<(f32, usize)> = dists.into_iter().zip(indexes).collect();
+        reranked.sort_by(|a, b| {
+            a.0.partial_cmp(&b.0)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then(a.1.cmp(&b.1))
+        });
+        reranked.truncate(k);
+        Ok(reranked)
     }
 
-    /// Creates a reader for queries.
-    pub fn reader(&self) -> CoverTreeReader<D> {
-        CoverTreeReader {
-            parameters: Arc::clone(&self.parameters),
-            layers: self.layers.iter().map(|l| l.reader()).collect(),
-            root_address: self.root_address,
-            final_addresses: self.final_addresses.factory().handle(),
-        }
+    /// A summary of the labels of the `k` nearest neighbors. For vector labels, like regression targets, this is the
+    /// per-target mean and variance of the neighbors, a kNN regression. For categorical labels it's the neighbors' votes.
+    pub fn knn_label_summary<P: Deref<Target = D::Point> + Send + Sync>(
+        &self,
+        point: &P,
+        k: usize,
+    ) -> GokoResult<SummaryCounter<D::LabelSummary>> {
+        let indexes: Vec<usize> = self.knn(point, k)?.iter().map(|(_, i)| *i).collect();
+        Ok(self.parameters.point_cloud.label_summary(&indexes)?)
     }
 
-    pub(crate) unsafe fn insert_raw(
-        &mut self,
-        scale_index: i32,
-        point_index: usize,
-        node: CoverNode<D>,
-    ) {
-        self.layers[self.parameters.internal_index(scale_index)].insert_raw(point_index, node);
+    /// The `k` nearest neighbors with their metadata attached, closest first. Points without metadata get `None`. This
+    /// lets results be joined back to document ids or URLs without the caller keeping a lookup table.
+    pub fn knn_with_metadata<P: Deref<Target = D::Point> + Send + Sync>(
+        &self,
+        point: &P,
+        k: usize,
+    ) -> GokoResult<Vec<(f32, usize, Option<&D::Metadata>)>> {
+        self.knn(point, k)?
+            .into_iter()
+            .map(|(d, i)| Ok((d, i, self.parameters.point_cloud.metadata(i)?)))
+            .collect()
     }
 
-    /// Loads a tree from a protobuf. There's a `load_tree` in `utils` that handles loading from a path to a protobuf file.
-    pub fn load(cover_proto: &CoreProto, point_cloud: Arc<D>) -> GokoResult<CoverTreeWriter<D>> {
-        let partition_type = if cover_proto.partition_type == "first" {
-            PartitionType::First
-        } else {
-            PartitionType::Nearest
-        };
-
-        let parameters = Arc::new(CoverTreeParameters {
-            total_nodes: atomic::AtomicUsize::new(0),
-            use_singletons: cover_proto.use_singletons,
-            scale_base: cover_proto.scale_base as f32,
-            leaf_cutoff: cover_proto.cutoff as usize,
-            min_res_index: cover_proto.resolution as i32,
-            point_cloud,
-            verbosity: 2,
-            partition_type,
-            plugins: RwLock::new(TreePluginSet::new()),
-            rng_seed: None,
-        });
-        let root_address = (
-            cover_proto.get_root_scale(),
-            cover_proto.get_root_index() as usize,
-        );
-        let layers: Vec<CoverLayerWriter<D>> = cover_proto
-            .get_layers()
-            .par_iter()
-            .map(|l| CoverLayerWriter::load(l))
-            .collect();
-
-        let (_final_addresses_reader, final_addresses) = monomap::new();
+    /// The `k` nearest neighbors as `(distance, index, label, address)`, closest first. The address is the node the
+    /// point ends up in, the leaf it centers or the node holding it as a singleton. Points without a label get `None`.
+    /// This saves looking up each neighbor's label and path one by one after the query.
+    pub fn knn_annotated<P: Deref<Target = D::Point> + Send + Sync>(
+        &self,
+        point: &P,
+        k: usize,
+    ) -> GokoResult<Vec<(f32, usize, Option<&D::Label>, NodeAddress)>> {
+        self.knn(point, k)?
+            .into_iter()
+            .map(|(d, i)| {
+                let address = self
+                    .final_addresses
+                    .get_and(&i, |addr| *addr)
+                    .ok_or(GokoError::IndexNotInTree(i))?;
+                Ok((d, i, self.parameters.point_cloud.label(i)?, address))
+            })
+            .collect()
+    }
 
-        let mut tree = CoverTreeWriter {
-            parameters,
-            layers,
-            root_address,
-            final_addresses,
-        };
+    /// The `k` nearest neighbors, leaving out the `excluded` points. This is for temporary exclusions, like quarantined
+    /// data, that shouldn't need an edit to the tree. The excluded points are counted on each node along their paths, and
+    /// nodes that cover nothing but excluded points are never opened.
+    pub fn knn_excluding<P: Deref<Target = D::Point> + Send + Sync>(
+        &self,
+        point: &P,
+        k: usize,
+        excluded: &RoaringBitmap,
+    ) -> GokoResult<Vec<(f32, usize)>> {
+        let timer = self.parameters.latencies.start();
+        let knn = self.with_middleware(Operation::Knn, self.preprocess(point), |point| {
+            let point = &point;
+            let mut query_heap = KnnQueryHeap::new(k, self.parameters.scale_base);
+            query_heap.exclude(self.parameters.point_cloud.deleted_indexes());
+            query_heap.exclude(excluded.iter().map(|pi| pi as usize));
+            for address in self.fully_excluded_nodes(excluded)? {
+                query_heap.mark_visited(address);
+            }
 
-        tree.refresh_final_indexes();
+            let root_center = self.parameters.point_cloud.point(self.root_address.1)?;
+            let dist_to_root = self
+                .parameters
+                .point_cloud
+                .metric()
+                .dist(&root_center, &point);
+            query_heap.push_nodes(&[self.root_address], &[dist_to_root], None);
+            self.search_knn_heap(point, &mut query_heap);
 
-        Ok(tree)
+            Ok(query_heap.unpack())
+        });
+        self.parameters.latencies.record(Operation::Knn, timer);
+        knn
     }
 
-    /// Completely redoes the final index map.
-    pub fn refresh_final_indexes(&mut self) {
-        let reader = self.reader();
-        let mut unvisited_nodes: Vec<NodeAddress> = vec![self.root_address];
-        while !unvisited_nodes.is_empty() {
-            let cur_add = unvisited_nodes.pop().unwrap();
-            reader
-                .get_node_and(cur_add, |n| {
-                    for singleton in n.singletons() {
-                        self.final_addresses.insert(*singleton, cur_add);
-                    }
-                    if let Some((nested_si, child_addresses)) = n.children() {
-                        unvisited_nodes.extend(child_addresses);
-                        unvisited_nodes.push((nested_si, cur_add.1));
-                    } else {
-                        self.final_addresses.insert(cur_add.1, cur_add);
-                    }
-                })
-                .unwrap();
+    /// The nodes whose whole coverage is in the excluded set.
+    fn fully_excluded_nodes(&self, excluded: &RoaringBitmap) -> GokoResult<Vec<NodeAddress>> {
+        let mut excluded_counts: HashMap<NodeAddress, usize> = HashMap::new();
+        for pi in excluded.iter() {
+            let pi = pi as usize;
+            let mut parent = Some(
+                self.final_addresses
+                    .get_and(&pi, |addr| *addr)
+                    .ok_or(GokoError::IndexNotInTree(pi))?,
+            );
+            while let Some(address) = parent {
+                *excluded_counts.entry(address).or_insert(0) += 1;
+                parent = self.get_node_and(address, |n| n.parent_address()).flatten();
+            }
         }
-
-        self.final_addresses.refresh();
-        self.final_addresses.refresh();
+        Ok(excluded_counts
+            .into_iter()
+            .filter(|(address, count)| {
+                self.get_node_and(*address, |n| n.coverage_count() <= *count)
+                    .unwrap_or(false)
+            })
+            .map(|(address, _)| address)
+            .collect())
     }
 
-    /// Encodes the tree into a protobuf. See `utils::save_tree` for saving to a file on disk.
-    pub fn save(&self) -> CoreProto {
-        let mut cover_proto = CoreProto::new();
-        match self.parameters.partition_type {
-            PartitionType::First => cover_proto.set_partition_type("first".to_string()),
-            PartitionType::Nearest => cover_proto.set_partition_type("nearest".to_string()),
+    /// The `k` nearest neighbors among the points in the given partitions, see [`pointcloud::partitions`]. Unknown
+    /// partition keys match nothing, and a point cloud without partitions has no points in any partition. With the
+    /// [`PartitionPlugin`] attached nodes that cover no point of the partitions are never opened, without it only the
+    /// results are filtered.
+    pub fn knn_in_partitions<P: Deref<Target = D::Point> + Send + Sync, S: AsRef<str>>(
+        &self,
+        point: &P,
+        k: usize,
+        partitions: &[S],
+    ) -> GokoResult<Vec<(f32, usize)>> {
+        let partition_map = match self.parameters.point_cloud.partitions() {
+            Some(partition_map) => partition_map,
+            None => return Ok(Vec::new()),
+        };
+        let ids = partition_map.ids(partitions);
+        if ids.is_empty() {
+            return Ok(Vec::new());
         }
-        cover_proto.set_scale_base(self.parameters.scale_base);
-        cover_proto.set_cutoff(self.parameters.leaf_cutoff as u64);
-        cover_proto.set_resolution(self.parameters.min_res_index);
-        cover_proto.set_use_singletons(self.parameters.use_singletons);
-        cover_proto.set_dim(self.parameters.point_cloud.dim() as u64);
-        cover_proto.set_count(self.parameters.point_cloud.len() as u64);
-        cover_proto.set_root_scale(self.root_address.0);
-        cover_proto.set_root_index(self.root_address.1 as u64);
-        cover_proto.set_layers(self.layers.iter().map(|l| l.save()).collect());
-        let name_map: std::collections::HashMap<String, u64> =
-            self.final_addresses.map_into(|k, _v| {
-                (
-                    self.parameters.point_cloud.name(*k).unwrap().to_string(),
-                    *k as u64,
-                )
-            });
-        cover_proto.set_name_map(name_map);
-        cover_proto
+        let timer = self.parameters.latencies.start();
+        let knn = self.with_middleware(Operation::Knn, self.preprocess(point), |point| {
+            let point = &point;
+            let mut query_heap = KnnQueryHeap::new(k, self.parameters.scale_base);
+            query_heap.exclude(self.parameters.point_cloud.deleted_indexes());
+            query_heap.restrict_to(partition_map.union(&ids));
+            let pruned = |address: NodeAddress| {
+                self.get_node_plugin_and::<NodePartitionCounts, _, _>(address, |p| {
+                    p.count_in(&ids) == 0
+                })
+                .unwrap_or(false)
+            };
+
+            let root_center = self.parameters.point_cloud.point(self.root_address.1)?;
+            let dist_to_root = self
+                .parameters
+                .point_cloud
+                .metric()
+                .dist(&root_center, &point);
+            query_heap.push_nodes(&[self.root_address], &[dist_to_root], None);
+            self.search_pruned_knn_heap(point, &mut query_heap, &pruned);
+
+            Ok(query_heap.unpack())
+        });
+        self.parameters.latencies.record(Operation::Knn, timer);
+        knn
     }
 
-    /// Swaps the maps on each layer so that any `CoverTreeReaders` see the updated tree.
-    /// Only call once you have a valid tree.
-    pub fn refresh(&mut self) {
-        self.layers.iter_mut().rev().for_each(|l| l.refresh());
+    /// The `k` nearest neighbors no farther than `radius` from the point, closest first. There can be fewer than `k`, or
+    /// none. Until `k` neighbors are found the radius bounds the search, and after that the `k`th neighbor's distance
+    /// does, so nodes that can't beat both are never opened. This is cheaper than a `knn` followed by a filter when most
+    /// of the `k` nearest are outside the radius.
+    pub fn knn_within<P: Deref<Target = D::Point> + Send + Sync>(
+        &self,
+        point: &P,
+        k: usize,
+        radius: f32,
+    ) -> GokoResult<Vec<(f32, usize)>> {
+        let timer = self.parameters.latencies.start();
+        let knn = self.with_middleware(Operation::Knn, self.preprocess(point), |point| {
+            let point = &point;
+            let mut query_heap = KnnQueryHeap::with_radius(k, radius, self.parameters.scale_base);
+            query_heap.exclude(self.parameters.point_cloud.deleted_indexes());
+
+            let root_center = self.parameters.point_cloud.point(self.root_address.1)?;
+            let dist_to_root = self
+                .parameters
+                .point_cloud
+                .metric()
+                .dist(&root_center, &point);
+            query_heap.push_nodes(&[self.root_address], &[dist_to_root], None);
+            self.search_knn_heap(point, &mut query_heap);
+
+            Ok(query_heap.unpack())
+        });
+        self.parameters.latencies.record(Operation::Knn, timer);
+        knn
     }
-}
 
-#[cfg(test)]
-pub(crate) mod tests {
-    use super::*;
+    /// A warm start KNN, for streams of queries where each one is close to the last. The hint is a node address from a
+    /// previous similar query, for example the last address of its `path` or a node above it. The hinted subtree is searched
+    /// first, which usually finds the nearest neighbors quickly. The search then starts over from the root with the
+    /// distance to the `k`th neighbor found so far, which rules out almost every other node, so the result is exactly the
+    /// same as `knn`.
+    ///
+    /// If the hint isn't in the tree, for example because the tree was edited since the previous query, this is just `knn`.
+    pub fn knn_hinted<P: Deref<Target = D::Point> + Send + Sync>(
+        &self,
+        point: &P,
+        k: usize,
+        hint: NodeAddress,
+    ) -> GokoResult<Vec<(f32, usize)>> {
+        if hint.0 >= self.root_address.0 || self.get_node_and(hint, |_| ()).is_none() {
+            return self.knn(point, k);
+        }
+        let timer = self.parameters.latencies.start();
+        let knn = self.with_middleware(Operation::Knn, self.preprocess(point), |point| {
+            let point = &point;
+            let mut query_heap = KnnQueryHeap::new(k, self.parameters.scale_base);
+            query_heap.exclude(self.parameters.point_cloud.deleted_indexes());
+
+            let hint_center = self.parameters.point_cloud.point(hint.1)?;
+            let dist_to_hint = self
+                .parameters
+                .point_cloud
+                .metric()
+                .dist(&hint_center, &point);
+            query_heap.push_nodes(&[hint], &[dist_to_hint], None);
+            self.search_knn_heap(point, &mut query_heap);
+            // Every node has one parent, so this skips the whole hinted subtree from here on
+            query_heap.mark_visited(hint);
+
+            let root_center = self.parameters.point_cloud.point(self.root_address.1)?;
+            let dist_to_root = self
+                .parameters
+                .point_cloud
+                .metric()
+                .dist(&root_center, &point);
+            query_heap.push_nodes(&[self.root_address], &[dist_to_root], None);
+            self.search_knn_heap(point, &mut query_heap);
+
+            Ok(query_heap.unpack())
+        });
+        self.parameters.latencies.record(Operation::Knn, timer);
+        knn
+    }
 
-    use crate::utils::cover_tree_from_labeled_yaml;
-    use std::path::Path;
+    /// Searches every node on the heap, and every node they lead to, until the heap is empty.
+    fn search_knn_heap<P: Deref<Target = D::Point> + Send + Sync>(
+        &self,
+        point: &P,
+        query_heap: &mut KnnQueryHeap,
+    ) {
+        self.search_pruned_knn_heap(point, query_heap, &|_| false);
+    }
 
-    pub(crate) fn build_mnist_tree() -> CoverTreeWriter<DefaultLabeledCloud<L2>> {
+    /// `search_knn_heap` that skips the nodes, and so the subtrees, the `pruned` predicate picks out.
+    fn search_pruned_knn_heap<P, F>(&self, point: &P, query_heap: &mut KnnQueryHeap, pruned: &F)
+    where
+        P: Deref<Target = D::Point> + Send + Sync,
+        F: Fn(NodeAddress) -> bool,
+    {
+        self.greedy_knn_nodes(point, query_heap, pruned);
+        while let Some((_dist, address)) = query_heap.closest_unvisited_singleton_covering_address()
+        {
+            if pruned(address) {
+                continue;
+            }
+            self.get_node_and(address, |n| {
+                n.singleton_knn(point, &self.parameters.point_cloud, query_heap)
+            });
+            self.greedy_knn_nodes(point, query_heap, pruned);
+        }
+    }
+
+    /// Same as knn, but only deals with non-singleton points
+    pub fn routing_knn<P: Deref<Target = D::Point> + Send + Sync>(
+        &self,
+        point: &P,
+        k: usize,
+    ) -> GokoResult<Vec<(f32, usize)>> {
+        let timer = self.parameters.latencies.start();
+        let knn = self.with_middleware(Operation::Knn, self.preprocess(point), |point| {
+            let point = &point;
+            let mut query_heap = KnnQueryHeap::new(k, self.parameters.scale_base);
+            query_heap.exclude(self.parameters.point_cloud.deleted_indexes());
+
+            let root_center = self.parameters.point_cloud.point(self.root_address.1)?;
+            let dist_to_root = self
+                .parameters
+                .point_cloud
+                .metric()
+                .dist(&root_center, &point);
+            query_heap.push_nodes(&[self.root_address], &[dist_to_root], None);
+            self.greedy_knn_nodes(point, &mut query_heap, &|_| false);
+
+            while self.greedy_knn_nodes(point, &mut query_heap, &|_| false) {}
+            Ok(query_heap.unpack())
+        });
+        self.parameters.latencies.record(Operation::Knn, timer);
+        knn
+    }
+
+    /// A coarse knn that stops descending at `min_scale_index` and returns the `k` nearest nodes there, as
+    /// `(distance to the center, address)`, closest first. The nodes it picks from are the ones at or above the cutoff
+    /// whose children are all below it, along with the leaves above it, so every point is under exactly one of them.
+    /// Nothing under the cutoff is read. A subtree is skipped when the query is too far from it, by its radius, for
+    /// any node in it to make the `k` nearest.
+    pub fn knn_to_scale<P: Deref<Target = D::Point> + Send + Sync>(
+        &self,
+        point: &P,
+        k: usize,
+        min_scale_index: i32,
+    ) -> GokoResult<Vec<(f32, NodeAddress)>> {
+        let timer = self.parameters.latencies.start();
+        let point = &self.transform_query(Operation::Knn, self.preprocess(point));
+        let point_cloud = &self.parameters.point_cloud;
+        let mut found: Vec<(f32, NodeAddress)> = Vec::with_capacity(k + 1);
+        if k == 0 {
+            return Ok(found);
+        }
+        let root_center = point_cloud.point(self.root_address.1)?;
+        let dist_to_root = point_cloud.metric().dist(&root_center, &point);
+        let mut unvisited = std::collections::BinaryHeap::new();
+        unvisited.push(QueryAddress {
+            min_dist: 0.0,
+            dist_to_center: dist_to_root,
+            address: self.root_address,
+        });
+        while let Some(query_address) = unvisited.pop() {
+            if found.len() == k && query_address.min_dist > found[k - 1].0 {
+                break;
+            }
+            let (dist, address) = (query_address.dist_to_center, query_address.address);
+            let children = self
+                .get_node_and(address, |n| {
+                    n.children()
+                        .filter(|(nested_scale, _)| *nested_scale >= min_scale_index)
+                        .map(|(nested_scale, c)| (nested_scale, c.to_vec()))
+                })
+                .ok_or(GokoError::NodeNotInTree(address))?;
+            match children {
+                None => {
+                    if found.len() < k || dist < found[k - 1].0 {
+                        let i = found.partition_point(|(d, _)| *d <= dist);
+                        found.insert(i, (dist, address));
+                        found.truncate(k);
+                    }
+                }
+                Some((nested_scale, children)) => {
+                    let mut addresses = vec![(nested_scale, address.1)];
+                    let mut distances = vec![dist];
+                    let centers: Vec<usize> = children.iter().map(|(_, pi)| *pi).collect();
+                    distances.extend(point_cloud.distances_to_point(point, &centers)?);
+                    addresses.extend(children);
+                    for (dist, address) in distances.into_iter().zip(addresses) {
+                        let radius = self
+                            .get_node_and(address, |n| n.radius())
+                            .ok_or(GokoError::NodeNotInTree(address))?;
+                        unvisited.push(QueryAddress {
+                            min_dist: (dist - radius).max(0.0),
+                            dist_to_center: dist,
+                            address,
+                        });
+                    }
+                }
+            }
+        }
+        self.parameters.latencies.record(Operation::Knn, timer);
+        Ok(found)
+    }
+
+    fn greedy_knn_nodes<P, F>(&self, point: &P, query_heap: &mut KnnQueryHeap, pruned: &F) -> bool
+    where
+        P: Deref<Target = D::Point> + Send + Sync,
+        F: Fn(NodeAddress) -> bool,
+    {
+        let mut did_something = false;
+        while let Some((dist, nearest_address)) =
+            query_heap.closest_unvisited_child_covering_address()
+        {
+            if pruned(nearest_address) {
+                continue;
+            } else if self
+                .get_node_and(nearest_address, |n| n.is_leaf())
+                .unwrap_or(true)
+            {
+                break;
+            } else {
+                self.get_node_and(nearest_address, |n| {
+                    n.child_knn(Some(dist), point, &self.parameters.point_cloud, query_heap)
+                });
+            }
+            did_something = true;
+        }
+        did_something
+    }
+
+    /// The search of `knn` for queries that measure or rank the points differently than the tree does. `dists` gives
+    /// the distances from the query to some points, `radius` bounds the distance from a node's center to everything it
+    /// covers, and `score` ranks a point by its index and distance, see [`KnnQueryHeap::push_scored_nodes`].
+    fn search_scored_knn_heap<Dist, R, S>(
+        &self,
+        query_heap: &mut KnnQueryHeap,
+        dists: Dist,
+        radius: R,
+        score: S,
+    ) -> GokoResult<()>
+    where
+        Dist: Fn(&[usize]) -> GokoResult<Vec<f32>>,
+        R: Fn(NodeAddress) -> f32,
+        S: Fn(usize, f32) -> f32,
+    {
+        let dist_to_root = dists(&[self.root_address.1])?;
+        query_heap.push_scored_nodes(&[self.root_address], &dist_to_root, &radius, &score, None);
+        loop {
+            while let Some((dist, address)) = query_heap.closest_unvisited_child_covering_address()
+            {
+                let children = self
+                    .get_node_and(address, |n| {
+                        n.children()
+                            .map(|(nested_scale, addresses)| (nested_scale, addresses.to_vec()))
+                    })
+                    .flatten();
+                if let Some((nested_scale, child_addresses)) = children {
+                    query_heap.push_scored_nodes(
+                        &[(nested_scale, address.1)],
+                        &[dist],
+                        &radius,
+                        &score,
+                        None,
+                    );
+                    let child_indexes: Vec<usize> =
+                        child_addresses.iter().map(|(_si, pi)| *pi).collect();
+                    query_heap.push_scored_nodes(
+                        &child_addresses,
+                        &dists(&child_indexes)?,
+                        &radius,
+                        &score,
+                        Some(address),
+                    );
+                }
+            }
+            match query_heap.closest_unvisited_singleton_covering_address() {
+                Some((_dist, address)) => {
+                    let singletons = self
+                        .get_node_and(address, |n| n.singletons_decoded().into_owned())
+                        .unwrap_or_default();
+                    query_heap.push_scored_outliers(&singletons, &dists(&singletons)?, &score);
+                }
+                None => break,
+            }
+        }
+        Ok(())
+    }
+
+    /// The `k` points with the best hybrid score, best first, for recommendations that should favor some points, like
+    /// the ones in the query's category. A point's score is its distance plus `weight * (1 - affinity(index))`, with
+    /// the affinity clamped to `[0, 1]`. Points with an affinity of 1 are ranked by their distance alone, and ones with
+    /// an affinity of 0 are pushed `weight` farther away. The scores are returned in place of the distances.
+    ///
+    /// The affinity is computed as points are pushed onto the query heap. The score is never less than the distance, so
+    /// the nodes are pruned as in `knn`, though a large weight leaves less to prune.
+    pub fn knn_scored<P, F>(
+        &self,
+        point: &P,
+        k: usize,
+        weight: f32,
+        affinity: F,
+    ) -> GokoResult<Vec<(f32, usize)>>
+    where
+        P: Deref<Target = D::Point> + Send + Sync,
+        F: Fn(usize) -> f32,
+    {
+        if !(weight.is_finite() && weight >= 0.0) {
+            return Err(GokoError::InvalidQuery(
+                "the affinity weight must be finite and non-negative",
+            ));
+        }
+        let timer = self.parameters.latencies.start();
+        let knn = self.with_middleware(Operation::Knn, self.preprocess(point), |point| {
+            let point = &point;
+            let point_cloud = &self.parameters.point_cloud;
+            let scale_base = self.parameters.scale_base;
+            let mut query_heap = KnnQueryHeap::new(k, scale_base);
+            query_heap.exclude(point_cloud.deleted_indexes());
+            self.search_scored_knn_heap(
+                &mut query_heap,
+                |indexes| Ok(point_cloud.distances_to_point(point, indexes)?),
+                |(si, _)| scale_base.powi(si),
+                |pi, d| d + weight * (1.0 - affinity(pi).max(0.0).min(1.0)),
+            )?;
+            Ok(query_heap.unpack())
+        });
+        self.parameters.latencies.record(Operation::Knn, timer);
+        knn
+    }
+
+    /// `knn_scored` with a same label bonus. Points with the label have an affinity of 1, and the rest, unlabeled
+    /// points included, have an affinity of 0.
+    pub fn knn_label_boosted<P>(
+        &self,
+        point: &P,
+        k: usize,
+        label: &D::Label,
+        weight: f32,
+    ) -> GokoResult<Vec<(f32, usize)>>
+    where
+        P: Deref<Target = D::Point> + Send + Sync,
+        D::Label: PartialEq,
+    {
+        let point_cloud = &self.parameters.point_cloud;
+        self.knn_scored(point, k, weight, |pi| match point_cloud.label(pi) {
+            Ok(Some(point_label)) if point_label == label => 1.0,
+            _ => 0.0,
+        })
+    }
+
+    /// The `k` nearest neighbors that pass a filter, closest first. `predicate` is given a point's index and decides if
+    /// it can be a neighbor, look the point's label or metadata up in the point cloud to filter on them. There can be
+    /// fewer than `k` if few points pass.
+    ///
+    /// `subtree_may_match` is given a node's label summary, see [`CoverTreeWriter::generate_summaries`], and returns
+    /// false when no point under the node can pass. Those nodes are never opened, which is what makes a filter that
+    /// few points pass cheap. It has to be conservative, a node it rules out is skipped with everything under it. Pass
+    /// `|_| true` to filter on the points alone. Nodes without a summary are always opened.
+    pub fn knn_where<P, F, S>(
+        &self,
+        point: &P,
+        k: usize,
+        predicate: F,
+        subtree_may_match: S,
+    ) -> GokoResult<Vec<(f32, usize)>>
+    where
+        P: Deref<Target = D::Point> + Send + Sync,
+        F: Fn(usize) -> bool,
+        S: Fn(&SummaryCounter<D::LabelSummary>) -> bool,
+    {
+        let timer = self.parameters.latencies.start();
+        let knn = self.with_middleware(Operation::Knn, self.preprocess(point), |point| {
+            let point = &point;
+            let point_cloud = &self.parameters.point_cloud;
+            let scale_base = self.parameters.scale_base;
+            let mut query_heap = KnnQueryHeap::new(k, scale_base);
+            query_heap.exclude(point_cloud.deleted_indexes());
+            let radius = |address: NodeAddress| {
+                let pruned = self
+                    .get_node_label_summary(address)
+                    .map(|summary| !subtree_may_match(&summary))
+                    .unwrap_or(false);
+                // Nothing is within a negative radius of the node, so it's never opened
+                if pruned {
+                    std::f32::NEG_INFINITY
+                } else {
+                    scale_base.powi(address.0)
+                }
+            };
+            // Points that fail are scored out of reach, so they're never kept
+            self.search_scored_knn_heap(
+                &mut query_heap,
+                |indexes| Ok(point_cloud.distances_to_point(point, indexes)?),
+                radius,
+                |pi, d| if predicate(pi) { d } else { std::f32::INFINITY },
+            )?;
+            Ok(query_heap.unpack())
+        });
+        self.parameters.latencies.record(Operation::Knn, timer);
+        knn
+    }
+
+    /// `knn_where` for neighbors with one of the given categorical labels. Nodes whose label summary has none of the
+    /// labels are never opened, unlabeled points never pass.
+    pub fn knn_with_labels<P>(
+        &self,
+        point: &P,
+        k: usize,
+        labels: &[i64],
+    ) -> GokoResult<Vec<(f32, usize)>>
+    where
+        P: Deref<Target = D::Point> + Send + Sync,
+        D: PointCloud<Label = i64, LabelSummary = CategorySummary>,
+    {
+        let point_cloud = &self.parameters.point_cloud;
+        self.knn_where(
+            point,
+            k,
+            |pi| match point_cloud.label(pi) {
+                Ok(Some(label)) => labels.contains(label),
+                _ => false,
+            },
+            |summary| {
+                summary
+                    .summary
+                    .items
+                    .iter()
+                    .any(|(label, count)| *count > 0 && labels.contains(label))
+            },
+        )
+    }
+
+    /// # Dry Insert Query
+    pub fn path<P: Deref<Target = D::Point> + Send + Sync>(
+        &self,
+        point: &P,
+    ) -> GokoResult<Vec<(f32, NodeAddress)>> {
+        let timer = self.parameters.latencies.start();
+        let path =
+            self.untimed_path(&self.transform_query(Operation::DryInsert, self.preprocess(point)));
+        self.parameters
+            .latencies
+            .record(Operation::DryInsert, timer);
+        path
+    }
+
+    /// `path` with each node's covering radius, radius and coverage count, and whether the query fell inside the node's
+    /// cover. This explains why a point lands where it does without looking every node up again.
+    pub fn path_detailed<P: Deref<Target = D::Point> + Send + Sync>(
+        &self,
+        point: &P,
+    ) -> GokoResult<Vec<PathStep>> {
+        self.path(point)?
+            .into_iter()
+            .map(|(distance, address)| {
+                let (radius, coverage_count) = self
+                    .get_node_and(address, |n| (n.radius(), n.coverage_count()))
+                    .ok_or(GokoError::NodeNotInTree(address))?;
+                let cover_radius = self.scale(address.0);
+                Ok(PathStep {
+                    distance,
+                    address,
+                    cover_radius,
+                    radius,
+                    coverage_count,
+                    inside: distance <= cover_radius,
+                })
+            })
+            .collect()
+    }
+
+    /// `path` that stops before it goes below `min_scale_index`, so the last node is the one the point would be
+    /// inserted under at that resolution. Nothing under the cutoff is read.
+    pub fn path_to_scale<P: Deref<Target = D::Point> + Send + Sync>(
+        &self,
+        point: &P,
+        min_scale_index: i32,
+    ) -> GokoResult<Vec<(f32, NodeAddress)>> {
+        let timer = self.parameters.latencies.start();
+        let point = self.transform_query(Operation::DryInsert, self.preprocess(point));
+        let path = self.untimed_path_to_scale(&point, min_scale_index);
+        self.parameters
+            .latencies
+            .record(Operation::DryInsert, timer);
+        path
+    }
+
+    /// `path` without recording its latency or preprocessing the point, for when it's part of a larger operation on a
+    /// point that's already in the cloud.
+    pub(crate) fn untimed_path<P: Deref<Target = D::Point> + Send + Sync>(
+        &self,
+        point: &P,
+    ) -> GokoResult<Vec<(f32, NodeAddress)>> {
+        self.untimed_path_to_scale(point, i32::MIN)
+    }
+
+    fn untimed_path_to_scale<P: Deref<Target = D::Point> + Send + Sync>(
+        &self,
+        point: &P,
+        min_scale_index: i32,
+    ) -> GokoResult<Vec<(f32, NodeAddress)>> {
+        let root_center = self.parameters.point_cloud.point(self.root_address.1)?;
+        let mut current_distance = self
+            .parameters
+            .point_cloud
+            .metric()
+            .dist(&root_center, &point);
+        let mut current_address = self.root_address;
+        let mut trace = vec![(current_distance, current_address)];
+        while let Some(nearest) = self.get_node_and(current_address, |n| {
+            match (n.children(), self.parameters.partition_type) {
+                (Some((nested_scale, _)), _) if nested_scale < min_scale_index => Ok(None),
+                (_, PartitionType::Nearest) => n.nearest_covering_child(
+                    self.parameters.scale_base,
+                    current_distance,
+                    point,
+                    &self.parameters.point_cloud,
+                ),
+                (_, PartitionType::First) => n.first_covering_child(
+                    self.parameters.scale_base,
+                    current_distance,
+                    point,
+                    &self.parameters.point_cloud,
+                ),
+            }
+        }) {
+            if let Some(nearest) = nearest? {
+                trace.push(nearest);
+                current_distance = nearest.0;
+                current_address = nearest.1;
+            } else {
+                break;
+            }
+        }
+        Ok(trace)
+    }
+
+    ///
+    pub fn known_path(&self, point_index: usize) -> GokoResult<Vec<(f32, NodeAddress)>> {
+        self.final_addresses
+            .get_and(&point_index, |addr| {
+                let mut path = Vec::with_capacity((self.root_address().0 - addr.0) as usize);
+                let mut parent = Some(*addr);
+                while let Some(addr) = parent {
+                    path.push(addr);
+                    parent = self.get_node_and(addr, |n| n.parent_address()).flatten();
+                }
+                (&mut path[..]).reverse();
+                let point_indexes: Vec<usize> = path.iter().map(|na| na.1).collect();
+                let dists = self
+                    .parameters
+                    .point_cloud
+                    .distances_to_point_index(point_index, &point_indexes[..])
+                    .unwrap();
+                dists.iter().zip(path).map(|(d, a)| (*d, a)).collect()
+            })
+            .ok_or(GokoError::IndexNotInTree(point_index))
+    }
+
+    /// The node that covers a point at the given scale index. This is the lowest node on the
+    /// point's known path whose scale index is at least `scale_index`.
+    pub fn covering_node(&self, point_index: usize, scale_index: i32) -> Option<NodeAddress> {
+        let mut address = self.final_addresses.get_and(&point_index, |addr| *addr)?;
+        while address.0 < scale_index {
+            address = self
+                .get_node_and(address, |n| n.parent_address())
+                .flatten()?;
+        }
+        Some(address)
+    }
+
+    /// For every point in the point cloud, the center index of the node that covers it at the given
+    /// scale index, see [`CoverTreeReader::covering_node`]. Points that aren't in the tree get -1.
+    /// The nodes covering the points at one scale index don't overlap, so their centers identify them.
+    pub fn assignments(&self, scale_index: i32) -> Vec<i64> {
+        let point_indexes: Vec<usize> = (0..self.parameters.point_cloud.len()).collect();
+        let indexes_iter = point_indexes.par_chunks(1000);
+        let reader_copies = indexes_iter.len();
+        let chunked_assignments: Vec<Vec<i64>> = indexes_iter
+            .zip(repeatn(self.clone(), reader_copies))
+            .map(|(chunk_indexes, reader)| {
+                chunk_indexes
+                    .iter()
+                    .map(|pi| match reader.covering_node(*pi, scale_index) {
+                        Some((_, center_index)) => center_index as i64,
+                        None => -1,
+                    })
+                    .collect()
+            })
+            .collect();
+        chunked_assignments.concat()
+    }
+
+    /// The statistics of a node. The covering radius is measured, so this reads the node's whole subtree and computes
+    /// the distance from its center to every point under it.
+    pub fn node_stats(&self, address: NodeAddress) -> GokoResult<NodeStats> {
+        let (radius, coverage_count, singleton_count, child_count) = self
+            .get_node_and(address, |n| {
+                (
+                    n.radius(),
+                    n.coverage_count(),
+                    n.singletons_len(),
+                    n.children_len(),
+                )
+            })
+            .ok_or(GokoError::NodeNotInTree(address))?;
+        let mut covered = Vec::new();
+        let mut unvisited = vec![address];
+        while let Some(na) = unvisited.pop() {
+            self.get_node_and(na, |n| {
+                covered.extend(n.singletons_iter());
+                match n.children() {
+                    Some((nested_scale, children)) => {
+                        unvisited.push((nested_scale, na.1));
+                        unvisited.extend_from_slice(children);
+                    }
+                    None => covered.push(na.1),
+                }
+            })
+            .ok_or(GokoError::NodeNotInTree(na))?;
+        }
+        let covering_radius = self
+            .parameters
+            .point_cloud
+            .distances_to_point_index(address.1, &covered)?
+            .into_iter()
+            .fold(0.0, f32::max);
+        Ok(NodeStats {
+            address,
+            cover_radius: self.scale(address.0),
+            covering_radius,
+            radius,
+            coverage_count,
+            singleton_count,
+            child_count,
+        })
+    }
+
+    ///Computes the fractal dimension of a node
+    pub fn node_fractal_dim(&self, node_address: NodeAddress) -> f32 {
+        let count: f32 = self
+            .get_node_and(node_address, |n| {
+                (n.singletons_len() + n.children_len()) as f32
+            })
+            .unwrap() as f32;
+        count.log(self.parameters.scale_base)
+    }
+
+    ///Computes the weighted fractal dimension of a node
+    pub fn node_weighted_fractal_dim(&self, node_address: NodeAddress) -> f32 {
+        let weighted_count: f32 = self
+            .get_node_and(node_address, |n| {
+                let singleton_count = n.singletons_len() as f32;
+                let mut max_pop: usize = 1;
+                let mut weighted_count: f32 = 0.0;
+                if let Some((nested_scale, children)) = n.children() {
+                    let mut pops: Vec<usize> = children
+                        .iter()
+                        .map(|child_addr| {
+                            self.get_node_and(*child_addr, |child| child.coverage_count())
+                                .unwrap()
+                        })
+                        .collect();
+                    pops.push(
+                        self.get_node_and((nested_scale, node_address.1), |child| {
+                            child.coverage_count()
+                        })
+                        .unwrap(),
+                    );
+                    max_pop = *pops.iter().max().unwrap();
+                    pops.iter()
+                        .for_each(|p| weighted_count += (*p as f32) / (max_pop as f32));
+                }
+                weighted_count + singleton_count / (max_pop as f32)
+            })
+            .unwrap();
+        weighted_count.log(self.parameters.scale_base)
+    }
+
+    ///Computes the fractal dimension of a layer
+    pub fn layer_fractal_dim(&self, scale_index: i32) -> f32 {
+        let parent_layer = self.layer(scale_index);
+        let parent_count = parent_layer.len() as f32;
+        let mut child_count: f32 = 0.0;
+        parent_layer
+            .for_each_node(|_, n| child_count += (n.singletons_len() + n.children_len()) as f32);
+        child_count.log(self.parameters.scale_base) - parent_count.log(self.parameters.scale_base)
+    }
+
+    ///Computes the weighted fractal dimension of a node
+    pub fn layer_weighted_fractal_dim(&self, scale_index: i32) -> f32 {
+        // gather the coverages of every node on the layer and their's children
+        let parent_layer = self.layer(scale_index);
+        let mut parent_coverage_counts: Vec<usize> = Vec::new();
+        let mut child_coverage_counts: Vec<usize> = Vec::new();
+        let mut singletons_count: f32 = 0.0;
+        parent_layer.for_each_node(|center_index, n| {
+            parent_coverage_counts.push(n.coverage_count());
+
+            singletons_count += n.singletons_len() as f32;
+            if let Some((nested_scale, children)) = n.children() {
+                child_coverage_counts.extend(children.iter().map(|child_addr| {
+                    self.get_node_and(*child_addr, |child| child.coverage_count())
+                        .unwrap()
+                }));
+                child_coverage_counts.push(
+                    self.get_node_and((nested_scale, *center_index), |child| {
+                        child.coverage_count()
+                    })
+                    .unwrap(),
+                );
+            }
+        });
+        // Get the maximum count
+        let max_parent_pop: f32 = *parent_coverage_counts.iter().max().unwrap_or(&1) as f32;
+        let max_child_pop: f32 = *child_coverage_counts.iter().max().unwrap_or(&1) as f32;
+
+        // Normalize the counts by the maximum
+        let weighted_child_sum: f32 = singletons_count / max_child_pop
+            + child_coverage_counts
+                .iter()
+                .fold(0.0, |a, c| a + (*c as f32) / max_child_pop);
+        let weighted_parent_sum: f32 = parent_coverage_counts
+            .iter()
+            .fold(0.0, |a, c| a + (*c as f32) / max_parent_pop);
+
+        // take the log and return
+        weighted_child_sum.log(self.parameters.scale_base)
+            - weighted_parent_sum.log(self.parameters.scale_base)
+    }
+
+    /// Checks that there are no node addresses in the child list of any node that don't reference a node in the tree.
+    /// Please calmly panic if there are, the tree is very invalid.
+    pub(crate) fn no_dangling_refs(&self) -> bool {
+        let mut refs_to_check = vec![self.root_address];
+        while let Some(node_addr) = refs_to_check.pop() {
+            println!("checking {:?}", node_addr);
+            println!("refs_to_check: {:?}", refs_to_check);
+            let node_exists = self.get_node_and(node_addr, |n| {
+                if let Some((nested_scale, other_children)) = n.children() {
+                    println!(
+                        "Pushing: {:?}, {:?}",
+                        (nested_scale, other_children),
+                        other_children
+                    );
+                    refs_to_check.push((nested_scale, node_addr.1));
+                    refs_to_check.extend(&other_children[..]);
+                }
+            });
+            if node_exists.is_none() {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+impl<D: PointCloud<Point = [f32]>> CoverTreeReader<D>
+where
+    D::Metric: MaskedMetric,
+{
+    /// The `k` nearest neighbors under the distance over the masked dimensions only, see
+    /// [`pointcloud::metrics::masked`]. The distances returned are the masked ones. This is the same search as `knn`,
+    /// the radius of a node under the full metric bounds its masked radius. With the
+    /// [`crate::plugins::masking::MaskingPlugin`] attached the
+    /// radius is bounded by the node's spread in the masked dimensions as well, which prunes much more when the mask
+    /// is a small part of the dimensions.
+    pub fn knn_masked<P: Deref<Target = [f32]> + Send + Sync>(
+        &self,
+        point: &P,
+        k: usize,
+        mask: &DimMask,
+    ) -> GokoResult<Vec<(f32, usize)>> {
+        let timer = self.parameters.latencies.start();
+        let knn = self.with_middleware(Operation::Knn, self.preprocess(point), |point| {
+            let point = &point;
+            let point_cloud = &self.parameters.point_cloud;
+            let metric = point_cloud.metric();
+            let dists = |indexes: &[usize]| -> GokoResult<Vec<f32>> {
+                indexes
+                    .iter()
+                    .map(|pi| -> GokoResult<f32> {
+                        Ok(metric.masked_dist(&point_cloud.point(*pi)?, point, mask))
+                    })
+                    .collect()
+            };
+            let radius = |address: NodeAddress| {
+                let scale = self.parameters.scale_base.powi(address.0);
+                self.get_node_plugin_and::<NodeDeviations, _, _>(address, |p| {
+                    metric.masked_norm(&p.deviations, mask).min(scale)
+                })
+                .unwrap_or(scale)
+            };
+            let mut query_heap = KnnQueryHeap::new(k, self.parameters.scale_base);
+            query_heap.exclude(point_cloud.deleted_indexes());
+
+            self.search_scored_knn_heap(&mut query_heap, dists, radius, |_, d| d)?;
+            Ok(query_heap.unpack())
+        });
+        self.parameters.latencies.record(Operation::Knn, timer);
+        knn
+    }
+}
+
+/// An update to a single node, queued on the layer that holds it.
+pub(crate) type NodeUpdate<D> = Box<dyn Fn(&mut CoverNode<D>) + Send + Sync>;
+/// Rebuilds the node component of one plugin for a single node. One of these is registered for each
+/// plugin attached with `add_plugin`, so that edits to the tree can update the plugins locally.
+pub(crate) type PluginUpdater<D> =
+    Box<dyn Fn(&CoverNode<D>, &CoverTreeReader<D>) -> Option<NodeUpdate<D>> + Send + Sync>;
+
+///
+pub struct CoverTreeWriter<D: PointCloud> {
+    pub(crate) parameters: Arc<CoverTreeParameters<D>>,
+    pub(crate) layers: Vec<CoverLayerWriter<D>>,
+    pub(crate) root_address: NodeAddress,
+    pub(crate) final_addresses: MonoWriteHandle<usize, NodeAddress>,
+    pub(crate) plugin_updaters: Vec<PluginUpdater<D>>,
+    /// The name and json parameters of each persistent plugin, in the order they were attached.
+    pub(crate) saved_plugins: Vec<(&'static str, String)>,
+    pub(crate) maintenance_pool: Option<PoolHandle>,
+    pub(crate) build_report: BuildReport,
+}
+
+impl<D: PointCloud> CoverTreeWriter<D> {
+    /// Runs maintenance, like adding plugins and regenerating summaries, as background work on a
+    /// [`crate::scheduler::SharedPool`]. Maintenance then waits for its turn behind queries on the
+    /// pool, and hands its slot over to waiting queries between layers. Trees built with a thread
+    /// pool use it for maintenance by default.
+    pub fn set_maintenance_pool(&mut self, pool: Option<PoolHandle>) {
+        self.maintenance_pool = pool;
+    }
+
+    /// Where the time of the build, and of attaching the plugins since, went. A tree that was
+    /// loaded from a file has an empty report.
+    pub fn build_report(&self) -> &BuildReport {
+        &self.build_report
+    }
+
+    ///
+    pub fn generate_meta_summaries(&mut self) {
+        let started = Instant::now();
+        self.attach_plugin::<MetaSummaryPlugin>(MetaSummaryPlugin::default());
+        self.save_plugin_parameters(&MetaSummaryPlugin::default())
+            .expect("the summary plugins have no parameters");
+        self.build_report.summaries += started.elapsed();
+    }
+
+    ///
+    pub fn generate_summaries(&mut self) {
+        let started = Instant::now();
+        self.attach_plugin::<LabelSummaryPlugin>(LabelSummaryPlugin::default());
+        self.save_plugin_parameters(&LabelSummaryPlugin::default())
+            .expect("the summary plugins have no parameters");
+        self.build_report.summaries += started.elapsed();
+    }
+
+    ///
+    pub fn add_plugin<P: GokoPlugin<D>>(&mut self, plug_in: P) {
+        let started = Instant::now();
+        self.attach_plugin(plug_in);
+        self.build_report.plugins.push(PluginTiming {
+            name: std::any::type_name::<P>().to_string(),
+            time: started.elapsed(),
+        });
+    }
+
+    /// `add_plugin` for a plugin that's saved with the tree, see [`CoverTreeWriter::save`]. Attaching a plugin with the
+    /// same name again replaces its saved parameters.
+    pub fn add_persistent_plugin<P: PersistentPlugin<D>>(&mut self, plug_in: P) -> GokoResult<()> {
+        self.save_plugin_parameters(&plug_in)?;
+        self.add_plugin(plug_in);
+        Ok(())
+    }
+
+    fn save_plugin_parameters<P: PersistentPlugin<D>>(&mut self, plug_in: &P) -> GokoResult<()> {
+        let parameters = serde_json::to_string(plug_in)
+            .map_err(|e| GokoError::PluginStateError(e.to_string()))?;
+        match self
+            .saved_plugins
+            .iter_mut()
+            .find(|(name, _)| *name == P::NAME)
+        {
+            Some(saved) => saved.1 = parameters,
+            None => self.saved_plugins.push((P::NAME, parameters)),
+        }
+        Ok(())
+    }
+
+    fn attach_plugin<P: GokoPlugin<D>>(&mut self, plug_in: P) {
+        let maintenance_pool = self.maintenance_pool.clone();
+        let mut slot = maintenance_pool.as_ref().map(|pool| pool.background_slot());
+        P::prepare_tree(&plug_in, self);
+        let reader = self.reader();
+        for layer in self.layers.iter_mut() {
+            layer.reader().for_each_node(|pi, n| {
+                if let Some(node_component) = P::node_component(&plug_in, n, &reader) {
+                    unsafe {
+                        layer.update_node(*pi, move |n| n.insert_plugin(node_component.clone()))
+                    }
+                }
+            });
+            layer.refresh();
+            if let Some(slot) = slot.as_mut() {
+                slot.yield_to_foreground();
+            }
+        }
+        let updater_plug_in = plug_in.clone();
+        self.plugin_updaters.push(Box::new(
+            move |node: &CoverNode<D>, reader: &CoverTreeReader<D>| {
+                P::node_component(&updater_plug_in, node, reader).map(|node_component| {
+                    let update: NodeUpdate<D> = Box::new(move |n: &mut CoverNode<D>| {
+                        n.insert_plugin(node_component.clone())
+                    });
+                    update
+                })
+            },
+        ));
+        self.parameters.plugins.write().unwrap().insert(plug_in);
+        self.parameters.publishes.plugin_computed(
+            std::any::type_name::<P>(),
+            TypeId::of::<P::NodeComponent>() != TypeId::of::<()>(),
+        );
+    }
+
+    /// Rebuilds the node components of every attached plugin for the given nodes. The nodes are
+    /// updated from the bottom of the tree up, so pass every ancestor of an edited node as well.
+    /// The structural edits should already be visible to readers, as the plugins read the tree.
+    pub(crate) fn recompute_node_plugins(&mut self, mut addresses: Vec<NodeAddress>) {
+        if self.plugin_updaters.is_empty() {
+            return;
+        }
+        addresses.sort();
+        addresses.dedup();
+        let maintenance_pool = self.maintenance_pool.clone();
+        let mut slot = maintenance_pool.as_ref().map(|pool| pool.background_slot());
+        let mut i = 0;
+        while i < addresses.len() {
+            let scale_index = addresses[i].0;
+            let reader = self.reader();
+            let layer_index = self.parameters.internal_index(scale_index);
+            let plugin_updaters = &self.plugin_updaters;
+            let layer = &mut self.layers[layer_index];
+            while i < addresses.len() && addresses[i].0 == scale_index {
+                let center_index = addresses[i].1;
+                let updates: Vec<NodeUpdate<D>> = reader
+                    .get_node_and(addresses[i], |n| {
+                        plugin_updaters
+                            .iter()
+                            .filter_map(|updater| updater(n, &reader))
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                for update in updates {
+                    unsafe { layer.update_node(center_index, update) };
+                }
+                i += 1;
+            }
+            layer.refresh();
+            if let Some(slot) = slot.as_mut() {
+                slot.yield_to_foreground();
+            }
+        }
+        self.parameters.publishes.node_plugins_rebuilt();
+    }
+
+    /// Provides a reference to a `CoverLayerWriter`. Do not use, unless you're going to leave the tree in a *valid* state.
+    pub(crate) unsafe fn layer(&mut self, scale_index: i32) -> &mut CoverLayerWriter<D> {
+        &mut self.layers[self.parameters.internal_index(scale_index)]
+    }
+
+    pub(crate) unsafe fn update_node<F>(&mut self, address: NodeAddress, update_fn: F)
+    where
+        F: Fn(&mut CoverNode<D>) + 'static + Send + Sync,
+    {
+        self.layers[self.parameters.internal_index(address.0)].update_node(address.1, update_fn);
+    }
+
+    /// Creates a reader for queries.
+    pub fn reader(&self) -> CoverTreeReader<D> {
+        CoverTreeReader {
+            parameters: Arc::clone(&self.parameters),
+            layers: self.layers.iter().map(|l| l.reader()).collect(),
+            root_address: self.root_address,
+            final_addresses: self.final_addresses.factory().handle(),
+            middleware: Vec::new(),
+            writer: None,
+        }
+    }
+
+    pub(crate) unsafe fn insert_raw(
+        &mut self,
+        scale_index: i32,
+        point_index: usize,
+        node: CoverNode<D>,
+    ) {
+        self.layers[self.parameters.internal_index(scale_index)].insert_raw(point_index, node);
+    }
+
+    /// Writes the tree to a file, see `to_proto`. Load it with [`CoverTreeReader::load`], or `load_file` for a writer,
+    /// on the same point cloud. The point cloud itself isn't saved, only its dimension and number of points, to check
+    /// it's the same one.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> GokoResult<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        let mut cos = CodedOutputStream::new(&mut file);
+        self.to_proto().write_to(&mut cos)?;
+        cos.flush()?;
+        Ok(())
+    }
+
+    /// Reads a tree that was written by `save` into a writer, with the point cloud it was built on. The saved plugins
+    /// are attached again from `plugins`, [`PluginRegistry::default`] has the built in ones.
+    pub fn load_file<P: AsRef<Path>>(
+        path: P,
+        point_cloud: Arc<D>,
+        plugins: &PluginRegistry<D>,
+    ) -> GokoResult<CoverTreeWriter<D>> {
+        let mut file = BufReader::new(File::open(path)?);
+        let mut cover_proto = CoreProto::new();
+        cover_proto.merge_from(&mut CodedInputStream::new(&mut file))?;
+        CoverTreeWriter::from_proto(&cover_proto, point_cloud, plugins)
+    }
+
+    /// Loads a tree from a protobuf, with the plugins on the default registry.
+    #[deprecated(note = "use `from_proto`, or `load_file` to read a saved tree")]
+    pub fn load(cover_proto: &CoreProto, point_cloud: Arc<D>) -> GokoResult<CoverTreeWriter<D>> {
+        CoverTreeWriter::from_proto(cover_proto, point_cloud, &PluginRegistry::default())
+    }
+
+    /// Loads a tree from a protobuf. The saved plugins are attached again, so they have to be on the registry. There's
+    /// a `load_tree` in `utils` that handles loading from a path to a protobuf file.
+    pub fn from_proto(
+        cover_proto: &CoreProto,
+        point_cloud: Arc<D>,
+        plugins: &PluginRegistry<D>,
+    ) -> GokoResult<CoverTreeWriter<D>> {
+        if cover_proto.get_dim() as usize != point_cloud.dim() {
+            return Err(GokoError::SavedTreeMismatch(
+                "the point cloud has a different dimension",
+            ));
+        }
+        if cover_proto.get_count() as usize != point_cloud.len() {
+            return Err(GokoError::SavedTreeMismatch(
+                "the point cloud has a different number of points",
+            ));
+        }
+        let partition_type = if cover_proto.partition_type == "first" {
+            PartitionType::First
+        } else {
+            PartitionType::Nearest
+        };
+
+        let parameters = Arc::new(CoverTreeParameters {
+            total_nodes: atomic::AtomicUsize::new(0),
+            use_singletons: cover_proto.use_singletons,
+            scale_base: cover_proto.scale_base as f32,
+            leaf_cutoff: cover_proto.cutoff as usize,
+            min_res_index: cover_proto.resolution as i32,
+            point_cloud,
+            verbosity: cover_proto.get_verbosity(),
+            partition_type,
+            plugins: RwLock::new(TreePluginSet::new()),
+            latencies: LatencyStats::new(),
+            publishes: PublishLog::new(),
+            rng_seed: if cover_proto.get_has_rng_seed() {
+                Some(cover_proto.get_rng_seed())
+            } else {
+                None
+            },
+        });
+        let root_address = (
+            cover_proto.get_root_scale(),
+            cover_proto.get_root_index() as usize,
+        );
+        let layers: Vec<CoverLayerWriter<D>> = cover_proto
+            .get_layers()
+            .par_iter()
+            .map(|l| CoverLayerWriter::load(l))
+            .collect();
+
+        let (_final_addresses_reader, final_addresses) = monomap::new();
+
+        let mut tree = CoverTreeWriter {
+            parameters,
+            layers,
+            root_address,
+            final_addresses,
+            plugin_updaters: Vec::new(),
+            saved_plugins: Vec::new(),
+            maintenance_pool: None,
+            build_report: BuildReport::default(),
+        };
+
+        tree.refresh_final_indexes();
+        tree.parameters.publishes.published();
+        for plugin in cover_proto.get_plugins() {
+            plugins.load(&mut tree, plugin.get_name(), plugin.get_parameters_json())?;
+        }
+
+        Ok(tree)
+    }
+
+    /// Completely redoes the final index map.
+    pub fn refresh_final_indexes(&mut self) {
+        let reader = self.reader();
+        let mut unvisited_nodes: Vec<NodeAddress> = vec![self.root_address];
+        while !unvisited_nodes.is_empty() {
+            let cur_add = unvisited_nodes.pop().unwrap();
+            reader
+                .get_node_and(cur_add, |n| {
+                    for singleton in n.singletons_iter() {
+                        self.final_addresses.insert(singleton, cur_add);
+                    }
+                    if let Some((nested_si, child_addresses)) = n.children() {
+                        unvisited_nodes.extend(child_addresses);
+                        unvisited_nodes.push((nested_si, cur_add.1));
+                    } else {
+                        self.final_addresses.insert(cur_add.1, cur_add);
+                    }
+                })
+                .unwrap();
+        }
+
+        self.final_addresses.refresh();
+        self.final_addresses.refresh();
+    }
+
+    /// Encodes the tree into a protobuf, with the parameters of its persistent plugins. See `save` for saving to a file
+    /// on disk.
+    pub fn to_proto(&self) -> CoreProto {
+        let mut cover_proto = CoreProto::new();
+        match self.parameters.partition_type {
+            PartitionType::First => cover_proto.set_partition_type("first".to_string()),
+            PartitionType::Nearest => cover_proto.set_partition_type("nearest".to_string()),
+        }
+        cover_proto.set_scale_base(self.parameters.scale_base);
+        cover_proto.set_cutoff(self.parameters.leaf_cutoff as u64);
+        cover_proto.set_resolution(self.parameters.min_res_index);
+        cover_proto.set_use_singletons(self.parameters.use_singletons);
+        cover_proto.set_dim(self.parameters.point_cloud.dim() as u64);
+        cover_proto.set_count(self.parameters.point_cloud.len() as u64);
+        cover_proto.set_root_scale(self.root_address.0);
+        cover_proto.set_root_index(self.root_address.1 as u64);
+        cover_proto.set_layers(self.layers.iter().map(|l| l.save()).collect());
+        let name_map: std::collections::HashMap<String, u64> =
+            self.final_addresses.map_into(|k, _v| {
+                (
+                    self.parameters.point_cloud.name(*k).unwrap().to_string(),
+                    *k as u64,
+                )
+            });
+        cover_proto.set_name_map(name_map);
+        if let Some(seed) = self.parameters.rng_seed {
+            cover_proto.set_has_rng_seed(true);
+            cover_proto.set_rng_seed(seed);
+        }
+        cover_proto.set_verbosity(self.parameters.verbosity);
+        cover_proto.set_plugins(
+            self.saved_plugins
+                .iter()
+                .map(|(name, parameters)| {
+                    let mut plugin_proto = PluginProto::new();
+                    plugin_proto.set_name(name.to_string());
+                    plugin_proto.set_parameters_json(parameters.clone());
+                    plugin_proto
+                })
+                .collect(),
+        );
+        cover_proto
+    }
+
+    /// Swaps the maps on each layer so that any `CoverTreeReaders` see the updated tree.
+    /// Only call once you have a valid tree.
+    pub fn refresh(&mut self) {
+        self.layers.iter_mut().rev().for_each(|l| l.refresh());
+        self.parameters.publishes.published();
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    use crate::utils::cover_tree_from_labeled_yaml;
+    use std::path::Path;
+
+    pub(crate) fn build_mnist_tree() -> CoverTreeWriter<DefaultLabeledCloud<L2>> {
         let file_name = "../data/mnist_complex.yml";
         let path = Path::new(file_name);
         if !path.exists() {
             panic!("{} does not exist", file_name);
         }
 
-        cover_tree_from_labeled_yaml(&path).unwrap()
+        cover_tree_from_labeled_yaml(&path).unwrap()
+    }
+
+    pub(crate) fn build_basic_tree() -> CoverTreeWriter<DefaultLabeledCloud<L2>> {
+        let data = vec![0.499, 0.49, 0.48, -0.49, 0.0];
+        let labels = vec![0, 0, 0, 1, 1];
+
+        let point_cloud = DefaultLabeledCloud::<L2>::new_simple(data, 1, labels);
+        let builder = CoverTreeBuilder {
+            scale_base: 2.0,
+            leaf_cutoff: 1,
+            min_res_index: -9,
+            use_singletons: true,
+            partition_type: PartitionType::Nearest,
+            verbosity: 0,
+            rng_seed: Some(0),
+            memory_limit: None,
+            prune_chains: false,
+            thread_pool: None,
+            progress_callback: None,
+            progress_interval: None,
+            cancel_token: None,
+        };
+        builder.build(Arc::new(point_cloud)).unwrap()
+    }
+
+    #[test]
+    fn len_is_num_layers() {
+        let tree = build_basic_tree();
+        let reader = tree.reader();
+
+        let mut l = 0;
+        for _ in reader.layers() {
+            l += 1;
+        }
+        assert_eq!(reader.len(), l);
+    }
+
+    #[test]
+    fn layer_has_correct_scale_index() {
+        let tree = build_basic_tree();
+        let reader = tree.reader();
+        let mut got_one = false;
+        for (si, l) in reader.layers() {
+            println!(
+                "Scale Index, correct: {:?}, Scale Index, layer: {:?}",
+                si,
+                l.scale_index()
+            );
+            assert_eq!(si, l.scale_index());
+            got_one = true;
+        }
+        assert!(got_one);
+    }
+
+    #[test]
+    fn greedy_knn_nodes() {
+        let data = vec![0.499, 0.49, 0.48, -0.49, 0.0];
+        let labels = vec![0, 0, 0, 1, 1];
+
+        let point_cloud = DefaultLabeledCloud::<L2>::new_simple(data, 1, labels);
+        let builder = CoverTreeBuilder {
+            scale_base: 2.0,
+            leaf_cutoff: 1,
+            min_res_index: -9,
+            use_singletons: false,
+            partition_type: PartitionType::Nearest,
+            verbosity: 0,
+            rng_seed: Some(0),
+            memory_limit: None,
+            prune_chains: false,
+            thread_pool: None,
+            progress_callback: None,
+            progress_interval: None,
+            cancel_token: None,
+        };
+        let tree = builder.build(Arc::new(point_cloud)).unwrap();
+        let reader = tree.reader();
+
+        let point = [-0.5];
+
+        let mut query_heap = KnnQueryHeap::new(5, reader.parameters.scale_base);
+        let dist_to_root = reader
+            .parameters
+            .point_cloud
+            .distances_to_point(&point.as_ref(), &[reader.root_address().1])
+            .unwrap()[0];
+        query_heap.push_nodes(&[reader.root_address()], &[dist_to_root], None);
+
+        assert_eq!(
+            reader.root_address(),
+            query_heap
+                .closest_unvisited_child_covering_address()
+                .unwrap()
+                .1
+        );
+
+        reader.greedy_knn_nodes(&point.as_ref(), &mut query_heap);
+        println!("{:#?}", query_heap);
+        println!(
+            "{:#?}",
+            query_heap.closest_unvisited_child_covering_address()
+        );
+    }
+
+    #[test]
+    fn path_sanity() {
+        let writer = build_basic_tree();
+        let reader = writer.reader();
+        let trace = reader.path(&[0.495f32].as_ref()).unwrap();
+        assert!(trace.len() == 4 || trace.len() == 3);
+        println!("{:?}", trace);
+        for i in 0..(trace.len() - 1) {
+            assert!((trace[i].1).0 > (trace[i + 1].1).0);
+        }
+    }
+
+    #[test]
+    fn known_path_sanity() {
+        let writer = build_basic_tree();
+        let reader = writer.reader();
+        for i in 0..5 {
+            let trace = reader.known_path(i).unwrap();
+            println!("i {}, trace {:?}", i, trace);
+            println!(
+                "final address: {:?}",
+                reader.final_addresses.get_and(&i, |i| *i)
+            );
+            let ad = trace.last().unwrap().1;
+            reader
+                .get_node_and(ad, |n| {
+                    if !n.is_leaf() {
+                        assert!(n.singletons().contains(&i));
+                    } else {
+                        assert!(
+                            (ad.1 != i && n.singletons().contains(&i))
+                                || (ad.1 == i && !n.singletons().contains(&i))
+                        );
+                    }
+                })
+                .unwrap();
+        }
+        let known_trace = reader.known_path(4).unwrap();
+        let trace = reader.path(&[0.0f32].as_ref()).unwrap();
+        println!(
+            "Testing known: {:?} matches unknown {:?}",
+            known_trace, trace
+        );
+        for (p, kp) in trace.iter().zip(known_trace) {
+            assert_eq!(*p, kp);
+        }
+    }
+
+    #[test]
+    fn path_to_scale_stops_at_the_cutoff() {
+        let writer = build_basic_tree();
+        let reader = writer.reader();
+        let root_scale = reader.root_address().0;
+        for x in &[0.495f32, 0.485, -0.3, 0.1] {
+            let path = reader.path(&[*x].as_ref()).unwrap();
+            for min_scale_index in reader.parameters().min_res_index..=root_scale {
+                let cut: Vec<(f32, NodeAddress)> = path
+                    .iter()
+                    .copied()
+                    .take_while(|(_, address)| address.0 >= min_scale_index)
+                    .collect();
+                assert_eq!(
+                    reader
+                        .path_to_scale(&[*x].as_ref(), min_scale_index)
+                        .unwrap(),
+                    cut
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn knn_to_scale_matches_brute_force() {
+        let data = [0.499f32, 0.49, 0.48, -0.49, 0.0];
+        let writer = build_basic_tree();
+        let reader = writer.reader();
+        let root_scale = reader.root_address().0;
+        for min_scale_index in (reader.parameters().min_res_index - 1)..=root_scale {
+            let mut frontier = Vec::new();
+            for (scale_index, layer) in reader.layers() {
+                if scale_index < min_scale_index {
+                    continue;
+                }
+                layer.for_each_node(|pi, n| match n.children() {
+                    Some((nested_scale, _)) if nested_scale >= min_scale_index => {}
+                    _ => frontier.push(*pi),
+                });
+            }
+            for x in &[0.495f32, 0.485, -0.3, 0.1] {
+                let mut expected: Vec<f32> =
+                    frontier.iter().map(|pi| (x - data[*pi]).abs()).collect();
+                expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                expected.truncate(3);
+                let found = reader
+                    .knn_to_scale(&[*x].as_ref(), 3, min_scale_index)
+                    .unwrap();
+                assert_eq!(found.len(), expected.len());
+                for ((d, address), e) in found.iter().zip(expected) {
+                    assert_approx_eq!(*d, e);
+                    assert!(address.0 >= min_scale_index);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn detailed_path_matches_path() {
+        let writer = build_basic_tree();
+        let reader = writer.reader();
+        let point = [0.1f32];
+        let trace = reader.path(&point.as_ref()).unwrap();
+        let detailed = reader.path_detailed(&point.as_ref()).unwrap();
+        assert_eq!(trace.len(), detailed.len());
+        for ((d, address), step) in trace.iter().zip(&detailed) {
+            assert_eq!((*d, *address), (step.distance, step.address));
+            assert_eq!(step.cover_radius, reader.scale(address.0));
+            reader
+                .get_node_and(*address, |n| {
+                    assert_eq!(step.radius, n.radius());
+                    assert_eq!(step.coverage_count, n.coverage_count());
+                })
+                .unwrap();
+        }
+        // Every node the query descends into covers it
+        assert!(detailed[1..].iter().all(|step| step.inside));
+    }
+
+    #[test]
+    fn node_stats_match_the_nodes() {
+        let writer = build_basic_tree();
+        let reader = writer.reader();
+        for (scale_index, layer) in reader.layers() {
+            layer.for_each_node(|pi, n| {
+                let stats = reader.node_stats((scale_index, *pi)).unwrap();
+                assert_eq!(stats.address, (scale_index, *pi));
+                assert_eq!(stats.cover_radius, reader.scale(scale_index));
+                // A fresh build's radii are exact
+                assert_approx_eq!(stats.covering_radius, n.radius());
+                assert_eq!(stats.radius, n.radius());
+                assert_eq!(stats.coverage_count, n.coverage_count());
+                assert_eq!(stats.singleton_count, n.singletons_len());
+                assert_eq!(stats.child_count, n.children_len());
+            });
+        }
+        let root = reader.root_address();
+        assert_eq!(reader.node_stats(root).unwrap().coverage_count, 5);
+        // The root is alone on its layer
+        assert!(reader.node_stats((root.0, (root.1 + 1) % 5)).is_err());
+    }
+
+    #[test]
+    fn knn_singletons_on() {
+        println!("2 nearest neighbors of 0.0 are 0.48 and 0.0");
+        let writer = build_basic_tree();
+        let reader = writer.reader();
+        let zero_nbrs = reader.knn(&[0.1f32].as_ref(), 2).unwrap();
+        println!("{:?}", zero_nbrs);
+        assert!(zero_nbrs[0].1 == 4);
+        assert!(zero_nbrs[1].1 == 2);
+    }
+
+    #[test]
+    fn label_summary() {
+        let data = vec![0.499, 0.49, 0.48, -0.49, 0.0];
+        let labels = vec![0, 0, 0, 1, 1];
+
+        let point_cloud = DefaultLabeledCloud::<L2>::new_simple(data, 1, labels);
+        let builder = CoverTreeBuilder {
+            scale_base: 2.0,
+            leaf_cutoff: 1,
+            min_res_index: -9,
+            use_singletons: false,
+            partition_type: PartitionType::Nearest,
+            verbosity: 0,
+            rng_seed: Some(0),
+            memory_limit: None,
+            prune_chains: false,
+            thread_pool: None,
+            progress_callback: None,
+            progress_interval: None,
+            cancel_token: None,
+        };
+        let mut tree = builder.build(Arc::new(point_cloud)).unwrap();
+        tree.generate_summaries();
+        let reader = tree.reader();
+
+        for (_, layer) in reader.layers() {
+            layer.for_each_node(|_, n| println!("{:?}", n.label_summary()));
+        }
+
+        let l = reader
+            .get_node_label_summary(reader.root_address())
+            .unwrap();
+        assert_eq!(l.summary.items.len(), 2);
+        assert_eq!(l.nones, 0);
+        assert_eq!(l.errors, 0);
+    }
+
+    #[test]
+    fn knn_singletons_off() {
+        let data = vec![0.499, 0.49, 0.48, -0.49, 0.0];
+        let labels = vec![0, 0, 0, 1, 1];
+
+        let point_cloud = DefaultLabeledCloud::<L2>::new_simple(data, 1, labels);
+        let builder = CoverTreeBuilder {
+            scale_base: 2.0,
+            leaf_cutoff: 1,
+            min_res_index: -9,
+            use_singletons: false,
+            partition_type: PartitionType::Nearest,
+            verbosity: 0,
+            rng_seed: Some(0),
+            memory_limit: None,
+            prune_chains: false,
+            thread_pool: None,
+            progress_callback: None,
+            progress_interval: None,
+            cancel_token: None,
+        };
+        let tree = builder.build(Arc::new(point_cloud)).unwrap();
+        let reader = tree.reader();
+
+        println!("2 nearest neighbors of 0.1 are 0.48 and 0.0");
+        let zero_nbrs = reader.knn(&[0.1f32].as_ref(), 2).unwrap();
+        println!("{:?}", zero_nbrs);
+        assert!(zero_nbrs[0].1 == 4);
+        assert!(zero_nbrs[1].1 == 2);
+    }
+
+    #[test]
+    fn test_save_load_tree() {
+        let data = vec![0.499, 0.49, 0.48, -0.49, 0.0];
+        let labels = vec![0, 0, 0, 1, 1];
+
+        let point_cloud = Arc::new(DefaultLabeledCloud::<L2>::new_simple(data, 1, labels));
+        let builder = CoverTreeBuilder {
+            scale_base: 2.0,
+            leaf_cutoff: 1,
+            min_res_index: -9,
+            use_singletons: false,
+            partition_type: PartitionType::Nearest,
+            verbosity: 0,
+            rng_seed: Some(0),
+            memory_limit: None,
+            prune_chains: false,
+            thread_pool: None,
+            progress_callback: None,
+            progress_interval: None,
+            cancel_token: None,
+        };
+        let tree = builder.build(Arc::clone(&point_cloud)).unwrap();
+        let reader = tree.reader();
+        let proto = tree.to_proto();
+
+        assert_eq!(reader.layers.len(), proto.get_layers().len());
+
+        for (layer, proto_layer) in reader.layers.iter().zip(proto.get_layers()) {
+            assert_eq!(layer.len(), proto_layer.get_nodes().len());
+        }
+
+        let reconstructed_tree_writer = CoverTreeWriter::from_proto(
+            &proto,
+            Arc::clone(&point_cloud),
+            &PluginRegistry::default(),
+        )
+        .unwrap();
+        let reconstructed_tree = reconstructed_tree_writer.reader();
+
+        assert_eq!(reader.layers.len(), reconstructed_tree.layers.len());
+        for (layer, reconstructed_layer) in reader.layers.iter().zip(reconstructed_tree.layers) {
+            assert_eq!(layer.len(), reconstructed_layer.len());
+
+            layer.for_each_node(|pi, n| {
+                reconstructed_layer
+                    .get_node_and(*pi, |rn| {
+                        assert_eq!(n.address(), rn.address());
+                        assert_eq!(n.parent_address(), rn.parent_address());
+                        assert_eq!(n.singletons(), rn.singletons());
+                    })
+                    .unwrap();
+            })
+        }
+    }
+
+    #[test]
+    fn knn_within_filters_knn() {
+        let writer = build_basic_tree();
+        let reader = writer.reader();
+        for query in &[-0.6f32, -0.2, 0.05, 0.3, 0.485, 0.9] {
+            for k in 1..=5 {
+                let full = reader.knn(&[*query].as_ref(), k).unwrap();
+                for radius in &[0.0f32, 0.1, 0.3, 0.5, 1.0, 10.0] {
+                    let expected: Vec<(f32, usize)> =
+                        full.iter().filter(|(d, _)| d <= radius).cloned().collect();
+                    let within = reader.knn_within(&[*query].as_ref(), k, *radius).unwrap();
+                    assert_eq!(
+                        expected, within,
+                        "query {} k {} radius {}",
+                        query, k, radius
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn sparse_tree_matches_dense() {
+        use pointcloud::data_sources::{DataRam, SparseDataRam};
+        use pointcloud::points::SparseRef;
+        // Points in 6 dimensions with about half of the coordinates zero
+        let dim = 6;
+        let mut seed = 12345u32;
+        let dense: Vec<f32> = (0..40 * dim)
+            .map(|_| {
+                seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                let x = (seed >> 16) as f32 / 65536.0;
+                if x < 0.5 {
+                    0.0
+                } else {
+                    x
+                }
+            })
+            .collect();
+        let mut indptr = vec![0u32];
+        let mut indices = Vec::new();
+        let mut values = Vec::new();
+        for point in dense.chunks(dim) {
+            for (i, x) in point.iter().enumerate() {
+                if *x != 0.0 {
+                    indices.push(i as u32);
+                    values.push(*x);
+                }
+            }
+            indptr.push(indices.len() as u32);
+        }
+        let sparse_cloud =
+            SparseDataRam::from_csr(indptr, indices, values, Some(dim), L2 {}).unwrap();
+        let dense_cloud = DataRam::<L2>::new(dense.clone(), dim).unwrap();
+
+        let mut builder = CoverTreeBuilder::new();
+        builder
+            .set_leaf_cutoff(1)
+            .set_min_res_index(-10)
+            .set_rng_seed(0);
+        let sparse_tree = builder.build(Arc::new(sparse_cloud)).unwrap();
+        let dense_tree = builder.build(Arc::new(dense_cloud)).unwrap();
+        let sparse_reader = sparse_tree.reader();
+        let dense_reader = dense_tree.reader();
+        for point in dense.chunks(dim) {
+            let (indexes, values): (Vec<u32>, Vec<f32>) = point
+                .iter()
+                .enumerate()
+                .filter(|(_, x)| **x != 0.0)
+                .map(|(i, x)| (i as u32, *x))
+                .unzip();
+            let query = SparseRef::new(dim, &values, &indexes);
+            let sparse_knn = sparse_reader.knn(&query, 5).unwrap();
+            let dense_knn = dense_reader.knn(&point, 5).unwrap();
+            for ((sd, si), (dd, di)) in sparse_knn.iter().zip(&dense_knn) {
+                assert_eq!(si, di);
+                assert_approx_eq!(sd, dd);
+            }
+        }
+    }
+
+    #[test]
+    fn vec_label_summaries_regress() {
+        use pointcloud::data_sources::DataRam;
+        use pointcloud::label_sources::VecLabels;
+        // Two targets, the point's value and its negation
+        let data = vec![0.499, 0.49, 0.48, -0.49, 0.0];
+        let targets: Vec<f32> = data.iter().flat_map(|x| vec![*x, -*x]).collect();
+        let point_cloud = SimpleLabeledCloud::new(
+            DataRam::<L2>::new(data.clone(), 1).unwrap(),
+            VecLabels::new(targets, 2, None),
+        );
+        let builder = CoverTreeBuilder::new();
+        let mut tree = builder.build(Arc::new(point_cloud)).unwrap();
+        tree.generate_summaries();
+        let reader = tree.reader();
+
+        let root_summary = reader
+            .get_node_label_summary(reader.root_address())
+            .unwrap();
+        assert_eq!(root_summary.summary.count, data.len());
+        let mean = data.iter().sum::<f32>() / data.len() as f32;
+        assert_approx_eq!(root_summary.summary.mean()[0], mean);
+        assert_approx_eq!(root_summary.summary.mean()[1], -mean);
+
+        let knn_summary = reader.knn_label_summary(&&[0.495f32][..], 2).unwrap();
+        assert_eq!(knn_summary.summary.count, 2);
+        assert_approx_eq!(knn_summary.summary.mean()[0], 0.4945);
+        assert_approx_eq!(knn_summary.summary.mean()[1], -0.4945);
+    }
+
+    #[test]
+    fn knn_excluding_matches_filtered_knn() {
+        let writer = build_basic_tree();
+        let reader = writer.reader();
+        for mask in 0..32u32 {
+            let excluded: RoaringBitmap = (0..5u32).filter(|i| mask & (1 << i) != 0).collect();
+            for query in &[-0.6f32, -0.2, 0.05, 0.3, 0.487, 0.9] {
+                let all = reader.knn(&[*query].as_ref(), 5).unwrap();
+                let expected: Vec<(f32, usize)> = all
+                    .into_iter()
+                    .filter(|(_, pi)| !excluded.contains(*pi as u32))
+                    .take(2)
+                    .collect();
+                let got = reader
+                    .knn_excluding(&[*query].as_ref(), 2, &excluded)
+                    .unwrap();
+                assert_eq!(got, expected, "query {} excluded {:?}", query, excluded);
+            }
+        }
+        let mut unknown = RoaringBitmap::new();
+        unknown.insert(10);
+        assert!(reader
+            .knn_excluding(&[0.0f32].as_ref(), 2, &unknown)
+            .is_err());
+    }
+
+    #[test]
+    fn knn_in_partitions_matches_filtered_knn() {
+        use crate::plugins::partitions::PartitionPlugin;
+        use pointcloud::data_sources::DataRam;
+        use pointcloud::partitions::{PartitionMap, SimplePartitionedCloud};
+        let data = vec![0.499, 0.49, 0.48, -0.49, 0.0];
+        let data = DataRam::<L2>::new(data, 1).unwrap();
+        let keys = [Some("a"), Some("b"), Some("a"), None, Some("b")];
+        let point_cloud =
+            SimplePartitionedCloud::new(data, PartitionMap::from_keys(&keys)).unwrap();
+        let builder = CoverTreeBuilder {
+            scale_base: 2.0,
+            leaf_cutoff: 1,
+            min_res_index: -9,
+            use_singletons: true,
+            partition_type: PartitionType::Nearest,
+            verbosity: 0,
+            rng_seed: Some(0),
+            memory_limit: None,
+            prune_chains: false,
+            thread_pool: None,
+            progress_callback: None,
+            progress_interval: None,
+            cancel_token: None,
+        };
+        let mut writer = builder.build(Arc::new(point_cloud)).unwrap();
+        writer.add_plugin::<PartitionPlugin>(PartitionPlugin::default());
+        let reader = writer.reader();
+        let root = reader.root_address();
+        let root_counts = reader
+            .get_node_plugin_and::<NodePartitionCounts, _, _>(root, |p| p.count_in(&[0, 1]))
+            .unwrap();
+        assert_eq!(root_counts, 4);
+
+        for partitions in &[vec!["a"], vec!["b"], vec!["a", "b"], vec!["c"]] {
+            for query in &[-0.6f32, -0.2, 0.05, 0.3, 0.487, 0.9] {
+                let expected: Vec<(f32, usize)> = reader
+                    .knn(&[*query].as_ref(), 5)
+                    .unwrap()
+                    .into_iter()
+                    .filter(|(_, pi)| keys[*pi].map(|key| partitions.contains(&key)) == Some(true))
+                    .take(2)
+                    .collect();
+                let got = reader
+                    .knn_in_partitions(&[*query].as_ref(), 2, &partitions[..])
+                    .unwrap();
+                assert_eq!(got, expected, "query {} in {:?}", query, partitions);
+            }
+        }
+        drop(reader);
+
+        let new_indexes = writer
+            .extend_in_partition(&[-0.48], &[None], Some("c"))
+            .unwrap();
+        let reader = writer.reader();
+        let got = reader
+            .knn_in_partitions(&[0.3f32].as_ref(), 2, &["c"][..])
+            .unwrap();
+        assert_eq!(got.len(), 1);
+        assert_eq!(got[0].1, new_indexes[0]);
+    }
+
+    #[test]
+    fn knn_with_metadata_joins_back() {
+        use pointcloud::data_sources::DataRam;
+        use pointcloud::meta_sources::MetadataMap;
+        let data = vec![0.499, 0.49, 0.48, -0.49, 0.0];
+        let data = DataRam::<L2>::new(data, 1).unwrap();
+        let metadata = MetadataMap::from_values(vec![
+            Some("a".to_string()),
+            Some("b".to_string()),
+            None,
+            Some("d".to_string()),
+            Some("e".to_string()),
+        ]);
+        let point_cloud = SimpleMetaCloud::new(data, metadata);
+        let builder = CoverTreeBuilder {
+            scale_base: 2.0,
+            leaf_cutoff: 1,
+            min_res_index: -9,
+            use_singletons: true,
+            partition_type: PartitionType::Nearest,
+            verbosity: 0,
+            rng_seed: Some(0),
+            memory_limit: None,
+            prune_chains: false,
+            thread_pool: None,
+            progress_callback: None,
+            progress_interval: None,
+            cancel_token: None,
+        };
+        let writer = builder.build(Arc::new(point_cloud)).unwrap();
+        let reader = writer.reader();
+        let query = [0.485f32];
+        let plain = reader.knn(&query.as_ref(), 3).unwrap();
+        let joined = reader.knn_with_metadata(&query.as_ref(), 3).unwrap();
+        assert_eq!(plain.len(), joined.len());
+        for ((d, i), (jd, ji, m)) in plain.iter().zip(joined.iter()) {
+            assert_eq!(d, jd);
+            assert_eq!(i, ji);
+            let expected = ["a", "b", "", "d", "e"][*i];
+            assert_eq!(m.map(|s| s.as_str()).unwrap_or(""), expected);
+        }
+    }
+
+    #[test]
+    fn queries_are_normalized_like_the_data() {
+        use pointcloud::data_sources::DataRam;
+        use pointcloud::normalization::Normalization;
+        let data = vec![0.0, 10.0, 20.0, 30.0, 40.0];
+        let point_cloud = DataRam::<L2>::new(data, 1)
+            .unwrap()
+            .with_normalization(Normalization::MinMax)
+            .unwrap();
+        let builder = CoverTreeBuilder {
+            scale_base: 2.0,
+            leaf_cutoff: 1,
+            min_res_index: -9,
+            use_singletons: true,
+            partition_type: PartitionType::Nearest,
+            verbosity: 0,
+            rng_seed: Some(0),
+            memory_limit: None,
+            prune_chains: false,
+            thread_pool: None,
+            progress_callback: None,
+            progress_interval: None,
+            cancel_token: None,
+        };
+        let writer = builder.build(Arc::new(point_cloud)).unwrap();
+        let reader = writer.reader();
+        let knn = reader.knn(&[21.0f32].as_ref(), 2).unwrap();
+        assert_eq!(knn[0].1, 2);
+        assert_approx_eq!(knn[0].0, 0.025);
+        assert_eq!(knn[1].1, 3);
+        for (dist, address) in reader.path(&[39.0f32].as_ref()).unwrap() {
+            let center = reader.point_cloud().point(address.1).unwrap()[0];
+            assert_approx_eq!(dist, (center - 0.975).abs());
+        }
+    }
+
+    #[test]
+    fn knn_hinted_matches_knn() {
+        let writer = build_basic_tree();
+        let reader = writer.reader();
+        let mut addresses = Vec::new();
+        for (scale_index, layer) in reader.layers() {
+            layer.for_each_node(|pi, _| addresses.push((scale_index, *pi)));
+        }
+        // A hint that isn't in the tree falls back to a cold query
+        addresses.push((reader.root_address().0 + 10, 0));
+        for query in &[-0.6f32, -0.2, 0.05, 0.3, 0.485, 0.9] {
+            for k in 1..=5 {
+                let cold = reader.knn(&[*query].as_ref(), k).unwrap();
+                for hint in &addresses {
+                    let warm = reader.knn_hinted(&[*query].as_ref(), k, *hint).unwrap();
+                    assert_eq!(cold, warm, "query {} k {} hint {:?}", query, k, hint);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn assignments_follow_known_paths() {
+        let writer = build_basic_tree();
+        let reader = writer.reader();
+        let root_address = reader.root_address();
+        let root_assignments = reader.assignments(root_address.0);
+        assert_eq!(root_assignments, vec![root_address.1 as i64; 5]);
+
+        for (scale_index, _) in reader.layers() {
+            let assignments = reader.assignments(scale_index);
+            assert_eq!(assignments.len(), 5);
+            for (pi, center_index) in assignments.iter().enumerate() {
+                let expected = reader
+                    .known_path(pi)
+                    .unwrap()
+                    .iter()
+                    .filter(|(_, address)| address.0 >= scale_index)
+                    .last()
+                    .map(|(_, address)| address.1 as i64)
+                    .unwrap();
+                assert_eq!(*center_index, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn knn_masked_matches_brute_force() {
+        use crate::plugins::masking::MaskingPlugin;
+        use pointcloud::data_sources::DataRam;
+        let dim = 6;
+        let mut seed = 54321u32;
+        let data: Vec<f32> = (0..60 * dim)
+            .map(|_| {
+                seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                (seed >> 16) as f32 / 65536.0
+            })
+            .collect();
+        let point_cloud = DataRam::<L2>::new(data.clone(), dim).unwrap();
+        let builder = CoverTreeBuilder {
+            scale_base: 2.0,
+            leaf_cutoff: 1,
+            min_res_index: -9,
+            use_singletons: true,
+            partition_type: PartitionType::Nearest,
+            verbosity: 0,
+            rng_seed: Some(0),
+            memory_limit: None,
+            prune_chains: false,
+            thread_pool: None,
+            progress_callback: None,
+            progress_interval: None,
+            cancel_token: None,
+        };
+        let mut writer = builder.build(Arc::new(point_cloud)).unwrap();
+        let masks = [vec![0], vec![1, 4], vec![0, 2, 3, 5]];
+        let queries: Vec<Vec<f32>> = data
+            .chunks(dim)
+            .take(10)
+            .map(|p| p.iter().map(|x| x + 0.05).collect())
+            .collect();
+
+        let check = |reader: &CoverTreeReader<DataRam<L2>>| {
+            for dims in &masks {
+                let mask = DimMask::new(dims.clone(), dim).unwrap();
+                for query in &queries {
+                    let mut expected: Vec<f32> = data
+                        .chunks(dim)
+                        .map(|p| L2 {}.masked_dist(p, query, &mask))
+                        .collect();
+                    expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                    let knn = reader.knn_masked(&&query[..], 5, &mask).unwrap();
+                    assert_eq!(knn.len(), 5);
+                    for ((d, _), e) in knn.iter().zip(&expected) {
+                        assert_approx_eq!(d, e);
+                    }
+                }
+            }
+        };
+        check(&writer.reader());
+
+        writer.add_plugin::<MaskingPlugin>(MaskingPlugin::default());
+        let reader = writer.reader();
+        let root = reader.root_address();
+        let root_center = reader.point_cloud().point(root.1).unwrap().to_vec();
+        let deviations = reader
+            .get_node_plugin_and::<NodeDeviations, _, _>(root, |p| p.deviations.clone())
+            .unwrap();
+        for point in data.chunks(dim) {
+            for ((x, c), d) in point.iter().zip(&root_center).zip(deviations.iter()) {
+                assert!((x - c).abs() <= d + 1e-6);
+            }
+        }
+        check(&reader);
+    }
+
+    #[test]
+    fn f16_tree_matches_f32_on_rounded_data() {
+        use pointcloud::data_sources::{DataRam, DataRamF16};
+        let dim = 6;
+        let mut seed = 2468u32;
+        let data: Vec<f32> = (0..50 * dim)
+            .map(|_| {
+                seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                (seed >> 16) as f32 / 65536.0
+            })
+            .collect();
+        let half_cloud = DataRamF16::<L2>::new(&data, dim).unwrap();
+        let rounded: Vec<f32> = (0..half_cloud.len())
+            .flat_map(|i| half_cloud.point(i).unwrap().dense())
+            .collect();
+        let full_cloud = DataRam::<L2>::new(rounded.clone(), dim).unwrap();
+
+        let mut builder = CoverTreeBuilder::new();
+        builder
+            .set_leaf_cutoff(1)
+            .set_min_res_index(-10)
+            .set_rng_seed(0);
+        let half_tree = builder.build(Arc::new(half_cloud)).unwrap();
+        let full_tree = builder.build(Arc::new(full_cloud)).unwrap();
+        let half_reader = half_tree.reader();
+        let full_reader = full_tree.reader();
+        for point in rounded.chunks(dim) {
+            let half_point = DataRamF16::<L2>::to_f16(point);
+            let half_knn = half_reader.knn(&&half_point[..], 5).unwrap();
+            let full_knn = full_reader.knn(&point, 5).unwrap();
+            for ((hd, hi), (fd, fi)) in half_knn.iter().zip(&full_knn) {
+                assert_eq!(hi, fi);
+                assert_approx_eq!(hd, fd);
+            }
+        }
     }
 
-    pub(crate) fn build_basic_tree() -> CoverTreeWriter<DefaultLabeledCloud<L2>> {
-        let data = vec![0.499, 0.49, 0.48, -0.49, 0.0];
-        let labels = vec![0, 0, 0, 1, 1];
+    #[test]
+    fn reranked_f16_knn_has_exact_distances() {
+        use pointcloud::data_sources::{DataRam, DataRamF16};
+        let dim = 6;
+        let mut seed = 1357u32;
+        let data: Vec<f32> = (0..100 * dim)
+            .map(|_| {
+                seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                (seed >> 16) as f32 / 65536.0
+            })
+            .collect();
+        let half_cloud = DataRamF16::<L2>::new(&data, dim).unwrap();
+        let full_cloud = DataRam::<L2>::new(data.clone(), dim).unwrap();
+
+        let mut builder = CoverTreeBuilder::new();
+        builder
+            .set_leaf_cutoff(1)
+            .set_min_res_index(-10)
+            .set_rng_seed(0);
+        let half_tree = builder.build(Arc::new(half_cloud)).unwrap();
+        let half_reader = half_tree.reader();
+        for point in data.chunks(dim).take(20) {
+            let half_point = DataRamF16::<L2>::to_f16(point);
+            let reranked = half_reader
+                .knn_reranked(&&half_point[..], &full_cloud, &point, 5, 0.0, 3)
+                .unwrap();
+            let mut brute_force: Vec<(f32, usize)> = full_cloud
+                .distances_to_point(&point, &(0..100).collect::<Vec<usize>>())
+                .unwrap()
+                .into_iter()
+                .zip(0..100)
+                .collect();
+            brute_force.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            assert_eq!(reranked.len(), 5);
+            for ((rd, _), (bd, _)) in reranked.iter().zip(&brute_force) {
+                assert_approx_eq!(rd, bd);
+            }
+        }
+    }
 
-        let point_cloud = DefaultLabeledCloud::<L2>::new_simple(data, 1, labels);
+    #[test]
+    fn knn_label_boosted_matches_brute_force() {
+        let data = vec![0.499, 0.49, 0.48, -0.49, 0.0, 0.2, -0.3, 0.45];
+        let labels = vec![0, 0, 0, 1, 1, 1, 0, 1];
+        let point_cloud = DefaultLabeledCloud::<L2>::new_simple(data.clone(), 1, labels.clone());
         let builder = CoverTreeBuilder {
             scale_base: 2.0,
             leaf_cutoff: 1,
@@ -718,240 +3312,375 @@ pub(crate) mod tests {
             partition_type: PartitionType::Nearest,
             verbosity: 0,
             rng_seed: Some(0),
+            memory_limit: None,
+            prune_chains: false,
+            thread_pool: None,
+            progress_callback: None,
+            progress_interval: None,
+            cancel_token: None,
         };
-        builder.build(Arc::new(point_cloud)).unwrap()
+        let writer = builder.build(Arc::new(point_cloud)).unwrap();
+        let reader = writer.reader();
+        for query in &[-0.6f32, -0.2, 0.05, 0.3, 0.485, 0.9] {
+            for weight in &[0.0f32, 0.1, 0.5, 2.0] {
+                for label in &[0i64, 1] {
+                    let mut expected: Vec<(f32, usize)> = data
+                        .iter()
+                        .zip(&labels)
+                        .enumerate()
+                        .map(|(i, (x, l))| {
+                            let bonus = if l == label { 0.0 } else { *weight };
+                            ((x - query).abs() + bonus, i)
+                        })
+                        .collect();
+                    expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                    let boosted = reader
+                        .knn_label_boosted(&[*query].as_ref(), 3, label, *weight)
+                        .unwrap();
+                    assert_eq!(boosted.len(), 3);
+                    for ((d, _), (e, _)) in boosted.iter().zip(&expected) {
+                        assert_approx_eq!(d, e);
+                    }
+                }
+            }
+            let knn = reader.knn(&[*query].as_ref(), 3).unwrap();
+            let unweighted = reader
+                .knn_scored(&[*query].as_ref(), 3, 0.0, |_| 0.0)
+                .unwrap();
+            for ((d, _), (e, _)) in knn.iter().zip(&unweighted) {
+                assert_approx_eq!(d, e);
+            }
+        }
+        assert!(reader
+            .knn_scored(&[0.0f32].as_ref(), 3, -1.0, |_| 1.0)
+            .is_err());
     }
 
     #[test]
-    fn len_is_num_layers() {
-        let tree = build_basic_tree();
+    fn collapsed_duplicates_keep_their_counts() {
+        use crate::plugins::discrete::prelude::*;
+        use pointcloud::data_sources::DataRam;
+        use pointcloud::label_sources::SmallIntLabels;
+        let data = vec![0.0, 0.0, 0.0, 0.5, 0.5, -0.3, 0.25, 0.0, 0.9, 0.9, 0.9, 0.9];
+        let labels = SmallIntLabels::new(vec![0, 0, 1, 0, 0, 1, 1, 0, 1, 1, 1, 0], None);
+        let mut data = DataRam::<L2>::new(data, 1).unwrap();
+        data.collapse_duplicates_by(|pi| labels.label(pi).ok().map(|l| l.copied()));
+        let point_cloud = SimpleLabeledCloud::new(data, labels);
+        assert_eq!(point_cloud.reference_indexes(), vec![0, 2, 3, 5, 6, 8, 11]);
+
+        let mut builder = CoverTreeBuilder::new();
+        builder
+            .set_leaf_cutoff(1)
+            .set_min_res_index(-9)
+            .set_rng_seed(0);
+        let mut tree = builder.build(Arc::new(point_cloud)).unwrap();
+        tree.generate_summaries();
+        tree.add_plugin::<GokoDirichlet>(GokoDirichlet::default());
         let reader = tree.reader();
+        let root = reader.root_address();
+        assert_eq!(reader.get_node_and(root, |n| n.coverage_count()), Some(12));
+        let summary = reader.get_node_label_summary(root).unwrap();
+        assert_eq!(summary.count(), 12);
+        assert!(summary.summary.items.contains(&(0, 6)));
+        let total = reader
+            .get_node_plugin_and::<Dirichlet, _, _>(root, |p| p.total())
+            .unwrap();
+        assert_approx_eq!(total, 12.0);
 
-        let mut l = 0;
-        for _ in reader.layers() {
-            l += 1;
-        }
-        assert_eq!(reader.len(), l);
+        // The copies aren't in the tree
+        assert!(reader.known_path(9).is_err());
+        assert!(reader.known_path(8).is_ok());
     }
 
     #[test]
-    fn layer_has_correct_scale_index() {
+    fn range_matches_brute_force() {
+        let data = [0.499f32, 0.49, 0.48, -0.49, 0.0];
         let tree = build_basic_tree();
         let reader = tree.reader();
-        let mut got_one = false;
-        for (si, l) in reader.layers() {
-            println!(
-                "Scale Index, correct: {:?}, Scale Index, layer: {:?}",
-                si,
-                l.scale_index()
-            );
-            assert_eq!(si, l.scale_index());
-            got_one = true;
+        for query in [0.0f32, 0.485, -0.3, 2.0].iter() {
+            for radius in [0.0f32, 0.006, 0.2, 0.5, 1.0].iter() {
+                let found: Vec<usize> = reader
+                    .range(&[*query].as_ref(), *radius)
+                    .unwrap()
+                    .iter()
+                    .map(|(_, pi)| *pi)
+                    .collect();
+                let mut expected: Vec<(f32, usize)> = data
+                    .iter()
+                    .enumerate()
+                    .map(|(pi, x)| ((x - query).abs(), pi))
+                    .filter(|(d, _)| *d <= *radius)
+                    .collect();
+                expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                let expected: Vec<usize> = expected.iter().map(|(_, pi)| *pi).collect();
+                assert_eq!(found, expected, "query {} radius {}", query, radius);
+            }
         }
-        assert!(got_one);
     }
 
     #[test]
-    fn greedy_knn_nodes() {
-        let data = vec![0.499, 0.49, 0.48, -0.49, 0.0];
-        let labels = vec![0, 0, 0, 1, 1];
-
-        let point_cloud = DefaultLabeledCloud::<L2>::new_simple(data, 1, labels);
-        let builder = CoverTreeBuilder {
-            scale_base: 2.0,
-            leaf_cutoff: 1,
-            min_res_index: -9,
-            use_singletons: false,
-            partition_type: PartitionType::Nearest,
-            verbosity: 0,
-            rng_seed: Some(0),
-        };
-        let tree = builder.build(Arc::new(point_cloud)).unwrap();
+    fn knn_batch_matches_knn() {
+        let tree = build_basic_tree();
         let reader = tree.reader();
-
-        let point = [-0.5];
-
-        let mut query_heap = KnnQueryHeap::new(5, reader.parameters.scale_base);
-        let dist_to_root = reader
-            .parameters
-            .point_cloud
-            .distances_to_point(&point.as_ref(), &[reader.root_address().1])
-            .unwrap()[0];
-        query_heap.push_nodes(&[reader.root_address()], &[dist_to_root], None);
-
-        assert_eq!(
-            reader.root_address(),
-            query_heap
-                .closest_unvisited_child_covering_address()
-                .unwrap()
-                .1
-        );
-
-        reader.greedy_knn_nodes(&point.as_ref(), &mut query_heap);
-        println!("{:#?}", query_heap);
-        println!(
-            "{:#?}",
-            query_heap.closest_unvisited_child_covering_address()
-        );
+        let queries = [0.3f32, 0.1, -0.2, 0.47, 0.495, 5.0, 0.31];
+        let points: Vec<&[f32]> = queries.iter().map(std::slice::from_ref).collect();
+        let batch = reader.knn_batch(&points, 3).unwrap();
+        assert_eq!(batch.len(), queries.len());
+        for (point, (dists, indexes)) in points.iter().zip(batch.iter()) {
+            let expected = reader.knn(point, 3).unwrap();
+            assert_eq!(indexes.len(), expected.len());
+            for ((d, pi), (ed, epi)) in dists.iter().zip(indexes).zip(expected) {
+                assert_eq!(*pi, epi);
+                assert_approx_eq!(*d, ed);
+            }
+        }
+        assert_eq!(batch.offsets().len(), queries.len() + 1);
+        assert_eq!(batch.indexes().len(), *batch.offsets().last().unwrap());
+        assert!(batch.get(queries.len()).is_none());
     }
 
     #[test]
-    fn path_sanity() {
-        let writer = build_basic_tree();
-        let reader = writer.reader();
-        let trace = reader.path(&[0.495f32].as_ref()).unwrap();
-        assert!(trace.len() == 4 || trace.len() == 3);
-        println!("{:?}", trace);
-        for i in 0..(trace.len() - 1) {
-            assert!((trace[i].1).0 > (trace[i + 1].1).0);
+    fn knn_approx_stays_within_the_error() {
+        let data: Vec<f32> = (0..2000)
+            .map(|i| ((i * 7919) % 1009) as f32 / 1009.0)
+            .collect();
+        let labels = vec![0; 500];
+        let point_cloud = DefaultLabeledCloud::<L2>::new_simple(data, 4, labels);
+        let mut builder = CoverTreeBuilder::new();
+        builder.set_leaf_cutoff(5).set_rng_seed(0);
+        let tree = builder.build(Arc::new(point_cloud)).unwrap();
+        let reader = tree.reader();
+        for q in 0..20 {
+            let query: Vec<f32> = (0..4)
+                .map(|j| (((q * 31 + j) * 613) % 1013) as f32 / 1013.0)
+                .collect();
+            let exact = reader.knn(&query.as_slice(), 5).unwrap();
+            assert_eq!(reader.knn_approx(&query.as_slice(), 5, 0.0).unwrap(), exact);
+            let approx = reader.knn_approx(&query.as_slice(), 5, 0.5).unwrap();
+            assert_eq!(approx.len(), 5);
+            assert!(approx[4].0 <= 1.5 * exact[4].0 + 1e-6);
         }
     }
 
     #[test]
-    fn known_path_sanity() {
-        let writer = build_basic_tree();
-        let reader = writer.reader();
-        for i in 0..5 {
-            let trace = reader.known_path(i).unwrap();
-            println!("i {}, trace {:?}", i, trace);
-            println!(
-                "final address: {:?}",
-                reader.final_addresses.get_and(&i, |i| *i)
-            );
-            let ad = trace.last().unwrap().1;
-            reader
-                .get_node_and(ad, |n| {
-                    if !n.is_leaf() {
-                        assert!(n.singletons().contains(&i));
-                    } else {
-                        assert!(
-                            (ad.1 != i && n.singletons().contains(&i))
-                                || (ad.1 == i && !n.singletons().contains(&i))
-                        );
-                    }
-                })
-                .unwrap();
-        }
-        let known_trace = reader.known_path(4).unwrap();
-        let trace = reader.path(&[0.0f32].as_ref()).unwrap();
-        println!(
-            "Testing known: {:?} matches unknown {:?}",
-            known_trace, trace
-        );
-        for (p, kp) in trace.iter().zip(known_trace) {
-            assert_eq!(*p, kp);
+    fn all_knn_matches_brute_force() {
+        let data: Vec<f32> = (0..1500)
+            .map(|i| ((i * 7919) % 1009) as f32 / 1009.0)
+            .collect();
+        let labels = vec![0; 500];
+        let point_cloud = DefaultLabeledCloud::<L2>::new_simple(data, 3, labels);
+        let mut builder = CoverTreeBuilder::new();
+        builder.set_leaf_cutoff(5).set_rng_seed(0);
+        let tree = builder.build(Arc::new(point_cloud)).unwrap();
+        let reader = tree.reader();
+        let cloud = reader.point_cloud();
+        let k = 4;
+        let graph = reader.all_knn(k).unwrap();
+        assert_eq!(graph.len(), cloud.len());
+        for (i, (dists, indexes)) in graph.iter().enumerate() {
+            let others: Vec<usize> = (0..cloud.len()).filter(|j| *j != i).collect();
+            let mut expected = cloud.distances_to_point_index(i, &others).unwrap();
+            expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            assert!(!indexes.contains(&i));
+            assert_eq!(dists.len(), k);
+            for (d, ed) in dists.iter().zip(&expected) {
+                assert_approx_eq!(*d, *ed);
+            }
         }
     }
 
     #[test]
-    fn knn_singletons_on() {
-        println!("2 nearest neighbors of 0.0 are 0.48 and 0.0");
-        let writer = build_basic_tree();
+    fn knn_with_labels_matches_brute_force() {
+        let data: Vec<f32> = (0..60).map(|i| ((i * 37) % 61) as f32 / 61.0).collect();
+        let labels: Vec<i64> = (0..60).map(|i| if i < 40 { i % 2 } else { 2 }).collect();
+        let point_cloud = DefaultLabeledCloud::<L2>::new_simple(data.clone(), 1, labels.clone());
+        let mut builder = CoverTreeBuilder::new();
+        builder
+            .set_leaf_cutoff(2)
+            .set_min_res_index(-9)
+            .set_rng_seed(0);
+        let mut writer = builder.build(Arc::new(point_cloud)).unwrap();
+        writer.generate_summaries();
         let reader = writer.reader();
-        let zero_nbrs = reader.knn(&[0.1f32].as_ref(), 2).unwrap();
-        println!("{:?}", zero_nbrs);
-        assert!(zero_nbrs[0].1 == 4);
-        assert!(zero_nbrs[1].1 == 2);
+        let brute = |query: f32, pass: &dyn Fn(usize) -> bool| -> Vec<f32> {
+            let mut dists: Vec<f32> = (0..data.len())
+                .filter(|i| pass(*i))
+                .map(|i| (data[i] - query).abs())
+                .collect();
+            dists.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            dists.truncate(4);
+            dists
+        };
+        for query in &[-0.2f32, 0.1, 0.5, 0.77, 1.3] {
+            for wanted in &[vec![2i64], vec![0, 2], vec![1], vec![3]] {
+                let found = reader
+                    .knn_with_labels(&[*query].as_ref(), 4, wanted)
+                    .unwrap();
+                let expected = brute(*query, &|i| wanted.contains(&labels[i]));
+                assert_eq!(found.len(), expected.len());
+                for ((d, pi), e) in found.iter().zip(&expected) {
+                    assert!(wanted.contains(&labels[*pi]));
+                    assert_approx_eq!(*d, *e);
+                }
+            }
+            let found = reader
+                .knn_where(&[*query].as_ref(), 4, |pi| pi % 3 == 0, |_| true)
+                .unwrap();
+            let expected = brute(*query, &|i| i % 3 == 0);
+            for ((d, pi), e) in found.iter().zip(&expected) {
+                assert_eq!(pi % 3, 0);
+                assert_approx_eq!(*d, *e);
+            }
+        }
     }
 
     #[test]
-    fn label_summary() {
-        let data = vec![0.499, 0.49, 0.48, -0.49, 0.0];
-        let labels = vec![0, 0, 0, 1, 1];
+    fn saved_trees_keep_their_plugins() {
+        use crate::plugins::calibration::{CalibrationMethod, DistanceCalibration};
+        use crate::plugins::discrete::prelude::*;
+        use tempdir::TempDir;
 
-        let point_cloud = DefaultLabeledCloud::<L2>::new_simple(data, 1, labels);
-        let builder = CoverTreeBuilder {
-            scale_base: 2.0,
-            leaf_cutoff: 1,
-            min_res_index: -9,
-            use_singletons: false,
-            partition_type: PartitionType::Nearest,
-            verbosity: 0,
-            rng_seed: Some(0),
-        };
-        let mut tree = builder.build(Arc::new(point_cloud)).unwrap();
+        let mut tree = build_basic_tree();
         tree.generate_summaries();
+        tree.add_persistent_plugin(GokoDirichlet::default())
+            .unwrap();
+        let calibration = DistanceCalibration::fit_samples(
+            &[(0.1, true), (0.9, false)],
+            CalibrationMethod::Platt,
+        )
+        .unwrap();
+        tree.add_persistent_plugin(calibration.clone()).unwrap();
         let reader = tree.reader();
 
-        for (_, layer) in reader.layers() {
-            layer.for_each_node(|_, n| println!("{:?}", n.label_summary()));
+        let dir = TempDir::new("goko_save").unwrap();
+        let path = dir.path().join("basic.tree");
+        tree.save(&path).unwrap();
+        let point_cloud = Arc::clone(reader.point_cloud());
+        let loaded_reader = CoverTreeReader::load(&path, point_cloud).unwrap();
+
+        assert_eq!(loaded_reader.parameters().rng_seed, Some(0));
+        assert_eq!(
+            loaded_reader.knn(&[0.2], 3).unwrap(),
+            reader.knn(&[0.2], 3).unwrap()
+        );
+        assert_eq!(
+            loaded_reader.get_plugin_and::<DistanceCalibration, _, _>(|c| c.clone()),
+            Some(calibration)
+        );
+        for (si, layer) in reader.layers() {
+            layer.for_each_node(|pi, n| {
+                let address = (si, *pi);
+                let counts = n.get_plugin_and::<Dirichlet, _, _>(|d| d.ln_prob_vector());
+                let loaded_counts = loaded_reader
+                    .get_node_plugin_and::<Dirichlet, _, _>(address, |d| d.ln_prob_vector());
+                assert_eq!(counts, loaded_counts);
+                assert_eq!(
+                    reader
+                        .get_node_label_summary(address)
+                        .map(|s| s.summary.items.clone()),
+                    loaded_reader
+                        .get_node_label_summary(address)
+                        .map(|s| s.summary.items.clone())
+                );
+            });
         }
 
-        let l = reader
-            .get_node_label_summary(reader.root_address())
-            .unwrap();
-        assert_eq!(l.summary.items.len(), 2);
-        assert_eq!(l.nones, 0);
-        assert_eq!(l.errors, 0);
+        let other_cloud = Arc::new(DefaultLabeledCloud::<L2>::new_simple(
+            vec![0.0, 1.0],
+            1,
+            vec![0, 1],
+        ));
+        assert!(CoverTreeReader::load(&path, other_cloud).is_err());
+        assert!(CoverTreeWriter::load_file(
+            &path,
+            Arc::clone(reader.point_cloud()),
+            &PluginRegistry::empty()
+        )
+        .is_err());
     }
 
     #[test]
-    fn knn_singletons_off() {
-        let data = vec![0.499, 0.49, 0.48, -0.49, 0.0];
-        let labels = vec![0, 0, 0, 1, 1];
+    fn health_tracks_publishes_and_stale_plugins() {
+        use crate::plugins::calibration::{CalibrationMethod, DistanceCalibration};
 
-        let point_cloud = DefaultLabeledCloud::<L2>::new_simple(data, 1, labels);
-        let builder = CoverTreeBuilder {
-            scale_base: 2.0,
-            leaf_cutoff: 1,
-            min_res_index: -9,
-            use_singletons: false,
-            partition_type: PartitionType::Nearest,
-            verbosity: 0,
-            rng_seed: Some(0),
-        };
-        let tree = builder.build(Arc::new(point_cloud)).unwrap();
+        let mut tree = build_basic_tree();
+        tree.generate_summaries();
+        let calibration = DistanceCalibration::fit_samples(
+            &[(0.1, true), (0.9, false)],
+            CalibrationMethod::Platt,
+        )
+        .unwrap();
+        tree.add_plugin(calibration);
         let reader = tree.reader();
 
-        println!("2 nearest neighbors of 0.1 are 0.48 and 0.0");
-        let zero_nbrs = reader.knn(&[0.1f32].as_ref(), 2).unwrap();
-        println!("{:?}", zero_nbrs);
-        assert!(zero_nbrs[0].1 == 4);
-        assert!(zero_nbrs[1].1 == 2);
+        let health = reader.health();
+        assert_eq!(health.generation, 1);
+        assert_eq!(health.point_count, 5);
+        assert!(health.memory_estimate > 0);
+        assert!(health.last_publish_ms.is_some());
+        assert_eq!(health.plugins.len(), 2);
+        assert!(health.is_ready());
+
+        let root_center = reader.root_address().1;
+        let point = (0..5).find(|pi| *pi != root_center).unwrap();
+        tree.remove(point).unwrap();
+        let health = tree.reader().health();
+        assert!(health.generation > 1);
+        assert_eq!(health.point_count, 4);
+        let stale: Vec<&str> = health.stale_plugins().collect();
+        assert_eq!(stale, vec![std::any::type_name::<DistanceCalibration>()]);
+        assert!(!health.is_ready());
     }
 
     #[test]
-    fn test_save_load_tree() {
-        let data = vec![0.499, 0.49, 0.48, -0.49, 0.0];
-        let labels = vec![0, 0, 0, 1, 1];
-
-        let point_cloud = Arc::new(DefaultLabeledCloud::<L2>::new_simple(data, 1, labels));
-        let builder = CoverTreeBuilder {
-            scale_base: 2.0,
-            leaf_cutoff: 1,
-            min_res_index: -9,
-            use_singletons: false,
-            partition_type: PartitionType::Nearest,
-            verbosity: 0,
-            rng_seed: Some(0),
-        };
-        let tree = builder.build(Arc::clone(&point_cloud)).unwrap();
+    fn subtrees_answer_queries_on_their_own_points() {
+        let tree = build_basic_tree();
         let reader = tree.reader();
-        let proto = tree.save();
-
-        assert_eq!(reader.layers.len(), proto.get_layers().len());
-
-        for (layer, proto_layer) in reader.layers.iter().zip(proto.get_layers()) {
-            assert_eq!(layer.len(), proto_layer.get_nodes().len());
-        }
 
-        let reconstructed_tree_writer =
-            CoverTreeWriter::load(&proto, Arc::clone(&point_cloud)).unwrap();
-        let reconstructed_tree = reconstructed_tree_writer.reader();
+        let whole = reader.subtree(reader.root_address()).unwrap();
+        let whole_reader = whole.reader();
+        assert_eq!(whole_reader.point_cloud().len(), 5);
+        assert_eq!(
+            whole_reader.knn(&[0.1f32].as_ref(), 3).unwrap(),
+            reader.knn(&[0.1f32].as_ref(), 3).unwrap()
+        );
 
-        assert_eq!(reader.layers.len(), reconstructed_tree.layers.len());
-        for (layer, reconstructed_layer) in reader.layers.iter().zip(reconstructed_tree.layers) {
-            assert_eq!(layer.len(), reconstructed_layer.len());
+        let (nested_scale, _) = reader
+            .get_node_and(reader.root_address(), |n| n.children())
+            .flatten()
+            .unwrap();
+        let address = (nested_scale, reader.root_address().1);
+        let coverage = reader
+            .get_node_and(address, |n| n.coverage_count())
+            .unwrap();
+        let sub = reader.subtree(address).unwrap();
+        let sub_reader = sub.reader();
+        assert_eq!(sub_reader.root_address(), address);
+        assert_eq!(
+            sub_reader.get_node_and(address, |n| n.parent_address()),
+            Some(None)
+        );
+        let covered = sub_reader.point_cloud().reference_indexes();
+        assert_eq!(covered.len(), coverage);
+        let nbrs = sub_reader.knn(&[0.1f32].as_ref(), 5).unwrap();
+        assert_eq!(nbrs.len(), covered.len());
+        assert!(nbrs.iter().all(|(_, pi)| covered.contains(pi)));
+    }
 
-            layer.for_each_node(|pi, n| {
-                reconstructed_layer
-                    .get_node_and(*pi, |rn| {
-                        assert_eq!(n.address(), rn.address());
-                        assert_eq!(n.parent_address(), rn.parent_address());
-                        assert_eq!(n.singletons(), rn.singletons());
-                    })
-                    .unwrap();
-            })
+    #[test]
+    fn annotated_knn_has_labels_and_addresses() {
+        let tree = build_basic_tree();
+        let reader = tree.reader();
+        let point_cloud = reader.point_cloud();
+
+        let annotated = reader.knn_annotated(&[0.1f32].as_ref(), 3).unwrap();
+        let plain = reader.knn(&[0.1f32].as_ref(), 3).unwrap();
+        assert_eq!(annotated.len(), plain.len());
+        for ((d, i, label, address), (plain_d, plain_i)) in annotated.iter().zip(&plain) {
+            assert_eq!((*d, *i), (*plain_d, *plain_i));
+            assert_eq!(*label, point_cloud.label(*i).unwrap());
+            let path = reader.known_path(*i).unwrap();
+            assert_eq!(*address, path.last().unwrap().1);
         }
     }
 }
@@ -0,0 +1,429 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! Checking that a tree is still a legal cover tree.
+//!
+//! [`CoverTreeReader::validate`] walks the whole tree and checks the three invariants the queries
+//! rely on:
+//!
+//! * Covering: every point under a node is within the node's scale of its center, the bound the
+//!   kNN queries prune a subtree with, and within the node's stored radius, the bound the range
+//!   queries prune with. A point past either is one the queries can miss.
+//! * Separation: the centers of a routing node's children and singletons are at least the scale
+//!   of its nested child away from each other.
+//! * Nesting: a node's children are on lower scales than it, point back at it as their parent,
+//!   and between them cover each of its points exactly once, which its coverage count records.
+
+use crate::errors::GokoResult;
+use crate::*;
+use hashbrown::HashSet;
+use serde::{Deserialize, Serialize};
+
+/// Distances are recomputed, so they're allowed to be off by this fraction before they count.
+const TOLERANCE: f32 = 1.0e-5;
+
+/// The invariant a [`TreeViolation`] breaks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Invariant {
+    /// Every point under a node is within its radius
+    Covering,
+    /// The children of a routing node are separated by the child scale
+    Separation,
+    /// The children of a node are below it and cover it exactly
+    Nesting,
+}
+
+/// A single place where a tree isn't a legal cover tree.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum TreeViolation {
+    /// A node refers to a child that isn't in the tree. The parent is `None` if it's the root that's missing.
+    MissingNode {
+        /// The node that holds the reference
+        parent: Option<NodeAddress>,
+        /// The address that isn't in the tree
+        child: NodeAddress,
+    },
+    /// A node can be reached more than once from the root.
+    Revisited {
+        /// The node that was reached again
+        address: NodeAddress,
+    },
+    /// A child's parent address isn't the node that holds it.
+    ParentMismatch {
+        /// The child
+        address: NodeAddress,
+        /// The node that holds the child, `None` for the root
+        expected: Option<NodeAddress>,
+        /// The parent address the child has
+        found: Option<NodeAddress>,
+    },
+    /// A child isn't on a lower scale than its parent.
+    ScaleOrder {
+        /// The parent
+        parent: NodeAddress,
+        /// The child
+        child: NodeAddress,
+    },
+    /// A node's coverage count isn't the number of points under it, each counted with its multiplicity.
+    CoverageCount {
+        /// The node
+        address: NodeAddress,
+        /// The coverage count the node has
+        stored: usize,
+        /// The number of points under the node, with collapsed duplicates counted
+        counted: usize,
+    },
+    /// A point is covered more than once, by different branches of the tree.
+    DuplicatePoint {
+        /// The point's index
+        point_index: usize,
+    },
+    /// A point under a node is farther from the center than the node's scale, so kNN queries can prune it.
+    Covering {
+        /// The node
+        address: NodeAddress,
+        /// The point's index
+        point_index: usize,
+        /// The distance from the node's center to the point
+        distance: f32,
+        /// The node's scale
+        scale: f32,
+    },
+    /// A point under a node is farther from the center than the node's radius, so range queries can prune it.
+    Radius {
+        /// The node
+        address: NodeAddress,
+        /// The point's index
+        point_index: usize,
+        /// The distance from the node's center to the point
+        distance: f32,
+        /// The node's radius
+        radius: f32,
+    },
+    /// Two children or singletons of a routing node are closer than the scale of its nested child.
+    Separation {
+        /// The routing node
+        address: NodeAddress,
+        /// The center index of the first child or singleton
+        first: usize,
+        /// The center index of the second child or singleton
+        second: usize,
+        /// The distance between them
+        distance: f32,
+        /// The scale of the nested child
+        scale: f32,
+    },
+}
+
+impl TreeViolation {
+    /// The invariant this breaks.
+    pub fn invariant(&self) -> Invariant {
+        match self {
+            TreeViolation::Covering { .. } | TreeViolation::Radius { .. } => Invariant::Covering,
+            TreeViolation::Separation { .. } => Invariant::Separation,
+            _ => Invariant::Nesting,
+        }
+    }
+}
+
+/// The result of [`CoverTreeReader::validate`].
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ValidationReport {
+    /// The number of nodes that were reached from the root and checked
+    pub nodes_checked: usize,
+    /// Everything that was found wrong, in the order the walk found it
+    pub violations: Vec<TreeViolation>,
+}
+
+impl ValidationReport {
+    /// If no violations were found.
+    pub fn is_valid(&self) -> bool {
+        self.violations.is_empty()
+    }
+
+    /// The violations of a single invariant.
+    pub fn violations_of(&self, invariant: Invariant) -> impl Iterator<Item = &TreeViolation> {
+        self.violations
+            .iter()
+            .filter(move |v| v.invariant() == invariant)
+    }
+}
+
+struct NodeContents {
+    parent_address: Option<NodeAddress>,
+    radius: f32,
+    coverage_count: usize,
+    children: Option<(i32, Vec<NodeAddress>)>,
+    singletons: Vec<usize>,
+}
+
+impl<D: PointCloud> CoverTreeReader<D> {
+    /// Checks the covering, separation and nesting invariants across the whole tree, see the
+    /// [`ValidationReport`]. This computes the distance from every node's center to every point
+    /// under it, so it's as expensive as building the tree. Errors only if the point cloud does.
+    pub fn validate(&self) -> GokoResult<ValidationReport> {
+        let mut report = ValidationReport::default();
+        let mut visited = HashSet::new();
+        let points = self.validate_node(self.root_address(), None, &mut report, &mut visited)?;
+        let mut seen = HashSet::new();
+        for pi in points {
+            if !seen.insert(pi) {
+                report
+                    .violations
+                    .push(TreeViolation::DuplicatePoint { point_index: pi });
+            }
+        }
+        Ok(report)
+    }
+
+    fn has_layer(&self, scale_index: i32) -> bool {
+        let min_res_index = self.parameters().min_res_index;
+        scale_index >= min_res_index - 1
+            && self.parameters().internal_index(scale_index) < self.len()
+    }
+
+    /// Checks a node and its subtree, returning the points under it with its center first.
+    fn validate_node(
+        &self,
+        address: NodeAddress,
+        parent: Option<NodeAddress>,
+        report: &mut ValidationReport,
+        visited: &mut HashSet<NodeAddress>,
+    ) -> GokoResult<Vec<usize>> {
+        if !visited.insert(address) {
+            report.violations.push(TreeViolation::Revisited { address });
+            return Ok(Vec::new());
+        }
+        let contents = if self.has_layer(address.0) {
+            self.get_node_and(address, |n| NodeContents {
+                parent_address: n.parent_address(),
+                radius: n.radius(),
+                coverage_count: n.coverage_count(),
+                children: n.children().map(|(si, c)| (si, c.to_vec())),
+                singletons: n.singletons_decoded().into_owned(),
+            })
+        } else {
+            None
+        };
+        let contents = match contents {
+            Some(contents) => contents,
+            None => {
+                report.violations.push(TreeViolation::MissingNode {
+                    parent,
+                    child: address,
+                });
+                return Ok(Vec::new());
+            }
+        };
+        report.nodes_checked += 1;
+        if contents.parent_address != parent {
+            report.violations.push(TreeViolation::ParentMismatch {
+                address,
+                expected: parent,
+                found: contents.parent_address,
+            });
+        }
+
+        let mut points = vec![address.1];
+        points.extend(&contents.singletons);
+        if let Some((nested_scale, other_children)) = &contents.children {
+            let nested_address = (*nested_scale, address.1);
+            let mut centers = vec![address.1];
+            for child in std::iter::once(&nested_address).chain(other_children.iter()) {
+                if child.0 >= address.0 {
+                    report.violations.push(TreeViolation::ScaleOrder {
+                        parent: address,
+                        child: *child,
+                    });
+                }
+                let child_points = self.validate_node(*child, Some(address), report, visited)?;
+                if child == &nested_address {
+                    // The nested child's center is this node's center, which is already counted
+                    points.extend(child_points.iter().skip(1));
+                } else {
+                    centers.push(child.1);
+                    points.extend(child_points);
+                }
+            }
+            centers.extend(&contents.singletons);
+            self.check_separation(address, *nested_scale, &centers, report)?;
+        }
+
+        let distances = self
+            .point_cloud()
+            .distances_to_point_index(address.1, &points[1..])?;
+        let scale = self.scale(address.0);
+        for (pi, d) in points[1..].iter().zip(distances) {
+            if d > scale * (1.0 + TOLERANCE) {
+                report.violations.push(TreeViolation::Covering {
+                    address,
+                    point_index: *pi,
+                    distance: d,
+                    scale,
+                });
+            }
+            if d > contents.radius * (1.0 + TOLERANCE) + f32::EPSILON {
+                report.violations.push(TreeViolation::Radius {
+                    address,
+                    point_index: *pi,
+                    distance: d,
+                    radius: contents.radius,
+                });
+            }
+        }
+        let counted = points
+            .iter()
+            .map(|pi| self.point_cloud().multiplicity(*pi))
+            .sum();
+        if contents.coverage_count != counted {
+            report.violations.push(TreeViolation::CoverageCount {
+                address,
+                stored: contents.coverage_count,
+                counted,
+            });
+        }
+        Ok(points)
+    }
+
+    fn check_separation(
+        &self,
+        address: NodeAddress,
+        nested_scale: i32,
+        centers: &[usize],
+        report: &mut ValidationReport,
+    ) -> GokoResult<()> {
+        let scale = self.scale(nested_scale);
+        for (i, first) in centers.iter().enumerate() {
+            let distances = self
+                .point_cloud()
+                .distances_to_point_index(*first, &centers[i + 1..])?;
+            for (second, distance) in centers[i + 1..].iter().zip(distances) {
+                if distance < scale * (1.0 - TOLERANCE) {
+                    report.violations.push(TreeViolation::Separation {
+                        address,
+                        first: *first,
+                        second: *second,
+                        distance,
+                        scale,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tree::tests::build_basic_tree;
+    use super::*;
+
+    #[test]
+    fn basic_tree_is_valid() {
+        let tree = build_basic_tree();
+        let reader = tree.reader();
+        let report = reader.validate().unwrap();
+        assert!(report.is_valid(), "{:?}", report.violations);
+        assert_eq!(report.nodes_checked, reader.node_count());
+    }
+
+    #[test]
+    fn collapsed_duplicates_are_valid() {
+        use pointcloud::data_sources::DataRam;
+        use pointcloud::label_sources::SmallIntLabels;
+        use std::sync::Arc;
+        let data = vec![0.0, 0.0, 0.0, 0.5, 0.5, -0.3, 0.25, 0.0, 0.9, 0.9, 0.9, 0.9];
+        let labels = SmallIntLabels::new(vec![0, 0, 1, 0, 0, 1, 1, 0, 1, 1, 1, 0], None);
+        let mut data = DataRam::<L2>::new(data, 1).unwrap();
+        data.collapse_duplicates_by(|pi| labels.label(pi).ok().map(|l| l.copied()));
+        let point_cloud = SimpleLabeledCloud::new(data, labels);
+
+        let mut builder = CoverTreeBuilder::new();
+        builder
+            .set_leaf_cutoff(1)
+            .set_min_res_index(-9)
+            .set_rng_seed(0);
+        let tree = builder.build(Arc::new(point_cloud)).unwrap();
+        let reader = tree.reader();
+        let report = reader.validate().unwrap();
+        assert!(!report
+            .violations
+            .iter()
+            .any(|v| matches!(v, TreeViolation::CoverageCount { .. })));
+        assert!(report.is_valid(), "{:?}", report.violations);
+    }
+
+    #[test]
+    fn validate_reports_corrupted_nodes() {
+        let mut tree = build_basic_tree();
+        let root_address = tree.reader().root_address();
+        unsafe {
+            tree.update_node(root_address, |n| {
+                n.set_radius(0.0);
+                n.set_coverage_count(1000);
+            });
+        }
+        tree.refresh();
+        let report = tree.reader().validate().unwrap();
+        assert!(!report.is_valid());
+        assert!(report.violations.contains(&TreeViolation::CoverageCount {
+            address: root_address,
+            stored: 1000,
+            counted: 5,
+        }));
+        assert_eq!(report.violations_of(Invariant::Covering).count(), 4);
+        assert_eq!(report.violations_of(Invariant::Separation).count(), 0);
+    }
+
+    #[test]
+    fn validate_flags_points_past_the_scale() {
+        let mut tree = build_basic_tree();
+        let old_root = tree.reader().root_address();
+        let new_indexes = tree.extend(&[3.0], &[Some(&1)]).unwrap();
+        assert_eq!(new_indexes, vec![5]);
+        let new_root = tree.reader().root_address();
+        assert!(tree.reader().validate().unwrap().is_valid());
+
+        // Attach the far point under the old root, as if it had never been raised. Its radius covers the point, but
+        // its scale doesn't and kNN queries prune on the scale.
+        unsafe {
+            tree.update_node(new_root, |n| {
+                n.remove_singleton(5);
+            });
+            tree.update_node(old_root, |n| {
+                n.insert_singleton(5);
+                n.set_radius(10.0);
+            });
+        }
+        tree.refresh();
+        let report = tree.reader().validate().unwrap();
+        assert!(report.violations.iter().any(|v| matches!(
+            v,
+            TreeViolation::Covering {
+                address,
+                point_index: 5,
+                ..
+            } if *address == old_root
+        )));
+        assert!(!report
+            .violations
+            .iter()
+            .any(|v| matches!(v, TreeViolation::Radius { address, .. } if *address == old_root)));
+    }
+}
@@ -0,0 +1,239 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! Sanitized dumps of a tree for bug reports.
+//!
+//! A [`DebugBundle`] has the shape of a tree and nothing of its data: the build parameters, the
+//! build report, and for each node its address, parent, children, radius and counts. Points only
+//! show up as indexes, no vectors, labels or metadata are written. The bundle is saved as a single
+//! gzipped JSON file that can be attached to an issue, and [`DebugBundle::structural_issues`]
+//! checks the loaded bundle for the broken links and counts a bad tree would have.
+
+use crate::build_report::BuildReport;
+use crate::*;
+use errors::{GokoError, GokoResult};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use hashbrown::HashMap;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+
+/// The statistics of one node of the tree.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NodeStats {
+    /// The address of the node
+    pub address: NodeAddress,
+    /// The address of the node's parent, `None` for the root
+    pub parent_address: Option<NodeAddress>,
+    /// The distance from the center to the furthest point the node covers
+    pub radius: f32,
+    /// The number of points the node covers
+    pub coverage_count: usize,
+    /// The number of singletons the node holds
+    pub singletons_len: usize,
+    /// The scale index of the nested child, `None` for leaves
+    pub nested_scale: Option<i32>,
+    /// The routing children of the node, not counting the nested child
+    pub children: Vec<NodeAddress>,
+}
+
+/// The shape of a tree without its data, see [`crate::debug_bundle`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DebugBundle {
+    /// The version of goko that wrote the bundle
+    pub goko_version: String,
+    /// The parameters the tree was built with
+    pub parameters: BuilderConfig,
+    /// The dimension of the point cloud
+    pub dim: usize,
+    /// The number of points in the point cloud
+    pub point_count: usize,
+    /// The root of the tree
+    pub root_address: NodeAddress,
+    /// Where the time of the build and of the plugins went
+    pub build_report: BuildReport,
+    /// Every node of the tree, sorted by address
+    pub nodes: Vec<NodeStats>,
+}
+
+impl DebugBundle {
+    /// Collects the bundle of a tree.
+    pub fn new<D: PointCloud>(tree: &CoverTreeWriter<D>) -> DebugBundle {
+        let reader = tree.reader();
+        let parameters = reader.parameters();
+        let mut nodes = Vec::new();
+        for (_, layer) in reader.layers() {
+            layer.for_each_node(|_, n| {
+                let children = n.children();
+                nodes.push(NodeStats {
+                    address: n.address(),
+                    parent_address: n.parent_address(),
+                    radius: n.radius(),
+                    coverage_count: n.coverage_count(),
+                    singletons_len: n.singletons_len(),
+                    nested_scale: children.map(|(nested_scale, _)| nested_scale),
+                    children: children
+                        .map(|(_, addresses)| addresses.to_vec())
+                        .unwrap_or_default(),
+                })
+            });
+        }
+        nodes.sort_by_key(|n| n.address);
+        DebugBundle {
+            goko_version: env!("CARGO_PKG_VERSION").to_string(),
+            parameters: BuilderConfig {
+                scale_base: Some(parameters.scale_base),
+                leaf_cutoff: Some(parameters.leaf_cutoff),
+                min_res_index: Some(parameters.min_res_index),
+                use_singletons: Some(parameters.use_singletons),
+                partition_type: Some(parameters.partition_type),
+                verbosity: Some(parameters.verbosity),
+                rng_seed: parameters.rng_seed,
+                memory_limit: None,
+                prune_chains: None,
+            },
+            dim: parameters.point_cloud.dim(),
+            point_count: parameters.point_cloud.len(),
+            root_address: reader.root_address(),
+            build_report: tree.build_report().clone(),
+            nodes,
+        }
+    }
+
+    /// Writes the bundle to `path` as gzipped JSON.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> GokoResult<()> {
+        let mut encoder =
+            GzEncoder::new(BufWriter::new(File::create(path)?), Compression::default());
+        serde_json::to_writer(&mut encoder, self).map_err(|e| GokoError::IoError(e.into()))?;
+        encoder.finish()?.flush()?;
+        Ok(())
+    }
+
+    /// Reads a bundle written by `save`.
+    pub fn load<P: AsRef<Path>>(path: P) -> GokoResult<DebugBundle> {
+        let decoder = GzDecoder::new(BufReader::new(File::open(path)?));
+        serde_json::from_reader(decoder).map_err(|e| GokoError::IoError(e.into()))
+    }
+
+    /// Checks the links and counts between the nodes, and returns a description of each problem
+    /// found. A tree goko built has none. Coverage counts are only checked from below, as points
+    /// that stand for collapsed duplicates count more than once.
+    pub fn structural_issues(&self) -> Vec<String> {
+        let mut issues = Vec::new();
+        let nodes: HashMap<NodeAddress, &NodeStats> =
+            self.nodes.iter().map(|n| (n.address, n)).collect();
+        match nodes.get(&self.root_address) {
+            None => issues.push(format!("the root {:?} is missing", self.root_address)),
+            Some(root) if root.parent_address.is_some() => issues.push(format!(
+                "the root {:?} has the parent {:?}",
+                self.root_address, root.parent_address
+            )),
+            _ => (),
+        }
+
+        let mut reached = 0;
+        let mut unvisited = vec![self.root_address];
+        while let Some(address) = unvisited.pop() {
+            let node = match nodes.get(&address) {
+                Some(node) => node,
+                None => continue,
+            };
+            reached += 1;
+            if !(node.radius >= 0.0 && node.radius.is_finite()) {
+                issues.push(format!("{:?} has the radius {}", address, node.radius));
+            }
+            let mut covered = node.singletons_len;
+            let nested = node
+                .nested_scale
+                .map(|scale_index| (scale_index, address.1));
+            if nested.is_none() {
+                covered += 1;
+            }
+            for child in nested.iter().chain(&node.children) {
+                if child.0 >= address.0 {
+                    issues.push(format!(
+                        "{:?} has the child {:?} at the same or a higher scale",
+                        address, child
+                    ));
+                    continue;
+                }
+                match nodes.get(child) {
+                    None => issues.push(format!("{:?} has the missing child {:?}", address, child)),
+                    Some(child_node) => {
+                        if child_node.parent_address != Some(address) {
+                            issues.push(format!(
+                                "{:?} is a child of {:?} but has the parent {:?}",
+                                child, address, child_node.parent_address
+                            ));
+                        }
+                        covered += child_node.coverage_count;
+                        unvisited.push(*child);
+                    }
+                }
+            }
+            if node.coverage_count < covered {
+                issues.push(format!(
+                    "{:?} covers {} points but its children and singletons cover {}",
+                    address, node.coverage_count, covered
+                ));
+            }
+        }
+        if reached < self.nodes.len() {
+            issues.push(format!(
+                "{} nodes can't be reached from the root",
+                self.nodes.len() - reached
+            ));
+        }
+        issues
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::covertree::tests::build_basic_tree;
+    use tempdir::TempDir;
+
+    #[test]
+    fn bundles_round_trip_and_find_broken_links() {
+        let tree = build_basic_tree();
+        let bundle = DebugBundle::new(&tree);
+        assert_eq!(bundle.point_count, 5);
+        assert_eq!(bundle.parameters.scale_base, Some(2.0));
+        assert!(bundle.structural_issues().is_empty());
+
+        let dir = TempDir::new("goko_debug_bundle").unwrap();
+        let path = dir.path().join("bundle.json.gz");
+        bundle.save(&path).unwrap();
+        let loaded = DebugBundle::load(&path).unwrap();
+        assert_eq!(loaded, bundle);
+
+        let mut broken = loaded.clone();
+        let leaf = broken
+            .nodes
+            .iter()
+            .position(|n| n.nested_scale.is_none() && n.parent_address.is_some())
+            .unwrap();
+        broken.nodes.remove(leaf);
+        assert!(!broken.structural_issues().is_empty());
+    }
+}
@@ -20,8 +20,10 @@
 //! The errors that can occor when a cover tree is loading, working or saving.
 //! Most errors are floated up from `PointCloud` as that's the i/o layer.
 
+use crate::NodeAddress;
 use pointcloud::pc_errors::PointCloudError;
 use protobuf::ProtobufError;
+use rayon::ThreadPoolBuildError;
 use std::error::Error;
 use std::fmt;
 use std::io;
@@ -47,6 +49,59 @@ pub enum GokoError {
     DoubleNest,
     /// Inserted a node before you changed it from a leaf node into a normal node. Insert the nested child first.
     InsertBeforeNest,
+    /// The node address doesn't reference a node in the tree
+    NodeNotInTree(NodeAddress),
+    /// The requested edit would leave the tree in an invalid state
+    InvalidTreeEdit(&'static str),
+    /// The build ran past the builder's memory limit and stopped early
+    MemoryLimitReached {
+        /// The limit, in bytes, that was set on the builder
+        limit: usize,
+        /// The number of points that made it into the partial tree
+        indexed_points: usize,
+    },
+    /// The build's cancel token was cancelled before the build finished
+    BuildCancelled,
+    /// Rayon could not start the threads for a shared pool
+    ThreadPoolError(ThreadPoolBuildError),
+    /// There wasn't one label for every point in the tree
+    LabelCountMismatch {
+        /// The number of points in the tree
+        points: usize,
+        /// The number of labels given
+        labels: usize,
+    },
+    /// There wasn't one event id for every event in a batch
+    EventIdCountMismatch {
+        /// The number of events in the batch
+        events: usize,
+        /// The number of ids given
+        ids: usize,
+    },
+    /// Cross validation needs at least 2 folds and at least one point per fold
+    InvalidFoldCount {
+        /// The number of folds asked for
+        folds: usize,
+        /// The number of points to split into folds
+        points: usize,
+    },
+    /// The metric returned NaN, most likely the data has missing values and no NaN policy
+    NanDistance {
+        /// The center the distance was measured from
+        center: usize,
+        /// The point the distance was measured to
+        point: usize,
+    },
+    /// No point in the tree's point cloud has this partition key, or the cloud has no partitions
+    UnknownPartition(String),
+    /// A query was given a parameter it can't use
+    InvalidQuery(&'static str),
+    /// A saved tree doesn't fit the point cloud it was loaded with
+    SavedTreeMismatch(&'static str),
+    /// A saved tree has a plugin that isn't on the registry it was loaded with
+    UnknownPlugin(String),
+    /// A plugin's saved parameters couldn't be written or read back
+    PluginStateError(String),
 }
 
 impl fmt::Display for GokoError {
@@ -55,6 +110,7 @@ impl fmt::Display for GokoError {
             GokoError::PointCloudError(ref e) => write!(f, "{}", e),
             GokoError::ProtobufError(ref e) => write!(f, "{}", e),
             GokoError::IoError(ref e) => write!(f, "{}", e),
+            GokoError::ThreadPoolError(ref e) => write!(f, "{}", e),
             GokoError::IndexNotInTree { .. } => {
                 write!(f, "there was an issue grabbing a name from the known names")
             }
@@ -70,6 +126,52 @@ impl fmt::Display for GokoError {
                 f,
                 "Inserted a node into a node that does not have a nested child"
             ),
+            GokoError::NodeNotInTree(address) => {
+                write!(f, "the node {:?} is not in the tree", address)
+            }
+            GokoError::InvalidTreeEdit(reason) => write!(f, "invalid tree edit: {}", reason),
+            GokoError::MemoryLimitReached {
+                limit,
+                indexed_points,
+            } => write!(
+                f,
+                "the build reached the memory limit of {} bytes after indexing {} points",
+                limit, indexed_points
+            ),
+            GokoError::BuildCancelled => write!(f, "the build was cancelled"),
+            GokoError::LabelCountMismatch { points, labels } => write!(
+                f,
+                "there are {} points in the tree but {} labels were given",
+                points, labels
+            ),
+            GokoError::EventIdCountMismatch { events, ids } => write!(
+                f,
+                "there are {} events in the batch but {} ids were given",
+                events, ids
+            ),
+            GokoError::InvalidFoldCount { folds, points } => write!(
+                f,
+                "can't split {} points into {} cross validation folds",
+                points, folds
+            ),
+            GokoError::NanDistance { center, point } => write!(
+                f,
+                "the distance between points {} and {} is NaN, load the data with a NaN policy",
+                center, point
+            ),
+            GokoError::UnknownPartition(ref key) => {
+                write!(f, "no point is in the partition {:?}", key)
+            }
+            GokoError::InvalidQuery(reason) => write!(f, "invalid query: {}", reason),
+            GokoError::SavedTreeMismatch(reason) => {
+                write!(f, "the saved tree doesn't match the point cloud: {}", reason)
+            }
+            GokoError::UnknownPlugin(ref name) => {
+                write!(f, "the saved tree has the unregistered plugin {:?}", name)
+            }
+            GokoError::PluginStateError(ref e) => {
+                write!(f, "couldn't save or load a plugin's parameters: {}", e)
+            }
         }
     }
 }
@@ -81,6 +183,7 @@ impl Error for GokoError {
             GokoError::PointCloudError(ref e) => e.description(),
             GokoError::ProtobufError(ref e) => e.description(),
             GokoError::IoError(ref e) => e.description(),
+            GokoError::ThreadPoolError(ref e) => e.description(),
             GokoError::IndexNotInTree { .. } => {
                 "there was an issue grabbing a name from the known names"
             }
@@ -93,6 +196,27 @@ impl Error for GokoError {
             GokoError::InvalidProbDistro => {
                 "The probability distribution you are trying to sample from is invalid, probably because it was infered from 0 points."
             }
+            GokoError::NodeNotInTree { .. } => "the node is not in the tree",
+            GokoError::InvalidTreeEdit(reason) => reason,
+            GokoError::MemoryLimitReached { .. } => {
+                "the build reached the memory limit and stopped early"
+            }
+            GokoError::BuildCancelled => "the build was cancelled",
+            GokoError::LabelCountMismatch { .. } => {
+                "there wasn't one label for every point in the tree"
+            }
+            GokoError::EventIdCountMismatch { .. } => {
+                "there wasn't one id for every event in the batch"
+            }
+            GokoError::InvalidFoldCount { .. } => {
+                "there were too few points or folds for cross validation"
+            }
+            GokoError::NanDistance { .. } => "the metric returned NaN",
+            GokoError::UnknownPartition(..) => "no point is in the partition",
+            GokoError::InvalidQuery(reason) => reason,
+            GokoError::SavedTreeMismatch(reason) => reason,
+            GokoError::UnknownPlugin(..) => "the saved tree has an unregistered plugin",
+            GokoError::PluginStateError(..) => "couldn't save or load a plugin's parameters",
         }
     }
 
@@ -101,10 +225,24 @@ impl Error for GokoError {
             GokoError::PointCloudError(ref e) => Some(e),
             GokoError::ProtobufError(ref e) => Some(e),
             GokoError::IoError(ref e) => Some(e),
+            GokoError::ThreadPoolError(ref e) => Some(e),
             GokoError::IndexNotInTree { .. } => None,
             GokoError::DoubleNest => None,
             GokoError::InsertBeforeNest => None,
             GokoError::InvalidProbDistro => None,
+            GokoError::NodeNotInTree { .. } => None,
+            GokoError::InvalidTreeEdit(..) => None,
+            GokoError::MemoryLimitReached { .. } => None,
+            GokoError::BuildCancelled => None,
+            GokoError::LabelCountMismatch { .. } => None,
+            GokoError::EventIdCountMismatch { .. } => None,
+            GokoError::InvalidFoldCount { .. } => None,
+            GokoError::NanDistance { .. } => None,
+            GokoError::UnknownPartition(..) => None,
+            GokoError::InvalidQuery(..) => None,
+            GokoError::SavedTreeMismatch(..) => None,
+            GokoError::UnknownPlugin(..) => None,
+            GokoError::PluginStateError(..) => None,
         }
     }
 }
@@ -126,3 +264,9 @@ impl From<io::Error> for GokoError {
         GokoError::IoError(err)
     }
 }
+
+impl From<ThreadPoolBuildError> for GokoError {
+    fn from(err: ThreadPoolBuildError) -> Self {
+        GokoError::ThreadPoolError(err)
+    }
+}
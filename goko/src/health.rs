@@ -0,0 +1,186 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! Health reports for serving a tree.
+//!
+//! Every tree carries a [`PublishLog`] in its parameters. Each time the writer publishes its edits
+//! to the readers, with [`CoverTreeWriter::refresh`](crate::CoverTreeWriter::refresh), the tree's
+//! generation goes up by one. Plugins remember the generation they were last computed at, so a
+//! plugin that wasn't rebuilt after an edit shows up as stale. Plugins with node components are
+//! rebuilt on the edited nodes, plugins that only keep state for the whole tree, like the
+//! [`DistanceCalibration`](crate::plugins::calibration::DistanceCalibration), stay stale until
+//! they're attached again.
+//!
+//! [`CoverTreeReader::health`](crate::CoverTreeReader::health) puts this together with the size of
+//! the tree into a [`TreeHealth`], which is what a readiness check should gate traffic on.
+
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Debug)]
+struct PluginGeneration {
+    name: String,
+    generation: u64,
+    rebuilt_by_edits: bool,
+}
+
+/// The publishes of a tree, and the generation each plugin was last computed at.
+#[derive(Debug)]
+pub struct PublishLog {
+    generation: AtomicU64,
+    last_publish: Mutex<Option<SystemTime>>,
+    plugins: Mutex<Vec<PluginGeneration>>,
+}
+
+impl Default for PublishLog {
+    fn default() -> Self {
+        PublishLog::new()
+    }
+}
+
+impl PublishLog {
+    /// A log for a tree that hasn't been published yet.
+    pub fn new() -> PublishLog {
+        PublishLog {
+            generation: AtomicU64::new(0),
+            last_publish: Mutex::new(None),
+            plugins: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// How many times the tree has been published to its readers.
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    /// When the tree was last published, `None` if it never was.
+    pub fn last_publish(&self) -> Option<SystemTime> {
+        *self.last_publish.lock().unwrap()
+    }
+
+    /// The attached plugins, in the order they were first attached.
+    pub fn plugins(&self) -> Vec<PluginHealth> {
+        let generation = self.generation();
+        self.plugins
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|p| PluginHealth {
+                name: p.name.clone(),
+                generation: p.generation,
+                stale: p.generation < generation,
+            })
+            .collect()
+    }
+
+    pub(crate) fn published(&self) {
+        let mut last_publish = self.last_publish.lock().unwrap();
+        self.generation.fetch_add(1, Ordering::AcqRel);
+        *last_publish = Some(SystemTime::now());
+    }
+
+    /// Records that a plugin was computed over the whole tree. Attaching it again replaces the record.
+    pub(crate) fn plugin_computed(&self, name: &str, rebuilt_by_edits: bool) {
+        let generation = self.generation();
+        let mut plugins = self.plugins.lock().unwrap();
+        match plugins.iter_mut().find(|p| p.name == name) {
+            Some(plugin) => {
+                plugin.generation = generation;
+                plugin.rebuilt_by_edits = rebuilt_by_edits;
+            }
+            None => plugins.push(PluginGeneration {
+                name: name.to_string(),
+                generation,
+                rebuilt_by_edits,
+            }),
+        }
+    }
+
+    /// Records that the node components of the plugins were rebuilt after an edit.
+    pub(crate) fn node_plugins_rebuilt(&self) {
+        let generation = self.generation();
+        for plugin in self.plugins.lock().unwrap().iter_mut() {
+            if plugin.rebuilt_by_edits {
+                plugin.generation = generation;
+            }
+        }
+    }
+}
+
+/// The state of one plugin of the tree.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PluginHealth {
+    /// The type name of the plugin
+    pub name: String,
+    /// The generation of the tree the plugin was last computed at
+    pub generation: u64,
+    /// If the tree was published since the plugin was last computed
+    pub stale: bool,
+}
+
+/// A snapshot of the tree for health and readiness checks, see [`crate::CoverTreeReader::health`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TreeHealth {
+    /// How many times the tree has been published to its readers
+    pub generation: u64,
+    /// The number of points the tree covers
+    pub point_count: usize,
+    /// Rough estimate of the bytes the nodes and the final address map take, the same one the
+    /// build's memory limit uses. The point cloud and the plugins are not counted.
+    pub memory_estimate: usize,
+    /// Milliseconds since the unix epoch of the last publish, `None` if the tree never was
+    pub last_publish_ms: Option<u64>,
+    /// The attached plugins, in the order they were first attached
+    pub plugins: Vec<PluginHealth>,
+}
+
+impl TreeHealth {
+    pub(crate) fn new(
+        publishes: &PublishLog,
+        point_count: usize,
+        memory_estimate: usize,
+    ) -> TreeHealth {
+        TreeHealth {
+            generation: publishes.generation(),
+            point_count,
+            memory_estimate,
+            last_publish_ms: publishes.last_publish().map(|t| {
+                t.duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_millis() as u64)
+                    .unwrap_or(0)
+            }),
+            plugins: publishes.plugins(),
+        }
+    }
+
+    /// If the tree has been published, covers some points and none of its plugins are stale.
+    pub fn is_ready(&self) -> bool {
+        self.generation > 0 && self.point_count > 0 && self.stale_plugins().next().is_none()
+    }
+
+    /// The names of the plugins that weren't rebuilt since the last publish.
+    pub fn stale_plugins(&self) -> impl Iterator<Item = &str> {
+        self.plugins
+            .iter()
+            .filter(|p| p.stale)
+            .map(|p| p.name.as_str())
+    }
+}
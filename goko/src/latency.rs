@@ -0,0 +1,243 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! Latency histograms for the tree's main operations.
+//!
+//! Every tree carries a [`LatencyStats`] in its parameters, reachable from a reader with
+//! [`CoverTreeReader::latency_stats`](crate::CoverTreeReader::latency_stats). Once enabled, each
+//! `knn`, dry insert (a `path` query, where the point would go if it were inserted), tracker push,
+//! insert and `range` query records its latency in an HDR histogram, so the p50, p99 and p999 can be read without
+//! wrapping the call sites. Tracking is off by default, it costs two clock reads and a lock per
+//! operation.
+
+use hdrhistogram::Histogram;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// The operations whose latencies are tracked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Operation {
+    /// `CoverTreeReader::knn` and its variants
+    Knn,
+    /// `CoverTreeReader::path`, the path a point would be inserted along
+    DryInsert,
+    /// Adding a path to a tracker
+    TrackerPush,
+    /// `CoverTreeWriter::extend`, timed per call
+    Insert,
+    /// `CoverTreeReader::range`
+    Range,
+}
+
+impl Operation {
+    /// All operations, in the order `LatencyStats::summaries` reports them.
+    pub const ALL: [Operation; 5] = [
+        Operation::Knn,
+        Operation::DryInsert,
+        Operation::TrackerPush,
+        Operation::Insert,
+        Operation::Range,
+    ];
+
+    /// The lower case name of the operation, `knn`, `dry_insert`, `tracker_push`, `insert` or
+    /// `range`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Operation::Knn => "knn",
+            Operation::DryInsert => "dry_insert",
+            Operation::TrackerPush => "tracker_push",
+            Operation::Insert => "insert",
+            Operation::Range => "range",
+        }
+    }
+
+    fn index(&self) -> usize {
+        match self {
+            Operation::Knn => 0,
+            Operation::DryInsert => 1,
+            Operation::TrackerPush => 2,
+            Operation::Insert => 3,
+            Operation::Range => 4,
+        }
+    }
+}
+
+/// The latency percentiles of an operation, in nanoseconds.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct LatencySummary {
+    /// The number of recorded operations
+    pub count: u64,
+    /// The mean latency
+    pub mean: f64,
+    /// The median latency
+    pub p50: u64,
+    /// The 99th percentile
+    pub p99: u64,
+    /// The 99.9th percentile
+    pub p999: u64,
+    /// The slowest recorded operation
+    pub max: u64,
+}
+
+/// Per operation latency histograms. They're shared by every reader and writer of a tree.
+#[derive(Debug)]
+pub struct LatencyStats {
+    enabled: AtomicBool,
+    histograms: Vec<Mutex<Histogram<u64>>>,
+}
+
+impl Default for LatencyStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LatencyStats {
+    /// Disabled histograms that track from a nanosecond to an hour with 3 significant digits.
+    pub fn new() -> LatencyStats {
+        let histograms = Operation::ALL
+            .iter()
+            .map(|_| Mutex::new(Histogram::new_with_bounds(1, 3_600_000_000_000, 3).unwrap()))
+            .collect();
+        LatencyStats {
+            enabled: AtomicBool::new(false),
+            histograms,
+        }
+    }
+
+    /// Starts recording latencies.
+    pub fn enable(&self) {
+        self.enabled.store(true, Ordering::Relaxed);
+    }
+
+    /// Stops recording latencies, what has been recorded is kept.
+    pub fn disable(&self) {
+        self.enabled.store(false, Ordering::Relaxed);
+    }
+
+    /// If latencies are being recorded.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Call at the start of an operation and pass the result to `record` at the end. This doesn't
+    /// read the clock when tracking is disabled.
+    pub fn start(&self) -> Option<Instant> {
+        if self.is_enabled() {
+            Some(Instant::now())
+        } else {
+            None
+        }
+    }
+
+    /// Records the latency of an operation that began at `start`.
+    pub fn record(&self, op: Operation, start: Option<Instant>) {
+        if let Some(start) = start {
+            let nanos = start.elapsed().as_nanos() as u64;
+            self.histograms[op.index()]
+                .lock()
+                .unwrap()
+                .saturating_record(nanos.max(1));
+        }
+    }
+
+    /// The percentiles of one operation.
+    pub fn summary(&self, op: Operation) -> LatencySummary {
+        let histogram = self.histograms[op.index()].lock().unwrap();
+        if histogram.is_empty() {
+            return LatencySummary::default();
+        }
+        LatencySummary {
+            count: histogram.len(),
+            mean: histogram.mean(),
+            p50: histogram.value_at_quantile(0.5),
+            p99: histogram.value_at_quantile(0.99),
+            p999: histogram.value_at_quantile(0.999),
+            max: histogram.max(),
+        }
+    }
+
+    /// The percentiles of every operation.
+    pub fn summaries(&self) -> Vec<(Operation, LatencySummary)> {
+        Operation::ALL
+            .iter()
+            .map(|op| (*op, self.summary(*op)))
+            .collect()
+    }
+
+    /// Clears the histogram of one operation.
+    pub fn reset_operation(&self, op: Operation) {
+        self.histograms[op.index()].lock().unwrap().reset();
+    }
+
+    /// Clears every histogram.
+    pub fn reset(&self) {
+        for op in Operation::ALL.iter() {
+            self.reset_operation(*op);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::covertree::tests::build_basic_tree;
+
+    #[test]
+    fn disabled_records_nothing() {
+        let stats = LatencyStats::new();
+        let start = stats.start();
+        assert!(start.is_none());
+        stats.record(Operation::Knn, start);
+        assert_eq!(stats.summary(Operation::Knn).count, 0);
+    }
+
+    #[test]
+    fn records_and_resets() {
+        let writer = build_basic_tree();
+        let reader = writer.reader();
+        reader.latency_stats().enable();
+        for _ in 0..10 {
+            reader.knn(&[0.3f32].as_ref(), 2).unwrap();
+        }
+        reader.path(&[0.3f32].as_ref()).unwrap();
+        let knn = reader.latency_stats().summary(Operation::Knn);
+        assert_eq!(knn.count, 10);
+        assert!(knn.p50 <= knn.p99 && knn.p99 <= knn.p999 && knn.p999 <= knn.max);
+        assert_eq!(
+            reader.latency_stats().summary(Operation::DryInsert).count,
+            1
+        );
+        assert_eq!(reader.latency_stats().summary(Operation::Insert).count, 0);
+
+        reader.latency_stats().reset_operation(Operation::Knn);
+        assert_eq!(reader.latency_stats().summary(Operation::Knn).count, 0);
+        assert_eq!(
+            reader.latency_stats().summary(Operation::DryInsert).count,
+            1
+        );
+        reader.latency_stats().reset();
+        assert_eq!(
+            reader.latency_stats().summary(Operation::DryInsert).count,
+            0
+        );
+    }
+}
@@ -68,7 +68,16 @@ pub(crate) mod monomap;
 mod covertree;
 pub use covertree::*;
 
+pub mod build_progress;
+pub mod build_report;
+pub mod cluster_comparison;
+pub mod cluster_quality;
+pub mod debug_bundle;
+pub mod health;
+pub mod latency;
+pub mod middleware;
 pub mod query_interface;
+pub mod scheduler;
 
 mod tree_file_format;
 pub mod utils;
@@ -0,0 +1,151 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! Hooks on a reader's query path, for cross-cutting concerns like filtering out points that
+//! shouldn't be shown, reranking, or audit logs, without forking the traversal code.
+//!
+//! Middleware is added to a reader with
+//! [`CoverTreeReader::add_middleware`](crate::CoverTreeReader::add_middleware) and is carried by
+//! its clones, readers made by the writer start without any. It runs on every query that takes a
+//! point, after the point cloud's own preprocessing of the query. Each middleware's query
+//! transform is run in the order they were added, each seeing the point the last one made, then
+//! the search runs on the final point.
+//!
+//! The queries that return points, `knn` and all its variants, `range` and each query of a
+//! `knn_batch`, then put their `(distance, index)` results through each middleware's
+//! `process_results` in the same order and hand the final results to every `audit`. `path`,
+//! `path_to_scale` and `knn_to_scale` return nodes, so for those only the query is transformed.
+//! `knn_reranked` runs its candidate search through the middleware and rescores what's left.
+
+use crate::latency::Operation;
+use pointcloud::*;
+
+/// A hook on the query path, see the [module docs](self). Every method does nothing by default,
+/// implement the ones you need.
+pub trait QueryMiddleware<D: PointCloud>: Send + Sync + 'static {
+    /// Rewrites the query point before the search. `None` leaves it as it is.
+    fn transform_query(&self, _operation: Operation, _point: &D::Point) -> Option<Box<D::Point>> {
+        None
+    }
+
+    /// Filters or reorders the results, `(distance, index)` pairs closest first for a plain
+    /// query. The point is the one the search ran on. Dropping results can leave a `knn` with
+    /// fewer than `k`.
+    fn process_results(
+        &self,
+        _operation: Operation,
+        _point: &D::Point,
+        results: Vec<(f32, usize)>,
+    ) -> Vec<(f32, usize)> {
+        results
+    }
+
+    /// Sees the final results of every query that succeeded, for logging.
+    fn audit(&self, _operation: Operation, _point: &D::Point, _results: &[(f32, usize)]) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::covertree::tests::build_basic_tree;
+    use roaring::RoaringBitmap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    struct ShiftAndDropZero {
+        shift: f32,
+        audits: Arc<AtomicUsize>,
+    }
+
+    impl QueryMiddleware<DefaultLabeledCloud<L2>> for ShiftAndDropZero {
+        fn transform_query(&self, _operation: Operation, point: &[f32]) -> Option<Box<[f32]>> {
+            Some(point.iter().map(|x| x + self.shift).collect())
+        }
+
+        fn process_results(
+            &self,
+            _operation: Operation,
+            _point: &[f32],
+            mut results: Vec<(f32, usize)>,
+        ) -> Vec<(f32, usize)> {
+            results.retain(|(_, pi)| *pi != 0);
+            results
+        }
+
+        fn audit(&self, _operation: Operation, _point: &[f32], _results: &[(f32, usize)]) {
+            self.audits.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn middleware_transforms_filters_and_audits() {
+        let tree = build_basic_tree();
+        let plain = tree.reader();
+        let mut reader = tree.reader();
+        let audits = Arc::new(AtomicUsize::new(0));
+        reader.add_middleware(ShiftAndDropZero {
+            shift: 0.5,
+            audits: Arc::clone(&audits),
+        });
+
+        // The query moves to 0.5, next to points 0, 1 and 2, and point 0 is dropped
+        let found = reader.knn(&[0.0f32].as_ref(), 3).unwrap();
+        let indexes: Vec<usize> = found.iter().map(|(_, pi)| *pi).collect();
+        assert_eq!(indexes, vec![1, 2]);
+
+        let ranged = reader.clone().range(&[0.0f32].as_ref(), 0.1).unwrap();
+        let indexes: Vec<usize> = ranged.iter().map(|(_, pi)| *pi).collect();
+        assert_eq!(indexes, vec![1, 2]);
+        assert_eq!(audits.load(Ordering::SeqCst), 2);
+
+        // Every other query sees the same point and drops the same point
+        let batch = reader.knn_batch(&[[0.0f32].as_ref()], 3).unwrap();
+        assert_eq!(batch.get(0).unwrap().1, &[1, 2]);
+        let variants = vec![
+            reader.knn_approx(&[0.0f32].as_ref(), 3, 0.0).unwrap(),
+            reader.knn_within(&[0.0f32].as_ref(), 3, 0.1).unwrap(),
+            reader
+                .knn_excluding(&[0.0f32].as_ref(), 3, &RoaringBitmap::new())
+                .unwrap(),
+            reader
+                .knn_scored(&[0.0f32].as_ref(), 3, 0.0, |_| 1.0)
+                .unwrap(),
+            reader
+                .knn_where(&[0.0f32].as_ref(), 3, |_| true, |_| true)
+                .unwrap(),
+        ];
+        for found in variants {
+            let indexes: Vec<usize> = found.iter().map(|(_, pi)| *pi).collect();
+            assert_eq!(indexes, vec![1, 2]);
+        }
+        assert_eq!(audits.load(Ordering::SeqCst), 8);
+        assert_eq!(
+            reader.path(&[0.0f32].as_ref()).unwrap(),
+            plain.path(&[0.5f32].as_ref()).unwrap()
+        );
+        assert_eq!(audits.load(Ordering::SeqCst), 8);
+
+        reader.clear_middleware();
+        assert_eq!(
+            reader.knn(&[0.0f32].as_ref(), 3).unwrap(),
+            plain.knn(&[0.0f32].as_ref(), 3).unwrap()
+        );
+        assert_eq!(audits.load(Ordering::SeqCst), 8);
+    }
+}
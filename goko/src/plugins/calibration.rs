@@ -0,0 +1,401 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! # Distance calibration
+//!
+//! A kNN distance means nothing on its own, 0.3 is close for one dataset and far for another, so
+//! a threshold tuned on one tree doesn't carry over to the next. Calibration maps the distance to
+//! a neighbor to the probability that the neighbor has the same label as the query. It's fit on a
+//! labeled holdout set: every holdout point's `k` nearest neighbors in the tree give a sample of
+//! a distance and whether the labels matched.
+//!
+//! The calibration is attached to the tree with `add_plugin`, like any other plugin, and is then
+//! used by [`CoverTreeReader::knn_probabilities`] and [`CoverTreeReader::predict`].
+
+use super::*;
+use crate::errors::{GokoError, GokoResult};
+use serde::{Deserialize, Serialize};
+use std::ops::Deref;
+
+/// How the distances are mapped to probabilities.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CalibrationMethod {
+    /// Platt scaling, a sigmoid in the distance. It needs little data and is smooth, but it
+    /// assumes the probability falls off like a sigmoid.
+    Platt,
+    /// Isotonic regression, the best fitting probability that never increases with the distance.
+    /// It makes no assumption about the shape but needs a larger holdout.
+    Isotonic,
+}
+
+/// A map from the distance to a neighbor to the probability that it has the query's label.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum DistanceCalibration {
+    /// `1 / (1 + exp(a * distance + b))`
+    Platt {
+        /// The slope, positive when closer neighbors are more likely to match
+        a: f64,
+        /// The offset
+        b: f64,
+    },
+    /// A step function, the probability is `probabilities[i]` for distances up to `distances[i]`
+    /// and the last probability past the end.
+    Isotonic {
+        /// The upper end of each step, ascending
+        distances: Vec<f32>,
+        /// The probability of each step, non-increasing
+        probabilities: Vec<f32>,
+    },
+}
+
+impl DistanceCalibration {
+    /// Fits the calibration on the tree's neighbors of a labeled holdout set. The holdout points
+    /// shouldn't be in the tree, or they'll be their own nearest neighbors.
+    pub fn fit<D, H>(
+        reader: &CoverTreeReader<D>,
+        holdout: &H,
+        k: usize,
+        method: CalibrationMethod,
+    ) -> GokoResult<DistanceCalibration>
+    where
+        D: PointCloud,
+        D::Label: PartialEq,
+        H: PointCloud<Point = D::Point, Label = D::Label>,
+    {
+        let point_cloud = reader.point_cloud();
+        let mut samples = Vec::new();
+        for hi in holdout.reference_indexes() {
+            let label = match holdout.label(hi)? {
+                Some(label) => label,
+                None => continue,
+            };
+            let point = holdout.point(hi)?;
+            for (dist, pi) in reader.knn(&point, k)? {
+                if let Some(neighbor_label) = point_cloud.label(pi)? {
+                    samples.push((dist, neighbor_label == label));
+                }
+            }
+        }
+        DistanceCalibration::fit_samples(&samples, method)
+    }
+
+    /// Fits the calibration on pairs of a distance and whether the labels matched.
+    pub fn fit_samples(
+        samples: &[(f32, bool)],
+        method: CalibrationMethod,
+    ) -> GokoResult<DistanceCalibration> {
+        if samples.is_empty() {
+            return Err(GokoError::InvalidQuery(
+                "calibration needs at least one labeled neighbor of a holdout point",
+            ));
+        }
+        Ok(match method {
+            CalibrationMethod::Platt => {
+                let (a, b) = fit_platt(samples);
+                DistanceCalibration::Platt { a, b }
+            }
+            CalibrationMethod::Isotonic => {
+                let (distances, probabilities) = fit_isotonic(samples);
+                DistanceCalibration::Isotonic {
+                    distances,
+                    probabilities,
+                }
+            }
+        })
+    }
+
+    /// The probability that a neighbor at this distance has the query's label.
+    pub fn probability(&self, distance: f32) -> f32 {
+        match self {
+            DistanceCalibration::Platt { a, b } => {
+                (1.0 / (1.0 + (a * distance as f64 + b).exp())) as f32
+            }
+            DistanceCalibration::Isotonic {
+                distances,
+                probabilities,
+            } => {
+                let step = distances
+                    .partition_point(|d| *d < distance)
+                    .min(probabilities.len() - 1);
+                probabilities[step]
+            }
+        }
+    }
+}
+
+impl<D: PointCloud> PersistentPlugin<D> for DistanceCalibration {
+    const NAME: &'static str = "calibration";
+}
+
+/// The calibration is stored once for the tree, the nodes get nothing.
+impl<D: PointCloud> GokoPlugin<D> for DistanceCalibration {
+    type NodeComponent = ();
+    fn node_component(
+        _parameters: &Self,
+        _my_node: &CoverNode<D>,
+        _my_tree: &CoverTreeReader<D>,
+    ) -> Option<Self::NodeComponent> {
+        None
+    }
+}
+
+/// Platt's sigmoid fit by Newton's method with a backtracking line search, following Lin, Lin &
+/// Weng, "A note on Platt's probabilistic outputs for support vector machines". The targets are
+/// smoothed so a perfectly separated holdout doesn't send the slope to infinity.
+fn fit_platt(samples: &[(f32, bool)]) -> (f64, f64) {
+    let matches = samples.iter().filter(|(_, m)| *m).count() as f64;
+    let misses = samples.len() as f64 - matches;
+    let hi_target = (matches + 1.0) / (matches + 2.0);
+    let lo_target = 1.0 / (misses + 2.0);
+    let samples: Vec<(f64, f64)> = samples
+        .iter()
+        .map(|(d, m)| (*d as f64, if *m { hi_target } else { lo_target }))
+        .collect();
+    let objective = |a: f64, b: f64| -> f64 {
+        samples
+            .iter()
+            .map(|(d, t)| {
+                let f = d * a + b;
+                if f >= 0.0 {
+                    t * f + (-f).exp().ln_1p()
+                } else {
+                    (t - 1.0) * f + f.exp().ln_1p()
+                }
+            })
+            .sum()
+    };
+
+    let mut a = 0.0;
+    let mut b = ((misses + 1.0) / (matches + 1.0)).ln();
+    let mut value = objective(a, b);
+    for _ in 0..100 {
+        let (mut h11, mut h22, mut h21, mut g1, mut g2) = (1e-12, 1e-12, 0.0, 0.0, 0.0);
+        for (d, t) in &samples {
+            let f = d * a + b;
+            let (p, q) = if f >= 0.0 {
+                ((-f).exp() / (1.0 + (-f).exp()), 1.0 / (1.0 + (-f).exp()))
+            } else {
+                (1.0 / (1.0 + f.exp()), f.exp() / (1.0 + f.exp()))
+            };
+            h11 += d * d * p * q;
+            h22 += p * q;
+            h21 += d * p * q;
+            g1 += d * (t - p);
+            g2 += t - p;
+        }
+        if g1.abs() < 1e-5 && g2.abs() < 1e-5 {
+            break;
+        }
+        let det = h11 * h22 - h21 * h21;
+        let da = -(h22 * g1 - h21 * g2) / det;
+        let db = -(-h21 * g1 + h11 * g2) / det;
+        let descent = g1 * da + g2 * db;
+        let mut step = 1.0;
+        while step >= 1e-10 {
+            let candidate = objective(a + step * da, b + step * db);
+            if candidate < value + 1e-4 * step * descent {
+                a += step * da;
+                b += step * db;
+                value = candidate;
+                break;
+            }
+            step /= 2.0;
+        }
+        if step < 1e-10 {
+            break;
+        }
+    }
+    (a, b)
+}
+
+/// Pool adjacent violators for a non-increasing fit. Ties in distance are sorted misses first
+/// so they always end up in the same step, and neighboring steps with the same probability are
+/// merged.
+fn fit_isotonic(samples: &[(f32, bool)]) -> (Vec<f32>, Vec<f32>) {
+    let mut sorted = samples.to_vec();
+    sorted.sort_by(|a, b| {
+        a.0.partial_cmp(&b.0)
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then(a.1.cmp(&b.1))
+    });
+    // (matches, count, largest distance) of each step
+    let mut steps: Vec<(f32, f32, f32)> = Vec::new();
+    for (d, m) in sorted {
+        steps.push((if m { 1.0 } else { 0.0 }, 1.0, d));
+        while steps.len() > 1 {
+            let (m1, c1, _) = steps[steps.len() - 2];
+            let (m2, c2, d2) = steps[steps.len() - 1];
+            if m1 / c1 > m2 / c2 {
+                break;
+            }
+            steps.pop();
+            *steps.last_mut().unwrap() = (m1 + m2, c1 + c2, d2);
+        }
+    }
+    steps.iter().map(|(m, c, d)| (*d, m / c)).unzip()
+}
+
+impl<D: PointCloud> CoverTreeReader<D> {
+    /// The `k` nearest neighbors with the probability that each has the query's label in place of
+    /// the distance, from the [`DistanceCalibration`] attached to the tree. Errors if there isn't
+    /// one.
+    pub fn knn_probabilities<P: Deref<Target = D::Point> + Send + Sync>(
+        &self,
+        point: &P,
+        k: usize,
+    ) -> GokoResult<Vec<(f32, usize)>> {
+        let knn = self.knn(point, k)?;
+        self.get_plugin_and(|calibration: &DistanceCalibration| {
+            knn.iter()
+                .map(|(d, pi)| (calibration.probability(*d), *pi))
+                .collect()
+        })
+        .ok_or(GokoError::InvalidQuery(
+            "the tree has no distance calibration, attach one with add_plugin",
+        ))
+    }
+
+    /// Predicts the label of the point by a vote of its `k` nearest neighbors, each weighted by
+    /// its calibrated probability of sharing the query's label. Returns the winning label and its
+    /// share of the votes, `None` if no neighbor has a label. Errors if the tree has no
+    /// [`DistanceCalibration`].
+    pub fn predict<P: Deref<Target = D::Point> + Send + Sync>(
+        &self,
+        point: &P,
+        k: usize,
+    ) -> GokoResult<Option<(&D::Label, f32)>>
+    where
+        D::Label: PartialEq,
+    {
+        let point_cloud = self.point_cloud();
+        let mut votes: Vec<(&D::Label, f32)> = Vec::new();
+        let mut total = 0.0;
+        for (probability, pi) in self.knn_probabilities(point, k)? {
+            let label = match point_cloud.label(pi)? {
+                Some(label) => label,
+                None => continue,
+            };
+            total += probability;
+            match votes.iter_mut().find(|(l, _)| *l == label) {
+                Some((_, weight)) => *weight += probability,
+                None => votes.push((label, probability)),
+            }
+        }
+        let best =
+            votes
+                .into_iter()
+                .fold(
+                    None,
+                    |best: Option<(&D::Label, f32)>, (label, weight)| match best {
+                        Some((_, best_weight)) if best_weight >= weight => best,
+                        _ => Some((label, weight)),
+                    },
+                );
+        Ok(best.map(|(label, weight)| (label, if total > 0.0 { weight / total } else { 0.0 })))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CoverTreeBuilder;
+    use pointcloud::*;
+    use std::sync::Arc;
+
+    /// Class 0 on `[0, 1)` and class 1 on `[2, 3)`.
+    fn two_classes(n: usize) -> DefaultLabeledCloud<L2> {
+        let data: Vec<f32> = (0..n)
+            .map(|i| ((i * 7919) % 1009) as f32 / 1009.0 + (2 * (i % 2)) as f32)
+            .collect();
+        let labels = (0..n).map(|i| (i % 2) as i64).collect();
+        DefaultLabeledCloud::<L2>::new_simple(data, 1, labels)
+    }
+
+    /// Spread over `[0, 3)`, labeled by the closer class, so the far neighbors of the points in
+    /// the gap are a mix of both.
+    fn holdout(n: usize) -> DefaultLabeledCloud<L2> {
+        let data: Vec<f32> = (0..n)
+            .map(|i| 3.0 * ((i * 613) % 1013) as f32 / 1013.0)
+            .collect();
+        let labels = data.iter().map(|x| (*x >= 1.5) as i64).collect();
+        DefaultLabeledCloud::<L2>::new_simple(data, 1, labels)
+    }
+
+    #[test]
+    fn calibrated_probabilities_fall_with_distance() {
+        let mut builder = CoverTreeBuilder::new();
+        builder.set_leaf_cutoff(5).set_rng_seed(0);
+        let mut tree = builder.build(Arc::new(two_classes(400))).unwrap();
+        let holdout = holdout(300);
+
+        for method in [CalibrationMethod::Platt, CalibrationMethod::Isotonic].iter() {
+            let calibration =
+                DistanceCalibration::fit(&tree.reader(), &holdout, 20, *method).unwrap();
+            let near = calibration.probability(0.001);
+            let far = calibration.probability(1.5);
+            assert!(near > 0.5 && far < near, "{:?}: {} {}", method, near, far);
+            let mut previous = 1.0;
+            for i in 0..100 {
+                let p = calibration.probability(i as f32 * 0.02);
+                assert!((0.0..=1.0).contains(&p));
+                assert!(p <= previous + 1e-6);
+                previous = p;
+            }
+        }
+
+        let in_class_0: &[f32] = &[0.5];
+        let in_class_1: &[f32] = &[2.5];
+        let reader = tree.reader();
+        assert!(reader.predict(&in_class_0, 10).is_err());
+        let calibration =
+            DistanceCalibration::fit(&reader, &holdout, 20, CalibrationMethod::Isotonic).unwrap();
+        tree.add_plugin::<DistanceCalibration>(calibration);
+        let reader = tree.reader();
+        let probabilities = reader.knn_probabilities(&in_class_0, 10).unwrap();
+        assert_eq!(probabilities.len(), 10);
+        let (label, share) = reader.predict(&in_class_0, 10).unwrap().unwrap();
+        assert_eq!(*label, 0);
+        assert_approx_eq!(share, 1.0);
+        let (label, _) = reader.predict(&in_class_1, 10).unwrap().unwrap();
+        assert_eq!(*label, 1);
+    }
+
+    #[test]
+    fn isotonic_pools_violators() {
+        let samples = [
+            (0.1, true),
+            (0.2, false),
+            (0.3, true),
+            (0.4, false),
+            (0.5, false),
+        ];
+        let calibration =
+            DistanceCalibration::fit_samples(&samples, CalibrationMethod::Isotonic).unwrap();
+        assert_eq!(
+            calibration,
+            DistanceCalibration::Isotonic {
+                distances: vec![0.1, 0.3, 0.5],
+                probabilities: vec![1.0, 0.5, 0.0],
+            }
+        );
+        assert_eq!(calibration.probability(0.25), 0.5);
+        assert_eq!(calibration.probability(9.0), 0.0);
+        assert!(DistanceCalibration::fit_samples(&[], CalibrationMethod::Platt).is_err());
+    }
+}
@@ -0,0 +1,175 @@
+//! # Coverage drift
+//!
+//! Trackers watch how a stream of queries compares to the training distribution, but they can't
+//! see the tree itself going stale. When points are inserted, removed or the tree is edited, a
+//! node's population can move far from what it was built with. This plugin records every node's
+//! coverage count when it's attached, and [`CoverageDriftAlarm`] compares the current counts to
+//! that baseline and flags the nodes whose population changed by more than a factor.
+//!
+//! Nodes created after the plugin was attached get a baseline of their coverage when they were made.
+
+use super::*;
+use crate::covertree::node::CoverNode;
+use crate::covertree::CoverTreeReader;
+
+/// The coverage count of a node when its baseline was taken.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CoverageBaseline {
+    /// The number of points the node covered
+    pub coverage_count: usize,
+}
+
+impl<D: PointCloud> NodePlugin<D> for CoverageBaseline {}
+
+/// Records the coverage count of each node as its baseline. Attach it right after building.
+#[derive(Debug, Clone, Default)]
+pub struct CoverageDriftPlugin {}
+
+impl<D: PointCloud> GokoPlugin<D> for CoverageDriftPlugin {
+    type NodeComponent = CoverageBaseline;
+    fn node_component(
+        _parameters: &Self,
+        my_node: &CoverNode<D>,
+        _my_tree: &CoverTreeReader<D>,
+    ) -> Option<Self::NodeComponent> {
+        // Edits recompute the plugins of the nodes they touch, keep the old baseline when there is one
+        let baseline = my_node
+            .get_plugin_and::<CoverageBaseline, _, _>(|b| *b)
+            .unwrap_or_else(|| CoverageBaseline {
+                coverage_count: my_node.coverage_count(),
+            });
+        Some(baseline)
+    }
+}
+
+/// A node whose population moved too far from its baseline.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CoverageDrift {
+    /// The node that drifted
+    pub address: NodeAddress,
+    /// The coverage count when the baseline was taken
+    pub baseline_count: usize,
+    /// The coverage count now
+    pub current_count: usize,
+    /// `current_count / baseline_count`, this is infinite if the baseline was empty
+    pub ratio: f32,
+}
+
+/// Checks the tree's coverage counts against the baseline set by [`CoverageDriftPlugin`].
+#[derive(Debug, Clone)]
+pub struct CoverageDriftAlarm {
+    /// Nodes that grew or shrank by more than this factor are flagged. Should be more than 1.
+    pub factor: f32,
+    /// Nodes that covered fewer than this many points both then and now are skipped, as small
+    /// nodes drift by large factors from a handful of points.
+    pub min_coverage: usize,
+}
+
+impl Default for CoverageDriftAlarm {
+    fn default() -> Self {
+        CoverageDriftAlarm {
+            factor: 2.0,
+            min_coverage: 10,
+        }
+    }
+}
+
+impl CoverageDriftAlarm {
+    /// Creates an alarm that flags nodes that changed by more than the given factor.
+    pub fn new(factor: f32) -> Self {
+        CoverageDriftAlarm {
+            factor,
+            ..Default::default()
+        }
+    }
+
+    /// Sets the coverage below which nodes are skipped.
+    pub fn set_min_coverage(&mut self, min_coverage: usize) -> &mut Self {
+        self.min_coverage = min_coverage;
+        self
+    }
+
+    /// Checks one node, returns `None` if it has no baseline or didn't drift too far.
+    pub fn check_node<D: PointCloud>(&self, node: &CoverNode<D>) -> Option<CoverageDrift> {
+        let baseline_count = node.get_plugin_and::<CoverageBaseline, _, _>(|b| b.coverage_count)?;
+        let current_count = node.coverage_count();
+        if baseline_count.max(current_count) < self.min_coverage {
+            return None;
+        }
+        let ratio = current_count as f32 / baseline_count as f32;
+        if ratio > self.factor || ratio * self.factor < 1.0 {
+            Some(CoverageDrift {
+                address: (*node.scale_index(), *node.center_index()),
+                baseline_count,
+                current_count,
+                ratio,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Checks every node in the tree, from the top layer down. Returns an empty list if the
+    /// plugin isn't attached.
+    pub fn check<D: PointCloud>(&self, reader: &CoverTreeReader<D>) -> Vec<CoverageDrift> {
+        let mut drifted = Vec::new();
+        for (_, layer) in reader.layers() {
+            layer.for_each_node(|_, n| {
+                if let Some(drift) = self.check_node(n) {
+                    drifted.push(drift);
+                }
+            });
+        }
+        drifted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::covertree::tests::build_basic_tree;
+
+    #[test]
+    fn fresh_tree_has_no_drift() {
+        let mut tree = build_basic_tree();
+        tree.add_plugin::<CoverageDriftPlugin>(CoverageDriftPlugin::default());
+        let reader = tree.reader();
+        let mut alarm = CoverageDriftAlarm::new(1.5);
+        alarm.set_min_coverage(0);
+        assert!(alarm.check(&reader).is_empty());
+        let root_baseline = reader
+            .get_node_plugin_and::<CoverageBaseline, _, _>(reader.root_address(), |b| *b)
+            .unwrap();
+        assert_eq!(
+            root_baseline.coverage_count,
+            reader.parameters().point_cloud.len()
+        );
+    }
+
+    #[test]
+    fn changed_coverage_is_flagged() {
+        let mut tree = build_basic_tree();
+        tree.add_plugin::<CoverageDriftPlugin>(CoverageDriftPlugin::default());
+        let root_address = tree.reader().root_address();
+        let root_count = tree.reader().parameters().point_cloud.len();
+        unsafe {
+            tree.update_node(root_address, move |n| n.set_coverage_count(4 * root_count));
+        }
+        tree.refresh();
+        // The plugin is recomputed when nodes are edited, and keeps the old baseline
+        tree.recompute_node_plugins(vec![root_address]);
+
+        let reader = tree.reader();
+        let mut alarm = CoverageDriftAlarm::new(2.0);
+        alarm.set_min_coverage(0);
+        let drifted = alarm.check(&reader);
+        assert_eq!(drifted.len(), 1);
+        assert_eq!(drifted[0].address, root_address);
+        assert_eq!(drifted[0].baseline_count, root_count);
+        assert_eq!(drifted[0].current_count, 4 * root_count);
+        assert_approx_eq!(drifted[0].ratio, 4.0);
+
+        alarm.set_min_coverage(5 * root_count);
+        assert!(alarm.check(&reader).is_empty());
+    }
+}
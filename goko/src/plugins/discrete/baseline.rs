@@ -1,5 +1,6 @@
 //! See the paper for how this works
 
+use crate::errors::GokoError;
 use crate::plugins::discrete::tracker::*;
 use crate::*;
 use rand::prelude::*;
@@ -8,12 +9,16 @@ use rayon::iter::repeatn;
 
 /// Trains a baseline by sampling randomly from the training set (used to create the tree)
 /// This baseline is _not_ realistic.
+///
+/// With a partition set the sequences are sampled from that partition's points only, and the
+/// trackers use that partition's prior, see [`BayesCategoricalTracker::set_partition`].
 pub struct DirichletBaseline {
     sample_rate: usize,
     sequence_len: usize,
     num_sequences: usize,
     prior_weight: f64,
     observation_weight: f64,
+    partition: Option<String>,
 }
 
 impl Default for DirichletBaseline {
@@ -24,6 +29,7 @@ impl Default for DirichletBaseline {
             num_sequences: 8,
             prior_weight: 1.0,
             observation_weight: 1.0,
+            partition: None,
         }
     }
 }
@@ -54,12 +60,28 @@ impl DirichletBaseline {
         self.sample_rate = sample_rate;
     }
 
+    /// Trains on one partition's points, `None` for all of them.
+    pub fn set_partition(&mut self, partition: Option<String>) {
+        self.partition = partition;
+    }
+
     /// Trains the sequences up.
     pub fn train<D: PointCloud>(
         &self,
         reader: CoverTreeReader<D>,
     ) -> GokoResult<KLDivergenceBaseline> {
-        let point_indexes = reader.point_cloud().reference_indexes();
+        let point_cloud = reader.point_cloud();
+        let point_indexes: Vec<usize> = match &self.partition {
+            Some(partition) => point_cloud
+                .partitions()
+                .and_then(|partitions| partitions.members(partitions.id(partition)?))
+                .ok_or_else(|| GokoError::UnknownPartition(partition.clone()))?
+                .iter()
+                .map(|pi| pi as usize)
+                .filter(|pi| !point_cloud.is_deleted(*pi))
+                .collect(),
+            None => point_cloud.reference_indexes(),
+        };
         let sequence_len = if self.sequence_len == 0 {
             point_indexes.len()
         } else {
@@ -67,12 +89,14 @@ impl DirichletBaseline {
         };
 
         let results: Vec<Vec<KLDivergenceStats>> = repeatn(reader, self.num_sequences)
-            .map(|reader| {
-                let mut tracker = BayesCategoricalTracker::new(
-                    0,
-                    reader,
-                );
-                (&point_indexes[..])
+            .map(|reader| -> GokoResult<Vec<KLDivergenceStats>> {
+                let mut tracker = BayesCategoricalTracker::new(0, reader);
+                tracker.set_prior_weight(self.prior_weight);
+                tracker.set_observation_weight(self.observation_weight);
+                if let Some(partition) = &self.partition {
+                    tracker.set_partition(partition)?;
+                }
+                Ok((&point_indexes[..])
                     .choose_multiple(&mut thread_rng(), sequence_len)
                     .enumerate()
                     .filter_map(|(i, pi)| {
@@ -83,9 +107,9 @@ impl DirichletBaseline {
                             None
                         }
                     })
-                    .collect()
+                    .collect())
             })
-            .collect();
+            .collect::<GokoResult<Vec<_>>>()?;
         let len = results[0].len();
         let mut sequence_len = Vec::with_capacity(len);
         let mut stats: Vec<KLDivergenceBaselineStats> =
@@ -9,6 +9,7 @@ use crate::plugins::*;
 
 use rand::distributions::{Distribution, Uniform};
 use rand::Rng;
+use serde::{Deserialize, Serialize};
 /// Simple probability density function for where things go by count
 /// Stored as a flat vector in the order of the node addresses.
 #[derive(Debug, Clone, Default)]
@@ -152,9 +153,13 @@ impl Categorical {
 impl<D: PointCloud> NodePlugin<D> for Categorical {}
 
 /// Zero sized type that can be passed around. Equivilant to `()`
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GokoCategorical {}
 
+impl<D: PointCloud> PersistentPlugin<D> for GokoCategorical {
+    const NAME: &'static str = "categorical";
+}
+
 /// Parent trait that make this all work. Ideally this should be included in the `TreePlugin` but rust doesn't like it.
 impl<D: PointCloud> GokoPlugin<D> for GokoCategorical {
     type NodeComponent = Categorical;
@@ -164,6 +169,7 @@ impl<D: PointCloud> GokoPlugin<D> for GokoCategorical {
         my_tree: &CoverTreeReader<D>,
     ) -> Option<Self::NodeComponent> {
         let mut bucket = Categorical::new();
+        let point_cloud = &my_tree.parameters().point_cloud;
 
         // If we're a routing node then grab the childen's values
         if let Some((nested_scale, child_addresses)) = my_node.children() {
@@ -181,9 +187,13 @@ impl<D: PointCloud> GokoPlugin<D> for GokoCategorical {
                     bucket.add_child_pop(Some(*ca), p.total() as f64);
                 });
             }
-            bucket.add_child_pop(None, my_node.singletons_len() as f64);
+            bucket.add_child_pop(None, my_node.singletons_multiplicity(point_cloud) as f64);
         } else {
-            bucket.add_child_pop(None, my_node.singletons_len() as f64 + 1.0);
+            let center_multiplicity = point_cloud.multiplicity(*my_node.center_index());
+            bucket.add_child_pop(
+                None,
+                (my_node.singletons_multiplicity(point_cloud) + center_multiplicity) as f64,
+            );
         }
         Some(bucket)
     }
@@ -20,6 +20,7 @@ use statrs::function::gamma::{digamma, ln_gamma};
 use rand::distributions::{Distribution, Uniform};
 
 use super::categorical::*;
+use serde::{Deserialize, Serialize};
 
 /// Simple probability density function for where things go by count
 ///
@@ -52,6 +53,21 @@ impl Dirichlet {
                 .fold(0.0, |x, a| x + a)
     }
 
+    /// The raw concentration parameters, the child parameters are sorted by the child's address.
+    /// The second element is the parameter for the singletons.
+    pub fn params(&self) -> (&[(NodeAddress, f64)], f64) {
+        (&self.child_counts, self.singleton_count)
+    }
+
+    /// Replaces every parameter with `f(child, parameter)`, in the same order as `params`. The
+    /// singleton parameter is passed `None` as its child.
+    pub fn map_params<F: FnMut(Option<NodeAddress>, f64) -> f64>(&mut self, mut f: F) {
+        self.child_counts
+            .iter_mut()
+            .for_each(|(na, p)| *p = f(Some(*na), *p));
+        self.singleton_count = f(None, self.singleton_count);
+    }
+
     /// Gives the probability vector for this
     pub fn prob_vector(&self) -> Option<(Vec<(NodeAddress, f64)>, f64)> {
         let total = self.total();
@@ -238,12 +254,16 @@ impl<D: PointCloud> NodePlugin<D> for Dirichlet {}
 /// Stores the log probabilities for each node in the tree.
 ///
 /// This is the probability that when you sample from the tree you end up at a particular node.
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct GokoDirichlet {
     // probability that you'd pass thru this node.
 //pub cond_ln_probs: HashMap<NodeAddress,f64>,
 }
 
+impl<D: PointCloud> PersistentPlugin<D> for GokoDirichlet {
+    const NAME: &'static str = "dirichlet";
+}
+
 /// Parent trait that make this all work. Ideally this should be included in the `TreePlugin` but rust doesn't like it.
 impl<D: PointCloud> GokoPlugin<D> for GokoDirichlet {
     type NodeComponent = Dirichlet;
@@ -253,6 +273,7 @@ impl<D: PointCloud> GokoPlugin<D> for GokoDirichlet {
         my_tree: &CoverTreeReader<D>,
     ) -> Option<Self::NodeComponent> {
         let mut bucket = Dirichlet::new();
+        let point_cloud = &my_tree.parameters().point_cloud;
 
         // If we're a routing node then grab the childen's values
         if let Some((nested_scale, child_addresses)) = my_node.children() {
@@ -267,9 +288,13 @@ impl<D: PointCloud> GokoPlugin<D> for GokoDirichlet {
                     bucket.add_child_pop(Some(*ca), p.total());
                 });
             }
-            bucket.add_child_pop(None, my_node.singletons_len() as f64);
+            bucket.add_child_pop(None, my_node.singletons_multiplicity(point_cloud) as f64);
         } else {
-            bucket.add_child_pop(None, (my_node.singletons_len() + 1) as f64);
+            let center_multiplicity = point_cloud.multiplicity(*my_node.center_index());
+            bucket.add_child_pop(
+                None,
+                (my_node.singletons_multiplicity(point_cloud) + center_multiplicity) as f64,
+            );
         }
         Some(bucket)
     }
@@ -14,6 +14,7 @@ pub mod baseline;
 pub mod categorical;
 pub mod dirichlet;
 pub mod tracker;
+pub mod two_window;
 
 #[allow(unused_imports)]
 pub mod prelude {
@@ -22,4 +23,5 @@ pub mod prelude {
     pub use super::categorical::*;
     pub use super::dirichlet::*;
     pub use super::tracker::*;
+    pub use super::two_window::*;
 }
@@ -1,6 +1,8 @@
 //! See the paper for how this works
 
 use crate::covertree::CoverTreeReader;
+use crate::errors::{GokoError, GokoResult};
+use crate::latency::Operation;
 use crate::plugins::*;
 use hashbrown::HashMap;
 
@@ -14,12 +16,42 @@ use std::fmt;
 
 use std::collections::VecDeque;
 
+/// Sets the prior pseudo-counts of a node's children. It's given the node's address, the child's
+/// address (`None` for the singletons) and the pseudo-count from the tree, after the prior weight.
+/// It returns the pseudo-count to use, which should be positive.
+pub type PriorFn = dyn Fn(NodeAddress, Option<NodeAddress>, f64) -> f64 + Send + Sync;
+
+/// A caller's id for an event in the sequence, like a log line number or a timestamp. It's carried
+/// through to the stats so alerts can be joined back to the events that caused them.
+pub type EventId = u64;
+
 /// Computes a frequentist KL divergence calculation on each node the sequence touches.
+///
+/// The prior at each node is the node's [`Dirichlet`], the number of points that fell into each
+/// child during the build. On very imbalanced trees a single prior weight either drowns out the
+/// evidence at the high traffic nodes or lets it swamp the rest, so the pseudo-counts can also be
+/// set per node with [`BayesCategoricalTracker::set_prior_counts`], or per child with
+/// [`BayesCategoricalTracker::set_prior_fn`]. Explicit counts take precedence over the callback.
+///
+/// Paths can be added with an [`EventId`], and the stats report the ids of the oldest and newest
+/// events in the window.
+///
+/// On a tree shared by several tenants the prior can be scoped to one partition's points with
+/// [`BayesCategoricalTracker::set_partition`], so a tenant's traffic is compared to its own
+/// training data rather than everyone's.
 pub struct BayesCategoricalTracker<D: PointCloud> {
     running_evidence: HashMap<NodeAddress, Categorical>,
     sequence_queue: VecDeque<Vec<(f32, NodeAddress)>>,
+    event_id_queue: VecDeque<Option<EventId>>,
+    first_event_id: Option<EventId>,
+    last_event_id: Option<EventId>,
     sequence_count: usize,
     window_size: usize,
+    prior_weight: f64,
+    observation_weight: f64,
+    prior_counts: HashMap<NodeAddress, Vec<f64>>,
+    prior_fn: Option<Box<PriorFn>>,
+    partition_counts: Option<HashMap<NodeAddress, Categorical>>,
     reader: CoverTreeReader<D>,
 }
 
@@ -27,8 +59,12 @@ impl<D: PointCloud> fmt::Debug for BayesCategoricalTracker<D> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
-            "PointCloud {{ sequence_queue: {:?}, window_size: {}, running_evidence: {:?}}}",
-            self.sequence_queue, self.window_size, self.running_evidence,
+            "PointCloud {{ sequence_queue: {:?}, window_size: {}, prior_weight: {}, observation_weight: {}, running_evidence: {:?}}}",
+            self.sequence_queue,
+            self.window_size,
+            self.prior_weight,
+            self.observation_weight,
+            self.running_evidence,
         )
     }
 }
@@ -42,12 +78,103 @@ impl<D: PointCloud> BayesCategoricalTracker<D> {
         BayesCategoricalTracker {
             running_evidence: HashMap::new(),
             sequence_queue: VecDeque::new(),
+            event_id_queue: VecDeque::new(),
+            first_event_id: None,
+            last_event_id: None,
             sequence_count: 0,
             window_size,
+            prior_weight: 1.0,
+            observation_weight: 1.0,
+            prior_counts: HashMap::new(),
+            prior_fn: None,
+            partition_counts: None,
             reader,
         }
     }
 
+    /// Sets a new prior weight, default 1.0. Every pseudo-count from the tree is multiplied by
+    /// this.
+    pub fn set_prior_weight(&mut self, prior_weight: f64) {
+        self.prior_weight = prior_weight;
+    }
+
+    /// Sets a new observation weight, default 1.0. Each observation in the sequence counts this
+    /// much. Only change this before adding paths.
+    pub fn set_observation_weight(&mut self, observation_weight: f64) {
+        self.observation_weight = observation_weight;
+    }
+
+    /// Overrides the prior pseudo-counts of a node. The counts are in the order of the node's
+    /// [`Dirichlet::params`], the children sorted by address followed by the singletons. They
+    /// aren't scaled by the prior weight.
+    pub fn set_prior_counts(&mut self, address: NodeAddress, counts: Vec<f64>) -> GokoResult<()> {
+        let param_count = self
+            .reader
+            .get_node_plugin_and::<Dirichlet, _, _>(address, |p| p.params().0.len() + 1)
+            .ok_or(GokoError::NodeNotInTree(address))?;
+        if counts.len() != param_count || counts.iter().any(|c| !(c.is_finite() && *c > 0.0)) {
+            return Err(GokoError::InvalidProbDistro);
+        }
+        self.prior_counts.insert(address, counts);
+        Ok(())
+    }
+
+    /// Sets a callback that picks the prior pseudo-count of each child, see [`PriorFn`].
+    pub fn set_prior_fn<F>(&mut self, prior_fn: F)
+    where
+        F: Fn(NodeAddress, Option<NodeAddress>, f64) -> f64 + Send + Sync + 'static,
+    {
+        self.prior_fn = Some(Box::new(prior_fn));
+    }
+
+    /// Scopes the prior to the points of one partition, see [`pointcloud::partitions`]. The
+    /// prior at each node becomes the number of the partition's points that went to each child,
+    /// times the prior weight, plus a single pseudo-observation spread like the whole tree's prior
+    /// so the children the partition never reached aren't impossible. Explicit prior counts still
+    /// take precedence, the prior callback isn't used. Deleted points aren't counted. Set the prior
+    /// weight before this.
+    pub fn set_partition(&mut self, partition: &str) -> GokoResult<()> {
+        let point_cloud = self.reader.point_cloud();
+        let members = point_cloud
+            .partitions()
+            .and_then(|partitions| partitions.members(partitions.id(partition)?))
+            .ok_or_else(|| GokoError::UnknownPartition(partition.to_string()))?;
+        let mut counts = HashMap::new();
+        for pi in members.iter() {
+            let pi = pi as usize;
+            if !point_cloud.is_deleted(pi) {
+                add_trace(&mut counts, &self.reader.known_path(pi)?, self.prior_weight);
+            }
+        }
+        self.partition_counts = Some(counts);
+        Ok(())
+    }
+
+    /// The prior this tracker uses at a node, before any of the sequence's evidence.
+    pub fn prior(&self, address: NodeAddress) -> Option<Dirichlet> {
+        let mut prior = self
+            .reader
+            .get_node_plugin_and::<Dirichlet, _, _>(address, |p| p.clone())?;
+        if let Some(counts) = self.prior_counts.get(&address) {
+            let mut counts = counts.iter();
+            prior.map_params(|_, _| *counts.next().unwrap());
+        } else if let Some(partition_counts) = &self.partition_counts {
+            let total = prior.total();
+            if total > 0.0 {
+                prior.weight(1.0 / total);
+            }
+            if let Some(counts) = partition_counts.get(&address) {
+                prior.add_evidence(counts);
+            }
+        } else {
+            prior.weight(self.prior_weight);
+            if let Some(prior_fn) = &self.prior_fn {
+                prior.map_params(|child, count| prior_fn(address, child, count));
+            }
+        }
+        Some(prior)
+    }
+
     /// Appends a tracker to this one,
     pub fn append(mut self, other: &Self) -> Self {
         for (k, v) in other.running_evidence.iter() {
@@ -58,6 +185,14 @@ impl<D: PointCloud> BayesCategoricalTracker<D> {
         }
         self.sequence_queue
             .extend(other.sequence_queue.iter().cloned());
+        self.event_id_queue
+            .extend(other.event_id_queue.iter().cloned());
+        if self.sequence_count == 0 {
+            self.first_event_id = other.first_event_id;
+        }
+        if other.sequence_count > 0 {
+            self.last_event_id = other.last_event_id;
+        }
         self.sequence_count += other.sequence_count;
         self
     }
@@ -75,48 +210,20 @@ impl<D: PointCloud> BayesCategoricalTracker<D> {
     }
 
     fn add_trace_to_pdfs(&mut self, trace: &[(f32, NodeAddress)]) {
-        let parent_address_iter = trace.iter().map(|(_, ca)| ca);
-        let mut child_address_iter = trace.iter().map(|(_, ca)| ca);
-        child_address_iter.next();
-        for (parent, child) in parent_address_iter.zip(child_address_iter) {
-            self.running_evidence
-                .entry(*parent)
-                .or_default()
-                .add_child_pop(Some(*child), 1.0);
-        }
-        let last = trace.last().unwrap().1;
-        self.running_evidence
-            .entry(last)
-            .or_default()
-            .add_child_pop(None, 1.0);
+        add_trace(&mut self.running_evidence, trace, self.observation_weight);
     }
 
     fn remove_trace_from_pdfs(&mut self, trace: &[(f32, NodeAddress)]) {
-        let parent_address_iter = trace.iter().map(|(_, ca)| ca);
-        let mut child_address_iter = trace.iter().map(|(_, ca)| ca);
-        child_address_iter.next();
-        for (parent, child) in parent_address_iter.zip(child_address_iter) {
-            let parent_evidence = self.running_evidence.get_mut(parent).unwrap();
-            parent_evidence.remove_child_pop(Some(*child), 1.0);
-        }
-        let last = trace.last().unwrap().1;
-        self.running_evidence
-            .get_mut(&last)
-            .unwrap()
-            .remove_child_pop(None, 1.0);
+        remove_trace(&mut self.running_evidence, trace, self.observation_weight);
     }
 
     /// Gives the probability vector for this
     pub fn prob_vector(&self, na: NodeAddress) -> Option<(Vec<(NodeAddress, f64)>, f64)> {
-        self.reader
-            .get_node_plugin_and::<Dirichlet, _, _>(na, |p| {
-                let mut dir = p.clone();
-                if let Some(e) = self.running_evidence.get(&na) {
-                    dir.add_evidence(e)
-                }
-                dir.prob_vector()
-            })
-            .flatten()
+        let mut dir = self.prior(na)?;
+        if let Some(e) = self.running_evidence.get(&na) {
+            dir.add_evidence(e)
+        }
+        dir.prob_vector()
     }
 
     /// Gives the probability vector for this
@@ -129,16 +236,70 @@ impl<D: PointCloud> BayesCategoricalTracker<D> {
 
     /// Adds an element to the trace
     pub fn add_path(&mut self, trace: Vec<(f32, NodeAddress)>) {
+        self.add_path_with_id(trace, None)
+    }
+
+    /// Adds an element to the trace, tagged with the caller's id for the event.
+    pub fn add_path_with_id(&mut self, trace: Vec<(f32, NodeAddress)>, id: Option<EventId>) {
+        let timer = self.reader.latency_stats().start();
         self.add_trace_to_pdfs(&trace);
+        if self.sequence_count == 0 {
+            self.first_event_id = id;
+        }
+        self.last_event_id = id;
         self.sequence_count += 1;
         if self.window_size != 0 {
             self.sequence_queue.push_back(trace);
+            self.event_id_queue.push_back(id);
 
             if self.sequence_queue.len() > self.window_size {
                 let oldest = self.sequence_queue.pop_front().unwrap();
+                self.event_id_queue.pop_front();
                 self.remove_trace_from_pdfs(&oldest);
             }
         }
+        self.reader
+            .latency_stats()
+            .record(Operation::TrackerPush, timer);
+    }
+
+    /// Adds a batch of paths, returning the stats after each one. If there are ids, there has to
+    /// be one for each path, and each path's stats carry its id as the `last_event_id`.
+    pub fn add_paths(
+        &mut self,
+        traces: Vec<Vec<(f32, NodeAddress)>>,
+        ids: Option<&[EventId]>,
+    ) -> GokoResult<Vec<KLDivergenceStats>> {
+        if let Some(ids) = ids {
+            if ids.len() != traces.len() {
+                return Err(GokoError::EventIdCountMismatch {
+                    events: traces.len(),
+                    ids: ids.len(),
+                });
+            }
+        }
+        Ok(traces
+            .into_iter()
+            .enumerate()
+            .map(|(i, trace)| {
+                self.add_path_with_id(trace, ids.map(|ids| ids[i]));
+                self.kl_div_stats()
+            })
+            .collect())
+    }
+
+    /// The id of the oldest event still in the window, if it was given one.
+    pub fn first_event_id(&self) -> Option<EventId> {
+        if self.window_size != 0 {
+            self.event_id_queue.front().copied().flatten()
+        } else {
+            self.first_event_id
+        }
+    }
+
+    /// The id of the most recent event, if it was given one.
+    pub fn last_event_id(&self) -> Option<EventId> {
+        self.last_event_id
     }
 
     /// The running categorical distributions
@@ -161,11 +322,8 @@ impl<D: PointCloud> BayesCategoricalTracker<D> {
             .iter()
             .filter_map(|(address, sequence_pdf)| {
                 let kl_option = self
-                    .reader
-                    .get_node_plugin_and::<Dirichlet, _, _>(*address, |p| {
-                        p.posterior_kl_divergence(sequence_pdf).unwrap()
-                    })
-                    .map(|kl| (kl, *address));
+                    .prior(*address)
+                    .map(|p| (p.posterior_kl_divergence(sequence_pdf).unwrap(), *address));
                 if let None = kl_option {
                     println!("Unable to find node at {:?}", address);
                 }
@@ -176,47 +334,30 @@ impl<D: PointCloud> BayesCategoricalTracker<D> {
 
     /// A set of stats for the sequence that are helpful.
     pub fn kl_div_stats(&self) -> KLDivergenceStats {
-        let mut max = f64::MIN;
-        let mut min = f64::MAX;
-        let mut nz_count = 0;
-        let mut moment1_nz = 0.0;
-        let mut moment2_nz = 0.0;
-        self.all_node_kl().iter().for_each(|(kl, _address)| {
-            if *kl > 1.0e-10 {
-                moment1_nz += kl;
-                moment2_nz += kl * kl;
-                if max < *kl {
-                    max = *kl;
-                }
-                if *kl < min {
-                    min = *kl;
-                }
-
-                nz_count += 1;
-            }
-        });
-        KLDivergenceStats {
-            max,
-            min,
-            nz_count,
-            moment1_nz,
-            moment2_nz,
-            sequence_len: self.sequence_len(),
-        }
+        KLDivergenceStats::from_node_kl(
+            &self.all_node_kl(),
+            self.sequence_len(),
+            self.first_event_id(),
+            self.last_event_id(),
+        )
     }
 
-    /// The KL Divergence between the prior and posterior of the whole tree.
+    /// The KL Divergence between the prior and posterior of the whole tree. This treats the tree
+    /// as one distribution over the nodes' singletons, so only the prior weight applies, not
+    /// per node pseudo-counts.
     pub fn kl_div(&self) -> f64 {
-        let prior_total =
-            (self.reader.parameters().point_cloud.len() + self.reader.node_count()) as f64;
-        let posterior_total = prior_total + self.sequence_len() as f64;
+        let prior_total = self.prior_weight
+            * (self.reader.parameters().point_cloud.len() + self.reader.node_count()) as f64;
+        let posterior_total = prior_total + self.observation_weight * self.sequence_len() as f64;
         let mut prior_total_lng = 0.0;
         let mut posterior_total_lng = 0.0;
         let mut digamma_portion = 0.0;
         for (addr, evidence) in self.running_evidence.iter() {
             if evidence.singleton_count > 0.0 {
                 self.reader.get_node_and(*addr, |n| {
-                    let prior = n.singletons_len() as f64 + 1.0;
+                    let point_cloud = &self.reader.parameters().point_cloud;
+                    let prior =
+                        self.prior_weight * (n.singletons_multiplicity(point_cloud) as f64 + 1.0);
                     prior_total_lng += ln_gamma(prior);
                     posterior_total_lng += ln_gamma(evidence.singleton_count + prior);
                     digamma_portion += evidence.singleton_count
@@ -269,6 +410,49 @@ impl<D: PointCloud> BayesCategoricalTracker<D> {
     }
 }
 
+/// Adds a path to the per-node counts of which child each point went to, the last node's
+/// singletons for the end of the path.
+pub(super) fn add_trace(
+    evidence: &mut HashMap<NodeAddress, Categorical>,
+    trace: &[(f32, NodeAddress)],
+    weight: f64,
+) {
+    let parent_address_iter = trace.iter().map(|(_, ca)| ca);
+    let mut child_address_iter = trace.iter().map(|(_, ca)| ca);
+    child_address_iter.next();
+    for (parent, child) in parent_address_iter.zip(child_address_iter) {
+        evidence
+            .entry(*parent)
+            .or_default()
+            .add_child_pop(Some(*child), weight);
+    }
+    let last = trace.last().unwrap().1;
+    evidence
+        .entry(last)
+        .or_default()
+        .add_child_pop(None, weight);
+}
+
+/// Removes a path that was added with [`add_trace`].
+pub(super) fn remove_trace(
+    evidence: &mut HashMap<NodeAddress, Categorical>,
+    trace: &[(f32, NodeAddress)],
+    weight: f64,
+) {
+    let parent_address_iter = trace.iter().map(|(_, ca)| ca);
+    let mut child_address_iter = trace.iter().map(|(_, ca)| ca);
+    child_address_iter.next();
+    for (parent, child) in parent_address_iter.zip(child_address_iter) {
+        let parent_evidence = evidence.get_mut(parent).unwrap();
+        parent_evidence.remove_child_pop(Some(*child), weight);
+    }
+    let last = trace.last().unwrap().1;
+    evidence
+        .get_mut(&last)
+        .unwrap()
+        .remove_child_pop(None, weight);
+}
+
 /// Tracks the non-zero KL div (all KL divergences above 1e-10)
 #[derive(Debug, Serialize, Deserialize)]
 pub struct KLDivergenceStats {
@@ -285,6 +469,90 @@ pub struct KLDivergenceStats {
     /// The number of sequence elements that went into calculating this stat. This is not the total lenght
     /// We can drop old sequence elements
     pub sequence_len: usize,
+    /// The id of the oldest event in the window, if it was given one
+    #[serde(default)]
+    pub first_event_id: Option<EventId>,
+    /// The id of the most recent event, if it was given one
+    #[serde(default)]
+    pub last_event_id: Option<EventId>,
+    /// The same stats split by the scale index of the nodes, from the coarsest scale to the finest.
+    /// Only the scales with a non-zero divergence are included.
+    #[serde(default)]
+    pub per_scale: Vec<ScaleKLDivergenceStats>,
+}
+
+impl KLDivergenceStats {
+    /// Collects the stats from the per-node KL divergences, overall and per scale index.
+    pub(super) fn from_node_kl(
+        node_kl: &[(f64, NodeAddress)],
+        sequence_len: usize,
+        first_event_id: Option<EventId>,
+        last_event_id: Option<EventId>,
+    ) -> KLDivergenceStats {
+        let mut max = f64::MIN;
+        let mut min = f64::MAX;
+        let mut nz_count = 0;
+        let mut moment1_nz = 0.0;
+        let mut moment2_nz = 0.0;
+        let mut per_scale: Vec<ScaleKLDivergenceStats> = Vec::new();
+        for (kl, address) in node_kl {
+            if *kl > 1.0e-10 {
+                moment1_nz += kl;
+                moment2_nz += kl * kl;
+                max = max.max(*kl);
+                min = min.min(*kl);
+                nz_count += 1;
+
+                let scale_stats = match per_scale.iter().position(|s| s.scale_index == address.0) {
+                    Some(i) => &mut per_scale[i],
+                    None => {
+                        per_scale.push(ScaleKLDivergenceStats {
+                            scale_index: address.0,
+                            max: f64::MIN,
+                            nz_count: 0,
+                            moment1_nz: 0.0,
+                            moment2_nz: 0.0,
+                        });
+                        per_scale.last_mut().unwrap()
+                    }
+                };
+                scale_stats.max = scale_stats.max.max(*kl);
+                scale_stats.nz_count += 1;
+                scale_stats.moment1_nz += kl;
+                scale_stats.moment2_nz += kl * kl;
+            }
+        }
+        // Coarsest scale first
+        per_scale.sort_by(|a, b| b.scale_index.cmp(&a.scale_index));
+        KLDivergenceStats {
+            max,
+            min,
+            nz_count,
+            moment1_nz,
+            moment2_nz,
+            sequence_len,
+            first_event_id,
+            last_event_id,
+            per_scale,
+        }
+    }
+}
+
+/// The non-zero KL divergence of the nodes of one scale index. Drift concentrated in the coarse
+/// scales means the whole distribution has shifted, drift concentrated in the fine scales means
+/// there's something new in a small region.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScaleKLDivergenceStats {
+    /// The scale index of the nodes
+    pub scale_index: i32,
+    /// The maximum non-zero KL divergence at this scale
+    pub max: f64,
+    /// The number of nodes at this scale that have a non-zero divergence
+    pub nz_count: u64,
+    /// The first moment, the total divergence at this scale
+    pub moment1_nz: f64,
+    /// The second moment, use this with the `nz_count` and first moment to get the variance
+    pub moment2_nz: f64,
 }
 
 /// Stats that let you compute the fractal dim of the query dataset wrt the base covertree
@@ -368,4 +636,158 @@ pub(crate) mod tests {
         println!("Merge KL Div: {}", tracker1.kl_div());
         assert_approx_eq!(tracker.kl_div(), tracker1.kl_div());
     }
+
+    #[test]
+    fn dirichlet_tree_prior_config_test() {
+        let mut tree = build_basic_tree();
+        tree.add_plugin::<GokoDirichlet>(GokoDirichlet::default());
+        let root = tree.root_address;
+        let tree_prior = tree
+            .reader()
+            .get_node_plugin_and::<Dirichlet, _, _>(root, |p| p.clone())
+            .unwrap();
+        let param_count = tree_prior.params().0.len() + 1;
+
+        let mut tracker = BayesCategoricalTracker::new(0, tree.reader());
+        tracker.set_prior_weight(2.0);
+        assert_approx_eq!(
+            tracker.prior(root).unwrap().total(),
+            2.0 * tree_prior.total()
+        );
+
+        // The callback sees the weighted counts
+        tracker.set_prior_fn(|_, child, count| match child {
+            Some(_) => count,
+            None => 0.5 * count,
+        });
+        let prior = tracker.prior(root).unwrap();
+        assert_approx_eq!(prior.params().1, tree_prior.params().1);
+
+        // Explicit counts win over the callback and aren't weighted
+        assert!(tracker.set_prior_counts(root, vec![1.0]).is_err());
+        assert!(tracker
+            .set_prior_counts(root, vec![0.0; param_count])
+            .is_err());
+        tracker
+            .set_prior_counts(root, vec![1.0; param_count])
+            .unwrap();
+        let (child_probs, singleton_prob) = tracker.prob_vector(root).unwrap();
+        assert_approx_eq!(singleton_prob, 1.0 / param_count as f64);
+        for (_, p) in child_probs {
+            assert_approx_eq!(p, 1.0 / param_count as f64);
+        }
+
+        // Evidence lands on top of the configured prior
+        tracker.add_path(tree.reader().known_path(0).unwrap());
+        assert!(tracker.all_node_kl().iter().any(|(kl, _)| *kl > 0.0));
+    }
+
+    #[test]
+    fn dirichlet_tree_event_ids_test() {
+        let mut tree = build_basic_tree();
+        tree.add_plugin::<GokoDirichlet>(GokoDirichlet::default());
+        let reader = tree.reader();
+        let paths: Vec<Vec<(f32, NodeAddress)>> =
+            (0..4).map(|i| reader.known_path(i).unwrap()).collect();
+
+        let mut tracker = BayesCategoricalTracker::new(2, tree.reader());
+        assert!(tracker.add_paths(paths.clone(), Some(&[1, 2])).is_err());
+        assert_eq!(tracker.sequence_len(), 0);
+        let stats = tracker
+            .add_paths(paths.clone(), Some(&[100, 101, 102, 103]))
+            .unwrap();
+        let ids: Vec<(Option<EventId>, Option<EventId>)> = stats
+            .iter()
+            .map(|s| (s.first_event_id, s.last_event_id))
+            .collect();
+        assert_eq!(
+            ids,
+            vec![
+                (Some(100), Some(100)),
+                (Some(100), Some(101)),
+                (Some(101), Some(102)),
+                (Some(102), Some(103)),
+            ]
+        );
+
+        // Without a window the first event is never dropped
+        let mut unbounded = BayesCategoricalTracker::new(0, tree.reader());
+        unbounded
+            .add_paths(paths.clone(), Some(&[7, 8, 9, 10]))
+            .unwrap();
+        unbounded.add_path(paths[0].clone());
+        let stats = unbounded.kl_div_stats();
+        assert_eq!(stats.first_event_id, Some(7));
+        assert_eq!(stats.last_event_id, None);
+    }
+
+    #[test]
+    fn dirichlet_tree_per_scale_stats_test() {
+        let mut tree = build_basic_tree();
+        tree.add_plugin::<GokoDirichlet>(GokoDirichlet::default());
+        let reader = tree.reader();
+        let mut tracker = BayesCategoricalTracker::new(0, tree.reader());
+        for _ in 0..5 {
+            tracker.add_path(reader.known_path(0).unwrap());
+        }
+        let stats = tracker.kl_div_stats();
+        assert!(!stats.per_scale.is_empty());
+        assert_eq!(stats.per_scale[0].scale_index, reader.root_address().0);
+        assert!(stats
+            .per_scale
+            .windows(2)
+            .all(|w| w[0].scale_index > w[1].scale_index));
+        let nz_count: u64 = stats.per_scale.iter().map(|s| s.nz_count).sum();
+        let moment1_nz: f64 = stats.per_scale.iter().map(|s| s.moment1_nz).sum();
+        assert_eq!(nz_count, stats.nz_count);
+        assert_approx_eq!(moment1_nz, stats.moment1_nz);
+    }
+
+    #[test]
+    fn partition_scoped_prior_and_baseline() {
+        use crate::plugins::discrete::baseline::DirichletBaseline;
+        use pointcloud::data_sources::DataRam;
+        use pointcloud::partitions::{PartitionMap, SimplePartitionedCloud};
+        use std::sync::Arc;
+        let data = DataRam::<L2>::new(vec![0.499, 0.49, 0.48, -0.49, 0.0], 1).unwrap();
+        let keys = [Some("a"), Some("b"), Some("a"), None, Some("b")];
+        let point_cloud =
+            SimplePartitionedCloud::new(data, PartitionMap::from_keys(&keys)).unwrap();
+        let builder = CoverTreeBuilder {
+            scale_base: 2.0,
+            leaf_cutoff: 1,
+            min_res_index: -9,
+            use_singletons: true,
+            partition_type: PartitionType::Nearest,
+            verbosity: 0,
+            rng_seed: Some(0),
+            memory_limit: None,
+            prune_chains: false,
+            thread_pool: None,
+            progress_callback: None,
+            progress_interval: None,
+            cancel_token: None,
+        };
+        let mut tree = builder.build(Arc::new(point_cloud)).unwrap();
+        tree.add_plugin::<GokoDirichlet>(GokoDirichlet::default());
+        let reader = tree.reader();
+        let root = reader.root_address();
+
+        let mut tracker = BayesCategoricalTracker::new(0, tree.reader());
+        tracker.set_prior_weight(2.0);
+        assert!(tracker.set_partition("c").is_err());
+        tracker.set_partition("a").unwrap();
+        // One pseudo-observation from the tree, and both of a's points at weight 2
+        assert_approx_eq!(tracker.prior(root).unwrap().total(), 5.0);
+        tracker.add_path(reader.known_path(0).unwrap());
+        assert!(tracker.kl_div_stats().max >= 0.0);
+
+        let mut trainer = DirichletBaseline::default();
+        trainer.set_sample_rate(1);
+        trainer.set_partition(Some("a".to_string()));
+        let baseline = trainer.train(tree.reader()).unwrap();
+        assert_eq!(baseline.sequence_len, vec![1, 2]);
+        trainer.set_partition(Some("c".to_string()));
+        assert!(trainer.train(tree.reader()).is_err());
+    }
 }
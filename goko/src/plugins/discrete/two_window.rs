@@ -0,0 +1,226 @@
+//! # Two window tracker
+//!
+//! [`BayesCategoricalTracker`](super::tracker::BayesCategoricalTracker) compares the recent
+//! queries to the training set. If the stream has drifted slowly away from the training set the
+//! divergence is already high, and an abrupt change only moves it a little. This tracker keeps two
+//! adjacent windows over the stream instead, a reference window of older queries and a test window
+//! of the most recent ones, and compares the node visits of the test window to the reference.
+//! Points enter the test window, move to the reference window when they age out of it, and are
+//! dropped when they age out of the reference window.
+//!
+//! The prior at each node is the reference window's counts, on top of the node's [`Dirichlet`]
+//! scaled to a total of the prior weight. The tree's part keeps the prior defined for children the
+//! reference window never visited, and is small so that the reference window dominates.
+
+use crate::covertree::CoverTreeReader;
+use crate::latency::Operation;
+use crate::plugins::*;
+use hashbrown::HashMap;
+
+use super::categorical::*;
+use super::dirichlet::*;
+use super::tracker::{add_trace, remove_trace, EventId, KLDivergenceStats};
+
+use std::collections::VecDeque;
+use std::fmt;
+
+type Trace = Vec<(f32, NodeAddress)>;
+
+/// Compares the node visits of a test window of the most recent paths to the reference window of
+/// paths before it.
+pub struct TwoWindowTracker<D: PointCloud> {
+    reference_evidence: HashMap<NodeAddress, Categorical>,
+    test_evidence: HashMap<NodeAddress, Categorical>,
+    reference_queue: VecDeque<(Option<EventId>, Trace)>,
+    test_queue: VecDeque<(Option<EventId>, Trace)>,
+    reference_size: usize,
+    test_size: usize,
+    prior_weight: f64,
+    reader: CoverTreeReader<D>,
+}
+
+impl<D: PointCloud> fmt::Debug for TwoWindowTracker<D> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "TwoWindowTracker {{ reference_size: {}, test_size: {}, reference_len: {}, test_len: {}, prior_weight: {}}}",
+            self.reference_size,
+            self.test_size,
+            self.reference_queue.len(),
+            self.test_queue.len(),
+            self.prior_weight,
+        )
+    }
+}
+
+impl<D: PointCloud> TwoWindowTracker<D> {
+    /// Creates a tracker with a reference window of `reference_size` paths and a test window of
+    /// `test_size` paths. Both should be more than 0.
+    pub fn new(
+        reference_size: usize,
+        test_size: usize,
+        reader: CoverTreeReader<D>,
+    ) -> TwoWindowTracker<D> {
+        TwoWindowTracker {
+            reference_evidence: HashMap::new(),
+            test_evidence: HashMap::new(),
+            reference_queue: VecDeque::new(),
+            test_queue: VecDeque::new(),
+            reference_size,
+            test_size,
+            prior_weight: 1.0,
+            reader,
+        }
+    }
+
+    /// Sets the total weight of the tree's part of the prior at each node, default 1.0.
+    pub fn set_prior_weight(&mut self, prior_weight: f64) {
+        self.prior_weight = prior_weight;
+    }
+
+    /// Adds a path to the test window, moving the oldest test path to the reference window if the
+    /// test window is full.
+    pub fn add_path(&mut self, trace: Vec<(f32, NodeAddress)>) {
+        self.add_path_with_id(trace, None)
+    }
+
+    /// Adds a path, tagged with the caller's id for the event.
+    pub fn add_path_with_id(&mut self, trace: Vec<(f32, NodeAddress)>, id: Option<EventId>) {
+        let timer = self.reader.latency_stats().start();
+        add_trace(&mut self.test_evidence, &trace, 1.0);
+        self.test_queue.push_back((id, trace));
+        if self.test_queue.len() > self.test_size {
+            let (id, oldest) = self.test_queue.pop_front().unwrap();
+            remove_trace(&mut self.test_evidence, &oldest, 1.0);
+            add_trace(&mut self.reference_evidence, &oldest, 1.0);
+            self.reference_queue.push_back((id, oldest));
+            if self.reference_queue.len() > self.reference_size {
+                let (_, oldest) = self.reference_queue.pop_front().unwrap();
+                remove_trace(&mut self.reference_evidence, &oldest, 1.0);
+            }
+        }
+        self.reader
+            .latency_stats()
+            .record(Operation::TrackerPush, timer);
+    }
+
+    /// The number of paths in the reference window.
+    pub fn reference_len(&self) -> usize {
+        self.reference_queue.len()
+    }
+
+    /// The number of paths in the test window.
+    pub fn test_len(&self) -> usize {
+        self.test_queue.len()
+    }
+
+    /// If both windows are full. Before then the divergence is against a partial reference.
+    pub fn is_ready(&self) -> bool {
+        self.reference_queue.len() == self.reference_size && self.test_queue.len() == self.test_size
+    }
+
+    /// The prior at a node, the scaled tree prior plus the reference window's counts.
+    pub fn prior(&self, address: NodeAddress) -> Option<Dirichlet> {
+        let mut prior = self
+            .reader
+            .get_node_plugin_and::<Dirichlet, _, _>(address, |p| p.clone())?;
+        let total = prior.total();
+        if total > 0.0 {
+            prior.weight(self.prior_weight / total);
+        }
+        if let Some(e) = self.reference_evidence.get(&address) {
+            prior.add_evidence(e);
+        }
+        Some(prior)
+    }
+
+    /// Gives the per-node KL divergence of the test window from the reference, with the node
+    /// address. Only the nodes the test window visits are included.
+    pub fn all_node_kl(&self) -> Vec<(f64, NodeAddress)> {
+        self.test_evidence
+            .iter()
+            .filter_map(|(address, test_pdf)| {
+                self.prior(*address)
+                    .and_then(|p| p.posterior_kl_divergence(test_pdf))
+                    .map(|kl| (kl, *address))
+            })
+            .collect()
+    }
+
+    /// A set of stats for the test window, in the same form as the single window tracker's. The
+    /// sequence length is the test window's, and the event ids are those of its oldest and newest
+    /// paths.
+    pub fn kl_div_stats(&self) -> KLDivergenceStats {
+        KLDivergenceStats::from_node_kl(
+            &self.all_node_kl(),
+            self.test_queue.len(),
+            self.test_queue.front().and_then(|(id, _)| *id),
+            self.test_queue.back().and_then(|(id, _)| *id),
+        )
+    }
+
+    /// Easy access to the cover tree read head associated to this tracker
+    pub fn reader(&self) -> &CoverTreeReader<D> {
+        &self.reader
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::covertree::tests::build_basic_tree;
+
+    #[test]
+    fn windows_slide_test() {
+        let mut tree = build_basic_tree();
+        tree.add_plugin::<GokoDirichlet>(GokoDirichlet::default());
+        let reader = tree.reader();
+        let mut tracker = TwoWindowTracker::new(3, 2, tree.reader());
+        for i in 0..4 {
+            tracker.add_path_with_id(reader.known_path(i).unwrap(), Some(i as u64));
+        }
+        assert_eq!(tracker.reference_len(), 2);
+        assert_eq!(tracker.test_len(), 2);
+        assert!(!tracker.is_ready());
+        tracker.add_path_with_id(reader.known_path(4).unwrap(), Some(4));
+        tracker.add_path_with_id(reader.known_path(0).unwrap(), Some(5));
+        assert!(tracker.is_ready());
+        assert_eq!(tracker.reference_len(), 3);
+        let stats = tracker.kl_div_stats();
+        assert_eq!(stats.sequence_len, 2);
+        assert_eq!(stats.first_event_id, Some(4));
+        assert_eq!(stats.last_event_id, Some(5));
+    }
+
+    #[test]
+    fn abrupt_change_test() {
+        let mut tree = build_basic_tree();
+        tree.add_plugin::<GokoDirichlet>(GokoDirichlet::default());
+        let reader = tree.reader();
+        // Points 0 and 3 are on opposite sides of the root
+        let left = reader.known_path(0).unwrap();
+        let right = reader.known_path(3).unwrap();
+
+        let mut steady = TwoWindowTracker::new(10, 5, tree.reader());
+        let mut changed = TwoWindowTracker::new(10, 5, tree.reader());
+        for _ in 0..10 {
+            steady.add_path(left.clone());
+            changed.add_path(left.clone());
+        }
+        for _ in 0..5 {
+            steady.add_path(left.clone());
+            changed.add_path(right.clone());
+        }
+        let root = reader.root_address();
+        let root_kl = |tracker: &TwoWindowTracker<_>| {
+            tracker
+                .all_node_kl()
+                .iter()
+                .find(|(_, a)| *a == root)
+                .unwrap()
+                .0
+        };
+        assert!(root_kl(&changed) > root_kl(&steady));
+        assert!(changed.kl_div_stats().max > steady.kl_div_stats().max);
+    }
+}
@@ -10,6 +10,7 @@ use crate::covertree::CoverTreeReader;
 
 use rand::prelude::*;
 use rand_distr::StandardNormal;
+use serde::{Deserialize, Serialize};
 use std::f32::consts::PI;
 
 /// Node component, coded in such a way that it can be efficiently, recursively computed.
@@ -171,26 +172,128 @@ impl DiagGaussian {
     pub fn count(&self) -> usize {
         self.count
     }
+
+    /// The mean and variance of each dimension, with the dimension's name from the point cloud.
+    /// Dimensions without a name are named by their index.
+    pub fn named_moments<D: PointCloud>(&self, point_cloud: &D) -> Vec<(String, f32, f32)> {
+        self.mean()
+            .into_iter()
+            .zip(self.var())
+            .enumerate()
+            .map(|(i, (mean, var))| {
+                let name = point_cloud
+                    .dim_name(i)
+                    .map(|n| n.to_string())
+                    .unwrap_or_else(|| i.to_string());
+                (name, mean, var)
+            })
+            .collect()
+    }
+
+    /// Creates the diagonal gaussian with this location and scale (standard deviation) in each
+    /// coordinate, standing in for `count` points.
+    pub fn from_location_scale(location: &[f32], scale: &[f32], count: usize) -> DiagGaussian {
+        let c = count as f32;
+        DiagGaussian {
+            moment1: location.iter().map(|u| u * c).collect(),
+            moment2: location
+                .iter()
+                .zip(scale)
+                .map(|(u, s)| (s * s + u * u) * c)
+                .collect(),
+            count,
+        }
+    }
+}
+
+/// How [`GokoDiagGaussian`] estimates the location and scale of each coordinate.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum GaussianEstimator {
+    /// The mean and variance, computed from the moments. This is the default.
+    #[default]
+    Moments,
+    /// The median and the median absolute deviation, scaled by 1.4826 so that it estimates the
+    /// standard deviation of normal data. Up to half the points can be outliers without moving
+    /// these far.
+    Median,
+    /// The mean of the values left after trimming this fraction from each end, with the scale
+    /// from the median absolute deviation. The fraction should be less than 0.5.
+    TrimmedMean(f32),
+}
+
+/// The consistency constant of the median absolute deviation for normal data.
+const MAD_SCALE: f32 = 1.4826;
+
+/// Sorts the values and returns their median.
+fn sorted_median(values: &mut [f32]) -> f32 {
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    let len = values.len();
+    if len == 0 {
+        0.0
+    } else if len % 2 == 1 {
+        values[len / 2]
+    } else {
+        (values[len / 2 - 1] + values[len / 2]) / 2.0
+    }
+}
+
+/// The robust location and scale of one coordinate's values, which are reordered.
+fn robust_location_scale(values: &mut [f32], estimator: GaussianEstimator) -> (f32, f32) {
+    let median = sorted_median(values);
+    let location = match estimator {
+        GaussianEstimator::TrimmedMean(trim) => {
+            let cut = ((values.len() as f32) * trim.clamp(0.0, 0.5)) as usize;
+            let kept = &values[cut..values.len() - cut];
+            if kept.is_empty() {
+                median
+            } else {
+                kept.iter().sum::<f32>() / kept.len() as f32
+            }
+        }
+        _ => median,
+    };
+    let mut deviations: Vec<f32> = values.iter().map(|x| (x - median).abs()).collect();
+    let scale = MAD_SCALE * sorted_median(&mut deviations);
+    (location, scale)
 }
 
 impl<D: PointCloud> NodePlugin<D> for DiagGaussian {}
 
 /// Zero sized type that can be passed around. Equivilant to `()`
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GokoDiagGaussian {
     recursive: bool,
+    estimator: GaussianEstimator,
 }
 
 impl GokoDiagGaussian {
     /// Sets this up to build the gaussians recursively, so the gaussian for a node is for the total cover space.
     pub fn recursive() -> Self {
-        Self { recursive: true }
+        Self {
+            recursive: true,
+            estimator: GaussianEstimator::Moments,
+        }
     }
 
     /// Produces a gaussian off of just the singletons attached to the node, not the total cover space
     pub fn singletons() -> Self {
-        Self { recursive: false }
+        Self {
+            recursive: false,
+            estimator: GaussianEstimator::Moments,
+        }
     }
+
+    /// Estimates the location and scale of the gaussians robustly, for data with heavy tailed
+    /// noise where a few outliers would dominate the moments. The robust gaussians can't be
+    /// merged from the children's, so each node reads all of its points.
+    pub fn with_estimator(mut self, estimator: GaussianEstimator) -> Self {
+        self.estimator = estimator;
+        self
+    }
+}
+
+impl<D: PointCloud> PersistentPlugin<D> for GokoDiagGaussian {
+    const NAME: &'static str = "diag_gaussian";
 }
 
 impl<D: PointCloud> GokoPlugin<D> for GokoDiagGaussian {
@@ -200,15 +303,37 @@ impl<D: PointCloud> GokoPlugin<D> for GokoDiagGaussian {
         my_node: &CoverNode<D>,
         my_tree: &CoverTreeReader<D>,
     ) -> Option<Self::NodeComponent> {
+        if parameters.estimator != GaussianEstimator::Moments {
+            let indexes = gaussian_point_indexes(my_node, my_tree, parameters.recursive);
+            let point_cloud = &my_tree.parameters().point_cloud;
+            let dim = point_cloud.dim();
+            let mut columns = vec![Vec::with_capacity(indexes.len()); dim];
+            for i in &indexes {
+                let point = point_cloud.point(*i).ok()?;
+                columns
+                    .iter_mut()
+                    .zip(point.dense_iter())
+                    .for_each(|(c, x)| c.push(x));
+            }
+            let (location, scale): (Vec<f32>, Vec<f32>) = columns
+                .iter_mut()
+                .map(|c| robust_location_scale(c, parameters.estimator))
+                .unzip();
+            return Some(DiagGaussian::from_location_scale(
+                &location,
+                &scale,
+                indexes.len(),
+            ));
+        }
         let moment1 = my_tree
             .parameters()
             .point_cloud
-            .moment_1(my_node.singletons())
+            .moment_1(&my_node.singletons_decoded())
             .unwrap();
         let moment2 = my_tree
             .parameters()
             .point_cloud
-            .moment_2(my_node.singletons())
+            .moment_2(&my_node.singletons_decoded())
             .unwrap();
         let count = my_node.singletons_len();
         let mut my_dg = DiagGaussian {
@@ -367,4 +492,55 @@ pub(crate) mod tests {
             });
         }
     }
+
+    #[test]
+    fn named_moments_use_dim_names() {
+        use pointcloud::data_sources::DataRam;
+        let data = DataRam::<L2>::new(vec![0.0, 1.0, 2.0, 3.0], 2)
+            .unwrap()
+            .with_dim_names(vec!["x".to_string(), "y".to_string()])
+            .unwrap();
+        let mut gaussian = DiagGaussian::new(2);
+        gaussian.add_point(&data.point(0).unwrap());
+        gaussian.add_point(&data.point(1).unwrap());
+        let moments = gaussian.named_moments(&data);
+        assert_eq!(moments[0].0, "x");
+        assert_eq!(moments[1].0, "y");
+        assert_approx_eq!(moments[1].1, 2.0);
+
+        let unnamed = DataRam::<L2>::new(vec![0.0, 1.0], 2).unwrap();
+        assert_eq!(gaussian.named_moments(&unnamed)[1].0, "1");
+    }
+
+    #[test]
+    fn robust_location_scale_ignores_outliers() {
+        let mut values = vec![1.0, 2.0, 3.0, 4.0, 1000.0];
+        let (location, scale) = robust_location_scale(&mut values, GaussianEstimator::Median);
+        assert_approx_eq!(location, 3.0);
+        assert_approx_eq!(scale, MAD_SCALE);
+
+        let mut values = vec![1000.0, 2.0, 3.0, 4.0, -1000.0];
+        let (location, _) = robust_location_scale(&mut values, GaussianEstimator::TrimmedMean(0.2));
+        assert_approx_eq!(location, 3.0);
+
+        let gaussian = DiagGaussian::from_location_scale(&[3.0], &[2.0], 5);
+        assert_approx_eq!(gaussian.mean()[0], 3.0);
+        assert_approx_eq!(gaussian.var()[0], 4.0, 1e-4);
+    }
+
+    #[test]
+    fn robust_gaussian_counts_cover() {
+        let mut ct = build_basic_tree();
+        ct.add_plugin::<GokoDiagGaussian>(
+            GokoDiagGaussian::recursive().with_estimator(GaussianEstimator::Median),
+        );
+        let reader = ct.reader();
+        let root = reader.root_address();
+        let (median, count) = reader
+            .get_node_plugin_and::<DiagGaussian, _, _>(root, |p| (p.mean()[0], p.count))
+            .unwrap();
+        // The median of 0.499, 0.49, 0.48, -0.49, 0.0
+        assert_approx_eq!(median, 0.48);
+        assert_eq!(count, 5);
+    }
 }
@@ -0,0 +1,189 @@
+//! # Gaussian goodness of fit
+//!
+//! The [`DiagGaussian`] plugin assumes the points a node covers are normally distributed along
+//! each coordinate. This plugin checks that assumption per node, with the skewness and excess
+//! kurtosis of each coordinate and the fraction of values more than 3 standard deviations from
+//! the mean. For normal data all three are close to 0, the last is about 0.0027. Large values mark
+//! the nodes where the Gaussian's likelihoods shouldn't be relied on.
+
+use super::*;
+use crate::covertree::node::CoverNode;
+use crate::covertree::CoverTreeReader;
+use pointcloud::pc_errors::PointCloudResult;
+use serde::{Deserialize, Serialize};
+
+/// The fraction of a normal distribution more than 3 standard deviations from the mean.
+pub const NORMAL_OUTSIDE_3_SIGMA: f32 = 0.0027;
+
+/// Node component with the per coordinate normality diagnostics of the points under a node.
+#[derive(Debug, Clone, Default)]
+pub struct GaussianFit {
+    /// The skewness of each coordinate, 0 for a normal distribution
+    pub skewness: Vec<f32>,
+    /// The excess kurtosis of each coordinate, 0 for a normal distribution
+    pub excess_kurtosis: Vec<f32>,
+    /// The fraction of each coordinate's values more than 3 standard deviations from the mean
+    pub outside_3_sigma: Vec<f32>,
+    /// The number of points these were computed from
+    pub count: usize,
+}
+
+impl GaussianFit {
+    /// Computes the diagnostics from a set of points. Coordinates with no variance get 0 for all
+    /// three.
+    pub fn from_points<T: PointRef>(points: &[T], dim: usize) -> GaussianFit {
+        let count = points.len();
+        let mut mean = vec![0.0f64; dim];
+        for point in points {
+            mean.iter_mut()
+                .zip(point.dense_iter())
+                .for_each(|(m, x)| *m += x as f64);
+        }
+        mean.iter_mut().for_each(|m| *m /= count.max(1) as f64);
+
+        let mut moment2 = vec![0.0f64; dim];
+        let mut moment3 = vec![0.0f64; dim];
+        let mut moment4 = vec![0.0f64; dim];
+        for point in points {
+            for (i, x) in point.dense_iter().enumerate() {
+                let d = x as f64 - mean[i];
+                moment2[i] += d * d;
+                moment3[i] += d * d * d;
+                moment4[i] += d * d * d * d;
+            }
+        }
+
+        let mut skewness = vec![0.0; dim];
+        let mut excess_kurtosis = vec![0.0; dim];
+        let mut std_dev = vec![0.0; dim];
+        for (i, m2) in moment2.iter().enumerate() {
+            let var = m2 / count.max(1) as f64;
+            if var > 0.0 {
+                skewness[i] = (moment3[i] / count as f64 / var.powf(1.5)) as f32;
+                excess_kurtosis[i] = (moment4[i] / count as f64 / (var * var) - 3.0) as f32;
+                std_dev[i] = var.sqrt();
+            }
+        }
+
+        let mut outside_3_sigma = vec![0.0; dim];
+        for point in points {
+            for (i, x) in point.dense_iter().enumerate() {
+                if std_dev[i] > 0.0 && (x as f64 - mean[i]).abs() > 3.0 * std_dev[i] {
+                    outside_3_sigma[i] += 1.0;
+                }
+            }
+        }
+        outside_3_sigma
+            .iter_mut()
+            .for_each(|o| *o /= count.max(1) as f32);
+
+        GaussianFit {
+            skewness,
+            excess_kurtosis,
+            outside_3_sigma,
+            count,
+        }
+    }
+
+    /// The largest absolute skewness over the coordinates
+    pub fn max_abs_skewness(&self) -> f32 {
+        self.skewness.iter().fold(0.0f32, |a, s| a.max(s.abs()))
+    }
+
+    /// The largest absolute excess kurtosis over the coordinates
+    pub fn max_abs_excess_kurtosis(&self) -> f32 {
+        self.excess_kurtosis
+            .iter()
+            .fold(0.0f32, |a, k| a.max(k.abs()))
+    }
+
+    /// The largest fraction of values more than 3 standard deviations out over the coordinates
+    pub fn max_outside_3_sigma(&self) -> f32 {
+        self.outside_3_sigma.iter().fold(0.0f32, |a, o| a.max(*o))
+    }
+}
+
+impl<D: PointCloud> NodePlugin<D> for GaussianFit {}
+
+/// Builds a [`GaussianFit`] for every node, over the same points as the matching
+/// [`GokoDiagGaussian`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GokoGaussianFit {
+    recursive: bool,
+}
+
+impl GokoGaussianFit {
+    /// Checks the points of the total cover space of each node.
+    pub fn recursive() -> Self {
+        Self { recursive: true }
+    }
+
+    /// Checks just the singletons attached to each node, and the center of leaves.
+    pub fn singletons() -> Self {
+        Self { recursive: false }
+    }
+}
+
+impl<D: PointCloud> PersistentPlugin<D> for GokoGaussianFit {
+    const NAME: &'static str = "gaussian_fit";
+}
+
+impl<D: PointCloud> GokoPlugin<D> for GokoGaussianFit {
+    type NodeComponent = GaussianFit;
+    fn node_component(
+        parameters: &Self,
+        my_node: &CoverNode<D>,
+        my_tree: &CoverTreeReader<D>,
+    ) -> Option<Self::NodeComponent> {
+        let indexes = gaussian_point_indexes(my_node, my_tree, parameters.recursive);
+        let point_cloud = &my_tree.parameters().point_cloud;
+        let points = indexes
+            .iter()
+            .map(|i| point_cloud.point(*i))
+            .collect::<PointCloudResult<Vec<_>>>()
+            .ok()?;
+        Some(GaussianFit::from_points(&points, point_cloud.dim()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::covertree::tests::build_basic_tree;
+
+    #[test]
+    fn symmetric_points_fit() {
+        let data: Vec<[f32; 1]> = vec![[-1.0], [0.0], [1.0]];
+        let points: Vec<&[f32]> = data.iter().map(|p| &p[..]).collect();
+        let fit = GaussianFit::from_points(&points, 1);
+        assert_eq!(fit.count, 3);
+        assert_approx_eq!(fit.skewness[0], 0.0);
+        // Three evenly spaced points are lighter tailed than a normal distribution
+        assert_approx_eq!(fit.excess_kurtosis[0], -1.5);
+        assert_approx_eq!(fit.outside_3_sigma[0], 0.0);
+    }
+
+    #[test]
+    fn outlier_is_caught() {
+        let mut data: Vec<[f32; 1]> = (0..20).map(|i| [(i % 2) as f32]).collect();
+        data.push([100.0]);
+        let points: Vec<&[f32]> = data.iter().map(|p| &p[..]).collect();
+        let fit = GaussianFit::from_points(&points, 1);
+        assert!(fit.max_abs_skewness() > 3.0);
+        assert!(fit.max_abs_excess_kurtosis() > 10.0);
+        assert_approx_eq!(fit.max_outside_3_sigma(), 1.0 / 21.0);
+    }
+
+    #[test]
+    fn recursive_fit_counts_cover() {
+        let mut tree = build_basic_tree();
+        tree.add_plugin::<GokoGaussianFit>(GokoGaussianFit::recursive());
+        let reader = tree.reader();
+        for (_, layer) in reader.layers() {
+            layer.for_each_node(|_, n| {
+                let count = n.get_plugin_and::<GaussianFit, _, _>(|p| p.count).unwrap();
+                assert_eq!(count, n.coverage_count(), "Node: {:?}", n.address());
+            });
+        }
+    }
+}
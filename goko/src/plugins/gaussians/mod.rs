@@ -4,11 +4,15 @@
 //! It also has trackers used to see when queries and sequences are out of distribution.
 
 use super::*;
+use crate::covertree::node::CoverNode;
+use crate::covertree::CoverTreeReader;
 use rand::Rng;
 use std::fmt::Debug;
 
 mod diag_gaussian;
 pub use diag_gaussian::*;
+mod goodness_of_fit;
+pub use goodness_of_fit::*;
 
 /*
 There's an issue with rust-numpy and ndarray causing the linear algebra package for ndarray to fail.
@@ -21,6 +25,38 @@ pub use svd_gaussian::*;
 
 use pointcloud::PointRef;
 
+/// The points a gaussian plugin's node component is estimated from. These are the singletons of
+/// the node and the center of leaves, or the whole cover space of the node if `recursive`.
+pub(crate) fn gaussian_point_indexes<D: PointCloud>(
+    my_node: &CoverNode<D>,
+    my_tree: &CoverTreeReader<D>,
+    recursive: bool,
+) -> Vec<usize> {
+    let mut indexes = my_node.singletons_decoded().into_owned();
+    match my_node.children() {
+        Some((nested_scale, child_addresses)) => {
+            if recursive {
+                let mut unvisited = vec![(nested_scale, *my_node.center_index())];
+                unvisited.extend(child_addresses);
+                while let Some(address) = unvisited.pop() {
+                    my_tree.get_node_and(address, |n| {
+                        indexes.extend(n.singletons_iter());
+                        match n.children() {
+                            Some((nested_scale, child_addresses)) => {
+                                unvisited.push((nested_scale, *n.center_index()));
+                                unvisited.extend(child_addresses);
+                            }
+                            None => indexes.push(*n.center_index()),
+                        }
+                    });
+                }
+            }
+        }
+        None => indexes.push(*my_node.center_index()),
+    }
+    indexes
+}
+
 ///
 pub trait ContinousDistribution: Clone + 'static {
     /// Pass none if you want to test for a singleton, returns 0 if
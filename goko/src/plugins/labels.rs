@@ -4,6 +4,7 @@ use super::*;
 use crate::covertree::node::CoverNode;
 use crate::covertree::CoverTreeReader;
 //use pointcloud::*;
+use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 
 /// Wrapper around the summary found in the point cloud
@@ -24,9 +25,13 @@ impl<T: Summary + Clone> Clone for NodeLabelSummary<T> {
 impl<D: PointCloud> NodePlugin<D> for NodeLabelSummary<D::LabelSummary> {}
 
 /// Plug in that allows for summaries of labels to be attached to
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct LabelSummaryPlugin {}
 
+impl<D: PointCloud> PersistentPlugin<D> for LabelSummaryPlugin {
+    const NAME: &'static str = "label_summaries";
+}
+
 impl<D: PointCloud> GokoPlugin<D> for LabelSummaryPlugin {
     type NodeComponent = NodeLabelSummary<D::LabelSummary>;
     fn node_component(
@@ -37,7 +42,7 @@ impl<D: PointCloud> GokoPlugin<D> for LabelSummaryPlugin {
         let mut bucket = my_tree
             .parameters()
             .point_cloud
-            .label_summary(my_node.singletons())
+            .label_summary(&my_node.singletons_decoded())
             .unwrap();
         // If we're a routing node then grab the childen's values
         if let Some((nested_scale, child_addresses)) = my_node.children() {
@@ -52,12 +57,13 @@ impl<D: PointCloud> GokoPlugin<D> for LabelSummaryPlugin {
                 });
             }
         } else {
-            bucket.add(
-                my_tree
-                    .parameters()
-                    .point_cloud
-                    .label(*my_node.center_index()),
-            );
+            // Through the summary so deleted and collapsed centers are counted right
+            let center_summary = my_tree
+                .parameters()
+                .point_cloud
+                .label_summary(&[*my_node.center_index()])
+                .unwrap();
+            bucket.combine(&center_summary);
         }
         Some(NodeLabelSummary {
             summary: Arc::new(bucket),
@@ -83,9 +89,13 @@ impl<T: Summary + Clone> Clone for NodeMetaSummary<T> {
 impl<D: PointCloud> NodePlugin<D> for NodeMetaSummary<D::MetaSummary> {}
 
 /// Plug in that allows for summaries of Metas to be attached to
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct MetaSummaryPlugin {}
 
+impl<D: PointCloud> PersistentPlugin<D> for MetaSummaryPlugin {
+    const NAME: &'static str = "meta_summaries";
+}
+
 impl<D: PointCloud> GokoPlugin<D> for MetaSummaryPlugin {
     type NodeComponent = NodeMetaSummary<D::MetaSummary>;
     fn node_component(
@@ -96,7 +106,7 @@ impl<D: PointCloud> GokoPlugin<D> for MetaSummaryPlugin {
         let mut bucket = my_tree
             .parameters()
             .point_cloud
-            .metasummary(my_node.singletons())
+            .metasummary(&my_node.singletons_decoded())
             .unwrap();
         // If we're a routing node then grab the childen's values
         if let Some((nested_scale, child_addresses)) = my_node.children() {
@@ -111,12 +121,13 @@ impl<D: PointCloud> GokoPlugin<D> for MetaSummaryPlugin {
                 });
             }
         } else {
-            bucket.add(
-                my_tree
-                    .parameters()
-                    .point_cloud
-                    .metadata(*my_node.center_index()),
-            );
+            // Through the summary so deleted and collapsed centers are counted right
+            let center_summary = my_tree
+                .parameters()
+                .point_cloud
+                .metasummary(&[*my_node.center_index()])
+                .unwrap();
+            bucket.combine(&center_summary);
         }
         Some(NodeMetaSummary {
             summary: Arc::new(bucket),
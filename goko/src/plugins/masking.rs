@@ -0,0 +1,70 @@
+//! Plugin that bounds each node's spread in every dimension, for queries over some of the dimensions, see
+//! [`pointcloud::metrics::masked`].
+//!
+//! A node's radius under the full metric bounds its radius under a masked one, so masked queries work without this.
+//! When only a few of many dimensions are masked in that bound is loose, and almost nothing gets pruned. With this
+//! attached the masked radius of a node is computed from the spread of its points in the masked dimensions alone. See
+//! [`CoverTreeReader::knn_masked`].
+
+use super::*;
+use crate::covertree::node::CoverNode;
+use crate::covertree::CoverTreeReader;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// The largest distance in each dimension from a node's center to a point it covers. This is an upper bound, a
+/// routing node's is built from its children's.
+#[derive(Debug, Clone, Default)]
+pub struct NodeDeviations {
+    /// The bounds, one per dimension
+    pub deviations: Arc<Vec<f32>>,
+}
+
+impl<D: PointCloud> NodePlugin<D> for NodeDeviations {}
+
+/// Plug in that attaches the per dimension bounds to every node.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MaskingPlugin {}
+
+impl<D: PointCloud<Point = [f32]>> PersistentPlugin<D> for MaskingPlugin {
+    const NAME: &'static str = "masking";
+}
+
+impl<D: PointCloud<Point = [f32]>> GokoPlugin<D> for MaskingPlugin {
+    type NodeComponent = NodeDeviations;
+    fn node_component(
+        _parameters: &Self,
+        my_node: &CoverNode<D>,
+        my_tree: &CoverTreeReader<D>,
+    ) -> Option<Self::NodeComponent> {
+        let point_cloud = &my_tree.parameters().point_cloud;
+        let center = point_cloud.point(*my_node.center_index()).ok()?;
+        let mut deviations = vec![0.0f32; center.len()];
+        // If we're a routing node then grab the childen's values, shifted by the distance between the centers
+        if let Some((nested_scale, child_addresses)) = my_node.children() {
+            let nested_address = (nested_scale, *my_node.center_index());
+            for address in std::iter::once(&nested_address).chain(child_addresses) {
+                let child_center = point_cloud.point(address.1).ok()?;
+                my_tree.get_node_plugin_and::<Self::NodeComponent, _, _>(*address, |p| {
+                    for (((d, cd), x), c) in deviations
+                        .iter_mut()
+                        .zip(p.deviations.iter())
+                        .zip(child_center.iter())
+                        .zip(center.iter())
+                    {
+                        *d = d.max(cd + (x - c).abs());
+                    }
+                })?;
+            }
+        }
+        for pi in my_node.singletons_iter() {
+            let point = point_cloud.point(pi).ok()?;
+            for ((d, x), c) in deviations.iter_mut().zip(point.iter()).zip(center.iter()) {
+                *d = d.max((x - c).abs());
+            }
+        }
+        Some(NodeDeviations {
+            deviations: Arc::new(deviations),
+        })
+    }
+}
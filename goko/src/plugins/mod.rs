@@ -8,16 +8,27 @@
 //! plugin for the child nodes.
 //!
 //! None of this is parallelized. We need to move to Tokio to take advantage of the async computation there to || it.
+//!
+//! Plugins that implement `PersistentPlugin` are saved with the tree, see [`CoverTreeWriter::save`]. Only their parameters
+//! are written, the node components are rebuilt from them when the tree is loaded.
 
 use crate::covertree::node::CoverNode;
 use crate::covertree::CoverTreeReader;
+use crate::errors::{GokoError, GokoResult};
 use crate::*;
+use hashbrown::HashMap;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 use std::fmt::Debug;
 use type_map::concurrent::TypeMap;
 
+pub mod calibration;
+pub mod coverage_drift;
 pub mod discrete;
 pub mod gaussians;
 pub mod labels;
+pub mod masking;
+pub mod partitions;
 pub mod utils;
 
 /// Mockup for the plugin interface attached to the node. These are meant to be functions that Goko uses to maintain the plugin.
@@ -37,6 +48,84 @@ pub trait GokoPlugin<D: PointCloud>: Send + Sync + Debug + Clone + 'static {
     ) -> Option<Self::NodeComponent>;
 }
 
+/// For plugins that only store something for the whole tree.
+impl<D: PointCloud> NodePlugin<D> for () {}
+
+/// A plugin whose parameters are saved with the tree. Attach it with [`CoverTreeWriter::add_persistent_plugin`] and
+/// register it on the [`PluginRegistry`] the tree is loaded with, the built in ones are registered by default.
+pub trait PersistentPlugin<D: PointCloud>: GokoPlugin<D> + Serialize + DeserializeOwned {
+    /// The name the parameters are saved under, this has to be unique among the plugins of a tree.
+    const NAME: &'static str;
+}
+
+type PluginLoader<D> = Box<dyn Fn(&mut CoverTreeWriter<D>, &str) -> GokoResult<()> + Send + Sync>;
+
+/// The plugins a saved tree can have, by name. When a tree is loaded its plugins are attached again, in the order they
+/// were first attached, from the parameters that were saved.
+///
+/// The default registry has the built in plugins that work on any point cloud. The
+/// [`masking::MaskingPlugin`] only works on dense clouds, register it yourself for those.
+pub struct PluginRegistry<D: PointCloud> {
+    loaders: HashMap<&'static str, PluginLoader<D>>,
+}
+
+impl<D: PointCloud> Default for PluginRegistry<D> {
+    fn default() -> Self {
+        let mut registry = PluginRegistry::empty();
+        registry
+            .register::<calibration::DistanceCalibration>()
+            .register::<discrete::categorical::GokoCategorical>()
+            .register::<discrete::dirichlet::GokoDirichlet>()
+            .register::<gaussians::GokoDiagGaussian>()
+            .register::<gaussians::GokoGaussianFit>()
+            .register::<labels::LabelSummaryPlugin>()
+            .register::<labels::MetaSummaryPlugin>()
+            .register::<partitions::PartitionPlugin>()
+            .register::<utils::GokoCoverageIndexes>();
+        registry
+    }
+}
+
+impl<D: PointCloud> PluginRegistry<D> {
+    /// A registry with every built in persistent plugin.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A registry without any plugins, a tree loaded with it has none.
+    pub fn empty() -> Self {
+        PluginRegistry {
+            loaders: HashMap::new(),
+        }
+    }
+
+    /// Adds a plugin, replacing any registered under the same name.
+    pub fn register<P: PersistentPlugin<D>>(&mut self) -> &mut Self {
+        self.loaders.insert(
+            P::NAME,
+            Box::new(|tree: &mut CoverTreeWriter<D>, parameters: &str| {
+                let plug_in: P = serde_json::from_str(parameters)
+                    .map_err(|e| GokoError::PluginStateError(e.to_string()))?;
+                tree.add_persistent_plugin(plug_in)
+            }),
+        );
+        self
+    }
+
+    /// Attaches a saved plugin to the tree.
+    pub(crate) fn load(
+        &self,
+        tree: &mut CoverTreeWriter<D>,
+        name: &str,
+        parameters: &str,
+    ) -> GokoResult<()> {
+        match self.loaders.get(name) {
+            Some(loader) => loader(tree, parameters),
+            None => Err(GokoError::UnknownPlugin(name.to_string())),
+        }
+    }
+}
+
 pub(crate) type NodePluginSet = TypeMap;
 pub(crate) type TreePluginSet = TypeMap;
 
@@ -0,0 +1,73 @@
+//! Plugin that counts the points of each partition under every node, see [`pointcloud::partitions`].
+//!
+//! With this attached, queries restricted to some partitions skip the nodes that cover none of their points. See
+//! [`CoverTreeReader::knn_in_partitions`].
+
+use super::*;
+use crate::covertree::node::CoverNode;
+use crate::covertree::CoverTreeReader;
+use hashbrown::HashMap;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// The number of points of each partition a node covers, by partition id. Deleted points and points in no
+/// partition aren't counted.
+#[derive(Debug, Clone, Default)]
+pub struct NodePartitionCounts {
+    /// The counts, refenced counted to eliminate duplicates
+    pub counts: Arc<HashMap<u32, usize>>,
+}
+
+impl NodePartitionCounts {
+    /// The number of points under the node that are in any of the partitions.
+    pub fn count_in(&self, ids: &[u32]) -> usize {
+        ids.iter().filter_map(|id| self.counts.get(id)).sum()
+    }
+}
+
+impl<D: PointCloud> NodePlugin<D> for NodePartitionCounts {}
+
+/// Plug in that attaches the partition counts to every node. Nodes of trees on clouds without partitions get none.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PartitionPlugin {}
+
+impl<D: PointCloud> PersistentPlugin<D> for PartitionPlugin {
+    const NAME: &'static str = "partitions";
+}
+
+impl<D: PointCloud> GokoPlugin<D> for PartitionPlugin {
+    type NodeComponent = NodePartitionCounts;
+    fn node_component(
+        _parameters: &Self,
+        my_node: &CoverNode<D>,
+        my_tree: &CoverTreeReader<D>,
+    ) -> Option<Self::NodeComponent> {
+        let point_cloud = &my_tree.parameters().point_cloud;
+        let partitions = point_cloud.partitions()?;
+        let mut counts: HashMap<u32, usize> = HashMap::new();
+        let mut points = my_node.singletons_decoded().into_owned();
+        // If we're a routing node then grab the childen's values
+        if let Some((nested_scale, child_addresses)) = my_node.children() {
+            let nested_address = (nested_scale, *my_node.center_index());
+            for address in std::iter::once(&nested_address).chain(child_addresses) {
+                my_tree.get_node_plugin_and::<Self::NodeComponent, _, _>(*address, |p| {
+                    for (id, count) in p.counts.iter() {
+                        *counts.entry(*id).or_insert(0) += count;
+                    }
+                });
+            }
+        } else {
+            points.push(*my_node.center_index());
+        }
+        for pi in points {
+            if !point_cloud.is_deleted(pi) {
+                if let Some(id) = partitions.partition(pi) {
+                    *counts.entry(id).or_insert(0) += 1;
+                }
+            }
+        }
+        Some(NodePartitionCounts {
+            counts: Arc::new(counts),
+        })
+    }
+}
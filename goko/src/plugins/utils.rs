@@ -4,6 +4,7 @@ use super::*;
 use crate::covertree::node::CoverNode;
 use crate::covertree::CoverTreeReader;
 //use pointcloud::*;
+use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 
 /// Contains all points that this node covers, if the coverage is lower than the limit set in the parameters.
@@ -22,7 +23,7 @@ impl CoverageIndexes {
 }
 
 /// A plugin that helps gather all the indexes that the node covers into an array you can use.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GokoCoverageIndexes {
     /// The actual limit
     pub max: usize,
@@ -40,6 +41,10 @@ impl GokoCoverageIndexes {
     }
 }
 
+impl<D: PointCloud> PersistentPlugin<D> for GokoCoverageIndexes {
+    const NAME: &'static str = "coverage_indexes";
+}
+
 impl<D: PointCloud> GokoPlugin<D> for GokoCoverageIndexes {
     type NodeComponent = CoverageIndexes;
     fn node_component(
@@ -48,7 +53,7 @@ impl<D: PointCloud> GokoPlugin<D> for GokoCoverageIndexes {
         my_tree: &CoverTreeReader<D>,
     ) -> Option<Self::NodeComponent> {
         if my_node.coverage_count() < parameters.max {
-            let mut indexes = my_node.singletons().to_vec();
+            let mut indexes = my_node.singletons_decoded().into_owned();
             // If we're a routing node then grab the childen's values
             if let Some((nested_scale, child_addresses)) = my_node.children() {
                 my_tree.get_node_plugin_and::<Self::NodeComponent, _, _>(
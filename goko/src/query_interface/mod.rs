@@ -1,20 +1,46 @@
 //! Interfacees that simplify bulk queries
 
 //use crossbeam_channel::unbounded;
+use crate::scheduler::PoolHandle;
 use crate::*;
 use ndarray::ArrayView2;
 use rayon::iter::repeatn;
+use std::collections::HashMap;
 use std::ops::Deref;
 
 /// Inteface for bulk queries. Handles cloning the readers for you
 pub struct BulkInterface<D: PointCloud> {
     reader: CoverTreeReader<D>,
+    thread_pool: Option<PoolHandle>,
 }
 
 impl<D: PointCloud> BulkInterface<D> {
     /// Creates a new one.
     pub fn new(reader: CoverTreeReader<D>) -> Self {
-        BulkInterface { reader }
+        BulkInterface {
+            reader,
+            thread_pool: None,
+        }
+    }
+
+    /// Creates a new one that runs its queries on a [`crate::scheduler::SharedPool`]. Each bulk
+    /// call takes one slot on the shared pool.
+    pub fn with_thread_pool(reader: CoverTreeReader<D>, thread_pool: PoolHandle) -> Self {
+        BulkInterface {
+            reader,
+            thread_pool: Some(thread_pool),
+        }
+    }
+
+    fn run<OP, R>(&self, op: OP) -> R
+    where
+        OP: FnOnce() -> R + Send,
+        R: Send,
+    {
+        match &self.thread_pool {
+            Some(handle) => handle.install(op),
+            None => op(),
+        }
     }
 
     /// Applies the passed in fn to the passed in indexes and collects the result in a vector. Core function for this struct.
@@ -23,12 +49,17 @@ impl<D: PointCloud> BulkInterface<D> {
         F: Fn(&CoverTreeReader<D>, usize) -> T + Send + Sync,
         T: Send + Sync,
     {
-        let indexes_iter = point_indexes.par_chunks(100);
-        let reader_copies = indexes_iter.len();
-        let mut chunked_results: Vec<Vec<T>> = indexes_iter
-            .zip(repeatn(self.reader.clone(), reader_copies))
-            .map(|(chunk_indexes, reader)| chunk_indexes.iter().map(|p| f(&reader, *p)).collect())
-            .collect();
+        let reader = self.reader.clone();
+        let mut chunked_results: Vec<Vec<T>> = self.run(|| {
+            let indexes_iter = point_indexes.par_chunks(100);
+            let reader_copies = indexes_iter.len();
+            indexes_iter
+                .zip(repeatn(reader, reader_copies))
+                .map(|(chunk_indexes, reader)| {
+                    chunk_indexes.iter().map(|p| f(&reader, *p)).collect()
+                })
+                .collect()
+        });
         chunked_results
             .drain(..)
             .reduce(|mut a, mut x| {
@@ -48,12 +79,15 @@ impl<D: PointCloud> BulkInterface<D> {
         F: Fn(&CoverTreeReader<D>, &P) -> T + Send + Sync,
         T: Send + Sync,
     {
-        let point_iter = points.par_chunks(100);
-        let reader_copies = point_iter.len();
-        let mut chunked_results: Vec<Vec<T>> = point_iter
-            .zip(repeatn(self.reader.clone(), reader_copies))
-            .map(|(chunk_points, reader)| chunk_points.iter().map(|p| f(&reader, p)).collect())
-            .collect();
+        let reader = self.reader.clone();
+        let mut chunked_results: Vec<Vec<T>> = self.run(|| {
+            let point_iter = points.par_chunks(100);
+            let reader_copies = point_iter.len();
+            point_iter
+                .zip(repeatn(reader, reader_copies))
+                .map(|(chunk_points, reader)| chunk_points.iter().map(|p| f(&reader, p)).collect())
+                .collect()
+        });
         chunked_results
             .drain(..)
             .reduce(|mut a, mut x| {
@@ -102,6 +136,59 @@ impl<D: PointCloud> BulkInterface<D> {
     ) -> Vec<GokoResult<Vec<(f32, usize)>>> {
         self.point_map_with_reader(points, |reader, p| reader.routing_knn(p, k))
     }
+
+    /// The `k` nearest neighbors of every point in the tree, not counting the point itself. The results are in the
+    /// order of the point cloud's `reference_indexes`. This is the dual-tree `CoverTreeReader::all_knn`, run on this
+    /// interface's pool.
+    pub fn all_knn(&self, k: usize) -> GokoResult<Vec<Vec<(f32, usize)>>> {
+        let reader = self.reader.clone();
+        let knn = self.run(move || reader.all_knn(k))?;
+        Ok(knn
+            .iter()
+            .map(|(dists, indexes)| dists.iter().copied().zip(indexes.iter().copied()).collect())
+            .collect())
+    }
+
+    /// All pairs of points that are each in the other's `k` nearest neighbors, as `(i, j, dist)` with `i < j`. The
+    /// pairs are sorted by distance, closest first. This is the mutual kNN graph, the core of agglomerative
+    /// clustering and deduplication. Ties at the `k`th neighbor are broken by index, like `knn`.
+    pub fn mutual_knn_pairs(&self, k: usize) -> GokoResult<Vec<(usize, usize, f32)>> {
+        let point_indexes = self.reader.point_cloud().reference_indexes();
+        let neighbors = self.all_knn(k)?;
+        let positions: HashMap<usize, usize> = point_indexes
+            .iter()
+            .enumerate()
+            .map(|(position, i)| (*i, position))
+            .collect();
+        let mut pairs: Vec<(usize, usize, f32)> = point_indexes
+            .iter()
+            .zip(&neighbors)
+            .flat_map(|(i, i_neighbors)| {
+                i_neighbors
+                    .iter()
+                    .filter(|(_, j)| i < j)
+                    .filter(|(_, j)| {
+                        positions
+                            .get(j)
+                            .map(|p| neighbors[*p].iter().any(|(_, l)| l == i))
+                            .unwrap_or(false)
+                    })
+                    .map(move |(d, j)| (*i, *j, *d))
+            })
+            .collect();
+        pairs.sort_by(|a, b| {
+            a.2.partial_cmp(&b.2)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then((a.0, a.1).cmp(&(b.0, b.1)))
+        });
+        Ok(pairs)
+    }
+
+    /// All pairs of points that are each other's nearest neighbor, see `mutual_knn_pairs`. Each point is in at most
+    /// one pair.
+    pub fn reciprocal_nn_pairs(&self) -> GokoResult<Vec<(usize, usize, f32)>> {
+        self.mutual_knn_pairs(1)
+    }
 }
 
 impl<D: PointCloud<Point = [f32]>> BulkInterface<D> {
@@ -112,18 +199,20 @@ impl<D: PointCloud<Point = [f32]>> BulkInterface<D> {
         T: Send + Sync,
     {
         let indexes: Vec<usize> = (0..points.nrows()).collect();
-        let point_iter = indexes.par_chunks(100);
-        let reader_copies = point_iter.len();
-
-        let mut chunked_results: Vec<Vec<T>> = point_iter
-            .zip(repeatn(self.reader.clone(), reader_copies))
-            .map(|(chunk_points, reader)| {
-                chunk_points
-                    .iter()
-                    .map(|i| f(&reader, &points.row(*i).as_slice().unwrap()))
-                    .collect()
-            })
-            .collect();
+        let reader = self.reader.clone();
+        let mut chunked_results: Vec<Vec<T>> = self.run(|| {
+            let point_iter = indexes.par_chunks(100);
+            let reader_copies = point_iter.len();
+            point_iter
+                .zip(repeatn(reader, reader_copies))
+                .map(|(chunk_points, reader)| {
+                    chunk_points
+                        .iter()
+                        .map(|i| f(&reader, &points.row(*i).as_slice().unwrap()))
+                        .collect()
+                })
+                .collect()
+        });
         chunked_results
             .drain(..)
             .reduce(|mut a, mut x| {
@@ -137,9 +226,12 @@ impl<D: PointCloud<Point = [f32]>> BulkInterface<D> {
 #[cfg(test)]
 pub(crate) mod tests {
     use super::*;
+    use crate::scheduler::{SharedPool, TenantSettings};
     use std::env;
 
     use crate::covertree::tests::build_mnist_tree;
+    use pointcloud::{metrics::L2, DefaultCloud};
+    use std::sync::Arc;
 
     #[test]
     fn bulk_path() {
@@ -182,4 +274,67 @@ pub(crate) mod tests {
             }
         }
     }
+
+    #[test]
+    fn mutual_pairs_match_brute_force() {
+        // No two pairs are the same distance apart, so there are no ties to break
+        let data = vec![0.0, 0.1, 0.25, 0.7, 0.72, 1.5, -0.33, 2.9];
+        let point_cloud = Arc::new(DefaultCloud::<L2>::new(data, 1).unwrap());
+        let mut builder = CoverTreeBuilder::new();
+        builder.set_min_res_index(-9).set_rng_seed(0);
+        let writer = builder.build(point_cloud).unwrap();
+        let reader = writer.reader();
+        let interface = BulkInterface::new(writer.reader());
+        let cloud = reader.point_cloud();
+        let n = cloud.len();
+        let brute_knn = |i: usize, k: usize| -> Vec<usize> {
+            let mut dists: Vec<(f32, usize)> = (0..n)
+                .filter(|j| *j != i)
+                .map(|j| (cloud.distances_to_point_index(i, &[j]).unwrap()[0], j))
+                .collect();
+            dists.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            dists.iter().take(k).map(|(_, j)| *j).collect()
+        };
+        for k in 1..n {
+            let pairs = interface.mutual_knn_pairs(k).unwrap();
+            let mut expected = Vec::new();
+            for i in 0..n {
+                for j in brute_knn(i, k) {
+                    if i < j && brute_knn(j, k).contains(&i) {
+                        expected.push((i, j));
+                    }
+                }
+            }
+            let mut found: Vec<(usize, usize)> = pairs.iter().map(|(i, j, _)| (*i, *j)).collect();
+            found.sort_unstable();
+            assert_eq!(found, expected, "k {}", k);
+            assert!(pairs.windows(2).all(|w| w[0].2 <= w[1].2));
+        }
+        let reciprocal = interface.reciprocal_nn_pairs().unwrap();
+        assert_eq!(reciprocal, interface.mutual_knn_pairs(1).unwrap());
+    }
+
+    #[test]
+    fn bulk_knn_on_shared_pool() {
+        if env::var("TRAVIS_RUST_VERSION").is_err() {
+            let tree = build_mnist_tree();
+            let reader = tree.reader();
+            let pool = SharedPool::new(2, 1).unwrap();
+            let handle = PoolHandle::new(&pool, TenantSettings::default());
+            let interface = BulkInterface::with_thread_pool(tree.reader(), handle);
+            let cloud = reader.point_cloud();
+
+            let points: Vec<&[f32]> = (0..10).map(|i| cloud.point(i).unwrap()).collect();
+
+            let knn_results = interface.knn(&points, 5);
+            for (i, knn) in knn_results.iter().enumerate() {
+                let old_knn = reader.knn(&cloud.point(i).unwrap(), 5).unwrap();
+                for ((d1, a1), (d2, a2)) in (knn.as_ref().unwrap()).iter().zip(old_knn) {
+                    assert_approx_eq!(*d1, d2);
+                    assert_eq!(*a1, a2);
+                }
+            }
+            assert_eq!(pool.running_jobs(), 0);
+        }
+    }
 }
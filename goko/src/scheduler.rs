@@ -0,0 +1,535 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! Sharing one bounded thread pool between many trees.
+//!
+//! By default every build and bulk query runs on rayon's global pool, and a process that hosts
+//! many trees either shares that pool with no say in who goes first, or gives each tree its own
+//! pool and oversubscribes the CPUs. A [`SharedPool`] owns a single bounded rayon pool and hands
+//! out [`PoolHandle`]s, one per tree. Work submitted through a handle waits for one of the pool's
+//! job slots. When slots free up they go to the waiting tree with the highest priority, then to the
+//! tree with the fewest jobs already running, then first come first served.
+//!
+//! Work also comes in two classes. Foreground work, like queries, always goes ahead of background
+//! work, like regenerating summaries or recomputing plugins. Background work never takes the last
+//! free slot of a pool with more than one slot, and long background jobs call
+//! [`PoolSlot::yield_to_foreground`] between steps to hand their slot over when queries are waiting.
+//!
+//! ```rust,no_run
+//! # use goko::scheduler::*;
+//! let pool = SharedPool::new(4, 2).unwrap();
+//! let important = PoolHandle::new(&pool, TenantSettings { priority: 10, max_concurrent: 0 });
+//! let background = PoolHandle::new(&pool, TenantSettings::default());
+//! let total: u64 = important.install(|| (0..1000u64).sum());
+//! ```
+
+use crate::errors::GokoResult;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+
+/// The per tree scheduling settings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TenantSettings {
+    /// Waiting work from trees with a higher priority is started first.
+    pub priority: i32,
+    /// The most jobs this tree may run at once, 0 means it can use every slot in the pool.
+    pub max_concurrent: usize,
+}
+
+impl Default for TenantSettings {
+    fn default() -> TenantSettings {
+        TenantSettings {
+            priority: 0,
+            max_concurrent: 0,
+        }
+    }
+}
+
+/// The two classes of work on a [`SharedPool`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum WorkClass {
+    /// Latency sensitive work, like queries. Always scheduled before background work.
+    Foreground,
+    /// Maintenance, like summary regeneration, that should yield to foreground work.
+    Background,
+}
+
+#[derive(Debug)]
+struct Waiter {
+    ticket: u64,
+    tenant: usize,
+    priority: i32,
+    class: WorkClass,
+}
+
+#[derive(Debug, Default)]
+struct PoolState {
+    running: usize,
+    running_background: usize,
+    running_per_tenant: HashMap<usize, usize>,
+    waiting: Vec<Waiter>,
+    next_ticket: u64,
+}
+
+impl PoolState {
+    fn tenant_running(&self, tenant: usize) -> usize {
+        self.running_per_tenant.get(&tenant).copied().unwrap_or(0)
+    }
+
+    fn foreground_waiting(&self) -> bool {
+        self.waiting
+            .iter()
+            .any(|w| w.class == WorkClass::Foreground)
+    }
+
+    /// The ticket of the waiter that gets the next free slot, ignoring trees that are at their cap
+    /// and background work when it has used up its slots.
+    fn next_in_line(&self, caps: &HashMap<usize, usize>, background_jobs: usize) -> Option<u64> {
+        self.waiting
+            .iter()
+            .filter(|w| match caps.get(&w.tenant) {
+                Some(&cap) if cap > 0 => self.tenant_running(w.tenant) < cap,
+                _ => true,
+            })
+            .filter(|w| {
+                w.class == WorkClass::Foreground || self.running_background < background_jobs
+            })
+            .min_by_key(|w| {
+                (
+                    w.class,
+                    -(w.priority as i64),
+                    self.tenant_running(w.tenant),
+                    w.ticket,
+                )
+            })
+            .map(|w| w.ticket)
+    }
+}
+
+/// A bounded rayon pool shared between trees. See the [module docs](self).
+#[derive(Debug)]
+pub struct SharedPool {
+    pool: rayon::ThreadPool,
+    max_jobs: usize,
+    state: Mutex<PoolState>,
+    caps: Mutex<HashMap<usize, usize>>,
+    slot_freed: Condvar,
+    next_tenant: AtomicUsize,
+}
+
+impl SharedPool {
+    /// Starts a pool with `num_threads` worker threads that runs at most `max_jobs` jobs at once.
+    /// Jobs run inside the pool and share all of its threads, so `max_jobs` bounds how many trees
+    /// compete for the threads at any one time. A `max_jobs` of 0 is treated as 1.
+    pub fn new(num_threads: usize, max_jobs: usize) -> GokoResult<Arc<SharedPool>> {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(num_threads)
+            .thread_name(|i| format!("goko-shared-{}", i))
+            .build()?;
+        Ok(Arc::new(SharedPool {
+            pool,
+            max_jobs: max_jobs.max(1),
+            state: Mutex::new(PoolState::default()),
+            caps: Mutex::new(HashMap::new()),
+            slot_freed: Condvar::new(),
+            next_tenant: AtomicUsize::new(0),
+        }))
+    }
+
+    /// The number of worker threads in the pool.
+    pub fn num_threads(&self) -> usize {
+        self.pool.current_num_threads()
+    }
+
+    /// The number of jobs that can run at once.
+    pub fn max_jobs(&self) -> usize {
+        self.max_jobs
+    }
+
+    /// The number of jobs currently running.
+    pub fn running_jobs(&self) -> usize {
+        self.state.lock().unwrap().running
+    }
+
+    /// The number of jobs waiting for a slot.
+    pub fn waiting_jobs(&self) -> usize {
+        self.state.lock().unwrap().waiting.len()
+    }
+
+    /// The number of slots background work may hold at once. One slot is kept for foreground work
+    /// unless the pool only has the one.
+    pub fn background_jobs(&self) -> usize {
+        if self.max_jobs > 1 {
+            self.max_jobs - 1
+        } else {
+            1
+        }
+    }
+
+    fn acquire(&self, tenant: usize, priority: i32, class: WorkClass) {
+        let mut state = self.state.lock().unwrap();
+        let ticket = state.next_ticket;
+        state.next_ticket += 1;
+        state.waiting.push(Waiter {
+            ticket,
+            tenant,
+            priority,
+            class,
+        });
+        loop {
+            if state.running < self.max_jobs {
+                let caps = self.caps.lock().unwrap();
+                if state.next_in_line(&caps, self.background_jobs()) == Some(ticket) {
+                    break;
+                }
+            }
+            state = self.slot_freed.wait(state).unwrap();
+        }
+        state.waiting.retain(|w| w.ticket != ticket);
+        state.running += 1;
+        if class == WorkClass::Background {
+            state.running_background += 1;
+        }
+        *state.running_per_tenant.entry(tenant).or_insert(0) += 1;
+        // Another slot may still be open for the next waiter in line
+        self.slot_freed.notify_all();
+    }
+
+    fn release(&self, tenant: usize, class: WorkClass) {
+        let mut state = self.state.lock().unwrap();
+        state.running -= 1;
+        if class == WorkClass::Background {
+            state.running_background -= 1;
+        }
+        let remove = match state.running_per_tenant.get_mut(&tenant) {
+            Some(count) => {
+                *count -= 1;
+                *count == 0
+            }
+            None => false,
+        };
+        if remove {
+            state.running_per_tenant.remove(&tenant);
+        }
+        self.slot_freed.notify_all();
+    }
+}
+
+/// The registration of one tree with the pool, shared by all clones of its handle.
+#[derive(Debug)]
+struct Tenant {
+    id: usize,
+    pool: Arc<SharedPool>,
+}
+
+impl Drop for Tenant {
+    fn drop(&mut self) {
+        self.pool.caps.lock().unwrap().remove(&self.id);
+    }
+}
+
+/// A tree's handle on a [`SharedPool`]. Cloning the handle keeps the same tree identity, so the
+/// clones share the tree's concurrency cap.
+#[derive(Debug, Clone)]
+pub struct PoolHandle {
+    tenant: Arc<Tenant>,
+    settings: TenantSettings,
+}
+
+impl PoolHandle {
+    /// Registers a new tree with the pool.
+    pub fn new(pool: &Arc<SharedPool>, settings: TenantSettings) -> PoolHandle {
+        let id = pool.next_tenant.fetch_add(1, Ordering::SeqCst);
+        pool.caps
+            .lock()
+            .unwrap()
+            .insert(id, settings.max_concurrent);
+        PoolHandle {
+            tenant: Arc::new(Tenant {
+                id,
+                pool: Arc::clone(pool),
+            }),
+            settings,
+        }
+    }
+
+    /// The settings this tree was registered with.
+    pub fn settings(&self) -> TenantSettings {
+        self.settings
+    }
+
+    /// The pool this handle schedules on.
+    pub fn pool(&self) -> &Arc<SharedPool> {
+        &self.tenant.pool
+    }
+
+    /// Waits for a foreground slot and holds it until the returned guard is dropped. Use this when
+    /// the work is spawned onto the pool rather than run with [`PoolHandle::install`].
+    pub fn slot(&self) -> PoolSlot<'_> {
+        self.class_slot(WorkClass::Foreground)
+    }
+
+    /// Waits for a background slot and holds it until the returned guard is dropped.
+    pub fn background_slot(&self) -> PoolSlot<'_> {
+        self.class_slot(WorkClass::Background)
+    }
+
+    fn class_slot(&self, class: WorkClass) -> PoolSlot<'_> {
+        self.tenant
+            .pool
+            .acquire(self.tenant.id, self.settings.priority, class);
+        PoolSlot {
+            handle: self,
+            class,
+        }
+    }
+
+    /// Waits for a slot, then runs `op` inside the shared pool. Rayon parallel iterators and
+    /// spawns inside `op` use the shared pool's threads.
+    pub fn install<OP, R>(&self, op: OP) -> R
+    where
+        OP: FnOnce() -> R + Send,
+        R: Send,
+    {
+        let _slot = self.slot();
+        self.tenant.pool.pool.install(op)
+    }
+
+    /// Like [`PoolHandle::install`], but runs `op` as background work.
+    pub fn install_background<OP, R>(&self, op: OP) -> R
+    where
+        OP: FnOnce() -> R + Send,
+        R: Send,
+    {
+        let _slot = self.background_slot();
+        self.tenant.pool.pool.install(op)
+    }
+
+    /// Runs `op` inside the shared pool without taking a slot. Only for work that is already
+    /// accounted for by a held [`PoolSlot`].
+    pub(crate) fn install_unscheduled<OP, R>(&self, op: OP) -> R
+    where
+        OP: FnOnce() -> R + Send,
+        R: Send,
+    {
+        self.tenant.pool.pool.install(op)
+    }
+}
+
+/// A held job slot on a [`SharedPool`], released on drop.
+#[derive(Debug)]
+pub struct PoolSlot<'a> {
+    handle: &'a PoolHandle,
+    class: WorkClass,
+}
+
+impl<'a> PoolSlot<'a> {
+    /// The class of work this slot was taken for.
+    pub fn class(&self) -> WorkClass {
+        self.class
+    }
+
+    /// If this is a background slot and foreground work is waiting, hands the slot over and waits
+    /// for another one. Returns whether it yielded. Call this between the steps of a long
+    /// background job.
+    pub fn yield_to_foreground(&mut self) -> bool {
+        if self.class != WorkClass::Background {
+            return false;
+        }
+        let pool = &self.handle.tenant.pool;
+        if !pool.state.lock().unwrap().foreground_waiting() {
+            return false;
+        }
+        pool.release(self.handle.tenant.id, self.class);
+        pool.acquire(
+            self.handle.tenant.id,
+            self.handle.settings.priority,
+            self.class,
+        );
+        true
+    }
+}
+
+impl<'a> Drop for PoolSlot<'a> {
+    fn drop(&mut self) {
+        self.handle
+            .tenant
+            .pool
+            .release(self.handle.tenant.id, self.class);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rayon::prelude::*;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn install_runs_on_the_shared_pool() {
+        let pool = SharedPool::new(2, 1).unwrap();
+        let handle = PoolHandle::new(&pool, TenantSettings::default());
+        let (threads, total) = handle.install(|| {
+            (
+                rayon::current_num_threads(),
+                (0..1000u64).into_par_iter().sum::<u64>(),
+            )
+        });
+        assert_eq!(threads, 2);
+        assert_eq!(total, 499500);
+        assert_eq!(pool.running_jobs(), 0);
+    }
+
+    #[test]
+    fn higher_priority_goes_first() {
+        let pool = SharedPool::new(1, 1).unwrap();
+        let blocker = PoolHandle::new(&pool, TenantSettings::default());
+        let low = PoolHandle::new(&pool, TenantSettings::default());
+        let high = PoolHandle::new(
+            &pool,
+            TenantSettings {
+                priority: 5,
+                max_concurrent: 0,
+            },
+        );
+
+        let slot = blocker.slot();
+        let (order_sender, order_receiver) = mpsc::channel();
+        let low_sender = order_sender.clone();
+        let low_thread = thread::spawn(move || low.install(|| low_sender.send("low").unwrap()));
+        while pool.waiting_jobs() < 1 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        let high_thread =
+            thread::spawn(move || high.install(|| order_sender.send("high").unwrap()));
+        while pool.waiting_jobs() < 2 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        drop(slot);
+        low_thread.join().unwrap();
+        high_thread.join().unwrap();
+        let order: Vec<&str> = order_receiver.iter().collect();
+        assert_eq!(order, vec!["high", "low"]);
+    }
+
+    #[test]
+    fn tenant_cap_is_respected() {
+        let pool = SharedPool::new(2, 2).unwrap();
+        let capped = PoolHandle::new(
+            &pool,
+            TenantSettings {
+                priority: 0,
+                max_concurrent: 1,
+            },
+        );
+        let other = PoolHandle::new(&pool, TenantSettings::default());
+
+        let first = capped.slot();
+        let capped_clone = capped.clone();
+        let waiting = thread::spawn(move || capped_clone.install(|| ()));
+        while pool.waiting_jobs() < 1 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        // The second slot is free, but the capped tree can't take it, so the other tree can.
+        assert_eq!(pool.running_jobs(), 1);
+        other.install(|| ());
+        assert_eq!(pool.waiting_jobs(), 1);
+        drop(first);
+        waiting.join().unwrap();
+        assert_eq!(pool.running_jobs(), 0);
+    }
+
+    #[test]
+    fn foreground_goes_before_background() {
+        let pool = SharedPool::new(1, 1).unwrap();
+        let blocker = PoolHandle::new(&pool, TenantSettings::default());
+        let maintenance = PoolHandle::new(
+            &pool,
+            TenantSettings {
+                priority: 5,
+                max_concurrent: 0,
+            },
+        );
+        let queries = PoolHandle::new(&pool, TenantSettings::default());
+
+        let slot = blocker.slot();
+        let (order_sender, order_receiver) = mpsc::channel();
+        let background_sender = order_sender.clone();
+        let background_thread = thread::spawn(move || {
+            maintenance.install_background(|| background_sender.send("background").unwrap())
+        });
+        while pool.waiting_jobs() < 1 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        let foreground_thread =
+            thread::spawn(move || queries.install(|| order_sender.send("foreground").unwrap()));
+        while pool.waiting_jobs() < 2 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        drop(slot);
+        background_thread.join().unwrap();
+        foreground_thread.join().unwrap();
+        let order: Vec<&str> = order_receiver.iter().collect();
+        assert_eq!(order, vec!["foreground", "background"]);
+    }
+
+    #[test]
+    fn background_leaves_a_slot_for_foreground() {
+        let pool = SharedPool::new(2, 2).unwrap();
+        let maintenance = PoolHandle::new(&pool, TenantSettings::default());
+        let queries = PoolHandle::new(&pool, TenantSettings::default());
+
+        let first = maintenance.background_slot();
+        let maintenance_clone = maintenance.clone();
+        let second_background = thread::spawn(move || maintenance_clone.install_background(|| ()));
+        while pool.waiting_jobs() < 1 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(pool.running_jobs(), 1);
+        // The reserved slot is still open for queries
+        assert_eq!(queries.install(|| 1 + 1), 2);
+        drop(first);
+        second_background.join().unwrap();
+    }
+
+    #[test]
+    fn background_yields_to_waiting_queries() {
+        let pool = SharedPool::new(1, 1).unwrap();
+        let maintenance = PoolHandle::new(&pool, TenantSettings::default());
+        let queries = PoolHandle::new(&pool, TenantSettings::default());
+
+        let mut slot = maintenance.background_slot();
+        assert!(!slot.yield_to_foreground());
+        let (done_sender, done_receiver) = mpsc::channel();
+        let query_thread = thread::spawn(move || queries.install(|| done_sender.send(()).unwrap()));
+        while pool.waiting_jobs() < 1 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert!(slot.yield_to_foreground());
+        // The query ran while the maintenance job waited for its slot back
+        assert!(done_receiver.try_recv().is_ok());
+        assert_eq!(slot.class(), WorkClass::Background);
+        drop(slot);
+        query_thread.join().unwrap();
+        assert_eq!(pool.running_jobs(), 0);
+    }
+}
@@ -828,6 +828,207 @@ impl ::protobuf::reflect::ProtobufValue for LayerProto {
     }
 }
 
+#[derive(PartialEq,Clone,Default)]
+pub struct PluginProto {
+    // message fields
+    pub name: ::std::string::String,
+    pub parameters_json: ::std::string::String,
+    // special fields
+    pub unknown_fields: ::protobuf::UnknownFields,
+    pub cached_size: ::protobuf::CachedSize,
+}
+
+impl<'a> ::std::default::Default for &'a PluginProto {
+    fn default() -> &'a PluginProto {
+        <PluginProto as ::protobuf::Message>::default_instance()
+    }
+}
+
+impl PluginProto {
+    pub fn new() -> PluginProto {
+        ::std::default::Default::default()
+    }
+
+    // string name = 1;
+
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+    pub fn clear_name(&mut self) {
+        self.name.clear();
+    }
+
+    // Param is passed by value, moved
+    pub fn set_name(&mut self, v: ::std::string::String) {
+        self.name = v;
+    }
+
+    // Mutable pointer to the field.
+    // If field is not initialized, it is initialized with default value first.
+    pub fn mut_name(&mut self) -> &mut ::std::string::String {
+        &mut self.name
+    }
+
+    // Take field
+    pub fn take_name(&mut self) -> ::std::string::String {
+        ::std::mem::replace(&mut self.name, ::std::string::String::new())
+    }
+
+    // string parameters_json = 2;
+
+
+    pub fn get_parameters_json(&self) -> &str {
+        &self.parameters_json
+    }
+    pub fn clear_parameters_json(&mut self) {
+        self.parameters_json.clear();
+    }
+
+    // Param is passed by value, moved
+    pub fn set_parameters_json(&mut self, v: ::std::string::String) {
+        self.parameters_json = v;
+    }
+
+    // Mutable pointer to the field.
+    // If field is not initialized, it is initialized with default value first.
+    pub fn mut_parameters_json(&mut self) -> &mut ::std::string::String {
+        &mut self.parameters_json
+    }
+
+    // Take field
+    pub fn take_parameters_json(&mut self) -> ::std::string::String {
+        ::std::mem::replace(&mut self.parameters_json, ::std::string::String::new())
+    }
+}
+
+impl ::protobuf::Message for PluginProto {
+    fn is_initialized(&self) -> bool {
+        true
+    }
+
+    fn merge_from(&mut self, is: &mut ::protobuf::CodedInputStream<'_>) -> ::protobuf::ProtobufResult<()> {
+        while !is.eof()? {
+            let (field_number, wire_type) = is.read_tag_unpack()?;
+            match field_number {
+                1 => {
+                    ::protobuf::rt::read_singular_proto3_string_into(wire_type, is, &mut self.name)?;
+                },
+                2 => {
+                    ::protobuf::rt::read_singular_proto3_string_into(wire_type, is, &mut self.parameters_json)?;
+                },
+                _ => {
+                    ::protobuf::rt::read_unknown_or_skip_group(field_number, wire_type, is, self.mut_unknown_fields())?;
+                },
+            };
+        }
+        ::std::result::Result::Ok(())
+    }
+
+    // Compute sizes of nested messages
+    #[allow(unused_variables)]
+    fn compute_size(&self) -> u32 {
+        let mut my_size = 0;
+        if !self.name.is_empty() {
+            my_size += ::protobuf::rt::string_size(1, &self.name);
+        }
+        if !self.parameters_json.is_empty() {
+            my_size += ::protobuf::rt::string_size(2, &self.parameters_json);
+        }
+        my_size += ::protobuf::rt::unknown_fields_size(self.get_unknown_fields());
+        self.cached_size.set(my_size);
+        my_size
+    }
+
+    fn write_to_with_cached_sizes(&self, os: &mut ::protobuf::CodedOutputStream<'_>) -> ::protobuf::ProtobufResult<()> {
+        if !self.name.is_empty() {
+            os.write_string(1, &self.name)?;
+        }
+        if !self.parameters_json.is_empty() {
+            os.write_string(2, &self.parameters_json)?;
+        }
+        os.write_unknown_fields(self.get_unknown_fields())?;
+        ::std::result::Result::Ok(())
+    }
+
+    fn get_cached_size(&self) -> u32 {
+        self.cached_size.get()
+    }
+
+    fn get_unknown_fields(&self) -> &::protobuf::UnknownFields {
+        &self.unknown_fields
+    }
+
+    fn mut_unknown_fields(&mut self) -> &mut ::protobuf::UnknownFields {
+        &mut self.unknown_fields
+    }
+
+    fn as_any(&self) -> &dyn (::std::any::Any) {
+        self as &dyn (::std::any::Any)
+    }
+    fn as_any_mut(&mut self) -> &mut dyn (::std::any::Any) {
+        self as &mut dyn (::std::any::Any)
+    }
+    fn into_any(self: ::std::boxed::Box<Self>) -> ::std::boxed::Box<dyn (::std::any::Any)> {
+        self
+    }
+
+    fn descriptor(&self) -> &'static ::protobuf::reflect::MessageDescriptor {
+        Self::descriptor_static()
+    }
+
+    fn new() -> PluginProto {
+        PluginProto::new()
+    }
+
+    fn descriptor_static() -> &'static ::protobuf::reflect::MessageDescriptor {
+        static descriptor: ::protobuf::rt::LazyV2<::protobuf::reflect::MessageDescriptor> = ::protobuf::rt::LazyV2::INIT;
+        descriptor.get(|| {
+            let mut fields = ::std::vec::Vec::new();
+            fields.push(::protobuf::reflect::accessor::make_simple_field_accessor::<_, ::protobuf::types::ProtobufTypeString>(
+                "name",
+                |m: &PluginProto| { &m.name },
+                |m: &mut PluginProto| { &mut m.name },
+            ));
+            fields.push(::protobuf::reflect::accessor::make_simple_field_accessor::<_, ::protobuf::types::ProtobufTypeString>(
+                "parameters_json",
+                |m: &PluginProto| { &m.parameters_json },
+                |m: &mut PluginProto| { &mut m.parameters_json },
+            ));
+            ::protobuf::reflect::MessageDescriptor::new_pb_name::<PluginProto>(
+                "PluginProto",
+                fields,
+                file_descriptor_proto()
+            )
+        })
+    }
+
+    fn default_instance() -> &'static PluginProto {
+        static instance: ::protobuf::rt::LazyV2<PluginProto> = ::protobuf::rt::LazyV2::INIT;
+        instance.get(PluginProto::new)
+    }
+}
+
+impl ::protobuf::Clear for PluginProto {
+    fn clear(&mut self) {
+        self.name.clear();
+        self.parameters_json.clear();
+        self.unknown_fields.clear();
+    }
+}
+
+impl ::std::fmt::Debug for PluginProto {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter<'_>) -> ::std::fmt::Result {
+        ::protobuf::text_format::fmt(self, f)
+    }
+}
+
+impl ::protobuf::reflect::ProtobufValue for PluginProto {
+    fn as_ref(&self) -> ::protobuf::reflect::ReflectValueRef {
+        ::protobuf::reflect::ReflectValueRef::Message(self)
+    }
+}
+
 #[derive(PartialEq,Clone,Default)]
 pub struct CoreProto {
     // message fields
@@ -842,6 +1043,10 @@ pub struct CoreProto {
     pub root_index: u64,
     pub layers: ::protobuf::RepeatedField<LayerProto>,
     pub name_map: ::std::collections::HashMap<::std::string::String, u64>,
+    pub has_rng_seed: bool,
+    pub rng_seed: u64,
+    pub verbosity: u32,
+    pub plugins: ::protobuf::RepeatedField<PluginProto>,
     // special fields
     pub unknown_fields: ::protobuf::UnknownFields,
     pub cached_size: ::protobuf::CachedSize,
@@ -1053,6 +1258,76 @@ impl CoreProto {
     pub fn take_name_map(&mut self) -> ::std::collections::HashMap<::std::string::String, u64> {
         ::std::mem::replace(&mut self.name_map, ::std::collections::HashMap::new())
     }
+
+    // bool has_rng_seed = 13;
+
+
+    pub fn get_has_rng_seed(&self) -> bool {
+        self.has_rng_seed
+    }
+    pub fn clear_has_rng_seed(&mut self) {
+        self.has_rng_seed = false;
+    }
+
+    // Param is passed by value, moved
+    pub fn set_has_rng_seed(&mut self, v: bool) {
+        self.has_rng_seed = v;
+    }
+
+    // uint64 rng_seed = 14;
+
+
+    pub fn get_rng_seed(&self) -> u64 {
+        self.rng_seed
+    }
+    pub fn clear_rng_seed(&mut self) {
+        self.rng_seed = 0;
+    }
+
+    // Param is passed by value, moved
+    pub fn set_rng_seed(&mut self, v: u64) {
+        self.rng_seed = v;
+    }
+
+    // uint32 verbosity = 15;
+
+
+    pub fn get_verbosity(&self) -> u32 {
+        self.verbosity
+    }
+    pub fn clear_verbosity(&mut self) {
+        self.verbosity = 0;
+    }
+
+    // Param is passed by value, moved
+    pub fn set_verbosity(&mut self, v: u32) {
+        self.verbosity = v;
+    }
+
+    // repeated .CoverTree.PluginProto plugins = 16;
+
+
+    pub fn get_plugins(&self) -> &[PluginProto] {
+        &self.plugins
+    }
+    pub fn clear_plugins(&mut self) {
+        self.plugins.clear();
+    }
+
+    // Param is passed by value, moved
+    pub fn set_plugins(&mut self, v: ::protobuf::RepeatedField<PluginProto>) {
+        self.plugins = v;
+    }
+
+    // Mutable pointer to the field.
+    pub fn mut_plugins(&mut self) -> &mut ::protobuf::RepeatedField<PluginProto> {
+        &mut self.plugins
+    }
+
+    // Take field
+    pub fn take_plugins(&mut self) -> ::protobuf::RepeatedField<PluginProto> {
+        ::std::mem::replace(&mut self.plugins, ::protobuf::RepeatedField::new())
+    }
 }
 
 impl ::protobuf::Message for CoreProto {
@@ -1062,6 +1337,11 @@ impl ::protobuf::Message for CoreProto {
                 return false;
             }
         };
+        for v in &self.plugins {
+            if !v.is_initialized() {
+                return false;
+            }
+        };
         true
     }
 
@@ -1134,6 +1414,30 @@ impl ::protobuf::Message for CoreProto {
                 12 => {
                     ::protobuf::rt::read_map_into::<::protobuf::types::ProtobufTypeString, ::protobuf::types::ProtobufTypeUint64>(wire_type, is, &mut self.name_map)?;
                 },
+                13 => {
+                    if wire_type != ::protobuf::wire_format::WireTypeVarint {
+                        return ::std::result::Result::Err(::protobuf::rt::unexpected_wire_type(wire_type));
+                    }
+                    let tmp = is.read_bool()?;
+                    self.has_rng_seed = tmp;
+                },
+                14 => {
+                    if wire_type != ::protobuf::wire_format::WireTypeVarint {
+                        return ::std::result::Result::Err(::protobuf::rt::unexpected_wire_type(wire_type));
+                    }
+                    let tmp = is.read_uint64()?;
+                    self.rng_seed = tmp;
+                },
+                15 => {
+                    if wire_type != ::protobuf::wire_format::WireTypeVarint {
+                        return ::std::result::Result::Err(::protobuf::rt::unexpected_wire_type(wire_type));
+                    }
+                    let tmp = is.read_uint32()?;
+                    self.verbosity = tmp;
+                },
+                16 => {
+                    ::protobuf::rt::read_repeated_message_into(wire_type, is, &mut self.plugins)?;
+                },
                 _ => {
                     ::protobuf::rt::read_unknown_or_skip_group(field_number, wire_type, is, self.mut_unknown_fields())?;
                 },
@@ -1178,6 +1482,19 @@ impl ::protobuf::Message for CoreProto {
             my_size += 1 + ::protobuf::rt::compute_raw_varint32_size(len) + len;
         };
         my_size += ::protobuf::rt::compute_map_size::<::protobuf::types::ProtobufTypeString, ::protobuf::types::ProtobufTypeUint64>(12, &self.name_map);
+        if self.has_rng_seed != false {
+            my_size += 2;
+        }
+        if self.rng_seed != 0 {
+            my_size += ::protobuf::rt::value_size(14, self.rng_seed, ::protobuf::wire_format::WireTypeVarint);
+        }
+        if self.verbosity != 0 {
+            my_size += ::protobuf::rt::value_size(15, self.verbosity, ::protobuf::wire_format::WireTypeVarint);
+        }
+        for value in &self.plugins {
+            let len = value.compute_size();
+            my_size += 2 + ::protobuf::rt::compute_raw_varint32_size(len) + len;
+        };
         my_size += ::protobuf::rt::unknown_fields_size(self.get_unknown_fields());
         self.cached_size.set(my_size);
         my_size
@@ -1217,6 +1534,20 @@ impl ::protobuf::Message for CoreProto {
             v.write_to_with_cached_sizes(os)?;
         };
         ::protobuf::rt::write_map_with_cached_sizes::<::protobuf::types::ProtobufTypeString, ::protobuf::types::ProtobufTypeUint64>(12, &self.name_map, os)?;
+        if self.has_rng_seed != false {
+            os.write_bool(13, self.has_rng_seed)?;
+        }
+        if self.rng_seed != 0 {
+            os.write_uint64(14, self.rng_seed)?;
+        }
+        if self.verbosity != 0 {
+            os.write_uint32(15, self.verbosity)?;
+        }
+        for v in &self.plugins {
+            os.write_tag(16, ::protobuf::wire_format::WireTypeLengthDelimited)?;
+            os.write_raw_varint32(v.get_cached_size())?;
+            v.write_to_with_cached_sizes(os)?;
+        };
         os.write_unknown_fields(self.get_unknown_fields())?;
         ::std::result::Result::Ok(())
     }
@@ -1310,6 +1641,26 @@ impl ::protobuf::Message for CoreProto {
                 |m: &CoreProto| { &m.name_map },
                 |m: &mut CoreProto| { &mut m.name_map },
             ));
+            fields.push(::protobuf::reflect::accessor::make_simple_field_accessor::<_, ::protobuf::types::ProtobufTypeBool>(
+                "has_rng_seed",
+                |m: &CoreProto| { &m.has_rng_seed },
+                |m: &mut CoreProto| { &mut m.has_rng_seed },
+            ));
+            fields.push(::protobuf::reflect::accessor::make_simple_field_accessor::<_, ::protobuf::types::ProtobufTypeUint64>(
+                "rng_seed",
+                |m: &CoreProto| { &m.rng_seed },
+                |m: &mut CoreProto| { &mut m.rng_seed },
+            ));
+            fields.push(::protobuf::reflect::accessor::make_simple_field_accessor::<_, ::protobuf::types::ProtobufTypeUint32>(
+                "verbosity",
+                |m: &CoreProto| { &m.verbosity },
+                |m: &mut CoreProto| { &mut m.verbosity },
+            ));
+            fields.push(::protobuf::reflect::accessor::make_repeated_field_accessor::<_, ::protobuf::types::ProtobufTypeMessage<PluginProto>>(
+                "plugins",
+                |m: &CoreProto| { &m.plugins },
+                |m: &mut CoreProto| { &mut m.plugins },
+            ));
             ::protobuf::reflect::MessageDescriptor::new_pb_name::<CoreProto>(
                 "CoreProto",
                 fields,
@@ -1337,6 +1688,10 @@ impl ::protobuf::Clear for CoreProto {
         self.root_index = 0;
         self.layers.clear();
         self.name_map.clear();
+        self.has_rng_seed = false;
+        self.rng_seed = 0;
+        self.verbosity = 0;
+        self.plugins.clear();
         self.unknown_fields.clear();
     }
 }
@@ -1368,19 +1723,24 @@ static file_descriptor_proto_data: &'static [u8] = b"\
     dexes\x120\n\x14outlier_summary_json\x18\x0c\x20\x01(\tR\x12outlierSumma\
     ryJson\x12\x16\n\x06radius\x18\r\x20\x01(\x02R\x06radius\"Y\n\nLayerProt\
     o\x12\x1f\n\x0bscale_index\x18\x01\x20\x01(\x05R\nscaleIndex\x12*\n\x05n\
-    odes\x18\x02\x20\x03(\x0b2\x14.CoverTree.NodeProtoR\x05nodes\"\xbf\x03\n\
-    \tCoreProto\x12%\n\x0euse_singletons\x18\x01\x20\x01(\x08R\ruseSingleton\
-    s\x12\x1d\n\nscale_base\x18\x02\x20\x01(\x02R\tscaleBase\x12\x16\n\x06cu\
-    toff\x18\x03\x20\x01(\x04R\x06cutoff\x12\x1e\n\nresolution\x18\x04\x20\
-    \x01(\x11R\nresolution\x12%\n\x0epartition_type\x18\x05\x20\x01(\tR\rpar\
-    titionType\x12\x10\n\x03dim\x18\x07\x20\x01(\x04R\x03dim\x12\x14\n\x05co\
-    unt\x18\x08\x20\x01(\x04R\x05count\x12\x1d\n\nroot_scale\x18\t\x20\x01(\
-    \x05R\trootScale\x12\x1d\n\nroot_index\x18\n\x20\x01(\x04R\trootIndex\
-    \x12-\n\x06layers\x18\x0b\x20\x03(\x0b2\x15.CoverTree.LayerProtoR\x06lay\
-    ers\x12<\n\x08name_map\x18\x0c\x20\x03(\x0b2!.CoverTree.CoreProto.NameMa\
-    pEntryR\x07nameMap\x1a:\n\x0cNameMapEntry\x12\x10\n\x03key\x18\x01\x20\
-    \x01(\tR\x03key\x12\x14\n\x05value\x18\x02\x20\x01(\x04R\x05value:\x028\
-    \x01b\x06proto3\
+    odes\x18\x02\x20\x03(\x0b2\x14.CoverTree.NodeProtoR\x05nodes\"J\n\x0bPlu\
+    ginProto\x12\x12\n\x04name\x18\x01\x20\x01(\tR\x04name\x12\'\n\x0fparame\
+    ters_json\x18\x02\x20\x01(\tR\x0eparametersJson\"\xcc\x04\n\tCoreProto\
+    \x12%\n\x0euse_singletons\x18\x01\x20\x01(\x08R\ruseSingletons\x12\x1d\n\
+    \nscale_base\x18\x02\x20\x01(\x02R\tscaleBase\x12\x16\n\x06cutoff\x18\
+    \x03\x20\x01(\x04R\x06cutoff\x12\x1e\n\nresolution\x18\x04\x20\x01(\x11R\
+    \nresolution\x12%\n\x0epartition_type\x18\x05\x20\x01(\tR\rpartitionType\
+    \x12\x10\n\x03dim\x18\x07\x20\x01(\x04R\x03dim\x12\x14\n\x05count\x18\
+    \x08\x20\x01(\x04R\x05count\x12\x1d\n\nroot_scale\x18\t\x20\x01(\x05R\tr\
+    ootScale\x12\x1d\n\nroot_index\x18\n\x20\x01(\x04R\trootIndex\x12-\n\x06\
+    layers\x18\x0b\x20\x03(\x0b2\x15.CoverTree.LayerProtoR\x06layers\x12<\n\
+    \x08name_map\x18\x0c\x20\x03(\x0b2!.CoverTree.CoreProto.NameMapEntryR\
+    \x07nameMap\x12\x20\n\x0chas_rng_seed\x18\r\x20\x01(\x08R\nhasRngSeed\
+    \x12\x19\n\x08rng_seed\x18\x0e\x20\x01(\x04R\x07rngSeed\x12\x1c\n\tverbo\
+    sity\x18\x0f\x20\x01(\rR\tverbosity\x120\n\x07plugins\x18\x10\x20\x03(\
+    \x0b2\x16.CoverTree.PluginProtoR\x07plugins\x1a:\n\x0cNameMapEntry\x12\
+    \x10\n\x03key\x18\x01\x20\x01(\tR\x03key\x12\x14\n\x05value\x18\x02\x20\
+    \x01(\x04R\x05value:\x028\x01b\x06proto3\
 ";
 
 static file_descriptor_proto_lazy: ::protobuf::rt::LazyV2<::protobuf::descriptor::FileDescriptorProto> = ::protobuf::rt::LazyV2::INIT;
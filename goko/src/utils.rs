@@ -17,11 +17,16 @@
 * under the License.
 */
 
-//! Utility functions for i/o
+//! Utility functions for i/o, and common tasks built on a tree like deduplication
 
 use crate::errors::{GokoError, GokoResult};
 use crate::tree_file_format::*;
 use protobuf::{CodedInputStream, CodedOutputStream, Message};
+use rand::rngs::SmallRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use rayon::prelude::*;
+use std::collections::HashSet;
 use std::fs::File;
 use std::fs::{read_to_string, remove_file, OpenOptions};
 use std::path::Path;
@@ -30,9 +35,13 @@ use yaml_rust::YamlLoader;
 
 use crate::builders::CoverTreeBuilder;
 
-use crate::CoverTreeWriter;
+use crate::plugins::PluginRegistry;
+use crate::{CoverTreeReader, CoverTreeWriter};
 
+use pointcloud::data_sources::{DataMmapFile, MmapFileWriter};
 use pointcloud::loaders::{labeled_ram_from_yaml, ram_from_yaml};
+use pointcloud::subset_cloud::SubsetCloud;
+use pointcloud::summaries::{CategorySummary, VecSummary};
 use pointcloud::*;
 
 /// Given a yaml file on disk, it builds a covertree.
@@ -115,6 +124,17 @@ pub fn cover_tree_from_yaml<P: AsRef<Path>>(
     Ok(builder.build(Arc::new(point_cloud))?)
 }
 
+/// Maps a point file written by a [`MmapFileWriter`] and builds a covertree over it. The points
+/// stay on disk and are paged in as the build reads them, so the cloud can be larger than RAM.
+/// Only the tree itself has to fit in memory, use [`CoverTreeBuilder::set_memory_limit`] to cap it.
+pub fn cover_tree_from_mmap_file<P: AsRef<Path>>(
+    path: P,
+    builder: &CoverTreeBuilder,
+) -> GokoResult<CoverTreeWriter<DataMmapFile<L2>>> {
+    let point_cloud = DataMmapFile::<L2>::open(path)?;
+    builder.build(Arc::new(point_cloud))
+}
+
 /// Helper function that handles the file I/O and protobuf decoding for you.
 pub fn load_tree<P: AsRef<Path>, D: PointCloud>(
     tree_path: P,
@@ -139,7 +159,7 @@ pub fn load_tree<P: AsRef<Path>, D: PointCloud>(
         panic!("Proto buff was unable to read {:#?}", e)
     }
 
-    CoverTreeWriter::load(&cover_proto, point_cloud)
+    CoverTreeWriter::from_proto(&cover_proto, point_cloud, &PluginRegistry::default())
 }
 
 /// Helper function that handles the file I/O and protobuf encoding for you.
@@ -159,7 +179,7 @@ pub fn save_tree<P: AsRef<Path>, D: PointCloud>(
         remove_file(&tree_path).map_err(GokoError::from)?;
     }
 
-    let cover_proto = cover_tree.save();
+    let cover_proto = cover_tree.to_proto();
 
     let mut core_file = OpenOptions::new()
         .read(true)
@@ -173,3 +193,299 @@ pub fn save_tree<P: AsRef<Path>, D: PointCloud>(
     cos.flush().map_err(GokoError::from)?;
     Ok(())
 }
+
+/// Which point of a group of duplicates [`dedupe`] keeps.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DedupeKeep {
+    /// The point with the lowest index, the one the group was found around.
+    First,
+    /// The member with the smallest total distance to the rest of the group, the medoid. This works for any metric,
+    /// unlike the mean, and is always one of the points.
+    Centroid,
+}
+
+/// A group of points that are all within the dedupe radius of the point they were grouped around.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DuplicateGroup {
+    /// The point kept for the group
+    pub canonical: usize,
+    /// Every point in the group, including the canonical one, in index order
+    pub members: Vec<usize>,
+}
+
+/// Groups the points of the tree's point cloud that are within `radius` of each other. The points are taken in
+/// index order, and each point that isn't in a group yet starts one with every other ungrouped point within `radius`
+/// of it, found with [`CoverTreeReader::knn_within`]. So every member is within `radius` of the point the group was
+/// started from, and the groups don't depend on the tree's shape.
+///
+/// Every point is in exactly one group, most groups are usually just one point. The groups are ordered by their
+/// first member.
+pub fn dedupe<D: PointCloud>(
+    reader: &CoverTreeReader<D>,
+    radius: f32,
+    keep: DedupeKeep,
+) -> GokoResult<Vec<DuplicateGroup>> {
+    let point_cloud = reader.point_cloud();
+    let mut point_indexes = point_cloud.reference_indexes();
+    point_indexes.sort_unstable();
+    let mut grouped = HashSet::new();
+    let mut groups = Vec::new();
+    for i in point_indexes {
+        if grouped.contains(&i) {
+            continue;
+        }
+        let point = point_cloud.point(i)?;
+        let mut members: Vec<usize> = reader
+            .knn_within(&point, point_cloud.len(), radius)?
+            .iter()
+            .map(|(_, j)| *j)
+            .filter(|j| *j == i || !grouped.contains(j))
+            .collect();
+        if !members.contains(&i) {
+            members.push(i);
+        }
+        members.sort_unstable();
+        grouped.extend(members.iter().copied());
+        let canonical = match keep {
+            DedupeKeep::First => i,
+            DedupeKeep::Centroid => medoid(point_cloud.as_ref(), &members)?,
+        };
+        groups.push(DuplicateGroup { canonical, members });
+    }
+    Ok(groups)
+}
+
+/// The member with the smallest total distance to the others, the lowest index on ties.
+fn medoid<D: PointCloud>(point_cloud: &D, members: &[usize]) -> GokoResult<usize> {
+    let mut best = (f32::MAX, members[0]);
+    for m in members {
+        let total: f32 = point_cloud
+            .distances_to_point_index(*m, members)?
+            .iter()
+            .sum();
+        if total < best.0 {
+            best = (total, *m);
+        }
+    }
+    Ok(best.1)
+}
+
+/// A label summary a kNN prediction can be read off, so it can be scored against the true label.
+pub trait KnnPrediction: Summary {
+    /// The error of the prediction for the true label. `None` if the summary is empty.
+    fn prediction_error(&self, label: &Self::Label) -> Option<f32>;
+}
+
+impl KnnPrediction for CategorySummary {
+    /// Predicts the most common category, ties go to the one seen first. The error is 0 for a
+    /// right prediction and 1 for a wrong one.
+    fn prediction_error(&self, label: &i64) -> Option<f32> {
+        let mut best: Option<(i64, usize)> = None;
+        for (category, count) in &self.items {
+            if best.map_or(true, |(_, best_count)| *count > best_count) {
+                best = Some((*category, *count));
+            }
+        }
+        best.map(|(category, _)| if category == *label { 0.0 } else { 1.0 })
+    }
+}
+
+impl KnnPrediction for VecSummary {
+    /// Predicts the mean of each target. The error is the mean squared error over the targets.
+    fn prediction_error(&self, label: &[f32]) -> Option<f32> {
+        if self.count == 0 {
+            return None;
+        }
+        let squared: f32 = self
+            .mean()
+            .iter()
+            .zip(label)
+            .map(|(m, l)| (m - l) * (m - l))
+            .sum();
+        Some(squared / label.len().max(1) as f32)
+    }
+}
+
+/// The cross validated error of a kNN prediction with `k` neighbors.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KnnScore {
+    /// The number of neighbors
+    pub k: usize,
+    /// The mean error over the held out labeled points. For categorical labels this is the
+    /// misclassification rate, for vector labels the mean squared error.
+    pub error: f32,
+    /// The number of held out points that had a label and got a prediction
+    pub count: usize,
+}
+
+/// Picks `k` for kNN classification or regression by cross validation. The points are shuffled
+/// with the builder's seed and dealt into `folds` folds. For each fold a tree is built with the
+/// builder on the other folds, in parallel, and every labeled point of the held out fold is
+/// predicted from its nearest neighbors in that tree. Returns the error for each of the `ks`, in
+/// the same order.
+///
+/// The trees are built on [`SubsetCloud`] views, so the point cloud isn't copied.
+pub fn cv_knn<D>(
+    point_cloud: Arc<D>,
+    ks: &[usize],
+    folds: usize,
+    builder: &CoverTreeBuilder,
+) -> GokoResult<Vec<KnnScore>>
+where
+    D: PointCloud,
+    D::LabelSummary: KnnPrediction<Label = D::Label>,
+{
+    let mut point_indexes = point_cloud.reference_indexes();
+    if folds < 2 || folds > point_indexes.len() {
+        return Err(GokoError::InvalidFoldCount {
+            folds,
+            points: point_indexes.len(),
+        });
+    }
+    point_indexes.sort_unstable();
+    let mut rng = SmallRng::seed_from_u64(builder.rng_seed.unwrap_or(0));
+    point_indexes.shuffle(&mut rng);
+    let max_k = ks.iter().copied().max().unwrap_or(0);
+
+    let fold_errors = (0..folds)
+        .into_par_iter()
+        .map(|fold| -> GokoResult<Vec<(f32, usize)>> {
+            let mut train = Vec::with_capacity(point_indexes.len());
+            let mut test = Vec::with_capacity(point_indexes.len() / folds + 1);
+            for (i, pi) in point_indexes.iter().enumerate() {
+                if i % folds == fold {
+                    test.push(*pi);
+                } else {
+                    train.push(*pi);
+                }
+            }
+            let train_cloud = SubsetCloud::new(Arc::clone(&point_cloud), train)?;
+            let tree = builder.build(Arc::new(train_cloud))?;
+            let reader = tree.reader();
+
+            let mut errors = vec![(0.0f32, 0usize); ks.len()];
+            for pi in test {
+                let label = match point_cloud.label(pi)? {
+                    Some(label) => label,
+                    None => continue,
+                };
+                let point = point_cloud.point(pi)?;
+                let neighbors: Vec<usize> = reader
+                    .knn_preprocessed(&point, max_k)?
+                    .iter()
+                    .map(|(_, i)| *i)
+                    .collect();
+                for (k, (total, count)) in ks.iter().zip(errors.iter_mut()) {
+                    let summary =
+                        point_cloud.label_summary(&neighbors[..(*k).min(neighbors.len())])?;
+                    if let Some(error) = summary.summary.prediction_error(label) {
+                        *total += error;
+                        *count += 1;
+                    }
+                }
+            }
+            Ok(errors)
+        })
+        .collect::<GokoResult<Vec<_>>>()?;
+
+    Ok(ks
+        .iter()
+        .enumerate()
+        .map(|(i, k)| {
+            let (total, count) = fold_errors.iter().fold((0.0, 0), |(t, c), errors| {
+                (t + errors[i].0, c + errors[i].1)
+            });
+            KnnScore {
+                k: *k,
+                error: if count > 0 { total / count as f32 } else { 0.0 },
+                count,
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn builds_on_mmap_files() {
+        let dir = TempDir::new("goko_utils").unwrap();
+        let path = dir.path().join("points.pcf4");
+        let mut writer = MmapFileWriter::create(&path, 1).unwrap();
+        writer.write_points(&[0.499, 0.49, 0.48]).unwrap();
+        writer.write_points(&[-0.49, 0.0]).unwrap();
+        writer.finish().unwrap();
+
+        let mut builder = CoverTreeBuilder::new();
+        builder
+            .set_scale_base(2.0)
+            .set_leaf_cutoff(1)
+            .set_min_res_index(-9);
+        let tree = cover_tree_from_mmap_file(&path, &builder).unwrap();
+        let reader = tree.reader();
+        assert_eq!(reader.parameters().point_cloud.len(), 5);
+        let nbrs = reader.knn(&[0.0f32].as_ref(), 2).unwrap();
+        assert_eq!(nbrs[0].1, 4);
+    }
+
+    #[test]
+    fn dedupe_groups_close_points() {
+        let data = vec![0.0, 1.0, 0.01, 0.02, 5.0, 1.005, 0.03];
+        let point_cloud = Arc::new(DefaultCloud::<L2>::new(data, 1).unwrap());
+        let mut builder = CoverTreeBuilder::new();
+        builder.set_min_res_index(-9).set_rng_seed(0);
+        let tree = builder.build(point_cloud).unwrap();
+        let reader = tree.reader();
+
+        let groups = dedupe(&reader, 0.025, DedupeKeep::First).unwrap();
+        let members: Vec<Vec<usize>> = groups.iter().map(|g| g.members.clone()).collect();
+        // 6 is within the radius of 2 and 3 but not 0, and 2 and 3 are taken by 0's group
+        assert_eq!(members, vec![vec![0, 2, 3], vec![1, 5], vec![4], vec![6]]);
+        let canonical: Vec<usize> = groups.iter().map(|g| g.canonical).collect();
+        assert_eq!(canonical, vec![0, 1, 4, 6]);
+
+        let groups = dedupe(&reader, 0.025, DedupeKeep::Centroid).unwrap();
+        assert_eq!(groups[0].canonical, 2);
+        assert_eq!(groups[1].canonical, 1);
+    }
+
+    #[test]
+    fn cv_knn_scores_ks() {
+        // Two well separated clusters, each with a few points of the other's label mixed in
+        let mut data = Vec::new();
+        let mut labels = Vec::new();
+        for i in 0..40 {
+            data.push(i as f32 * 0.01);
+            labels.push(if i % 10 == 0 { 1 } else { 0 });
+            data.push(10.0 + i as f32 * 0.01);
+            labels.push(if i % 10 == 0 { 0 } else { 1 });
+        }
+        let point_cloud = Arc::new(DefaultLabeledCloud::<L2>::new_simple(data, 1, labels));
+        let mut builder = CoverTreeBuilder::new();
+        builder.set_min_res_index(-9).set_rng_seed(0);
+
+        let scores = cv_knn(point_cloud, &[1, 15], 4, &builder).unwrap();
+        assert_eq!(scores.len(), 2);
+        assert_eq!(scores[0].k, 1);
+        assert_eq!(scores[1].k, 15);
+        assert_eq!(scores[0].count, 80);
+        // 15 neighbors outvote the mislabeled points, so only the 8 mislabeled points themselves
+        // are missed. A single neighbor misses those and can be fooled by them.
+        assert_approx_eq!(scores[1].error, 0.1);
+        assert!(scores[0].error >= scores[1].error);
+    }
+
+    #[test]
+    fn cv_knn_checks_folds() {
+        let point_cloud = Arc::new(DefaultLabeledCloud::<L2>::new_simple(
+            vec![0.0, 1.0],
+            1,
+            vec![0, 1],
+        ));
+        let builder = CoverTreeBuilder::new();
+        assert!(cv_knn(Arc::clone(&point_cloud), &[1], 1, &builder).is_err());
+        assert!(cv_knn(point_cloud, &[1], 3, &builder).is_err());
+    }
+}
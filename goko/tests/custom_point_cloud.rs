@@ -0,0 +1,130 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! Builds a tree on a point cloud implemented outside the crates, the way a user would wrap
+//! their own storage.
+#![feature(generic_associated_types)]
+
+extern crate goko;
+extern crate pointcloud;
+use goko::CoverTreeBuilder;
+use pointcloud::pc_errors::{PointCloudError, PointCloudResult};
+use pointcloud::*;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// A key value store of points, the tree addresses them by the order they were put in.
+struct KeyValueCloud {
+    keys: Vec<String>,
+    indexes: HashMap<String, usize>,
+    values: HashMap<String, Vec<f32>>,
+    dim: usize,
+    metric: L2,
+}
+
+impl KeyValueCloud {
+    fn new(dim: usize) -> KeyValueCloud {
+        KeyValueCloud {
+            keys: Vec::new(),
+            indexes: HashMap::new(),
+            values: HashMap::new(),
+            dim,
+            metric: L2 {},
+        }
+    }
+
+    fn put(&mut self, key: &str, value: Vec<f32>) {
+        self.indexes.insert(key.to_string(), self.keys.len());
+        self.keys.push(key.to_string());
+        self.values.insert(key.to_string(), value);
+    }
+}
+
+impl PointCloud for KeyValueCloud {
+    type Point = [f32];
+    type PointRef<'a> = &'a [f32];
+    type Metric = L2;
+    type Label = ();
+    type LabelSummary = ();
+    type Metadata = ();
+    type MetaSummary = ();
+
+    fn len(&self) -> usize {
+        self.keys.len()
+    }
+    fn dim(&self) -> usize {
+        self.dim
+    }
+    fn point<'a, 'b: 'a>(&'b self, i: usize) -> PointCloudResult<&'a [f32]> {
+        self.keys
+            .get(i)
+            .and_then(|key| self.values.get(key))
+            .map(|value| &value[..])
+            .ok_or_else(|| PointCloudError::data_access(i, "no such key".to_string()))
+    }
+    fn metric(&self) -> &L2 {
+        &self.metric
+    }
+    fn name(&self, pi: usize) -> PointCloudResult<String> {
+        self.keys
+            .get(pi)
+            .cloned()
+            .ok_or(PointCloudError::UnknownName)
+    }
+    fn index(&self, pn: &str) -> PointCloudResult<usize> {
+        self.indexes
+            .get(pn)
+            .copied()
+            .ok_or(PointCloudError::UnknownName)
+    }
+    fn names(&self) -> Vec<String> {
+        self.keys.clone()
+    }
+}
+
+#[test]
+fn builds_on_a_user_point_cloud() {
+    let mut cloud = KeyValueCloud::new(2);
+    for i in 0..50 {
+        let x = i as f32 / 10.0;
+        cloud.put(&format!("point-{}", i), vec![x, x.sin()]);
+    }
+    let mut builder = CoverTreeBuilder::new();
+    builder
+        .set_leaf_cutoff(1)
+        .set_min_res_index(-9)
+        .set_rng_seed(0);
+    let tree = builder.build(Arc::new(cloud)).unwrap();
+    let reader = tree.reader();
+    let root_coverage = reader
+        .get_node_and(reader.root_address(), |n| n.coverage_count())
+        .unwrap();
+    assert_eq!(root_coverage, 50);
+
+    let query = [2.05f32, 2.05f32.sin()];
+    let knn = reader.knn(&&query[..], 2).unwrap();
+    let names: Vec<String> = knn
+        .iter()
+        .map(|(_, pi)| reader.point_cloud().name(*pi).unwrap())
+        .collect();
+    names.iter().for_each(|name| {
+        assert!(name == "point-20" || name == "point-21", "{}", name);
+    });
+    assert_eq!(reader.point_cloud().index("point-21").unwrap(), 21);
+}
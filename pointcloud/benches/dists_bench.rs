@@ -23,7 +23,7 @@ use pointcloud::*;
 
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
 
-fn build_ram_random_test<M: Metric<[f32]>>(count: usize, data_dim: usize) -> DataRam<M> {
+fn build_ram_random_test<M: Metric<[f32]> + Default>(count: usize, data_dim: usize) -> DataRam<M> {
     DataRam::<M>::new(
         (0..count * data_dim)
             .map(|_i| rand::random::<f32>())
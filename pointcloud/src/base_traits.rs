@@ -6,8 +6,11 @@ use std::fmt::Debug;
 
 use std::ops::Deref;
 
+use crate::partitions::PartitionMap;
 use crate::pc_errors::*;
+use crate::subset_cloud::SubsetCloud;
 use serde::{Deserialize, Serialize};
+use std::sync::Arc;
 
 /// A trait to ensure that we can create matrices and statiscial vectors from your point reference.
 ///
@@ -27,10 +30,11 @@ pub trait PointRef: Send + Sync {
 
 /// Metric trait. Done as a trait so that it's easy to switch out.
 ///
-/// Implement this then benchmark it to hell, this is the core loop of everything.
+/// Implement this then benchmark it to hell, this is the core loop of everything. The metric is
+/// held by the point cloud, so it can carry parameters like per-dimension weights.
 pub trait Metric<T: ?Sized>: Send + Sync + 'static {
     /// Distance calculator. Optimize the hell out of this if you're implementing it.
-    fn dist(x: &T, y: &T) -> f32;
+    fn dist(&self, x: &T, y: &T) -> f32;
     // Implemented, but the system that uses this isn't yet.
     //fn norm(x: &RawSparse<f32, u32>) -> f32
 }
@@ -43,9 +47,68 @@ fn chunk(data_dim: usize) -> usize {
 }
 
 /// Base trait for a point cloud
+///
+/// This is the storage layer the trees are built on. Anything that can hand out its points by
+/// index can implement it, like a wrapper around an embedded key value store or a feature
+/// service, and then be passed to goko's `CoverTreeBuilder::build`. Only the point access,
+/// `len`, `dim`, `point` and `metric`, has to be written. The labels, metadata and names have
+/// defaults for an unlabeled cloud with `()` labels and metadata, the points are named by their
+/// index.
+///
+/// The points are addressed by index, `0..len()`, and the tree keeps these. A backend with other
+/// keys should keep its own map from index to key. The points shouldn't change once a tree is
+/// built on them, a tree can only be extended with new points, see [`ExtendableCloud`].
+///
+/// ```
+/// #![feature(generic_associated_types)]
+/// use pointcloud::pc_errors::{PointCloudError, PointCloudResult};
+/// use pointcloud::*;
+///
+/// /// Points in a flat buffer, standing in for some other storage
+/// struct FlatStore {
+///     data: Vec<f32>,
+///     dim: usize,
+///     metric: L2,
+/// }
+///
+/// impl PointCloud for FlatStore {
+///     type Point = [f32];
+///     type PointRef<'a> = &'a [f32];
+///     type Metric = L2;
+///     type Label = ();
+///     type LabelSummary = ();
+///     type Metadata = ();
+///     type MetaSummary = ();
+///
+///     fn len(&self) -> usize {
+///         self.data.len() / self.dim
+///     }
+///     fn dim(&self) -> usize {
+///         self.dim
+///     }
+///     fn point<'a, 'b: 'a>(&'b self, i: usize) -> PointCloudResult<&'a [f32]> {
+///         self.data
+///             .get(i * self.dim..(i + 1) * self.dim)
+///             .ok_or_else(|| PointCloudError::data_access(i, "no such point".to_string()))
+///     }
+///     fn metric(&self) -> &L2 {
+///         &self.metric
+///     }
+/// }
+///
+/// let store = FlatStore {
+///     data: vec![0.0, 0.0, 3.0, 4.0],
+///     dim: 2,
+///     metric: L2 {},
+/// };
+/// let dists = store.distances_to_point_index(0, &[1]).unwrap();
+/// assert!((dists[0] - 5.0).abs() < 1e-6);
+/// assert_eq!(store.name(1).unwrap(), "1");
+/// assert_eq!(store.label_summary(&[0, 1]).unwrap().nones(), 2);
+/// ```
 pub trait PointCloud: Send + Sync + 'static {
     /// The derefrenced, raw point. Think [f32]
-    type Point: ?Sized;
+    type Point: ?Sized + Send + Sync;
     /// A reference to a point. Think &'a [f32]
     ///
     /// It might be better to move this to the `point` function, but this way we can rely on it for other things.
@@ -62,34 +125,143 @@ pub trait PointCloud: Send + Sync + 'static {
     /// A summary of the underlying metadata
     type MetaSummary: Summary<Label = Self::Metadata>;
 
-    /// Expensive metadata object for the sample
-    fn metadata(&self, pn: usize) -> PointCloudResult<Option<&Self::Metadata>>;
-    /// Expensive metadata summary over the samples
-    fn metasummary(&self, pns: &[usize]) -> PointCloudResult<SummaryCounter<Self::MetaSummary>>;
+    /// Expensive metadata object for the sample. No point has any by default.
+    fn metadata(&self, _pn: usize) -> PointCloudResult<Option<&Self::Metadata>> {
+        Ok(None)
+    }
+    /// Expensive metadata summary over the samples. By default every point that wasn't deleted
+    /// is counted as having none, as many times as its multiplicity.
+    fn metasummary(&self, pns: &[usize]) -> PointCloudResult<SummaryCounter<Self::MetaSummary>> {
+        Ok(SummaryCounter {
+            summary: Self::MetaSummary::default(),
+            nones: pns
+                .iter()
+                .filter(|pi| !self.is_deleted(**pi))
+                .map(|pi| self.multiplicity(*pi))
+                .sum(),
+            errors: 0,
+        })
+    }
 
     /// Grabs a label reference. Supports errors (the label could be remote),
-    /// and partially labeled datasets with the option.
-    fn label(&self, pn: usize) -> PointCloudResult<Option<&Self::Label>>;
-    /// Grabs a label summary of a set of indexes.
-    fn label_summary(&self, pns: &[usize]) -> PointCloudResult<SummaryCounter<Self::LabelSummary>>;
+    /// and partially labeled datasets with the option. No point has one by default.
+    fn label(&self, _pn: usize) -> PointCloudResult<Option<&Self::Label>> {
+        Ok(None)
+    }
+    /// Grabs a label summary of a set of indexes. By default every point that wasn't deleted is
+    /// counted as unlabeled, as many times as its multiplicity.
+    fn label_summary(&self, pns: &[usize]) -> PointCloudResult<SummaryCounter<Self::LabelSummary>> {
+        Ok(SummaryCounter {
+            summary: Self::LabelSummary::default(),
+            nones: pns
+                .iter()
+                .filter(|pi| !self.is_deleted(**pi))
+                .map(|pi| self.multiplicity(*pi))
+                .sum(),
+            errors: 0,
+        })
+    }
+    /// The name of a label, for label sets that store categories as codes. `None` by default.
+    fn label_name(&self, _label: &Self::Label) -> Option<&str> {
+        None
+    }
     /// Grabs the name of the point.
-    /// Returns an error if the access errors out, and a None if the name is unknown
-    fn name(&self, pi: usize) -> PointCloudResult<String>;
-    /// Converts a name to an index you can use
-    fn index(&self, pn: &str) -> PointCloudResult<usize>;
+    /// Returns an error if the access errors out, and a None if the name is unknown. The index by
+    /// default.
+    fn name(&self, pi: usize) -> PointCloudResult<String> {
+        Ok(pi.to_string())
+    }
+    /// Converts a name to an index you can use. Parses the index by default.
+    fn index(&self, pn: &str) -> PointCloudResult<usize> {
+        pn.parse::<usize>().map_err(|_| {
+            ParsingError::RegularParsingError("Unable to parse your str into an usize").into()
+        })
+    }
     /// Gather's all valid known names
-    fn names(&self) -> Vec<String>;
+    fn names(&self) -> Vec<String> {
+        (0..self.len()).map(|i| i.to_string()).collect()
+    }
 
     /// The number of samples this cloud covers
     fn len(&self) -> usize;
     /// If this is empty
-    fn is_empty(&self) -> bool;
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
     /// The dimension of the underlying data
     fn dim(&self) -> usize;
-    /// Indexes used for access
-    fn reference_indexes(&self) -> Vec<usize>;
+    /// Indexes used for access. All the points that weren't deleted or collapsed into another by
+    /// default.
+    fn reference_indexes(&self) -> Vec<usize> {
+        (0..self.len())
+            .filter(|i| !self.is_deleted(*i) && self.multiplicity(*i) > 0)
+            .collect()
+    }
     /// Gets a point from this dataset
     fn point<'a, 'b: 'a>(&'b self, i: usize) -> PointCloudResult<Self::PointRef<'a>>;
+    /// The metric instance used for all distances on this point cloud
+    fn metric(&self) -> &Self::Metric;
+    /// If the point was deleted. Deleted points keep their index and their data, but queries and
+    /// summaries leave them out. Nothing is deleted by default.
+    fn is_deleted(&self, _pi: usize) -> bool {
+        false
+    }
+    /// The indexes of all the deleted points, in no particular order.
+    fn deleted_indexes(&self) -> Vec<usize> {
+        Vec::new()
+    }
+    /// How many identical points this one stands for, see [`crate::duplicates`]. A copy that was
+    /// collapsed into another point has a multiplicity of 0 and is left out of the reference
+    /// indexes. Every point counts once by default.
+    fn multiplicity(&self, _pi: usize) -> usize {
+        1
+    }
+    /// The point this one was collapsed into, itself unless it's a collapsed copy.
+    fn representative(&self, pi: usize) -> usize {
+        pi
+    }
+    /// Puts a query point through the preprocessing this cloud's points went through when they
+    /// were loaded, like a [`crate::normalization::Normalizer`]. `None` if there's none and the
+    /// point can be used as is.
+    fn preprocess_query(&self, _point: &Self::Point) -> Option<Box<Self::Point>> {
+        None
+    }
+    /// The partition keys of the points, see [`crate::partitions`]. `None` if the points
+    /// weren't partitioned.
+    fn partitions(&self) -> Option<&PartitionMap> {
+        None
+    }
+    /// Splits the points into a training view of `fraction` of them and a held-out view of the
+    /// rest, shuffled with the seed. The views share this cloud and keep its indexes, so a tree
+    /// built on the training view can be evaluated on the held-out points. See [`SubsetCloud`].
+    fn split(
+        self: Arc<Self>,
+        fraction: f32,
+        seed: u64,
+    ) -> PointCloudResult<(SubsetCloud<Self>, SubsetCloud<Self>)>
+    where
+        Self: Sized,
+    {
+        SubsetCloud::split(self, fraction, seed)
+    }
+    /// A view of `n` of the points, picked at random with the seed. See [`SubsetCloud`].
+    fn subsample(self: Arc<Self>, n: usize, seed: u64) -> PointCloudResult<SubsetCloud<Self>>
+    where
+        Self: Sized,
+    {
+        SubsetCloud::subsample(self, n, seed)
+    }
+    /// The names of the dimensions, like the columns of a CSV header. `None` if the data didn't
+    /// come with any.
+    fn dim_names(&self) -> Option<&[String]> {
+        None
+    }
+    /// The name of a dimension, if the dimensions are named.
+    fn dim_name(&self, i: usize) -> Option<&str> {
+        self.dim_names()
+            .and_then(|names| names.get(i))
+            .map(|name| name.as_str())
+    }
 
     /// Returns a dense array
     fn point_dense_array(&self, index: usize) -> PointCloudResult<Array1<f32>> {
@@ -127,11 +299,11 @@ pub trait PointCloud: Send + Sync + 'static {
             for j in js.iter() {
                 if i < j && !indexes.contains(&(*i, *j)) {
                     let y = self.point(*j)?;
-                    vals.push(Self::Metric::dist(&x,&y));
+                    vals.push(self.metric().dist(&x,&y));
                     indexes.push((*i, *j));
                 } else if j < i && !indexes.contains(&(*j, *i)) {
                     let y = self.point(*j)?;
-                    vals.push(Self::Metric::dist(&x,&y));
+                    vals.push(self.metric().dist(&x,&y));
                     indexes.push((*j, *i));
                 }
             }
@@ -185,7 +357,7 @@ pub trait PointCloud: Send + Sync + 'static {
                             for (d, j) in chunk_dists.iter_mut().zip(chunk_indexes) {
                                 match self
                                     .point(*j)
-                                    .map(|y| Self::Metric::dist(&x,&y))
+                                    .map(|y| self.metric().dist(&x,&y))
                                 {
                                     Ok(dist) => *d = dist,
                                     Err(e) => {
@@ -205,7 +377,7 @@ pub trait PointCloud: Send + Sync + 'static {
                 let x = self.point(*i)?;
                 for (l, j) in js.iter().enumerate() {
                     let y = self.point(*j)?;
-                    dists[k * js.len() + l] = Self::Metric::dist(&x,&y);
+                    dists[k * js.len() + l] = self.metric().dist(&x,&y);
                 }
             }
         }
@@ -235,7 +407,7 @@ pub trait PointCloud: Send + Sync + 'static {
                 .zip(indexes_iter)
                 .for_each(|(chunk_dists, chunk_indexes)| {
                     for (d, i) in chunk_dists.iter_mut().zip(chunk_indexes) {
-                        match self.point(*i).map(|y| Self::Metric::dist(&x, &y)) {
+                        match self.point(*i).map(|y| self.metric().dist(&x, &y)) {
                             Ok(dist) => *d = dist,
                             Err(e) => {
                                 *error.lock().unwrap() = Err(e);
@@ -250,7 +422,7 @@ pub trait PointCloud: Send + Sync + 'static {
                 .iter()
                 .map(|i| {
                     let y = self.point(*i)?;
-                    Ok(Self::Metric::dist(&x, &y))
+                    Ok(self.metric().dist(&x, &y))
                 })
                 .collect()
         }
@@ -374,6 +546,58 @@ pub trait LabelSet: Debug + Send + Sync + 'static {
     fn label(&self, pn: usize) -> PointCloudResult<Option<&Self::Label>>;
     /// Grabs a label summary of a set of indexes.
     fn label_summary(&self, pns: &[usize]) -> PointCloudResult<SummaryCounter<Self::LabelSummary>>;
+    /// The name of a label, for label sets that store categories as codes. `None` by default.
+    fn label_name(&self, _label: &Self::Label) -> Option<&str> {
+        None
+    }
+}
+
+/// A label set whose labels can be corrected after it's been loaded.
+pub trait LabelSetMut: LabelSet {
+    /// Replaces the label of a point. Passing `None` marks the point as unlabeled.
+    fn set_label(&mut self, pn: usize, label: Option<&Self::Label>) -> PointCloudResult<()>;
+}
+
+/// A point cloud whose labels can be corrected after it's been loaded.
+pub trait LabeledCloudMut: PointCloud {
+    /// Replaces the label of a point. Passing `None` marks the point as unlabeled.
+    fn set_label(&mut self, pn: usize, label: Option<&Self::Label>) -> PointCloudResult<()>;
+}
+
+/// A label set that new labels can be appended to.
+pub trait ExtendableLabelSet: LabelSet {
+    /// Appends the label of a new point. Passing `None` appends an unlabeled point.
+    fn push_label(&mut self, label: Option<&Self::Label>) -> PointCloudResult<()>;
+}
+
+/// A point cloud that new points can be appended to. The new points get the indexes after the
+/// existing ones.
+pub trait ExtendableCloud: PointCloud {
+    /// Appends the points, given as consecutive rows of `dim` floats, with one label each.
+    /// `None` appends an unlabeled point.
+    fn extend_points(
+        &mut self,
+        points: &[f32],
+        labels: &[Option<&Self::Label>],
+    ) -> PointCloudResult<()>;
+    /// Collapses a point into another, like the copies of a cloud these points were appended
+    /// from, see [`crate::duplicates`]. Clouds can't collapse points by default.
+    fn collapse_point(&mut self, pi: usize, _into: usize) -> PointCloudResult<()> {
+        Err(PointCloudError::DataAccessError {
+            index: pi,
+            reason: "this point cloud can't collapse points".to_string(),
+        })
+    }
+}
+
+/// A point cloud whose points can be marked as deleted, see [`PointCloud::is_deleted`]. Deleted
+/// points are also left out of [`PointCloud::reference_indexes`], so a tree built after the
+/// deletion doesn't have them.
+pub trait DeletableCloud: PointCloud {
+    /// Marks the point as deleted. Deleting a deleted point does nothing.
+    fn delete_point(&mut self, pi: usize) -> PointCloudResult<()>;
+    /// Brings back a deleted point. Restoring a point that wasn't deleted does nothing.
+    fn restore_point(&mut self, pi: usize) -> PointCloudResult<()>;
 }
 
 /// Simply shoves together a point cloud and a label set, for a modular label system
@@ -429,6 +653,23 @@ impl<S: Summary + Clone> SummaryCounter<S> {
     }
 }
 
+/// The indexes that a label or metadata set should summarize: the points that weren't deleted,
+/// each repeated as many times as its multiplicity. `None` if that's just `pns`.
+fn counted_indexes<D: PointCloud>(data: &D, pns: &[usize]) -> Option<Vec<usize>> {
+    if pns
+        .iter()
+        .all(|pi| !data.is_deleted(*pi) && data.multiplicity(*pi) == 1)
+    {
+        return None;
+    }
+    Some(
+        pns.iter()
+            .filter(|pi| !data.is_deleted(**pi))
+            .flat_map(|pi| std::iter::repeat(*pi).take(data.multiplicity(*pi)))
+            .collect(),
+    )
+}
+
 /// Simply shoves together a point cloud and a label set, for a modular label system
 #[derive(Debug)]
 pub struct SimpleLabeledCloud<D, L> {
@@ -443,6 +684,51 @@ impl<D, L> SimpleLabeledCloud<D, L> {
     }
 }
 
+impl<D: PointCloud, L: LabelSetMut> LabeledCloudMut for SimpleLabeledCloud<D, L> {
+    fn set_label(&mut self, pn: usize, label: Option<&Self::Label>) -> PointCloudResult<()> {
+        self.labels.set_label(pn, label)
+    }
+}
+
+impl<D: DeletableCloud, L: LabelSet> DeletableCloud for SimpleLabeledCloud<D, L> {
+    fn delete_point(&mut self, pi: usize) -> PointCloudResult<()> {
+        self.data.delete_point(pi)
+    }
+    fn restore_point(&mut self, pi: usize) -> PointCloudResult<()> {
+        self.data.restore_point(pi)
+    }
+}
+
+impl<D: ExtendableCloud, L: ExtendableLabelSet> ExtendableCloud for SimpleLabeledCloud<D, L> {
+    fn extend_points(
+        &mut self,
+        points: &[f32],
+        labels: &[Option<&Self::Label>],
+    ) -> PointCloudResult<()> {
+        let dim = self.data.dim();
+        if points.len() != dim * labels.len() {
+            return Err(PointCloudError::DataAccessError {
+                index: self.data.len(),
+                reason: format!(
+                    "got {} floats for {} labels of dimension {} points",
+                    points.len(),
+                    labels.len(),
+                    dim
+                ),
+            });
+        }
+        let unlabeled: Vec<Option<&D::Label>> = vec![None; labels.len()];
+        self.data.extend_points(points, &unlabeled)?;
+        for label in labels {
+            self.labels.push_label(*label)?;
+        }
+        Ok(())
+    }
+    fn collapse_point(&mut self, pi: usize, into: usize) -> PointCloudResult<()> {
+        self.data.collapse_point(pi, into)
+    }
+}
+
 impl<D: PointCloud, L: LabelSet> PointCloud for SimpleLabeledCloud<D, L> {
     /// Underlying metric this point cloud uses
     type Metric = D::Metric;
@@ -474,6 +760,34 @@ impl<D: PointCloud, L: LabelSet> PointCloud for SimpleLabeledCloud<D, L> {
     fn point<'a, 'b: 'a>(&'b self, i: usize) -> PointCloudResult<Self::PointRef<'a>> {
         self.data.point(i)
     }
+    #[inline]
+    fn metric(&self) -> &Self::Metric {
+        self.data.metric()
+    }
+    #[inline]
+    fn is_deleted(&self, pi: usize) -> bool {
+        self.data.is_deleted(pi)
+    }
+    fn deleted_indexes(&self) -> Vec<usize> {
+        self.data.deleted_indexes()
+    }
+    #[inline]
+    fn multiplicity(&self, pi: usize) -> usize {
+        self.data.multiplicity(pi)
+    }
+    #[inline]
+    fn representative(&self, pi: usize) -> usize {
+        self.data.representative(pi)
+    }
+    fn dim_names(&self) -> Option<&[String]> {
+        self.data.dim_names()
+    }
+    fn preprocess_query(&self, point: &Self::Point) -> Option<Box<Self::Point>> {
+        self.data.preprocess_query(point)
+    }
+    fn partitions(&self) -> Option<&PartitionMap> {
+        self.data.partitions()
+    }
 
     fn metadata(&self, pn: usize) -> PointCloudResult<Option<&Self::Metadata>> {
         self.data.metadata(pn)
@@ -488,9 +802,16 @@ impl<D: PointCloud, L: LabelSet> PointCloud for SimpleLabeledCloud<D, L> {
     fn label(&self, pn: usize) -> PointCloudResult<Option<&Self::Label>> {
         self.labels.label(pn)
     }
-    /// Grabs a label summary of a set of indexes.
+    /// Grabs a label summary of a set of indexes, leaving out the deleted points and counting
+    /// each point as many times as its multiplicity.
     fn label_summary(&self, pns: &[usize]) -> PointCloudResult<SummaryCounter<Self::LabelSummary>> {
-        self.labels.label_summary(pns)
+        match counted_indexes(&self.data, pns) {
+            Some(counted) => self.labels.label_summary(&counted),
+            None => self.labels.label_summary(pns),
+        }
+    }
+    fn label_name(&self, label: &Self::Label) -> Option<&str> {
+        self.labels.label_name(label)
     }
     /// Grabs the name of the point.
     /// Returns an error if the access errors out, and a None if the name is unknown
@@ -568,6 +889,34 @@ impl<D: PointCloud, N: NamedSet> PointCloud for SimpleNamedCloud<D, N> {
     fn point<'a, 'b: 'a>(&'b self, i: usize) -> PointCloudResult<Self::PointRef<'a>> {
         self.data.point(i)
     }
+    #[inline]
+    fn metric(&self) -> &Self::Metric {
+        self.data.metric()
+    }
+    #[inline]
+    fn is_deleted(&self, pi: usize) -> bool {
+        self.data.is_deleted(pi)
+    }
+    fn deleted_indexes(&self) -> Vec<usize> {
+        self.data.deleted_indexes()
+    }
+    #[inline]
+    fn multiplicity(&self, pi: usize) -> usize {
+        self.data.multiplicity(pi)
+    }
+    #[inline]
+    fn representative(&self, pi: usize) -> usize {
+        self.data.representative(pi)
+    }
+    fn dim_names(&self) -> Option<&[String]> {
+        self.data.dim_names()
+    }
+    fn preprocess_query(&self, point: &Self::Point) -> Option<Box<Self::Point>> {
+        self.data.preprocess_query(point)
+    }
+    fn partitions(&self) -> Option<&PartitionMap> {
+        self.data.partitions()
+    }
 
     fn metadata(&self, pn: usize) -> PointCloudResult<Option<&Self::Metadata>> {
         self.data.metadata(pn)
@@ -586,6 +935,9 @@ impl<D: PointCloud, N: NamedSet> PointCloud for SimpleNamedCloud<D, N> {
     fn label_summary(&self, pns: &[usize]) -> PointCloudResult<SummaryCounter<Self::LabelSummary>> {
         self.data.label_summary(pns)
     }
+    fn label_name(&self, label: &Self::Label) -> Option<&str> {
+        self.data.label_name(label)
+    }
     /// Grabs the name of the point.
     /// Returns an error if the access errors out, and a None if the name is unknown
     fn name(&self, pi: usize) -> PointCloudResult<String> {
@@ -601,6 +953,150 @@ impl<D: PointCloud, N: NamedSet> PointCloud for SimpleNamedCloud<D, N> {
     }
 }
 
+/// Simply shoves together a point cloud and a metadata set, so query results can be joined back to where the points
+/// came from. See [`crate::meta_sources::MetadataMap`].
+#[derive(Debug)]
+pub struct SimpleMetaCloud<D, M> {
+    data: D,
+    metadata: M,
+}
+
+impl<D: PointCloud, M: MetaSet> SimpleMetaCloud<D, M> {
+    /// Creates a new one
+    pub fn new(data: D, metadata: M) -> Self {
+        SimpleMetaCloud { data, metadata }
+    }
+
+    /// The metadata set
+    pub fn metadata_set(&self) -> &M {
+        &self.metadata
+    }
+
+    /// Mutable access to the metadata set, to add or change the metadata of points
+    pub fn metadata_mut(&mut self) -> &mut M {
+        &mut self.metadata
+    }
+}
+
+impl<D: ExtendableCloud, M: MetaSet + Send + Sync + 'static> ExtendableCloud
+    for SimpleMetaCloud<D, M>
+{
+    /// The new points have no metadata.
+    fn extend_points(
+        &mut self,
+        points: &[f32],
+        labels: &[Option<&Self::Label>],
+    ) -> PointCloudResult<()> {
+        self.data.extend_points(points, labels)
+    }
+    fn collapse_point(&mut self, pi: usize, into: usize) -> PointCloudResult<()> {
+        self.data.collapse_point(pi, into)
+    }
+}
+
+impl<D: DeletableCloud, M: MetaSet + Send + Sync + 'static> DeletableCloud
+    for SimpleMetaCloud<D, M>
+{
+    fn delete_point(&mut self, pi: usize) -> PointCloudResult<()> {
+        self.data.delete_point(pi)
+    }
+    fn restore_point(&mut self, pi: usize) -> PointCloudResult<()> {
+        self.data.restore_point(pi)
+    }
+}
+
+impl<D: PointCloud, M: MetaSet + Send + Sync + 'static> PointCloud for SimpleMetaCloud<D, M> {
+    /// Underlying metric this point cloud uses
+    type Metric = D::Metric;
+    type Point = D::Point;
+    type PointRef<'a> = D::PointRef<'a>;
+    type Metadata = M::Metadata;
+    type MetaSummary = M::MetaSummary;
+
+    type Label = D::Label;
+    type LabelSummary = D::LabelSummary;
+
+    #[inline]
+    fn dim(&self) -> usize {
+        self.data.dim()
+    }
+    #[inline]
+    fn len(&self) -> usize {
+        self.data.len()
+    }
+    #[inline]
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+    #[inline]
+    fn reference_indexes(&self) -> Vec<usize> {
+        self.data.reference_indexes()
+    }
+    #[inline]
+    fn point<'a, 'b: 'a>(&'b self, i: usize) -> PointCloudResult<Self::PointRef<'a>> {
+        self.data.point(i)
+    }
+    #[inline]
+    fn metric(&self) -> &Self::Metric {
+        self.data.metric()
+    }
+    #[inline]
+    fn is_deleted(&self, pi: usize) -> bool {
+        self.data.is_deleted(pi)
+    }
+    fn deleted_indexes(&self) -> Vec<usize> {
+        self.data.deleted_indexes()
+    }
+    #[inline]
+    fn multiplicity(&self, pi: usize) -> usize {
+        self.data.multiplicity(pi)
+    }
+    #[inline]
+    fn representative(&self, pi: usize) -> usize {
+        self.data.representative(pi)
+    }
+    fn dim_names(&self) -> Option<&[String]> {
+        self.data.dim_names()
+    }
+    fn preprocess_query(&self, point: &Self::Point) -> Option<Box<Self::Point>> {
+        self.data.preprocess_query(point)
+    }
+    fn partitions(&self) -> Option<&PartitionMap> {
+        self.data.partitions()
+    }
+
+    fn metadata(&self, pn: usize) -> PointCloudResult<Option<&Self::Metadata>> {
+        self.metadata.metadata(pn)
+    }
+    /// Expensive metadata summary over the samples, leaving out the deleted points and counting
+    /// each point as many times as its multiplicity.
+    fn metasummary(&self, pns: &[usize]) -> PointCloudResult<SummaryCounter<Self::MetaSummary>> {
+        match counted_indexes(&self.data, pns) {
+            Some(counted) => self.metadata.metasummary(&counted),
+            None => self.metadata.metasummary(pns),
+        }
+    }
+
+    fn label(&self, pn: usize) -> PointCloudResult<Option<&Self::Label>> {
+        self.data.label(pn)
+    }
+    fn label_summary(&self, pns: &[usize]) -> PointCloudResult<SummaryCounter<Self::LabelSummary>> {
+        self.data.label_summary(pns)
+    }
+    fn label_name(&self, label: &Self::Label) -> Option<&str> {
+        self.data.label_name(label)
+    }
+    fn name(&self, pi: usize) -> PointCloudResult<String> {
+        self.data.name(pi)
+    }
+    fn index(&self, pn: &str) -> PointCloudResult<usize> {
+        self.data.index(pn)
+    }
+    fn names(&self) -> Vec<String> {
+        self.data.names()
+    }
+}
+
 /// Allows for expensive metadata, this is identical to the label trait, but enables slower update
 pub trait MetaSet {
     /// Underlying metadata
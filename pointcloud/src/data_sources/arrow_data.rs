@@ -0,0 +1,209 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! Data backed by an Arrow array, without copying the floats out of it. Needs the `arrow` feature.
+
+use arrow::array::{Array, FixedSizeListArray, Float32Array};
+use arrow::datatypes::DataType;
+use arrow::record_batch::RecordBatch;
+use std::ops::Range;
+
+use crate::base_traits::*;
+use crate::metrics::*;
+use crate::pc_errors::{ParsingError, PointCloudError, PointCloudResult};
+
+/// The flat `f32` values of an Arrow array. The array shares its buffer with wherever it came
+/// from, so this is just a reference count.
+#[derive(Debug)]
+pub struct ArrowValues {
+    array: Float32Array,
+}
+
+impl ArrowValues {
+    /// The number of floats.
+    pub fn len(&self) -> usize {
+        self.array.len()
+    }
+
+    /// If there are no floats.
+    pub fn is_empty(&self) -> bool {
+        self.array.is_empty()
+    }
+
+    /// A slice of the floats.
+    pub fn get(&self, range: Range<usize>) -> Option<&[f32]> {
+        self.array.values().get(range)
+    }
+}
+
+/// Points stored as an Arrow `FixedSizeList<Float32>` column, where each list is one point. The
+/// list's values are laid out point after point, so the points can be read straight out of the
+/// Arrow buffer.
+#[derive(Debug)]
+pub struct DataArrow<M = L2> {
+    name: String,
+    data: ArrowValues,
+    dim: usize,
+    metric: M,
+}
+
+fn arrow_error(reason: String) -> PointCloudError {
+    PointCloudError::ParsingError(ParsingError::ArrowError { reason })
+}
+
+impl<M: Default> DataArrow<M> {
+    /// Wraps a `FixedSizeList<Float32>` array without copying it. The list values can't have nulls.
+    pub fn from_fixed_size_list(array: &FixedSizeListArray) -> PointCloudResult<DataArrow<M>> {
+        Self::with_metric(array, M::default())
+    }
+
+    /// Wraps the `FixedSizeList<Float32>` column with the given name without copying it.
+    pub fn from_record_batch(batch: &RecordBatch, column: &str) -> PointCloudResult<DataArrow<M>> {
+        let index = batch
+            .schema()
+            .index_of(column)
+            .map_err(|e| arrow_error(e.to_string()))?;
+        let array = batch
+            .column(index)
+            .as_any()
+            .downcast_ref::<FixedSizeListArray>()
+            .ok_or_else(|| {
+                arrow_error(format!(
+                    "column {} is a {:?}, not a fixed size list of f32",
+                    column,
+                    batch.column(index).data_type()
+                ))
+            })?;
+        let mut data = Self::from_fixed_size_list(array)?;
+        data.name = column.to_string();
+        Ok(data)
+    }
+}
+
+impl<M> DataArrow<M> {
+    /// Wraps a `FixedSizeList<Float32>` array without copying it, measuring distances with the
+    /// given metric instance.
+    pub fn with_metric(array: &FixedSizeListArray, metric: M) -> PointCloudResult<DataArrow<M>> {
+        match array.value_type() {
+            DataType::Float32 => {}
+            t => {
+                return Err(arrow_error(format!(
+                    "expected a fixed size list of f32, got a list of {:?}",
+                    t
+                )))
+            }
+        }
+        if array.null_count() > 0 {
+            return Err(arrow_error("the list has null points".to_string()));
+        }
+        let dim = array.value_length() as usize;
+        if dim == 0 {
+            return Err(arrow_error("the lists are empty".to_string()));
+        }
+        // The child array covers every list, including ones sliced off of this array
+        let values = array
+            .values()
+            .slice(array.offset() * dim, array.len() * dim);
+        let values = Float32Array::from(values.data().clone());
+        if values.null_count() > 0 {
+            return Err(arrow_error("the points have null values".to_string()));
+        }
+        Ok(DataArrow {
+            name: "Arrow".to_string(),
+            data: ArrowValues { array: values },
+            dim,
+            metric,
+        })
+    }
+
+    /// Swaps out the metric, keeping the data.
+    pub fn replace_metric<N>(self, metric: N) -> DataArrow<N> {
+        DataArrow {
+            name: self.name,
+            data: self.data,
+            dim: self.dim,
+            metric,
+        }
+    }
+}
+
+make_point_cloud!(DataArrow);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow::array::ArrayData;
+    use arrow::datatypes::{Field, Schema};
+    use std::sync::Arc;
+
+    fn fixed_size_list(values: &[f32], dim: i32) -> FixedSizeListArray {
+        let value_data = Float32Array::from(values.to_vec()).data().clone();
+        let list_data = ArrayData::builder(DataType::FixedSizeList(
+            Box::new(Field::new("item", DataType::Float32, false)),
+            dim,
+        ))
+        .len(values.len() / dim as usize)
+        .add_child_data(value_data)
+        .build();
+        FixedSizeListArray::from(list_data)
+    }
+
+    #[test]
+    fn reads_points_in_place() {
+        let values = [0.0f32, 1.0, 2.0, 3.0, 4.0, 5.0];
+        let array = fixed_size_list(&values, 2);
+        let data = DataArrow::<L2>::from_fixed_size_list(&array).unwrap();
+        assert_eq!(data.len(), 3);
+        assert_eq!(data.dim(), 2);
+        assert_eq!(data.point(1).unwrap(), &[2.0, 3.0][..]);
+        // The points are read from the array's own buffer
+        let point_ptr = data.point(0).unwrap().as_ptr();
+        let buffer_ptr = array.values().data().buffers()[0].as_ptr() as *const f32;
+        assert_eq!(point_ptr, buffer_ptr);
+        assert!(data.point(3).is_err());
+    }
+
+    #[test]
+    fn respects_slices() {
+        let values = [0.0f32, 1.0, 2.0, 3.0, 4.0, 5.0];
+        let array = fixed_size_list(&values, 2);
+        let sliced = array.slice(1, 2);
+        let sliced = sliced
+            .as_any()
+            .downcast_ref::<FixedSizeListArray>()
+            .unwrap();
+        let data = DataArrow::<L2>::from_fixed_size_list(sliced).unwrap();
+        assert_eq!(data.len(), 2);
+        assert_eq!(data.point(0).unwrap(), &[2.0, 3.0][..]);
+    }
+
+    #[test]
+    fn reads_from_record_batch() {
+        let array = fixed_size_list(&[0.0, 1.0, 2.0, 3.0], 2);
+        let schema = Schema::new(vec![Field::new(
+            "embedding",
+            array.data_type().clone(),
+            false,
+        )]);
+        let batch = RecordBatch::try_new(Arc::new(schema), vec![Arc::new(array)]).unwrap();
+        let data = DataArrow::<L2>::from_record_batch(&batch, "embedding").unwrap();
+        assert_eq!(data.point(1).unwrap(), &[2.0, 3.0][..]);
+        assert!(DataArrow::<L2>::from_record_batch(&batch, "missing").is_err());
+    }
+}
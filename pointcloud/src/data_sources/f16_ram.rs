@@ -0,0 +1,148 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! Data stored in ram in half precision.
+//!
+//! This takes half the memory of a [`DataRam`](super::DataRam). The metrics convert the points to
+//! `f32` as they go, see [`crate::metrics::half_precision`], so only the storage loses precision.
+//! Embeddings usually don't notice the rounding. Queries are `f16` points too, use
+//! [`DataRamF16::to_f16`] to convert an `f32` one.
+
+use crate::base_traits::*;
+use crate::metrics::*;
+use crate::pc_errors::{ParsingError, PointCloudError, PointCloudResult};
+use crate::points::f16;
+use half::slice::HalfFloatSliceExt;
+
+/// Points stored in ram as `f16`s, measured in `f32`.
+#[derive(Debug)]
+pub struct DataRamF16<M = L2> {
+    name: String,
+    data: Vec<f16>,
+    dim: usize,
+    metric: M,
+}
+
+impl<M: Default> DataRamF16<M> {
+    /// Rounds the `f32` data to half precision. Values outside the range of an `f16`, about
+    /// ±65504, become infinite.
+    pub fn new(data: &[f32], dim: usize) -> PointCloudResult<DataRamF16<M>> {
+        Self::from_f16(Self::to_f16(data), dim)
+    }
+
+    /// Stores data that's already in half precision.
+    pub fn from_f16(data: Vec<f16>, dim: usize) -> PointCloudResult<DataRamF16<M>> {
+        Self::with_metric(data, dim, M::default())
+    }
+}
+
+impl<M> DataRamF16<M> {
+    /// Stores data that's already in half precision, measuring distances with the given metric
+    /// instance.
+    pub fn with_metric(data: Vec<f16>, dim: usize, metric: M) -> PointCloudResult<DataRamF16<M>> {
+        if dim == 0 || data.len() % dim != 0 {
+            return Err(PointCloudError::ParsingError(
+                ParsingError::RegularParsingError(
+                    "the data's length isn't a multiple of the dimension",
+                ),
+            ));
+        }
+        Ok(DataRamF16 {
+            name: "RAM f16".to_string(),
+            data,
+            dim,
+            metric,
+        })
+    }
+
+    /// Rounds a point, or many, to half precision. Use this for query points.
+    pub fn to_f16(point: &[f32]) -> Vec<f16> {
+        let mut half_point = vec![f16::from_f32(0.0); point.len()];
+        half_point.convert_from_f32_slice(point);
+        half_point
+    }
+
+    /// Swaps out the metric, keeping the data.
+    pub fn replace_metric<N>(self, metric: N) -> DataRamF16<N> {
+        DataRamF16 {
+            name: self.name,
+            data: self.data,
+            dim: self.dim,
+            metric,
+        }
+    }
+}
+
+impl<M: Metric<[f16]>> PointCloud for DataRamF16<M> {
+    type Metric = M;
+    type Point = [f16];
+    type PointRef<'a> = &'a [f16];
+    type LabelSummary = ();
+    type Label = ();
+    type MetaSummary = ();
+    type Metadata = ();
+
+    #[inline]
+    fn dim(&self) -> usize {
+        self.dim
+    }
+    #[inline]
+    fn len(&self) -> usize {
+        self.data.len() / self.dim
+    }
+    #[inline]
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+    #[inline]
+    fn metric(&self) -> &M {
+        &self.metric
+    }
+    #[inline]
+    fn point<'a, 'b: 'a>(&'b self, i: usize) -> PointCloudResult<&'a [f16]> {
+        match self.data.get(self.dim * i..(self.dim * i + self.dim)) {
+            None => Err(PointCloudError::data_access(i, self.name.clone())),
+            Some(x) => Ok(x),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stores_rounded_points() {
+        let data = [0.0f32, 1.0, 2.5, -3.25, 0.1, 70000.0];
+        let cloud = DataRamF16::<L2>::new(&data, 2).unwrap();
+        assert_eq!(cloud.len(), 3);
+        assert_eq!(cloud.point(1).unwrap().dense(), vec![2.5, -3.25]);
+        let rounded = cloud.point(2).unwrap().dense();
+        assert_approx_eq!(rounded[0], 0.1, 1e-3);
+        assert!(rounded[1].is_infinite());
+        assert!(cloud.point(3).is_err());
+        assert!(DataRamF16::<L2>::new(&data, 4).is_err());
+
+        let dists = cloud
+            .distances_to_point(&&DataRamF16::<L2>::to_f16(&[0.0, 0.0])[..], &[0, 1])
+            .unwrap();
+        assert_approx_eq!(dists[0], 1.0);
+        assert_approx_eq!(dists[1], (2.5f32 * 2.5 + 3.25 * 3.25).sqrt());
+    }
+}
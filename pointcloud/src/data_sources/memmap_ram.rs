@@ -21,15 +21,17 @@
 
 use super::memmapf32::Mmapf32;
 use crate::pc_errors::{PointCloudError, PointCloudResult};
+use hashbrown::HashSet;
 use std::fs::OpenOptions;
-use std::marker::PhantomData;
 use std::path::Path;
 
 use crate::metrics::*;
 
 use crate::base_traits::*;
+use crate::duplicates::Duplicates;
 use crate::label_sources::VecLabels;
-use crate::pc_errors::ParsingError;
+use crate::missing_values::{MissingValues, NanPolicy};
+use crate::normalization::{Normalization, Normalizer};
 
 /// A thin wrapper to give a `Box<[f32]>` dimensionality.
 #[derive(Debug)]
@@ -37,7 +39,10 @@ pub struct DataMemmap<M = L2> {
     name: String,
     data: Mmapf32,
     dim: usize,
-    metric: PhantomData<M>,
+    metric: M,
+    deleted: HashSet<usize>,
+    dim_names: Option<Vec<String>>,
+    duplicates: Duplicates,
 }
 
 /// The data stored in ram.
@@ -46,12 +51,24 @@ pub struct DataRam<M = L2> {
     name: String,
     data: Vec<f32>,
     dim: usize,
-    metric: PhantomData<M>,
+    metric: M,
+    deleted: HashSet<usize>,
+    dim_names: Option<Vec<String>>,
+    duplicates: Duplicates,
+    normalizer: Option<Normalizer>,
+    missing_values: Option<MissingValues>,
 }
 
-impl<M> DataMemmap<M> {
+impl<M: Default> DataMemmap<M> {
     /// Creates a new one from a path. The name is the path.
     pub fn new(dim: usize, path: &Path) -> PointCloudResult<DataMemmap<M>> {
+        Self::with_metric(dim, path, M::default())
+    }
+}
+
+impl<M> DataMemmap<M> {
+    /// Creates a new one from a path that measures distances with the given metric instance.
+    pub fn with_metric(dim: usize, path: &Path, metric: M) -> PointCloudResult<DataMemmap<M>> {
         let name = path.to_string_lossy().to_string();
         if !path.exists() {
             panic!("data file {:?} does not exist", path);
@@ -67,7 +84,10 @@ impl<M> DataMemmap<M> {
             name,
             data,
             dim,
-            metric: PhantomData,
+            metric,
+            deleted: HashSet::new(),
+            dim_names: None,
+            duplicates: Duplicates::default(),
         })
     }
 
@@ -76,6 +96,11 @@ impl<M> DataMemmap<M> {
         VecLabels::new(self.data.to_vec(), self.dim, None)
     }
 
+    /// Memmaps are read only, convert to ram to normalize the data or handle missing values.
+    fn preprocess(&self, _point: &[f32]) -> Option<Box<[f32]>> {
+        None
+    }
+
     /// Reads and consumes this memmap and copies it into ram.
     pub fn convert_to_ram(self) -> DataRam<M> {
         let dim = self.dim;
@@ -86,115 +111,377 @@ impl<M> DataMemmap<M> {
             name,
             data,
             dim,
-            metric: PhantomData,
+            metric: self.metric,
+            deleted: self.deleted,
+            dim_names: self.dim_names,
+            duplicates: self.duplicates,
+            normalizer: None,
+            missing_values: None,
         }
     }
 }
 
-impl<M> DataRam<M> {
+impl<M: Default> DataRam<M> {
     /// Consumes your box and dimension and gives a dimensioned box.
     pub fn new(data: Vec<f32>, dim: usize) -> Result<DataRam<M>, PointCloudError> {
+        Self::with_metric(data, dim, M::default())
+    }
+}
+
+impl<M> DataRam<M> {
+    /// Consumes your box and dimension and gives a dimensioned box that measures distances with
+    /// the given metric instance.
+    pub fn with_metric(
+        data: Vec<f32>,
+        dim: usize,
+        metric: M,
+    ) -> Result<DataRam<M>, PointCloudError> {
         assert!(data.len() % dim == 0);
         let name = "RAM".to_string();
         Ok(DataRam {
             name,
             data,
             dim,
-            metric: PhantomData,
+            metric,
+            deleted: HashSet::new(),
+            dim_names: None,
+            duplicates: Duplicates::default(),
+            normalizer: None,
+            missing_values: None,
         })
     }
 
+    /// Fits a normalization to the points that weren't deleted and normalizes all of them in
+    /// place. The normalizer is kept and applied to every point added later, and to query points
+    /// through [`PointCloud::preprocess_query`]. The data can only be normalized once.
+    pub fn normalize(&mut self, kind: Normalization) -> PointCloudResult<()> {
+        if self.normalizer.is_some() {
+            return Err(PointCloudError::AlreadyNormalized);
+        }
+        let normalizer = if self.deleted.is_empty() {
+            Normalizer::fit(kind, &self.data, self.dim)
+        } else {
+            let mut kept = Vec::with_capacity(self.data.len());
+            for (pi, point) in self.data.chunks_exact(self.dim).enumerate() {
+                if !self.deleted.contains(&pi) {
+                    kept.extend_from_slice(point);
+                }
+            }
+            Normalizer::fit(kind, &kept, self.dim)
+        };
+        normalizer.apply_all(&mut self.data, self.dim);
+        self.normalizer = Some(normalizer);
+        Ok(())
+    }
+
+    /// Fits the NaN policy to the points that weren't deleted and applies it to all of them in
+    /// place. Like the normalization it's kept and applied to points added later and to query
+    /// points. Set it before normalizing, the normalization is fitted without the missing values.
+    pub fn handle_missing_values(&mut self, policy: NanPolicy) -> PointCloudResult<()> {
+        let missing_values = if self.deleted.is_empty() {
+            MissingValues::fit(policy, &self.data, self.dim)?
+        } else {
+            let mut kept = Vec::with_capacity(self.data.len());
+            for (pi, point) in self.data.chunks_exact(self.dim).enumerate() {
+                if !self.deleted.contains(&pi) {
+                    kept.extend_from_slice(point);
+                }
+            }
+            MissingValues::fit(policy, &kept, self.dim)?
+        };
+        missing_values.apply_all(&mut self.data, self.dim, 0)?;
+        self.missing_values = Some(missing_values);
+        Ok(())
+    }
+
+    /// Handles the missing values, see `handle_missing_values`.
+    pub fn with_nan_policy(mut self, policy: NanPolicy) -> PointCloudResult<Self> {
+        self.handle_missing_values(policy)?;
+        Ok(self)
+    }
+
+    /// The NaN policy the data was loaded with, if there was one.
+    pub fn missing_values(&self) -> Option<&MissingValues> {
+        self.missing_values.as_ref()
+    }
+
+    /// Fills in and normalizes a query point, `None` if there's nothing to do to it.
+    fn preprocess(&self, point: &[f32]) -> Option<Box<[f32]>> {
+        let imputes = self
+            .missing_values
+            .as_ref()
+            .filter(|m| m.policy() == NanPolicy::ImputeMean);
+        if imputes.is_none() && self.normalizer.is_none() {
+            return None;
+        }
+        let mut point = point.to_vec();
+        if let Some(missing_values) = imputes {
+            missing_values.impute(&mut point);
+        }
+        if let Some(normalizer) = &self.normalizer {
+            normalizer.apply(&mut point);
+        }
+        Some(point.into_boxed_slice())
+    }
+
+    /// Normalizes the data, see `normalize`.
+    pub fn with_normalization(mut self, kind: Normalization) -> PointCloudResult<Self> {
+        self.normalize(kind)?;
+        Ok(self)
+    }
+
+    /// The normalization the data went through, if it was normalized.
+    pub fn normalizer(&self) -> Option<&Normalizer> {
+        self.normalizer.as_ref()
+    }
+
+    /// Swaps out the metric, keeping the data.
+    pub fn replace_metric<N>(self, metric: N) -> DataRam<N> {
+        DataRam {
+            name: self.name,
+            data: self.data,
+            dim: self.dim,
+            metric,
+            deleted: self.deleted,
+            dim_names: self.dim_names,
+            duplicates: self.duplicates,
+            normalizer: self.normalizer,
+            missing_values: self.missing_values,
+        }
+    }
+
     /// Converts this to a label set
     pub fn convert_to_labels(self) -> VecLabels {
         VecLabels::new(self.data, self.dim, None)
     }
 
-    /// Merges two ram sets together.
+    /// Merges two ram sets together. The deleted points of `other` stay deleted and its collapsed
+    /// points stay collapsed. If this set was normalized, or had a NaN policy, the other set has
+    /// to have gone through the same.
     pub fn merge(&mut self, other: DataRam<M>) {
         assert!(self.dim == other.dim);
+        assert!(self.normalizer == other.normalizer);
+        assert!(self.missing_values == other.missing_values);
+        let offset = self.data.len() / self.dim;
+        self.deleted
+            .extend(other.deleted.iter().map(|pi| pi + offset));
+        self.duplicates.append(&other.duplicates, offset);
         self.data.extend(other.data);
     }
 }
 
+impl<M: Metric<[f32]>> ExtendableCloud for DataRam<M> {
+    /// The labels are only counted, the data has none.
+    fn extend_points(&mut self, points: &[f32], labels: &[Option<&()>]) -> PointCloudResult<()> {
+        if points.len() != self.dim * labels.len() {
+            return Err(PointCloudError::DataAccessError {
+                index: self.len(),
+                reason: format!(
+                    "got {} floats for {} points of dimension {}",
+                    points.len(),
+                    labels.len(),
+                    self.dim
+                ),
+            });
+        }
+        let start = self.data.len();
+        if let Some(missing_values) = &self.missing_values {
+            // Check before adding anything, so rejected points don't end up half added
+            let mut points = points.to_vec();
+            missing_values.apply_all(&mut points, self.dim, self.len())?;
+            self.data.extend(points);
+        } else {
+            self.data.extend_from_slice(points);
+        }
+        if let Some(normalizer) = &self.normalizer {
+            normalizer.apply_all(&mut self.data[start..], self.dim);
+        }
+        Ok(())
+    }
+
+    /// The point has to stand only for itself.
+    fn collapse_point(&mut self, pi: usize, into: usize) -> PointCloudResult<()> {
+        if pi >= self.len() || into >= self.len() || self.multiplicity(pi) != 1 {
+            return Err(PointCloudError::data_access(pi, self.name.clone()));
+        }
+        self.duplicates.collapse(pi, into);
+        Ok(())
+    }
+}
+
+/// The data access of a dense `f32` source, shared by every source with a flat `data` buffer.
+macro_rules! dense_point_access {
+    () => {
+        type Metric = M;
+        type Point = [f32];
+        type PointRef<'a> = &'a [f32];
+        type LabelSummary = ();
+        type Label = ();
+        type MetaSummary = ();
+        type Metadata = ();
+
+        #[inline]
+        fn dim(&self) -> usize {
+            self.dim
+        }
+        #[inline]
+        fn len(&self) -> usize {
+            self.data.len() / self.dim
+        }
+        #[inline]
+        fn is_empty(&self) -> bool {
+            self.data.is_empty()
+        }
+        #[inline]
+        fn metric(&self) -> &M {
+            &self.metric
+        }
+        #[inline]
+        fn point<'a, 'b: 'a>(&'b self, i: usize) -> PointCloudResult<&'a [f32]> {
+            match self
+                .data
+                .get(self.dim * (i as usize)..(self.dim * (i as usize) + self.dim))
+            {
+                None => Err(PointCloudError::data_access(i as usize, self.name.clone())),
+                Some(x) => Ok(x),
+            }
+        }
+    };
+}
+
+/// Read only sources just give access to the data, the editable ones also track deletions, dimension names and
+/// the preprocessing of their points.
 macro_rules! make_point_cloud {
     ($name:ident) => {
         impl<M: Metric<[f32]>> PointCloud for $name<M> {
-            type Metric = M;
-            type Point = [f32];
-            type PointRef<'a> = &'a [f32];
-            type LabelSummary = ();
-            type Label = ();
-            type MetaSummary = ();
-            type Metadata = ();
-
-            fn metadata(&self, _pn: usize) -> PointCloudResult<Option<&Self::Metadata>> {
-                Ok(None)
+            dense_point_access!();
+        }
+    };
+    ($name:ident, editable) => {
+        impl<M: Metric<[f32]>> PointCloud for $name<M> {
+            dense_point_access!();
+
+            #[inline]
+            fn is_deleted(&self, pi: usize) -> bool {
+                !self.deleted.is_empty() && self.deleted.contains(&pi)
+            }
+            fn deleted_indexes(&self) -> Vec<usize> {
+                self.deleted.iter().copied().collect()
             }
-            fn metasummary(
-                &self,
-                pns: &[usize],
-            ) -> PointCloudResult<SummaryCounter<Self::MetaSummary>> {
-                Ok(SummaryCounter {
-                    summary: (),
-                    nones: pns.len(),
-                    errors: 0,
-                })
+            #[inline]
+            fn multiplicity(&self, pi: usize) -> usize {
+                if self.duplicates.is_empty() {
+                    1
+                } else {
+                    self.duplicates.multiplicity(pi)
+                }
             }
-            fn label(&self, _pn: usize) -> PointCloudResult<Option<&Self::Label>> {
-                Ok(None)
+            #[inline]
+            fn representative(&self, pi: usize) -> usize {
+                self.duplicates.representative(pi)
             }
-            fn label_summary(
-                &self,
-                pns: &[usize],
-            ) -> PointCloudResult<SummaryCounter<Self::LabelSummary>> {
-                Ok(SummaryCounter {
-                    summary: (),
-                    nones: pns.len(),
-                    errors: 0,
-                })
+            fn dim_names(&self) -> Option<&[String]> {
+                self.dim_names.as_deref()
             }
-            fn name(&self, pi: usize) -> PointCloudResult<String> {
-                Ok(pi.to_string())
+            fn preprocess_query(&self, point: &[f32]) -> Option<Box<[f32]>> {
+                self.preprocess(point)
             }
-            fn index(&self, pn: &str) -> PointCloudResult<usize> {
-                pn.parse::<usize>().map_err(|_| ParsingError::RegularParsingError("Unable to parse your str into an usize").into())
+        }
+    };
+}
+
+make_point_cloud!(DataRam, editable);
+make_point_cloud!(DataMemmap, editable);
+
+macro_rules! make_deletable_cloud {
+    ($name:ident) => {
+        impl<M: Metric<[f32]>> DeletableCloud for $name<M> {
+            fn delete_point(&mut self, pi: usize) -> PointCloudResult<()> {
+                if pi >= self.len() {
+                    return Err(PointCloudError::data_access(pi, self.name.clone()));
+                }
+                self.deleted.insert(pi);
+                Ok(())
             }
-            fn names(&self) -> Vec<String> {
-                (0..self.len()).map(|i| i.to_string()).collect()
+            fn restore_point(&mut self, pi: usize) -> PointCloudResult<()> {
+                self.deleted.remove(&pi);
+                Ok(())
             }
+        }
+    };
+}
 
-            #[inline]
-            fn dim(&self) -> usize {
-                self.dim
+make_deletable_cloud!(DataRam);
+make_deletable_cloud!(DataMemmap);
+
+macro_rules! make_named_dims {
+    ($name:ident) => {
+        impl<M> $name<M> {
+            /// Names the dimensions, there has to be one name per dimension.
+            pub fn set_dim_names(&mut self, names: Vec<String>) -> PointCloudResult<()> {
+                if names.len() != self.dim {
+                    return Err(PointCloudError::DimNamesError {
+                        dim: self.dim,
+                        names: names.len(),
+                    });
+                }
+                self.dim_names = Some(names);
+                Ok(())
             }
-            #[inline]
-            fn len(&self) -> usize {
-                self.data.len() / self.dim
+
+            /// Names the dimensions, see `set_dim_names`.
+            pub fn with_dim_names(mut self, names: Vec<String>) -> PointCloudResult<Self> {
+                self.set_dim_names(names)?;
+                Ok(self)
             }
-            #[inline]
-            fn is_empty(&self) -> bool {
-                self.data.is_empty()
+        }
+    };
+}
+
+make_named_dims!(DataRam);
+make_named_dims!(DataMemmap);
+
+macro_rules! make_collapsible {
+    ($name:ident) => {
+        impl<M> $name<M> {
+            /// Collapses the identical points into the first of them, see [`crate::duplicates`].
+            /// Deleted points are left alone. Collapsing again starts over from the current data.
+            pub fn collapse_duplicates(&mut self) {
+                self.collapse_duplicates_by(|_| Some(()));
             }
-            #[inline]
-            fn reference_indexes(&self) -> Vec<usize> {
-                (0..self.len()).map(|i| i as usize).collect()
+
+            /// Collapses the points that are identical and have the same key, like their label.
+            /// Points with a key of `None` are left alone, and so are the deleted points.
+            pub fn collapse_duplicates_by<K: std::hash::Hash + Eq, F: Fn(usize) -> Option<K>>(
+                &mut self,
+                key: F,
+            ) {
+                let deleted = &self.deleted;
+                self.duplicates = Duplicates::find_by(&self.data, self.dim, |pi| {
+                    if deleted.contains(&pi) {
+                        None
+                    } else {
+                        key(pi)
+                    }
+                });
             }
-            #[inline]
-            fn point<'a, 'b: 'a>(&'b self, i: usize) -> PointCloudResult<&'a [f32]> {
-                match self
-                    .data
-                    .get(self.dim * (i as usize)..(self.dim * (i as usize) + self.dim))
-                {
-                    None => Err(PointCloudError::data_access(i as usize, self.name.clone())),
-                    Some(x) => Ok(x),
-                }
+
+            /// Collapses the identical points, see `collapse_duplicates`.
+            pub fn with_collapsed_duplicates(mut self) -> Self {
+                self.collapse_duplicates();
+                self
+            }
+
+            /// The points that were collapsed, empty unless the duplicates were collapsed.
+            pub fn duplicates(&self) -> &Duplicates {
+                &self.duplicates
             }
         }
     };
 }
 
-make_point_cloud!(DataRam);
-make_point_cloud!(DataMemmap);
+make_collapsible!(DataRam);
+make_collapsible!(DataMemmap);
 
 #[cfg(test)]
 pub mod tests {
@@ -311,4 +598,116 @@ pub mod tests {
             assert_approx_eq!(5.0f32.sqrt(), d);
         }
     }
+
+    #[test]
+    fn deleted_points_leave_summaries() {
+        let mut pc = build_ram_fixed_labeled_test(5, 2);
+        pc.delete_point(1).unwrap();
+        pc.delete_point(3).unwrap();
+        assert!(pc.delete_point(5).is_err());
+        assert!(pc.is_deleted(1));
+        assert!(!pc.is_deleted(2));
+        let mut deleted = pc.deleted_indexes();
+        deleted.sort_unstable();
+        assert_eq!(deleted, vec![1, 3]);
+        // The data is kept
+        assert_eq!(pc.point(1).unwrap(), &[1.0, 1.0][..]);
+
+        let summary = pc.label_summary(&[0, 1, 2, 3, 4]).unwrap();
+        assert_eq!(summary.count(), 3);
+        assert!(summary.summary.items.iter().all(|(l, _)| *l % 2 == 0));
+
+        pc.restore_point(1).unwrap();
+        assert_eq!(pc.label_summary(&[0, 1, 2, 3, 4]).unwrap().count(), 4);
+    }
+
+    #[test]
+    fn collapsed_points_count_in_summaries() {
+        let mut data =
+            DataRam::<L2>::new(vec![0.0, 0.0, 1.0, 1.0, 0.0, 0.0, 0.0, 0.0, 2.0, 2.0], 2).unwrap();
+        let labels = SmallIntLabels::new(vec![0, 1, 0, 1, 0], None);
+        data.collapse_duplicates_by(|pi| labels.label(pi).ok().map(|l| l.copied()));
+        assert_eq!(data.multiplicity(0), 2);
+        assert_eq!(data.multiplicity(2), 0);
+        // Same point, different label
+        assert_eq!(data.multiplicity(3), 1);
+        assert_eq!(data.reference_indexes(), vec![0, 1, 3, 4]);
+
+        let pc = SimpleLabeledCloud::new(data, labels);
+        let summary = pc.label_summary(&pc.reference_indexes()).unwrap();
+        assert_eq!(summary.count(), 5);
+        assert!(summary.summary.items.contains(&(0, 3)));
+
+        let mut data = DataRam::<L2>::new(vec![1.0, 1.0, 1.0], 1).unwrap();
+        data.delete_point(0).unwrap();
+        data.collapse_duplicates();
+        assert_eq!(data.multiplicity(0), 1);
+        assert_eq!(data.multiplicity(1), 2);
+        let mut other = DataRam::<L2>::new(vec![3.0, 3.0], 1)
+            .unwrap()
+            .with_collapsed_duplicates();
+        other.extend_points(&[3.0], &[None]).unwrap();
+        data.merge(other);
+        assert_eq!(data.reference_indexes(), vec![1, 3, 5]);
+        assert_eq!(data.multiplicity(3), 2);
+
+        data.extend_points(&[3.0], &[None]).unwrap();
+        data.collapse_point(6, 4).unwrap();
+        assert_eq!(data.representative(6), 3);
+        assert_eq!(data.multiplicity(3), 3);
+        assert!(data.collapse_point(3, 1).is_err());
+    }
+
+    #[test]
+    fn dim_names_reach_the_labeled_cloud() {
+        let mut data = DataRam::<L2>::new(vec![0.0; 6], 3).unwrap();
+        assert!(data.set_dim_names(vec!["a".to_string()]).is_err());
+        data.set_dim_names(vec!["a".to_string(), "b".to_string(), "c".to_string()])
+            .unwrap();
+        let pc = SimpleLabeledCloud::new(data, SmallIntLabels::new(vec![0, 1], None));
+        assert_eq!(pc.dim_names().unwrap().len(), 3);
+        assert_eq!(pc.dim_name(1), Some("b"));
+        assert_eq!(pc.dim_name(3), None);
+    }
+
+    #[test]
+    fn normalization_reaches_added_and_query_points() {
+        let mut data = DataRam::<L2>::new(vec![0.0, 10.0, 2.0, 30.0], 2).unwrap();
+        data.normalize(Normalization::MinMax).unwrap();
+        assert_approx_eq!(data.point(1).unwrap()[1], 1.0);
+        assert!(data.normalize(Normalization::ZScore).is_err());
+
+        data.extend_points(&[1.0, 20.0], &[None]).unwrap();
+        assert_approx_eq!(data.point(2).unwrap()[0], 0.5);
+        assert_approx_eq!(data.point(2).unwrap()[1], 0.5);
+
+        let pc = SimpleLabeledCloud::new(data, SmallIntLabels::new(vec![0, 1, 0], None));
+        let query = pc.preprocess_query(&[2.0, 10.0][..]).unwrap();
+        assert_approx_eq!(query[0], 1.0);
+        assert_approx_eq!(query[1], 0.0);
+    }
+
+    #[test]
+    fn nan_policy_reaches_added_and_query_points() {
+        let data = DataRam::<L2>::new(vec![0.0, f32::NAN, 2.0, 1.0], 2).unwrap();
+        assert!(DataRam::<L2>::new(vec![0.0, f32::NAN], 2)
+            .unwrap()
+            .with_nan_policy(NanPolicy::Reject)
+            .is_err());
+        let mut data = data.with_nan_policy(NanPolicy::ImputeMean).unwrap();
+        assert_approx_eq!(data.point(0).unwrap()[1], 1.0);
+
+        data.extend_points(&[f32::NAN, 3.0], &[None]).unwrap();
+        assert_approx_eq!(data.point(2).unwrap()[0], 1.0);
+        let query = data.preprocess_query(&[f32::NAN, 5.0][..]).unwrap();
+        assert_approx_eq!(query[0], 1.0);
+        assert_approx_eq!(query[1], 5.0);
+
+        let mut data = DataRam::<L2>::new(vec![0.0, 1.0], 2)
+            .unwrap()
+            .with_nan_policy(NanPolicy::Reject)
+            .unwrap();
+        assert!(data.extend_points(&[f32::NAN, 0.0], &[None]).is_err());
+        assert_eq!(data.len(), 1);
+    }
 }
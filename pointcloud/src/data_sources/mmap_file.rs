@@ -0,0 +1,270 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! Points in a self describing binary file that's memory mapped, so clouds larger than RAM can be
+//! indexed. The OS pages points in as they're read and evicts them under memory pressure.
+//!
+//! The file is a 32 byte header followed by the points as little endian `f32`s, point after point:
+//!
+//! | bytes  | contents                       |
+//! |--------|--------------------------------|
+//! | 0..8   | the magic bytes `GOKOPCF4`     |
+//! | 8..12  | the format version, `u32`      |
+//! | 12..16 | reserved, 0                    |
+//! | 16..24 | the dimension, `u64`           |
+//! | 24..32 | the number of points, `u64`    |
+//!
+//! Use [`MmapFileWriter`] to write one a chunk at a time.
+
+use super::memmapf32::MmapOptionsf32;
+use super::memmapf32::Mmapf32;
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::base_traits::*;
+use crate::metrics::*;
+use crate::pc_errors::{ParsingError, PointCloudError, PointCloudResult};
+
+const MAGIC: &[u8; 8] = b"GOKOPCF4";
+const VERSION: u32 = 1;
+const HEADER_LEN: u64 = 32;
+
+fn mmap_file_error(path: &Path, reason: String) -> PointCloudError {
+    PointCloudError::ParsingError(ParsingError::MmapFileError {
+        file_name: path.to_string_lossy().to_string(),
+        reason,
+    })
+}
+
+/// Reads the header of a point file, returning the dimension and the number of points. Leaves
+/// the reader at the start of the points.
+pub(crate) fn read_point_file_header<R: Read>(
+    reader: &mut R,
+    path: &Path,
+) -> PointCloudResult<(usize, usize)> {
+    let mut header = [0u8; HEADER_LEN as usize];
+    reader
+        .read_exact(&mut header)
+        .map_err(|_| mmap_file_error(path, "the file is too short to have a header".to_string()))?;
+    if &header[0..8] != MAGIC {
+        return Err(mmap_file_error(
+            path,
+            "the magic bytes are wrong".to_string(),
+        ));
+    }
+    let version = u32::from_le_bytes(header[8..12].try_into().unwrap());
+    if version != VERSION {
+        return Err(mmap_file_error(
+            path,
+            format!("version {} isn't supported", version),
+        ));
+    }
+    let dim = u64::from_le_bytes(header[16..24].try_into().unwrap()) as usize;
+    let count = u64::from_le_bytes(header[24..32].try_into().unwrap()) as usize;
+    if dim == 0 || count == 0 {
+        return Err(mmap_file_error(
+            path,
+            format!("the file has {} points of dimension {}", count, dim),
+        ));
+    }
+    Ok((dim, count))
+}
+
+/// Points memory mapped from a file written by [`MmapFileWriter`]. The mapping is read only.
+#[derive(Debug)]
+pub struct DataMmapFile<M = L2> {
+    name: String,
+    data: Mmapf32,
+    dim: usize,
+    metric: M,
+}
+
+impl<M: Default> DataMmapFile<M> {
+    /// Maps the file. The name is the path.
+    pub fn open<P: AsRef<Path>>(path: P) -> PointCloudResult<DataMmapFile<M>> {
+        Self::with_metric(path, M::default())
+    }
+}
+
+impl<M> DataMmapFile<M> {
+    /// Maps the file, measuring distances with the given metric instance.
+    pub fn with_metric<P: AsRef<Path>>(path: P, metric: M) -> PointCloudResult<DataMmapFile<M>> {
+        let path = path.as_ref();
+        let mut file = File::open(path)?;
+        let (dim, count) = read_point_file_header(&mut file, path)?;
+        let data_len = count * dim * std::mem::size_of::<f32>();
+        let file_len = file.metadata()?.len();
+        if file_len < HEADER_LEN + data_len as u64 {
+            return Err(mmap_file_error(
+                path,
+                format!(
+                    "the header says there are {} points, but the file is only {} bytes long",
+                    count, file_len
+                ),
+            ));
+        }
+        let data = unsafe {
+            MmapOptionsf32::new()
+                .offset(HEADER_LEN)
+                .len(data_len)
+                .map(&file)
+        }?;
+        Ok(DataMmapFile {
+            name: path.to_string_lossy().to_string(),
+            data,
+            dim,
+            metric,
+        })
+    }
+
+    /// Swaps out the metric, keeping the mapping.
+    pub fn replace_metric<N>(self, metric: N) -> DataMmapFile<N> {
+        DataMmapFile {
+            name: self.name,
+            data: self.data,
+            dim: self.dim,
+            metric,
+        }
+    }
+}
+
+make_point_cloud!(DataMmapFile);
+
+/// Writes a file that [`DataMmapFile`] can map. The points are streamed to disk, so the file can
+/// be far larger than RAM. The point count in the header is only written by
+/// [`MmapFileWriter::finish`], an unfinished file says it has no points and won't open.
+#[derive(Debug)]
+pub struct MmapFileWriter {
+    file: BufWriter<File>,
+    dim: usize,
+    count: usize,
+}
+
+impl MmapFileWriter {
+    /// Creates the file, overwriting it if it exists, and writes the header.
+    pub fn create<P: AsRef<Path>>(path: P, dim: usize) -> PointCloudResult<MmapFileWriter> {
+        if dim == 0 {
+            return Err(mmap_file_error(
+                path.as_ref(),
+                "the dimension can't be 0".to_string(),
+            ));
+        }
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(MAGIC)?;
+        file.write_all(&VERSION.to_le_bytes())?;
+        file.write_all(&0u32.to_le_bytes())?;
+        file.write_all(&(dim as u64).to_le_bytes())?;
+        file.write_all(&0u64.to_le_bytes())?;
+        Ok(MmapFileWriter {
+            file,
+            dim,
+            count: 0,
+        })
+    }
+
+    /// The dimension of the points.
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    /// The number of points written so far.
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    /// If no points have been written.
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Appends a chunk of points, laid out point after point. The chunk has to hold a whole
+    /// number of points.
+    pub fn write_points(&mut self, points: &[f32]) -> PointCloudResult<()> {
+        if points.len() % self.dim != 0 {
+            return Err(PointCloudError::DataAccessError {
+                index: self.count,
+                reason: format!(
+                    "a chunk of {} floats isn't a whole number of points of dimension {}",
+                    points.len(),
+                    self.dim
+                ),
+            });
+        }
+        for x in points {
+            self.file.write_all(&x.to_le_bytes())?;
+        }
+        self.count += points.len() / self.dim;
+        Ok(())
+    }
+
+    /// Writes the point count into the header and closes the file. Returns the number of points.
+    pub fn finish(self) -> PointCloudResult<usize> {
+        let mut file = self.file.into_inner().map_err(|e| e.into_error())?;
+        file.seek(SeekFrom::Start(24))?;
+        file.write_all(&(self.count as u64).to_le_bytes())?;
+        file.sync_all()?;
+        Ok(self.count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn writes_and_maps_points() {
+        let dir = TempDir::new("mmap_file").unwrap();
+        let path = dir.path().join("points.pcf4");
+        let mut writer = MmapFileWriter::create(&path, 3).unwrap();
+        writer.write_points(&[0.0, 1.0, 2.0]).unwrap();
+        writer
+            .write_points(&[3.0, 4.0, 5.0, 6.0, 7.0, 8.0])
+            .unwrap();
+        assert!(writer.write_points(&[1.0]).is_err());
+        assert_eq!(writer.finish().unwrap(), 3);
+
+        let data = DataMmapFile::<L2>::open(&path).unwrap();
+        assert_eq!(data.len(), 3);
+        assert_eq!(data.dim(), 3);
+        assert_eq!(data.point(1).unwrap(), &[3.0, 4.0, 5.0][..]);
+        assert!(data.point(3).is_err());
+        assert_approx_eq!(
+            data.distances_to_point_index(0, &[2]).unwrap()[0],
+            (3.0f32 * 36.0).sqrt()
+        );
+    }
+
+    #[test]
+    fn bad_files_are_errors() {
+        let dir = TempDir::new("mmap_file").unwrap();
+        let unfinished = dir.path().join("unfinished.pcf4");
+        let mut writer = MmapFileWriter::create(&unfinished, 2).unwrap();
+        writer.write_points(&[0.0, 1.0]).unwrap();
+        drop(writer);
+        assert!(DataMmapFile::<L2>::open(&unfinished).is_err());
+
+        let garbage = dir.path().join("garbage.pcf4");
+        std::fs::write(&garbage, b"not a point cloud at all, just some bytes").unwrap();
+        assert!(DataMmapFile::<L2>::open(&garbage).is_err());
+        assert!(DataMmapFile::<L2>::open(dir.path().join("missing.pcf4")).is_err());
+    }
+}
@@ -18,13 +18,25 @@
 */
 
 //! Some data sources and a trait to dimension and uniformly reference the data contained.
-//! The only currently supported are memmaps and ram blobs.
+//! The only currently supported are memmaps, memory mapped point files, ram blobs, half precision
+//! ram blobs, sparse ram blobs and, with the `arrow` feature, Arrow arrays.
 
+#[macro_use]
 mod memmap_ram;
+#[cfg(feature = "arrow")]
+mod arrow_data;
+mod f16_ram;
+mod mmap_file;
 mod sparse_ram;
 
 #[allow(dead_code)]
 mod memmapf32;
 
+#[cfg(feature = "arrow")]
+pub use arrow_data::*;
+pub use f16_ram::DataRamF16;
 #[doc(hidden)]
 pub use memmap_ram::*;
+pub(crate) use mmap_file::read_point_file_header;
+pub use mmap_file::*;
+pub use sparse_ram::SparseDataRam;
@@ -1,7 +1,7 @@
 use crate::pc_errors::PointCloudResult;
 use std::convert::TryInto;
-use std::marker::PhantomData;
 use crate::pc_errors::ParsingError;
+use crate::pc_errors::PointCloudError;
 
 use crate::base_traits::*;
 use crate::metrics::*;
@@ -15,7 +15,7 @@ pub struct SparseDataRam<CoefField: std::fmt::Debug = f32, Index: std::fmt::Debu
     col_index: Vec<Index>,
     row_index: Vec<Index>,
     dim: usize,
-    metric: PhantomData<M>,
+    metric: M,
 }
 
 impl<CoefField, Index, M> SparseDataRam<CoefField, Index, M>
@@ -23,20 +23,112 @@ where
     CoefField: std::fmt::Debug + 'static,
     Index: std::fmt::Debug + 'static,
 {
+    /// Creates a new one from compressed sparse row arrays. The values and column indexes of
+    /// point `i` are at `row_index[i]..row_index[i + 1]`, so `row_index` has one more entry than
+    /// there are points. The column indexes of each point should be sorted.
     pub fn new(
         values: Vec<CoefField>,
         col_index: Vec<Index>,
         row_index: Vec<Index>,
         dim: usize,
     ) -> SparseDataRam<CoefField, Index> {
-        SparseDataRam::<CoefField, Index> {
+        SparseDataRam::with_metric(values, col_index, row_index, dim, L2 {})
+    }
+
+    /// Creates a new one from compressed sparse row arrays, measuring distances with the given
+    /// metric instance.
+    pub fn with_metric(
+        values: Vec<CoefField>,
+        col_index: Vec<Index>,
+        row_index: Vec<Index>,
+        dim: usize,
+        metric: M,
+    ) -> SparseDataRam<CoefField, Index, M> {
+        SparseDataRam {
             name: String::new(),
             values,
             col_index,
             row_index,
             dim,
-            metric: PhantomData,
+            metric,
+        }
+    }
+
+    /// Swaps out the metric, keeping the data.
+    pub fn replace_metric<N>(self, metric: N) -> SparseDataRam<CoefField, Index, N> {
+        SparseDataRam {
+            name: self.name,
+            values: self.values,
+            col_index: self.col_index,
+            row_index: self.row_index,
+            dim: self.dim,
+            metric,
+        }
+    }
+}
+
+impl<M> SparseDataRam<f32, u32, M> {
+    /// Builds a cloud from the arrays of a compressed sparse row matrix, scipy's `indptr`,
+    /// `indices` and `data`. Unlike [`SparseDataRam::new`] the arrays are checked, and the column
+    /// indexes of each row are sorted as scipy doesn't always keep them sorted. The dimension
+    /// defaults to one more than the largest column index.
+    pub fn from_csr(
+        indptr: Vec<u32>,
+        mut indices: Vec<u32>,
+        mut data: Vec<f32>,
+        dim: Option<usize>,
+        metric: M,
+    ) -> PointCloudResult<SparseDataRam<f32, u32, M>> {
+        let csr_error =
+            |index: usize, reason: String| PointCloudError::DataAccessError { index, reason };
+        if indptr.first() != Some(&0) {
+            return Err(csr_error(0, "indptr has to start with 0".to_string()));
+        }
+        if indices.len() != data.len() || *indptr.last().unwrap() as usize != data.len() {
+            return Err(csr_error(
+                0,
+                format!(
+                    "indptr ends at {} but there are {} indices and {} values",
+                    indptr.last().unwrap(),
+                    indices.len(),
+                    data.len()
+                ),
+            ));
+        }
+        let dim = dim.unwrap_or_else(|| indices.iter().max().map(|i| *i as usize + 1).unwrap_or(0));
+        for (pn, bounds) in indptr.windows(2).enumerate() {
+            let (start, end) = (bounds[0] as usize, bounds[1] as usize);
+            if end < start {
+                return Err(csr_error(pn, "indptr has to be non-decreasing".to_string()));
+            }
+            let row = &mut indices[start..end];
+            if !row.windows(2).all(|w| w[0] < w[1]) {
+                let mut pairs: Vec<(u32, f32)> = row
+                    .iter()
+                    .copied()
+                    .zip(data[start..end].iter().copied())
+                    .collect();
+                pairs.sort_by_key(|(i, _)| *i);
+                for (j, (i, v)) in pairs.drain(..).enumerate() {
+                    indices[start + j] = i;
+                    data[start + j] = v;
+                }
+                if indices[start..end].windows(2).any(|w| w[0] == w[1]) {
+                    return Err(csr_error(pn, "a column index is repeated".to_string()));
+                }
+            }
+            if let Some(max) = indices[start..end].last() {
+                if *max as usize >= dim {
+                    return Err(csr_error(
+                        pn,
+                        format!("the column index {} is past the dimension {}", max, dim),
+                    ));
+                }
+            }
         }
+        Ok(SparseDataRam::with_metric(
+            data, indices, indptr, dim, metric,
+        ))
     }
 }
 
@@ -46,7 +138,7 @@ where
 {
     type PointRef<'a> = SparseRef<'a, f32, u32>;
     type Point = RawSparse<f32, u32>;
-    type Metric = L2;
+    type Metric = M;
     type LabelSummary = ();
     type Label = ();
     type MetaSummary = ();
@@ -88,7 +180,7 @@ where
     }
     /// If this is empty
     fn is_empty(&self) -> bool {
-        self.row_index.len() > 1
+        self.row_index.len() <= 1
     }
     /// The dimension of the underlying data
     fn dim(&self) -> usize {
@@ -98,6 +190,10 @@ where
     fn reference_indexes(&self) -> Vec<usize> {
         (0..self.len()).collect()
     }
+    /// The metric instance for this cloud
+    fn metric(&self) -> &M {
+        &self.metric
+    }
     /// Gets a point from this dataset
     fn point<'a, 'b: 'a>(&'b self, pn: usize) -> PointCloudResult<Self::PointRef<'a>> {
         let lower_bound = self.row_index[pn].try_into();
@@ -111,3 +207,51 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_csr_sorts_rows() {
+        // The second row's columns are out of order
+        let cloud = SparseDataRam::from_csr(
+            vec![0, 2, 4, 4],
+            vec![0, 3, 2, 1],
+            vec![1.0, 2.0, 3.0, 4.0],
+            None,
+            L2 {},
+        )
+        .unwrap();
+        assert_eq!(cloud.len(), 3);
+        assert_eq!(cloud.dim(), 4);
+        let point = cloud.point(1).unwrap();
+        assert_eq!(point.indexes(), &[1, 2]);
+        assert_eq!(point.dense(), vec![0.0, 4.0, 3.0, 0.0]);
+        assert!(cloud.point(2).unwrap().values().is_empty());
+
+        let dense = [[1.0, 0.0, 0.0, 2.0], [0.0, 4.0, 3.0, 0.0], [0.0; 4]];
+        for i in 0..3 {
+            for j in 0..3 {
+                assert_approx_eq!(
+                    cloud.distances_to_point_index(i, &[j]).unwrap()[0],
+                    L2 {}.dist(&dense[i][..], &dense[j][..])
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn from_csr_checks_the_arrays() {
+        let csr = |indptr: Vec<u32>, indices: Vec<u32>, dim| {
+            let values = vec![1.0; indices.len()];
+            SparseDataRam::from_csr(indptr, indices, values, dim, L2 {})
+        };
+        assert!(csr(vec![0, 1], vec![0], None).is_ok());
+        assert!(csr(vec![1, 1], vec![0], None).is_err());
+        assert!(csr(vec![0, 2], vec![0], None).is_err());
+        assert!(csr(vec![0, 2, 1], vec![0, 1], None).is_err());
+        assert!(csr(vec![0, 2], vec![1, 1], None).is_err());
+        assert!(csr(vec![0, 1], vec![5], Some(3)).is_err());
+    }
+}
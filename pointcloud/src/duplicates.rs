@@ -0,0 +1,139 @@
+//! Collapsing identical points into one point with a count.
+//!
+//! Data with many exact copies, like repeated log lines or sensor readings stuck on a value,
+//! makes for deep chains of nodes that can never be split, because the copies are at distance 0.
+//! Collapsing them leaves one representative in the tree that stands for all of its copies. The
+//! copies keep their indexes and their data, but their multiplicity is 0 and they're left out of
+//! the reference indexes, so no tree indexes them. See [`crate::PointCloud::multiplicity`].
+//!
+//! Points are identical if every coordinate has the same bits, so `0.0` and `-0.0` are different
+//! points. Labeled data is collapsed by the points and the labels, two copies with different
+//! labels are kept apart.
+
+use hashbrown::HashMap;
+use std::hash::Hash;
+
+/// The copies that were found in some data, each mapped to the first point it's identical to.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Duplicates {
+    representatives: HashMap<usize, usize>,
+    counts: HashMap<usize, usize>,
+}
+
+impl Duplicates {
+    /// Finds the identical points in row major data of dimension `dim`.
+    pub fn find(data: &[f32], dim: usize) -> Duplicates {
+        Duplicates::find_by(data, dim, |_| Some(()))
+    }
+
+    /// Finds the points that are identical and have the same key, like their label. Points with
+    /// a key of `None` are left alone.
+    pub fn find_by<K: Hash + Eq, F: Fn(usize) -> Option<K>>(
+        data: &[f32],
+        dim: usize,
+        key: F,
+    ) -> Duplicates {
+        let mut firsts: HashMap<(Vec<u32>, K), usize> = HashMap::new();
+        let mut duplicates = Duplicates::default();
+        for (pi, point) in data.chunks_exact(dim).enumerate() {
+            let key = match key(pi) {
+                Some(key) => key,
+                None => continue,
+            };
+            let bits = point.iter().map(|x| x.to_bits()).collect();
+            let first = *firsts.entry((bits, key)).or_insert(pi);
+            if first != pi {
+                duplicates.representatives.insert(pi, first);
+                *duplicates.counts.entry(first).or_insert(1) += 1;
+            }
+        }
+        duplicates
+    }
+
+    /// The point that stands for this one, itself unless it was collapsed.
+    pub fn representative(&self, pi: usize) -> usize {
+        self.representatives.get(&pi).copied().unwrap_or(pi)
+    }
+
+    /// How many points this one stands for, 0 if it was collapsed into another.
+    pub fn multiplicity(&self, pi: usize) -> usize {
+        if self.representatives.contains_key(&pi) {
+            0
+        } else {
+            self.counts.get(&pi).copied().unwrap_or(1)
+        }
+    }
+
+    /// The number of points that were collapsed into another.
+    pub fn collapsed_count(&self) -> usize {
+        self.representatives.len()
+    }
+
+    /// If no point was collapsed.
+    pub fn is_empty(&self) -> bool {
+        self.representatives.is_empty()
+    }
+
+    /// Collapses a point into another one, or into the point that one was collapsed into. The
+    /// point can't stand for other points itself.
+    pub fn collapse(&mut self, pi: usize, into: usize) {
+        let first = self.representative(into);
+        if first != pi && !self.representatives.contains_key(&pi) {
+            self.representatives.insert(pi, first);
+            *self.counts.entry(first).or_insert(1) += 1;
+        }
+    }
+
+    /// Adds the duplicates of another data set that's appended after `offset` points of this
+    /// one. Points aren't collapsed across the two.
+    pub fn append(&mut self, other: &Duplicates, offset: usize) {
+        self.representatives.extend(
+            other
+                .representatives
+                .iter()
+                .map(|(pi, first)| (pi + offset, first + offset)),
+        );
+        self.counts
+            .extend(other.counts.iter().map(|(pi, count)| (pi + offset, *count)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collapses_into_the_first_copy() {
+        let data = [0.0, 1.0, 2.0, 3.0, 0.0, 1.0, 0.0, 1.0, 0.0, -1.0];
+        let duplicates = Duplicates::find(&data, 2);
+        assert_eq!(duplicates.collapsed_count(), 2);
+        assert_eq!(duplicates.multiplicity(0), 3);
+        assert_eq!(duplicates.multiplicity(1), 1);
+        assert_eq!(duplicates.multiplicity(2), 0);
+        assert_eq!(duplicates.multiplicity(3), 0);
+        assert_eq!(duplicates.multiplicity(4), 1);
+        assert_eq!(duplicates.representative(3), 0);
+        assert_eq!(duplicates.representative(1), 1);
+    }
+
+    #[test]
+    fn keys_keep_copies_apart() {
+        let data = [1.0, 1.0, 1.0, 1.0];
+        let labels = [Some(0), Some(1), Some(0), None];
+        let duplicates = Duplicates::find_by(&data, 1, |pi| labels[pi]);
+        assert_eq!(duplicates.multiplicity(0), 2);
+        assert_eq!(duplicates.multiplicity(1), 1);
+        assert_eq!(duplicates.multiplicity(2), 0);
+        assert_eq!(duplicates.multiplicity(3), 1);
+
+        let mut appended = duplicates.clone();
+        appended.append(&duplicates, 4);
+        assert_eq!(appended.multiplicity(4), 2);
+        assert_eq!(appended.representative(6), 4);
+        assert_eq!(appended.collapsed_count(), 2);
+
+        appended.collapse(5, 6);
+        assert_eq!(appended.representative(5), 4);
+        assert_eq!(appended.multiplicity(4), 3);
+    }
+}
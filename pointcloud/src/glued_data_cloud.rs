@@ -115,6 +115,49 @@ impl<D: PointCloud> PointCloud for HashGluedCloud<D> {
     fn dim(&self) -> usize {
         self.data_sources[0].dim()
     }
+    /// The metric of the first data source, the sources are assumed to share one.
+    fn metric(&self) -> &Self::Metric {
+        self.data_sources[0].metric()
+    }
+    fn is_deleted(&self, pi: usize) -> bool {
+        self.get_address(pi)
+            .map(|(i, j)| self.data_sources[i].is_deleted(j))
+            .unwrap_or(false)
+    }
+    fn deleted_indexes(&self) -> Vec<usize> {
+        self.addresses
+            .iter()
+            .filter(|(_, (i, j))| self.data_sources[*i].is_deleted(*j))
+            .map(|(pi, _)| *pi)
+            .collect()
+    }
+    fn multiplicity(&self, pi: usize) -> usize {
+        self.get_address(pi)
+            .map(|(i, j)| self.data_sources[i].multiplicity(j))
+            .unwrap_or(1)
+    }
+    /// Looks the representative's index up among all the addresses, they can be in any order
+    /// after a reindex.
+    fn representative(&self, pi: usize) -> usize {
+        match self.get_address(pi) {
+            Ok((i, j)) if self.data_sources[i].representative(j) != j => {
+                let first = (i, self.data_sources[i].representative(j));
+                self.addresses
+                    .iter()
+                    .find(|(_, address)| **address == first)
+                    .map(|(pn, _)| *pn)
+                    .unwrap_or(pi)
+            }
+            _ => pi,
+        }
+    }
+    /// The names of the first source's dimensions, the sources all have the same dimension.
+    fn dim_names(&self) -> Option<&[String]> {
+        self.data_sources[0].dim_names()
+    }
+    fn preprocess_query(&self, point: &Self::Point) -> Option<Box<Self::Point>> {
+        self.data_sources[0].preprocess_query(point)
+    }
     fn label(&self, pn: usize) -> PointCloudResult<Option<&Self::Label>> {
         let (i, j) = self.get_address(pn)?;
         self.data_sources[i].label(j)
@@ -127,6 +170,10 @@ impl<D: PointCloud> PointCloud for HashGluedCloud<D> {
         }
         Ok(summary)
     }
+    /// The label names of the first data source, the sources are assumed to share their categories.
+    fn label_name(&self, label: &Self::Label) -> Option<&str> {
+        self.data_sources.first().and_then(|d| d.label_name(label))
+    }
 
     fn name(&self, pi: usize) -> PointCloudResult<String> {
         let (i, j) = self.get_address(pi)?;
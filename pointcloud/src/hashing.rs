@@ -0,0 +1,290 @@
+//! Feature hashing, turning text into sparse points without a vocabulary.
+//!
+//! Each token, and optionally each run of up to `n` consecutive tokens, is hashed into one of
+//! `dim` columns with 32 bit MurmurHash3. The sign of the hash picks the sign of the token's
+//! contribution, so collisions cancel out on average rather than pile up. This is the hashing
+//! scikit-learn's `FeatureHasher` and `HashingVectorizer` do, so the same tokens get the same
+//! columns and signs as they would there.
+//!
+//! Since there's no vocabulary to fit, text that's never been seen before, like a new log line,
+//! vectorizes the same way the training set did. This is meant for log lines, so tokens with
+//! digits in them can be masked out. Without that every timestamp, pid and request id would be a
+//! token of its own.
+
+use crate::data_sources::SparseDataRam;
+use crate::pc_errors::PointCloudResult;
+use hashbrown::HashMap;
+
+/// Hashes text into sparse vectors of a fixed dimension, see the [module docs](self).
+#[derive(Debug, Clone)]
+pub struct HashingVectorizer {
+    dim: usize,
+    ngrams: usize,
+    lowercase: bool,
+    mask_numbers: bool,
+    alternate_sign: bool,
+    normalize: bool,
+}
+
+impl HashingVectorizer {
+    /// A vectorizer into `dim` columns. By default tokens are lowercased, numbers are masked,
+    /// only single tokens are hashed and the vectors are L2 normalized.
+    pub fn new(dim: usize) -> HashingVectorizer {
+        assert!(dim > 0, "Need at least one column to hash into");
+        HashingVectorizer {
+            dim,
+            ngrams: 1,
+            lowercase: true,
+            mask_numbers: true,
+            alternate_sign: true,
+            normalize: true,
+        }
+    }
+
+    /// Also hashes the runs of up to `ngrams` consecutive tokens, default 1.
+    pub fn set_ngrams(&mut self, ngrams: usize) -> &mut Self {
+        self.ngrams = ngrams.max(1);
+        self
+    }
+
+    /// Lowercases the tokens before hashing, default true.
+    pub fn set_lowercase(&mut self, lowercase: bool) -> &mut Self {
+        self.lowercase = lowercase;
+        self
+    }
+
+    /// Replaces every token that contains a digit with a single placeholder, default true.
+    pub fn set_mask_numbers(&mut self, mask_numbers: bool) -> &mut Self {
+        self.mask_numbers = mask_numbers;
+        self
+    }
+
+    /// Gives the tokens whose hash is negative a value of -1 rather than 1, default true. Without
+    /// this collisions add up, which biases the vectors when `dim` is small.
+    pub fn set_alternate_sign(&mut self, alternate_sign: bool) -> &mut Self {
+        self.alternate_sign = alternate_sign;
+        self
+    }
+
+    /// Scales the vectors to unit length, default true. With this the L2 distance between two
+    /// vectors is a function of their cosine similarity.
+    pub fn set_normalize(&mut self, normalize: bool) -> &mut Self {
+        self.normalize = normalize;
+        self
+    }
+
+    /// The number of columns the text is hashed into.
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    /// Splits the text on everything that isn't alphanumeric or an underscore.
+    pub fn tokens(&self, text: &str) -> Vec<String> {
+        text.split(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .filter(|token| !token.is_empty())
+            .map(|token| {
+                if self.mask_numbers && token.chars().any(|c| c.is_ascii_digit()) {
+                    "<num>".to_string()
+                } else if self.lowercase {
+                    token.to_lowercase()
+                } else {
+                    token.to_string()
+                }
+            })
+            .collect()
+    }
+
+    /// The sparse vector of the text, as column indexes in ascending order and their values.
+    /// Columns that cancelled out to 0 are left out.
+    pub fn transform(&self, text: &str) -> (Vec<u32>, Vec<f32>) {
+        self.transform_tokens(self.tokens(text))
+    }
+
+    /// The sparse vector of text that's already split into tokens. The tokens are hashed as they
+    /// are, they're not lowercased or masked.
+    pub fn transform_tokens<I, T>(&self, tokens: I) -> (Vec<u32>, Vec<f32>)
+    where
+        I: IntoIterator<Item = T>,
+        T: AsRef<str>,
+    {
+        let tokens: Vec<T> = tokens.into_iter().collect();
+        let mut columns: HashMap<u32, f32> = HashMap::new();
+        let mut gram = String::new();
+        for n in 1..=self.ngrams {
+            for window in tokens.windows(n) {
+                gram.clear();
+                for (i, token) in window.iter().enumerate() {
+                    if i > 0 {
+                        gram.push(' ');
+                    }
+                    gram.push_str(token.as_ref());
+                }
+                let hash = murmur3_32(gram.as_bytes(), 0) as i32;
+                let column = (hash.unsigned_abs() as u64 % self.dim as u64) as u32;
+                let sign = if self.alternate_sign && hash < 0 {
+                    -1.0
+                } else {
+                    1.0
+                };
+                *columns.entry(column).or_insert(0.0) += sign;
+            }
+        }
+        let mut columns: Vec<(u32, f32)> = columns.into_iter().filter(|(_, v)| *v != 0.0).collect();
+        columns.sort_unstable_by_key(|(i, _)| *i);
+        if self.normalize {
+            let norm = columns.iter().map(|(_, v)| v * v).sum::<f32>().sqrt();
+            if norm > 0.0 {
+                columns.iter_mut().for_each(|(_, v)| *v /= norm);
+            }
+        }
+        columns.into_iter().unzip()
+    }
+
+    /// Hashes each text into a point of a sparse cloud, in order, measured with the metric.
+    pub fn transform_all<'a, M, I>(
+        &self,
+        texts: I,
+        metric: M,
+    ) -> PointCloudResult<SparseDataRam<f32, u32, M>>
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        self.to_cloud(texts.into_iter().map(|text| self.transform(text)), metric)
+    }
+
+    /// Hashes each list of tokens into a point of a sparse cloud, see
+    /// [`HashingVectorizer::transform_tokens`].
+    pub fn transform_all_tokens<M, I, J, T>(
+        &self,
+        documents: I,
+        metric: M,
+    ) -> PointCloudResult<SparseDataRam<f32, u32, M>>
+    where
+        I: IntoIterator<Item = J>,
+        J: IntoIterator<Item = T>,
+        T: AsRef<str>,
+    {
+        self.to_cloud(
+            documents
+                .into_iter()
+                .map(|tokens| self.transform_tokens(tokens)),
+            metric,
+        )
+    }
+
+    fn to_cloud<M>(
+        &self,
+        rows: impl Iterator<Item = (Vec<u32>, Vec<f32>)>,
+        metric: M,
+    ) -> PointCloudResult<SparseDataRam<f32, u32, M>> {
+        let mut indptr = vec![0u32];
+        let mut indices = Vec::new();
+        let mut data = Vec::new();
+        for (columns, values) in rows {
+            indices.extend(columns);
+            data.extend(values);
+            indptr.push(indices.len() as u32);
+        }
+        SparseDataRam::from_csr(indptr, indices, data, Some(self.dim), metric)
+    }
+}
+
+/// The 32 bit x86 MurmurHash3 of the bytes.
+fn murmur3_32(key: &[u8], seed: u32) -> u32 {
+    const C1: u32 = 0xcc9e_2d51;
+    const C2: u32 = 0x1b87_3593;
+    let mix = |k: u32| k.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+
+    let mut h = seed;
+    let blocks = key.chunks_exact(4);
+    let tail = blocks.remainder();
+    for block in blocks {
+        h ^= mix(u32::from_le_bytes([block[0], block[1], block[2], block[3]]));
+        h = h.rotate_left(13).wrapping_mul(5).wrapping_add(0xe654_6b64);
+    }
+    if !tail.is_empty() {
+        let k = tail
+            .iter()
+            .enumerate()
+            .fold(0u32, |k, (i, b)| k | ((*b as u32) << (8 * i)));
+        h ^= mix(k);
+    }
+
+    h ^= key.len() as u32;
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x85eb_ca6b);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0xc2b2_ae35);
+    h ^ (h >> 16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{PointCloud, L2};
+
+    #[test]
+    fn hashing_masks_numbers_and_normalizes() {
+        let vectorizer = HashingVectorizer::new(1 << 16);
+        assert_eq!(
+            vectorizer.tokens("Connection from 10.0.0.7 port=4411 CLOSED"),
+            vec![
+                "connection",
+                "from",
+                "<num>",
+                "<num>",
+                "<num>",
+                "<num>",
+                "port",
+                "<num>",
+                "closed"
+            ]
+        );
+        let (columns, values) = vectorizer.transform("worker 12 started");
+        assert!(columns.windows(2).all(|w| w[0] < w[1]));
+        let norm: f32 = values.iter().map(|v| v * v).sum();
+        assert_approx_eq!(norm, 1.0);
+        assert_eq!(vectorizer.transform("worker 97 started"), (columns, values));
+    }
+
+    #[test]
+    fn hashing_matches_scikit_learn() {
+        assert_eq!(murmur3_32(b"", 0), 0);
+        assert_eq!(murmur3_32(b"hello", 0), 0x248b_fa47);
+        assert_eq!(
+            murmur3_32(b"The quick brown fox jumps over the lazy dog", 0),
+            0x2e4f_f723
+        );
+
+        // FeatureHasher(n_features=2**20).transform([["hello", "disk"]])
+        let mut vectorizer = HashingVectorizer::new(1 << 20);
+        vectorizer.set_normalize(false);
+        assert_eq!(
+            vectorizer.transform_tokens(vec!["hello", "disk"]),
+            (vec![751572, 784967], vec![-1.0, 1.0])
+        );
+        vectorizer.set_alternate_sign(false);
+        assert_eq!(
+            vectorizer.transform_tokens(vec!["disk", "disk"]),
+            (vec![751572], vec![2.0])
+        );
+    }
+
+    #[test]
+    fn hashing_builds_a_sparse_cloud() {
+        let mut vectorizer = HashingVectorizer::new(1 << 10);
+        vectorizer.set_ngrams(2);
+        let lines = vec!["disk full on /var", "", "disk full on /tmp"];
+        let cloud = vectorizer
+            .transform_all(lines.iter().copied(), L2 {})
+            .unwrap();
+        assert_eq!(cloud.len(), 3);
+        assert_eq!(cloud.dim(), 1 << 10);
+        let dist = cloud.distances_to_point_index(0, &[2]).unwrap()[0];
+        assert!(dist > 0.0 && dist < 1.0);
+
+        let tokens = vec![vec!["disk", "full"], vec!["disk", "full"]];
+        let cloud = vectorizer.transform_all_tokens(tokens, L2 {}).unwrap();
+        assert_eq!(cloud.distances_to_point_index(0, &[1]).unwrap()[0], 0.0);
+    }
+}
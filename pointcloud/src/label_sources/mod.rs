@@ -3,6 +3,7 @@
 use crate::base_traits::*;
 use crate::pc_errors::*;
 use crate::summaries::*;
+use hashbrown::HashMap;
 
 /// Labels for a small number of categories, using ints
 #[derive(Debug)]
@@ -56,6 +57,46 @@ impl LabelSet for SmallIntLabels {
     }
 }
 
+impl LabelSetMut for SmallIntLabels {
+    fn set_label(&mut self, pn: usize, label: Option<&i64>) -> PointCloudResult<()> {
+        if pn >= self.labels.len() {
+            return Err(PointCloudError::DataAccessError {
+                index: pn,
+                reason: "label index out of range".to_string(),
+            });
+        }
+        match label {
+            Some(label) => {
+                self.labels[pn] = *label;
+                if let Some(mask) = &mut self.mask {
+                    mask[pn] = true;
+                }
+            }
+            None => {
+                let len = self.labels.len();
+                self.mask.get_or_insert_with(|| vec![true; len])[pn] = false;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ExtendableLabelSet for SmallIntLabels {
+    fn push_label(&mut self, label: Option<&i64>) -> PointCloudResult<()> {
+        self.labels.push(label.copied().unwrap_or(0));
+        match (label.is_some(), &mut self.mask) {
+            (_, Some(mask)) => mask.push(label.is_some()),
+            (false, None) => {
+                let mut mask = vec![true; self.labels.len()];
+                mask[self.labels.len() - 1] = false;
+                self.mask = Some(mask);
+            }
+            (true, None) => {}
+        }
+        Ok(())
+    }
+}
+
 impl SmallIntLabels {
     /// Creates a new vec label.
     pub fn new(labels: Vec<i64>, mask: Option<Vec<bool>>) -> SmallIntLabels {
@@ -87,7 +128,151 @@ impl SmallIntLabels {
     //pub fn to_one_hot(&self) -> VecLabels {}
 }
 
-/// Uses a vector to label your data. It can be 1 hot encoded, but if you do that you should use `SmallIntLabels`
+/// Categorical labels with names, like strings. The labels are stored as the integer code of
+/// their category, so they summarize like [`SmallIntLabels`], and the names are looked up with
+/// [`LabelSet::label_name`].
+#[derive(Debug)]
+pub struct CategoricalLabels {
+    labels: SmallIntLabels,
+    names: Vec<String>,
+    codes: HashMap<String, i64>,
+}
+
+impl CategoricalLabels {
+    /// Names the integer labels, the name of label `i` is `names[i]`. Labels without a name are
+    /// allowed, they summarize as usual but [`LabelSet::label_name`] gives `None` for them.
+    pub fn new(labels: SmallIntLabels, names: Vec<String>) -> CategoricalLabels {
+        let codes = names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| (name.clone(), i as i64))
+            .collect();
+        CategoricalLabels {
+            labels,
+            names,
+            codes,
+        }
+    }
+
+    /// Gives each distinct name a code, in the order they first appear. `None` marks an unlabeled
+    /// point.
+    pub fn from_names<S: AsRef<str>>(labels: &[Option<S>]) -> CategoricalLabels {
+        let mut categorical =
+            CategoricalLabels::new(SmallIntLabels::new(Vec::new(), None), Vec::new());
+        let mut codes = Vec::with_capacity(labels.len());
+        let mut mask = Vec::with_capacity(labels.len());
+        for label in labels {
+            match label {
+                Some(name) => {
+                    codes.push(categorical.add_category(name.as_ref()));
+                    mask.push(true);
+                }
+                None => {
+                    codes.push(0);
+                    mask.push(false);
+                }
+            }
+        }
+        let mask = if mask.iter().all(|m| *m) {
+            None
+        } else {
+            Some(mask)
+        };
+        categorical.labels = SmallIntLabels::new(codes, mask);
+        categorical
+    }
+
+    /// The names of the categories, indexed by their code.
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// The name of a category's code.
+    pub fn name(&self, code: i64) -> Option<&str> {
+        if code < 0 {
+            return None;
+        }
+        self.names.get(code as usize).map(|n| n.as_str())
+    }
+
+    /// The code of a category's name.
+    pub fn code(&self, name: &str) -> Option<i64> {
+        self.codes.get(name).copied()
+    }
+
+    /// The code for a name, adding it as a new category if it's not known yet.
+    pub fn add_category(&mut self, name: &str) -> i64 {
+        match self.codes.get(name) {
+            Some(code) => *code,
+            None => {
+                let code = self.names.len() as i64;
+                self.names.push(name.to_string());
+                self.codes.insert(name.to_string(), code);
+                code
+            }
+        }
+    }
+
+    /// Replaces the label of a point by name, adding the name as a new category if needed.
+    /// Passing `None` marks the point as unlabeled.
+    pub fn set_label_name(&mut self, pn: usize, name: Option<&str>) -> PointCloudResult<()> {
+        let code = name.map(|name| self.add_category(name));
+        self.labels.set_label(pn, code.as_ref())
+    }
+
+    /// The underlying integer codes.
+    pub fn codes(&self) -> &SmallIntLabels {
+        &self.labels
+    }
+
+    /// Drops the names, leaving the integer codes.
+    pub fn into_codes(self) -> SmallIntLabels {
+        self.labels
+    }
+}
+
+impl From<SmallIntLabels> for CategoricalLabels {
+    fn from(labels: SmallIntLabels) -> CategoricalLabels {
+        CategoricalLabels::new(labels, Vec::new())
+    }
+}
+
+impl LabelSet for CategoricalLabels {
+    type Label = i64;
+    type LabelSummary = CategorySummary;
+
+    fn len(&self) -> usize {
+        self.labels.len()
+    }
+    fn is_empty(&self) -> bool {
+        self.labels.is_empty()
+    }
+    fn label(&self, pn: usize) -> PointCloudResult<Option<&i64>> {
+        self.labels.label(pn)
+    }
+    fn label_summary(&self, pns: &[usize]) -> PointCloudResult<SummaryCounter<Self::LabelSummary>> {
+        self.labels.label_summary(pns)
+    }
+    fn label_name(&self, label: &i64) -> Option<&str> {
+        self.name(*label)
+    }
+}
+
+impl ExtendableLabelSet for CategoricalLabels {
+    fn push_label(&mut self, label: Option<&i64>) -> PointCloudResult<()> {
+        self.labels.push_label(label)
+    }
+}
+
+impl LabelSetMut for CategoricalLabels {
+    fn set_label(&mut self, pn: usize, label: Option<&i64>) -> PointCloudResult<()> {
+        self.labels.set_label(pn, label)
+    }
+}
+
+/// Uses a vector to label your data, like regression targets or multi-label indicators. Node
+/// summaries of these are the per-target moments, see [`VecSummary`]. It can be 1 hot encoded,
+/// but if you do that you should use `SmallIntLabels`
 #[derive(Debug)]
 pub struct VecLabels {
     labels: Vec<f32>,
@@ -166,12 +351,69 @@ impl VecLabels {
     }
 }
 
+impl LabelSetMut for VecLabels {
+    fn set_label(&mut self, pn: usize, label: Option<&[f32]>) -> PointCloudResult<()> {
+        let range = self.label_dim * pn..self.label_dim * (pn + 1);
+        if range.end > self.labels.len() {
+            return Err(PointCloudError::DataAccessError {
+                index: pn,
+                reason: "label index out of range".to_string(),
+            });
+        }
+        match label {
+            Some(label) => {
+                if label.len() != self.label_dim {
+                    return Err(PointCloudError::DataAccessError {
+                        index: pn,
+                        reason: "label has the wrong dimension".to_string(),
+                    });
+                }
+                self.labels[range].copy_from_slice(label);
+                if let Some(mask) = &mut self.mask {
+                    mask[pn] = true;
+                }
+            }
+            None => {
+                let len = self.labels.len() / self.label_dim;
+                self.mask.get_or_insert_with(|| vec![true; len])[pn] = false;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ExtendableLabelSet for VecLabels {
+    fn push_label(&mut self, label: Option<&[f32]>) -> PointCloudResult<()> {
+        let pn = self.len();
+        match label {
+            Some(label) => {
+                if label.len() != self.label_dim {
+                    return Err(PointCloudError::DataAccessError {
+                        index: pn,
+                        reason: "label has the wrong dimension".to_string(),
+                    });
+                }
+                self.labels.extend_from_slice(label);
+                if let Some(mask) = &mut self.mask {
+                    mask.push(true);
+                }
+            }
+            None => {
+                self.labels
+                    .extend(std::iter::repeat(0.0).take(self.label_dim));
+                self.mask.get_or_insert_with(|| vec![true; pn]).push(false);
+            }
+        }
+        Ok(())
+    }
+}
+
 impl LabelSet for VecLabels {
     type Label = [f32];
     type LabelSummary = VecSummary;
 
     fn len(&self) -> usize {
-        self.labels.len()
+        self.labels.len() / self.label_dim
     }
     fn is_empty(&self) -> bool {
         self.labels.is_empty()
@@ -222,3 +464,72 @@ impl LabelSet for VecLabels {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn categorical_labels_name_codes() {
+        let mut labels =
+            CategoricalLabels::from_names(&[Some("cat"), Some("dog"), None, Some("cat")]);
+        assert_eq!(labels.names(), &["cat".to_string(), "dog".to_string()][..]);
+        assert_eq!(labels.label(3).unwrap(), Some(&0));
+        assert_eq!(labels.label(2).unwrap(), None);
+        assert_eq!(labels.label_name(&1), Some("dog"));
+        assert_eq!(labels.code("dog"), Some(1));
+        assert_eq!(labels.label_name(&7), None);
+
+        let summary = labels.label_summary(&[0, 1, 2, 3]).unwrap();
+        assert_eq!(summary.nones, 1);
+        assert!(summary.summary.items.contains(&(0, 2)));
+
+        labels.set_label_name(2, Some("bird")).unwrap();
+        assert_eq!(labels.label(2).unwrap(), Some(&2));
+        assert_eq!(labels.name(2), Some("bird"));
+        assert!(labels.set_label_name(9, Some("bird")).is_err());
+    }
+
+    #[test]
+    fn vec_labels_summarize_targets() {
+        let labels = VecLabels::new(
+            vec![1.0, 10.0, 3.0, 20.0, 5.0, 0.0],
+            2,
+            Some(vec![true, true, false]),
+        );
+        assert_eq!(labels.len(), 3);
+        assert_eq!(labels.label(1).unwrap(), Some(&[3.0, 20.0][..]));
+
+        let summary = labels.label_summary(&[0, 1, 2]).unwrap();
+        assert_eq!(summary.nones, 1);
+        assert_eq!(summary.summary.count, 2);
+        assert_eq!(summary.summary.mean(), vec![2.0, 15.0]);
+        assert_eq!(summary.summary.var(), vec![1.0, 25.0]);
+
+        // Combining into an empty summary keeps the other's moments
+        let mut combined = VecSummary::default();
+        combined.combine(&summary.summary);
+        assert_eq!(combined.count, 2);
+        assert_eq!(combined.mean(), vec![2.0, 15.0]);
+    }
+
+    #[test]
+    fn push_labels_extends_the_mask() {
+        let mut labels = SmallIntLabels::new(vec![1, 2], None);
+        labels.push_label(Some(&3)).unwrap();
+        labels.push_label(None).unwrap();
+        labels.push_label(Some(&4)).unwrap();
+        assert_eq!(labels.len(), 5);
+        assert_eq!(labels.label(2).unwrap(), Some(&3));
+        assert_eq!(labels.label(3).unwrap(), None);
+        assert_eq!(labels.label(4).unwrap(), Some(&4));
+
+        let mut labels = VecLabels::new(vec![1.0, 2.0], 2, None);
+        labels.push_label(None).unwrap();
+        labels.push_label(Some(&[3.0, 4.0][..])).unwrap();
+        assert!(labels.push_label(Some(&[3.0][..])).is_err());
+        assert_eq!(labels.len(), 3);
+        assert_eq!(labels.label(1).unwrap(), None);
+        assert_eq!(labels.label(2).unwrap(), Some(&[3.0, 4.0][..]));
+    }
+}
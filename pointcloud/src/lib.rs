@@ -41,25 +41,38 @@ pub mod metrics;
 pub mod points;
 
 pub mod data_sources;
+pub mod duplicates;
+pub mod hashing;
 
 pub mod glued_data_cloud;
+pub mod subset_cloud;
 
 pub mod label_sources;
+pub mod meta_sources;
+pub mod metric_learning;
+pub mod missing_values;
+pub mod normalization;
+pub mod partitions;
 pub mod summaries;
 
 pub mod loaders;
 
-use data_sources::DataRam;
-use label_sources::SmallIntLabels;
+use data_sources::{DataRam, SparseDataRam};
+use label_sources::{CategoricalLabels, SmallIntLabels};
 
 pub use metrics::L2;
 
 /// A sensible default for an labeled cloud
 pub type DefaultLabeledCloud<M = L2> = SimpleLabeledCloud<DataRam<M>, SmallIntLabels>;
+/// A labeled cloud whose labels are named categories, like strings
+pub type DefaultCategoricalCloud<M = L2> = SimpleLabeledCloud<DataRam<M>, CategoricalLabels>;
 /// A sensible default for an unlabeled cloud
 pub type DefaultCloud<M = L2> = DataRam<M>;
+/// A sensible default for a labeled cloud of sparse points, see [`SparseDataRam::from_csr`]
+pub type DefaultSparseLabeledCloud<M = L2> =
+    SimpleLabeledCloud<SparseDataRam<f32, u32, M>, SmallIntLabels>;
 
-impl<M: Metric<[f32]>> DefaultLabeledCloud<M> {
+impl<M: Metric<[f32]> + Default> DefaultLabeledCloud<M> {
     /// Simple way of gluing together the most common data source
     pub fn new_simple(data: Vec<f32>, dim: usize, labels: Vec<i64>) -> DefaultLabeledCloud<M> {
         SimpleLabeledCloud::new(
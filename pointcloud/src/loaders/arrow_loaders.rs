@@ -0,0 +1,232 @@
+//! Loaders for Arrow IPC files, also known as Feather v2, and in memory `RecordBatch`es. These
+//! are behind the `arrow` feature.
+//!
+//! Points stored in a `FixedSizeList<Float32>` column are used in place with [`DataArrow`], one
+//! data source per batch. Points spread over one numeric column per feature have to be
+//! interleaved, so those are copied into a [`DataRam`].
+
+use arrow::array::{Array, Float32Array, Int64Array};
+use arrow::compute::kernels::cast::cast;
+use arrow::datatypes::DataType;
+use arrow::ipc::reader::FileReader;
+use arrow::record_batch::RecordBatch;
+use std::fs::File;
+
+use super::*;
+use crate::DefaultLabeledCloud;
+
+fn arrow_error(reason: String) -> PointCloudError {
+    PointCloudError::ParsingError(ParsingError::ArrowError { reason })
+}
+
+/// Reads all the record batches in an Arrow IPC file.
+pub fn read_arrow_ipc<P: AsRef<Path>>(path: P) -> PointCloudResult<Vec<RecordBatch>> {
+    let reader = FileReader::try_new(File::open(&path)?).map_err(|e| {
+        arrow_error(format!(
+            "unable to open {}: {}",
+            path.as_ref().to_string_lossy(),
+            e
+        ))
+    })?;
+    reader
+        .map(|batch| batch.map_err(|e| arrow_error(e.to_string())))
+        .collect()
+}
+
+fn column_as<T: Array + From<arrow::array::ArrayData>>(
+    batch: &RecordBatch,
+    column: &str,
+    data_type: &DataType,
+) -> PointCloudResult<T> {
+    let index = batch
+        .schema()
+        .index_of(column)
+        .map_err(|e| arrow_error(e.to_string()))?;
+    let array = cast(batch.column(index), data_type).map_err(|e| {
+        arrow_error(format!(
+            "unable to read column {} as {:?}: {}",
+            column, data_type, e
+        ))
+    })?;
+    Ok(T::from(array.data().clone()))
+}
+
+/// Wraps the `FixedSizeList<Float32>` column of each batch without copying the points.
+pub fn arrow_from_record_batches<M: Metric<[f32]> + Default>(
+    batches: &[RecordBatch],
+    column: &str,
+) -> PointCloudResult<HashGluedCloud<DataArrow<M>>> {
+    let data_sources: PointCloudResult<Vec<DataArrow<M>>> = batches
+        .iter()
+        .map(|batch| DataArrow::from_record_batch(batch, column))
+        .collect();
+    Ok(HashGluedCloud::new(data_sources?))
+}
+
+/// Copies the given numeric columns of the batches into a dense cloud. The columns become the
+/// dimensions of the points, in the order given. Nulls aren't allowed.
+pub fn ram_from_record_batches<M: Metric<[f32]> + Default>(
+    batches: &[RecordBatch],
+    feature_columns: &[&str],
+) -> PointCloudResult<DataRam<M>> {
+    if feature_columns.is_empty() {
+        return Err(arrow_error("no feature columns were selected".to_string()));
+    }
+    let dim = feature_columns.len();
+    let row_count: usize = batches.iter().map(|b| b.num_rows()).sum();
+    let mut data = vec![0.0f32; row_count * dim];
+    let mut row_offset = 0;
+    for batch in batches {
+        for (j, column) in feature_columns.iter().enumerate() {
+            let values: Float32Array = column_as(batch, column, &DataType::Float32)?;
+            if values.null_count() > 0 {
+                return Err(arrow_error(format!("column {} has nulls", column)));
+            }
+            for (i, v) in values.values().iter().enumerate() {
+                data[(row_offset + i) * dim + j] = *v;
+            }
+        }
+        row_offset += batch.num_rows();
+    }
+    DataRam::new(data, dim)
+}
+
+/// Reads integer labels from a column of the batches. Null and negative labels are treated as
+/// unlabeled and are masked.
+pub fn labels_from_record_batches(
+    batches: &[RecordBatch],
+    label_column: &str,
+) -> PointCloudResult<SmallIntLabels> {
+    let mut labels = Vec::new();
+    let mut mask = Vec::new();
+    for batch in batches {
+        let values: Int64Array = column_as(batch, label_column, &DataType::Int64)?;
+        for i in 0..values.len() {
+            if values.is_null(i) {
+                labels.push(0);
+                mask.push(false);
+            } else {
+                labels.push(values.value(i));
+                mask.push(values.value(i) >= 0);
+            }
+        }
+    }
+    if mask.iter().all(|m| *m) {
+        Ok(SmallIntLabels::new(labels, None))
+    } else {
+        Ok(SmallIntLabels::new(labels, Some(mask)))
+    }
+}
+
+/// Opens an Arrow IPC file and wraps its `FixedSizeList<Float32>` column without copying the points.
+pub fn arrow_from_ipc<P: AsRef<Path>, M: Metric<[f32]> + Default>(
+    path: P,
+    column: &str,
+) -> PointCloudResult<HashGluedCloud<DataArrow<M>>> {
+    arrow_from_record_batches(&read_arrow_ipc(path)?, column)
+}
+
+/// Opens an Arrow IPC file and copies the given numeric columns into a dense cloud.
+pub fn ram_from_arrow_ipc<P: AsRef<Path>, M: Metric<[f32]> + Default>(
+    path: P,
+    feature_columns: &[&str],
+) -> PointCloudResult<DataRam<M>> {
+    ram_from_record_batches(&read_arrow_ipc(path)?, feature_columns)
+}
+
+/// Opens an Arrow IPC file and copies the given numeric columns into a dense cloud, with one column as labels.
+pub fn labeled_ram_from_arrow_ipc<P: AsRef<Path>, M: Metric<[f32]> + Default>(
+    path: P,
+    feature_columns: &[&str],
+    label_column: &str,
+) -> PointCloudResult<DefaultLabeledCloud<M>> {
+    let batches = read_arrow_ipc(path)?;
+    Ok(SimpleLabeledCloud::new(
+        ram_from_record_batches(&batches, feature_columns)?,
+        labels_from_record_batches(&batches, label_column)?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::L2;
+    use arrow::array::{ArrayData, FixedSizeListArray, Float64Array, Int32Array};
+    use arrow::datatypes::{Field, Schema};
+    use arrow::ipc::writer::FileWriter;
+    use std::sync::Arc;
+    use tempdir::TempDir;
+
+    fn test_batch() -> RecordBatch {
+        let embeddings = [0.0f32, 1.0, 2.0, 3.0, 4.0, 5.0];
+        let list_data = ArrayData::builder(DataType::FixedSizeList(
+            Box::new(Field::new("item", DataType::Float32, false)),
+            2,
+        ))
+        .len(3)
+        .add_child_data(Float32Array::from(embeddings.to_vec()).data().clone())
+        .build();
+        let embedding = FixedSizeListArray::from(list_data);
+        let x = Float64Array::from(vec![10.0, 11.0, 12.0]);
+        let y = Int32Array::from(vec![-1, -2, -3]);
+        let label = Int32Array::from(vec![Some(1), None, Some(2)]);
+        let schema = Schema::new(vec![
+            Field::new("embedding", embedding.data_type().clone(), false),
+            Field::new("x", DataType::Float64, false),
+            Field::new("y", DataType::Int32, false),
+            Field::new("label", DataType::Int32, true),
+        ]);
+        RecordBatch::try_new(
+            Arc::new(schema),
+            vec![
+                Arc::new(embedding),
+                Arc::new(x),
+                Arc::new(y),
+                Arc::new(label),
+            ],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn copies_feature_columns() {
+        let batches = vec![test_batch(), test_batch()];
+        let data = ram_from_record_batches::<L2>(&batches, &["y", "x"]).unwrap();
+        assert_eq!(data.len(), 6);
+        assert_eq!(data.point(1).unwrap(), &[-2.0, 11.0][..]);
+        assert_eq!(data.point(5).unwrap(), &[-3.0, 12.0][..]);
+        assert!(ram_from_record_batches::<L2>(&batches, &["missing"]).is_err());
+
+        let labels = labels_from_record_batches(&batches, "label").unwrap();
+        assert_eq!(labels.len(), 6);
+        assert_eq!(labels.label(0).unwrap(), Some(&1));
+        assert_eq!(labels.label(1).unwrap(), None);
+    }
+
+    #[test]
+    fn wraps_each_batch() {
+        let batches = vec![test_batch(), test_batch()];
+        let data = arrow_from_record_batches::<L2>(&batches, "embedding").unwrap();
+        assert_eq!(data.len(), 6);
+        assert_eq!(data.dim(), 2);
+        assert_eq!(data.point(4).unwrap(), &[2.0, 3.0][..]);
+        assert!(arrow_from_record_batches::<L2>(&batches, "x").is_err());
+    }
+
+    #[test]
+    fn reads_ipc_files() {
+        let dir = TempDir::new("arrow_loaders").unwrap();
+        let path = dir.path().join("points.arrow");
+        let batch = test_batch();
+        let file = File::create(&path).unwrap();
+        let mut writer = FileWriter::try_new(file, &batch.schema()).unwrap();
+        writer.write(&batch).unwrap();
+        writer.finish().unwrap();
+
+        let data = arrow_from_ipc::<_, L2>(&path, "embedding").unwrap();
+        assert_eq!(data.point(2).unwrap(), &[4.0, 5.0][..]);
+        let cloud = labeled_ram_from_arrow_ipc::<_, L2>(&path, &["x"], "label").unwrap();
+        assert_eq!(cloud.point(2).unwrap(), &[12.0][..]);
+        assert_eq!(cloud.label(2).unwrap(), Some(&2));
+    }
+}
@@ -0,0 +1,570 @@
+//! Streams points off disk a fixed size chunk at a time, so a dataset can be fed to a tree
+//! without ever being held in RAM all at once.
+//!
+//! A [`ChunkSource`] reads raw points out of a file, [`PointChunks`] turns that into an iterator
+//! of [`DataRam`] chunks. It reports how far through the file it is and can be stopped early,
+//! from another thread if need be, with a [`StopHandle`].
+
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use csv::{ReaderBuilder, StringRecord};
+
+use super::npy_loaders::{decode, numpy_error, read_header, NpyHeader};
+use super::*;
+use crate::metrics::L2;
+
+/// Something points can be read out of a few at a time.
+pub trait ChunkSource: Send {
+    /// The dimension of the points.
+    fn dim(&self) -> usize;
+    /// The number of points in the source, if it's known before reading them all.
+    fn total_points(&self) -> Option<usize>;
+    /// Appends up to `max_points` points to the buffer and returns how many it added. Returns 0
+    /// once the source is exhausted.
+    fn read_points(&mut self, max_points: usize, buffer: &mut Vec<f32>) -> PointCloudResult<usize>;
+    /// The names of the dimensions, if the source has them. The chunks are named with these.
+    fn dim_names(&self) -> Option<Vec<String>> {
+        None
+    }
+}
+
+fn truncated_error(points_read: usize, points: usize, file_name: &str) -> PointCloudError {
+    PointCloudError::DataAccessError {
+        index: points_read,
+        reason: format!(
+            "{} ended after {} of {} points",
+            file_name, points_read, points
+        ),
+    }
+}
+
+/// Reads `count` little endian floats onto the end of the buffer.
+fn read_f32s<R: Read>(reader: &mut R, count: usize, buffer: &mut Vec<f32>) -> std::io::Result<()> {
+    let mut bytes = vec![0u8; count * std::mem::size_of::<f32>()];
+    reader.read_exact(&mut bytes)?;
+    buffer.extend(
+        bytes
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+    );
+    Ok(())
+}
+
+/// Streams a point file, the format [`MmapFileWriter`] writes.
+#[derive(Debug)]
+pub struct PointFileSource {
+    file_name: String,
+    reader: BufReader<File>,
+    dim: usize,
+    count: usize,
+    points_read: usize,
+}
+
+impl PointFileSource {
+    /// Opens the file and reads its header.
+    pub fn open<P: AsRef<Path>>(path: P) -> PointCloudResult<PointFileSource> {
+        let path = path.as_ref();
+        let mut reader = BufReader::new(File::open(path)?);
+        let (dim, count) = read_point_file_header(&mut reader, path)?;
+        Ok(PointFileSource {
+            file_name: path.to_string_lossy().to_string(),
+            reader,
+            dim,
+            count,
+            points_read: 0,
+        })
+    }
+}
+
+impl ChunkSource for PointFileSource {
+    fn dim(&self) -> usize {
+        self.dim
+    }
+
+    fn total_points(&self) -> Option<usize> {
+        Some(self.count)
+    }
+
+    fn read_points(&mut self, max_points: usize, buffer: &mut Vec<f32>) -> PointCloudResult<usize> {
+        let points = max_points.min(self.count - self.points_read);
+        read_f32s(&mut self.reader, points * self.dim, buffer)
+            .map_err(|_| truncated_error(self.points_read, self.count, &self.file_name))?;
+        self.points_read += points;
+        Ok(points)
+    }
+}
+
+/// Streams a headerless file of little endian `f32`s, point after point. This is the layout
+/// [`DataMemmap`] maps.
+#[derive(Debug)]
+pub struct RawF32Source {
+    file_name: String,
+    reader: BufReader<File>,
+    dim: usize,
+    count: usize,
+    points_read: usize,
+}
+
+impl RawF32Source {
+    /// Opens the file. Its length has to be a whole number of points of the given dimension.
+    pub fn open<P: AsRef<Path>>(path: P, dim: usize) -> PointCloudResult<RawF32Source> {
+        let path = path.as_ref();
+        let file_name = path.to_string_lossy().to_string();
+        let file = File::open(path)?;
+        let point_len = (dim * std::mem::size_of::<f32>()) as u64;
+        let file_len = file.metadata()?.len();
+        if dim == 0 || file_len % point_len != 0 {
+            return Err(PointCloudError::DataAccessError {
+                index: 0,
+                reason: format!(
+                    "{} is {} bytes, which isn't a whole number of points of dimension {}",
+                    file_name, file_len, dim
+                ),
+            });
+        }
+        Ok(RawF32Source {
+            file_name,
+            reader: BufReader::new(file),
+            dim,
+            count: (file_len / point_len) as usize,
+            points_read: 0,
+        })
+    }
+}
+
+impl ChunkSource for RawF32Source {
+    fn dim(&self) -> usize {
+        self.dim
+    }
+
+    fn total_points(&self) -> Option<usize> {
+        Some(self.count)
+    }
+
+    fn read_points(&mut self, max_points: usize, buffer: &mut Vec<f32>) -> PointCloudResult<usize> {
+        let points = max_points.min(self.count - self.points_read);
+        read_f32s(&mut self.reader, points * self.dim, buffer)
+            .map_err(|_| truncated_error(self.points_read, self.count, &self.file_name))?;
+        self.points_read += points;
+        Ok(points)
+    }
+}
+
+/// Streams the rows of a 2 dimensional npy array. Any numeric dtype is converted to `f32`.
+/// Fortran ordered arrays can't be streamed by row and are an error.
+#[derive(Debug)]
+pub struct NpySource {
+    file_name: String,
+    reader: BufReader<File>,
+    header: NpyHeader,
+    points_read: usize,
+}
+
+impl NpySource {
+    /// Opens the file and reads its header.
+    pub fn open<P: AsRef<Path>>(path: P) -> PointCloudResult<NpySource> {
+        let file_name = path.as_ref().to_string_lossy().to_string();
+        let mut reader = BufReader::new(File::open(&path)?);
+        let header = read_header(&mut reader, &file_name)?;
+        if header.shape.len() != 2 {
+            return Err(numpy_error(
+                &file_name,
+                format!("expected a 2 dimensional array, got {}", header.shape.len()),
+            ));
+        }
+        if header.fortran_order {
+            return Err(numpy_error(
+                &file_name,
+                "fortran ordered arrays can't be streamed by row".to_string(),
+            ));
+        }
+        Ok(NpySource {
+            file_name,
+            reader,
+            header,
+            points_read: 0,
+        })
+    }
+}
+
+impl ChunkSource for NpySource {
+    fn dim(&self) -> usize {
+        self.header.shape[1]
+    }
+
+    fn total_points(&self) -> Option<usize> {
+        Some(self.header.shape[0])
+    }
+
+    fn read_points(&mut self, max_points: usize, buffer: &mut Vec<f32>) -> PointCloudResult<usize> {
+        let count = self.header.shape[0];
+        let points = max_points.min(count - self.points_read);
+        let item_size = self.header.item_size;
+        let mut bytes = vec![0u8; points * self.dim() * item_size];
+        self.reader.read_exact(&mut bytes).map_err(|_| {
+            numpy_error(
+                &self.file_name,
+                format!(
+                    "the file ended after {} of {} rows",
+                    self.points_read, count
+                ),
+            )
+        })?;
+        let header = &self.header;
+        buffer.extend(
+            bytes
+                .chunks_exact(item_size)
+                .map(|b| decode::<f32>(header, b)),
+        );
+        self.points_read += points;
+        Ok(points)
+    }
+}
+
+/// Streams the rows of a CSV of numbers, every column is a coordinate. The dimension is taken
+/// from the first row, every other row has to match it. The number of rows isn't known up front.
+/// Empty fields are missing values and read as NaN, see [`crate::missing_values`].
+#[derive(Debug)]
+pub struct CsvSource {
+    file_name: String,
+    reader: csv::Reader<File>,
+    record: StringRecord,
+    pending: bool,
+    dim: usize,
+    headers: Option<Vec<String>>,
+}
+
+impl CsvSource {
+    /// Opens the file and reads the first row. If there is a header row it names the dimensions.
+    pub fn open<P: AsRef<Path>>(path: P, has_headers: bool) -> PointCloudResult<CsvSource> {
+        let file_name = path.as_ref().to_string_lossy().to_string();
+        let mut reader = ReaderBuilder::new()
+            .has_headers(has_headers)
+            .from_reader(File::open(&path)?);
+        let headers = if has_headers {
+            let headers = reader
+                .headers()
+                .map_err(|e| csv_error(&file_name, &StringRecord::new(), e.to_string()))?;
+            Some(headers.iter().map(|h| h.trim().to_string()).collect())
+        } else {
+            None
+        };
+        let mut record = StringRecord::new();
+        let pending = reader
+            .read_record(&mut record)
+            .map_err(|e| csv_error(&file_name, &record, e.to_string()))?;
+        Ok(CsvSource {
+            file_name,
+            reader,
+            dim: record.len(),
+            record,
+            pending,
+            headers,
+        })
+    }
+
+    fn push_record(&self, buffer: &mut Vec<f32>) -> PointCloudResult<()> {
+        if self.record.len() != self.dim {
+            return Err(csv_error(
+                &self.file_name,
+                &self.record,
+                format!("expected {} columns, found {}", self.dim, self.record.len()),
+            ));
+        }
+        for field in self.record.iter() {
+            let field = field.trim();
+            if field.is_empty() {
+                buffer.push(f32::NAN);
+                continue;
+            }
+            let x = field.parse::<f32>().map_err(|_| {
+                csv_error(
+                    &self.file_name,
+                    &self.record,
+                    format!("{} isn't a number", field),
+                )
+            })?;
+            buffer.push(x);
+        }
+        Ok(())
+    }
+}
+
+fn csv_error(file_name: &str, record: &StringRecord, key: String) -> PointCloudError {
+    PointCloudError::ParsingError(ParsingError::CSVReadError {
+        file_name: file_name.to_string(),
+        line_number: record.position().map(|p| p.line() as usize).unwrap_or(0),
+        key,
+    })
+}
+
+impl ChunkSource for CsvSource {
+    fn dim(&self) -> usize {
+        self.dim
+    }
+
+    fn total_points(&self) -> Option<usize> {
+        None
+    }
+
+    fn read_points(&mut self, max_points: usize, buffer: &mut Vec<f32>) -> PointCloudResult<usize> {
+        let mut points = 0;
+        while self.pending && points < max_points {
+            self.push_record(buffer)?;
+            points += 1;
+            self.pending = self
+                .reader
+                .read_record(&mut self.record)
+                .map_err(|e| csv_error(&self.file_name, &self.record, e.to_string()))?;
+        }
+        Ok(points)
+    }
+
+    fn dim_names(&self) -> Option<Vec<String>> {
+        self.headers.clone()
+    }
+}
+
+/// Stops a [`PointChunks`] iterator. Clones all stop the same iterator.
+#[derive(Debug, Clone, Default)]
+pub struct StopHandle {
+    stopped: Arc<AtomicBool>,
+}
+
+impl StopHandle {
+    /// The iterator returns `None` from its next call on.
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+    }
+
+    /// If the iterator has been stopped.
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::SeqCst)
+    }
+}
+
+/// How far through its source a [`PointChunks`] iterator is.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChunkProgress {
+    /// The points handed out so far
+    pub points_read: usize,
+    /// The number of points in the source, if it's known
+    pub total_points: Option<usize>,
+}
+
+impl ChunkProgress {
+    /// The fraction of the source that's been read, if the size of the source is known.
+    pub fn fraction(&self) -> Option<f32> {
+        self.total_points.map(|total| {
+            if total == 0 {
+                1.0
+            } else {
+                self.points_read as f32 / total as f32
+            }
+        })
+    }
+}
+
+/// Iterates over a source in chunks of at most `chunk_size` points. Only one chunk is in memory
+/// at a time, unless the caller holds on to them. The iterator ends when the source runs out,
+/// after the first error, or once it's been stopped.
+#[derive(Debug)]
+pub struct PointChunks<S: ChunkSource, M = L2> {
+    source: S,
+    chunk_size: usize,
+    points_read: usize,
+    stop: StopHandle,
+    finished: bool,
+    metric: PhantomData<M>,
+}
+
+impl<S: ChunkSource, M> PointChunks<S, M> {
+    /// Chunks up the source. The chunk size has to be at least 1.
+    pub fn new(source: S, chunk_size: usize) -> PointChunks<S, M> {
+        assert!(chunk_size > 0, "the chunk size has to be at least 1");
+        PointChunks {
+            source,
+            chunk_size,
+            points_read: 0,
+            stop: StopHandle::default(),
+            finished: false,
+            metric: PhantomData,
+        }
+    }
+
+    /// The dimension of the points in the chunks.
+    pub fn dim(&self) -> usize {
+        self.source.dim()
+    }
+
+    /// How many points have been handed out so far, out of how many.
+    pub fn progress(&self) -> ChunkProgress {
+        ChunkProgress {
+            points_read: self.points_read,
+            total_points: self.source.total_points(),
+        }
+    }
+
+    /// A handle that can stop this iterator, from another thread if need be.
+    pub fn stop_handle(&self) -> StopHandle {
+        self.stop.clone()
+    }
+
+    /// Stops the iterator, the next call to `next` returns `None`.
+    pub fn stop(&self) {
+        self.stop.stop()
+    }
+}
+
+impl<S: ChunkSource, M: Default> Iterator for PointChunks<S, M> {
+    type Item = PointCloudResult<DataRam<M>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished || self.stop.is_stopped() {
+            return None;
+        }
+        let dim = self.source.dim();
+        let mut data = Vec::with_capacity(self.chunk_size * dim);
+        match self.source.read_points(self.chunk_size, &mut data) {
+            Ok(0) => {
+                self.finished = true;
+                None
+            }
+            Ok(points) => {
+                self.points_read += points;
+                let chunk = DataRam::new(data, dim);
+                match self.source.dim_names() {
+                    Some(names) => Some(chunk.and_then(|c| c.with_dim_names(names))),
+                    None => Some(chunk),
+                }
+            }
+            Err(e) => {
+                self.finished = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempdir::TempDir;
+
+    fn points(count: usize, dim: usize) -> Vec<f32> {
+        (0..count * dim).map(|i| i as f32).collect()
+    }
+
+    fn check_chunks<S: ChunkSource>(source: S, expected: &[f32]) {
+        let dim = source.dim();
+        let mut chunks = PointChunks::<S, L2>::new(source, 4);
+        let mut seen = Vec::new();
+        let mut sizes = Vec::new();
+        for chunk in chunks.by_ref() {
+            let chunk = chunk.unwrap();
+            sizes.push(chunk.len());
+            for i in 0..chunk.len() {
+                seen.extend_from_slice(chunk.point(i).unwrap());
+            }
+        }
+        assert_eq!(sizes, vec![4, 4, 2]);
+        assert_eq!(seen, expected);
+        assert_eq!(chunks.progress().points_read, expected.len() / dim);
+    }
+
+    #[test]
+    fn streams_point_files() {
+        let dir = TempDir::new("chunked_loaders").unwrap();
+        let path = dir.path().join("points.pcf4");
+        let data = points(10, 3);
+        let mut writer = MmapFileWriter::create(&path, 3).unwrap();
+        writer.write_points(&data).unwrap();
+        writer.finish().unwrap();
+
+        let source = PointFileSource::open(&path).unwrap();
+        assert_eq!(source.total_points(), Some(10));
+        check_chunks(source, &data);
+    }
+
+    #[test]
+    fn streams_raw_files() {
+        let dir = TempDir::new("chunked_loaders").unwrap();
+        let path = dir.path().join("points.dat");
+        let data = points(10, 2);
+        let mut file = File::create(&path).unwrap();
+        for x in &data {
+            file.write_all(&x.to_le_bytes()).unwrap();
+        }
+        drop(file);
+
+        check_chunks(RawF32Source::open(&path, 2).unwrap(), &data);
+        assert!(RawF32Source::open(&path, 3).is_err());
+    }
+
+    #[test]
+    fn streams_npy_files() {
+        let dir = TempDir::new("chunked_loaders").unwrap();
+        let path = dir.path().join("points.npy");
+        let header = "{'descr': '<f8', 'fortran_order': False, 'shape': (10, 2), }";
+        let mut file = File::create(&path).unwrap();
+        file.write_all(b"\x93NUMPY\x01\x00").unwrap();
+        file.write_all(&(header.len() as u16).to_le_bytes())
+            .unwrap();
+        file.write_all(header.as_bytes()).unwrap();
+        let data = points(10, 2);
+        for x in &data {
+            file.write_all(&(*x as f64).to_le_bytes()).unwrap();
+        }
+        drop(file);
+
+        check_chunks(NpySource::open(&path).unwrap(), &data);
+    }
+
+    #[test]
+    fn streams_csv_files() {
+        let dir = TempDir::new("chunked_loaders").unwrap();
+        let path = dir.path().join("points.csv");
+        let data = points(10, 2);
+        let mut file = File::create(&path).unwrap();
+        file.write_all(b"x,y\n").unwrap();
+        for p in data.chunks(2) {
+            writeln!(file, "{},{}", p[0], p[1]).unwrap();
+        }
+        drop(file);
+
+        let source = CsvSource::open(&path, true).unwrap();
+        assert_eq!(source.dim(), 2);
+        assert_eq!(source.total_points(), None);
+        assert_eq!(source.dim_names().unwrap(), vec!["x", "y"]);
+        check_chunks(source, &data);
+
+        let mut chunks = PointChunks::<_, L2>::new(CsvSource::open(&path, true).unwrap(), 4);
+        let chunk = chunks.next().unwrap().unwrap();
+        assert_eq!(chunk.dim_name(1), Some("y"));
+    }
+
+    #[test]
+    fn reports_progress_and_stops_early() {
+        let dir = TempDir::new("chunked_loaders").unwrap();
+        let path = dir.path().join("points.pcf4");
+        let mut writer = MmapFileWriter::create(&path, 1).unwrap();
+        writer.write_points(&points(10, 1)).unwrap();
+        writer.finish().unwrap();
+
+        let mut chunks = PointChunks::<_, L2>::new(PointFileSource::open(&path).unwrap(), 5);
+        assert_eq!(chunks.progress().fraction(), Some(0.0));
+        chunks.next().unwrap().unwrap();
+        assert_eq!(chunks.progress().points_read, 5);
+        assert_eq!(chunks.progress().fraction(), Some(0.5));
+
+        let handle = chunks.stop_handle();
+        handle.stop();
+        assert!(chunks.next().is_none());
+        assert_eq!(chunks.progress().points_read, 5);
+    }
+}
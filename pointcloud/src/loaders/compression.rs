@@ -0,0 +1,176 @@
+//! Transparent decompression for feature and label files. A file ending in `.gz` is read as
+//! gzip and one ending in `.zst` as zstandard, decompressing as it's read. The format of the
+//! contents comes from the extension before that, so `train.dat.zst` is a compressed memmap.
+
+use flate2::read::MultiGzDecoder;
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::{BufReader, Read};
+
+use super::*;
+
+/// The compression of a file, from its extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    /// Read as is
+    None,
+    /// `.gz`
+    Gzip,
+    /// `.zst`
+    Zstd,
+}
+
+impl Compression {
+    /// Works out the compression from the last extension.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Compression {
+        match path.as_ref().extension().and_then(OsStr::to_str) {
+            Some("gz") => Compression::Gzip,
+            Some("zst") => Compression::Zstd,
+            _ => Compression::None,
+        }
+    }
+
+    /// If the file needs decompressing.
+    pub fn is_compressed(&self) -> bool {
+        *self != Compression::None
+    }
+}
+
+/// The extension of the contents, skipping a compression extension. This is `dat` for both
+/// `train.dat` and `train.dat.gz`.
+pub fn content_extension(path: &Path) -> Option<&str> {
+    let path = if Compression::from_path(path).is_compressed() {
+        Path::new(path.file_stem()?)
+    } else {
+        path
+    };
+    path.extension().and_then(OsStr::to_str)
+}
+
+/// Opens a file for reading, decompressing it on the fly if it has a compressed extension.
+pub fn open_decompressed<P: AsRef<Path>>(path: P) -> PointCloudResult<Box<dyn Read>> {
+    let file = BufReader::new(File::open(&path)?);
+    Ok(match Compression::from_path(&path) {
+        Compression::None => Box::new(file),
+        Compression::Gzip => Box::new(MultiGzDecoder::new(file)),
+        Compression::Zstd => Box::new(zstd::Decoder::with_buffer(file)?),
+    })
+}
+
+/// Reads a file of little endian `f32`s, point after point, the layout [`DataMemmap`] maps. The
+/// file is decompressed as it's read, so only the decoded points are ever held in memory.
+pub fn ram_from_f32_file<P: AsRef<Path>, M: Metric<[f32]> + Default>(
+    path: P,
+    dim: usize,
+) -> PointCloudResult<DataRam<M>> {
+    let mut reader = open_decompressed(&path)?;
+    let mut data = Vec::new();
+    let mut buffer = vec![0u8; 1 << 16];
+    // A float can be split across reads
+    let mut carry = 0;
+    loop {
+        let read = reader.read(&mut buffer[carry..])?;
+        if read == 0 {
+            break;
+        }
+        let filled = carry + read;
+        let whole = filled - filled % 4;
+        data.extend(
+            buffer[..whole]
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+        );
+        buffer.copy_within(whole..filled, 0);
+        carry = filled - whole;
+    }
+    if carry != 0 || dim == 0 || data.len() % dim != 0 {
+        return Err(PointCloudError::DataAccessError {
+            index: data.len() / dim.max(1),
+            reason: format!(
+                "{} isn't a whole number of points of dimension {}",
+                path.as_ref().to_string_lossy(),
+                dim
+            ),
+        });
+    }
+    DataRam::new(data, dim)
+}
+
+/// Reads a set of `f32` files into one ram cloud, in order. Compressed files are decompressed,
+/// plain ones are memory mapped and copied in.
+pub fn ram_from_f32_files<M: Metric<[f32]> + Default>(
+    dim: usize,
+    paths: &[PathBuf],
+) -> PointCloudResult<DataRam<M>> {
+    if !paths
+        .iter()
+        .any(|p| Compression::from_path(p).is_compressed())
+    {
+        return Ok(convert_glued_memmap_to_ram(open_memmaps(dim, paths)?));
+    }
+    let mut data_set: Option<DataRam<M>> = None;
+    for path in paths {
+        let part = if Compression::from_path(path).is_compressed() {
+            ram_from_f32_file(path, dim)?
+        } else {
+            DataMemmap::<M>::new(dim, path)?.convert_to_ram()
+        };
+        match data_set.as_mut() {
+            Some(data_set) => data_set.merge(part),
+            None => data_set = Some(part),
+        }
+    }
+    Ok(data_set.unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::L2;
+    use flate2::write::GzEncoder;
+    use std::io::Write;
+    use tempdir::TempDir;
+
+    fn as_bytes(data: &[f32]) -> Vec<u8> {
+        data.iter().flat_map(|x| x.to_le_bytes().to_vec()).collect()
+    }
+
+    #[test]
+    fn detects_compression() {
+        assert_eq!(Compression::from_path("a.dat"), Compression::None);
+        assert_eq!(Compression::from_path("a.dat.gz"), Compression::Gzip);
+        assert_eq!(Compression::from_path("a.dat.zst"), Compression::Zstd);
+        assert_eq!(content_extension(Path::new("a/b.dat.zst")), Some("dat"));
+        assert_eq!(content_extension(Path::new("a/b.csv")), Some("csv"));
+    }
+
+    #[test]
+    fn reads_compressed_floats() {
+        let dir = TempDir::new("compression").unwrap();
+        let data: Vec<f32> = (0..30000).map(|i| i as f32 * 0.5).collect();
+        let bytes = as_bytes(&data);
+
+        let gz_path = dir.path().join("points.dat.gz");
+        let mut encoder = GzEncoder::new(File::create(&gz_path).unwrap(), Default::default());
+        encoder.write_all(&bytes).unwrap();
+        encoder.finish().unwrap();
+
+        let zst_path = dir.path().join("points.dat.zst");
+        std::fs::write(&zst_path, zstd::encode_all(&bytes[..], 3).unwrap()).unwrap();
+
+        let plain_path = dir.path().join("points.dat");
+        std::fs::write(&plain_path, &bytes).unwrap();
+
+        for path in &[&gz_path, &zst_path] {
+            let cloud = ram_from_f32_file::<_, L2>(path, 3).unwrap();
+            assert_eq!(cloud.len(), 10000);
+            assert_eq!(cloud.point(9999).unwrap(), &data[29997..]);
+            assert!(ram_from_f32_file::<_, L2>(path, 7).is_err());
+        }
+
+        let paths = vec![gz_path, plain_path, zst_path];
+        let cloud = ram_from_f32_files::<L2>(3, &paths).unwrap();
+        assert_eq!(cloud.len(), 30000);
+        assert_eq!(cloud.point(10000).unwrap(), &data[..3]);
+    }
+}
@@ -0,0 +1,610 @@
+//! The point cloud config as a serde struct, so the schema the yaml loaders read can also be
+//! written as JSON or TOML, or built in code. The fields are the same as the yaml loaders':
+//! ```toml
+//! data_path = "data/*.dat"
+//! data_dim = 784
+//! labels_path = "labels.csv"
+//! labels_index = 2
+//! ```
+//! Relative paths are relative to the config file's directory, or to the base directory set with
+//! [`PointCloudConfig::set_base_dir`] when the config is built in code.
+//!
+//! Labels can also be declared with a type in their own file, see [`LabelSchema`]:
+//! ```toml
+//! data_path = "data/*.dat"
+//! data_dim = 784
+//!
+//! [labels]
+//! path = "labels.csv"
+//! type = "string"
+//! column = "category"
+//! ```
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::ffi::OsStr;
+use std::fs;
+use std::hash::Hash;
+
+use super::yaml_loaders::{get_file_list, labels_from_files};
+use super::*;
+use crate::metrics::{WeightedL2, L2};
+use crate::missing_values::NanPolicy;
+use crate::normalization::Normalization;
+use crate::{DefaultCategoricalCloud, DefaultLabeledCloud};
+
+/// The formats a config can be written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+    /// `.json`
+    Json,
+    /// `.toml`
+    Toml,
+    /// `.yaml` or `.yml`
+    Yaml,
+}
+
+impl ConfigFormat {
+    /// Works out the format from the file's extension.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<ConfigFormat> {
+        match path.as_ref().extension().and_then(OsStr::to_str) {
+            Some("json") => Some(ConfigFormat::Json),
+            Some("toml") => Some(ConfigFormat::Toml),
+            Some("yaml") | Some("yml") => Some(ConfigFormat::Yaml),
+            _ => None,
+        }
+    }
+
+    /// Parses a config. Unknown fields are ignored, so one file can hold the configs of several
+    /// parts, like the point cloud and the tree builder.
+    pub fn parse<T: DeserializeOwned>(
+        &self,
+        contents: &str,
+        file_name: &str,
+    ) -> PointCloudResult<T> {
+        let parsed = match self {
+            ConfigFormat::Json => serde_json::from_str(contents).map_err(|e| e.to_string()),
+            ConfigFormat::Toml => toml::from_str(contents).map_err(|e| e.to_string()),
+            ConfigFormat::Yaml => serde_yaml::from_str(contents).map_err(|e| e.to_string()),
+        };
+        parsed.map_err(|reason| {
+            ParsingError::ConfigError {
+                file_name: file_name.to_string(),
+                reason,
+            }
+            .into()
+        })
+    }
+}
+
+/// Reads and parses a config file, picking the format from the extension.
+pub fn read_config<T: DeserializeOwned, P: AsRef<Path>>(path: P) -> PointCloudResult<T> {
+    let file_name = path.as_ref().to_string_lossy().to_string();
+    let format = ConfigFormat::from_path(&path).ok_or_else(|| ParsingError::ConfigError {
+        file_name: file_name.clone(),
+        reason: "the extension has to be json, toml, yaml or yml".to_string(),
+    })?;
+    let contents = fs::read_to_string(&path)?;
+    format.parse(&contents, &file_name)
+}
+
+/// Where a point cloud's data and labels are and how to read them.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PointCloudConfig {
+    /// A path or glob of the data files, flat little endian `f32`s that can be compressed
+    #[serde(default)]
+    pub data_path: String,
+    /// A list of paths and globs of data shards, used instead of the data path. See
+    /// [`ram_from_shards`].
+    #[serde(default)]
+    pub shards: Option<Vec<String>>,
+    /// The dimension of the data
+    pub data_dim: usize,
+    /// A path or glob of the label files, CSVs or `f32` memmaps
+    #[serde(default)]
+    pub labels_path: Option<String>,
+    /// A list of paths and globs of label shards, used instead of the labels path
+    #[serde(default)]
+    pub labels_shards: Option<Vec<String>>,
+    /// The column of the labels in a label CSV
+    #[serde(default)]
+    pub labels_index: Option<usize>,
+    /// The dimension of a label memmap, 1 for binary labels and more for one hot labels
+    #[serde(default)]
+    pub labels_dim: Option<usize>,
+    /// The weights of a [`WeightedL2`] metric
+    #[serde(default)]
+    pub metric_weights: Option<Vec<f32>>,
+    /// The names of the dimensions, one per dimension
+    #[serde(default)]
+    pub dim_names: Option<Vec<String>>,
+    /// Fitted to the data once it's loaded and applied to query points, see
+    /// [`crate::normalization`]
+    #[serde(default)]
+    pub normalization: Option<Normalization>,
+    /// How missing values are handled, applied before the normalization, see
+    /// [`crate::missing_values`]
+    #[serde(default)]
+    pub nan_policy: Option<NanPolicy>,
+    /// Collapses identical points, and labels, into one point with a count once the data is
+    /// loaded, see [`crate::duplicates`]
+    #[serde(default)]
+    pub collapse_duplicates: bool,
+    /// Typed labels in their own file, used instead of the labels path
+    #[serde(default)]
+    pub labels: Option<LabelSchema>,
+    #[serde(skip)]
+    base_dir: PathBuf,
+}
+
+impl PointCloudConfig {
+    /// A config for unlabeled data.
+    pub fn new<S: Into<String>>(data_path: S, data_dim: usize) -> PointCloudConfig {
+        PointCloudConfig {
+            data_path: data_path.into(),
+            shards: None,
+            data_dim,
+            labels_path: None,
+            labels_shards: None,
+            labels_index: None,
+            labels_dim: None,
+            metric_weights: None,
+            dim_names: None,
+            normalization: None,
+            nan_policy: None,
+            collapse_duplicates: false,
+            labels: None,
+            base_dir: PathBuf::new(),
+        }
+    }
+
+    /// Reads a JSON, TOML or YAML config file. Relative paths in it are relative to the file.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> PointCloudResult<PointCloudConfig> {
+        let mut config: PointCloudConfig = read_config(&path)?;
+        if let Some(dir) = path.as_ref().parent() {
+            config.base_dir = dir.to_path_buf();
+        }
+        Ok(config)
+    }
+
+    /// Parses a JSON config. Relative paths are relative to the working directory.
+    pub fn from_json_str(contents: &str) -> PointCloudResult<PointCloudConfig> {
+        ConfigFormat::Json.parse(contents, "<json>")
+    }
+
+    /// Parses a TOML config. Relative paths are relative to the working directory.
+    pub fn from_toml_str(contents: &str) -> PointCloudResult<PointCloudConfig> {
+        ConfigFormat::Toml.parse(contents, "<toml>")
+    }
+
+    /// Sets the directory relative paths are resolved against.
+    pub fn set_base_dir<P: AsRef<Path>>(&mut self, base_dir: P) -> &mut Self {
+        self.base_dir = base_dir.as_ref().to_path_buf();
+        self
+    }
+
+    /// A config for data split into shards.
+    pub fn from_shards<S: Into<String>>(shards: Vec<S>, data_dim: usize) -> PointCloudConfig {
+        let mut config = PointCloudConfig::new("", data_dim);
+        config.shards = Some(shards.into_iter().map(|s| s.into()).collect());
+        config
+    }
+
+    /// The data files the shards or the data path match, in the order they're concatenated. URLs
+    /// are downloaded, see [`fetch_cached`].
+    pub fn data_paths(&self) -> PointCloudResult<Vec<PathBuf>> {
+        match &self.shards {
+            Some(shards) => shard_paths(shards, &self.base_dir),
+            None => get_file_list(&self.data_path, &self.base_dir),
+        }
+    }
+
+    /// The label files the label shards or the labels path match, empty if there are neither.
+    pub fn labels_paths(&self) -> PointCloudResult<Vec<PathBuf>> {
+        match (&self.labels_shards, &self.labels_path) {
+            (Some(shards), _) => shard_paths(shards, &self.base_dir),
+            (None, Some(path)) => get_file_list(path, &self.base_dir),
+            (None, None) => Ok(Vec::new()),
+        }
+    }
+
+    /// The metric the config describes, unweighted if there are no weights.
+    pub fn weighted_l2(&self) -> PointCloudResult<WeightedL2> {
+        match &self.metric_weights {
+            Some(weights) => WeightedL2::new(weights.clone()),
+            None => Ok(WeightedL2::default()),
+        }
+    }
+}
+
+/// Reads the data the config points at into ram.
+pub fn ram_from_config<M: Metric<[f32]> + Default>(
+    config: &PointCloudConfig,
+) -> PointCloudResult<DataRam<M>> {
+    let mut data = unlabeled_ram_from_config(config)?;
+    if config.collapse_duplicates {
+        data.collapse_duplicates();
+    }
+    Ok(data)
+}
+
+/// Collapses the duplicates of labeled data if the config asks for it. Points are only collapsed
+/// if their labels match too, by the key `label_key` gives them.
+fn collapse_labeled<M, L: LabelSet, K: Hash + Eq>(
+    config: &PointCloudConfig,
+    data: &mut DataRam<M>,
+    labels: &L,
+    label_key: impl Fn(&L::Label) -> K,
+) {
+    if config.collapse_duplicates {
+        data.collapse_duplicates_by(|pi| labels.label(pi).ok().map(|l| l.map(&label_key)));
+    }
+}
+
+fn f32_label_key(label: &[f32]) -> Vec<u32> {
+    label.iter().map(|x| x.to_bits()).collect()
+}
+
+fn unlabeled_ram_from_config<M: Metric<[f32]> + Default>(
+    config: &PointCloudConfig,
+) -> PointCloudResult<DataRam<M>> {
+    let data_paths = config.data_paths()?;
+    if data_paths.is_empty() {
+        return Err(ParsingError::ConfigError {
+            file_name: config.data_path.clone(),
+            reason: "no data files match the data path".to_string(),
+        }
+        .into());
+    }
+    let data = if config.shards.is_some() {
+        ram_from_shards(config.data_dim, &data_paths)?.0
+    } else {
+        ram_from_f32_files(config.data_dim, &data_paths)?
+    };
+    let data = match &config.dim_names {
+        Some(names) => data.with_dim_names(names.clone())?,
+        None => data,
+    };
+    let data = match config.nan_policy {
+        Some(policy) => data.with_nan_policy(policy)?,
+        None => data,
+    };
+    match config.normalization {
+        Some(kind) => data.with_normalization(kind),
+        None => Ok(data),
+    }
+}
+
+/// Reads the typed labels of the config's [`LabelSchema`]. See [`LabelSchema::read`] for the
+/// point count.
+pub fn typed_labels_from_config(
+    config: &PointCloudConfig,
+    point_count: Option<usize>,
+) -> PointCloudResult<TypedLabels> {
+    match &config.labels {
+        Some(schema) => schema.read(&config.base_dir, point_count),
+        None => Err(ParsingError::MissingYamlError {
+            file_name: config.data_path.clone(),
+            field: "labels".to_string(),
+        }
+        .into()),
+    }
+}
+
+fn int_labels_from_config(
+    config: &PointCloudConfig,
+    point_count: Option<usize>,
+) -> PointCloudResult<SmallIntLabels> {
+    if config.labels.is_none() {
+        return labels_from_config(config);
+    }
+    typed_labels_from_config(config, point_count)?
+        .into_int_labels()
+        .ok_or_else(|| {
+            ParsingError::ConfigError {
+                file_name: config.data_path.clone(),
+                reason: "f32 labels can't be used as integer labels".to_string(),
+            }
+            .into()
+        })
+}
+
+/// Reads the labels the config points at. Typed labels have to be integers or strings, strings
+/// are given by the index of their name.
+pub fn labels_from_config(config: &PointCloudConfig) -> PointCloudResult<SmallIntLabels> {
+    if config.labels.is_some() {
+        return int_labels_from_config(config, None);
+    }
+    let labels_paths = config.labels_paths()?;
+    if labels_paths.is_empty() {
+        return Err(ParsingError::MissingYamlError {
+            file_name: config.labels_path.clone().unwrap_or_default(),
+            field: "labels_path".to_string(),
+        }
+        .into());
+    }
+    if config.labels_shards.is_some() {
+        return Ok(labels_from_shards(&labels_paths, config.labels_index, config.labels_dim)?.0);
+    }
+    labels_from_files(&labels_paths, config.labels_index, config.labels_dim)
+}
+
+/// Reads the labels the config points at as categories. String labels keep their names, other
+/// integer labels have none.
+pub fn categorical_labels_from_config(
+    config: &PointCloudConfig,
+    point_count: Option<usize>,
+) -> PointCloudResult<CategoricalLabels> {
+    if config.labels.is_none() {
+        return Ok(labels_from_config(config)?.into());
+    }
+    typed_labels_from_config(config, point_count)?
+        .into_categorical_labels()
+        .ok_or_else(|| {
+            ParsingError::ConfigError {
+                file_name: config.data_path.clone(),
+                reason: "f32 labels can't be used as categories".to_string(),
+            }
+            .into()
+        })
+}
+
+/// Reads sharded data and labels, and where each shard ended up. Both need shards, and the
+/// data and label shards have to line up.
+pub fn sharded_labeled_ram_from_config<M: Metric<[f32]> + Default>(
+    config: &PointCloudConfig,
+) -> PointCloudResult<(DefaultLabeledCloud<M>, ShardMap)> {
+    if config.shards.is_none() || config.labels_shards.is_none() {
+        return Err(ParsingError::ConfigError {
+            file_name: config.data_path.clone(),
+            reason: "both shards and labels_shards have to be set".to_string(),
+        }
+        .into());
+    }
+    labeled_ram_from_shards(
+        config.data_dim,
+        &config.data_paths()?,
+        &config.labels_paths()?,
+        config.labels_index,
+        config.labels_dim,
+    )
+}
+
+/// Reads the data and labels the config points at.
+pub fn labeled_ram_from_config<M: Metric<[f32]> + Default>(
+    config: &PointCloudConfig,
+) -> PointCloudResult<DefaultLabeledCloud<M>> {
+    let mut data_set = unlabeled_ram_from_config(config)?;
+    let label_set = int_labels_from_config(config, Some(data_set.len()))?;
+    collapse_labeled(config, &mut data_set, &label_set, |l| *l);
+    Ok(SimpleLabeledCloud::new(data_set, label_set))
+}
+
+/// Reads the data and labels the config points at, with the names of string labels.
+pub fn categorical_labeled_ram_from_config<M: Metric<[f32]> + Default>(
+    config: &PointCloudConfig,
+) -> PointCloudResult<DefaultCategoricalCloud<M>> {
+    let mut data_set = unlabeled_ram_from_config(config)?;
+    let label_set = categorical_labels_from_config(config, Some(data_set.len()))?;
+    collapse_labeled(config, &mut data_set, &label_set, |l| *l);
+    Ok(SimpleLabeledCloud::new(data_set, label_set))
+}
+
+/// Reads the data and vector labels the config points at, like regression targets. The labels
+/// are either `labels_dim` floats per point in the labels files, or the `f32` labels of the
+/// config's [`LabelSchema`].
+pub fn vec_labeled_ram_from_config<M: Metric<[f32]> + Default>(
+    config: &PointCloudConfig,
+) -> PointCloudResult<SimpleLabeledCloud<DataRam<M>, VecLabels>> {
+    let mut data_set = unlabeled_ram_from_config(config)?;
+    let label_set = if config.labels.is_some() {
+        match typed_labels_from_config(config, Some(data_set.len()))? {
+            TypedLabels::Float(labels) => labels,
+            _ => {
+                return Err(ParsingError::ConfigError {
+                    file_name: config.data_path.clone(),
+                    reason: "vector labels have to be f32".to_string(),
+                }
+                .into())
+            }
+        }
+    } else {
+        let labels_dim = config
+            .labels_dim
+            .ok_or_else(|| ParsingError::MissingYamlError {
+                file_name: config.data_path.clone(),
+                field: "labels_dim".to_string(),
+            })?;
+        ram_from_f32_files::<L2>(labels_dim, &config.labels_paths()?)?.convert_to_labels()
+    };
+    if label_set.len() != data_set.len() {
+        return Err(ParsingError::ConfigError {
+            file_name: config.data_path.clone(),
+            reason: format!(
+                "there are {} labels for {} points",
+                label_set.len(),
+                data_set.len()
+            ),
+        }
+        .into());
+    }
+    collapse_labeled(config, &mut data_set, &label_set, f32_label_key);
+    Ok(SimpleLabeledCloud::new(data_set, label_set))
+}
+
+/// Reads the data and the typed labels of the config's [`LabelSchema`], which have to cover
+/// every point.
+pub fn typed_labeled_ram_from_config<M: Metric<[f32]> + Default>(
+    config: &PointCloudConfig,
+) -> PointCloudResult<(DataRam<M>, TypedLabels)> {
+    let mut data_set = unlabeled_ram_from_config(config)?;
+    let label_set = typed_labels_from_config(config, Some(data_set.len()))?;
+    match &label_set {
+        TypedLabels::Int(labels) => collapse_labeled(config, &mut data_set, labels, |l| *l),
+        TypedLabels::String(labels) => collapse_labeled(config, &mut data_set, labels, |l| *l),
+        TypedLabels::Float(labels) => {
+            collapse_labeled(config, &mut data_set, labels, f32_label_key)
+        }
+    }
+    Ok((data_set, label_set))
+}
+
+/// Reads the data and labels the config points at, measuring distances with the config's
+/// weighted metric.
+pub fn weighted_labeled_ram_from_config(
+    config: &PointCloudConfig,
+) -> PointCloudResult<DefaultLabeledCloud<WeightedL2>> {
+    let metric = config.weighted_l2()?;
+    let mut data_set = unlabeled_ram_from_config::<L2>(config)?;
+    let label_set = int_labels_from_config(config, Some(data_set.len()))?;
+    collapse_labeled(config, &mut data_set, &label_set, |l| *l);
+    if !metric.weights().is_empty() && metric.weights().len() != data_set.dim() {
+        return Err(PointCloudError::MetricParameterError {
+            message: "the number of metric weights must match the data dimension",
+        });
+    }
+    Ok(SimpleLabeledCloud::new(
+        data_set.replace_metric(metric),
+        label_set,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    fn write_floats(path: &Path, data: &[f32]) {
+        let bytes: Vec<u8> = data.iter().flat_map(|x| x.to_le_bytes().to_vec()).collect();
+        fs::write(path, bytes).unwrap();
+    }
+
+    #[test]
+    fn formats_share_a_schema() {
+        let json = r#"{"data_path": "a.dat", "data_dim": 3, "labels_path": "a.csv", "labels_index": 1, "scale_base": 1.3}"#;
+        let toml =
+            "data_path = \"a.dat\"\ndata_dim = 3\nlabels_path = \"a.csv\"\nlabels_index = 1\n";
+        let yaml = "---\ndata_path: a.dat\ndata_dim: 3\nlabels_path: a.csv\nlabels_index: 1\n";
+        let from_json = PointCloudConfig::from_json_str(json).unwrap();
+        let from_toml = PointCloudConfig::from_toml_str(toml).unwrap();
+        let from_yaml: PointCloudConfig = ConfigFormat::Yaml.parse(yaml, "<yaml>").unwrap();
+        assert_eq!(from_json, from_toml);
+        assert_eq!(from_json, from_yaml);
+        assert_eq!(from_json.labels_index, Some(1));
+
+        assert!(PointCloudConfig::from_json_str(r#"{"data_dim": 3}"#).is_err());
+        assert!(PointCloudConfig::from_toml_str("data_path = 3").is_err());
+    }
+
+    #[test]
+    fn loads_from_config_files() {
+        let dir = TempDir::new("config").unwrap();
+        write_floats(&dir.path().join("points.dat"), &[0.0, 1.0, 2.0, 3.0]);
+        fs::write(dir.path().join("labels.csv"), "id,label\n0,1\n1,2\n").unwrap();
+        fs::write(
+            dir.path().join("config.toml"),
+            "data_path = \"points.dat\"\ndata_dim = 2\nlabels_path = \"labels.csv\"\nlabels_index = 1\n",
+        )
+        .unwrap();
+
+        let config = PointCloudConfig::from_path(dir.path().join("config.toml")).unwrap();
+        let cloud = labeled_ram_from_config::<L2>(&config).unwrap();
+        assert_eq!(cloud.len(), 2);
+        assert_eq!(cloud.point(1).unwrap(), &[2.0, 3.0][..]);
+        assert_eq!(cloud.label(1).unwrap(), Some(&2));
+
+        // The same config built in code
+        let mut config = PointCloudConfig::new("points.dat", 2);
+        config.set_base_dir(dir.path());
+        assert_eq!(ram_from_config::<L2>(&config).unwrap().len(), 2);
+        assert!(labels_from_config(&config).is_err());
+
+        config.dim_names = Some(vec!["x".to_string(), "y".to_string()]);
+        let cloud = ram_from_config::<L2>(&config).unwrap();
+        assert_eq!(cloud.dim_name(0), Some("x"));
+        config.dim_names = Some(vec!["x".to_string()]);
+        assert!(ram_from_config::<L2>(&config).is_err());
+
+        config.dim_names = None;
+        config.normalization = Some(Normalization::MinMax);
+        let cloud = ram_from_config::<L2>(&config).unwrap();
+        assert_eq!(cloud.point(1).unwrap(), &[1.0, 1.0][..]);
+
+        config.nan_policy = Some(NanPolicy::Reject);
+        assert!(ram_from_config::<L2>(&config).is_ok());
+
+        config.normalization = None;
+        write_floats(&dir.path().join("points.dat"), &[1.0, 1.0, 1.0, 1.0]);
+        config.collapse_duplicates = true;
+        let cloud = ram_from_config::<L2>(&config).unwrap();
+        assert_eq!(cloud.reference_indexes(), vec![0]);
+        assert_eq!(cloud.multiplicity(0), 2);
+        // The labels differ
+        config.labels_path = Some("labels.csv".to_string());
+        config.labels_index = Some(1);
+        let cloud = labeled_ram_from_config::<L2>(&config).unwrap();
+        assert_eq!(cloud.reference_indexes(), vec![0, 1]);
+    }
+
+    #[test]
+    fn loads_typed_labels() {
+        let dir = TempDir::new("config").unwrap();
+        write_floats(
+            &dir.path().join("points.dat"),
+            &[0.0, 1.0, 2.0, 3.0, 4.0, 5.0],
+        );
+        fs::write(
+            dir.path().join("labels.csv"),
+            "point,kind,weight
+2,b,0.25
+0,a,0.5
+",
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("config.toml"),
+            "data_path = \"points.dat\"\ndata_dim = 2\n\n[labels]\npath = \"labels.csv\"\ntype = \"string\"\ncolumn = \"kind\"\nid_column = \"point\"\n",
+        )
+        .unwrap();
+
+        let mut config = PointCloudConfig::from_path(dir.path().join("config.toml")).unwrap();
+        let cloud = labeled_ram_from_config::<L2>(&config).unwrap();
+        assert_eq!(cloud.label(0).unwrap(), Some(&1));
+        assert_eq!(cloud.label(1).unwrap(), None);
+        assert_eq!(cloud.label(2).unwrap(), Some(&0));
+        let cloud = categorical_labeled_ram_from_config::<L2>(&config).unwrap();
+        assert_eq!(cloud.label(0).unwrap(), Some(&1));
+        assert_eq!(cloud.label_name(&1), Some("a"));
+
+        let schema = config.labels.as_mut().unwrap();
+        schema.label_type = LabelType::F32;
+        schema.column = Some(CsvColumn::Index(2));
+        assert!(labeled_ram_from_config::<L2>(&config).is_err());
+        let (data, labels) = typed_labeled_ram_from_config::<L2>(&config).unwrap();
+        assert_eq!(data.len(), labels.len());
+        match labels {
+            TypedLabels::Float(labels) => assert_eq!(labels.label(2).unwrap(), Some(&[0.25][..])),
+            other => panic!("expected float labels, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn loads_vec_labels() {
+        let dir = TempDir::new("config").unwrap();
+        write_floats(&dir.path().join("points.dat"), &[0.0, 1.0, 2.0, 3.0]);
+        write_floats(&dir.path().join("targets.dat"), &[0.5, 1.5, 2.5, 3.5]);
+        let mut config = PointCloudConfig::new("points.dat", 2);
+        config.set_base_dir(dir.path());
+        config.labels_path = Some("targets.dat".to_string());
+        assert!(vec_labeled_ram_from_config::<L2>(&config).is_err());
+
+        config.labels_dim = Some(2);
+        let cloud = vec_labeled_ram_from_config::<L2>(&config).unwrap();
+        assert_eq!(cloud.label(1).unwrap(), Some(&[2.5, 3.5][..]));
+        let summary = cloud.label_summary(&[0, 1]).unwrap();
+        assert_eq!(summary.summary.mean(), vec![1.5, 2.5]);
+
+        config.labels_dim = Some(4);
+        assert!(vec_labeled_ram_from_config::<L2>(&config).is_err());
+    }
+}
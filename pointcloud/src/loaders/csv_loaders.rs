@@ -7,7 +7,7 @@ use std::path::Path;
 
 use crate::label_sources::*;
 
-/// Opens a CSV and reads a single column from it as a integer label. Negative labels are treated as unlabeled and are masked.
+/// Opens a CSV, which can be gzip or zstandard compressed, and reads a single column from it as a integer label. Negative labels are treated as unlabeled and are masked.
 pub fn open_int_csv<P: AsRef<Path> + std::fmt::Debug>(
     path: &P,
     index: usize,
@@ -20,6 +20,8 @@ pub fn open_int_csv<P: AsRef<Path> + std::fmt::Debug>(
         Ok(file) => {
             if path.as_ref().extension().unwrap() == "gz" {
                 read_csv(index, path, Reader::from_reader(GzDecoder::new(file)))
+            } else if path.as_ref().extension().unwrap() == "zst" {
+                read_csv(index, path, Reader::from_reader(zstd::Decoder::new(file)?))
             } else {
                 read_csv(index, path, Reader::from_reader(file))
             }
@@ -0,0 +1,590 @@
+//! Standard benchmark datasets, downloaded once and converted to plain `f32` point files with a
+//! yaml config. Needs the `datasets` feature, which turns on `remote`.
+//!
+//! [`prepare_dataset`] downloads a dataset into the remote cache, see [`fetch_cached`], and
+//! converts it into a directory of its own under [`datasets_dir`]. The points go to a little
+//! endian `f32` file, labels to a CSV and the held out queries and their ground truth, if the
+//! dataset has them, to a `f32` file and a `.ivecs` file. The yaml config next to them opens the
+//! points with [`ram_from_yaml`] or [`labeled_ram_from_yaml`], and builds a tree with goko's
+//! `cover_tree_from_yaml`. Later calls reuse the converted files.
+//!
+//! The SHA-256 of every download is recorded in `checksums.json` in [`datasets_dir`] the first
+//! time it's fetched, and checked against that record every time the dataset is prepared. A
+//! mismatch is an error, delete the cached download and the record to accept a new version.
+//!
+//! Each dataset's URL can be overridden with `POINTCLOUD_DATASET_URL_<NAME>`, where the name is
+//! upper cased with dashes turned into underscores, like `POINTCLOUD_DATASET_URL_GLOVE_100`. The
+//! SIFT archives are only served over FTP, which the downloader can't fetch, so point their
+//! variable at an HTTP mirror, or copy the archive to its [`remote_cache_path`].
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+
+use super::*;
+
+const CHECKSUMS_FILE: &str = "checksums.json";
+const PREPARED_FILE: &str = "prepared.json";
+
+/// The benchmark datasets [`prepare_dataset`] knows how to fetch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BenchmarkDataset {
+    /// The 10,000 point, 128 dimensional SIFT sample of the texmex corpus, with 100 queries and
+    /// their 100 nearest neighbors.
+    SiftSmall,
+    /// The 1,000,000 point, 128 dimensional SIFT set of the texmex corpus, with 10,000 queries
+    /// and their 100 nearest neighbors.
+    Sift,
+    /// The 400,000 word, 100 dimensional GloVe embeddings trained on Wikipedia and Gigaword.
+    /// Compare them with the cosine distance.
+    Glove100,
+    /// The 581,012 point, 54 dimensional UCI forest covertype data, labeled with one of 7 cover
+    /// types. The cover types are ordered by elevation, which makes it a common drift benchmark.
+    Covertype,
+}
+
+impl BenchmarkDataset {
+    /// Every known dataset.
+    pub const ALL: [BenchmarkDataset; 4] = [
+        BenchmarkDataset::SiftSmall,
+        BenchmarkDataset::Sift,
+        BenchmarkDataset::Glove100,
+        BenchmarkDataset::Covertype,
+    ];
+
+    /// The dataset's name, which is also the name of its directory.
+    pub fn name(&self) -> &'static str {
+        match self {
+            BenchmarkDataset::SiftSmall => "siftsmall",
+            BenchmarkDataset::Sift => "sift",
+            BenchmarkDataset::Glove100 => "glove-100",
+            BenchmarkDataset::Covertype => "covertype",
+        }
+    }
+
+    /// Looks a dataset up by its name.
+    pub fn from_name(name: &str) -> Option<BenchmarkDataset> {
+        BenchmarkDataset::ALL
+            .iter()
+            .copied()
+            .find(|d| d.name() == name.to_lowercase())
+    }
+
+    /// Where the dataset is downloaded from when there's no override.
+    pub fn default_url(&self) -> &'static str {
+        match self {
+            BenchmarkDataset::SiftSmall => {
+                "ftp://ftp.irisa.fr/local/texmex/corpus/siftsmall.tar.gz"
+            }
+            BenchmarkDataset::Sift => "ftp://ftp.irisa.fr/local/texmex/corpus/sift.tar.gz",
+            BenchmarkDataset::Glove100 => "https://nlp.stanford.edu/data/glove.6B.zip",
+            BenchmarkDataset::Covertype => {
+                "https://archive.ics.uci.edu/ml/machine-learning-databases/covtype/covtype.data.gz"
+            }
+        }
+    }
+
+    /// The environment variable that overrides the URL.
+    pub fn url_var(&self) -> String {
+        format!(
+            "POINTCLOUD_DATASET_URL_{}",
+            self.name().to_uppercase().replace('-', "_")
+        )
+    }
+
+    /// Where the dataset is downloaded from.
+    pub fn url(&self) -> String {
+        env::var(self.url_var()).unwrap_or_else(|_| self.default_url().to_string())
+    }
+}
+
+/// The files of a converted dataset.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PreparedDataset {
+    /// The yaml config of the points and labels
+    pub config: PathBuf,
+    /// The points, a little endian `f32` file
+    pub data: PathBuf,
+    /// The labels, a CSV with the label in column 1, if the dataset is labeled
+    pub labels: Option<PathBuf>,
+    /// The held out queries, a little endian `f32` file of the same dimension as the points
+    pub queries: Option<PathBuf>,
+    /// The indexes of each query's nearest neighbors among the points, closest first, see
+    /// [`ground_truth_from_ivecs`]
+    pub ground_truth: Option<PathBuf>,
+    /// The number of points
+    pub count: usize,
+    /// The dimension of the points
+    pub dim: usize,
+}
+
+/// The directory converted datasets are kept in, `datasets` in the [`remote_cache_dir`].
+pub fn datasets_dir() -> PathBuf {
+    remote_cache_dir().join("datasets")
+}
+
+fn dataset_error(dataset: BenchmarkDataset, reason: String) -> PointCloudError {
+    PointCloudError::ParsingError(ParsingError::DatasetError {
+        dataset: dataset.name().to_string(),
+        reason,
+    })
+}
+
+/// Downloads the dataset, checks its checksum and converts it, unless it's already converted.
+pub fn prepare_dataset(dataset: BenchmarkDataset) -> PointCloudResult<PreparedDataset> {
+    let dir = datasets_dir().join(dataset.name());
+    let prepared_path = dir.join(PREPARED_FILE);
+    if prepared_path.exists() {
+        let prepared = serde_json::from_reader(File::open(&prepared_path)?)
+            .map_err(|e| dataset_error(dataset, e.to_string()))?;
+        return Ok(prepared);
+    }
+    let url = dataset.url();
+    let archive = fetch_cached(&url)?;
+    verify_checksum(&url, &archive)?;
+
+    // Converts into a scratch directory that's renamed when it's complete, so a failed conversion
+    // doesn't leave a partial dataset behind.
+    let part_dir = datasets_dir().join(format!("{}.part", dataset.name()));
+    if part_dir.exists() {
+        fs::remove_dir_all(&part_dir)?;
+    }
+    fs::create_dir_all(&part_dir)?;
+    let converted = match dataset {
+        BenchmarkDataset::SiftSmall | BenchmarkDataset::Sift => {
+            convert_texmex(dataset, &archive, &part_dir)
+        }
+        BenchmarkDataset::Glove100 => convert_glove(dataset, &archive, &part_dir),
+        BenchmarkDataset::Covertype => convert_covertype(dataset, &archive, &part_dir),
+    };
+    let (count, dim, has_labels, has_queries) = match converted {
+        Ok(converted) => converted,
+        Err(e) => {
+            let _ = fs::remove_dir_all(&part_dir);
+            return Err(e);
+        }
+    };
+    let mut config = format!(
+        "---\ndata_path: data.dat\ncount: {}\ndata_dim: {}\n",
+        count, dim
+    );
+    if has_labels {
+        config.push_str("labels_path: labels.csv\nlabels_index: 1\n");
+    }
+    fs::write(part_dir.join("config.yml"), config)?;
+    if dir.exists() {
+        fs::remove_dir_all(&dir)?;
+    }
+    fs::rename(&part_dir, &dir)?;
+
+    let prepared = PreparedDataset {
+        config: dir.join("config.yml"),
+        data: dir.join("data.dat"),
+        labels: if has_labels {
+            Some(dir.join("labels.csv"))
+        } else {
+            None
+        },
+        queries: if has_queries {
+            Some(dir.join("queries.dat"))
+        } else {
+            None
+        },
+        ground_truth: if has_queries {
+            Some(dir.join("ground_truth.ivecs"))
+        } else {
+            None
+        },
+        count,
+        dim,
+    };
+    let prepared_file = File::create(&prepared_path)?;
+    serde_json::to_writer_pretty(prepared_file, &prepared)
+        .map_err(|e| dataset_error(dataset, e.to_string()))?;
+    Ok(prepared)
+}
+
+/// The hex SHA-256 of a file.
+pub fn sha256_file<P: AsRef<Path>>(path: P) -> PointCloudResult<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 1 << 16];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+/// Checks the download of a URL against the checksum recorded for it, and records it if there's
+/// none yet. Returns the checksum.
+pub fn verify_checksum(url: &str, path: &Path) -> PointCloudResult<String> {
+    let checksum = sha256_file(path)?;
+    let record_path = datasets_dir().join(CHECKSUMS_FILE);
+    let mut record: BTreeMap<String, String> = if record_path.exists() {
+        serde_json::from_reader(File::open(&record_path)?).map_err(|e| {
+            PointCloudError::RemoteError {
+                url: url.to_string(),
+                reason: format!("unable to read {}: {}", record_path.to_string_lossy(), e),
+            }
+        })?
+    } else {
+        BTreeMap::new()
+    };
+    match record.get(url) {
+        Some(expected) if *expected != checksum => Err(PointCloudError::RemoteError {
+            url: url.to_string(),
+            reason: format!(
+                "the download at {} has checksum {} but {} was recorded, delete the download and its entry in {} to accept it",
+                path.to_string_lossy(),
+                checksum,
+                expected,
+                record_path.to_string_lossy()
+            ),
+        }),
+        Some(_) => Ok(checksum),
+        None => {
+            record.insert(url.to_string(), checksum.clone());
+            fs::create_dir_all(datasets_dir())?;
+            serde_json::to_writer_pretty(File::create(&record_path)?, &record).map_err(|e| {
+                PointCloudError::RemoteError {
+                    url: url.to_string(),
+                    reason: e.to_string(),
+                }
+            })?;
+            Ok(checksum)
+        }
+    }
+}
+
+/// Converts a texmex `.tar.gz` of `_base.fvecs`, `_query.fvecs` and `_groundtruth.ivecs` files.
+fn convert_texmex(
+    dataset: BenchmarkDataset,
+    archive: &Path,
+    dir: &Path,
+) -> PointCloudResult<(usize, usize, bool, bool)> {
+    let mut base = None;
+    let mut queries = None;
+    let mut ground_truth = false;
+    for_each_tar_entry(open_decompressed(archive)?, |name, entry| {
+        if name.ends_with("_base.fvecs") {
+            base = Some(fvecs_to_f32_file(entry, name, &dir.join("data.dat"))?);
+        } else if name.ends_with("_query.fvecs") {
+            queries = Some(fvecs_to_f32_file(entry, name, &dir.join("queries.dat"))?);
+        } else if name.ends_with("_groundtruth.ivecs") {
+            io::copy(entry, &mut File::create(dir.join("ground_truth.ivecs"))?)?;
+            ground_truth = true;
+        }
+        Ok(())
+    })?;
+    let (count, dim) =
+        base.ok_or_else(|| dataset_error(dataset, "the archive has no base vectors".to_string()))?;
+    let has_queries = match queries {
+        Some((_, query_dim)) if query_dim != dim => {
+            return Err(dataset_error(
+                dataset,
+                format!("the queries have dimension {}, not {}", query_dim, dim),
+            ))
+        }
+        Some(_) => ground_truth,
+        None => false,
+    };
+    Ok((count, dim, false, has_queries))
+}
+
+/// Converts the 100 dimensional text file in the GloVe zip, a word and its vector on each line.
+fn convert_glove(
+    dataset: BenchmarkDataset,
+    archive: &Path,
+    dir: &Path,
+) -> PointCloudResult<(usize, usize, bool, bool)> {
+    let mut zip = zip::ZipArchive::new(File::open(archive)?)
+        .map_err(|e| dataset_error(dataset, e.to_string()))?;
+    let entry = zip
+        .by_name("glove.6B.100d.txt")
+        .map_err(|e| dataset_error(dataset, e.to_string()))?;
+    let (count, dim) =
+        text_vectors_to_f32_file(dataset, BufReader::new(entry), &dir.join("data.dat"))?;
+    Ok((count, dim, false, false))
+}
+
+/// Converts the gzipped covertype CSV, 54 features and the cover type on each line.
+fn convert_covertype(
+    dataset: BenchmarkDataset,
+    archive: &Path,
+    dir: &Path,
+) -> PointCloudResult<(usize, usize, bool, bool)> {
+    let reader = BufReader::new(open_decompressed(archive)?);
+    let (count, dim) = labeled_csv_to_files(
+        dataset,
+        reader,
+        &dir.join("data.dat"),
+        &dir.join("labels.csv"),
+    )?;
+    Ok((count, dim, true, false))
+}
+
+/// Calls `f` with the name and contents of every regular file in a tar stream.
+fn for_each_tar_entry<R: Read, F: FnMut(&str, &mut dyn Read) -> PointCloudResult<()>>(
+    mut reader: R,
+    mut f: F,
+) -> PointCloudResult<()> {
+    let mut header = [0u8; 512];
+    let mut long_name: Option<String> = None;
+    loop {
+        if let Err(e) = reader.read_exact(&mut header) {
+            // Some writers leave out the closing zero blocks
+            if e.kind() == io::ErrorKind::UnexpectedEof {
+                return Ok(());
+            }
+            return Err(e.into());
+        }
+        if header.iter().all(|b| *b == 0) {
+            return Ok(());
+        }
+        let field = |bytes: &[u8]| {
+            let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+            String::from_utf8_lossy(&bytes[..end]).to_string()
+        };
+        let size_field = field(&header[124..136]);
+        let size = u64::from_str_radix(size_field.trim(), 8)
+            .map_err(|_| ParsingError::RegularParsingError("a tar header has a malformed size"))?;
+        let mut name = field(&header[..100]);
+        if &header[257..262] == b"ustar" {
+            let prefix = field(&header[345..500]);
+            if !prefix.is_empty() {
+                name = format!("{}/{}", prefix, name);
+            }
+        }
+        if let Some(long) = long_name.take() {
+            name = long;
+        }
+        let mut entry = (&mut reader).take(size);
+        match header[156] {
+            b'0' | 0 => f(&name, &mut entry)?,
+            // A GNU long name, the name of the next entry
+            b'L' => {
+                let mut long = Vec::new();
+                entry.read_to_end(&mut long)?;
+                long_name = Some(field(&long));
+            }
+            _ => {}
+        }
+        io::copy(&mut entry, &mut io::sink())?;
+        let padding = (512 - size % 512) % 512;
+        io::copy(&mut (&mut reader).take(padding), &mut io::sink())?;
+    }
+}
+
+/// Strips the dimension off each vector of a `.fvecs` stream, returns the count and dimension.
+fn fvecs_to_f32_file<R: Read>(
+    mut reader: R,
+    file_name: &str,
+    path: &Path,
+) -> PointCloudResult<(usize, usize)> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    let mut file_dim = None;
+    let mut count = 0;
+    let mut buffer = Vec::new();
+    while let Some(dim) = read_vec_dim(&mut reader, file_name)? {
+        if *file_dim.get_or_insert(dim) != dim {
+            return Err(PointCloudError::ParsingError(ParsingError::VecsError {
+                file_name: file_name.to_string(),
+                reason: format!("vector {} has dimension {}", count, dim),
+            }));
+        }
+        buffer.resize(4 * dim, 0);
+        reader.read_exact(&mut buffer)?;
+        writer.write_all(&buffer)?;
+        count += 1;
+    }
+    writer.flush()?;
+    Ok((count, file_dim.unwrap_or(0)))
+}
+
+/// Writes the vectors of lines of a token followed by its values, returns the count and dimension.
+fn text_vectors_to_f32_file<R: BufRead>(
+    dataset: BenchmarkDataset,
+    reader: R,
+    path: &Path,
+) -> PointCloudResult<(usize, usize)> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    let mut file_dim = None;
+    let mut count = 0;
+    for line in reader.lines() {
+        let line = line?;
+        let values = line
+            .split(' ')
+            .skip(1)
+            .map(|v| v.parse::<f32>())
+            .collect::<Result<Vec<f32>, _>>()
+            .map_err(|e| dataset_error(dataset, format!("line {}: {}", count + 1, e)))?;
+        if *file_dim.get_or_insert(values.len()) != values.len() {
+            return Err(dataset_error(
+                dataset,
+                format!("line {} has {} values", count + 1, values.len()),
+            ));
+        }
+        for v in values {
+            writer.write_all(&v.to_le_bytes())?;
+        }
+        count += 1;
+    }
+    writer.flush()?;
+    Ok((count, file_dim.unwrap_or(0)))
+}
+
+/// Writes the values of header-less CSV lines whose last column is an integer label, returns the
+/// count and dimension.
+fn labeled_csv_to_files<R: BufRead>(
+    dataset: BenchmarkDataset,
+    reader: R,
+    data_path: &Path,
+    labels_path: &Path,
+) -> PointCloudResult<(usize, usize)> {
+    let mut data = BufWriter::new(File::create(data_path)?);
+    let mut labels = BufWriter::new(File::create(labels_path)?);
+    writeln!(labels, "index,label")?;
+    let mut file_dim = None;
+    let mut count = 0;
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.trim().split(',').collect();
+        let (label, values) = fields.split_last().unwrap();
+        let label = label
+            .parse::<i64>()
+            .map_err(|e| dataset_error(dataset, format!("line {}: {}", count + 1, e)))?;
+        if *file_dim.get_or_insert(values.len()) != values.len() {
+            return Err(dataset_error(
+                dataset,
+                format!("line {} has {} values", count + 1, values.len()),
+            ));
+        }
+        for v in values {
+            let v = v
+                .parse::<f32>()
+                .map_err(|e| dataset_error(dataset, format!("line {}: {}", count + 1, e)))?;
+            data.write_all(&v.to_le_bytes())?;
+        }
+        writeln!(labels, "{},{}", count, label)?;
+        count += 1;
+    }
+    data.flush()?;
+    labels.flush()?;
+    Ok((count, file_dim.unwrap_or(0)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::L2;
+    use tempdir::TempDir;
+
+    fn tar_entry(name: &str, contents: &[u8]) -> Vec<u8> {
+        let mut header = [0u8; 512];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        let size = format!("{:011o}\0", contents.len());
+        header[124..136].copy_from_slice(size.as_bytes());
+        header[156] = b'0';
+        let mut entry = header.to_vec();
+        entry.extend_from_slice(contents);
+        entry.resize(entry.len() + (512 - contents.len() % 512) % 512, 0);
+        entry
+    }
+
+    #[test]
+    fn reads_tar_entries() {
+        let mut tar = tar_entry("a/one.txt", b"hello");
+        tar.extend(tar_entry("a/two.txt", &[7u8; 600]));
+        tar.extend_from_slice(&[0u8; 1024]);
+        let mut seen = Vec::new();
+        for_each_tar_entry(&tar[..], |name, entry| {
+            let mut contents = Vec::new();
+            entry.read_to_end(&mut contents)?;
+            seen.push((name.to_string(), contents.len()));
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(
+            seen,
+            vec![("a/one.txt".to_string(), 5), ("a/two.txt".to_string(), 600)]
+        );
+    }
+
+    #[test]
+    fn converts_fvecs() {
+        let dir = TempDir::new("datasets").unwrap();
+        let mut fvecs = Vec::new();
+        for v in &[[1.0f32, 2.0], [3.0, 4.0], [5.0, 6.0]] {
+            fvecs.extend_from_slice(&2i32.to_le_bytes());
+            for x in v {
+                fvecs.extend_from_slice(&x.to_le_bytes());
+            }
+        }
+        let path = dir.path().join("data.dat");
+        let (count, dim) = fvecs_to_f32_file(&fvecs[..], "test.fvecs", &path).unwrap();
+        assert_eq!((count, dim), (3, 2));
+        let data = ram_from_f32_file::<_, L2>(&path, 2).unwrap();
+        assert_eq!(&*data.point(2).unwrap(), &[5.0, 6.0][..]);
+    }
+
+    #[test]
+    fn converts_text_vectors() {
+        let dir = TempDir::new("datasets").unwrap();
+        let path = dir.path().join("data.dat");
+        let text = "the 0.5 -1.0 2.0\nof 1.0 1.0 1.0\n";
+        let (count, dim) =
+            text_vectors_to_f32_file(BenchmarkDataset::Glove100, text.as_bytes(), &path).unwrap();
+        assert_eq!((count, dim), (2, 3));
+        let data = ram_from_f32_file::<_, L2>(&path, 3).unwrap();
+        assert_eq!(&*data.point(0).unwrap(), &[0.5, -1.0, 2.0][..]);
+        assert!(text_vectors_to_f32_file(
+            BenchmarkDataset::Glove100,
+            "a 1 2\nb 1\n".as_bytes(),
+            &path
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn converts_labeled_csv() {
+        let dir = TempDir::new("datasets").unwrap();
+        let data_path = dir.path().join("data.dat");
+        let labels_path = dir.path().join("labels.csv");
+        let csv = "1,2,5\n3,4,2\n";
+        let (count, dim) = labeled_csv_to_files(
+            BenchmarkDataset::Covertype,
+            csv.as_bytes(),
+            &data_path,
+            &labels_path,
+        )
+        .unwrap();
+        assert_eq!((count, dim), (2, 2));
+        let labels = open_int_csv(&labels_path, 1).unwrap();
+        assert_eq!(labels.label(1).unwrap(), Some(&2));
+    }
+
+    #[test]
+    fn names_round_trip() {
+        for dataset in &BenchmarkDataset::ALL {
+            assert_eq!(BenchmarkDataset::from_name(dataset.name()), Some(*dataset));
+        }
+        assert_eq!(
+            BenchmarkDataset::Glove100.url_var(),
+            "POINTCLOUD_DATASET_URL_GLOVE_100"
+        );
+    }
+}
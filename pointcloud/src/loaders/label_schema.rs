@@ -0,0 +1,493 @@
+//! Labels in their own file, with a declared type. The labels can be a column of a CSV or a
+//! `.npy` array, and are `u64`, `i64`, `string` or `f32`. They're matched to the points by row
+//! order, or by an id column of the CSV that holds each label's point index. With an id column the
+//! labels can be in any order and points without a row are unlabeled.
+//! ```yaml
+//! labels:
+//!   path: labels.csv
+//!   type: string
+//!   column: category
+//!   id_column: point
+//! ```
+//! CSVs need a header row, columns can be given by name or index. Empty cells are unlabeled. The
+//! files can be compressed, see [`open_decompressed`].
+
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+
+use super::npy_loaders::{numpy_error, read_npy};
+use super::yaml_loaders::get_file_list;
+use super::*;
+
+/// The type the labels in a label file are declared as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LabelType {
+    /// Unsigned integers, they have to fit in an `i64`
+    U64,
+    /// Signed integers
+    I64,
+    /// Strings, each distinct string is given an integer
+    String,
+    /// Floats
+    F32,
+}
+
+/// A column of a CSV, by its name in the header or its index.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum CsvColumn {
+    /// The column's index, counting from 0
+    Index(usize),
+    /// The column's name in the header
+    Name(String),
+}
+
+/// Where a point cloud's labels are, their type and how they line up with the points.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LabelSchema {
+    /// The CSV or `.npy` file, relative to the config
+    pub path: String,
+    /// The type of the labels
+    #[serde(rename = "type")]
+    pub label_type: LabelType,
+    /// The column of the labels in a CSV. Defaults to the first column that isn't the id column.
+    #[serde(default)]
+    pub column: Option<CsvColumn>,
+    /// The column of a CSV holding the index of the point each label is for. Without it the
+    /// labels are matched to the points by row order.
+    #[serde(default)]
+    pub id_column: Option<CsvColumn>,
+}
+
+/// Labels read with a [`LabelSchema`].
+#[derive(Debug)]
+pub enum TypedLabels {
+    /// `u64` or `i64` labels
+    Int(SmallIntLabels),
+    /// String labels, each distinct string is a category, coded in the order they first appear
+    String(CategoricalLabels),
+    /// `f32` labels, 1 dimensional
+    Float(VecLabels),
+}
+
+impl TypedLabels {
+    /// The number of points the labels cover.
+    pub fn len(&self) -> usize {
+        match self {
+            TypedLabels::Int(labels) => labels.len(),
+            TypedLabels::String(labels) => labels.len(),
+            TypedLabels::Float(labels) => labels.len(),
+        }
+    }
+
+    /// If there are no labels.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The integer labels, the indexes of the names for string labels. `None` for float labels.
+    pub fn into_int_labels(self) -> Option<SmallIntLabels> {
+        match self {
+            TypedLabels::Int(labels) => Some(labels),
+            TypedLabels::String(labels) => Some(labels.into_codes()),
+            TypedLabels::Float(_) => None,
+        }
+    }
+
+    /// The labels as categories, named for string labels and unnamed for integer labels. `None`
+    /// for float labels.
+    pub fn into_categorical_labels(self) -> Option<CategoricalLabels> {
+        match self {
+            TypedLabels::Int(labels) => Some(labels.into()),
+            TypedLabels::String(labels) => Some(labels),
+            TypedLabels::Float(_) => None,
+        }
+    }
+}
+
+impl LabelSchema {
+    /// A schema for labels matched to the points by row order.
+    pub fn new<S: Into<String>>(path: S, label_type: LabelType) -> LabelSchema {
+        LabelSchema {
+            path: path.into(),
+            label_type,
+            column: None,
+            id_column: None,
+        }
+    }
+
+    /// Sets the column of the labels in a CSV.
+    pub fn set_column(&mut self, column: CsvColumn) -> &mut Self {
+        self.column = Some(column);
+        self
+    }
+
+    /// Sets the column holding each label's point index.
+    pub fn set_id_column(&mut self, id_column: CsvColumn) -> &mut Self {
+        self.id_column = Some(id_column);
+        self
+    }
+
+    /// Reads the labels, relative to `base_dir`. If `point_count` is given there has to be a row
+    /// for each point when matching by row order, and ids have to be less than it. Otherwise an id
+    /// column covers the points up to the largest id.
+    pub fn read(
+        &self,
+        base_dir: &Path,
+        point_count: Option<usize>,
+    ) -> PointCloudResult<TypedLabels> {
+        let paths = get_file_list(&self.path, base_dir)?;
+        let path = match paths.as_slice() {
+            [path] => path,
+            _ => {
+                return Err(self.config_error(format!(
+                    "the labels path has to match one file, it matches {}",
+                    paths.len()
+                )))
+            }
+        };
+        let file_name = path.to_string_lossy().to_string();
+        let (values, ids) = match content_extension(path) {
+            Some("npy") => {
+                if self.id_column.is_some() {
+                    return Err(self.config_error(
+                        "npy labels are matched by row order, they can't have an id column"
+                            .to_string(),
+                    ));
+                }
+                (self.read_npy(path, &file_name)?, None)
+            }
+            _ => self.read_csv(path, &file_name)?,
+        };
+        let values = place_labels(values, ids, point_count, &file_name)?;
+        Ok(values.into_typed())
+    }
+
+    fn config_error(&self, reason: String) -> PointCloudError {
+        ParsingError::ConfigError {
+            file_name: self.path.clone(),
+            reason,
+        }
+        .into()
+    }
+
+    fn read_npy(&self, path: &Path, file_name: &str) -> PointCloudResult<LabelColumn> {
+        let mut reader = open_decompressed(path)?;
+        let check_shape = |shape: &[usize]| {
+            if shape.len() > 2 || (shape.len() == 2 && shape[1] != 1) {
+                Err(numpy_error(
+                    file_name,
+                    format!("expected labels of shape (n,) or (n, 1), got {:?}", shape),
+                ))
+            } else {
+                Ok(())
+            }
+        };
+        match self.label_type {
+            LabelType::U64 | LabelType::I64 => {
+                let (shape, values) = read_npy::<_, i64>(&mut reader, file_name)?;
+                check_shape(&shape)?;
+                if self.label_type == LabelType::U64 && values.iter().any(|v| *v < 0) {
+                    return Err(numpy_error(
+                        file_name,
+                        "u64 labels have to fit in an i64".to_string(),
+                    ));
+                }
+                Ok(LabelColumn::Int(values.into_iter().map(Some).collect()))
+            }
+            LabelType::F32 => {
+                let (shape, values) = read_npy::<_, f32>(&mut reader, file_name)?;
+                check_shape(&shape)?;
+                Ok(LabelColumn::Float(values.into_iter().map(Some).collect()))
+            }
+            LabelType::String => Err(self.config_error(
+                "string labels have to be in a CSV, npy string arrays aren't supported".to_string(),
+            )),
+        }
+    }
+
+    fn read_csv(
+        &self,
+        path: &Path,
+        file_name: &str,
+    ) -> PointCloudResult<(LabelColumn, Option<Vec<usize>>)> {
+        let mut reader = csv::Reader::from_reader(open_decompressed(path)?);
+        let headers = reader
+            .headers()
+            .map_err(|e| csv_error(file_name, &e, "unable to read the header".to_string()))?
+            .clone();
+        let column_index = |column: &CsvColumn| match column {
+            CsvColumn::Index(i) if *i < headers.len() => Ok(*i),
+            CsvColumn::Name(name) => headers
+                .iter()
+                .position(|h| h == name)
+                .ok_or_else(|| self.config_error(format!("the CSV has no column named {}", name))),
+            CsvColumn::Index(i) => Err(self.config_error(format!(
+                "the CSV has {} columns, there's no column {}",
+                headers.len(),
+                i
+            ))),
+        };
+        let id_index = self
+            .id_column
+            .as_ref()
+            .map(|c| column_index(c))
+            .transpose()?;
+        let label_index = match &self.column {
+            Some(column) => column_index(column)?,
+            None => (0..headers.len())
+                .find(|i| Some(*i) != id_index)
+                .ok_or_else(|| self.config_error("the CSV has no label column".to_string()))?,
+        };
+
+        let mut values = LabelColumn::empty(self.label_type);
+        let mut ids = id_index.map(|_| Vec::new());
+        for record in reader.records() {
+            let record = record.map_err(|e| csv_error(file_name, &e, e.to_string()))?;
+            let line = record.position().map(|p| p.line() as usize).unwrap_or(0);
+            let cell_error = |key: String| {
+                PointCloudError::from(ParsingError::CSVReadError {
+                    file_name: file_name.to_string(),
+                    line_number: line,
+                    key,
+                })
+            };
+            if let (Some(ids), Some(id_index)) = (ids.as_mut(), id_index) {
+                let id = record.get(id_index).unwrap_or("").trim();
+                ids.push(
+                    id.parse::<usize>()
+                        .map_err(|_| cell_error(format!("{:?} isn't a point index", id)))?,
+                );
+            }
+            let cell = record.get(label_index).unwrap_or("").trim();
+            values.push_str(cell).map_err(|_| {
+                cell_error(format!("{:?} isn't a {:?} label", cell, self.label_type))
+            })?;
+        }
+        Ok((values, ids))
+    }
+}
+
+fn csv_error(file_name: &str, err: &csv::Error, key: String) -> PointCloudError {
+    ParsingError::CSVReadError {
+        file_name: file_name.to_string(),
+        line_number: err.position().map(|p| p.line() as usize).unwrap_or(0),
+        key,
+    }
+    .into()
+}
+
+/// A column of labels as they're read, `None` for the unlabeled points.
+enum LabelColumn {
+    Int(Vec<Option<i64>>),
+    Unsigned(Vec<Option<i64>>),
+    Float(Vec<Option<f32>>),
+    String(Vec<Option<String>>),
+}
+
+impl LabelColumn {
+    fn empty(label_type: LabelType) -> LabelColumn {
+        match label_type {
+            LabelType::U64 => LabelColumn::Unsigned(Vec::new()),
+            LabelType::I64 => LabelColumn::Int(Vec::new()),
+            LabelType::F32 => LabelColumn::Float(Vec::new()),
+            LabelType::String => LabelColumn::String(Vec::new()),
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            LabelColumn::Int(v) | LabelColumn::Unsigned(v) => v.len(),
+            LabelColumn::Float(v) => v.len(),
+            LabelColumn::String(v) => v.len(),
+        }
+    }
+
+    /// Parses a CSV cell onto the end of the column. Empty cells are unlabeled.
+    fn push_str(&mut self, cell: &str) -> Result<(), ()> {
+        if cell.is_empty() {
+            match self {
+                LabelColumn::Int(v) | LabelColumn::Unsigned(v) => v.push(None),
+                LabelColumn::Float(v) => v.push(None),
+                LabelColumn::String(v) => v.push(None),
+            }
+            return Ok(());
+        }
+        match self {
+            LabelColumn::Int(v) => v.push(Some(cell.parse().map_err(|_| ())?)),
+            LabelColumn::Unsigned(v) => {
+                let label: u64 = cell.parse().map_err(|_| ())?;
+                v.push(Some(i64::try_from(label).map_err(|_| ())?));
+            }
+            LabelColumn::Float(v) => v.push(Some(cell.parse().map_err(|_| ())?)),
+            LabelColumn::String(v) => v.push(Some(cell.to_string())),
+        }
+        Ok(())
+    }
+
+    fn into_typed(self) -> TypedLabels {
+        match self {
+            LabelColumn::Int(v) | LabelColumn::Unsigned(v) => {
+                let (labels, mask) = unzip_labels(v, 0);
+                TypedLabels::Int(SmallIntLabels::new(labels, mask))
+            }
+            LabelColumn::Float(v) => {
+                let (labels, mask) = unzip_labels(v, 0.0);
+                TypedLabels::Float(VecLabels::new(labels, 1, mask))
+            }
+            LabelColumn::String(v) => TypedLabels::String(CategoricalLabels::from_names(&v)),
+        }
+    }
+}
+
+/// Splits optional labels into the labels and a mask, with no mask if every point is labeled.
+fn unzip_labels<T: Copy>(values: Vec<Option<T>>, fill: T) -> (Vec<T>, Option<Vec<bool>>) {
+    let mask: Vec<bool> = values.iter().map(|v| v.is_some()).collect();
+    let labels = values.into_iter().map(|v| v.unwrap_or(fill)).collect();
+    if mask.iter().all(|m| *m) {
+        (labels, None)
+    } else {
+        (labels, Some(mask))
+    }
+}
+
+/// Puts each label at its point's index, or checks the count when they're in row order.
+fn place_labels(
+    values: LabelColumn,
+    ids: Option<Vec<usize>>,
+    point_count: Option<usize>,
+    file_name: &str,
+) -> PointCloudResult<LabelColumn> {
+    let config_error = |reason: String| -> PointCloudError {
+        ParsingError::ConfigError {
+            file_name: file_name.to_string(),
+            reason,
+        }
+        .into()
+    };
+    let ids = match ids {
+        None => {
+            return match point_count {
+                Some(count) if count != values.len() => Err(config_error(format!(
+                    "there are {} labels for {} points",
+                    values.len(),
+                    count
+                ))),
+                _ => Ok(values),
+            }
+        }
+        Some(ids) => ids,
+    };
+    let len = match point_count {
+        Some(count) => count,
+        None => ids.iter().max().map(|m| m + 1).unwrap_or(0),
+    };
+    let mut seen = vec![false; len];
+    for id in &ids {
+        if *id >= len {
+            return Err(config_error(format!(
+                "the id {} is past the last of the {} points",
+                id, len
+            )));
+        }
+        if seen[*id] {
+            return Err(config_error(format!(
+                "the id {} has more than one label",
+                id
+            )));
+        }
+        seen[*id] = true;
+    }
+    Ok(match values {
+        LabelColumn::Int(v) => LabelColumn::Int(scatter(v, &ids, len)),
+        LabelColumn::Unsigned(v) => LabelColumn::Unsigned(scatter(v, &ids, len)),
+        LabelColumn::Float(v) => LabelColumn::Float(scatter(v, &ids, len)),
+        LabelColumn::String(v) => LabelColumn::String(scatter(v, &ids, len)),
+    })
+}
+
+fn scatter<T>(values: Vec<Option<T>>, ids: &[usize], len: usize) -> Vec<Option<T>> {
+    let mut placed: Vec<Option<T>> = (0..len).map(|_| None).collect();
+    for (value, id) in values.into_iter().zip(ids) {
+        placed[*id] = value;
+    }
+    placed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempdir::TempDir;
+
+    #[test]
+    fn reads_typed_csv_columns() {
+        let dir = TempDir::new("label_schema").unwrap();
+        fs::write(
+            dir.path().join("labels.csv"),
+            "point,category,score,count\n2,cat,0.5,7\n0,dog,1.5,\n3,cat,,18446744073709551615\n",
+        )
+        .unwrap();
+
+        let mut schema = LabelSchema::new("labels.csv", LabelType::String);
+        schema
+            .set_column(CsvColumn::Name("category".to_string()))
+            .set_id_column(CsvColumn::Name("point".to_string()));
+        match schema.read(dir.path(), Some(5)).unwrap() {
+            TypedLabels::String(labels) => {
+                assert_eq!(labels.names(), &["cat".to_string(), "dog".to_string()][..]);
+                assert_eq!(labels.label_name(&1), Some("dog"));
+                assert_eq!(labels.len(), 5);
+                assert_eq!(labels.label(0).unwrap(), Some(&1));
+                assert_eq!(labels.label(1).unwrap(), None);
+                assert_eq!(labels.label(2).unwrap(), Some(&0));
+                assert_eq!(labels.label(3).unwrap(), Some(&0));
+            }
+            other => panic!("expected string labels, got {:?}", other),
+        }
+
+        // In row order, the empty cell is unlabeled
+        let mut schema = LabelSchema::new("labels.csv", LabelType::F32);
+        schema.set_column(CsvColumn::Index(2));
+        match schema.read(dir.path(), Some(3)).unwrap() {
+            TypedLabels::Float(labels) => {
+                assert_eq!(labels.label(1).unwrap(), Some(&[1.5][..]));
+                assert_eq!(labels.label(2).unwrap(), None);
+            }
+            other => panic!("expected float labels, got {:?}", other),
+        }
+        assert!(schema.read(dir.path(), Some(4)).is_err());
+
+        // The last count doesn't fit in an i64
+        let mut schema = LabelSchema::new("labels.csv", LabelType::U64);
+        schema.set_column(CsvColumn::Name("count".to_string()));
+        assert!(schema.read(dir.path(), None).is_err());
+
+        let mut schema = LabelSchema::new("labels.csv", LabelType::I64);
+        schema.set_column(CsvColumn::Name("missing".to_string()));
+        assert!(schema.read(dir.path(), None).is_err());
+    }
+
+    #[test]
+    fn bad_ids_are_errors() {
+        let dir = TempDir::new("label_schema").unwrap();
+        fs::write(dir.path().join("dupes.csv"), "id,label\n0,1\n0,2\n").unwrap();
+        fs::write(dir.path().join("past.csv"), "id,label\n0,1\n5,2\n").unwrap();
+        for file in &["dupes.csv", "past.csv"] {
+            let mut schema = LabelSchema::new(*file, LabelType::I64);
+            schema.set_id_column(CsvColumn::Index(0));
+            assert!(schema.read(dir.path(), Some(3)).is_err());
+        }
+        // Without a point count the ids set the length
+        let mut schema = LabelSchema::new("past.csv", LabelType::I64);
+        schema.set_id_column(CsvColumn::Index(0));
+        let labels = schema
+            .read(dir.path(), None)
+            .unwrap()
+            .into_int_labels()
+            .unwrap();
+        assert_eq!(labels.len(), 6);
+        assert_eq!(labels.label(5).unwrap(), Some(&2));
+    }
+}
@@ -0,0 +1,228 @@
+//! Loaders for the libsvm/svmlight sparse text format. Each line is one point:
+//!
+//! ```text
+//! <label> [qid:<query id>] <index>:<value> <index>:<value> ... [# comment]
+//! ```
+//!
+//! Indexes are one based in most files. If any index in the file is 0 the whole file is read as
+//! zero based instead. The labels have to be integers, binary datasets usually use `-1` and `+1`.
+//! Query ids are skipped.
+
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+use super::*;
+use crate::metrics::L2;
+
+/// A sparse cloud with integer labels, as read from a libsvm file.
+pub type LibSvmCloud<M = L2> = SimpleLabeledCloud<SparseDataRam<f32, u32, M>, SmallIntLabels>;
+
+struct LibSvmParser<'a> {
+    file_name: &'a str,
+    labels: Vec<i64>,
+    values: Vec<f32>,
+    col_index: Vec<u32>,
+    row_index: Vec<u32>,
+    min_index: u32,
+    max_index: u32,
+}
+
+impl<'a> LibSvmParser<'a> {
+    fn new(file_name: &'a str) -> Self {
+        LibSvmParser {
+            file_name,
+            labels: Vec::new(),
+            values: Vec::new(),
+            col_index: Vec::new(),
+            row_index: vec![0],
+            min_index: u32::MAX,
+            max_index: 0,
+        }
+    }
+
+    fn error(&self, line_number: usize, reason: String) -> PointCloudError {
+        PointCloudError::ParsingError(ParsingError::LibSvmError {
+            file_name: self.file_name.to_string(),
+            line_number,
+            reason,
+        })
+    }
+
+    fn parse_line(&mut self, line_number: usize, line: &str) -> PointCloudResult<()> {
+        let line = match line.find('#') {
+            Some(comment) => &line[..comment],
+            None => line,
+        };
+        let mut tokens = line.split_whitespace();
+        let label = match tokens.next() {
+            Some(label) => label,
+            // Blank and comment only lines aren't points
+            None => return Ok(()),
+        };
+        let label = label
+            .trim_start_matches('+')
+            .parse::<i64>()
+            .map_err(|_| self.error(line_number, format!("{} isn't an integer label", label)))?;
+
+        let row_start = self.values.len();
+        for token in tokens {
+            let (index, value) = match token.find(':') {
+                Some(split) => (&token[..split], &token[split + 1..]),
+                None => return Err(self.error(line_number, format!("{} isn't index:value", token))),
+            };
+            if index == "qid" {
+                continue;
+            }
+            let index = index
+                .parse::<u32>()
+                .map_err(|_| self.error(line_number, format!("{} isn't an index", index)))?;
+            let value = value
+                .parse::<f32>()
+                .map_err(|_| self.error(line_number, format!("{} isn't a number", value)))?;
+            self.min_index = self.min_index.min(index);
+            self.max_index = self.max_index.max(index);
+            self.col_index.push(index);
+            self.values.push(value);
+        }
+
+        // The sparse points need their indexes in order
+        let row_indexes = &self.col_index[row_start..];
+        if !row_indexes.windows(2).all(|w| w[0] <= w[1]) {
+            let mut row: Vec<(u32, f32)> = row_indexes
+                .iter()
+                .copied()
+                .zip(self.values[row_start..].iter().copied())
+                .collect();
+            row.sort_by_key(|(i, _)| *i);
+            for (j, (i, v)) in row.drain(..).enumerate() {
+                self.col_index[row_start + j] = i;
+                self.values[row_start + j] = v;
+            }
+        }
+        if self.col_index[row_start..].windows(2).any(|w| w[0] == w[1]) {
+            return Err(self.error(line_number, "an index is repeated".to_string()));
+        }
+
+        self.labels.push(label);
+        self.row_index.push(self.values.len() as u32);
+        Ok(())
+    }
+
+    fn finish<M>(mut self, dim: Option<usize>, metric: M) -> PointCloudResult<LibSvmCloud<M>> {
+        // One based unless a 0 shows up
+        if self.min_index > 0 && self.min_index != u32::MAX {
+            self.col_index.iter_mut().for_each(|i| *i -= 1);
+            self.max_index -= 1;
+        }
+        let needed_dim = if self.values.is_empty() {
+            0
+        } else {
+            self.max_index as usize + 1
+        };
+        let dim = match dim {
+            Some(dim) if dim < needed_dim => {
+                return Err(self.error(
+                    0,
+                    format!(
+                        "the file has {} dimensions, more than the {} requested",
+                        needed_dim, dim
+                    ),
+                ))
+            }
+            Some(dim) => dim,
+            None => needed_dim,
+        };
+        Ok(SimpleLabeledCloud::new(
+            SparseDataRam::with_metric(self.values, self.col_index, self.row_index, dim, metric),
+            SmallIntLabels::new(self.labels, None),
+        ))
+    }
+}
+
+/// Reads a libsvm/svmlight file into a sparse cloud with its labels. The dimension is the largest
+/// index in the file, unless a dimension is given. Pass it in when loading a test set whose
+/// largest features might not show up, so that the test set matches the training set.
+pub fn labeled_sparse_from_libsvm<P: AsRef<Path>, M: Default>(
+    path: P,
+    dim: Option<usize>,
+) -> PointCloudResult<LibSvmCloud<M>> {
+    let file_name = path.as_ref().to_string_lossy().to_string();
+    let reader = BufReader::new(File::open(&path)?);
+    let mut parser = LibSvmParser::new(&file_name);
+    for (i, line) in reader.lines().enumerate() {
+        // Line numbers start at 1, 0 is used for errors about the whole file
+        parser.parse_line(i + 1, &line?)?;
+    }
+    parser.finish(dim, M::default())
+}
+
+/// Parses libsvm/svmlight lines that are already in memory.
+pub fn labeled_sparse_from_libsvm_str<M: Default>(
+    contents: &str,
+    dim: Option<usize>,
+) -> PointCloudResult<LibSvmCloud<M>> {
+    let mut parser = LibSvmParser::new("<string>");
+    for (i, line) in contents.lines().enumerate() {
+        parser.parse_line(i + 1, line)?;
+    }
+    parser.finish(dim, M::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempdir::TempDir;
+
+    #[test]
+    fn reads_one_based_files() {
+        let contents = "+1 1:0.5 3:2.0\n-1 qid:4 2:1.5 # a comment\n\n3 4:1.0 1:-1.0\n";
+        let cloud = labeled_sparse_from_libsvm_str::<L2>(contents, None).unwrap();
+        assert_eq!(cloud.len(), 3);
+        assert_eq!(cloud.dim(), 4);
+        assert_eq!(cloud.point(0).unwrap().dense(), vec![0.5, 0.0, 2.0, 0.0]);
+        assert_eq!(cloud.point(1).unwrap().dense(), vec![0.0, 1.5, 0.0, 0.0]);
+        // Out of order indexes are sorted
+        assert_eq!(cloud.point(2).unwrap().indexes(), &[0, 3]);
+        assert_eq!(cloud.label(0).unwrap(), Some(&1));
+        assert_eq!(cloud.label(1).unwrap(), Some(&-1));
+        assert_eq!(cloud.label(2).unwrap(), Some(&3));
+    }
+
+    #[test]
+    fn reads_zero_based_files() {
+        let contents = "0 0:1.0 2:2.0\n1 1:3.0\n";
+        let cloud = labeled_sparse_from_libsvm_str::<L2>(contents, Some(5)).unwrap();
+        assert_eq!(cloud.dim(), 5);
+        assert_eq!(
+            cloud.point(0).unwrap().dense(),
+            vec![1.0, 0.0, 2.0, 0.0, 0.0]
+        );
+        assert!(labeled_sparse_from_libsvm_str::<L2>(contents, Some(2)).is_err());
+    }
+
+    #[test]
+    fn malformed_lines_are_errors() {
+        assert!(labeled_sparse_from_libsvm_str::<L2>("0.5 1:1.0\n", None).is_err());
+        assert!(labeled_sparse_from_libsvm_str::<L2>("1 1-1.0\n", None).is_err());
+        assert!(labeled_sparse_from_libsvm_str::<L2>("1 1:x\n", None).is_err());
+        assert!(labeled_sparse_from_libsvm_str::<L2>("1 1:1.0 1:2.0\n", None).is_err());
+    }
+
+    #[test]
+    fn reads_files() {
+        let dir = TempDir::new("libsvm_loaders").unwrap();
+        let path = dir.path().join("points.svm");
+        let mut file = File::create(&path).unwrap();
+        file.write_all(b"1 1:1.0 2:2.0\n2 2:3.0\n").unwrap();
+        drop(file);
+
+        let cloud = labeled_sparse_from_libsvm::<_, L2>(&path, None).unwrap();
+        assert_eq!(cloud.len(), 2);
+        assert_eq!(cloud.point(1).unwrap().dense(), vec![0.0, 3.0]);
+        assert_approx_eq!(
+            cloud.distances_to_point_index(0, &[1]).unwrap()[0],
+            2.0f32.sqrt()
+        );
+    }
+}
@@ -12,9 +12,41 @@ mod yaml_loaders;
 pub use yaml_loaders::*;
 mod csv_loaders;
 pub use csv_loaders::*;
+mod npy_loaders;
+pub use npy_loaders::*;
+#[cfg(feature = "parquet")]
+mod parquet_loaders;
+#[cfg(feature = "parquet")]
+pub use parquet_loaders::*;
+#[cfg(feature = "arrow")]
+mod arrow_loaders;
+#[cfg(feature = "arrow")]
+pub use arrow_loaders::*;
+mod libsvm_loaders;
+pub use libsvm_loaders::*;
+mod chunked_loaders;
+pub use chunked_loaders::*;
+mod compression;
+pub use compression::*;
+mod config;
+pub use config::*;
+mod sharded_loaders;
+pub use sharded_loaders::*;
+mod vecs_loaders;
+pub use vecs_loaders::*;
+mod tfrecord_loaders;
+pub use tfrecord_loaders::*;
+mod remote;
+pub use remote::*;
+#[cfg(feature = "datasets")]
+mod datasets;
+#[cfg(feature = "datasets")]
+pub use datasets::*;
+mod label_schema;
+pub use label_schema::*;
 
 /// Opens a set of memmaps of both data and labels
-pub fn open_labeled_memmaps<M: Metric<[f32]>>(
+pub fn open_labeled_memmaps<M: Metric<[f32]> + Default>(
     data_dim: usize,
     label_dim: usize,
     data_paths: &[PathBuf],
@@ -40,7 +72,7 @@ pub fn open_labeled_memmaps<M: Metric<[f32]>>(
 }
 
 /// Opens a set of memmaps of just data
-pub fn open_memmaps<M: Metric<[f32]>>(
+pub fn open_memmaps<M: Metric<[f32]> + Default>(
     data_dim: usize,
     data_paths: &[PathBuf],
 ) -> PointCloudResult<HashGluedCloud<DataMemmap<M>>> {
@@ -0,0 +1,598 @@
+//! Loaders for numpy's `.npy` and `.npz` files. These read the arrays straight off disk, so a
+//! dataset saved from python doesn't have to be loaded into python's memory to be indexed.
+//!
+//! Data can be any float or integer dtype of either endianness, in C or fortran order, and is
+//! converted to `f32`. It has to be 2 dimensional, or 1 dimensional for 1 dimensional data.
+//! Labels can be any integer dtype, or a float dtype holding whole numbers, with shape `(n,)` or
+//! `(n, 1)`. Negative labels are treated as unlabeled and are masked.
+
+use std::fs::File;
+use std::io::{BufReader, Read};
+use zip::result::ZipError;
+use zip::ZipArchive;
+
+use super::*;
+use crate::DefaultLabeledCloud;
+
+const NPY_MAGIC: &[u8] = b"\x93NUMPY";
+// Elements are decoded this many at a time so that huge arrays aren't read into memory twice
+const READ_CHUNK_LEN: usize = 1 << 14;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum NpyKind {
+    Float,
+    Int,
+    Uint,
+    Bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct NpyHeader {
+    kind: NpyKind,
+    pub(super) item_size: usize,
+    big_endian: bool,
+    pub(super) fortran_order: bool,
+    pub(super) shape: Vec<usize>,
+}
+
+impl NpyHeader {
+    fn len(&self) -> usize {
+        self.shape.iter().product()
+    }
+}
+
+pub(super) trait NpyElement: Copy + Default {
+    fn from_f64(x: f64) -> Self;
+    fn from_i64(x: i64) -> Self;
+    fn from_u64(x: u64) -> Self;
+}
+
+impl NpyElement for f32 {
+    fn from_f64(x: f64) -> Self {
+        x as f32
+    }
+    fn from_i64(x: i64) -> Self {
+        x as f32
+    }
+    fn from_u64(x: u64) -> Self {
+        x as f32
+    }
+}
+
+impl NpyElement for i64 {
+    fn from_f64(x: f64) -> Self {
+        x as i64
+    }
+    fn from_i64(x: i64) -> Self {
+        x
+    }
+    fn from_u64(x: u64) -> Self {
+        x as i64
+    }
+}
+
+pub(super) fn numpy_error(file_name: &str, reason: String) -> PointCloudError {
+    PointCloudError::ParsingError(ParsingError::NumpyError {
+        file_name: file_name.to_string(),
+        reason,
+    })
+}
+
+/// Pulls the value of a key out of the header's python dict literal.
+fn header_value<'a>(header: &'a str, key: &str) -> Option<&'a str> {
+    let key_start = header
+        .find(&format!("'{}'", key))
+        .or_else(|| header.find(&format!("\"{}\"", key)))?;
+    let rest = &header[key_start + key.len() + 2..];
+    let rest = rest.trim_start().strip_prefix(':')?.trim_start();
+    let end = if rest.starts_with('(') {
+        rest.find(')')? + 1
+    } else if rest.starts_with('\'') || rest.starts_with('"') {
+        rest[1..].find(|c| c == '\'' || c == '"')? + 2
+    } else {
+        rest.find(|c| c == ',' || c == '}').unwrap_or(rest.len())
+    };
+    Some(rest[..end].trim())
+}
+
+fn parse_header(header: &str, file_name: &str) -> PointCloudResult<NpyHeader> {
+    let descr = header_value(header, "descr")
+        .ok_or_else(|| numpy_error(file_name, "the header has no 'descr'".to_string()))?
+        .trim_matches(|c| c == '\'' || c == '"');
+    let mut descr_chars = descr.chars();
+    let big_endian = match descr_chars.next() {
+        Some('>') => true,
+        Some('<') | Some('|') | Some('=') => false,
+        _ => {
+            return Err(numpy_error(
+                file_name,
+                format!("unsupported dtype {}", descr),
+            ))
+        }
+    };
+    let kind = match descr_chars.next() {
+        Some('f') => NpyKind::Float,
+        Some('i') => NpyKind::Int,
+        Some('u') => NpyKind::Uint,
+        Some('b') => NpyKind::Bool,
+        _ => {
+            return Err(numpy_error(
+                file_name,
+                format!("unsupported dtype {}", descr),
+            ))
+        }
+    };
+    let item_size: usize = descr_chars
+        .as_str()
+        .parse()
+        .map_err(|_| numpy_error(file_name, format!("unsupported dtype {}", descr)))?;
+    let supported = match kind {
+        NpyKind::Float => item_size == 4 || item_size == 8,
+        NpyKind::Int | NpyKind::Uint => [1, 2, 4, 8].contains(&item_size),
+        NpyKind::Bool => item_size == 1,
+    };
+    if !supported {
+        return Err(numpy_error(
+            file_name,
+            format!("unsupported dtype {}", descr),
+        ));
+    }
+
+    let fortran_order = match header_value(header, "fortran_order") {
+        Some("True") => true,
+        Some("False") => false,
+        _ => {
+            return Err(numpy_error(
+                file_name,
+                "the header has no 'fortran_order'".to_string(),
+            ))
+        }
+    };
+    let shape = header_value(header, "shape")
+        .ok_or_else(|| numpy_error(file_name, "the header has no 'shape'".to_string()))?
+        .trim_matches(|c| c == '(' || c == ')')
+        .split(',')
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .map(|s| {
+            s.trim_end_matches('L')
+                .parse::<usize>()
+                .map_err(|_| numpy_error(file_name, format!("malformed shape entry {}", s)))
+        })
+        .collect::<PointCloudResult<Vec<usize>>>()?;
+    Ok(NpyHeader {
+        kind,
+        item_size,
+        big_endian,
+        fortran_order,
+        shape,
+    })
+}
+
+pub(super) fn read_header<R: Read>(reader: &mut R, file_name: &str) -> PointCloudResult<NpyHeader> {
+    let mut preamble = [0u8; 8];
+    reader.read_exact(&mut preamble)?;
+    if &preamble[..6] != NPY_MAGIC {
+        return Err(numpy_error(file_name, "not a npy file".to_string()));
+    }
+    let header_len = match preamble[6] {
+        1 => {
+            let mut len = [0u8; 2];
+            reader.read_exact(&mut len)?;
+            u16::from_le_bytes(len) as usize
+        }
+        2 | 3 => {
+            let mut len = [0u8; 4];
+            reader.read_exact(&mut len)?;
+            u32::from_le_bytes(len) as usize
+        }
+        v => {
+            return Err(numpy_error(
+                file_name,
+                format!("unsupported npy format version {}", v),
+            ))
+        }
+    };
+    let mut header = vec![0u8; header_len];
+    reader.read_exact(&mut header)?;
+    let header = String::from_utf8_lossy(&header);
+    parse_header(&header, file_name)
+}
+
+pub(super) fn decode<T: NpyElement>(header: &NpyHeader, bytes: &[u8]) -> T {
+    let mut buf = [0u8; 8];
+    let buf = &mut buf[..header.item_size];
+    buf.copy_from_slice(bytes);
+    if header.big_endian {
+        buf.reverse();
+    }
+    match (header.kind, header.item_size) {
+        (NpyKind::Float, 4) => {
+            T::from_f64(f32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as f64)
+        }
+        (NpyKind::Float, _) => {
+            let mut b = [0u8; 8];
+            b.copy_from_slice(buf);
+            T::from_f64(f64::from_le_bytes(b))
+        }
+        (NpyKind::Int, 1) => T::from_i64(buf[0] as i8 as i64),
+        (NpyKind::Int, 2) => T::from_i64(i16::from_le_bytes([buf[0], buf[1]]) as i64),
+        (NpyKind::Int, 4) => {
+            T::from_i64(i32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as i64)
+        }
+        (NpyKind::Int, _) => {
+            let mut b = [0u8; 8];
+            b.copy_from_slice(buf);
+            T::from_i64(i64::from_le_bytes(b))
+        }
+        (NpyKind::Uint, 1) | (NpyKind::Bool, _) => T::from_u64(buf[0] as u64),
+        (NpyKind::Uint, 2) => T::from_u64(u16::from_le_bytes([buf[0], buf[1]]) as u64),
+        (NpyKind::Uint, 4) => {
+            T::from_u64(u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as u64)
+        }
+        (NpyKind::Uint, _) => {
+            let mut b = [0u8; 8];
+            b.copy_from_slice(buf);
+            T::from_u64(u64::from_le_bytes(b))
+        }
+    }
+}
+
+/// Reads a whole npy array, returning its shape and the elements in C order.
+pub(super) fn read_npy<R: Read, T: NpyElement>(
+    reader: &mut R,
+    file_name: &str,
+) -> PointCloudResult<(Vec<usize>, Vec<T>)> {
+    let header = read_header(reader, file_name)?;
+    let len = header.len();
+    let mut values = Vec::with_capacity(len);
+    let mut chunk = vec![0u8; READ_CHUNK_LEN * header.item_size];
+    while values.len() < len {
+        let count = (len - values.len()).min(READ_CHUNK_LEN);
+        let bytes = &mut chunk[..count * header.item_size];
+        reader.read_exact(bytes).map_err(|_| {
+            numpy_error(
+                file_name,
+                format!("the file ended after {} of {} elements", values.len(), len),
+            )
+        })?;
+        values.extend(
+            bytes
+                .chunks_exact(header.item_size)
+                .map(|b| decode::<T>(&header, b)),
+        );
+    }
+    if header.fortran_order && header.shape.len() == 2 {
+        let (rows, cols) = (header.shape[0], header.shape[1]);
+        let mut transposed = vec![T::default(); len];
+        for (i, v) in values.iter().enumerate() {
+            transposed[(i % rows) * cols + i / rows] = *v;
+        }
+        values = transposed;
+    } else if header.fortran_order && header.shape.len() > 2 {
+        return Err(numpy_error(
+            file_name,
+            "fortran ordered arrays with more than 2 dimensions are not supported".to_string(),
+        ));
+    }
+    Ok((header.shape, values))
+}
+
+fn data_from_parts<M: Metric<[f32]> + Default>(
+    shape: Vec<usize>,
+    values: Vec<f32>,
+    file_name: &str,
+) -> PointCloudResult<DataRam<M>> {
+    let dim = match shape.len() {
+        1 => 1,
+        2 => shape[1],
+        n => {
+            return Err(numpy_error(
+                file_name,
+                format!("expected a 1 or 2 dimensional data array, got {}", n),
+            ))
+        }
+    };
+    DataRam::new(values, dim)
+}
+
+fn labels_from_parts(
+    shape: Vec<usize>,
+    values: Vec<i64>,
+    file_name: &str,
+) -> PointCloudResult<SmallIntLabels> {
+    if shape.len() > 2 || (shape.len() == 2 && shape[1] != 1) {
+        return Err(numpy_error(
+            file_name,
+            format!("expected labels of shape (n,) or (n, 1), got {:?}", shape),
+        ));
+    }
+    let mask: Vec<bool> = values.iter().map(|l| *l >= 0).collect();
+    if mask.iter().all(|m| *m) {
+        Ok(SmallIntLabels::new(values, None))
+    } else {
+        Ok(SmallIntLabels::new(values, Some(mask)))
+    }
+}
+
+fn open_npz<P: AsRef<Path>>(path: P) -> PointCloudResult<ZipArchive<BufReader<File>>> {
+    let file_name = path.as_ref().to_string_lossy().to_string();
+    let file = BufReader::new(File::open(&path)?);
+    ZipArchive::new(file).map_err(|e| zip_error(e, &file_name))
+}
+
+fn zip_error(err: ZipError, file_name: &str) -> PointCloudError {
+    match err {
+        ZipError::Io(e) => PointCloudError::IoError(e),
+        e => numpy_error(file_name, e.to_string()),
+    }
+}
+
+/// Reads a named array out of an npz. Numpy stores `np.savez(path, data=x)` as `data.npy`, the
+/// name can be given with or without the extension.
+fn read_npz_array<T: NpyElement>(
+    archive: &mut ZipArchive<BufReader<File>>,
+    name: &str,
+    file_name: &str,
+) -> PointCloudResult<(Vec<usize>, Vec<T>)> {
+    let member = if name.ends_with(".npy") {
+        name.to_string()
+    } else {
+        format!("{}.npy", name)
+    };
+    let array_name = format!("{}:{}", file_name, member);
+    let mut array = archive.by_name(&member).map_err(|e| match e {
+        ZipError::FileNotFound => {
+            numpy_error(file_name, format!("there is no array named {}", name))
+        }
+        e => zip_error(e, file_name),
+    })?;
+    read_npy(&mut array, &array_name)
+}
+
+/// Opens a `.npy` file of data.
+pub fn ram_from_npy<P: AsRef<Path>, M: Metric<[f32]> + Default>(
+    path: P,
+) -> PointCloudResult<DataRam<M>> {
+    let file_name = path.as_ref().to_string_lossy().to_string();
+    let mut reader = BufReader::new(File::open(&path)?);
+    let (shape, values) = read_npy(&mut reader, &file_name)?;
+    data_from_parts(shape, values, &file_name)
+}
+
+/// Opens a `.npy` file of integer labels.
+pub fn labels_from_npy<P: AsRef<Path>>(path: P) -> PointCloudResult<SmallIntLabels> {
+    let file_name = path.as_ref().to_string_lossy().to_string();
+    let mut reader = BufReader::new(File::open(&path)?);
+    let (shape, values) = read_npy(&mut reader, &file_name)?;
+    labels_from_parts(shape, values, &file_name)
+}
+
+/// Opens a pair of `.npy` files, one of data and one of labels.
+pub fn labeled_ram_from_npy<P: AsRef<Path>, M: Metric<[f32]> + Default>(
+    data_path: P,
+    labels_path: P,
+) -> PointCloudResult<DefaultLabeledCloud<M>> {
+    let data = ram_from_npy(&data_path)?;
+    let labels = labels_from_npy(&labels_path)?;
+    check_label_count(data.len(), labels.len(), labels_path.as_ref())?;
+    Ok(SimpleLabeledCloud::new(data, labels))
+}
+
+/// The names of the arrays in a `.npz` file, without the `.npy` extension.
+pub fn npz_array_names<P: AsRef<Path>>(path: P) -> PointCloudResult<Vec<String>> {
+    let archive = open_npz(&path)?;
+    Ok(archive
+        .file_names()
+        .map(|name| name.trim_end_matches(".npy").to_string())
+        .collect())
+}
+
+/// Opens the data array with the given name in a `.npz` file.
+pub fn ram_from_npz<P: AsRef<Path>, M: Metric<[f32]> + Default>(
+    path: P,
+    data_name: &str,
+) -> PointCloudResult<DataRam<M>> {
+    let file_name = path.as_ref().to_string_lossy().to_string();
+    let mut archive = open_npz(&path)?;
+    let (shape, values) = read_npz_array(&mut archive, data_name, &file_name)?;
+    data_from_parts(shape, values, &file_name)
+}
+
+/// Opens the labels array with the given name in a `.npz` file.
+pub fn labels_from_npz<P: AsRef<Path>>(
+    path: P,
+    labels_name: &str,
+) -> PointCloudResult<SmallIntLabels> {
+    let file_name = path.as_ref().to_string_lossy().to_string();
+    let mut archive = open_npz(&path)?;
+    let (shape, values) = read_npz_array(&mut archive, labels_name, &file_name)?;
+    labels_from_parts(shape, values, &file_name)
+}
+
+/// Opens a `.npz` file with named data and labels arrays, like one written by
+/// `np.savez(path, data=x, labels=y)`.
+pub fn labeled_ram_from_npz<P: AsRef<Path>, M: Metric<[f32]> + Default>(
+    path: P,
+    data_name: &str,
+    labels_name: &str,
+) -> PointCloudResult<DefaultLabeledCloud<M>> {
+    let file_name = path.as_ref().to_string_lossy().to_string();
+    let mut archive = open_npz(&path)?;
+    let (shape, values) = read_npz_array(&mut archive, data_name, &file_name)?;
+    let data = data_from_parts(shape, values, &file_name)?;
+    let (shape, values) = read_npz_array(&mut archive, labels_name, &file_name)?;
+    let labels = labels_from_parts(shape, values, &file_name)?;
+    check_label_count(data.len(), labels.len(), path.as_ref())?;
+    Ok(SimpleLabeledCloud::new(data, labels))
+}
+
+fn check_label_count(data_len: usize, labels_len: usize, path: &Path) -> PointCloudResult<()> {
+    if data_len != labels_len {
+        return Err(numpy_error(
+            &path.to_string_lossy(),
+            format!("got {} labels for {} points", labels_len, data_len),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::L2;
+    use std::io::Write;
+    use tempdir::TempDir;
+
+    fn npy_bytes(descr: &str, fortran_order: bool, shape: &str, body: &[u8]) -> Vec<u8> {
+        let mut header = format!(
+            "{{'descr': '{}', 'fortran_order': {}, 'shape': {}, }}",
+            descr,
+            if fortran_order { "True" } else { "False" },
+            shape
+        );
+        // Numpy pads the header so the data starts on a 64 byte boundary
+        while (10 + header.len() + 1) % 64 != 0 {
+            header.push(' ');
+        }
+        header.push('\n');
+        let mut bytes = NPY_MAGIC.to_vec();
+        bytes.extend_from_slice(&[1, 0]);
+        bytes.extend_from_slice(&(header.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(header.as_bytes());
+        bytes.extend_from_slice(body);
+        bytes
+    }
+
+    fn f32_body(values: &[f32]) -> Vec<u8> {
+        values
+            .iter()
+            .flat_map(|v| v.to_le_bytes().to_vec())
+            .collect()
+    }
+
+    #[test]
+    fn parses_header() {
+        let header = "{'descr': '>i4', 'fortran_order': False, 'shape': (3, 2), }";
+        let header = parse_header(header, "test").unwrap();
+        assert_eq!(header.kind, NpyKind::Int);
+        assert_eq!(header.item_size, 4);
+        assert!(header.big_endian);
+        assert!(!header.fortran_order);
+        assert_eq!(header.shape, vec![3, 2]);
+
+        let header = "{'descr': '<f8', 'fortran_order': True, 'shape': (5,), }";
+        let header = parse_header(header, "test").unwrap();
+        assert_eq!(header.shape, vec![5]);
+        assert!(header.fortran_order);
+
+        let header = "{'descr': '<c8', 'fortran_order': False, 'shape': (5,), }";
+        assert!(parse_header(header, "test").is_err());
+    }
+
+    #[test]
+    fn reads_c_and_fortran_order() {
+        let values = [1.0f32, 2.0, 3.0, 4.0, 5.0, 6.0];
+        let bytes = npy_bytes("<f4", false, "(3, 2)", &f32_body(&values));
+        let (shape, read) = read_npy::<_, f32>(&mut &bytes[..], "test").unwrap();
+        assert_eq!(shape, vec![3, 2]);
+        assert_eq!(read, values.to_vec());
+
+        // The same 3x2 array stored column by column
+        let columns = [1.0f32, 3.0, 5.0, 2.0, 4.0, 6.0];
+        let bytes = npy_bytes("<f4", true, "(3, 2)", &f32_body(&columns));
+        let (_, read) = read_npy::<_, f32>(&mut &bytes[..], "test").unwrap();
+        assert_eq!(read, values.to_vec());
+    }
+
+    #[test]
+    fn converts_dtypes() {
+        let body: Vec<u8> = [1.5f64, -2.0]
+            .iter()
+            .flat_map(|v| v.to_be_bytes().to_vec())
+            .collect();
+        let bytes = npy_bytes(">f8", false, "(2,)", &body);
+        let (_, read) = read_npy::<_, f32>(&mut &bytes[..], "test").unwrap();
+        assert_eq!(read, vec![1.5, -2.0]);
+
+        let body: Vec<u8> = [3i16, -1]
+            .iter()
+            .flat_map(|v| v.to_le_bytes().to_vec())
+            .collect();
+        let bytes = npy_bytes("<i2", false, "(2,)", &body);
+        let (_, read) = read_npy::<_, i64>(&mut &bytes[..], "test").unwrap();
+        assert_eq!(read, vec![3, -1]);
+    }
+
+    #[test]
+    fn truncated_file_is_an_error() {
+        let bytes = npy_bytes("<f4", false, "(3, 2)", &f32_body(&[1.0, 2.0]));
+        assert!(read_npy::<_, f32>(&mut &bytes[..], "test").is_err());
+    }
+
+    #[test]
+    fn loads_npy_pair() {
+        let dir = TempDir::new("npy_loaders").unwrap();
+        let data_path = dir.path().join("data.npy");
+        let labels_path = dir.path().join("labels.npy");
+        let data = npy_bytes(
+            "<f4",
+            false,
+            "(2, 3)",
+            &f32_body(&[0.0, 1.0, 2.0, 3.0, 4.0, 5.0]),
+        );
+        File::create(&data_path).unwrap().write_all(&data).unwrap();
+        let labels: Vec<u8> = [7i64, -1]
+            .iter()
+            .flat_map(|v| v.to_le_bytes().to_vec())
+            .collect();
+        let labels = npy_bytes("<i8", false, "(2,)", &labels);
+        File::create(&labels_path)
+            .unwrap()
+            .write_all(&labels)
+            .unwrap();
+
+        let cloud = labeled_ram_from_npy::<_, L2>(&data_path, &labels_path).unwrap();
+        assert_eq!(cloud.len(), 2);
+        assert_eq!(cloud.dim(), 3);
+        assert_eq!(cloud.point(1).unwrap(), &[3.0, 4.0, 5.0][..]);
+        assert_eq!(cloud.label(0).unwrap(), Some(&7));
+        assert_eq!(cloud.label(1).unwrap(), None);
+    }
+
+    #[test]
+    fn loads_npz() {
+        let dir = TempDir::new("npz_loaders").unwrap();
+        let path = dir.path().join("dataset.npz");
+        let mut writer = zip::ZipWriter::new(File::create(&path).unwrap());
+        let options =
+            zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+        writer.start_file("data.npy", options).unwrap();
+        writer
+            .write_all(&npy_bytes(
+                "<f4",
+                false,
+                "(2, 2)",
+                &f32_body(&[0.0, 1.0, 2.0, 3.0]),
+            ))
+            .unwrap();
+        writer.start_file("labels.npy", options).unwrap();
+        let labels: Vec<u8> = [1i32, 2]
+            .iter()
+            .flat_map(|v| v.to_le_bytes().to_vec())
+            .collect();
+        writer
+            .write_all(&npy_bytes("<i4", false, "(2,)", &labels))
+            .unwrap();
+        writer.finish().unwrap();
+
+        let cloud = labeled_ram_from_npz::<_, L2>(&path, "data", "labels").unwrap();
+        assert_eq!(cloud.len(), 2);
+        assert_eq!(cloud.point(1).unwrap(), &[2.0, 3.0][..]);
+        assert_eq!(cloud.label(1).unwrap(), Some(&2));
+        assert!(ram_from_npz::<_, L2>(&path, "missing").is_err());
+        let mut names = npz_array_names(&path).unwrap();
+        names.sort();
+        assert_eq!(names, vec!["data".to_string(), "labels".to_string()]);
+    }
+}
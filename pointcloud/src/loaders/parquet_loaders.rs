@@ -0,0 +1,261 @@
+//! Loaders for Parquet files, behind the `parquet` feature. Only the requested columns are read.
+//! The feature columns can be any numeric type and are converted to `f32`. Nulls aren't allowed in
+//! them. The label column can be any integer type, or a float type holding whole numbers. Null and
+//! negative labels are treated as unlabeled and are masked.
+
+use parquet::file::reader::{FileReader, SerializedFileReader};
+use parquet::record::Field;
+use parquet::schema::types::Type;
+use std::fs::File;
+use std::sync::Arc;
+
+use super::*;
+use crate::DefaultLabeledCloud;
+
+fn parquet_error(file_name: &str, reason: String) -> PointCloudError {
+    PointCloudError::ParsingError(ParsingError::ParquetError {
+        file_name: file_name.to_string(),
+        reason,
+    })
+}
+
+fn field_to_f64(field: &Field) -> Option<f64> {
+    match *field {
+        Field::Bool(x) => Some(if x { 1.0 } else { 0.0 }),
+        Field::Byte(x) => Some(x as f64),
+        Field::Short(x) => Some(x as f64),
+        Field::Int(x) => Some(x as f64),
+        Field::Long(x) => Some(x as f64),
+        Field::UByte(x) => Some(x as f64),
+        Field::UShort(x) => Some(x as f64),
+        Field::UInt(x) => Some(x as f64),
+        Field::ULong(x) => Some(x as f64),
+        Field::Float(x) => Some(x as f64),
+        Field::Double(x) => Some(x),
+        _ => None,
+    }
+}
+
+fn field_to_label(field: &Field) -> Option<i64> {
+    match *field {
+        Field::Byte(x) => Some(x as i64),
+        Field::Short(x) => Some(x as i64),
+        Field::Int(x) => Some(x as i64),
+        Field::Long(x) => Some(x),
+        Field::UByte(x) => Some(x as i64),
+        Field::UShort(x) => Some(x as i64),
+        Field::UInt(x) => Some(x as i64),
+        Field::ULong(x) => Some(x as i64),
+        Field::Float(x) if x.fract() == 0.0 => Some(x as i64),
+        Field::Double(x) if x.fract() == 0.0 => Some(x as i64),
+        _ => None,
+    }
+}
+
+/// Reads the feature columns, in the order given, and optionally a label column. The labels come
+/// back with their mask.
+fn read_parquet<P: AsRef<Path>>(
+    path: P,
+    feature_columns: &[&str],
+    label_column: Option<&str>,
+) -> PointCloudResult<(Vec<f32>, Option<(Vec<i64>, Vec<bool>)>)> {
+    let file_name = path.as_ref().to_string_lossy().to_string();
+    if feature_columns.is_empty() {
+        return Err(parquet_error(
+            &file_name,
+            "no feature columns were selected".to_string(),
+        ));
+    }
+    let reader = SerializedFileReader::new(File::open(&path)?)
+        .map_err(|e| parquet_error(&file_name, e.to_string()))?;
+
+    // Project the file down to just the columns we need
+    let schema = reader.metadata().file_metadata().schema();
+    let wanted = |name: &str| feature_columns.contains(&name) || label_column == Some(name);
+    let mut selected: Vec<Arc<Type>> = schema
+        .get_fields()
+        .iter()
+        .filter(|f| wanted(f.name()))
+        .cloned()
+        .collect();
+    for name in feature_columns.iter().chain(label_column.iter()) {
+        if !selected.iter().any(|f| f.name() == *name) {
+            return Err(parquet_error(
+                &file_name,
+                format!("there is no column named {}", name),
+            ));
+        }
+    }
+    let projection = Type::group_type_builder(schema.name())
+        .with_fields(&mut selected)
+        .build()
+        .map_err(|e| parquet_error(&file_name, e.to_string()))?;
+
+    let row_count = reader.metadata().file_metadata().num_rows() as usize;
+    let mut data = Vec::with_capacity(row_count * feature_columns.len());
+    let mut labels = Vec::with_capacity(row_count);
+    let mut mask = Vec::with_capacity(row_count);
+    let mut row_features = vec![0.0f32; feature_columns.len()];
+    let rows = reader
+        .get_row_iter(Some(projection))
+        .map_err(|e| parquet_error(&file_name, e.to_string()))?;
+    for (row_index, row) in rows.enumerate() {
+        for (name, field) in row.get_column_iter() {
+            if label_column == Some(name.as_str()) {
+                match field {
+                    Field::Null => {
+                        labels.push(0);
+                        mask.push(false);
+                    }
+                    field => {
+                        let label = field_to_label(field).ok_or_else(|| {
+                            parquet_error(
+                                &file_name,
+                                format!("row {} has a non integer label {:?}", row_index, field),
+                            )
+                        })?;
+                        labels.push(label);
+                        mask.push(label >= 0);
+                    }
+                }
+            }
+            // A column can be both a feature and the label
+            if let Some(i) = feature_columns.iter().position(|c| *c == name.as_str()) {
+                row_features[i] = field_to_f64(field).ok_or_else(|| {
+                    parquet_error(
+                        &file_name,
+                        format!(
+                            "row {} has a non numeric value {:?} in column {}",
+                            row_index, field, name
+                        ),
+                    )
+                })? as f32;
+            }
+        }
+        data.extend_from_slice(&row_features);
+    }
+    let labels = label_column.map(|_| (labels, mask));
+    Ok((data, labels))
+}
+
+/// Reads the given columns of a Parquet file into a dense cloud. The columns become the dimensions
+/// of the points, in the order given.
+pub fn ram_from_parquet<P: AsRef<Path>, M: Metric<[f32]> + Default>(
+    path: P,
+    feature_columns: &[&str],
+) -> PointCloudResult<DataRam<M>> {
+    let (data, _) = read_parquet(path, feature_columns, None)?;
+    DataRam::new(data, feature_columns.len())
+}
+
+/// Reads the given feature columns of a Parquet file into a dense cloud, and one column as labels.
+pub fn labeled_ram_from_parquet<P: AsRef<Path>, M: Metric<[f32]> + Default>(
+    path: P,
+    feature_columns: &[&str],
+    label_column: &str,
+) -> PointCloudResult<DefaultLabeledCloud<M>> {
+    let (data, labels) = read_parquet(path, feature_columns, Some(label_column))?;
+    let (labels, mask) = labels.unwrap_or_default();
+    let mask = if mask.iter().all(|m| *m) {
+        None
+    } else {
+        Some(mask)
+    };
+    Ok(SimpleLabeledCloud::new(
+        DataRam::new(data, feature_columns.len())?,
+        SmallIntLabels::new(labels, mask),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::L2;
+    use parquet::column::writer::ColumnWriter;
+    use parquet::file::properties::WriterProperties;
+    use parquet::file::writer::{FileWriter, RowGroupWriter, SerializedFileWriter};
+    use parquet::schema::parser::parse_message_type;
+    use tempdir::TempDir;
+
+    fn write_test_file(path: &Path) {
+        let schema = Arc::new(
+            parse_message_type(
+                "message schema {
+                    REQUIRED FLOAT x;
+                    REQUIRED BINARY name (UTF8);
+                    REQUIRED DOUBLE y;
+                    OPTIONAL INT64 label;
+                    REQUIRED INT32 z;
+                }",
+            )
+            .unwrap(),
+        );
+        let props = Arc::new(WriterProperties::builder().build());
+        let file = File::create(path).unwrap();
+        let mut writer = SerializedFileWriter::new(file, schema, props).unwrap();
+        let mut row_group_writer = writer.next_row_group().unwrap();
+        while let Some(mut col_writer) = row_group_writer.next_column().unwrap() {
+            match col_writer {
+                ColumnWriter::FloatColumnWriter(ref mut typed) => {
+                    typed.write_batch(&[0.0, 1.0, 2.0], None, None).unwrap();
+                }
+                ColumnWriter::ByteArrayColumnWriter(ref mut typed) => {
+                    typed
+                        .write_batch(&["a".into(), "b".into(), "c".into()], None, None)
+                        .unwrap();
+                }
+                ColumnWriter::DoubleColumnWriter(ref mut typed) => {
+                    typed.write_batch(&[10.0, 11.0, 12.0], None, None).unwrap();
+                }
+                // The second label is null
+                ColumnWriter::Int64ColumnWriter(ref mut typed) => {
+                    typed.write_batch(&[3, 4], Some(&[1, 0, 1]), None).unwrap();
+                }
+                ColumnWriter::Int32ColumnWriter(ref mut typed) => {
+                    typed.write_batch(&[-1, -2, -3], None, None).unwrap();
+                }
+                _ => panic!("unexpected column"),
+            }
+            row_group_writer.close_column(col_writer).unwrap();
+        }
+        writer.close_row_group(row_group_writer).unwrap();
+        writer.close().unwrap();
+    }
+
+    #[test]
+    fn reads_selected_columns_in_order() {
+        let dir = TempDir::new("parquet_loaders").unwrap();
+        let path = dir.path().join("features.parquet");
+        write_test_file(&path);
+
+        let cloud = ram_from_parquet::<_, L2>(&path, &["z", "x"]).unwrap();
+        assert_eq!(cloud.len(), 3);
+        assert_eq!(cloud.dim(), 2);
+        assert_eq!(cloud.point(1).unwrap(), &[-2.0, 1.0][..]);
+    }
+
+    #[test]
+    fn reads_labels() {
+        let dir = TempDir::new("parquet_loaders").unwrap();
+        let path = dir.path().join("features.parquet");
+        write_test_file(&path);
+
+        let cloud = labeled_ram_from_parquet::<_, L2>(&path, &["x", "y"], "label").unwrap();
+        assert_eq!(cloud.len(), 3);
+        assert_eq!(cloud.point(2).unwrap(), &[2.0, 12.0][..]);
+        assert_eq!(cloud.label(0).unwrap(), Some(&3));
+        assert_eq!(cloud.label(1).unwrap(), None);
+        assert_eq!(cloud.label(2).unwrap(), Some(&4));
+    }
+
+    #[test]
+    fn bad_columns_are_errors() {
+        let dir = TempDir::new("parquet_loaders").unwrap();
+        let path = dir.path().join("features.parquet");
+        write_test_file(&path);
+
+        assert!(ram_from_parquet::<_, L2>(&path, &["missing"]).is_err());
+        assert!(ram_from_parquet::<_, L2>(&path, &["name"]).is_err());
+        assert!(ram_from_parquet::<_, L2>(&path, &[]).is_err());
+    }
+}
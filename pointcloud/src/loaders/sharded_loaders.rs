@@ -0,0 +1,263 @@
+//! Loaders for point clouds split across many files, like the per-worker dumps of a distributed
+//! feature job. The shards are given as a list of paths or globs, each glob is expanded in sorted
+//! order, and the shards are read in parallel and concatenated in that order. So a point's global
+//! index only depends on the shard list, and [`ShardMap`] maps it back to the shard it came from.
+//! ```yaml
+//! ---
+//! shards:
+//!   - features/part-*.dat.gz
+//!   - features/extra.dat
+//! labels_shards:
+//!   - labels/part-*.csv
+//! data_dim: 784
+//! labels_index: 1
+//! ```
+
+use rayon::prelude::*;
+
+use super::yaml_loaders::{get_file_list, labels_from_files};
+use super::*;
+use crate::DefaultLabeledCloud;
+
+/// One shard of a sharded point cloud.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Shard {
+    /// The file the shard was read from
+    pub path: PathBuf,
+    /// The global index of the shard's first point
+    pub start: usize,
+    /// The number of points in the shard
+    pub len: usize,
+}
+
+/// Where each shard's points ended up in the concatenated cloud.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ShardMap {
+    shards: Vec<Shard>,
+}
+
+impl ShardMap {
+    /// Lays the shards out one after the other, from their paths and point counts.
+    pub fn new(paths: &[PathBuf], lens: &[usize]) -> ShardMap {
+        let mut start = 0;
+        let shards = paths
+            .iter()
+            .zip(lens)
+            .map(|(path, len)| {
+                let shard = Shard {
+                    path: path.clone(),
+                    start,
+                    len: *len,
+                };
+                start += len;
+                shard
+            })
+            .collect();
+        ShardMap { shards }
+    }
+
+    /// The shards, in order.
+    pub fn shards(&self) -> &[Shard] {
+        &self.shards
+    }
+
+    /// The total number of points.
+    pub fn len(&self) -> usize {
+        self.shards.last().map(|s| s.start + s.len).unwrap_or(0)
+    }
+
+    /// If there are no points.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The global index of the `local_index`th point of a shard.
+    pub fn global_index(&self, shard: usize, local_index: usize) -> Option<usize> {
+        let shard = self.shards.get(shard)?;
+        if local_index < shard.len {
+            Some(shard.start + local_index)
+        } else {
+            None
+        }
+    }
+
+    /// The shard a global index is in and its index within the shard.
+    pub fn locate(&self, global_index: usize) -> Option<(usize, usize)> {
+        if global_index >= self.len() {
+            return None;
+        }
+        let shard = self
+            .shards
+            .partition_point(|s| s.start + s.len <= global_index);
+        Some((shard, global_index - self.shards[shard].start))
+    }
+}
+
+/// Expands a list of shard paths and globs, relative to `base_dir`. Each glob's matches are
+/// sorted, and the lists are concatenated in the order they're given. Shards can be URLs, see
+/// [`fetch_cached`].
+pub fn shard_paths<S: AsRef<str>>(
+    patterns: &[S],
+    base_dir: &Path,
+) -> PointCloudResult<Vec<PathBuf>> {
+    let mut shards = Vec::new();
+    for pattern in patterns {
+        let mut paths = get_file_list(pattern.as_ref(), base_dir)?;
+        paths.sort();
+        shards.extend(paths);
+    }
+    Ok(shards)
+}
+
+/// Reads the shards in parallel and concatenates them in order. Each shard is a file of `f32`s,
+/// which can be compressed. See [`ram_from_f32_files`].
+pub fn ram_from_shards<M: Metric<[f32]> + Default>(
+    dim: usize,
+    paths: &[PathBuf],
+) -> PointCloudResult<(DataRam<M>, ShardMap)> {
+    if paths.is_empty() {
+        return Err(PointCloudError::DataAccessError {
+            index: 0,
+            reason: "there are no shards to read".to_string(),
+        });
+    }
+    let mut parts = paths
+        .par_iter()
+        .map(|path| ram_from_f32_files::<M>(dim, &[path.clone()]))
+        .collect::<PointCloudResult<Vec<DataRam<M>>>>()?;
+    let lens: Vec<usize> = parts.iter().map(|p| p.len()).collect();
+    let data_set = parts
+        .drain(..)
+        .reduce(|mut a, b| {
+            a.merge(b);
+            a
+        })
+        .unwrap();
+    Ok((data_set, ShardMap::new(paths, &lens)))
+}
+
+/// Reads the label shards in parallel and concatenates them in order. CSVs need the column index,
+/// memmaps the dimension, like the `labels_path` of the yaml loaders.
+pub fn labels_from_shards(
+    paths: &[PathBuf],
+    labels_index: Option<usize>,
+    labels_dim: Option<usize>,
+) -> PointCloudResult<(SmallIntLabels, ShardMap)> {
+    if paths.is_empty() {
+        return Err(PointCloudError::DataAccessError {
+            index: 0,
+            reason: "there are no label shards to read".to_string(),
+        });
+    }
+    let mut parts = paths
+        .par_iter()
+        .map(|path| labels_from_files(&[path.clone()], labels_index, labels_dim))
+        .collect::<PointCloudResult<Vec<SmallIntLabels>>>()?;
+    let lens: Vec<usize> = parts.iter().map(|p| p.len()).collect();
+    let label_set = parts
+        .drain(..)
+        .reduce(|mut a, b| {
+            a.merge(&b);
+            a
+        })
+        .unwrap();
+    Ok((label_set, ShardMap::new(paths, &lens)))
+}
+
+/// Reads data and label shards and checks that they line up, shard by shard.
+pub fn labeled_ram_from_shards<M: Metric<[f32]> + Default>(
+    dim: usize,
+    paths: &[PathBuf],
+    labels_paths: &[PathBuf],
+    labels_index: Option<usize>,
+    labels_dim: Option<usize>,
+) -> PointCloudResult<(DefaultLabeledCloud<M>, ShardMap)> {
+    if paths.len() != labels_paths.len() {
+        return Err(PointCloudError::DataAccessError {
+            index: 0,
+            reason: format!(
+                "there are {} data shards but {} label shards",
+                paths.len(),
+                labels_paths.len()
+            ),
+        });
+    }
+    let (data, labels) = rayon::join(
+        || ram_from_shards(dim, paths),
+        || labels_from_shards(labels_paths, labels_index, labels_dim),
+    );
+    let (data_set, shard_map) = data?;
+    let (label_set, labels_map) = labels?;
+    for (data_shard, label_shard) in shard_map.shards().iter().zip(labels_map.shards()) {
+        if data_shard.len != label_shard.len {
+            return Err(PointCloudError::DataAccessError {
+                index: data_shard.start,
+                reason: format!(
+                    "{:?} has {} points but {:?} has {} labels",
+                    data_shard.path, data_shard.len, label_shard.path, label_shard.len
+                ),
+            });
+        }
+    }
+    Ok((SimpleLabeledCloud::new(data_set, label_set), shard_map))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::L2;
+    use std::fs;
+    use tempdir::TempDir;
+
+    fn write_floats(path: &Path, data: &[f32]) {
+        let bytes: Vec<u8> = data.iter().flat_map(|x| x.to_le_bytes().to_vec()).collect();
+        fs::write(path, bytes).unwrap();
+    }
+
+    #[test]
+    fn shard_map_locates_points() {
+        let paths: Vec<PathBuf> = vec!["a".into(), "b".into(), "c".into()];
+        let shard_map = ShardMap::new(&paths, &[2, 0, 3]);
+        assert_eq!(shard_map.len(), 5);
+        assert_eq!(shard_map.locate(1), Some((0, 1)));
+        assert_eq!(shard_map.locate(2), Some((2, 0)));
+        assert_eq!(shard_map.locate(5), None);
+        assert_eq!(shard_map.global_index(2, 2), Some(4));
+        assert_eq!(shard_map.global_index(1, 0), None);
+    }
+
+    #[test]
+    fn reads_shards_in_order() {
+        let dir = TempDir::new("shards").unwrap();
+        // Written out of order, the glob is sorted
+        for i in (0..4).rev() {
+            let points: Vec<f32> = (0..2 * (i + 1)).map(|j| (10 * i + j) as f32).collect();
+            write_floats(&dir.path().join(format!("part-{}.dat", i)), &points);
+            let labels: String = (0..i + 1).map(|j| format!("{},{}\n", j, i)).collect();
+            fs::write(
+                dir.path().join(format!("part-{}.csv", i)),
+                format!("id,label\n{}", labels),
+            )
+            .unwrap();
+        }
+        write_floats(&dir.path().join("extra.dat"), &[-1.0, -2.0]);
+        fs::write(dir.path().join("extra.csv"), "id,label\n0,7\n").unwrap();
+
+        let paths = shard_paths(&["part-*.dat", "extra.dat"], dir.path()).unwrap();
+        let labels_paths = shard_paths(&["part-*.csv", "extra.csv"], dir.path()).unwrap();
+        let (cloud, shard_map) =
+            labeled_ram_from_shards::<L2>(2, &paths, &labels_paths, Some(1), None).unwrap();
+        assert_eq!(cloud.len(), 11);
+        assert_eq!(shard_map.shards().len(), 5);
+        assert_eq!(shard_map.locate(10), Some((4, 0)));
+        assert_eq!(cloud.point(10).unwrap(), &[-1.0, -2.0][..]);
+        assert_eq!(shard_map.locate(3), Some((2, 0)));
+        assert_eq!(cloud.point(3).unwrap(), &[20.0, 21.0][..]);
+        assert_eq!(cloud.label(3).unwrap(), Some(&2));
+
+        // With a dimension of 1 there are twice as many points as labels in each shard
+        let err = labeled_ram_from_shards::<L2>(1, &paths, &labels_paths, Some(1), None);
+        assert!(err.is_err());
+        assert!(ram_from_shards::<L2>(1, &[]).is_err());
+    }
+}
@@ -0,0 +1,445 @@
+//! Loaders for TFRecord files of `tf.train.Example`s, the format a lot of embedding pipelines write.
+//! Each example has a float list feature with the vector and optionally an int64 feature with the
+//! label, the keys are passed in. A record is
+//!
+//! ```text
+//! u64 length, u32 masked crc32c of the length, length bytes of data, u32 masked crc32c of the data
+//! ```
+//!
+//! all little endian, and the checksums are checked. Whole file gzip or zstandard compression, the
+//! `GZIP` option of TensorFlow's writer, is handled by [`open_decompressed`]. The examples are
+//! decoded directly from the protobuf wire format, only the two features are kept.
+
+use std::io::{ErrorKind, Read};
+
+use super::*;
+use crate::DefaultLabeledCloud;
+
+fn tfrecord_error(file_name: &str, record: usize, reason: String) -> PointCloudError {
+    PointCloudError::ParsingError(ParsingError::TfRecordError {
+        file_name: file_name.to_string(),
+        record,
+        reason,
+    })
+}
+
+/// The CRC-32C (Castagnoli) checksum TFRecords use.
+fn crc32c(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0x82f6_3b78
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn masked_crc32c(data: &[u8]) -> u32 {
+    let crc = crc32c(data);
+    ((crc >> 15) | (crc << 17)).wrapping_add(0xa282_ead8)
+}
+
+/// Reads the next record's data into `data`. `false` at the end of the file.
+fn read_record<R: Read>(
+    reader: &mut R,
+    file_name: &str,
+    record: usize,
+    data: &mut Vec<u8>,
+) -> PointCloudResult<bool> {
+    let mut header = [0u8; 12];
+    match reader.read_exact(&mut header[..1]) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(false),
+        Err(e) => return Err(e.into()),
+    }
+    let cut_short = |e: std::io::Error| {
+        if e.kind() == ErrorKind::UnexpectedEof {
+            tfrecord_error(file_name, record, "the file ends in the middle".to_string())
+        } else {
+            e.into()
+        }
+    };
+    reader.read_exact(&mut header[1..]).map_err(cut_short)?;
+    let mut length_bytes = [0u8; 8];
+    length_bytes.copy_from_slice(&header[..8]);
+    let length_crc = u32::from_le_bytes([header[8], header[9], header[10], header[11]]);
+    if masked_crc32c(&length_bytes) != length_crc {
+        return Err(tfrecord_error(
+            file_name,
+            record,
+            "the length's checksum doesn't match".to_string(),
+        ));
+    }
+    let length = u64::from_le_bytes(length_bytes) as usize;
+    data.resize(length, 0);
+    reader.read_exact(data).map_err(cut_short)?;
+    let mut data_crc = [0u8; 4];
+    reader.read_exact(&mut data_crc).map_err(cut_short)?;
+    if masked_crc32c(data) != u32::from_le_bytes(data_crc) {
+        return Err(tfrecord_error(
+            file_name,
+            record,
+            "the data's checksum doesn't match".to_string(),
+        ));
+    }
+    Ok(true)
+}
+
+/// A field of a protobuf message, the wire types an Example uses.
+enum WireField<'a> {
+    Varint(u64),
+    Fixed32([u8; 4]),
+    Fixed64,
+    Bytes(&'a [u8]),
+}
+
+/// Reads protobuf fields out of a message.
+struct WireReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> WireReader<'a> {
+    fn varint(&mut self) -> Result<u64, &'static str> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let (byte, rest) = self.data.split_first().ok_or("a varint is cut short")?;
+            self.data = rest;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err("a varint is too long")
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], &'static str> {
+        if self.data.len() < len {
+            return Err("a field is cut short");
+        }
+        let (taken, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(taken)
+    }
+
+    /// The next field number and value, `None` at the end of the message.
+    fn next_field(&mut self) -> Result<Option<(u64, WireField<'a>)>, &'static str> {
+        if self.data.is_empty() {
+            return Ok(None);
+        }
+        let key = self.varint()?;
+        let field = match key & 7 {
+            0 => WireField::Varint(self.varint()?),
+            1 => {
+                self.take(8)?;
+                WireField::Fixed64
+            }
+            2 => {
+                let len = self.varint()? as usize;
+                WireField::Bytes(self.take(len)?)
+            }
+            5 => {
+                let bytes = self.take(4)?;
+                WireField::Fixed32([bytes[0], bytes[1], bytes[2], bytes[3]])
+            }
+            _ => return Err("an unsupported wire type"),
+        };
+        Ok(Some((key >> 3, field)))
+    }
+}
+
+/// The values of one `Feature`, if it's the kind we're looking for.
+enum FeatureValues {
+    Floats(Vec<f32>),
+    Ints(Vec<i64>),
+    Other,
+}
+
+fn parse_feature(data: &[u8]) -> Result<FeatureValues, &'static str> {
+    let mut reader = WireReader { data };
+    let mut values = FeatureValues::Other;
+    while let Some((number, field)) = reader.next_field()? {
+        // bytes_list = 1, float_list = 2, int64_list = 3, each a message with `repeated value = 1`
+        let list = match (number, field) {
+            (2, WireField::Bytes(list)) | (3, WireField::Bytes(list)) => list,
+            _ => continue,
+        };
+        let mut list_reader = WireReader { data: list };
+        let mut floats = Vec::new();
+        let mut ints = Vec::new();
+        while let Some((value_number, value)) = list_reader.next_field()? {
+            match (number, value_number, value) {
+                // Packed
+                (2, 1, WireField::Bytes(packed)) => {
+                    if packed.len() % 4 != 0 {
+                        return Err("a packed float list isn't whole floats");
+                    }
+                    floats.extend(
+                        packed
+                            .chunks_exact(4)
+                            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+                    );
+                }
+                (2, 1, WireField::Fixed32(b)) => floats.push(f32::from_le_bytes(b)),
+                (3, 1, WireField::Bytes(packed)) => {
+                    let mut packed_reader = WireReader { data: packed };
+                    while !packed_reader.data.is_empty() {
+                        ints.push(packed_reader.varint()? as i64);
+                    }
+                }
+                (3, 1, WireField::Varint(v)) => ints.push(v as i64),
+                _ => {}
+            }
+        }
+        values = if number == 2 {
+            FeatureValues::Floats(floats)
+        } else {
+            FeatureValues::Ints(ints)
+        };
+    }
+    Ok(values)
+}
+
+/// Finds the two features in a serialized `Example`.
+fn parse_example(
+    data: &[u8],
+    feature_key: &str,
+    label_key: Option<&str>,
+) -> Result<(Option<Vec<f32>>, Option<Vec<i64>>), &'static str> {
+    let mut features = None;
+    let mut label = None;
+    let mut example = WireReader { data };
+    // Example.features = 1
+    while let Some((number, field)) = example.next_field()? {
+        let features_message = match (number, field) {
+            (1, WireField::Bytes(message)) => message,
+            _ => continue,
+        };
+        let mut features_reader = WireReader {
+            data: features_message,
+        };
+        // Features.feature = 1, a map entry with key = 1 and value = 2
+        while let Some((number, field)) = features_reader.next_field()? {
+            let entry = match (number, field) {
+                (1, WireField::Bytes(entry)) => entry,
+                _ => continue,
+            };
+            let mut entry_reader = WireReader { data: entry };
+            let mut key = None;
+            let mut value = None;
+            while let Some((number, field)) = entry_reader.next_field()? {
+                match (number, field) {
+                    (1, WireField::Bytes(k)) => key = Some(k),
+                    (2, WireField::Bytes(v)) => value = Some(v),
+                    _ => {}
+                }
+            }
+            let (key, value) = match (key, value) {
+                (Some(key), Some(value)) => (key, value),
+                _ => continue,
+            };
+            if key == feature_key.as_bytes() {
+                match parse_feature(value)? {
+                    FeatureValues::Floats(floats) => features = Some(floats),
+                    _ => return Err("the feature isn't a float list"),
+                }
+            } else if label_key.map(|l| key == l.as_bytes()).unwrap_or(false) {
+                match parse_feature(value)? {
+                    FeatureValues::Ints(ints) => label = Some(ints),
+                    _ => return Err("the label isn't an int64 list"),
+                }
+            }
+        }
+    }
+    Ok((features, label))
+}
+
+/// Reads the examples of a set of TFRecord files, in order.
+fn read_tfrecords<P: AsRef<Path>>(
+    paths: &[P],
+    feature_key: &str,
+    label_key: Option<&str>,
+) -> PointCloudResult<(Vec<f32>, usize, Vec<i64>, Vec<bool>)> {
+    let mut values = Vec::new();
+    let mut dim = None;
+    let mut labels = Vec::new();
+    let mut mask = Vec::new();
+    let mut data = Vec::new();
+    for path in paths {
+        let file_name = path.as_ref().to_string_lossy().to_string();
+        let mut reader = open_decompressed(path)?;
+        let mut record = 0;
+        while read_record(&mut reader, &file_name, record, &mut data)? {
+            let (features, label) = parse_example(&data, feature_key, label_key)
+                .map_err(|reason| tfrecord_error(&file_name, record, reason.to_string()))?;
+            let features = features.ok_or_else(|| {
+                tfrecord_error(
+                    &file_name,
+                    record,
+                    format!("there's no {} feature", feature_key),
+                )
+            })?;
+            match dim {
+                Some(dim) if dim != features.len() => {
+                    return Err(tfrecord_error(
+                        &file_name,
+                        record,
+                        format!(
+                            "the feature has {} values, the ones before it have {}",
+                            features.len(),
+                            dim
+                        ),
+                    ))
+                }
+                _ => dim = Some(features.len()),
+            }
+            values.extend(features);
+            // Examples without a label, or with an empty one, are unlabeled
+            match label.as_ref().and_then(|l| l.first()) {
+                Some(label) => {
+                    labels.push(*label);
+                    mask.push(true);
+                }
+                None => {
+                    labels.push(0);
+                    mask.push(false);
+                }
+            }
+            record += 1;
+        }
+    }
+    match dim {
+        Some(dim) => Ok((values, dim, labels, mask)),
+        None => Err(tfrecord_error("", 0, "there are no records".to_string())),
+    }
+}
+
+/// Reads the float list feature `feature_key` of every example in the TFRecord files into ram.
+pub fn ram_from_tfrecords<P: AsRef<Path>, M: Metric<[f32]> + Default>(
+    paths: &[P],
+    feature_key: &str,
+) -> PointCloudResult<DataRam<M>> {
+    let (values, dim, _, _) = read_tfrecords(paths, feature_key, None)?;
+    DataRam::new(values, dim)
+}
+
+/// Reads the float list feature `feature_key` and the int64 feature `label_key` of every example
+/// in the TFRecord files. Examples without the label are unlabeled, and only the first value of a
+/// label list is used.
+pub fn labeled_ram_from_tfrecords<P: AsRef<Path>, M: Metric<[f32]> + Default>(
+    paths: &[P],
+    feature_key: &str,
+    label_key: &str,
+) -> PointCloudResult<DefaultLabeledCloud<M>> {
+    let (values, dim, labels, mask) = read_tfrecords(paths, feature_key, Some(label_key))?;
+    let mask = if mask.iter().all(|m| *m) {
+        None
+    } else {
+        Some(mask)
+    };
+    Ok(SimpleLabeledCloud::new(
+        DataRam::new(values, dim)?,
+        SmallIntLabels::new(labels, mask),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::L2;
+    use std::fs;
+    use tempdir::TempDir;
+
+    fn varint(mut v: u64, out: &mut Vec<u8>) {
+        while v >= 0x80 {
+            out.push((v as u8) | 0x80);
+            v >>= 7;
+        }
+        out.push(v as u8);
+    }
+
+    fn bytes_field(number: u64, data: &[u8], out: &mut Vec<u8>) {
+        varint((number << 3) | 2, out);
+        varint(data.len() as u64, out);
+        out.extend_from_slice(data);
+    }
+
+    fn feature_entry(key: &str, feature: &[u8], out: &mut Vec<u8>) {
+        let mut entry = Vec::new();
+        bytes_field(1, key.as_bytes(), &mut entry);
+        bytes_field(2, feature, &mut entry);
+        bytes_field(1, &entry, out);
+    }
+
+    fn example(features: &[f32], label: Option<i64>) -> Vec<u8> {
+        let mut packed = Vec::new();
+        features
+            .iter()
+            .for_each(|f| packed.extend(&f.to_le_bytes()));
+        let mut float_list = Vec::new();
+        bytes_field(1, &packed, &mut float_list);
+        let mut feature = Vec::new();
+        bytes_field(2, &float_list, &mut feature);
+
+        let mut feature_map = Vec::new();
+        feature_entry("embedding", &feature, &mut feature_map);
+        if let Some(label) = label {
+            // Unpacked, like some writers do
+            let mut int_list = Vec::new();
+            varint(1 << 3, &mut int_list);
+            varint(label as u64, &mut int_list);
+            let mut feature = Vec::new();
+            bytes_field(3, &int_list, &mut feature);
+            feature_entry("label", &feature, &mut feature_map);
+        }
+        let mut example = Vec::new();
+        bytes_field(1, &feature_map, &mut example);
+        example
+    }
+
+    fn record(data: &[u8], out: &mut Vec<u8>) {
+        let length = (data.len() as u64).to_le_bytes();
+        out.extend_from_slice(&length);
+        out.extend_from_slice(&masked_crc32c(&length).to_le_bytes());
+        out.extend_from_slice(data);
+        out.extend_from_slice(&masked_crc32c(data).to_le_bytes());
+    }
+
+    #[test]
+    fn crc32c_matches_known_values() {
+        assert_eq!(crc32c(b""), 0);
+        assert_eq!(crc32c(b"123456789"), 0xe306_9283);
+    }
+
+    #[test]
+    fn reads_examples() {
+        let dir = TempDir::new("tfrecord_loaders").unwrap();
+        let mut contents = Vec::new();
+        record(&example(&[1.0, 2.0], Some(3)), &mut contents);
+        record(&example(&[-1.0, 0.5], None), &mut contents);
+        record(&example(&[4.0, 4.0], Some(7)), &mut contents);
+        let path = dir.path().join("embeddings.tfrecord");
+        fs::write(&path, &contents).unwrap();
+
+        let cloud = labeled_ram_from_tfrecords::<_, L2>(&[&path], "embedding", "label").unwrap();
+        assert_eq!(cloud.len(), 3);
+        assert_eq!(cloud.dim(), 2);
+        assert_eq!(cloud.point(1).unwrap(), &[-1.0, 0.5][..]);
+        assert_eq!(cloud.label(0).unwrap(), Some(&3));
+        assert_eq!(cloud.label(1).unwrap(), None);
+        assert_eq!(cloud.label(2).unwrap(), Some(&7));
+
+        let data = ram_from_tfrecords::<_, L2>(&[&path, &path], "embedding").unwrap();
+        assert_eq!(data.len(), 6);
+        assert!(ram_from_tfrecords::<_, L2>(&[&path], "missing").is_err());
+
+        // A flipped byte fails the checksum
+        contents[20] ^= 1;
+        fs::write(&path, &contents).unwrap();
+        assert!(ram_from_tfrecords::<_, L2>(&[&path], "embedding").is_err());
+    }
+}
@@ -0,0 +1,250 @@
+//! Loaders for the `.fvecs`, `.bvecs` and `.ivecs` formats of the SIFT, GIST and BIGANN datasets
+//! that ann-benchmarks and FAISS use. Each vector is stored as a little endian `i32` dimension
+//! followed by that many values, `f32`s, `u8`s or `i32`s. Every vector in a file has the same
+//! dimension. The `.ivecs` files are usually ground truth, each vector is the indexes of a query's
+//! nearest neighbors in the base set, closest first.
+//!
+//! The files can be gzip or zstandard compressed, see [`open_decompressed`].
+
+use std::io::{ErrorKind, Read};
+
+use super::*;
+
+fn vecs_error(file_name: &str, reason: String) -> PointCloudError {
+    PointCloudError::ParsingError(ParsingError::VecsError {
+        file_name: file_name.to_string(),
+        reason,
+    })
+}
+
+/// Reads the dimension at the start of a vector. `None` at the end of the file.
+pub(super) fn read_vec_dim<R: Read>(reader: &mut R, file_name: &str) -> PointCloudResult<Option<usize>> {
+    let mut bytes = [0u8; 4];
+    let mut filled = 0;
+    while filled < 4 {
+        match reader.read(&mut bytes[filled..]) {
+            Ok(0) if filled == 0 => return Ok(None),
+            Ok(0) => {
+                return Err(vecs_error(
+                    file_name,
+                    "the file ends in the middle of a dimension".to_string(),
+                ))
+            }
+            Ok(read) => filled += read,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    let dim = i32::from_le_bytes(bytes);
+    if dim <= 0 {
+        return Err(vecs_error(file_name, format!("{} isn't a dimension", dim)));
+    }
+    Ok(Some(dim as usize))
+}
+
+/// Reads up to `max_vecs` vectors of `elem_size` byte values, decoding each value with `decode`.
+/// Returns the values, one vector after the other, and the dimension.
+fn read_vecs<P: AsRef<Path>, T, F: Fn(&[u8]) -> T>(
+    path: P,
+    elem_size: usize,
+    max_vecs: Option<usize>,
+    decode: F,
+) -> PointCloudResult<(Vec<T>, usize)> {
+    let file_name = path.as_ref().to_string_lossy().to_string();
+    let mut reader = open_decompressed(&path)?;
+    let mut values = Vec::new();
+    let mut file_dim = None;
+    let mut buffer = Vec::new();
+    let mut count = 0;
+    while max_vecs.map(|m| count < m).unwrap_or(true) {
+        let dim = match read_vec_dim(&mut reader, &file_name)? {
+            Some(dim) => dim,
+            None => break,
+        };
+        match file_dim {
+            Some(file_dim) if file_dim != dim => {
+                return Err(vecs_error(
+                    &file_name,
+                    format!(
+                        "vector {} has dimension {}, the ones before it have {}",
+                        count, dim, file_dim
+                    ),
+                ))
+            }
+            _ => file_dim = Some(dim),
+        }
+        buffer.resize(dim * elem_size, 0);
+        reader.read_exact(&mut buffer).map_err(|e| {
+            if e.kind() == ErrorKind::UnexpectedEof {
+                vecs_error(
+                    &file_name,
+                    format!("the file ends in the middle of vector {}", count),
+                )
+            } else {
+                e.into()
+            }
+        })?;
+        values.extend(buffer.chunks_exact(elem_size).map(&decode));
+        count += 1;
+    }
+    match file_dim {
+        Some(dim) => Ok((values, dim)),
+        None => Err(vecs_error(&file_name, "there are no vectors".to_string())),
+    }
+}
+
+/// Reads a `.fvecs` file into ram. At most `max_points` are read if it's set, the big datasets
+/// are often used by their first million or so.
+pub fn ram_from_fvecs<P: AsRef<Path>, M: Metric<[f32]> + Default>(
+    path: P,
+    max_points: Option<usize>,
+) -> PointCloudResult<DataRam<M>> {
+    let (values, dim) = read_vecs(path, 4, max_points, |b| {
+        f32::from_le_bytes([b[0], b[1], b[2], b[3]])
+    })?;
+    DataRam::new(values, dim)
+}
+
+/// Reads a `.bvecs` file into ram, converting the bytes to floats. At most `max_points` are read
+/// if it's set.
+pub fn ram_from_bvecs<P: AsRef<Path>, M: Metric<[f32]> + Default>(
+    path: P,
+    max_points: Option<usize>,
+) -> PointCloudResult<DataRam<M>> {
+    let (values, dim) = read_vecs(path, 1, max_points, |b| b[0] as f32)?;
+    DataRam::new(values, dim)
+}
+
+/// Reads a `.ivecs` file, one vector of integers for each vector in the file.
+pub fn read_ivecs<P: AsRef<Path>>(
+    path: P,
+    max_vecs: Option<usize>,
+) -> PointCloudResult<Vec<Vec<i32>>> {
+    let (values, dim) = read_vecs(path, 4, max_vecs, |b| {
+        i32::from_le_bytes([b[0], b[1], b[2], b[3]])
+    })?;
+    Ok(values.chunks_exact(dim).map(|v| v.to_vec()).collect())
+}
+
+/// Reads the ground truth of a benchmark from a `.ivecs` file, the indexes of each query's nearest
+/// neighbors, closest first. Only the first `k` of each are kept if it's set.
+pub fn ground_truth_from_ivecs<P: AsRef<Path>>(
+    path: P,
+    k: Option<usize>,
+) -> PointCloudResult<Vec<Vec<usize>>> {
+    let file_name = path.as_ref().to_string_lossy().to_string();
+    read_ivecs(&path, None)?
+        .iter()
+        .map(|neighbors| {
+            let k = k.unwrap_or(neighbors.len()).min(neighbors.len());
+            neighbors[..k]
+                .iter()
+                .map(|i| {
+                    if *i < 0 {
+                        Err(vecs_error(&file_name, format!("{} isn't an index", i)))
+                    } else {
+                        Ok(*i as usize)
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// The recall at `k` of a set of results against the ground truth, the fraction of each query's
+/// true `k` nearest neighbors that are in its first `k` results, averaged over the queries.
+pub fn recall_at_k(results: &[Vec<usize>], ground_truth: &[Vec<usize>], k: usize) -> f32 {
+    let mut found = 0;
+    let mut total = 0;
+    for (result, truth) in results.iter().zip(ground_truth) {
+        let truth = &truth[..k.min(truth.len())];
+        let result = &result[..k.min(result.len())];
+        found += truth.iter().filter(|i| result.contains(i)).count();
+        total += truth.len();
+    }
+    if total == 0 {
+        0.0
+    } else {
+        found as f32 / total as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::L2;
+    use std::fs;
+    use tempdir::TempDir;
+
+    fn vecs_bytes<T: Copy, F: Fn(T) -> Vec<u8>>(vecs: &[Vec<T>], encode: F) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for v in vecs {
+            bytes.extend_from_slice(&(v.len() as i32).to_le_bytes());
+            for x in v {
+                bytes.extend(encode(*x));
+            }
+        }
+        bytes
+    }
+
+    #[test]
+    fn reads_fvecs_and_bvecs() {
+        let dir = TempDir::new("vecs_loaders").unwrap();
+        let fvecs = vec![vec![0.5f32, 1.0, -2.0], vec![3.0, 4.0, 5.5]];
+        let fvecs_path = dir.path().join("base.fvecs");
+        fs::write(
+            &fvecs_path,
+            vecs_bytes(&fvecs, |x| x.to_le_bytes().to_vec()),
+        )
+        .unwrap();
+        let cloud = ram_from_fvecs::<_, L2>(&fvecs_path, None).unwrap();
+        assert_eq!(cloud.len(), 2);
+        assert_eq!(cloud.dim(), 3);
+        assert_eq!(cloud.point(1).unwrap(), &fvecs[1][..]);
+        assert_eq!(
+            ram_from_fvecs::<_, L2>(&fvecs_path, Some(1)).unwrap().len(),
+            1
+        );
+
+        let bvecs = vec![vec![0u8, 255], vec![7, 8], vec![9, 10]];
+        let bvecs_path = dir.path().join("base.bvecs");
+        fs::write(&bvecs_path, vecs_bytes(&bvecs, |x| vec![x])).unwrap();
+        let cloud = ram_from_bvecs::<_, L2>(&bvecs_path, None).unwrap();
+        assert_eq!(cloud.len(), 3);
+        assert_eq!(cloud.point(0).unwrap(), &[0.0, 255.0][..]);
+    }
+
+    #[test]
+    fn reads_ground_truth() {
+        let dir = TempDir::new("vecs_loaders").unwrap();
+        let ivecs = vec![vec![3i32, 1, 2], vec![0, 2, 1]];
+        let path = dir.path().join("groundtruth.ivecs");
+        fs::write(&path, vecs_bytes(&ivecs, |x| x.to_le_bytes().to_vec())).unwrap();
+        assert_eq!(read_ivecs(&path, None).unwrap(), ivecs);
+        let truth = ground_truth_from_ivecs(&path, Some(2)).unwrap();
+        assert_eq!(truth, vec![vec![3, 1], vec![0, 2]]);
+
+        let results = vec![vec![1, 3], vec![0, 5]];
+        assert_approx_eq!(recall_at_k(&results, &truth, 2), 0.75);
+        assert_approx_eq!(recall_at_k(&results, &truth, 1), 0.5);
+    }
+
+    #[test]
+    fn malformed_files_are_errors() {
+        let dir = TempDir::new("vecs_loaders").unwrap();
+        let path = dir.path().join("bad.fvecs");
+        // The second vector has a different dimension
+        let vecs = vec![vec![1.0f32, 2.0], vec![3.0]];
+        fs::write(&path, vecs_bytes(&vecs, |x| x.to_le_bytes().to_vec())).unwrap();
+        assert!(ram_from_fvecs::<_, L2>(&path, None).is_err());
+
+        // Cut off in the middle of a vector
+        let vecs = vec![vec![1.0f32, 2.0]];
+        let bytes = vecs_bytes(&vecs, |x| x.to_le_bytes().to_vec());
+        fs::write(&path, &bytes[..bytes.len() - 2]).unwrap();
+        assert!(ram_from_fvecs::<_, L2>(&path, None).is_err());
+
+        fs::write(&path, b"").unwrap();
+        assert!(ram_from_fvecs::<_, L2>(&path, None).is_err());
+    }
+}
@@ -6,7 +6,9 @@ use yaml_rust::YamlLoader;
 use log::{info, trace};
 
 use super::*;
-use crate::metrics::L2;
+use crate::metrics::{WeightedL2, L2};
+use crate::missing_values::NanPolicy;
+use crate::normalization::Normalization;
 use crate::DefaultLabeledCloud;
 
 /// Given a yaml file on disk, it builds a point cloud. Minimal example below.
@@ -18,7 +20,9 @@ use crate::DefaultLabeledCloud;
 /// data_dim: 784
 /// label_csv_index: 2
 /// ```
-pub fn labeled_ram_from_yaml<P: AsRef<Path>, M: Metric<[f32]>>(
+/// The paths can also be `https://` or `s3://` URLs, which are downloaded into a local cache the
+/// first time they're read. See [`fetch_cached`].
+pub fn labeled_ram_from_yaml<P: AsRef<Path>, M: Metric<[f32]> + Default>(
     path: P,
 ) -> PointCloudResult<DefaultLabeledCloud<M>> {
     let label_set = labels_from_yaml(&path)?;
@@ -36,7 +40,7 @@ pub fn labeled_ram_from_yaml<P: AsRef<Path>, M: Metric<[f32]>>(
 /// data_dim: 784
 /// label_dim: 10
 /// ```
-pub fn vec_labeled_ram_from_yaml<P: AsRef<Path>, M: Metric<[f32]>>(
+pub fn vec_labeled_ram_from_yaml<P: AsRef<Path>, M: Metric<[f32]> + Default>(
     path: P,
 ) -> PointCloudResult<SimpleLabeledCloud<DataRam<M>, VecLabels>> {
     info!("Opening labeled pointcloud yaml with path {:?}", &path.as_ref());
@@ -49,14 +53,14 @@ pub fn vec_labeled_ram_from_yaml<P: AsRef<Path>, M: Metric<[f32]>>(
         params_files["data_path"]
             .as_str()
             .expect("Unable to read the 'data_path'"),
-        path.as_ref(),
-    );
+        path.as_ref().parent().unwrap(),
+    )?;
     let labels_path = &get_file_list(
         params_files["labels_path"]
             .as_str()
             .expect("Unable to read the 'labels_path'"),
-        path.as_ref(),
-    );
+        path.as_ref().parent().unwrap(),
+    )?;
 
     let data_dim = params_files["data_dim"]
         .as_i64()
@@ -66,40 +70,188 @@ pub fn vec_labeled_ram_from_yaml<P: AsRef<Path>, M: Metric<[f32]>>(
         .as_i64()
         .expect("Unable to read the 'labels_dim'") as usize;
 
-    let label_set = convert_glued_memmap_to_ram::<L2>(open_memmaps(labels_dim, labels_path)?)
-        .convert_to_labels();
-    let data_set = convert_glued_memmap_to_ram(open_memmaps(data_dim, data_paths)?);
+    let label_set = ram_from_f32_files::<L2>(labels_dim, labels_path)?.convert_to_labels();
+    let data_set = ram_from_f32_files(data_dim, data_paths)?;
 
     Ok(SimpleLabeledCloud::new(data_set, label_set))
 }
 
-/// Given a yaml file on disk, it builds a point cloud. Minimal example below.
+/// Given a yaml file on disk, it builds a point cloud. Minimal example below. Any of the files the
+/// yaml points at can be gzip or zstandard compressed, ending in `.gz` or `.zst`. They're
+/// decompressed as they're read, see [`open_decompressed`].
 /// ```yaml
 /// ---
 /// data_path: DATAMEMMAP
 /// count: NUMBER_OF_DATA_POINTS
 /// data_dim: 784
 /// ```
-pub fn ram_from_yaml<P: AsRef<Path>, M: Metric<[f32]>>(path: P) -> PointCloudResult<DataRam<M>> {
+/// A `shards` list of files and globs can be given instead of the `data_path`, they're read in
+/// parallel. See [`ram_from_shards`]. The dimensions can be named with a `dim_names` list, one
+/// name per dimension. A `normalization` of `z_score`, `min_max` or `l2` is fitted to the data
+/// once it's loaded, see [`crate::normalization`]. A `nan_policy` of `reject`, `impute_mean` or
+/// `ignore_pairwise` handles the missing values before that, see [`crate::missing_values`].
+pub fn ram_from_yaml<P: AsRef<Path>, M: Metric<[f32]> + Default>(
+    path: P,
+) -> PointCloudResult<DataRam<M>> {
     info!("Opening unlabeled pointcloud yaml with path {:?}", &path.as_ref());
     let config = fs::read_to_string(&path)
         .unwrap_or_else(|_| panic!("Unable to read config file {:?}", &path.as_ref()));
 
     let params_files = &YamlLoader::load_from_str(&config).unwrap()[0];
 
-    let data_paths = &get_file_list(
-        params_files["data_path"]
-            .as_str()
-            .expect("Unable to read the 'data_path'"),
-        path.as_ref(),
-    );
-
     let data_dim = params_files["data_dim"]
         .as_i64()
         .expect("Unable to read the 'data_dim'") as usize;
 
-    let data_set = open_memmaps(data_dim, data_paths)?;
-    Ok(convert_glued_memmap_to_ram(data_set))
+    let dim_names = dim_names_from_yaml(path.as_ref(), params_files)?;
+    let normalization = normalization_from_yaml(path.as_ref(), params_files)?;
+    let nan_policy = nan_policy_from_yaml(path.as_ref(), params_files)?;
+    let data = if let Some(shards) = yaml_shards(path.as_ref(), params_files, "shards")? {
+        ram_from_shards(data_dim, &shards)?.0
+    } else {
+        let data_paths = &get_file_list(
+            params_files["data_path"]
+                .as_str()
+                .expect("Unable to read the 'data_path'"),
+            path.as_ref().parent().unwrap(),
+        )?;
+        ram_from_f32_files(data_dim, data_paths)?
+    };
+    let data = match dim_names {
+        Some(names) => data.with_dim_names(names)?,
+        None => data,
+    };
+    let data = match nan_policy {
+        Some(policy) => data.with_nan_policy(policy)?,
+        None => data,
+    };
+    match normalization {
+        Some(kind) => data.with_normalization(kind),
+        None => Ok(data),
+    }
+}
+
+/// Reads the `nan_policy`, `None` if there isn't one.
+fn nan_policy_from_yaml(
+    path: &Path,
+    params_files: &yaml_rust::Yaml,
+) -> PointCloudResult<Option<NanPolicy>> {
+    let entry = &params_files["nan_policy"];
+    if entry.is_badvalue() {
+        return Ok(None);
+    }
+    let policy = entry
+        .as_str()
+        .and_then(|policy| policy.parse().ok())
+        .ok_or_else(|| ParsingError::MalformedYamlError {
+            file_name: path.to_string_lossy().to_string(),
+            field: "nan_policy".to_string(),
+        })?;
+    Ok(Some(policy))
+}
+
+/// Reads the `normalization` kind, `None` if there isn't one.
+fn normalization_from_yaml(
+    path: &Path,
+    params_files: &yaml_rust::Yaml,
+) -> PointCloudResult<Option<Normalization>> {
+    let entry = &params_files["normalization"];
+    if entry.is_badvalue() {
+        return Ok(None);
+    }
+    let kind = entry
+        .as_str()
+        .and_then(|kind| kind.parse().ok())
+        .ok_or_else(|| ParsingError::MalformedYamlError {
+            file_name: path.to_string_lossy().to_string(),
+            field: "normalization".to_string(),
+        })?;
+    Ok(Some(kind))
+}
+
+/// Reads the `dim_names` list, `None` if there isn't one.
+fn dim_names_from_yaml(
+    path: &Path,
+    params_files: &yaml_rust::Yaml,
+) -> PointCloudResult<Option<Vec<String>>> {
+    let names_entry = &params_files["dim_names"];
+    if names_entry.is_badvalue() {
+        return Ok(None);
+    }
+    let malformed = || ParsingError::MalformedYamlError {
+        file_name: path.to_string_lossy().to_string(),
+        field: "dim_names".to_string(),
+    };
+    let names = names_entry
+        .as_vec()
+        .ok_or_else(malformed)?
+        .iter()
+        .map(|n| n.as_str().map(|n| n.to_string()).ok_or_else(malformed))
+        .collect::<Result<Vec<String>, ParsingError>>()?;
+    Ok(Some(names))
+}
+
+/// Reads the per-dimension weights of a [`WeightedL2`] metric from the yaml file. If there's no
+/// `metric_weights` entry this is the unweighted default.
+/// ```yaml
+/// ---
+/// data_dim: 3
+/// metric_weights: [1.0, 0.5, 2.0]
+/// ```
+pub fn weighted_l2_from_yaml<P: AsRef<Path>>(path: P) -> PointCloudResult<WeightedL2> {
+    let config = fs::read_to_string(&path)
+        .unwrap_or_else(|_| panic!("Unable to read config file {:?}", &path.as_ref()));
+    let params_files = &YamlLoader::load_from_str(&config).unwrap()[0];
+
+    let weights_entry = &params_files["metric_weights"];
+    if weights_entry.is_badvalue() {
+        return Ok(WeightedL2::default());
+    }
+    let malformed = || ParsingError::MalformedYamlError {
+        file_name: path.as_ref().to_string_lossy().to_string(),
+        field: "metric_weights".to_string(),
+    };
+    let weights = weights_entry
+        .as_vec()
+        .ok_or_else(malformed)?
+        .iter()
+        .map(|w| {
+            w.as_f64()
+                .or_else(|| w.as_i64().map(|i| i as f64))
+                .map(|w| w as f32)
+                .ok_or_else(malformed)
+        })
+        .collect::<Result<Vec<f32>, ParsingError>>()?;
+    WeightedL2::new(weights)
+}
+
+/// Given a yaml file on disk, it builds a point cloud that uses a weighted L2 metric. The weights
+/// are read with [`weighted_l2_from_yaml`]. Minimal example below.
+/// ```yaml
+/// ---
+/// data_path: DATAMEMMAP
+/// labels_path: LABELS_CSV
+/// count: NUMBER_OF_DATA_POINTS
+/// data_dim: 3
+/// label_csv_index: 2
+/// metric_weights: [1.0, 0.5, 2.0]
+/// ```
+pub fn weighted_labeled_ram_from_yaml<P: AsRef<Path>>(
+    path: P,
+) -> PointCloudResult<DefaultLabeledCloud<WeightedL2>> {
+    let metric = weighted_l2_from_yaml(&path)?;
+    let label_set = labels_from_yaml(&path)?;
+    let data_set = ram_from_yaml::<_, L2>(&path)?;
+    if !metric.weights().is_empty() && metric.weights().len() != data_set.dim() {
+        return Err(PointCloudError::MetricParameterError {
+            message: "the number of metric weights must match the data dimension",
+        });
+    }
+
+    Ok(SimpleLabeledCloud::new(
+        data_set.replace_metric(metric),
+        label_set,
+    ))
 }
 
 /// Given a yaml file on disk, it builds a point cloud. Minimal example below.
@@ -118,30 +270,66 @@ pub fn labels_from_yaml<P: AsRef<Path>>(path: P) -> PointCloudResult<SmallIntLab
     let path: &Path = path.as_ref();
     let params_files = &YamlLoader::load_from_str(&config).unwrap()[0];
 
+    let labels_index = params_files["labels_index"].as_i64().map(|i| i as usize);
+    let labels_dim = params_files["labels_dim"].as_i64().map(|i| i as usize);
+    if let Some(shards) = yaml_shards(path, params_files, "labels_shards")? {
+        return Ok(labels_from_shards(&shards, labels_index, labels_dim)?.0);
+    }
+
     trace!("Label path list, pre glob: {:?}", params_files["labels_path"]);
     let labels_path = &get_file_list(
         params_files["labels_path"]
             .as_str()
             .expect("Unable to read the 'labels_path'"),
-        path,
-    );
+        path.parent().unwrap(),
+    )?;
     trace!("Label path list, post glob: {:?}", labels_path);
 
-    let labels_index = params_files["labels_index"].as_i64().map(|i| i as usize);
-    let labels_dim = params_files["labels_dim"].as_i64().map(|i| i as usize);
+    labels_from_files(labels_path, labels_index, labels_dim)
+}
 
+/// Expands a yaml list of shard files and globs, `None` if the entry isn't there.
+fn yaml_shards(
+    path: &Path,
+    params: &yaml_rust::Yaml,
+    field: &str,
+) -> PointCloudResult<Option<Vec<PathBuf>>> {
+    let entry = &params[field];
+    if entry.is_badvalue() {
+        return Ok(None);
+    }
+    let malformed = || ParsingError::MalformedYamlError {
+        file_name: path.to_string_lossy().to_string(),
+        field: field.to_string(),
+    };
+    let patterns = entry
+        .as_vec()
+        .ok_or_else(malformed)?
+        .iter()
+        .map(|p| p.as_str().ok_or_else(malformed))
+        .collect::<Result<Vec<&str>, ParsingError>>()?;
+    Ok(Some(shard_paths(&patterns, path.parent().unwrap())?))
+}
+
+/// Opens each label file and concatenates them. CSVs need the column index, memmaps the dimension.
+pub(super) fn labels_from_files(
+    labels_path: &[PathBuf],
+    labels_index: Option<usize>,
+    labels_dim: Option<usize>,
+) -> PointCloudResult<SmallIntLabels> {
     let mut label_set: Vec<SmallIntLabels> = labels_path
         .iter()
         .map(|path| {
             info!("Opening label file with path {:?}", path);
-            match (
-                path.extension().unwrap().to_str().unwrap(),
-                labels_index,
-                labels_dim,
-            ) {
-                ("csv", Some(index), _) | ("gz", Some(index), _) => open_int_csv(&path, index),
-                ("dat", _, Some(dim)) => {
-                    let labels: VecLabels = DataMemmap::<L2>::new(dim, &path)?.convert_to_labels();
+            match (content_extension(path), labels_index, labels_dim) {
+                (Some("csv"), Some(index), _) => open_int_csv(&path, index),
+                // A bare `labels.gz` is a compressed CSV
+                (None, Some(index), _) if Compression::from_path(path).is_compressed() => {
+                    open_int_csv(&path, index)
+                }
+                (Some("dat"), _, Some(dim)) => {
+                    let labels: VecLabels =
+                        ram_from_f32_files::<L2>(dim, &[path.clone()])?.convert_to_labels();
 
                     match dim.cmp(&1) {
                         Ordering::Greater => Ok(labels.one_hot_to_int()),
@@ -171,7 +359,12 @@ pub fn labels_from_yaml<P: AsRef<Path>>(path: P) -> PointCloudResult<SmallIntLab
         .unwrap())
 }
 
-fn get_file_list(files_reg: &str, yaml_path: &Path) -> Vec<PathBuf> {
+/// Globs for the files, relative paths are relative to the directory the config is in. URLs are
+/// downloaded into the cache, see [`fetch_cached`].
+pub(super) fn get_file_list(files_reg: &str, base_dir: &Path) -> PointCloudResult<Vec<PathBuf>> {
+    if is_remote(files_reg) {
+        return Ok(vec![fetch_cached(files_reg)?]);
+    }
     let options = MatchOptions {
         case_sensitive: false,
         ..Default::default()
@@ -186,11 +379,9 @@ fn get_file_list(files_reg: &str, yaml_path: &Path) -> Vec<PathBuf> {
             Err(e) => panic!("Pattern reading error {:?}", e),
         };
     } else {
-        trace!("label path is not absolute, joining it with the yaml path files: {:?} , yaml: {:?}", files_reg_path, yaml_path);
+        trace!("label path is not absolute, joining it with the config's directory files: {:?} , directory: {:?}", files_reg_path, base_dir);
         glob_paths = match glob_with(
-            &yaml_path
-                .parent()
-                .unwrap()
+            &base_dir
                 .join(files_reg_path)
                 .to_str()
                 .unwrap(),
@@ -208,5 +399,5 @@ fn get_file_list(files_reg: &str, yaml_path: &Path) -> Vec<PathBuf> {
         };
         paths.push(path)
     }
-    paths
+    Ok(paths)
 }
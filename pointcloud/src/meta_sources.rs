@@ -0,0 +1,113 @@
+//! Metadata stores to glue onto the data sources, see [`crate::SimpleMetaCloud`].
+
+use crate::base_traits::*;
+use crate::pc_errors::*;
+use crate::summaries::CountSummary;
+use hashbrown::HashMap;
+use serde::Serialize;
+use std::fmt::Debug;
+
+/// Per point metadata, like document ids, urls or JSON values, for joining query results back to
+/// where the points came from. Points don't need to have any. The node summary just counts the
+/// points with metadata.
+#[derive(Debug, Clone)]
+pub struct MetadataMap<T = serde_json::Value> {
+    values: HashMap<usize, T>,
+}
+
+impl<T> Default for MetadataMap<T> {
+    fn default() -> Self {
+        MetadataMap {
+            values: HashMap::new(),
+        }
+    }
+}
+
+impl<T> MetadataMap<T> {
+    /// An empty store.
+    pub fn new() -> MetadataMap<T> {
+        Self::default()
+    }
+
+    /// Takes the metadata of the points in index order, `None` for points without any.
+    pub fn from_values(values: Vec<Option<T>>) -> MetadataMap<T> {
+        MetadataMap {
+            values: values
+                .into_iter()
+                .enumerate()
+                .filter_map(|(pi, v)| v.map(|v| (pi, v)))
+                .collect(),
+        }
+    }
+
+    /// Sets the metadata of a point, returning what it had before.
+    pub fn insert(&mut self, pi: usize, value: T) -> Option<T> {
+        self.values.insert(pi, value)
+    }
+
+    /// Removes the metadata of a point, returning what it had.
+    pub fn remove(&mut self, pi: usize) -> Option<T> {
+        self.values.remove(&pi)
+    }
+
+    /// The metadata of a point, if it has any.
+    pub fn get(&self, pi: usize) -> Option<&T> {
+        self.values.get(&pi)
+    }
+
+    /// The number of points with metadata.
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    /// If no point has metadata.
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+}
+
+impl<T: Serialize + Debug + Send + Sync + 'static> MetaSet for MetadataMap<T> {
+    type Metadata = T;
+    type MetaSummary = CountSummary<T>;
+
+    fn metadata(&self, pn: usize) -> PointCloudResult<Option<&T>> {
+        Ok(self.values.get(&pn))
+    }
+
+    fn metasummary(&self, pns: &[usize]) -> PointCloudResult<SummaryCounter<CountSummary<T>>> {
+        let count = pns
+            .iter()
+            .filter(|pi| self.values.contains_key(*pi))
+            .count();
+        Ok(SummaryCounter {
+            summary: CountSummary::new(count),
+            nones: pns.len() - count,
+            errors: 0,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_sources::DataRam;
+
+    #[test]
+    fn metadata_joins_to_points() {
+        let data = DataRam::<crate::L2>::new(vec![0.0, 1.0, 2.0], 1).unwrap();
+        let metadata = MetadataMap::from_values(vec![
+            Some("doc-0".to_string()),
+            None,
+            Some("doc-2".to_string()),
+        ]);
+        let mut cloud = SimpleMetaCloud::new(data, metadata);
+        assert_eq!(cloud.metadata(0).unwrap(), Some(&"doc-0".to_string()));
+        assert_eq!(cloud.metadata(1).unwrap(), None);
+        cloud.metadata_mut().insert(1, "doc-1".to_string());
+        assert_eq!(cloud.metadata(1).unwrap(), Some(&"doc-1".to_string()));
+
+        let summary = cloud.metasummary(&[0, 1, 2]).unwrap();
+        assert_eq!(summary.summary.count, 3);
+        assert_eq!(summary.nones, 0);
+    }
+}
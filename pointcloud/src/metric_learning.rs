@@ -0,0 +1,395 @@
+//! Learning a metric from labeled pairs of points.
+//!
+//! Supervision comes as pairs of point indexes, pairs that should be near each other and pairs
+//! that should be far apart. [`learn_diagonal`] fits a [`WeightedL2`] that weighs each dimension
+//! by how much more the dissimilar pairs spread along it than the similar pairs do.
+//! [`learn_low_rank`] fits a [`Mahalanobis`] projection: it whitens the similar pairs'
+//! differences and keeps the directions the dissimilar pairs spread the most along. Without
+//! dissimilar pairs both of these are plain covariance whitening of the similar pairs'
+//! differences.
+//!
+//! The learned metric is scaled so that the mean squared distance between the similar pairs is
+//! about the data's dimension, like z-scored data under the plain L2 distance. Build the tree
+//! with the learned metric, see [`crate::data_sources::DataRam::with_metric`].
+
+use crate::metrics::{Mahalanobis, WeightedL2};
+use crate::pc_errors::{PointCloudError, PointCloudResult};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/// Added to every variance, relative to the mean variance, so constant dimensions don't blow up.
+const REGULARIZATION: f64 = 1.0e-6;
+
+/// Learns a weight per dimension from row major data of dimension `dim`. Dimensions along which
+/// the dissimilar pairs differ a lot and the similar pairs differ little get heavy weights.
+/// `dissimilar` can be empty, then each dimension is weighed by the inverse of the similar pairs'
+/// variance along it.
+pub fn learn_diagonal(
+    data: &[f32],
+    dim: usize,
+    similar: &[(usize, usize)],
+    dissimilar: &[(usize, usize)],
+) -> PointCloudResult<WeightedL2> {
+    check_pairs(data, dim, similar, dissimilar)?;
+    let similar_var = regularize(pair_variances(data, dim, similar));
+    let weights: Vec<f64> = if dissimilar.is_empty() {
+        similar_var.iter().map(|s| 1.0 / s).collect()
+    } else {
+        let dissimilar_var = pair_variances(data, dim, dissimilar);
+        dissimilar_var
+            .iter()
+            .zip(&similar_var)
+            .map(|(d, s)| d / s)
+            .collect()
+    };
+    // Scale the weights so the similar pairs' mean squared distance is `dim`.
+    let similar_sq: f64 = weights.iter().zip(&similar_var).map(|(w, s)| w * s).sum();
+    let scale = if similar_sq > 0.0 {
+        dim as f64 / similar_sq
+    } else {
+        1.0
+    };
+    WeightedL2::new(weights.iter().map(|w| (w * scale) as f32).collect())
+}
+
+/// Learns a `rank x dim` projection from row major data of dimension `dim`. The similar pairs'
+/// differences are whitened, then the projection keeps the `rank` directions along which the
+/// whitened dissimilar pairs spread the most. `dissimilar` can be empty, then it keeps the
+/// directions the similar pairs spread the least along.
+pub fn learn_low_rank(
+    data: &[f32],
+    dim: usize,
+    similar: &[(usize, usize)],
+    dissimilar: &[(usize, usize)],
+    rank: usize,
+) -> PointCloudResult<Mahalanobis> {
+    check_pairs(data, dim, similar, dissimilar)?;
+    if rank == 0 || rank > dim {
+        return Err(PointCloudError::MetricParameterError {
+            message: "the rank must be between 1 and the data's dimension",
+        });
+    }
+    let mut similar_cov = pair_covariance(data, dim, similar);
+    let ridge = REGULARIZATION * (0..dim).map(|i| similar_cov[i * dim + i]).sum::<f64>()
+        / dim as f64
+        + f64::MIN_POSITIVE;
+    for i in 0..dim {
+        similar_cov[i * dim + i] += ridge;
+    }
+    // The whitening is `Λ^{-1/2} U^T`, a row per eigenvector of the similar covariance.
+    let (values, vectors) = symmetric_eigen(similar_cov, dim);
+    let mut whitening = vec![0.0f64; dim * dim];
+    for (k, value) in values.iter().enumerate() {
+        let scale = 1.0 / value.max(ridge).sqrt();
+        for j in 0..dim {
+            whitening[k * dim + j] = vectors[j * dim + k] * scale;
+        }
+    }
+
+    let projection = if dissimilar.is_empty() {
+        // The directions the similar pairs spread the least along have the largest scales
+        let mut order: Vec<usize> = (0..dim).collect();
+        order.sort_by(|a, b| values[*a].partial_cmp(&values[*b]).unwrap());
+        order
+            .iter()
+            .take(rank)
+            .flat_map(|k| whitening[k * dim..(k + 1) * dim].to_vec())
+            .collect::<Vec<f64>>()
+    } else {
+        // The dissimilar covariance in the whitened space is `W C_d W^T`.
+        let dissimilar_cov = pair_covariance(data, dim, dissimilar);
+        let whitened = mat_mul(
+            &mat_mul(&whitening, &dissimilar_cov, dim),
+            &transpose(&whitening, dim),
+            dim,
+        );
+        let (values, vectors) = symmetric_eigen(whitened, dim);
+        let mut order: Vec<usize> = (0..dim).collect();
+        order.sort_by(|a, b| values[*b].partial_cmp(&values[*a]).unwrap());
+        let mut projection = vec![0.0f64; rank * dim];
+        for (r, k) in order.iter().take(rank).enumerate() {
+            for j in 0..dim {
+                projection[r * dim + j] = (0..dim)
+                    .map(|i| vectors[i * dim + k] * whitening[i * dim + j])
+                    .sum();
+            }
+        }
+        projection
+    };
+    // Scale the projection so the similar pairs' mean squared distance is `dim`.
+    let similar_sq: f64 = similar
+        .iter()
+        .map(|(a, b)| {
+            let x = &data[a * dim..(a + 1) * dim];
+            let y = &data[b * dim..(b + 1) * dim];
+            projection
+                .chunks_exact(dim)
+                .map(|row| {
+                    let d: f64 = row
+                        .iter()
+                        .zip(x.iter().zip(y))
+                        .map(|(p, (xi, yi))| p * (*xi as f64 - *yi as f64))
+                        .sum();
+                    d * d
+                })
+                .sum::<f64>()
+        })
+        .sum::<f64>()
+        / similar.len() as f64;
+    let scale = if similar_sq > 0.0 {
+        (dim as f64 / similar_sq).sqrt()
+    } else {
+        1.0
+    };
+    Mahalanobis::new(projection.iter().map(|p| (p * scale) as f32).collect(), dim)
+}
+
+/// Samples up to `count` similar pairs, points with the same label, and up to `count` dissimilar
+/// pairs, points with different labels, with the seed. Points with a label of `None` are left out.
+pub fn sample_pairs(
+    labels: &[Option<i64>],
+    count: usize,
+    seed: u64,
+) -> (Vec<(usize, usize)>, Vec<(usize, usize)>) {
+    let labeled: Vec<usize> = (0..labels.len()).filter(|i| labels[*i].is_some()).collect();
+    let mut similar = Vec::with_capacity(count);
+    let mut dissimilar = Vec::with_capacity(count);
+    if labeled.len() < 2 {
+        return (similar, dissimilar);
+    }
+    let mut rng = StdRng::seed_from_u64(seed);
+    // Gives up after a bounded number of draws, in case one of the kinds of pairs is rare or absent
+    for _ in 0..(20 * count) {
+        if similar.len() >= count && dissimilar.len() >= count {
+            break;
+        }
+        let a = labeled[rng.gen_range(0..labeled.len())];
+        let b = labeled[rng.gen_range(0..labeled.len())];
+        if a == b {
+            continue;
+        }
+        if labels[a] == labels[b] {
+            if similar.len() < count {
+                similar.push((a, b));
+            }
+        } else if dissimilar.len() < count {
+            dissimilar.push((a, b));
+        }
+    }
+    (similar, dissimilar)
+}
+
+fn check_pairs(
+    data: &[f32],
+    dim: usize,
+    similar: &[(usize, usize)],
+    dissimilar: &[(usize, usize)],
+) -> PointCloudResult<()> {
+    if dim == 0 || data.len() % dim != 0 {
+        return Err(PointCloudError::MetricParameterError {
+            message: "the data's length must be a multiple of its dimension",
+        });
+    }
+    if similar.is_empty() {
+        return Err(PointCloudError::MetricParameterError {
+            message: "at least one similar pair is needed to learn a metric",
+        });
+    }
+    let len = data.len() / dim;
+    if similar
+        .iter()
+        .chain(dissimilar)
+        .any(|(a, b)| *a >= len || *b >= len)
+    {
+        return Err(PointCloudError::MetricParameterError {
+            message: "a pair has an index outside the data",
+        });
+    }
+    Ok(())
+}
+
+/// The mean squared difference of the pairs along each dimension.
+fn pair_variances(data: &[f32], dim: usize, pairs: &[(usize, usize)]) -> Vec<f64> {
+    let mut variances = vec![0.0f64; dim];
+    for (a, b) in pairs {
+        let x = &data[a * dim..(a + 1) * dim];
+        let y = &data[b * dim..(b + 1) * dim];
+        for (v, (xi, yi)) in variances.iter_mut().zip(x.iter().zip(y)) {
+            *v += (*xi as f64 - *yi as f64).powi(2);
+        }
+    }
+    variances
+        .iter_mut()
+        .for_each(|v| *v /= pairs.len().max(1) as f64);
+    variances
+}
+
+fn regularize(mut variances: Vec<f64>) -> Vec<f64> {
+    let ridge =
+        REGULARIZATION * variances.iter().sum::<f64>() / variances.len() as f64 + f64::MIN_POSITIVE;
+    variances.iter_mut().for_each(|v| *v += ridge);
+    variances
+}
+
+/// The row major mean outer product of the pairs' differences.
+fn pair_covariance(data: &[f32], dim: usize, pairs: &[(usize, usize)]) -> Vec<f64> {
+    let mut cov = vec![0.0f64; dim * dim];
+    let mut diff = vec![0.0f64; dim];
+    for (a, b) in pairs {
+        let x = &data[a * dim..(a + 1) * dim];
+        let y = &data[b * dim..(b + 1) * dim];
+        for (d, (xi, yi)) in diff.iter_mut().zip(x.iter().zip(y)) {
+            *d = *xi as f64 - *yi as f64;
+        }
+        for i in 0..dim {
+            for j in 0..dim {
+                cov[i * dim + j] += diff[i] * diff[j];
+            }
+        }
+    }
+    cov.iter_mut().for_each(|c| *c /= pairs.len().max(1) as f64);
+    cov
+}
+
+fn mat_mul(a: &[f64], b: &[f64], dim: usize) -> Vec<f64> {
+    let mut c = vec![0.0f64; dim * dim];
+    for i in 0..dim {
+        for k in 0..dim {
+            let aik = a[i * dim + k];
+            for j in 0..dim {
+                c[i * dim + j] += aik * b[k * dim + j];
+            }
+        }
+    }
+    c
+}
+
+fn transpose(a: &[f64], dim: usize) -> Vec<f64> {
+    let mut t = vec![0.0f64; dim * dim];
+    for i in 0..dim {
+        for j in 0..dim {
+            t[j * dim + i] = a[i * dim + j];
+        }
+    }
+    t
+}
+
+/// Cyclic Jacobi eigendecomposition of a symmetric row major matrix. Returns the eigenvalues and
+/// the eigenvectors as the columns of a row major matrix, in no particular order.
+fn symmetric_eigen(mut a: Vec<f64>, dim: usize) -> (Vec<f64>, Vec<f64>) {
+    let mut v = vec![0.0f64; dim * dim];
+    for i in 0..dim {
+        v[i * dim + i] = 1.0;
+    }
+    for _ in 0..100 {
+        let off: f64 = (0..dim)
+            .flat_map(|i| (0..dim).filter(move |j| *j != i).map(move |j| (i, j)))
+            .map(|(i, j)| a[i * dim + j] * a[i * dim + j])
+            .sum();
+        let diag: f64 = (0..dim).map(|i| a[i * dim + i] * a[i * dim + i]).sum();
+        if off <= 1.0e-24 * diag.max(f64::MIN_POSITIVE) {
+            break;
+        }
+        for p in 0..dim {
+            for q in (p + 1)..dim {
+                let apq = a[p * dim + q];
+                if apq == 0.0 {
+                    continue;
+                }
+                let theta = (a[q * dim + q] - a[p * dim + p]) / (2.0 * apq);
+                let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+                let c = 1.0 / (t * t + 1.0).sqrt();
+                let s = t * c;
+                for k in 0..dim {
+                    let akp = a[k * dim + p];
+                    let akq = a[k * dim + q];
+                    a[k * dim + p] = c * akp - s * akq;
+                    a[k * dim + q] = s * akp + c * akq;
+                }
+                for k in 0..dim {
+                    let apk = a[p * dim + k];
+                    let aqk = a[q * dim + k];
+                    a[p * dim + k] = c * apk - s * aqk;
+                    a[q * dim + k] = s * apk + c * aqk;
+                }
+                for k in 0..dim {
+                    let vkp = v[k * dim + p];
+                    let vkq = v[k * dim + q];
+                    v[k * dim + p] = c * vkp - s * vkq;
+                    v[k * dim + q] = s * vkp + c * vkq;
+                }
+            }
+        }
+    }
+    ((0..dim).map(|i| a[i * dim + i]).collect(), v)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::base_traits::Metric;
+
+    /// Two classes that differ along dimension 0, with a lot of shared noise along dimension 1.
+    fn two_classes() -> (Vec<f32>, Vec<Option<i64>>) {
+        let mut data = Vec::new();
+        let mut labels = Vec::new();
+        for i in 0..200 {
+            let label = i % 2;
+            data.push(label as f32 + 0.05 * rand::random::<f32>());
+            data.push(10.0 * rand::random::<f32>());
+            labels.push(Some(label as i64));
+        }
+        (data, labels)
+    }
+
+    #[test]
+    fn diagonal_weighs_the_separating_dimension() {
+        let (data, labels) = two_classes();
+        let (similar, dissimilar) = sample_pairs(&labels, 100, 0);
+        let metric = learn_diagonal(&data, 2, &similar, &dissimilar).unwrap();
+        assert!(metric.weights()[0] > 100.0 * metric.weights()[1]);
+    }
+
+    #[test]
+    fn diagonal_without_dissimilar_pairs_whitens() {
+        let data = vec![0.0, 0.0, 2.0, 0.5, 0.0, 0.0, -2.0, -0.5];
+        let metric = learn_diagonal(&data, 2, &[(0, 1), (2, 3)], &[]).unwrap();
+        // The similar pairs differ by 2 and 0.5, so the weights are in the ratio 1/4 to 4
+        assert_approx_eq!(metric.weights()[1] / metric.weights()[0], 16.0, 0.1);
+    }
+
+    #[test]
+    fn low_rank_separates_the_classes() {
+        let (data, labels) = two_classes();
+        let (similar, dissimilar) = sample_pairs(&labels, 100, 0);
+        let metric = learn_low_rank(&data, 2, &similar, &dissimilar, 1).unwrap();
+        assert_eq!(metric.rank(), 1);
+        let projection = metric.projection();
+        assert!(projection[0].abs() > 10.0 * projection[1].abs());
+        let same = metric.dist(&data[0..2], &data[4..6]);
+        let different = metric.dist(&data[0..2], &data[2..4]);
+        assert!(same < different);
+    }
+
+    #[test]
+    fn eigen_reconstructs_the_matrix() {
+        let a = vec![4.0, 1.0, 0.5, 1.0, 3.0, 0.2, 0.5, 0.2, 1.0];
+        let (values, vectors) = symmetric_eigen(a.clone(), 3);
+        for i in 0..3 {
+            for j in 0..3 {
+                let r: f64 = (0..3)
+                    .map(|k| vectors[i * 3 + k] * values[k] * vectors[j * 3 + k])
+                    .sum();
+                assert!((r - a[i * 3 + j]).abs() < 1.0e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn learning_rejects_bad_pairs() {
+        let data = vec![0.0, 1.0, 2.0, 3.0];
+        assert!(learn_diagonal(&data, 2, &[], &[(0, 1)]).is_err());
+        assert!(learn_diagonal(&data, 2, &[(0, 2)], &[]).is_err());
+        assert!(learn_low_rank(&data, 2, &[(0, 1)], &[], 3).is_err());
+    }
+}
@@ -0,0 +1,72 @@
+//! The Bray-Curtis dissimilarity, for compositional and count data.
+//!
+//! This is `sum |x_i - y_i| / sum |x_i + y_i|`, matching `scipy.spatial.distance.braycurtis`.
+//! It does not satisfy the triangle inequality in general, so the cover tree's guarantees only
+//! hold approximately. For non-negative data it's usually well enough behaved.
+
+use super::BrayCurtis;
+use crate::base_traits::Metric;
+
+impl Metric<[f32]> for BrayCurtis {
+    fn dist(&self, x: &[f32], y: &[f32]) -> f32 {
+        bray_curtis_dense_f32(x, y)
+    }
+}
+
+/// Bray-Curtis dissimilarity of two dense vectors. Two all zero vectors are at distance 0.
+#[inline]
+pub fn bray_curtis_dense_f32(x: &[f32], y: &[f32]) -> f32 {
+    let (diff, total) = x
+        .iter()
+        .zip(y)
+        .fold((0.0f32, 0.0f32), |(diff, total), (xi, yi)| {
+            (diff + (xi - yi).abs(), total + (xi + yi).abs())
+        });
+    if total > 0.0 {
+        diff / total
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_sources::DataRam;
+    use crate::PointCloud;
+
+    #[test]
+    fn bray_curtis_scipy_reference() {
+        // Values from scipy.spatial.distance.braycurtis
+        assert_approx_eq!(
+            BrayCurtis {}.dist(&[1.0, 0.0, 0.0][..], &[0.0, 1.0, 0.0][..]),
+            1.0
+        );
+        assert_approx_eq!(
+            BrayCurtis {}.dist(&[1.0, 1.0, 0.0][..], &[0.0, 1.0, 0.0][..]),
+            0.333_333_34
+        );
+        assert_approx_eq!(
+            BrayCurtis {}.dist(&[1.0, 2.0, 3.0][..], &[4.0, 5.0, 6.0][..]),
+            0.428_571_43
+        );
+        assert_approx_eq!(
+            BrayCurtis {}.dist(&[0.5, 0.25, 0.25][..], &[0.5, 0.25, 0.25][..]),
+            0.0
+        );
+    }
+
+    #[test]
+    fn bray_curtis_zero_vectors() {
+        assert_approx_eq!(BrayCurtis {}.dist(&[0.0, 0.0][..], &[0.0, 0.0][..]), 0.0);
+    }
+
+    #[test]
+    fn bray_curtis_point_cloud() {
+        let data = DataRam::<BrayCurtis>::new(vec![1.0, 0.0, 0.0, 1.0, 1.0, 1.0], 2).unwrap();
+        let dists = data.distances_to_point_index(0, &[0, 1, 2]).unwrap();
+        assert_approx_eq!(dists[0], 0.0);
+        assert_approx_eq!(dists[1], 1.0);
+        assert_approx_eq!(dists[2], 0.333_333_34);
+    }
+}
@@ -0,0 +1,104 @@
+//! Correlation distance, `sqrt(2 (1 - r))` where `r` is the Pearson correlation of the two vectors.
+//!
+//! Each pair is centered and normalized on the fly, so the point cloud stores the raw data.
+//! This is useful for time-series windows where the shape of the signal matters more than its
+//! offset or magnitude. Scipy's `correlation` is `1 - r`, which breaks the triangle inequality
+//! that the cover tree prunes with. This is the Euclidean distance between the z-normalized
+//! vectors instead, a true metric that orders pairs the same way. It ranges over `[0, 2]`.
+
+use super::Correlation;
+use crate::base_traits::Metric;
+
+impl Metric<[f32]> for Correlation {
+    fn dist(&self, x: &[f32], y: &[f32]) -> f32 {
+        correlation_dense_f32(x, y)
+    }
+}
+
+/// Correlation distance between two dense vectors.
+///
+/// A constant vector has no direction, so it is at distance 0 from another constant vector and
+/// at the uncorrelated distance, `sqrt(2)`, from everything else.
+#[inline]
+pub fn correlation_dense_f32(x: &[f32], y: &[f32]) -> f32 {
+    let len = x.len().min(y.len());
+    if len == 0 {
+        return 0.0;
+    }
+    let x_mean = x.iter().sum::<f32>() / len as f32;
+    let y_mean = y.iter().sum::<f32>() / len as f32;
+    let (cov, x_var, y_var) =
+        x.iter()
+            .zip(y)
+            .fold((0.0f32, 0.0f32, 0.0f32), |(cov, x_var, y_var), (xi, yi)| {
+                let xc = xi - x_mean;
+                let yc = yi - y_mean;
+                (cov + xc * yc, x_var + xc * xc, y_var + yc * yc)
+            });
+    match (x_var > 0.0, y_var > 0.0) {
+        (true, true) => {
+            let r = cov / (x_var.sqrt() * y_var.sqrt());
+            (2.0 * (1.0 - r)).max(0.0).min(4.0).sqrt()
+        }
+        (false, false) => 0.0,
+        _ => std::f32::consts::SQRT_2,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn correlation_scipy_reference() {
+        // The square roots of twice scipy.spatial.distance.correlation
+        assert_approx_eq!(
+            Correlation {}.dist(&[1.0, 2.0, 3.0][..], &[1.0, 2.0, 3.0][..]),
+            0.0
+        );
+        assert_approx_eq!(
+            Correlation {}.dist(&[1.0, 2.0, 3.0][..], &[3.0, 2.0, 1.0][..]),
+            2.0
+        );
+        assert_approx_eq!(
+            Correlation {}.dist(&[1.0, 2.0, 3.0][..], &[1.0, 2.0, 4.0][..]),
+            0.189_839_4
+        );
+    }
+
+    #[test]
+    fn correlation_ignores_offset_and_magnitude() {
+        let x = [0.5, -1.0, 2.0, 0.0];
+        let y: Vec<f32> = x.iter().map(|xi| 10.0 * xi + 3.0).collect();
+        assert_approx_eq!(Correlation {}.dist(&x[..], &y[..]), 0.0);
+    }
+
+    #[test]
+    fn correlation_constant_vectors() {
+        assert_approx_eq!(Correlation {}.dist(&[1.0, 1.0][..], &[2.0, 2.0][..]), 0.0);
+        assert_approx_eq!(
+            Correlation {}.dist(&[1.0, 1.0][..], &[1.0, 2.0][..]),
+            std::f32::consts::SQRT_2
+        );
+    }
+
+    #[test]
+    fn correlation_triangle_inequality() {
+        let dim = 8;
+        let mut windows: Vec<Vec<f32>> = (0..30)
+            .map(|_| (0..dim).map(|_| rand::random::<f32>() - 0.5).collect())
+            .collect();
+        windows.push(vec![1.0; dim]);
+        for x in &windows {
+            for y in &windows {
+                let xy = Correlation {}.dist(&x[..], &y[..]);
+                assert_approx_eq!(xy, Correlation {}.dist(&y[..], &x[..]));
+                for z in &windows {
+                    let xz = Correlation {}.dist(&x[..], &z[..]);
+                    let zy = Correlation {}.dist(&z[..], &y[..]);
+                    assert!(xy <= xz + zy + 1e-5);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,56 @@
+//! Cosine distance, `1 - x.y / (|x| |y|)`, for data where direction matters and magnitude doesn't.
+//!
+//! Like scipy's `cosine` this ranges over `[0, 2]`. It does not satisfy the triangle inequality,
+//! so the cover tree's guarantees only hold approximately. Normalize your data and use L2 if you
+//! need exact queries, the two orderings agree on the unit sphere.
+
+use super::Cosine;
+use crate::base_traits::Metric;
+
+impl Metric<[f32]> for Cosine {
+    fn dist(&self, x: &[f32], y: &[f32]) -> f32 {
+        cosine_dense_f32(x, y)
+    }
+}
+
+/// Cosine distance between two dense vectors.
+///
+/// The zero vector has no direction, so it is at distance 0 from another zero vector and at
+/// distance 1 (orthogonal) from everything else.
+#[inline]
+pub fn cosine_dense_f32(x: &[f32], y: &[f32]) -> f32 {
+    let (dot, x_norm, y_norm) = x.iter().zip(y).fold(
+        (0.0f32, 0.0f32, 0.0f32),
+        |(dot, x_norm, y_norm), (xi, yi)| (dot + xi * yi, x_norm + xi * xi, y_norm + yi * yi),
+    );
+    match (x_norm > 0.0, y_norm > 0.0) {
+        (true, true) => (1.0 - dot / (x_norm.sqrt() * y_norm.sqrt()))
+            .max(0.0)
+            .min(2.0),
+        (false, false) => 0.0,
+        _ => 1.0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cosine_scipy_reference() {
+        // Values from scipy.spatial.distance.cosine
+        assert_approx_eq!(Cosine {}.dist(&[1.0, 0.0][..], &[0.0, 1.0][..]), 1.0);
+        assert_approx_eq!(Cosine {}.dist(&[1.0, 0.0][..], &[-1.0, 0.0][..]), 2.0);
+        assert_approx_eq!(Cosine {}.dist(&[1.0, 2.0][..], &[2.0, 4.0][..]), 0.0);
+        assert_approx_eq!(
+            Cosine {}.dist(&[1.0, 2.0, 3.0][..], &[4.0, 5.0, 6.0][..]),
+            0.025_368_2
+        );
+    }
+
+    #[test]
+    fn cosine_zero_vectors() {
+        assert_approx_eq!(Cosine {}.dist(&[0.0, 0.0][..], &[0.0, 0.0][..]), 0.0);
+        assert_approx_eq!(Cosine {}.dist(&[0.0, 0.0][..], &[1.0, 2.0][..]), 1.0);
+    }
+}
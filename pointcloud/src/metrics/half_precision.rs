@@ -0,0 +1,85 @@
+//! L1 and L2 on half precision points, see [`crate::data_sources::DataRamF16`].
+//!
+//! The points are converted to `f32` a block at a time on the stack and the `f32` kernels run on
+//! the blocks, so only storage is in half precision. The distances are the same as on the `f32`
+//! values the `f16`s round to.
+
+use super::{l1_dense_f32, sq_l2_dense_f32, L1, L2};
+use crate::base_traits::Metric;
+use half::f16;
+use half::slice::HalfFloatSliceExt;
+
+/// The number of coordinates converted at a time.
+const BLOCK_LEN: usize = 64;
+
+/// Runs the `f32` kernel on each block of the two points and sums the results. The points should
+/// have the same length.
+#[inline]
+fn sum_over_f32_blocks<F: Fn(&[f32], &[f32]) -> f32>(x: &[f16], y: &[f16], kernel: F) -> f32 {
+    let mut x_block = [0.0f32; BLOCK_LEN];
+    let mut y_block = [0.0f32; BLOCK_LEN];
+    let mut total = 0.0;
+    for (x_chunk, y_chunk) in x.chunks(BLOCK_LEN).zip(y.chunks(BLOCK_LEN)) {
+        let len = x_chunk.len().min(y_chunk.len());
+        x_chunk[..len].convert_to_f32_slice(&mut x_block[..len]);
+        y_chunk[..len].convert_to_f32_slice(&mut y_block[..len]);
+        total += kernel(&x_block[..len], &y_block[..len]);
+    }
+    total
+}
+
+/// Squared L2 distance between two half precision vectors, computed in `f32`.
+#[inline]
+pub fn sq_l2_dense_f16(x: &[f16], y: &[f16]) -> f32 {
+    sum_over_f32_blocks(x, y, sq_l2_dense_f32)
+}
+
+/// L1 distance between two half precision vectors, computed in `f32`.
+#[inline]
+pub fn l1_dense_f16(x: &[f16], y: &[f16]) -> f32 {
+    sum_over_f32_blocks(x, y, l1_dense_f32)
+}
+
+impl Metric<[f16]> for L2 {
+    fn dist(&self, x: &[f16], y: &[f16]) -> f32 {
+        sq_l2_dense_f16(x, y).sqrt()
+    }
+}
+
+impl Metric<[f16]> for L1 {
+    fn dist(&self, x: &[f16], y: &[f16]) -> f32 {
+        l1_dense_f16(x, y).sqrt()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_f32_on_rounded_values() {
+        // Long enough to cross a block boundary and hit the kernels' SIMD and leftover paths
+        let x: Vec<f32> = (0..150).map(|i| (i as f32 * 0.37).sin()).collect();
+        let y: Vec<f32> = (0..150).map(|i| (i as f32 * 0.11).cos()).collect();
+        let x_half: Vec<f16> = x.iter().map(|v| f16::from_f32(*v)).collect();
+        let y_half: Vec<f16> = y.iter().map(|v| f16::from_f32(*v)).collect();
+        let x_rounded: Vec<f32> = x_half.iter().map(|v| v.to_f32()).collect();
+        let y_rounded: Vec<f32> = y_half.iter().map(|v| v.to_f32()).collect();
+        assert_approx_eq!(
+            L2 {}.dist(&x_half[..], &y_half[..]),
+            L2 {}.dist(&x_rounded[..], &y_rounded[..]),
+            1e-4
+        );
+        assert_approx_eq!(
+            L1 {}.dist(&x_half[..], &y_half[..]),
+            L1 {}.dist(&x_rounded[..], &y_rounded[..]),
+            1e-4
+        );
+        // And close to the full precision distance
+        assert_approx_eq!(
+            L2 {}.dist(&x_half[..], &y_half[..]),
+            L2 {}.dist(&x[..], &y[..]),
+            1e-2
+        );
+    }
+}
@@ -7,25 +7,25 @@ use packed_simd::*;
 use std::ops::Deref;
 
 impl Metric<[f32]> for L2 {
-    fn dist(x: &[f32], y: &[f32]) -> f32 {
+    fn dist(&self, x: &[f32], y: &[f32]) -> f32 {
         sq_l2_dense_f32(x.deref(), y.deref()).sqrt()
     }
 }
 
 impl<'a> Metric<RawSparse<f32, u32>> for L2 {
-    fn dist(x: &RawSparse<f32, u32>, y: &RawSparse<f32, u32>) -> f32 {
+    fn dist(&self, x: &RawSparse<f32, u32>, y: &RawSparse<f32, u32>) -> f32 {
         sq_l2_sparse_f32_f32(x.indexes(), x.values(), y.indexes(), y.values()).sqrt()
     }
 }
 
 impl<'a> Metric<RawSparse<f32, u16>> for L2 {
-    fn dist(x: &RawSparse<f32, u16>, y: &RawSparse<f32, u16>) -> f32 {
+    fn dist(&self, x: &RawSparse<f32, u16>, y: &RawSparse<f32, u16>) -> f32 {
         sq_l2_sparse_f32_f32(x.indexes(), x.values(), y.indexes(), y.values()).sqrt()
     }
 }
 
 impl<'a> Metric<RawSparse<f32, u8>> for L2 {
-    fn dist(x: &RawSparse<f32, u8>, y: &RawSparse<f32, u8>) -> f32 {
+    fn dist(&self, x: &RawSparse<f32, u8>, y: &RawSparse<f32, u8>) -> f32 {
         sq_l2_sparse_f32_f32(x.indexes(), x.values(), y.indexes(), y.values()).sqrt()
     }
 }
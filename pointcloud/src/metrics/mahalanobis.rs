@@ -0,0 +1,123 @@
+//! Mahalanobis distance through a linear projection.
+//!
+//! This is `||P (x - y)||`, where `P` is a `rank x dim` matrix. It's the Mahalanobis distance
+//! for the matrix `P^T P`, factored so a low rank metric only costs `rank` dot products. With a
+//! diagonal `P` this is [`super::WeightedL2`] with weights `P_ii^2`. The projection is usually
+//! learned from labeled pairs, see [`crate::metric_learning`]. The default has no projection and
+//! is the plain L2 distance.
+
+use super::{sq_l2_dense_f32, Mahalanobis};
+use crate::base_traits::Metric;
+use crate::pc_errors::{PointCloudError, PointCloudResult};
+
+impl Mahalanobis {
+    /// Creates the metric from a row major `rank x dim` projection. The entries have to be finite
+    /// and the point cloud's dimension has to match `dim`.
+    pub fn new(projection: Vec<f32>, dim: usize) -> PointCloudResult<Mahalanobis> {
+        if dim == 0 || projection.is_empty() || projection.len() % dim != 0 {
+            return Err(PointCloudError::MetricParameterError {
+                message: "the projection must be a non-empty multiple of the data's dimension",
+            });
+        }
+        if projection.iter().any(|p| !p.is_finite()) {
+            return Err(PointCloudError::MetricParameterError {
+                message: "the projection must be finite",
+            });
+        }
+        Ok(Mahalanobis { projection, dim })
+    }
+
+    /// The row major projection, empty if this is the default.
+    pub fn projection(&self) -> &[f32] {
+        &self.projection
+    }
+
+    /// The dimension of the data the projection applies to, 0 if this is the default.
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    /// The number of rows of the projection, the dimension points are projected down to.
+    pub fn rank(&self) -> usize {
+        if self.dim == 0 {
+            0
+        } else {
+            self.projection.len() / self.dim
+        }
+    }
+
+    /// Projects a point, so the plain L2 distance between projected points is this distance. A
+    /// tree can be built on the projected data when the rank is much smaller than the dimension.
+    pub fn project(&self, x: &[f32]) -> Vec<f32> {
+        if self.dim == 0 {
+            return x.to_vec();
+        }
+        self.projection
+            .chunks_exact(self.dim)
+            .map(|row| row.iter().zip(x).map(|(p, xi)| p * xi).sum())
+            .collect()
+    }
+}
+
+impl Metric<[f32]> for Mahalanobis {
+    fn dist(&self, x: &[f32], y: &[f32]) -> f32 {
+        if self.dim == 0 {
+            return sq_l2_dense_f32(x, y).sqrt();
+        }
+        self.projection
+            .chunks_exact(self.dim)
+            .map(|row| {
+                let d: f32 = row
+                    .iter()
+                    .zip(x.iter().zip(y))
+                    .map(|(p, (xi, yi))| p * (xi - yi))
+                    .sum();
+                d * d
+            })
+            .sum::<f32>()
+            .sqrt()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::{WeightedL2, L2};
+
+    #[test]
+    fn mahalanobis_diagonal_is_weighted_l2() {
+        let metric = Mahalanobis::new(vec![2.0, 0.0, 0.0, 0.5], 2).unwrap();
+        let weighted = WeightedL2::new(vec![4.0, 0.25]).unwrap();
+        let x = [1.0, 3.0];
+        let y = [-2.0, 0.5];
+        assert_approx_eq!(metric.dist(&x[..], &y[..]), weighted.dist(&x[..], &y[..]));
+    }
+
+    #[test]
+    fn mahalanobis_matches_projected_l2() {
+        let projection: Vec<f32> = (0..15).map(|_| rand::random::<f32>() - 0.5).collect();
+        let metric = Mahalanobis::new(projection, 5).unwrap();
+        assert_eq!(metric.rank(), 3);
+        let x: Vec<f32> = (0..5).map(|_| rand::random::<f32>()).collect();
+        let y: Vec<f32> = (0..5).map(|_| rand::random::<f32>()).collect();
+        assert_approx_eq!(
+            metric.dist(&x[..], &y[..]),
+            L2 {}.dist(&metric.project(&x)[..], &metric.project(&y)[..])
+        );
+    }
+
+    #[test]
+    fn mahalanobis_default_is_l2() {
+        assert_approx_eq!(
+            Mahalanobis::default().dist(&[0.0, 0.0][..], &[3.0, 4.0][..]),
+            5.0
+        );
+    }
+
+    #[test]
+    fn mahalanobis_rejects_bad_shapes() {
+        assert!(Mahalanobis::new(vec![1.0, 0.0, 1.0], 2).is_err());
+        assert!(Mahalanobis::new(vec![1.0, std::f32::NAN], 2).is_err());
+        assert!(Mahalanobis::new(vec![], 2).is_err());
+    }
+}
@@ -0,0 +1,233 @@
+//! Distances over a subset of the dimensions.
+//!
+//! A [`DimMask`] picks out some dimensions of the data, and a [`MaskedMetric`] measures the
+//! distance between two points on those alone, as if the other dimensions had been dropped from
+//! the data. This is "similarity on these 20 features only" without a separate point cloud, or a
+//! separate tree, for every choice of features.
+//!
+//! The metrics here are sums over the dimensions, so dropping dimensions never makes two points
+//! farther apart, and the masked distance is still a metric. The radius a cover tree node has
+//! under the full distance bounds its masked radius too, which is what lets the tree answer
+//! masked queries. A tighter per-node bound can be computed from the largest deviation from the
+//! center in each dimension with [`MaskedMetric::masked_norm`].
+
+use super::{PreciseL1, PreciseL2, WeightedL2, L1, L2};
+use crate::base_traits::Metric;
+use crate::pc_errors::{PointCloudError, PointCloudResult};
+
+/// A sorted set of dimensions to measure distances over.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DimMask {
+    dims: Vec<usize>,
+}
+
+impl DimMask {
+    /// Creates a mask of the given dimensions for data of dimension `dim`. Repeats are dropped.
+    /// The mask can't be empty and every dimension has to be less than `dim`.
+    pub fn new(mut dims: Vec<usize>, dim: usize) -> PointCloudResult<DimMask> {
+        dims.sort_unstable();
+        dims.dedup();
+        if dims.is_empty() {
+            return Err(PointCloudError::MetricParameterError {
+                message: "the mask must have at least one dimension",
+            });
+        }
+        if dims.iter().any(|d| *d >= dim) {
+            return Err(PointCloudError::MetricParameterError {
+                message: "the mask has a dimension outside the data",
+            });
+        }
+        Ok(DimMask { dims })
+    }
+
+    /// Creates a mask from dimension names, like the column names of a csv. See
+    /// [`crate::PointCloud::dim_names`].
+    pub fn from_names<S: AsRef<str>>(
+        names: &[S],
+        dim_names: &[String],
+    ) -> PointCloudResult<DimMask> {
+        let dims = names
+            .iter()
+            .map(|name| {
+                dim_names
+                    .iter()
+                    .position(|n| n == name.as_ref())
+                    .ok_or(PointCloudError::UnknownName)
+            })
+            .collect::<PointCloudResult<Vec<usize>>>()?;
+        DimMask::new(dims, dim_names.len())
+    }
+
+    /// The dimensions, in increasing order.
+    pub fn dims(&self) -> &[usize] {
+        &self.dims
+    }
+
+    /// The number of dimensions in the mask.
+    pub fn len(&self) -> usize {
+        self.dims.len()
+    }
+
+    /// Masks are never empty, this is here for clippy.
+    pub fn is_empty(&self) -> bool {
+        self.dims.is_empty()
+    }
+}
+
+/// A metric that can be restricted to the dimensions of a mask. The masked distance has to be no
+/// more than the full distance and satisfy the triangle inequality, so that the cover tree's
+/// bounds hold for it.
+pub trait MaskedMetric: Metric<[f32]> {
+    /// The distance between two points over the masked dimensions.
+    fn masked_dist(&self, x: &[f32], y: &[f32], mask: &DimMask) -> f32;
+    /// The largest masked distance between two points whose coordinates differ by at most
+    /// `deviations` in each dimension.
+    fn masked_norm(&self, deviations: &[f32], mask: &DimMask) -> f32;
+}
+
+fn masked_l1(x: &[f32], y: &[f32], mask: &DimMask) -> f64 {
+    mask.dims
+        .iter()
+        .map(|i| (x[*i] as f64 - y[*i] as f64).abs())
+        .sum()
+}
+
+fn masked_sq_l2(x: &[f32], y: &[f32], mask: &DimMask) -> f64 {
+    mask.dims
+        .iter()
+        .map(|i| (x[*i] as f64 - y[*i] as f64).powi(2))
+        .sum()
+}
+
+fn masked_sum(deviations: &[f32], mask: &DimMask) -> f64 {
+    mask.dims.iter().map(|i| deviations[*i] as f64).sum()
+}
+
+fn masked_sq_sum(deviations: &[f32], mask: &DimMask) -> f64 {
+    mask.dims
+        .iter()
+        .map(|i| (deviations[*i] as f64).powi(2))
+        .sum()
+}
+
+impl MaskedMetric for L1 {
+    fn masked_dist(&self, x: &[f32], y: &[f32], mask: &DimMask) -> f32 {
+        (masked_l1(x, y, mask) as f32).sqrt()
+    }
+    fn masked_norm(&self, deviations: &[f32], mask: &DimMask) -> f32 {
+        (masked_sum(deviations, mask) as f32).sqrt()
+    }
+}
+
+impl MaskedMetric for L2 {
+    fn masked_dist(&self, x: &[f32], y: &[f32], mask: &DimMask) -> f32 {
+        (masked_sq_l2(x, y, mask) as f32).sqrt()
+    }
+    fn masked_norm(&self, deviations: &[f32], mask: &DimMask) -> f32 {
+        (masked_sq_sum(deviations, mask) as f32).sqrt()
+    }
+}
+
+impl MaskedMetric for PreciseL1 {
+    fn masked_dist(&self, x: &[f32], y: &[f32], mask: &DimMask) -> f32 {
+        masked_l1(x, y, mask) as f32
+    }
+    fn masked_norm(&self, deviations: &[f32], mask: &DimMask) -> f32 {
+        masked_sum(deviations, mask) as f32
+    }
+}
+
+impl MaskedMetric for PreciseL2 {
+    fn masked_dist(&self, x: &[f32], y: &[f32], mask: &DimMask) -> f32 {
+        (masked_sq_l2(x, y, mask) as f32).sqrt()
+    }
+    fn masked_norm(&self, deviations: &[f32], mask: &DimMask) -> f32 {
+        (masked_sq_sum(deviations, mask) as f32).sqrt()
+    }
+}
+
+impl MaskedMetric for WeightedL2 {
+    fn masked_dist(&self, x: &[f32], y: &[f32], mask: &DimMask) -> f32 {
+        if self.weights.is_empty() {
+            return (masked_sq_l2(x, y, mask) as f32).sqrt();
+        }
+        let total: f64 = mask
+            .dims
+            .iter()
+            .map(|i| self.weights[*i] as f64 * (x[*i] as f64 - y[*i] as f64).powi(2))
+            .sum();
+        (total as f32).sqrt()
+    }
+    fn masked_norm(&self, deviations: &[f32], mask: &DimMask) -> f32 {
+        if self.weights.is_empty() {
+            return (masked_sq_sum(deviations, mask) as f32).sqrt();
+        }
+        let total: f64 = mask
+            .dims
+            .iter()
+            .map(|i| self.weights[*i] as f64 * (deviations[*i] as f64).powi(2))
+            .sum();
+        (total as f32).sqrt()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn masks_are_sorted_and_checked() {
+        let mask = DimMask::new(vec![3, 0, 3], 4).unwrap();
+        assert_eq!(mask.dims(), &[0, 3]);
+        assert!(DimMask::new(vec![4], 4).is_err());
+        assert!(DimMask::new(Vec::new(), 4).is_err());
+
+        let names = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+        assert_eq!(
+            DimMask::from_names(&["c", "a"], &names).unwrap().dims(),
+            &[0, 2]
+        );
+        assert!(DimMask::from_names(&["d"], &names).is_err());
+    }
+
+    #[test]
+    fn masked_matches_dropped_dimensions() {
+        let x = [1.0, 5.0, -2.0, 0.5];
+        let y = [0.0, -1.0, 2.0, 0.5];
+        let mask = DimMask::new(vec![0, 2], 4).unwrap();
+        let x_kept = [1.0, -2.0];
+        let y_kept = [0.0, 2.0];
+        assert_approx_eq!(
+            L2 {}.masked_dist(&x, &y, &mask),
+            L2 {}.dist(&x_kept[..], &y_kept[..])
+        );
+        assert_approx_eq!(
+            L1 {}.masked_dist(&x, &y, &mask),
+            L1 {}.dist(&x_kept[..], &y_kept[..])
+        );
+        assert!(L2 {}.masked_dist(&x, &y, &mask) <= L2 {}.dist(&x[..], &y[..]));
+
+        let weighted = WeightedL2::new(vec![2.0, 1.0, 0.5, 1.0]).unwrap();
+        let weighted_kept = WeightedL2::new(vec![2.0, 0.5]).unwrap();
+        assert_approx_eq!(
+            weighted.masked_dist(&x, &y, &mask),
+            weighted_kept.dist(&x_kept[..], &y_kept[..])
+        );
+    }
+
+    #[test]
+    fn masked_norm_bounds_the_distance() {
+        let center = [0.0, 0.0, 0.0];
+        let deviations = [1.0, 2.0, 3.0];
+        let corner = [-1.0, 2.0, -3.0];
+        let mask = DimMask::new(vec![1, 2], 3).unwrap();
+        assert_approx_eq!(
+            L2 {}.masked_norm(&deviations, &mask),
+            L2 {}.masked_dist(&center, &corner, &mask)
+        );
+        assert_approx_eq!(
+            L1 {}.masked_norm(&deviations, &mask),
+            L1 {}.masked_dist(&center, &corner, &mask)
+        );
+    }
+}
@@ -8,9 +8,71 @@ pub mod l2_f32;
 pub use l2_f32::*;
 pub mod l1_f32;
 pub use l1_f32::*;
+pub mod bray_curtis;
+pub use bray_curtis::*;
+pub mod correlation;
+pub use correlation::*;
+pub mod cosine;
+pub use cosine::*;
+pub mod jensen_shannon;
+pub use jensen_shannon::*;
+pub mod wasserstein;
+pub use wasserstein::*;
+pub mod weighted_l2;
+pub use weighted_l2::*;
+pub mod mahalanobis;
+pub use mahalanobis::*;
+pub mod precise;
+pub use precise::*;
+pub mod nan_euclidean;
+pub use nan_euclidean::*;
+pub mod half_precision;
+pub use half_precision::*;
+pub mod masked;
+pub use masked::*;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Default)]
 /// L2 distance trait.
 pub struct L2 {}
 /// L1 distance trait
+#[derive(Debug, Clone, Default)]
 pub struct L1 {}
+/// Bray-Curtis dissimilarity, see [`bray_curtis`] for details.
+#[derive(Debug, Clone, Default)]
+pub struct BrayCurtis {}
+/// Correlation distance, see [`correlation`] for details.
+#[derive(Debug, Clone, Default)]
+pub struct Correlation {}
+/// Cosine distance, see [`cosine`] for details.
+#[derive(Debug, Clone, Default)]
+pub struct Cosine {}
+/// Jensen-Shannon distance, see [`jensen_shannon`] for details.
+#[derive(Debug, Clone, Default)]
+pub struct JensenShannon {}
+/// 1-D Wasserstein (earth mover's) distance, see [`wasserstein`] for details.
+#[derive(Debug, Clone, Default)]
+pub struct Wasserstein {}
+/// L2 distance with a weight per dimension, see [`weighted_l2`] for details.
+#[derive(Debug, Clone, Default)]
+pub struct WeightedL2 {
+    weights: Vec<f32>,
+}
+/// L2 distance after a linear projection, see [`mahalanobis`] for details.
+#[derive(Debug, Clone, Default)]
+pub struct Mahalanobis {
+    projection: Vec<f32>,
+    dim: usize,
+}
+/// L2 distance that skips missing values, see [`nan_euclidean`] for details.
+#[derive(Debug, Clone, Default)]
+pub struct NanEuclidean {}
+/// L1 distance with a configurable accumulator, see [`precise`] for details.
+#[derive(Debug, Clone, Default)]
+pub struct PreciseL1 {
+    accumulation: Accumulation,
+}
+/// L2 distance with a configurable accumulator, see [`precise`] for details.
+#[derive(Debug, Clone, Default)]
+pub struct PreciseL2 {
+    accumulation: Accumulation,
+}
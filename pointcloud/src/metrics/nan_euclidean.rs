@@ -0,0 +1,51 @@
+//! L2 distance that skips missing values, for data loaded with the `ignore_pairwise` NaN policy.
+//!
+//! Only the dimensions where both points have a value are summed, and the sum is scaled up by
+//! `d / present` to make up for the ones that were skipped. It matches
+//! `sklearn.metrics.pairwise.nan_euclidean_distances`, except that a pair with no dimensions in
+//! common is at distance 0 rather than NaN.
+
+use super::NanEuclidean;
+use crate::base_traits::Metric;
+
+impl Metric<[f32]> for NanEuclidean {
+    fn dist(&self, x: &[f32], y: &[f32]) -> f32 {
+        nan_euclidean_dense_f32(x, y)
+    }
+}
+
+/// L2 distance between two dense vectors over the dimensions where neither is NaN.
+#[inline]
+pub fn nan_euclidean_dense_f32(x: &[f32], y: &[f32]) -> f32 {
+    let len = x.len().min(y.len());
+    let (total, present) = x
+        .iter()
+        .zip(y)
+        .filter(|(xi, yi)| !xi.is_nan() && !yi.is_nan())
+        .fold((0.0f32, 0usize), |(total, present), (xi, yi)| {
+            (total + (xi - yi) * (xi - yi), present + 1)
+        });
+    if present == 0 {
+        return 0.0;
+    }
+    (total * len as f32 / present as f32).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nan_euclidean_sklearn_reference() {
+        // Values from sklearn.metrics.pairwise.nan_euclidean_distances
+        assert_approx_eq!(
+            NanEuclidean {}.dist(&[3.0, f32::NAN, 5.0][..], &[1.0, 0.0, 0.0][..]),
+            6.595_453
+        );
+        assert_approx_eq!(NanEuclidean {}.dist(&[3.0, 4.0][..], &[0.0, 0.0][..]), 5.0);
+        assert_approx_eq!(
+            NanEuclidean {}.dist(&[f32::NAN, 1.0][..], &[2.0, f32::NAN][..]),
+            0.0
+        );
+    }
+}
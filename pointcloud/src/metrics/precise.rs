@@ -0,0 +1,200 @@
+//! L1 and L2 distances with a choice of how the sum over the coordinates is accumulated.
+//!
+//! The [`L1`](super::L1) and [`L2`](super::L2) kernels sum in 16 `f32` lanes. On long dense
+//! vectors a lane's running total gets large enough that the small terms added to it after are
+//! rounded away, and two points whose true distances are close can come out in the wrong order. [`Accumulation::F64`]
+//! sums in `f64` and [`Accumulation::Kahan`] uses compensated `f32` summation, both are slower than
+//! the SIMD kernels. [`Accumulation::F32`] is the plain kernel.
+
+use super::{l1_dense_f32, sq_l2_dense_f32, PreciseL1, PreciseL2};
+use crate::base_traits::Metric;
+
+/// How a distance kernel sums the per coordinate terms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Accumulation {
+    /// The SIMD `f32` kernels, fastest and least accurate.
+    #[default]
+    F32,
+    /// Each term is computed and summed in `f64`, the result is rounded to `f32` at the end.
+    F64,
+    /// Kahan (compensated) summation of `f32` terms.
+    Kahan,
+}
+
+impl Accumulation {
+    /// Parses `f32`, `f64` or `kahan`.
+    pub fn from_name(name: &str) -> Option<Accumulation> {
+        match name.to_lowercase().as_str() {
+            "f32" => Some(Accumulation::F32),
+            "f64" => Some(Accumulation::F64),
+            "kahan" => Some(Accumulation::Kahan),
+            _ => None,
+        }
+    }
+}
+
+/// Sums the terms with Kahan's compensated summation.
+#[inline]
+fn kahan_sum<I: Iterator<Item = f32>>(terms: I) -> f32 {
+    let mut sum = 0.0f32;
+    let mut compensation = 0.0f32;
+    for term in terms {
+        let y = term - compensation;
+        let t = sum + y;
+        compensation = (t - sum) - y;
+        sum = t;
+    }
+    sum
+}
+
+/// Squared L2 distance accumulated in `f64`.
+#[inline]
+pub fn sq_l2_dense_f32_f64(x: &[f32], y: &[f32]) -> f32 {
+    x.iter()
+        .zip(y)
+        .map(|(xi, yi)| {
+            let d = *xi as f64 - *yi as f64;
+            d * d
+        })
+        .sum::<f64>() as f32
+}
+
+/// Squared L2 distance with Kahan summation.
+#[inline]
+pub fn sq_l2_dense_f32_kahan(x: &[f32], y: &[f32]) -> f32 {
+    kahan_sum(x.iter().zip(y).map(|(xi, yi)| (xi - yi) * (xi - yi)))
+}
+
+/// L1 distance accumulated in `f64`.
+#[inline]
+pub fn l1_dense_f32_f64(x: &[f32], y: &[f32]) -> f32 {
+    x.iter()
+        .zip(y)
+        .map(|(xi, yi)| (*xi as f64 - *yi as f64).abs())
+        .sum::<f64>() as f32
+}
+
+/// L1 distance with Kahan summation.
+#[inline]
+pub fn l1_dense_f32_kahan(x: &[f32], y: &[f32]) -> f32 {
+    kahan_sum(x.iter().zip(y).map(|(xi, yi)| (xi - yi).abs()))
+}
+
+impl PreciseL1 {
+    /// Creates the metric with the given accumulator.
+    pub fn new(accumulation: Accumulation) -> PreciseL1 {
+        PreciseL1 { accumulation }
+    }
+
+    /// The accumulator this metric sums with.
+    pub fn accumulation(&self) -> Accumulation {
+        self.accumulation
+    }
+}
+
+impl PreciseL2 {
+    /// Creates the metric with the given accumulator.
+    pub fn new(accumulation: Accumulation) -> PreciseL2 {
+        PreciseL2 { accumulation }
+    }
+
+    /// The accumulator this metric sums with.
+    pub fn accumulation(&self) -> Accumulation {
+        self.accumulation
+    }
+}
+
+impl Metric<[f32]> for PreciseL1 {
+    fn dist(&self, x: &[f32], y: &[f32]) -> f32 {
+        match self.accumulation {
+            Accumulation::F32 => l1_dense_f32(x, y),
+            Accumulation::F64 => l1_dense_f32_f64(x, y),
+            Accumulation::Kahan => l1_dense_f32_kahan(x, y),
+        }
+    }
+}
+
+impl Metric<[f32]> for PreciseL2 {
+    fn dist(&self, x: &[f32], y: &[f32]) -> f32 {
+        match self.accumulation {
+            Accumulation::F32 => sq_l2_dense_f32(x, y),
+            Accumulation::F64 => sq_l2_dense_f32_f64(x, y),
+            Accumulation::Kahan => sq_l2_dense_f32_kahan(x, y),
+        }
+        .sqrt()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::{L1, L2};
+
+    /// Two points and a query at the origin. `far` is a big first coordinate plus 64 ones in the
+    /// first SIMD lane, `near` is the same big coordinate plus 32 ones in the second lane. In `f32`
+    /// the first lane's total is stuck at the big value, so the ones of `far` are all lost and it
+    /// comes out nearer than `near`.
+    fn lane_points(big: f32) -> (Vec<f32>, Vec<f32>, Vec<f32>) {
+        let dim = 16 * 66;
+        let mut far = vec![0.0; dim];
+        let mut near = vec![0.0; dim];
+        far[0] = big;
+        near[0] = big;
+        for j in 1..=64 {
+            far[16 * j] = 1.0;
+        }
+        for j in 1..=32 {
+            near[16 * j + 1] = 1.0;
+        }
+        (vec![0.0; dim], far, near)
+    }
+
+    #[test]
+    fn l2_ordering_is_stable() {
+        // The squared distance is 2^24 + 64 for far and 2^24 + 32 for near.
+        let (query, far, near) = lane_points(4096.0);
+        let plain = L2 {};
+        assert!(plain.dist(&query[..], &far[..]) < plain.dist(&query[..], &near[..]));
+        for accumulation in &[Accumulation::F64, Accumulation::Kahan] {
+            let metric = PreciseL2::new(*accumulation);
+            assert!(
+                metric.dist(&query[..], &far[..]) > metric.dist(&query[..], &near[..]),
+                "{:?}",
+                accumulation
+            );
+        }
+        let metric = PreciseL2::default();
+        assert_eq!(
+            metric.dist(&query[..], &far[..]),
+            plain.dist(&query[..], &far[..])
+        );
+    }
+
+    #[test]
+    fn l1_ordering_is_stable() {
+        let (query, far, near) = lane_points(16_777_216.0);
+        let plain = L1 {};
+        assert!(plain.dist(&query[..], &far[..]) < plain.dist(&query[..], &near[..]));
+        for accumulation in &[Accumulation::F64, Accumulation::Kahan] {
+            let metric = PreciseL1::new(*accumulation);
+            assert!(
+                metric.dist(&query[..], &far[..]) > metric.dist(&query[..], &near[..]),
+                "{:?}",
+                accumulation
+            );
+        }
+    }
+
+    #[test]
+    fn accumulations_agree_on_small_data() {
+        let x = [1.0, 2.0, 3.0];
+        let y = [2.0, 0.0, 3.5];
+        for accumulation in &[Accumulation::F32, Accumulation::F64, Accumulation::Kahan] {
+            assert_approx_eq!(
+                PreciseL2::new(*accumulation).dist(&x[..], &y[..]),
+                5.25f32.sqrt()
+            );
+            assert_approx_eq!(PreciseL1::new(*accumulation).dist(&x[..], &y[..]), 3.5);
+        }
+    }
+}
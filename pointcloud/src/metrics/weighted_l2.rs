@@ -0,0 +1,121 @@
+//! L2 distance with a non-negative weight per dimension.
+//!
+//! This is `sqrt(sum w_i (x_i - y_i)^2)`, the same as scaling each dimension by `sqrt(w_i)` and
+//! taking the plain L2 distance, but without materializing a rescaled copy of the point cloud.
+//! A weight of 0 drops the dimension. The default has no weights and is the plain L2 distance.
+
+use super::{sq_l2_dense_f32, WeightedL2};
+use crate::base_traits::Metric;
+use crate::pc_errors::{PointCloudError, PointCloudResult};
+use packed_simd::*;
+
+impl WeightedL2 {
+    /// Creates the metric from one weight per dimension. The weights have to be finite and
+    /// non-negative, and the point cloud's dimension has to match their number.
+    pub fn new(weights: Vec<f32>) -> PointCloudResult<WeightedL2> {
+        if weights.iter().any(|w| !w.is_finite() || *w < 0.0) {
+            return Err(PointCloudError::MetricParameterError {
+                message: "the weights must be finite and non-negative",
+            });
+        }
+        Ok(WeightedL2 { weights })
+    }
+
+    /// The per-dimension weights, empty if this is the unweighted default.
+    pub fn weights(&self) -> &[f32] {
+        &self.weights
+    }
+}
+
+impl Metric<[f32]> for WeightedL2 {
+    fn dist(&self, x: &[f32], y: &[f32]) -> f32 {
+        if self.weights.is_empty() {
+            sq_l2_dense_f32(x, y).sqrt()
+        } else {
+            sq_weighted_l2_dense_f32(&self.weights, x, y).sqrt()
+        }
+    }
+}
+
+/// Weighted squared L2 distance, `sum w_i (x_i - y_i)^2`. All three slices should have the same length.
+#[inline]
+pub fn sq_weighted_l2_dense_f32(mut w: &[f32], mut x: &[f32], mut y: &[f32]) -> f32 {
+    let mut d_acc_16 = f32x16::splat(0.0);
+    while y.len() > 16 && x.len() > 16 && w.len() > 16 {
+        let w_simd = f32x16::from_slice_unaligned(w);
+        let x_simd = f32x16::from_slice_unaligned(x);
+        let y_simd = f32x16::from_slice_unaligned(y);
+        let diff = x_simd - y_simd;
+        d_acc_16 += w_simd * diff * diff;
+        w = &w[16..];
+        y = &y[16..];
+        x = &x[16..];
+    }
+    let leftover = w
+        .iter()
+        .zip(x.iter().zip(y))
+        .map(|(wi, (xi, yi))| wi * (xi - yi) * (xi - yi))
+        .fold(0.0, |acc, d| acc + d);
+    leftover + d_acc_16.sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::base_traits::PointCloud;
+    use crate::data_sources::DataRam;
+    use crate::metrics::L2;
+
+    #[test]
+    fn weighted_l2_matches_rescaled_l2() {
+        let weights: Vec<f32> = (0..37).map(|i| (i % 5) as f32).collect();
+        let x: Vec<f32> = (0..37).map(|_| rand::random::<f32>()).collect();
+        let y: Vec<f32> = (0..37).map(|_| rand::random::<f32>()).collect();
+        let scaled_x: Vec<f32> = x
+            .iter()
+            .zip(&weights)
+            .map(|(xi, w)| xi * w.sqrt())
+            .collect();
+        let scaled_y: Vec<f32> = y
+            .iter()
+            .zip(&weights)
+            .map(|(yi, w)| yi * w.sqrt())
+            .collect();
+
+        let metric = WeightedL2::new(weights).unwrap();
+        assert_approx_eq!(
+            metric.dist(&x[..], &y[..]),
+            L2 {}.dist(&scaled_x[..], &scaled_y[..])
+        );
+    }
+
+    #[test]
+    fn weighted_l2_zero_weight_drops_dimension() {
+        let metric = WeightedL2::new(vec![1.0, 0.0]).unwrap();
+        assert_approx_eq!(metric.dist(&[0.0, 0.0][..], &[3.0, 100.0][..]), 3.0);
+        assert_approx_eq!(metric.dist(&[0.0, 0.0][..], &[0.0, 5.0][..]), 0.0);
+    }
+
+    #[test]
+    fn weighted_l2_default_is_l2() {
+        assert_approx_eq!(
+            WeightedL2::default().dist(&[0.0, 0.0][..], &[3.0, 4.0][..]),
+            5.0
+        );
+    }
+
+    #[test]
+    fn weighted_l2_rejects_negative_weights() {
+        assert!(WeightedL2::new(vec![1.0, -1.0]).is_err());
+        assert!(WeightedL2::new(vec![1.0, std::f32::NAN]).is_err());
+    }
+
+    #[test]
+    fn weighted_l2_cloud_distances() {
+        let metric = WeightedL2::new(vec![4.0, 1.0]).unwrap();
+        let data = DataRam::with_metric(vec![0.0, 0.0, 1.0, 0.0, 0.0, 1.0], 2, metric).unwrap();
+        let dists = data.distances_to_point_index(0, &[1, 2]).unwrap();
+        assert_approx_eq!(dists[0], 2.0);
+        assert_approx_eq!(dists[1], 1.0);
+    }
+}
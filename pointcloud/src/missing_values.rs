@@ -0,0 +1,171 @@
+//! What to do with NaNs in the data.
+//!
+//! A NaN in a point makes every distance to it NaN, and the tree can't be built on those. The
+//! policy is chosen when the data is loaded. `Reject` refuses data with missing values,
+//! `ImputeMean` fills them in with the mean of their dimension, and `IgnorePairwise` keeps them
+//! so a metric that skips them, like [`crate::metrics::NanEuclidean`], can be used.
+//!
+//! Like a [`crate::normalization::Normalizer`], the fitted [`MissingValues`] stays with the data
+//! source and is applied to points added later and to query points.
+
+use crate::pc_errors::*;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// The ways of handling missing values.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum NanPolicy {
+    /// Points with a NaN are an error
+    Reject,
+    /// NaNs are replaced with the mean of the values of their dimension
+    ImputeMean,
+    /// NaNs are kept, the metric has to skip them
+    IgnorePairwise,
+}
+
+impl FromStr for NanPolicy {
+    type Err = ParsingError;
+    fn from_str(s: &str) -> Result<NanPolicy, ParsingError> {
+        match s {
+            "reject" => Ok(NanPolicy::Reject),
+            "impute_mean" | "mean" => Ok(NanPolicy::ImputeMean),
+            "ignore_pairwise" | "ignore" => Ok(NanPolicy::IgnorePairwise),
+            _ => Err(ParsingError::RegularParsingError(
+                "Unknown NaN policy, expected reject, impute_mean or ignore_pairwise",
+            )),
+        }
+    }
+}
+
+/// A NaN policy fitted to some data. For `ImputeMean` this holds the per dimension means, a
+/// dimension without any values gets a mean of 0.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct MissingValues {
+    policy: NanPolicy,
+    means: Vec<f32>,
+}
+
+impl MissingValues {
+    /// Fits the policy to row major data of dimension `dim`. Rejecting fails on the first NaN.
+    pub fn fit(policy: NanPolicy, data: &[f32], dim: usize) -> PointCloudResult<MissingValues> {
+        let means = match policy {
+            NanPolicy::Reject => {
+                check_for_nans(data, dim, 0)?;
+                Vec::new()
+            }
+            NanPolicy::ImputeMean => {
+                let mut sums = vec![0.0f64; dim];
+                let mut counts = vec![0usize; dim];
+                for point in data.chunks_exact(dim) {
+                    for (i, x) in point.iter().enumerate() {
+                        if !x.is_nan() {
+                            sums[i] += *x as f64;
+                            counts[i] += 1;
+                        }
+                    }
+                }
+                sums.iter()
+                    .zip(counts.iter())
+                    .map(|(s, c)| if *c > 0 { (s / *c as f64) as f32 } else { 0.0 })
+                    .collect()
+            }
+            NanPolicy::IgnorePairwise => Vec::new(),
+        };
+        Ok(MissingValues { policy, means })
+    }
+
+    /// The policy that was fitted.
+    pub fn policy(&self) -> NanPolicy {
+        self.policy
+    }
+
+    /// The fitted means, empty unless the policy is `ImputeMean`.
+    pub fn means(&self) -> &[f32] {
+        &self.means
+    }
+
+    /// Applies the policy to row major data in place. The points are numbered from
+    /// `first_index` in the error.
+    pub fn apply_all(
+        &self,
+        data: &mut [f32],
+        dim: usize,
+        first_index: usize,
+    ) -> PointCloudResult<()> {
+        match self.policy {
+            NanPolicy::Reject => check_for_nans(data, dim, first_index),
+            NanPolicy::ImputeMean => {
+                data.chunks_exact_mut(dim)
+                    .for_each(|point| self.impute(point));
+                Ok(())
+            }
+            NanPolicy::IgnorePairwise => Ok(()),
+        }
+    }
+
+    /// Fills in the missing values of a point with the means, this does nothing for the other
+    /// policies.
+    pub fn impute(&self, point: &mut [f32]) {
+        for (x, mean) in point.iter_mut().zip(&self.means) {
+            if x.is_nan() {
+                *x = *mean;
+            }
+        }
+    }
+}
+
+fn check_for_nans(data: &[f32], dim: usize, first_index: usize) -> PointCloudResult<()> {
+    match data.iter().position(|x| x.is_nan()) {
+        Some(i) => Err(PointCloudError::MissingValue {
+            index: first_index + i / dim,
+            dim: i % dim,
+        }),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reject_finds_the_first_nan() {
+        let data = [0.0, 1.0, 2.0, f32::NAN];
+        match MissingValues::fit(NanPolicy::Reject, &data, 2) {
+            Err(PointCloudError::MissingValue { index, dim }) => {
+                assert_eq!(index, 1);
+                assert_eq!(dim, 1);
+            }
+            other => panic!("expected a missing value error, got {:?}", other),
+        }
+        let missing = MissingValues::fit(NanPolicy::Reject, &data[..2], 2).unwrap();
+        let mut more = [f32::NAN, 0.0];
+        assert!(missing.apply_all(&mut more, 2, 5).is_err());
+    }
+
+    #[test]
+    fn impute_mean_skips_nans() {
+        let mut data = [1.0, f32::NAN, 3.0, 4.0, f32::NAN, f32::NAN];
+        let missing = MissingValues::fit(NanPolicy::ImputeMean, &data, 2).unwrap();
+        assert_approx_eq!(missing.means()[0], 2.0);
+        assert_approx_eq!(missing.means()[1], 4.0);
+        missing.apply_all(&mut data, 2, 0).unwrap();
+        assert_approx_eq!(data[1], 4.0);
+        assert_approx_eq!(data[4], 2.0);
+        assert!(data.iter().all(|x| !x.is_nan()));
+    }
+
+    #[test]
+    fn parses_names() {
+        assert_eq!(
+            "impute_mean".parse::<NanPolicy>().unwrap(),
+            NanPolicy::ImputeMean
+        );
+        assert_eq!(
+            "ignore_pairwise".parse::<NanPolicy>().unwrap(),
+            NanPolicy::IgnorePairwise
+        );
+        assert!("drop".parse::<NanPolicy>().is_err());
+    }
+}
@@ -0,0 +1,202 @@
+//! Preprocessing fitted to the data when it's loaded, and applied to every query point after.
+//!
+//! A [`Normalizer`] is fitted once, the data is transformed in place and the normalizer stays
+//! with the data source. The tree asks the point cloud to preprocess query points, see
+//! [`crate::PointCloud::preprocess_query`], so queries can't end up in a different scale than
+//! the points they're compared to.
+
+use crate::pc_errors::*;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// The kinds of normalization.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Normalization {
+    /// Each dimension is shifted to mean 0 and scaled to variance 1
+    ZScore,
+    /// Each dimension is shifted and scaled to `[0, 1]`
+    MinMax,
+    /// Each point is scaled to length 1, nothing is fitted
+    L2,
+}
+
+impl FromStr for Normalization {
+    type Err = ParsingError;
+    fn from_str(s: &str) -> Result<Normalization, ParsingError> {
+        match s {
+            "z_score" | "zscore" => Ok(Normalization::ZScore),
+            "min_max" | "minmax" => Ok(Normalization::MinMax),
+            "l2" => Ok(Normalization::L2),
+            _ => Err(ParsingError::RegularParsingError(
+                "Unknown normalization, expected z_score, min_max or l2",
+            )),
+        }
+    }
+}
+
+/// A fitted normalization. For the per dimension kinds a point is transformed to
+/// `(x - shift) * scale`. Constant dimensions get a scale of 1 so they don't blow up.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Normalizer {
+    kind: Normalization,
+    shift: Vec<f32>,
+    scale: Vec<f32>,
+}
+
+impl Normalizer {
+    /// Fits the normalization to row major data of dimension `dim`.
+    pub fn fit(kind: Normalization, data: &[f32], dim: usize) -> Normalizer {
+        let count = data.len() / dim;
+        let (shift, scale) = match kind {
+            Normalization::ZScore => {
+                let mut mean = vec![0.0f64; dim];
+                let mut second = vec![0.0f64; dim];
+                let mut counts = vec![0usize; dim];
+                for point in data.chunks_exact(dim) {
+                    for (i, x) in point.iter().enumerate() {
+                        // Missing values are left out of the fit
+                        if !x.is_nan() {
+                            mean[i] += *x as f64;
+                            second[i] += (*x as f64) * (*x as f64);
+                            counts[i] += 1;
+                        }
+                    }
+                }
+                let (shift, scale) = mean
+                    .iter()
+                    .zip(second.iter())
+                    .zip(counts.iter())
+                    .map(|((m, s), c)| {
+                        let c = (*c).max(1) as f64;
+                        let m = m / c;
+                        let sd = (s / c - m * m).max(0.0).sqrt() as f32;
+                        (m as f32, inverse_or_one(sd))
+                    })
+                    .unzip();
+                (shift, scale)
+            }
+            Normalization::MinMax => {
+                let mut min = vec![f32::INFINITY; dim];
+                let mut max = vec![f32::NEG_INFINITY; dim];
+                for point in data.chunks_exact(dim) {
+                    for (i, x) in point.iter().enumerate() {
+                        min[i] = min[i].min(*x);
+                        max[i] = max[i].max(*x);
+                    }
+                }
+                if count == 0 {
+                    (vec![0.0; dim], vec![1.0; dim])
+                } else {
+                    let scale = min
+                        .iter()
+                        .zip(max.iter())
+                        .map(|(a, b)| inverse_or_one(b - a))
+                        .collect();
+                    (min, scale)
+                }
+            }
+            Normalization::L2 => (Vec::new(), Vec::new()),
+        };
+        Normalizer { kind, shift, scale }
+    }
+
+    /// The kind of normalization that was fitted.
+    pub fn kind(&self) -> Normalization {
+        self.kind
+    }
+
+    /// The fitted shift, empty for `L2`.
+    pub fn shift(&self) -> &[f32] {
+        &self.shift
+    }
+
+    /// The fitted scale, empty for `L2`.
+    pub fn scale(&self) -> &[f32] {
+        &self.scale
+    }
+
+    /// Normalizes a point in place.
+    pub fn apply(&self, point: &mut [f32]) {
+        match self.kind {
+            Normalization::ZScore | Normalization::MinMax => {
+                for ((x, shift), scale) in point.iter_mut().zip(&self.shift).zip(&self.scale) {
+                    *x = (*x - shift) * scale;
+                }
+            }
+            Normalization::L2 => {
+                let norm = point.iter().map(|x| x * x).sum::<f32>().sqrt();
+                if norm > 0.0 {
+                    point.iter_mut().for_each(|x| *x /= norm);
+                }
+            }
+        }
+    }
+
+    /// Normalizes every point of row major data in place.
+    pub fn apply_all(&self, data: &mut [f32], dim: usize) {
+        data.chunks_exact_mut(dim)
+            .for_each(|point| self.apply(point));
+    }
+
+    /// A normalized copy of the point.
+    pub fn transform(&self, point: &[f32]) -> Vec<f32> {
+        let mut point = point.to_vec();
+        self.apply(&mut point);
+        point
+    }
+}
+
+fn inverse_or_one(x: f32) -> f32 {
+    if x > f32::EPSILON {
+        1.0 / x
+    } else {
+        1.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn z_score_centers_and_scales() {
+        let data = [1.0, 5.0, 3.0, 5.0];
+        let normalizer = Normalizer::fit(Normalization::ZScore, &data, 2);
+        assert_approx_eq!(normalizer.shift()[0], 2.0);
+        assert_approx_eq!(normalizer.scale()[0], 1.0);
+        // The constant dimension isn't scaled
+        assert_approx_eq!(normalizer.scale()[1], 1.0);
+        let point = normalizer.transform(&[3.0, 5.0]);
+        assert_approx_eq!(point[0], 1.0);
+        assert_approx_eq!(point[1], 0.0);
+    }
+
+    #[test]
+    fn min_max_and_l2() {
+        let data = [0.0, -1.0, 4.0, 1.0];
+        let normalizer = Normalizer::fit(Normalization::MinMax, &data, 2);
+        let point = normalizer.transform(&[2.0, 1.0]);
+        assert_approx_eq!(point[0], 0.5);
+        assert_approx_eq!(point[1], 1.0);
+
+        let normalizer = Normalizer::fit(Normalization::L2, &data, 2);
+        let point = normalizer.transform(&[3.0, 4.0]);
+        assert_approx_eq!(point[0], 0.6);
+        assert_approx_eq!(point[1], 0.8);
+        assert_eq!(normalizer.transform(&[0.0, 0.0]), vec![0.0, 0.0]);
+    }
+
+    #[test]
+    fn parses_names() {
+        assert_eq!(
+            "z_score".parse::<Normalization>().unwrap(),
+            Normalization::ZScore
+        );
+        assert_eq!(
+            "min_max".parse::<Normalization>().unwrap(),
+            Normalization::MinMax
+        );
+        assert!("log".parse::<Normalization>().is_err());
+    }
+}
@@ -0,0 +1,320 @@
+//! Partition keys, for serving several tenants' data out of one tree.
+//!
+//! Each point can be tagged with a partition key, like a tenant id, when it's added. Queries can
+//! then be restricted to one or more partitions. The keys are interned, every partition gets a
+//! small integer id and a bitmap of its points.
+
+use crate::base_traits::*;
+use crate::pc_errors::{PointCloudError, PointCloudResult};
+use hashbrown::HashMap;
+use roaring::RoaringBitmap;
+
+/// The partition key of every point. Points without a key are in no partition.
+#[derive(Debug, Clone, Default)]
+pub struct PartitionMap {
+    names: Vec<String>,
+    ids: HashMap<String, u32>,
+    point_partitions: Vec<Option<u32>>,
+    members: Vec<RoaringBitmap>,
+}
+
+impl PartitionMap {
+    /// An empty map.
+    pub fn new() -> PartitionMap {
+        Self::default()
+    }
+
+    /// A map of `len` points in no partition.
+    pub fn unpartitioned(len: usize) -> PartitionMap {
+        PartitionMap {
+            point_partitions: vec![None; len],
+            ..Default::default()
+        }
+    }
+
+    /// Takes the keys of the points in index order, `None` for points without one.
+    pub fn from_keys<S: AsRef<str>>(keys: &[Option<S>]) -> PartitionMap {
+        let mut map = PartitionMap::new();
+        for key in keys {
+            map.push(key.as_ref().map(|k| k.as_ref()));
+        }
+        map
+    }
+
+    /// Appends the key of the next point.
+    pub fn push(&mut self, key: Option<&str>) {
+        let pi = self.point_partitions.len() as u32;
+        let id = key.map(|key| self.intern(key));
+        if let Some(id) = id {
+            self.members[id as usize].insert(pi);
+        }
+        self.point_partitions.push(id);
+    }
+
+    fn intern(&mut self, key: &str) -> u32 {
+        match self.ids.get(key) {
+            Some(id) => *id,
+            None => {
+                let id = self.names.len() as u32;
+                self.names.push(key.to_string());
+                self.ids.insert(key.to_string(), id);
+                self.members.push(RoaringBitmap::new());
+                id
+            }
+        }
+    }
+
+    /// The number of points with a key or without one.
+    pub fn len(&self) -> usize {
+        self.point_partitions.len()
+    }
+
+    /// If there are no points.
+    pub fn is_empty(&self) -> bool {
+        self.point_partitions.is_empty()
+    }
+
+    /// The id of the partition a point is in.
+    pub fn partition(&self, pi: usize) -> Option<u32> {
+        self.point_partitions.get(pi).copied().flatten()
+    }
+
+    /// The id of a partition key, `None` if no point has it.
+    pub fn id(&self, key: &str) -> Option<u32> {
+        self.ids.get(key).copied()
+    }
+
+    /// The key of a partition id.
+    pub fn key(&self, id: u32) -> Option<&str> {
+        self.names.get(id as usize).map(|n| n.as_str())
+    }
+
+    /// All the partition keys, in the order of their ids.
+    pub fn keys(&self) -> &[String] {
+        &self.names
+    }
+
+    /// The points in a partition.
+    pub fn members(&self, id: u32) -> Option<&RoaringBitmap> {
+        self.members.get(id as usize)
+    }
+
+    /// The ids of the keys. Keys no point has are left out.
+    pub fn ids<S: AsRef<str>>(&self, keys: &[S]) -> Vec<u32> {
+        keys.iter().filter_map(|k| self.id(k.as_ref())).collect()
+    }
+
+    /// The points in any of the partitions.
+    pub fn union(&self, ids: &[u32]) -> RoaringBitmap {
+        let mut points = RoaringBitmap::new();
+        for id in ids {
+            if let Some(members) = self.members(*id) {
+                points.union_with(members);
+            }
+        }
+        points
+    }
+}
+
+/// A point cloud points can be appended to with a partition key.
+pub trait PartitionedCloudMut: ExtendableCloud {
+    /// Appends the points, see [`ExtendableCloud::extend_points`], all in the same partition.
+    /// `None` leaves them in no partition.
+    fn extend_points_in_partition(
+        &mut self,
+        points: &[f32],
+        labels: &[Option<&Self::Label>],
+        partition: Option<&str>,
+    ) -> PointCloudResult<()>;
+}
+
+/// Simply shoves together a point cloud and the partition keys of its points.
+#[derive(Debug)]
+pub struct SimplePartitionedCloud<D> {
+    data: D,
+    partitions: PartitionMap,
+}
+
+impl<D: PointCloud> SimplePartitionedCloud<D> {
+    /// Creates a new one, there has to be a key, or `None`, for every point.
+    pub fn new(data: D, partitions: PartitionMap) -> PointCloudResult<Self> {
+        if data.len() != partitions.len() {
+            return Err(PointCloudError::DataAccessError {
+                index: partitions.len(),
+                reason: format!(
+                    "got {} partition keys for {} points",
+                    partitions.len(),
+                    data.len()
+                ),
+            });
+        }
+        Ok(SimplePartitionedCloud { data, partitions })
+    }
+
+    /// The underlying point cloud
+    pub fn data(&self) -> &D {
+        &self.data
+    }
+}
+
+impl<D: ExtendableCloud> ExtendableCloud for SimplePartitionedCloud<D> {
+    /// The new points are in no partition.
+    fn extend_points(
+        &mut self,
+        points: &[f32],
+        labels: &[Option<&Self::Label>],
+    ) -> PointCloudResult<()> {
+        self.extend_points_in_partition(points, labels, None)
+    }
+    fn collapse_point(&mut self, pi: usize, into: usize) -> PointCloudResult<()> {
+        self.data.collapse_point(pi, into)
+    }
+}
+
+impl<D: ExtendableCloud> PartitionedCloudMut for SimplePartitionedCloud<D> {
+    fn extend_points_in_partition(
+        &mut self,
+        points: &[f32],
+        labels: &[Option<&Self::Label>],
+        partition: Option<&str>,
+    ) -> PointCloudResult<()> {
+        self.data.extend_points(points, labels)?;
+        while self.partitions.len() < self.data.len() {
+            self.partitions.push(partition);
+        }
+        Ok(())
+    }
+}
+
+impl<D: DeletableCloud> DeletableCloud for SimplePartitionedCloud<D> {
+    fn delete_point(&mut self, pi: usize) -> PointCloudResult<()> {
+        self.data.delete_point(pi)
+    }
+    fn restore_point(&mut self, pi: usize) -> PointCloudResult<()> {
+        self.data.restore_point(pi)
+    }
+}
+
+impl<D: LabeledCloudMut> LabeledCloudMut for SimplePartitionedCloud<D> {
+    fn set_label(&mut self, pn: usize, label: Option<&Self::Label>) -> PointCloudResult<()> {
+        self.data.set_label(pn, label)
+    }
+}
+
+impl<D: PointCloud> PointCloud for SimplePartitionedCloud<D> {
+    type Metric = D::Metric;
+    type Point = D::Point;
+    type PointRef<'a> = D::PointRef<'a>;
+    type Metadata = D::Metadata;
+    type MetaSummary = D::MetaSummary;
+    type Label = D::Label;
+    type LabelSummary = D::LabelSummary;
+
+    #[inline]
+    fn dim(&self) -> usize {
+        self.data.dim()
+    }
+    #[inline]
+    fn len(&self) -> usize {
+        self.data.len()
+    }
+    #[inline]
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+    #[inline]
+    fn reference_indexes(&self) -> Vec<usize> {
+        self.data.reference_indexes()
+    }
+    #[inline]
+    fn point<'a, 'b: 'a>(&'b self, i: usize) -> PointCloudResult<Self::PointRef<'a>> {
+        self.data.point(i)
+    }
+    #[inline]
+    fn metric(&self) -> &Self::Metric {
+        self.data.metric()
+    }
+    #[inline]
+    fn is_deleted(&self, pi: usize) -> bool {
+        self.data.is_deleted(pi)
+    }
+    fn deleted_indexes(&self) -> Vec<usize> {
+        self.data.deleted_indexes()
+    }
+    #[inline]
+    fn multiplicity(&self, pi: usize) -> usize {
+        self.data.multiplicity(pi)
+    }
+    #[inline]
+    fn representative(&self, pi: usize) -> usize {
+        self.data.representative(pi)
+    }
+    fn dim_names(&self) -> Option<&[String]> {
+        self.data.dim_names()
+    }
+    fn preprocess_query(&self, point: &Self::Point) -> Option<Box<Self::Point>> {
+        self.data.preprocess_query(point)
+    }
+    fn partitions(&self) -> Option<&PartitionMap> {
+        Some(&self.partitions)
+    }
+    fn metadata(&self, pn: usize) -> PointCloudResult<Option<&Self::Metadata>> {
+        self.data.metadata(pn)
+    }
+    fn metasummary(&self, pns: &[usize]) -> PointCloudResult<SummaryCounter<Self::MetaSummary>> {
+        self.data.metasummary(pns)
+    }
+    fn label(&self, pn: usize) -> PointCloudResult<Option<&Self::Label>> {
+        self.data.label(pn)
+    }
+    fn label_summary(&self, pns: &[usize]) -> PointCloudResult<SummaryCounter<Self::LabelSummary>> {
+        self.data.label_summary(pns)
+    }
+    fn label_name(&self, label: &Self::Label) -> Option<&str> {
+        self.data.label_name(label)
+    }
+    fn name(&self, pi: usize) -> PointCloudResult<String> {
+        self.data.name(pi)
+    }
+    fn index(&self, pn: &str) -> PointCloudResult<usize> {
+        self.data.index(pn)
+    }
+    fn names(&self) -> Vec<String> {
+        self.data.names()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_sources::DataRam;
+    use crate::L2;
+
+    #[test]
+    fn keys_are_interned() {
+        let map = PartitionMap::from_keys(&[Some("a"), None, Some("b"), Some("a")]);
+        assert_eq!(map.keys(), &["a".to_string(), "b".to_string()][..]);
+        assert_eq!(map.partition(3), map.id("a"));
+        assert_eq!(map.partition(1), None);
+        let union = map.union(&map.ids(&["a", "c"]));
+        assert_eq!(union.iter().collect::<Vec<u32>>(), vec![0, 3]);
+    }
+
+    #[test]
+    fn new_points_get_the_partition() {
+        let data = DataRam::<L2>::new(vec![0.0, 1.0], 1).unwrap();
+        let partitions = PartitionMap::from_keys(&[Some("a"), Some("b")]);
+        let mut cloud = SimplePartitionedCloud::new(data, partitions).unwrap();
+        cloud
+            .extend_points_in_partition(&[2.0, 3.0], &[None, None], Some("b"))
+            .unwrap();
+        cloud.extend_points(&[4.0], &[None]).unwrap();
+        let map = cloud.partitions().unwrap();
+        let b = map.id("b").unwrap();
+        assert_eq!(map.members(b).unwrap().len(), 3);
+        assert_eq!(map.partition(4), None);
+
+        let data = DataRam::<L2>::new(vec![0.0, 1.0], 1).unwrap();
+        assert!(SimplePartitionedCloud::new(data, PartitionMap::new()).is_err());
+    }
+}
@@ -51,6 +51,34 @@ pub enum PointCloudError {
         /// Exact nesting error
         message: &'static str,
     },
+    /// The parameters passed to a metric are invalid
+    MetricParameterError {
+        /// What was wrong with them
+        message: &'static str,
+    },
+    /// A remote data path couldn't be downloaded
+    RemoteError {
+        /// The URL
+        url: String,
+        /// What went wrong
+        reason: String,
+    },
+    /// The number of dimension names doesn't match the dimension of the data
+    DimNamesError {
+        /// The dimension of the data
+        dim: usize,
+        /// The number of names given
+        names: usize,
+    },
+    /// The data was already normalized, normalizing it again would leave queries behind
+    AlreadyNormalized,
+    /// A point has a NaN and the NaN policy rejects those
+    MissingValue {
+        /// The point with the NaN
+        index: usize,
+        /// The dimension of the NaN
+        dim: usize,
+    },
 }
 
 impl fmt::Display for PointCloudError {
@@ -73,6 +101,24 @@ impl fmt::Display for PointCloudError {
                 "The metric failed, you probably mixed sparse and dense data"
             ),
             PointCloudError::NotSorted => write!(f, "Passed data that wasn't sorted"),
+            PointCloudError::MetricParameterError { message } => {
+                write!(f, "invalid metric parameters: {}", message)
+            }
+            PointCloudError::RemoteError {
+                ref url,
+                ref reason,
+            } => write!(f, "unable to download {}: {}", url, reason),
+            PointCloudError::DimNamesError { dim, names } => write!(
+                f,
+                "got {} dimension names for data of dimension {}",
+                names, dim
+            ),
+            PointCloudError::AlreadyNormalized => write!(f, "the data was already normalized"),
+            PointCloudError::MissingValue { index, dim } => write!(
+                f,
+                "point {} has a NaN in dimension {}, choose a NaN policy to load it",
+                index, dim
+            ),
         }
     }
 }
@@ -97,6 +143,13 @@ impl Error for PointCloudError {
                 "The metric failed, you probably mixed sparse and dense data"
             }
             PointCloudError::NotSorted => "Passed data that wasn't sorted",
+            PointCloudError::MetricParameterError { message } => message,
+            PointCloudError::RemoteError { .. } => "unable to download a remote data path",
+            PointCloudError::DimNamesError { .. } => {
+                "the number of dimension names doesn't match the data dimension"
+            }
+            PointCloudError::AlreadyNormalized => "the data was already normalized",
+            PointCloudError::MissingValue { .. } => "a point has a NaN",
         }
     }
 
@@ -109,6 +162,11 @@ impl Error for PointCloudError {
             PointCloudError::NodeNestingError { .. } => None,
             PointCloudError::MetricError { .. } => None,
             PointCloudError::NotSorted { .. } => None,
+            PointCloudError::MetricParameterError { .. } => None,
+            PointCloudError::RemoteError { .. } => None,
+            PointCloudError::DimNamesError { .. } => None,
+            PointCloudError::AlreadyNormalized => None,
+            PointCloudError::MissingValue { .. } => None,
         }
     }
 }
@@ -170,6 +228,71 @@ pub enum ParsingError {
         /// The column name that was messed up
         key: String,
     },
+    /// A numpy `.npy` or `.npz` file was malformed or holds an array we can't read
+    NumpyError {
+        /// The file that was messed up
+        file_name: String,
+        /// What was wrong with it
+        reason: String,
+    },
+    /// A Parquet file was malformed or doesn't have the requested columns, see the `parquet` feature
+    ParquetError {
+        /// The file that was messed up
+        file_name: String,
+        /// What was wrong with it
+        reason: String,
+    },
+    /// An Arrow array or file wasn't in the expected layout, see the `arrow` feature
+    ArrowError {
+        /// What was wrong with it
+        reason: String,
+    },
+    /// A memory mapped point file has a bad header or is cut short
+    MmapFileError {
+        /// The file that was messed up
+        file_name: String,
+        /// What was wrong with it
+        reason: String,
+    },
+    /// A line of a libsvm/svmlight file was malformed
+    LibSvmError {
+        /// The file that the error occored in
+        file_name: String,
+        /// The line that was messed up
+        line_number: usize,
+        /// What was wrong with it
+        reason: String,
+    },
+    /// A JSON, TOML or YAML config didn't match the config schema
+    ConfigError {
+        /// The file that was messed up
+        file_name: String,
+        /// What was wrong with it
+        reason: String,
+    },
+    /// A `.fvecs`, `.bvecs` or `.ivecs` file was malformed
+    VecsError {
+        /// The file that was messed up
+        file_name: String,
+        /// What was wrong with it
+        reason: String,
+    },
+    /// A TFRecord file or one of its examples was malformed
+    TfRecordError {
+        /// The file that was messed up
+        file_name: String,
+        /// The record, counting from 0
+        record: usize,
+        /// What was wrong with it
+        reason: String,
+    },
+    /// A benchmark dataset's download couldn't be converted, see the `datasets` feature
+    DatasetError {
+        /// The name of the dataset
+        dataset: String,
+        /// What was wrong with it
+        reason: String,
+    },
     /// Something else happened parsing a string
     RegularParsingError(&'static str),
 }
@@ -187,6 +310,15 @@ impl Error for ParsingError {
             ParsingError::MalformedYamlError { .. } => "there is a error reading a yaml entry",
             ParsingError::MissingYamlError { .. } => "not all message fields set",
             ParsingError::CSVReadError { .. } => "issue reading a CSV entry",
+            ParsingError::NumpyError { .. } => "issue reading a numpy array",
+            ParsingError::ParquetError { .. } => "issue reading a parquet file",
+            ParsingError::ArrowError { .. } => "issue reading arrow data",
+            ParsingError::MmapFileError { .. } => "issue mapping a point file",
+            ParsingError::LibSvmError { .. } => "issue reading a libsvm line",
+            ParsingError::ConfigError { .. } => "issue reading a config",
+            ParsingError::VecsError { .. } => "issue reading a vecs file",
+            ParsingError::TfRecordError { .. } => "issue reading a tfrecord",
+            ParsingError::DatasetError { .. } => "issue converting a benchmark dataset",
             ParsingError::RegularParsingError(..) => "Error parsing a string",
         }
     }
@@ -196,6 +328,15 @@ impl Error for ParsingError {
             ParsingError::MalformedYamlError { .. } => None,
             ParsingError::MissingYamlError { .. } => None,
             ParsingError::CSVReadError { .. } => None,
+            ParsingError::NumpyError { .. } => None,
+            ParsingError::ParquetError { .. } => None,
+            ParsingError::ArrowError { .. } => None,
+            ParsingError::MmapFileError { .. } => None,
+            ParsingError::LibSvmError { .. } => None,
+            ParsingError::ConfigError { .. } => None,
+            ParsingError::VecsError { .. } => None,
+            ParsingError::TfRecordError { .. } => None,
+            ParsingError::DatasetError { .. } => None,
             ParsingError::RegularParsingError(..) => None,
         }
     }
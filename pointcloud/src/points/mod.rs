@@ -2,6 +2,7 @@
 //! Abstracts data access over several files and glues metadata files to vector data files
 
 use crate::PointRef;
+use half::slice::HalfFloatSliceExt;
 use std::convert::{TryFrom, TryInto};
 use std::marker::PhantomData;
 use std::ops::Deref;
@@ -16,6 +17,32 @@ impl<'a> PointRef for &'a [f32] {
     }
 }
 
+pub use half::f16;
+
+/// Helper iterator for converting half precision floats to `f32`.
+pub struct ConverterF16<'a> {
+    iter: std::slice::Iter<'a, f16>,
+}
+
+impl<'a> Iterator for ConverterF16<'a> {
+    type Item = f32;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.next().map(|u| u.to_f32())
+    }
+}
+
+impl<'a> PointRef for &'a [f16] {
+    type DenseIter = ConverterF16<'a>;
+    fn dense(&self) -> Vec<f32> {
+        let mut dense = vec![0.0; self.len()];
+        self.convert_to_f32_slice(&mut dense);
+        dense
+    }
+    fn dense_iter(&self) -> Self::DenseIter {
+        ConverterF16 { iter: self.iter() }
+    }
+}
+
 macro_rules! make_misc_point {
     ($base:ident, $iter_name:ident) => {
         /// Helper iterator for converting one type into another. Cleans up a really messy map.
@@ -118,15 +145,20 @@ unsafe impl<T: Send, S: Send> Send for RawSparse<T, S> {}
 unsafe impl<T: Sync, S: Sync> Sync for RawSparse<T, S> {}
 
 impl<T: std::fmt::Debug, S: std::fmt::Debug + TryInto<usize>> RawSparse<T, S> {
-    pub(crate) fn indexes<'a>(&'a self) -> &'a [S] {
+    /// The sorted indexes of the non-zero values. Metrics outside this crate use these with the
+    /// values to implement `Metric<RawSparse<T, S>>`, with kernels like
+    /// [`sq_l2_sparse_f32_f32`](crate::metrics::sq_l2_sparse_f32_f32).
+    pub fn indexes<'a>(&'a self) -> &'a [S] {
         unsafe { std::slice::from_raw_parts::<'a>(self.indexes_ptr, self.len) }
     }
 
-    pub(crate) fn values<'a>(&'a self) -> &'a [T] {
+    /// The non-zero values, in the order of the indexes.
+    pub fn values<'a>(&'a self) -> &'a [T] {
         unsafe { std::slice::from_raw_parts::<'a>(self.values_ptr, self.len) }
     }
 
-    pub(crate) fn dim(&self) -> usize {
+    /// The dimension of the dense vector this represents.
+    pub fn dim(&self) -> usize {
         self.dim
     }
 }
@@ -0,0 +1,236 @@
+//! A view of some of the points of a point cloud, for building a tree on part of the data.
+
+use crate::base_traits::*;
+use crate::partitions::PartitionMap;
+use crate::pc_errors::{PointCloudError, PointCloudResult};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use std::sync::Arc;
+
+/// Restricts a shared point cloud to a subset of its points without copying them. The points keep
+/// the indexes they have in the full cloud, so a tree built on the view reports the same indexes
+/// as one built on the full cloud. Only [`PointCloud::reference_indexes`], [`PointCloud::len`]
+/// and [`PointCloud::names`] are restricted, every point of the full cloud can still be read
+/// through the view, for example to query a tree built on a training split with held-out points.
+#[derive(Debug)]
+pub struct SubsetCloud<D> {
+    cloud: Arc<D>,
+    indexes: Vec<usize>,
+}
+
+impl<D: PointCloud> SubsetCloud<D> {
+    /// Creates a view of the points at `indexes`. Errors if a point can't be read or if there
+    /// are no indexes.
+    pub fn new(cloud: Arc<D>, mut indexes: Vec<usize>) -> PointCloudResult<SubsetCloud<D>> {
+        indexes.sort_unstable();
+        indexes.dedup();
+        if let Some(pi) = indexes.iter().find(|pi| cloud.point(**pi).is_err()) {
+            return Err(PointCloudError::DataAccessError {
+                index: *pi,
+                reason: format!("the point cloud has no point {}", pi),
+            });
+        }
+        if indexes.is_empty() {
+            return Err(PointCloudError::DataAccessError {
+                index: 0,
+                reason: "a subset needs at least one point".to_string(),
+            });
+        }
+        Ok(SubsetCloud { cloud, indexes })
+    }
+
+    /// The indexes of the points in this view, sorted.
+    pub fn indexes(&self) -> &[usize] {
+        &self.indexes
+    }
+
+    /// The full point cloud this is a view of.
+    pub fn cloud(&self) -> &Arc<D> {
+        &self.cloud
+    }
+
+    /// Shuffles the cloud's reference points with the seed and splits them into a view of
+    /// `fraction` of them, rounded, and a view of the rest. The fraction has to leave at least one
+    /// point on each side. See [`PointCloud::split`].
+    pub fn split(
+        cloud: Arc<D>,
+        fraction: f32,
+        seed: u64,
+    ) -> PointCloudResult<(SubsetCloud<D>, SubsetCloud<D>)> {
+        let mut indexes = cloud.reference_indexes();
+        let count = (fraction * indexes.len() as f32).round() as usize;
+        if !(fraction > 0.0 && fraction < 1.0) || count == 0 || count == indexes.len() {
+            return Err(PointCloudError::DataAccessError {
+                index: count,
+                reason: format!(
+                    "a fraction of {} leaves one side of the split of {} points empty",
+                    fraction,
+                    indexes.len()
+                ),
+            });
+        }
+        indexes.shuffle(&mut StdRng::seed_from_u64(seed));
+        let rest = indexes.split_off(count);
+        Ok((
+            SubsetCloud::new(Arc::clone(&cloud), indexes)?,
+            SubsetCloud::new(cloud, rest)?,
+        ))
+    }
+
+    /// A view of `n` of the cloud's reference points, picked at random with the seed. See
+    /// [`PointCloud::subsample`].
+    pub fn subsample(cloud: Arc<D>, n: usize, seed: u64) -> PointCloudResult<SubsetCloud<D>> {
+        let indexes = cloud.reference_indexes();
+        if n > indexes.len() {
+            return Err(PointCloudError::DataAccessError {
+                index: n,
+                reason: format!("can't take {} of {} points", n, indexes.len()),
+            });
+        }
+        let sample = indexes
+            .choose_multiple(&mut StdRng::seed_from_u64(seed), n)
+            .copied()
+            .collect();
+        SubsetCloud::new(cloud, sample)
+    }
+}
+
+impl<D: PointCloud> PointCloud for SubsetCloud<D> {
+    type Metric = D::Metric;
+    type Point = D::Point;
+    type PointRef<'a> = D::PointRef<'a>;
+    type Label = D::Label;
+    type LabelSummary = D::LabelSummary;
+    type Metadata = D::Metadata;
+    type MetaSummary = D::MetaSummary;
+
+    #[inline]
+    fn point<'a, 'b: 'a>(&'b self, pi: usize) -> PointCloudResult<Self::PointRef<'a>> {
+        self.cloud.point(pi)
+    }
+    /// The number of points in the view
+    fn len(&self) -> usize {
+        self.indexes.len()
+    }
+    fn is_empty(&self) -> bool {
+        self.indexes.is_empty()
+    }
+    /// The indexes of the points in the view
+    fn reference_indexes(&self) -> Vec<usize> {
+        self.indexes.clone()
+    }
+    fn dim(&self) -> usize {
+        self.cloud.dim()
+    }
+    fn metric(&self) -> &Self::Metric {
+        self.cloud.metric()
+    }
+    fn is_deleted(&self, pi: usize) -> bool {
+        self.cloud.is_deleted(pi)
+    }
+    fn deleted_indexes(&self) -> Vec<usize> {
+        self.cloud.deleted_indexes()
+    }
+    fn multiplicity(&self, pi: usize) -> usize {
+        self.cloud.multiplicity(pi)
+    }
+    fn representative(&self, pi: usize) -> usize {
+        self.cloud.representative(pi)
+    }
+    fn dim_names(&self) -> Option<&[String]> {
+        self.cloud.dim_names()
+    }
+    fn preprocess_query(&self, point: &Self::Point) -> Option<Box<Self::Point>> {
+        self.cloud.preprocess_query(point)
+    }
+    fn partitions(&self) -> Option<&PartitionMap> {
+        self.cloud.partitions()
+    }
+    fn label(&self, pn: usize) -> PointCloudResult<Option<&Self::Label>> {
+        self.cloud.label(pn)
+    }
+    fn label_summary(&self, pns: &[usize]) -> PointCloudResult<SummaryCounter<Self::LabelSummary>> {
+        self.cloud.label_summary(pns)
+    }
+    fn label_name(&self, label: &Self::Label) -> Option<&str> {
+        self.cloud.label_name(label)
+    }
+    fn name(&self, pi: usize) -> PointCloudResult<String> {
+        self.cloud.name(pi)
+    }
+    fn index(&self, pn: &str) -> PointCloudResult<usize> {
+        self.cloud.index(pn)
+    }
+    /// The names of the points in the view
+    fn names(&self) -> Vec<String> {
+        self.indexes
+            .iter()
+            .filter_map(|pi| self.cloud.name(*pi).ok())
+            .collect()
+    }
+    fn metadata(&self, pn: usize) -> PointCloudResult<Option<&Self::Metadata>> {
+        self.cloud.metadata(pn)
+    }
+    fn metasummary(&self, pns: &[usize]) -> PointCloudResult<SummaryCounter<Self::MetaSummary>> {
+        self.cloud.metasummary(pns)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DefaultLabeledCloud;
+
+    #[test]
+    fn subset_keeps_global_indexes() {
+        let cloud = Arc::new(DefaultLabeledCloud::<crate::L2>::new_simple(
+            vec![0.0, 1.0, 2.0, 3.0],
+            1,
+            vec![0, 1, 0, 1],
+        ));
+        let subset = SubsetCloud::new(Arc::clone(&cloud), vec![3, 1, 3]).unwrap();
+        assert_eq!(subset.len(), 2);
+        assert_eq!(subset.reference_indexes(), vec![1, 3]);
+        assert_eq!(subset.point(3).unwrap(), &[3.0][..]);
+        assert_eq!(subset.label(1).unwrap(), Some(&1));
+        assert!(SubsetCloud::new(Arc::clone(&cloud), vec![4]).is_err());
+        assert!(SubsetCloud::new(cloud, vec![]).is_err());
+    }
+
+    #[test]
+    fn split_and_subsample_share_indexes() {
+        let cloud = Arc::new(DefaultLabeledCloud::<crate::L2>::new_simple(
+            (0..10).map(|i| i as f32).collect(),
+            1,
+            vec![0; 10],
+        ));
+        let (train, test) = Arc::clone(&cloud).split(0.7, 3).unwrap();
+        assert_eq!(train.len(), 7);
+        assert_eq!(test.len(), 3);
+        let mut all: Vec<usize> = train.indexes().to_vec();
+        all.extend(test.indexes());
+        all.sort_unstable();
+        assert_eq!(all, (0..10).collect::<Vec<usize>>());
+        for pi in test.indexes() {
+            assert_eq!(test.point(*pi).unwrap(), &[*pi as f32][..]);
+        }
+
+        let (again, _) = Arc::clone(&cloud).split(0.7, 3).unwrap();
+        assert_eq!(again.indexes(), train.indexes());
+        assert!(Arc::clone(&cloud).split(1.0, 3).is_err());
+        assert!(Arc::clone(&cloud).split(0.01, 3).is_err());
+
+        let sample = Arc::clone(&cloud).subsample(4, 5).unwrap();
+        assert_eq!(sample.len(), 4);
+        assert_eq!(
+            sample.indexes(),
+            Arc::clone(&cloud).subsample(4, 5).unwrap().indexes()
+        );
+        assert!(cloud.subsample(11, 5).is_err());
+
+        // Views of views keep the global indexes
+        let inner = Arc::new(train).subsample(3, 1).unwrap();
+        assert!(inner.indexes().iter().all(|pi| *pi < 10));
+    }
+}
@@ -3,6 +3,7 @@
 use hashbrown::HashMap;
 use std::default::Default;
 use std::iter::Iterator;
+use std::marker::PhantomData;
 
 use smallvec::SmallVec;
 
@@ -73,6 +74,33 @@ pub struct VecSummary {
     pub count: usize,
 }
 
+impl VecSummary {
+    /// The mean of each target, empty if nothing has been added.
+    pub fn mean(&self) -> Vec<f32> {
+        let count = self.count as f32;
+        self.moment1.iter().map(|m| m / count).collect()
+    }
+
+    /// The variance of each target, empty if nothing has been added.
+    pub fn var(&self) -> Vec<f32> {
+        let count = self.count as f32;
+        self.moment1
+            .iter()
+            .zip(&self.moment2)
+            .map(|(m1, m2)| {
+                let mean = m1 / count;
+                // Rounding can push this a little below 0
+                (m2 / count - mean * mean).max(0.0)
+            })
+            .collect()
+    }
+
+    /// The dimension of the summarized vectors, 0 if nothing has been added.
+    pub fn dim(&self) -> usize {
+        self.moment1.len()
+    }
+}
+
 impl Summary for VecSummary {
     type Label = [f32];
 
@@ -94,10 +122,17 @@ impl Summary for VecSummary {
             }
         } else {
             self.moment1.extend(val);
-            self.moment2.extend(val.iter().map(|x| x * x))
+            self.moment2.extend(val.iter().map(|x| x * x));
+            self.count += 1;
         }
     }
     fn combine(&mut self, other: &VecSummary) {
+        if self.moment1.is_empty() {
+            self.moment1 = other.moment1.clone();
+            self.moment2 = other.moment2.clone();
+            self.count = other.count;
+            return;
+        }
         self.moment1
             .iter_mut()
             .zip(&other.moment1)
@@ -205,3 +240,49 @@ impl Summary for StringSummary {
         self.items.values().sum()
     }
 }
+
+/// A summary that only counts, for labels or metadata that don't summarize, like document ids.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CountSummary<T: ?Sized> {
+    /// The number of values added
+    pub count: usize,
+    #[serde(skip)]
+    marker: PhantomData<T>,
+}
+
+impl<T: ?Sized> CountSummary<T> {
+    /// A summary of `count` values.
+    pub fn new(count: usize) -> Self {
+        CountSummary {
+            count,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T: ?Sized> Default for CountSummary<T> {
+    fn default() -> Self {
+        CountSummary::new(0)
+    }
+}
+
+impl<T: ?Sized> Clone for CountSummary<T> {
+    fn clone(&self) -> Self {
+        CountSummary::new(self.count)
+    }
+}
+
+impl<T: ?Sized + Debug + Send + Sync + 'static> Summary for CountSummary<T> {
+    type Label = T;
+    fn add(&mut self, _val: &T) {
+        self.count += 1;
+    }
+
+    fn combine(&mut self, other: &CountSummary<T>) {
+        self.count += other.count;
+    }
+
+    fn count(&self) -> usize {
+        self.count
+    }
+}
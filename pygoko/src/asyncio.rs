@@ -0,0 +1,188 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! Awaitable versions of the slow calls. The work runs on the rayon pool without the GIL, and the
+//! result is handed back to the event loop that made the call with `call_soon_threadsafe`, so an
+//! asyncio service doesn't need an executor to query a tree. pyo3-asyncio needs a newer pyo3 than
+//! this crate is on, so this is the small part of it we need.
+
+use goko::GokoResult;
+use pyo3::prelude::*;
+use std::collections::BTreeMap;
+
+type Finish = Box<dyn FnOnce(Python<'_>) -> PyResult<PyObject> + Send>;
+
+/// Resolves a future on its event loop's thread, it's what's scheduled with
+/// `call_soon_threadsafe`.
+#[pyclass]
+struct Resolver {
+    future: PyObject,
+    finish: Option<Finish>,
+}
+
+#[pymethods]
+impl Resolver {
+    #[call]
+    fn __call__(&mut self, py: Python<'_>) -> PyResult<()> {
+        let future = self.future.as_ref(py);
+        // The caller may have cancelled the future while the work ran.
+        if future.call_method0("done")?.is_true()? {
+            return Ok(());
+        }
+        if let Some(finish) = self.finish.take() {
+            match finish(py) {
+                Ok(value) => future.call_method1("set_result", (value,))?,
+                Err(e) => future.call_method1("set_exception", (e.into_py(py),))?,
+            };
+        }
+        Ok(())
+    }
+}
+
+/// Runs `work` on the rayon pool and returns a future on the running event loop. Once the work
+/// is done `finish` turns its result into the future's value, on the loop's thread. Goko errors
+/// are raised from the future as `ValueError`s. Errors if there's no running loop.
+pub fn spawn_future<T, W, F>(py: Python<'_>, work: W, finish: F) -> PyResult<PyObject>
+where
+    T: Send + 'static,
+    W: FnOnce() -> GokoResult<T> + Send + 'static,
+    F: FnOnce(Python<'_>, T) -> PyResult<PyObject> + Send + 'static,
+{
+    let event_loop: PyObject = py.import("asyncio")?.call0("get_running_loop")?.into();
+    let future: PyObject = event_loop.call_method0(py, "create_future")?;
+    let awaitable = future.clone_ref(py);
+    rayon::spawn(move || {
+        let result = work();
+        let gil = Python::acquire_gil();
+        let py = gil.python();
+        let resolver = Resolver {
+            future,
+            finish: Some(Box::new(move |py| match result {
+                Ok(value) => finish(py, value),
+                Err(e) => Err(pyo3::exceptions::PyValueError::new_err(e.to_string())),
+            })),
+        };
+        // This only fails if the loop was closed while we worked, there's no one to tell.
+        let scheduled = Py::new(py, resolver)
+            .and_then(|resolver| event_loop.call_method1(py, "call_soon_threadsafe", (resolver,)));
+        if let Err(e) = scheduled {
+            e.print(py);
+        }
+    });
+    Ok(awaitable)
+}
+
+/// Hands back the results of work in the order it was submitted, whatever order it finishes in.
+/// Each submission takes a ticket, and a result is only released once every ticket before it is.
+pub struct InOrder<T> {
+    next_ticket: u64,
+    next_out: u64,
+    finished: BTreeMap<u64, Option<T>>,
+}
+
+impl<T> Default for InOrder<T> {
+    fn default() -> Self {
+        InOrder {
+            next_ticket: 0,
+            next_out: 0,
+            finished: BTreeMap::new(),
+        }
+    }
+}
+
+impl<T> InOrder<T> {
+    /// The ticket for the next piece of work.
+    pub fn ticket(&mut self) -> u64 {
+        let ticket = self.next_ticket;
+        self.next_ticket += 1;
+        ticket
+    }
+
+    /// Records the result for a ticket, `None` if the work failed and there's nothing to hand back.
+    pub fn finish(&mut self, ticket: u64, result: Option<T>) {
+        self.finished.insert(ticket, result);
+    }
+
+    /// The results that no earlier ticket is waiting on, in ticket order.
+    pub fn ready(&mut self) -> Vec<T> {
+        let mut ready = Vec::new();
+        while let Some(result) = self.finished.remove(&self.next_out) {
+            ready.extend(result);
+            self.next_out += 1;
+        }
+        ready
+    }
+
+    /// Hands the ready results to `add` on whatever `target` gives. If `target` fails the results
+    /// are put back in front of the queue, so they go out first the next time any are ready.
+    pub fn drain_into<G, E>(
+        &mut self,
+        target: impl FnOnce() -> Result<G, E>,
+        mut add: impl FnMut(&mut G, T),
+    ) -> Result<(), E> {
+        let ready = self.ready();
+        match target() {
+            Ok(mut target) => {
+                for result in ready {
+                    add(&mut target, result);
+                }
+                Ok(())
+            }
+            Err(e) => {
+                // The tickets of the released results are done with, so they're free to reuse
+                for result in ready.into_iter().rev() {
+                    self.next_out -= 1;
+                    self.finished.insert(self.next_out, Some(result));
+                }
+                Err(e)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn drain(pending: &mut InOrder<i32>, added: Option<&mut Vec<i32>>) -> Result<(), ()> {
+        pending.drain_into(|| added.ok_or(()), |added, result| added.push(result))
+    }
+
+    #[test]
+    fn failed_drains_keep_their_results() {
+        let mut pending = InOrder::default();
+        let tickets: Vec<u64> = (0..5).map(|_| pending.ticket()).collect();
+        let mut added = Vec::new();
+
+        pending.finish(tickets[1], Some(1));
+        pending.finish(tickets[0], Some(0));
+        pending.finish(tickets[2], None);
+        // The tracker is busy, nothing is added and nothing is lost
+        assert!(drain(&mut pending, None).is_err());
+        pending.finish(tickets[4], Some(4));
+        assert!(drain(&mut pending, Some(&mut added)).is_ok());
+        assert_eq!(added, vec![0, 1]);
+
+        pending.finish(tickets[3], Some(3));
+        assert!(drain(&mut pending, None).is_err());
+        assert!(drain(&mut pending, Some(&mut added)).is_ok());
+        assert_eq!(added, vec![0, 1, 3, 4]);
+        assert_eq!(pending.ticket(), 5);
+    }
+}
@@ -1,20 +1,22 @@
 use pyo3::prelude::*;
 
-use ndarray::{Array, Array2};
-use numpy::{IntoPyArray, PyArray1, PyArray2};
+use ndarray::{Array, Array2, Array3};
+use numpy::{IntoPyArray, PyArray1, PyArray2, PyArray3};
 use pyo3::PyIterProtocol;
 
 use goko::layer::*;
+use goko::plugins::discrete::prelude::*;
 use goko::*;
 use pointcloud::*;
 use std::sync::Arc;
 
 use crate::node::*;
+use crate::PyPointCloud;
 
 #[pyclass(unsendable)]
 pub struct IterLayers {
-    pub parameters: Arc<CoverTreeParameters<DefaultLabeledCloud<L2>>>,
-    pub tree: CoverTreeReader<DefaultLabeledCloud<L2>>,
+    pub parameters: Arc<CoverTreeParameters<PyPointCloud>>,
+    pub tree: CoverTreeReader<PyPointCloud>,
     pub scale_indexes: Vec<i32>,
     pub index: usize,
 }
@@ -47,13 +49,13 @@ impl PyIterProtocol for IterLayers {
 
 #[pyclass(unsendable)]
 pub struct PyLayer {
-    pub parameters: Arc<CoverTreeParameters<DefaultLabeledCloud<L2>>>,
-    pub tree: CoverTreeReader<DefaultLabeledCloud<L2>>,
+    pub parameters: Arc<CoverTreeParameters<PyPointCloud>>,
+    pub tree: CoverTreeReader<PyPointCloud>,
     pub scale_index: i32,
 }
 
 impl PyLayer {
-    fn layer(&self) -> &CoverLayerReader<DefaultLabeledCloud<L2>> {
+    fn layer(&self) -> &CoverLayerReader<PyPointCloud> {
         self.tree.layer(self.scale_index)
     }
 }
@@ -153,7 +155,7 @@ impl PyLayer {
         Ok(self.layer().get_node_and(point_index, |node| {
             let singletons = node.singletons();
             let mut centers: Vec<f32> = Vec::with_capacity(singletons.len() * dim);
-            for pi in singletons {
+            for pi in singletons.iter() {
                 centers.extend(self.parameters.point_cloud.point(*pi).unwrap().dense_iter());
             }
             let py_centers = Array2::from_shape_vec((singletons.len(), dim), centers).unwrap();
@@ -163,6 +165,52 @@ impl PyLayer {
         }))
     }
 
+    /// Exports the dirichlet posterior parameters of every node on this layer in one go.
+    ///
+    /// Returns `(addresses, child_addresses, alphas)`. `addresses` is `(n, 2)` with the scale
+    /// and center index of each node. `child_addresses` is `(n, c, 2)` where `c` is the largest
+    /// number of children on this layer, padded with `-1`. `alphas` is `(n, c + 1)`, column 0
+    /// is the singleton parameter and column `j + 1` is the parameter of `child_addresses[:, j]`,
+    /// padded with `0`. Nodes without a dirichlet are left out.
+    pub fn dirichlet_params(
+        &self,
+    ) -> PyResult<(Py<PyArray2<i64>>, Py<PyArray3<i64>>, Py<PyArray2<f64>>)> {
+        let mut params: Vec<(usize, Vec<(NodeAddress, f64)>, f64)> =
+            Vec::with_capacity(self.layer().len());
+        self.layer().for_each_node(|pi, n| {
+            if let Some((children, singletons)) = n.get_plugin_and(|p: &Dirichlet| {
+                let (children, singletons) = p.params();
+                (Vec::from(children), singletons)
+            }) {
+                params.push((*pi, children, singletons));
+            }
+        });
+        params.sort_by_key(|(pi, _, _)| *pi);
+
+        let max_children = params.iter().map(|(_, c, _)| c.len()).max().unwrap_or(0);
+        let mut addresses = Array2::<i64>::zeros((params.len(), 2));
+        let mut child_addresses = Array3::<i64>::from_elem((params.len(), max_children, 2), -1);
+        let mut alphas = Array2::<f64>::zeros((params.len(), max_children + 1));
+        for (i, (pi, children, singletons)) in params.iter().enumerate() {
+            addresses[[i, 0]] = self.scale_index as i64;
+            addresses[[i, 1]] = *pi as i64;
+            alphas[[i, 0]] = *singletons;
+            for (j, ((si, ci), alpha)) in children.iter().enumerate() {
+                child_addresses[[i, j, 0]] = *si as i64;
+                child_addresses[[i, j, 1]] = *ci as i64;
+                alphas[[i, j + 1]] = *alpha;
+            }
+        }
+
+        let gil = pyo3::Python::acquire_gil();
+        let py = gil.python();
+        Ok((
+            addresses.into_pyarray(py).to_owned(),
+            child_addresses.into_pyarray(py).to_owned(),
+            alphas.into_pyarray(py).to_owned(),
+        ))
+    }
+
     pub fn node(&self, center_index: usize) -> PyResult<PyNode> {
         Ok(PyNode {
             parameters: Arc::clone(&self.parameters),
@@ -17,20 +17,40 @@
 * under the License.
 */
 
+use pointcloud::data_sources::DataRam;
+use pointcloud::label_sources::CategoricalLabels;
+use pointcloud::meta_sources::MetadataMap;
+use pointcloud::partitions::SimplePartitionedCloud;
+use pointcloud::{SimpleLabeledCloud, SimpleMetaCloud};
 use pyo3::prelude::*;
 
+/// The point cloud python trees are built on, the metric is picked by name at runtime. The labels
+/// are categories, named when they're given as strings. Points can carry a string of metadata,
+/// like a document id or url, that's handed back with query results. Points can also be tagged
+/// with a partition key, like a tenant id, to scope queries to some partitions.
+pub type PyPointCloud = SimpleLabeledCloud<
+    SimplePartitionedCloud<SimpleMetaCloud<DataRam<metric::PyMetric>, MetadataMap<String>>>,
+    CategoricalLabels,
+>;
+
+pub mod asyncio;
 pub mod layer;
+pub mod metric;
 pub mod node;
 pub mod plugins;
+pub mod sparse_tree;
 pub mod tree;
 
 use plugins::*;
+use sparse_tree::SparseCoverTree;
 use tree::CoverTree;
 
 #[pymodule]
 fn pygoko(_py: Python<'_>, m: &PyModule) -> PyResult<()> {
     m.add_class::<CoverTree>()?;
+    m.add_class::<SparseCoverTree>()?;
     m.add_class::<PyBayesCategoricalTracker>()?;
+    m.add_class::<PyTwoWindowTracker>()?;
     m.add_class::<PyKLDivergenceBaseline>()?;
     Ok(())
 }
@@ -0,0 +1,213 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+use pointcloud::metrics::*;
+use pointcloud::points::RawSparse;
+use pointcloud::Metric;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+
+/// The names `set_metric` accepts, matched case insensitively.
+pub const METRIC_NAMES: &[&str] = &[
+    "l1",
+    "l2",
+    "cosine",
+    "bray_curtis",
+    "correlation",
+    "jensen_shannon",
+    "wasserstein",
+    "nan_euclidean",
+];
+
+/// Every metric python can choose by name. The point cloud is built with one of these, so the
+/// choice carries through to the build and every query on the tree.
+#[derive(Debug, Clone)]
+pub enum PyMetric {
+    L1(L1),
+    L2(L2),
+    PreciseL1(PreciseL1),
+    PreciseL2(PreciseL2),
+    WeightedL2(WeightedL2),
+    Cosine(Cosine),
+    BrayCurtis(BrayCurtis),
+    Correlation(Correlation),
+    JensenShannon(JensenShannon),
+    Wasserstein(Wasserstein),
+    NanEuclidean(NanEuclidean),
+}
+
+impl Default for PyMetric {
+    fn default() -> PyMetric {
+        PyMetric::L2(L2 {})
+    }
+}
+
+impl PyMetric {
+    /// Looks up the metric by name. L2 uses the weights if there are any, the other metrics don't
+    /// support weights. L1 and unweighted L2 sum with `accumulation`, the other metrics only
+    /// support the default `f32` accumulation.
+    pub fn from_name(
+        name: &str,
+        weights: &WeightedL2,
+        accumulation: Accumulation,
+    ) -> PyResult<PyMetric> {
+        let metric = match name.to_lowercase().as_str() {
+            "l1" if accumulation == Accumulation::F32 => PyMetric::L1(L1 {}),
+            "l1" => PyMetric::PreciseL1(PreciseL1::new(accumulation)),
+            "l2" if !weights.weights().is_empty() => PyMetric::WeightedL2(weights.clone()),
+            "l2" if accumulation == Accumulation::F32 => PyMetric::L2(L2 {}),
+            "l2" => PyMetric::PreciseL2(PreciseL2::new(accumulation)),
+            "cosine" => PyMetric::Cosine(Cosine {}),
+            "bray_curtis" => PyMetric::BrayCurtis(BrayCurtis {}),
+            "correlation" => PyMetric::Correlation(Correlation {}),
+            "jensen_shannon" => PyMetric::JensenShannon(JensenShannon {}),
+            "wasserstein" => PyMetric::Wasserstein(Wasserstein {}),
+            "nan_euclidean" => PyMetric::NanEuclidean(NanEuclidean {}),
+            _ => {
+                return Err(PyValueError::new_err(format!(
+                    "unknown metric {:?}, expected one of {:?}",
+                    name, METRIC_NAMES
+                )))
+            }
+        };
+        if !weights.weights().is_empty() && !matches!(metric, PyMetric::WeightedL2(_)) {
+            return Err(PyValueError::new_err(format!(
+                "metric weights are only supported by l2, not {:?}",
+                name
+            )));
+        }
+        if accumulation != Accumulation::F32
+            && !matches!(metric, PyMetric::PreciseL1(_) | PyMetric::PreciseL2(_))
+        {
+            return Err(PyValueError::new_err(format!(
+                "{:?} accumulation is only supported by unweighted l1 and l2, not {:?}",
+                accumulation, name
+            )));
+        }
+        Ok(metric)
+    }
+
+    /// Checks that the metric's parameters fit data of this dimension.
+    pub fn check_dim(&self, dim: usize) -> PyResult<()> {
+        if let PyMetric::WeightedL2(metric) = self {
+            if metric.weights().len() != dim {
+                return Err(PyValueError::new_err(format!(
+                    "there are {} metric weights but the data has dimension {}",
+                    metric.weights().len(),
+                    dim
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl PyMetric {
+    /// If the metric can measure distances over some of the dimensions, see `knn_masked`.
+    pub fn supports_masks(&self) -> bool {
+        matches!(
+            self,
+            PyMetric::L1(_)
+                | PyMetric::L2(_)
+                | PyMetric::PreciseL1(_)
+                | PyMetric::PreciseL2(_)
+                | PyMetric::WeightedL2(_)
+        )
+    }
+}
+
+impl MaskedMetric for PyMetric {
+    fn masked_dist(&self, x: &[f32], y: &[f32], mask: &DimMask) -> f32 {
+        match self {
+            PyMetric::L1(m) => m.masked_dist(x, y, mask),
+            PyMetric::L2(m) => m.masked_dist(x, y, mask),
+            PyMetric::PreciseL1(m) => m.masked_dist(x, y, mask),
+            PyMetric::PreciseL2(m) => m.masked_dist(x, y, mask),
+            PyMetric::WeightedL2(m) => m.masked_dist(x, y, mask),
+            _ => unreachable!("check supports_masks before a masked query"),
+        }
+    }
+    fn masked_norm(&self, deviations: &[f32], mask: &DimMask) -> f32 {
+        match self {
+            PyMetric::L1(m) => m.masked_norm(deviations, mask),
+            PyMetric::L2(m) => m.masked_norm(deviations, mask),
+            PyMetric::PreciseL1(m) => m.masked_norm(deviations, mask),
+            PyMetric::PreciseL2(m) => m.masked_norm(deviations, mask),
+            PyMetric::WeightedL2(m) => m.masked_norm(deviations, mask),
+            _ => unreachable!("check supports_masks before a masked query"),
+        }
+    }
+}
+
+impl Metric<[f32]> for PyMetric {
+    #[inline]
+    fn dist(&self, x: &[f32], y: &[f32]) -> f32 {
+        match self {
+            PyMetric::L1(m) => m.dist(x, y),
+            PyMetric::L2(m) => m.dist(x, y),
+            PyMetric::PreciseL1(m) => m.dist(x, y),
+            PyMetric::PreciseL2(m) => m.dist(x, y),
+            PyMetric::WeightedL2(m) => m.dist(x, y),
+            PyMetric::Cosine(m) => m.dist(x, y),
+            PyMetric::BrayCurtis(m) => m.dist(x, y),
+            PyMetric::Correlation(m) => m.dist(x, y),
+            PyMetric::JensenShannon(m) => m.dist(x, y),
+            PyMetric::Wasserstein(m) => m.dist(x, y),
+            PyMetric::NanEuclidean(m) => m.dist(x, y),
+        }
+    }
+}
+
+/// The metrics a sparse tree can use, only these have kernels that work on the sparse entries
+/// directly.
+#[derive(Debug, Clone)]
+pub enum PySparseMetric {
+    L1(L1),
+    L2(L2),
+}
+
+impl Default for PySparseMetric {
+    fn default() -> PySparseMetric {
+        PySparseMetric::L2(L2 {})
+    }
+}
+
+impl PySparseMetric {
+    /// Looks up the metric by name, either `l1` or `l2`.
+    pub fn from_name(name: &str) -> PyResult<PySparseMetric> {
+        match name.to_lowercase().as_str() {
+            "l1" => Ok(PySparseMetric::L1(L1 {})),
+            "l2" => Ok(PySparseMetric::L2(L2 {})),
+            _ => Err(PyValueError::new_err(format!(
+                "unknown sparse metric {:?}, expected one of [\"l1\", \"l2\"]",
+                name
+            ))),
+        }
+    }
+}
+
+impl Metric<RawSparse<f32, u32>> for PySparseMetric {
+    #[inline]
+    fn dist(&self, x: &RawSparse<f32, u32>, y: &RawSparse<f32, u32>) -> f32 {
+        match self {
+            PySparseMetric::L1(m) => m.dist(x, y),
+            PySparseMetric::L2(m) => m.dist(x, y),
+        }
+    }
+}
@@ -4,9 +4,11 @@ use ndarray::{Array1, Array2};
 use numpy::{IntoPyArray, PyArray1, PyArray2};
 use pyo3::PyIterProtocol;
 
+use crate::PyPointCloud;
 use goko::plugins::discrete::prelude::*;
 use goko::plugins::gaussians::*;
 use goko::*;
+use pointcloud::summaries::CategorySummary;
 use pointcloud::*;
 use std::sync::Arc;
 
@@ -14,9 +16,9 @@ use pyo3::types::PyDict;
 
 #[pyclass(unsendable)]
 pub struct IterLayerNode {
-    pub parameters: Arc<CoverTreeParameters<DefaultLabeledCloud<L2>>>,
+    pub parameters: Arc<CoverTreeParameters<PyPointCloud>>,
     pub addresses: Vec<NodeAddress>,
-    pub tree: CoverTreeReader<DefaultLabeledCloud<L2>>,
+    pub tree: CoverTreeReader<PyPointCloud>,
     pub index: usize,
 }
 
@@ -49,9 +51,9 @@ impl PyIterProtocol for IterLayerNode {
 
 #[pyclass(unsendable)]
 pub struct PyNode {
-    pub parameters: Arc<CoverTreeParameters<DefaultLabeledCloud<L2>>>,
+    pub parameters: Arc<CoverTreeParameters<PyPointCloud>>,
     pub address: NodeAddress,
-    pub tree: CoverTreeReader<DefaultLabeledCloud<L2>>,
+    pub tree: CoverTreeReader<PyPointCloud>,
 }
 
 #[pymethods]
@@ -72,6 +74,26 @@ impl PyNode {
             .unwrap()
     }
 
+    /// The node's statistics as a dict with the `address`, `cover_radius`, measured `covering_radius`, stored
+    /// `radius`, `coverage_count`, `singleton_count` and `child_count`.
+    pub fn stats(&self) -> PyResult<PyObject> {
+        let stats = self
+            .tree
+            .node_stats(self.address)
+            .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))?;
+        let gil = pyo3::Python::acquire_gil();
+        let py = gil.python();
+        let dict = PyDict::new(py);
+        dict.set_item("address", stats.address)?;
+        dict.set_item("cover_radius", stats.cover_radius)?;
+        dict.set_item("covering_radius", stats.covering_radius)?;
+        dict.set_item("radius", stats.radius)?;
+        dict.set_item("coverage_count", stats.coverage_count)?;
+        dict.set_item("singleton_count", stats.singleton_count)?;
+        dict.set_item("child_count", stats.child_count)?;
+        Ok(dict.into())
+    }
+
     pub fn children(&self) -> Vec<PyNode> {
         self.children_addresses()
             .iter()
@@ -140,6 +162,21 @@ impl PyNode {
             .unwrap_or(vec![])
     }
 
+    /// The metadata of the points `singletons_indexes` lists, `None` for points without any.
+    pub fn singletons_metadata(&self) -> Vec<Option<String>> {
+        self.singletons_indexes()
+            .iter()
+            .map(|pi| {
+                self.parameters
+                    .point_cloud
+                    .metadata(*pi)
+                    .ok()
+                    .flatten()
+                    .cloned()
+            })
+            .collect()
+    }
+
     pub fn cover_mean(&self) -> PyResult<Option<Py<PyArray1<f32>>>> {
         let dim = self.parameters.point_cloud.dim();
         let gil = pyo3::Python::acquire_gil();
@@ -169,6 +206,28 @@ impl PyNode {
         Ok(py_mean.into_pyarray(py).to_owned())
     }
 
+    /// The mean and variance of each dimension of the node's gaussian, keyed by the dimension's
+    /// name, or its index if the dimensions aren't named.
+    pub fn named_gaussian(&self) -> PyResult<Option<PyObject>> {
+        let moments = self
+            .tree
+            .get_node_plugin_and::<DiagGaussian, _, _>(self.address, |p| {
+                p.named_moments(self.parameters.point_cloud.as_ref())
+            });
+        let gil = pyo3::Python::acquire_gil();
+        let py = gil.python();
+        match moments {
+            Some(moments) => {
+                let dict = PyDict::new(py);
+                for (name, mean, var) in moments {
+                    dict.set_item(name, (mean, var))?;
+                }
+                Ok(Some(dict.into()))
+            }
+            None => Ok(None),
+        }
+    }
+
     /*
     pub fn get_singular_values(&self) -> PyResult<Option<Py<PyArray1<f32>>>> {
         let gil = pyo3::Python::acquire_gil();
@@ -181,6 +240,27 @@ impl PyNode {
     }
     */
 
+    /// The normality diagnostics of the points the node's gaussian is fit to, `None` if the
+    /// node doesn't have them.
+    pub fn gaussian_fit(&self) -> PyResult<Option<PyObject>> {
+        let gil = pyo3::Python::acquire_gil();
+        let py = gil.python();
+        let dict = PyDict::new(py);
+        match self
+            .tree
+            .get_node_plugin_and::<GaussianFit, _, _>(self.address, |p| p.clone())
+        {
+            Some(fit) => {
+                dict.set_item("count", fit.count)?;
+                dict.set_item("skewness", fit.skewness.into_pyarray(py))?;
+                dict.set_item("excess_kurtosis", fit.excess_kurtosis.into_pyarray(py))?;
+                dict.set_item("outside_3_sigma", fit.outside_3_sigma.into_pyarray(py))?;
+                Ok(Some(dict.into()))
+            }
+            None => Ok(None),
+        }
+    }
+
     pub fn label_summary(&self) -> PyResult<Option<PyObject>> {
         let gil = pyo3::Python::acquire_gil();
         let py = gil.python();
@@ -190,9 +270,26 @@ impl PyNode {
                 dict.set_item("errors", s.errors)?;
                 dict.set_item("nones", s.nones)?;
                 dict.set_item("items", s.summary.items.to_vec())?;
+                dict.set_item(
+                    "names",
+                    label_names(&self.parameters.point_cloud, &s.summary),
+                )?;
                 Ok(Some(dict.into()))
             }
             None => Ok(None),
         }
     }
 }
+
+/// The name of each category in a label summary, in the order of its items. `None` for the
+/// categories without a name.
+pub(crate) fn label_names(
+    point_cloud: &PyPointCloud,
+    summary: &CategorySummary,
+) -> Vec<Option<String>> {
+    summary
+        .items
+        .iter()
+        .map(|(label, _)| point_cloud.label_name(label).map(|n| n.to_string()))
+        .collect()
+}
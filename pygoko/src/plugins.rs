@@ -1,9 +1,62 @@
+use crate::asyncio::{spawn_future, InOrder};
+use crate::PyPointCloud;
 use goko::plugins::discrete::prelude::*;
 use goko::*;
-use numpy::PyArray1;
-use pointcloud::*;
+use numpy::{PyArray1, PyArray2};
 use pyo3::prelude::*;
 use pyo3::types::PyDict;
+use std::sync::{Arc, Mutex};
+
+/// The paths `push_async` found, with their ids, waiting to be added to a tracker in the order they were pushed.
+pub type PendingPaths = Arc<Mutex<InOrder<(Vec<(f32, NodeAddress)>, Option<u64>)>>>;
+
+/// Finds a point's path on the rayon pool and files it under its ticket in `pending`. Once it's back on the event
+/// loop every path that's next in line is handed to `add`, so the tracker sees the points in the order they were
+/// pushed even when their paths are found out of order. If the tracker is borrowed when a push comes back that push
+/// raises, and the paths that were next in line go back into `pending` to be added by the next push that comes back.
+fn push_in_order<T, A>(
+    py: Python<'_>,
+    tracker: Py<T>,
+    reader: CoverTreeReader<PyPointCloud>,
+    pending: PendingPaths,
+    point: Vec<f32>,
+    id: Option<u64>,
+    add: A,
+) -> PyResult<PyObject>
+where
+    T: pyo3::PyClass,
+    A: Fn(&mut T, Vec<(f32, NodeAddress)>, Option<u64>) + Send + 'static,
+{
+    let ticket = pending.lock().unwrap().ticket();
+    let found = Arc::clone(&pending);
+    spawn_future(
+        py,
+        move || {
+            let path = reader.path(&point.as_slice());
+            let mut found = found.lock().unwrap();
+            match path {
+                Ok(path) => {
+                    found.finish(ticket, Some((path, id)));
+                    Ok(Ok(()))
+                }
+                Err(e) => {
+                    found.finish(ticket, None);
+                    Ok(Err(e))
+                }
+            }
+        },
+        move |py, result: GokoResult<()>| {
+            let cell: &PyCell<T> = tracker.as_ref(py);
+            pending.lock().unwrap().drain_into(
+                || cell.try_borrow_mut(),
+                |tracker, (path, id)| add(&mut *tracker, path, id),
+            )?;
+            result
+                .map(|_| py.None())
+                .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))
+        },
+    )
+}
 
 /*
 pub #[derive(Debug)]
@@ -24,18 +77,65 @@ impl PyBucketProbs {
 
 #[pyclass(unsendable)]
 pub struct PyBayesCategoricalTracker {
-    pub hkl: BayesCategoricalTracker<DefaultLabeledCloud<L2>>,
-    pub tree: CoverTreeReader<DefaultLabeledCloud<L2>>,
+    pub hkl: BayesCategoricalTracker<PyPointCloud>,
+    pub tree: CoverTreeReader<PyPointCloud>,
+    pub pending: PendingPaths,
 }
 
 #[pymethods]
 impl PyBayesCategoricalTracker {
-    pub fn push(&mut self, point: &PyArray1<f32>) {
+    pub fn push(&mut self, point: &PyArray1<f32>, id: Option<u64>) {
         let results = self
             .tree
             .path(&point.readonly().as_slice().unwrap())
             .unwrap();
-        self.hkl.add_path(results);
+        self.hkl.add_path_with_id(results, id);
+    }
+
+    /// `push` as an awaitable, `await tracker.push_async(point, id)`. The point's path is found on
+    /// the rayon pool and added to the tracker back on the event loop. Pushes that are awaited
+    /// together are added in the order they were made, as with `push`. A cancelled push is still
+    /// added, along with the next one to finish.
+    pub fn push_async(
+        slf: PyRef<'_, Self>,
+        point: &PyArray1<f32>,
+        id: Option<u64>,
+    ) -> PyResult<PyObject> {
+        let py = slf.py();
+        let reader = slf.tree.clone();
+        let pending = Arc::clone(&slf.pending);
+        let point = point.readonly().as_slice()?.to_vec();
+        let tracker: Py<Self> = slf.into();
+        push_in_order(
+            py,
+            tracker,
+            reader,
+            pending,
+            point,
+            id,
+            |t: &mut Self, path, id| t.hkl.add_path_with_id(path, id),
+        )
+    }
+
+    /// Pushes a batch of points, one per row, and returns the stats after each one. The optional
+    /// ids are carried through to each point's stats as `last_event_id`.
+    pub fn push_batch(
+        &mut self,
+        points: &PyArray2<f32>,
+        ids: Option<Vec<u64>>,
+    ) -> PyResult<Vec<PyObject>> {
+        let points = points.readonly();
+        let paths = points
+            .as_array()
+            .outer_iter()
+            .map(|point| self.tree.path(point.as_slice().unwrap()))
+            .collect::<GokoResult<Vec<_>>>()
+            .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))?;
+        let stats = self
+            .hkl
+            .add_paths(paths, ids.as_deref())
+            .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))?;
+        stats.iter().map(stats_dict).collect()
     }
 
     pub fn print(&self) {
@@ -50,6 +150,22 @@ impl PyBayesCategoricalTracker {
         self.hkl.evidence_prob_vector(node_address)
     }
 
+    pub fn prior(&self, node_address: (i32, usize)) -> Option<(Vec<((i32, usize), f64)>, f64)> {
+        self.hkl
+            .prior(node_address)
+            .map(|p| (p.params().0.to_vec(), p.params().1))
+    }
+
+    pub fn set_prior_counts(
+        &mut self,
+        node_address: (i32, usize),
+        counts: Vec<f64>,
+    ) -> PyResult<()> {
+        self.hkl
+            .set_prior_counts(node_address, counts)
+            .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))
+    }
+
     pub fn all_kl(&self) -> Vec<(f64, (i32, usize))> {
         self.hkl.all_node_kl()
     }
@@ -59,17 +175,91 @@ impl PyBayesCategoricalTracker {
     }
 
     pub fn stats(&self) -> PyResult<PyObject> {
-        let stats = self.hkl.kl_div_stats();
-        let gil = pyo3::Python::acquire_gil();
-        let py = gil.python();
-        let dict = PyDict::new(py);
-        dict.set_item("max", stats.max)?;
-        dict.set_item("min", stats.min)?;
-        dict.set_item("nz_count", stats.nz_count)?;
-        dict.set_item("moment1_nz", stats.moment1_nz)?;
-        dict.set_item("moment2_nz", stats.moment2_nz)?;
-        dict.set_item("sequence_len", stats.sequence_len)?;
-        Ok(dict.into())
+        stats_dict(&self.hkl.kl_div_stats())
+    }
+}
+
+fn stats_dict(stats: &KLDivergenceStats) -> PyResult<PyObject> {
+    let gil = pyo3::Python::acquire_gil();
+    let py = gil.python();
+    let dict = PyDict::new(py);
+    dict.set_item("max", stats.max)?;
+    dict.set_item("min", stats.min)?;
+    dict.set_item("nz_count", stats.nz_count)?;
+    dict.set_item("moment1_nz", stats.moment1_nz)?;
+    dict.set_item("moment2_nz", stats.moment2_nz)?;
+    dict.set_item("sequence_len", stats.sequence_len)?;
+    dict.set_item("first_event_id", stats.first_event_id)?;
+    dict.set_item("last_event_id", stats.last_event_id)?;
+    let per_scale = stats
+        .per_scale
+        .iter()
+        .map(|scale_stats| {
+            let scale_dict = PyDict::new(py);
+            scale_dict.set_item("scale_index", scale_stats.scale_index)?;
+            scale_dict.set_item("max", scale_stats.max)?;
+            scale_dict.set_item("nz_count", scale_stats.nz_count)?;
+            scale_dict.set_item("moment1_nz", scale_stats.moment1_nz)?;
+            scale_dict.set_item("moment2_nz", scale_stats.moment2_nz)?;
+            Ok(scale_dict.into())
+        })
+        .collect::<PyResult<Vec<PyObject>>>()?;
+    dict.set_item("per_scale", per_scale)?;
+    Ok(dict.into())
+}
+
+#[pyclass(unsendable)]
+pub struct PyTwoWindowTracker {
+    pub hkl: TwoWindowTracker<PyPointCloud>,
+    pub tree: CoverTreeReader<PyPointCloud>,
+    pub pending: PendingPaths,
+}
+
+#[pymethods]
+impl PyTwoWindowTracker {
+    pub fn push(&mut self, point: &PyArray1<f32>, id: Option<u64>) {
+        let results = self
+            .tree
+            .path(&point.readonly().as_slice().unwrap())
+            .unwrap();
+        self.hkl.add_path_with_id(results, id);
+    }
+
+    /// `push` as an awaitable, `await tracker.push_async(point, id)`. The point's path is found on
+    /// the rayon pool and added to the tracker back on the event loop. Pushes that are awaited
+    /// together are added in the order they were made, as with `push`. A cancelled push is still
+    /// added, along with the next one to finish.
+    pub fn push_async(
+        slf: PyRef<'_, Self>,
+        point: &PyArray1<f32>,
+        id: Option<u64>,
+    ) -> PyResult<PyObject> {
+        let py = slf.py();
+        let reader = slf.tree.clone();
+        let pending = Arc::clone(&slf.pending);
+        let point = point.readonly().as_slice()?.to_vec();
+        let tracker: Py<Self> = slf.into();
+        push_in_order(
+            py,
+            tracker,
+            reader,
+            pending,
+            point,
+            id,
+            |t: &mut Self, path, id| t.hkl.add_path_with_id(path, id),
+        )
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.hkl.is_ready()
+    }
+
+    pub fn all_kl(&self) -> Vec<(f64, (i32, usize))> {
+        self.hkl.all_node_kl()
+    }
+
+    pub fn stats(&self) -> PyResult<PyObject> {
+        stats_dict(&self.hkl.kl_div_stats())
     }
 }
 
@@ -0,0 +1,218 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+use numpy::PyArray1;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+
+use std::convert::TryFrom;
+use std::sync::Arc;
+
+use goko::plugins::discrete::prelude::*;
+use goko::*;
+use pointcloud::data_sources::SparseDataRam;
+use pointcloud::label_sources::SmallIntLabels;
+use pointcloud::points::SparseRef;
+use pointcloud::*;
+
+use crate::metric::PySparseMetric;
+
+/// The point cloud sparse python trees are built on.
+pub type PySparsePointCloud = DefaultSparseLabeledCloud<PySparseMetric>;
+
+/// A cover tree over sparse data, given in the compressed sparse row layout of
+/// `scipy.sparse.csr_matrix`. Queries are sparse too, as a pair of index and value arrays.
+#[pyclass(unsendable)]
+pub struct SparseCoverTree {
+    builder: CoverTreeBuilder,
+    writer: Option<CoverTreeWriter<PySparsePointCloud>>,
+    metric: String,
+}
+
+#[pymethods]
+impl SparseCoverTree {
+    #[new]
+    fn new() -> PyResult<SparseCoverTree> {
+        Ok(SparseCoverTree {
+            builder: CoverTreeBuilder::new(),
+            writer: None,
+            metric: "l2".to_string(),
+        })
+    }
+    pub fn set_scale_base(&mut self, x: f32) {
+        self.builder.set_scale_base(x);
+    }
+    pub fn set_leaf_cutoff(&mut self, x: usize) {
+        self.builder.set_leaf_cutoff(x);
+    }
+    pub fn set_min_res_index(&mut self, x: i32) {
+        self.builder.set_min_res_index(x);
+    }
+    pub fn set_use_singletons(&mut self, x: bool) {
+        self.builder.set_use_singletons(x);
+    }
+
+    /// Picks the metric the tree is built and queried with, `l1` or `l2`. Takes effect on the
+    /// next `fit_sparse`.
+    pub fn set_metric(&mut self, metric_name: String) -> PyResult<()> {
+        PySparseMetric::from_name(&metric_name)?;
+        self.metric = metric_name.to_lowercase();
+        Ok(())
+    }
+
+    /// Builds the tree from the `indptr`, `indices` and `data` arrays of a CSR matrix, replacing
+    /// any previous fit. The dimension defaults to one past the largest column index. Points
+    /// without labels get the label 0.
+    pub fn fit_sparse(
+        &mut self,
+        indptr: &PyArray1<i64>,
+        indices: &PyArray1<i64>,
+        data: &PyArray1<f32>,
+        dim: Option<usize>,
+        labels: Option<&PyArray1<i64>>,
+    ) -> PyResult<()> {
+        let indptr = to_u32(indptr.readonly().as_slice().unwrap(), "indptr")?;
+        let indices = to_u32(indices.readonly().as_slice().unwrap(), "indices")?;
+        let data = Vec::from(data.readonly().as_slice().unwrap());
+        let metric = PySparseMetric::from_name(&self.metric)?;
+        let data = SparseDataRam::from_csr(indptr, indices, data, dim, metric)
+            .map_err(|e| PyValueError::new_err(e.to_string()))?;
+        let labels: Vec<i64> = match labels {
+            Some(labels) => Vec::from(labels.readonly().as_slice().unwrap()),
+            None => vec![0; data.len()],
+        };
+        if labels.len() != data.len() {
+            return Err(PyValueError::new_err(format!(
+                "got {} labels for {} points",
+                labels.len(),
+                data.len()
+            )));
+        }
+
+        // Release the old tree before we allocate the new one
+        self.writer = None;
+        let point_cloud = Arc::new(SimpleLabeledCloud::new(
+            data,
+            SmallIntLabels::new(labels, None),
+        ));
+        let mut writer = self
+            .builder
+            .build(point_cloud)
+            .map_err(|e| PyValueError::new_err(e.to_string()))?;
+        writer.generate_summaries();
+        writer.add_plugin::<GokoDirichlet>(GokoDirichlet {});
+        self.writer = Some(writer);
+        Ok(())
+    }
+
+    pub fn top_scale(&self) -> Option<i32> {
+        self.writer
+            .as_ref()
+            .map(|w| w.reader().scale_range().end - 1)
+    }
+
+    pub fn bottom_scale(&self) -> Option<i32> {
+        self.writer.as_ref().map(|w| w.reader().scale_range().start)
+    }
+
+    /// The k nearest neighbors of the sparse point with these column indices and values.
+    pub fn knn(
+        &self,
+        indices: &PyArray1<i64>,
+        values: &PyArray1<f32>,
+        k: usize,
+    ) -> PyResult<Vec<(f32, usize)>> {
+        let reader = self.reader()?;
+        let indices = to_u32(indices.readonly().as_slice().unwrap(), "indices")?;
+        let values = values.readonly();
+        let point = self.query(&indices, values.as_slice().unwrap())?;
+        reader
+            .knn(&point, k)
+            .map_err(|e| PyValueError::new_err(e.to_string()))
+    }
+
+    /// The path of the sparse point with these column indices and values down the tree.
+    pub fn path(
+        &self,
+        indices: &PyArray1<i64>,
+        values: &PyArray1<f32>,
+    ) -> PyResult<Vec<(f32, (i32, usize))>> {
+        let reader = self.reader()?;
+        let indices = to_u32(indices.readonly().as_slice().unwrap(), "indices")?;
+        let values = values.readonly();
+        let point = self.query(&indices, values.as_slice().unwrap())?;
+        reader
+            .path(&point)
+            .map_err(|e| PyValueError::new_err(e.to_string()))
+    }
+
+    pub fn known_path(&self, point_index: usize) -> PyResult<Vec<(f32, (i32, usize))>> {
+        self.reader()?
+            .known_path(point_index)
+            .map_err(|e| PyValueError::new_err(e.to_string()))
+    }
+}
+
+impl SparseCoverTree {
+    fn reader(&self) -> PyResult<CoverTreeReader<PySparsePointCloud>> {
+        self.writer
+            .as_ref()
+            .map(|w| w.reader())
+            .ok_or_else(|| PyValueError::new_err("the tree has not been fit"))
+    }
+
+    fn query<'a>(
+        &self,
+        indices: &'a [u32],
+        values: &'a [f32],
+    ) -> PyResult<SparseRef<'a, f32, u32>> {
+        if indices.len() != values.len() {
+            return Err(PyValueError::new_err(format!(
+                "got {} indices for {} values",
+                indices.len(),
+                values.len()
+            )));
+        }
+        let dim = self.reader()?.parameters().point_cloud.dim();
+        if let Some(i) = indices.iter().find(|i| **i as usize >= dim) {
+            return Err(PyValueError::new_err(format!(
+                "index {} is out of bounds for dimension {}",
+                i, dim
+            )));
+        }
+        // The sparse kernels walk both points in column order
+        if indices.windows(2).any(|w| w[0] >= w[1]) {
+            return Err(PyValueError::new_err(
+                "the indices must be sorted and distinct",
+            ));
+        }
+        Ok(SparseRef::new(dim, values, indices))
+    }
+}
+
+fn to_u32(values: &[i64], name: &str) -> PyResult<Vec<u32>> {
+    values
+        .iter()
+        .map(|v| {
+            u32::try_from(*v).map_err(|_| {
+                PyValueError::new_err(format!("{} has the entry {}, out of range", name, v))
+            })
+        })
+        .collect()
+}
@@ -17,32 +17,63 @@
 * under the License.
 */
 
-use ndarray::Array1;
+use ndarray::{Array1, Array2};
 use numpy::{IntoPyArray, PyArray1, PyArray2};
 use pyo3::prelude::*;
 use pyo3::types::PyDict;
 use rand::prelude::*;
+use roaring::RoaringBitmap;
 
 use std::path::Path;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use goko::query_interface::BulkInterface;
 use goko::*;
-use pointcloud::loaders::labeled_ram_from_yaml;
+use pointcloud::data_sources::DataRam;
+use pointcloud::label_sources::{CategoricalLabels, SmallIntLabels};
+use pointcloud::loaders::{
+    labels_from_npy, labels_from_npz, labels_from_yaml, npz_array_names, ram_from_npy,
+    ram_from_npz, ram_from_yaml, weighted_l2_from_yaml,
+};
+use pointcloud::meta_sources::MetadataMap;
+use pointcloud::metrics::{Accumulation, DimMask, WeightedL2};
+use pointcloud::missing_values::NanPolicy;
+use pointcloud::normalization::Normalization;
+use pointcloud::partitions::{PartitionMap, SimplePartitionedCloud};
+use pointcloud::pc_errors::{ParsingError, PointCloudError};
 use pointcloud::*;
 
+use crate::asyncio::spawn_future;
 use crate::layer::*;
+use crate::metric::PyMetric;
 use crate::node::*;
 use crate::plugins::*;
+use crate::PyPointCloud;
+use goko::build_progress::{BuildProgress, CancelToken};
+use goko::debug_bundle::DebugBundle;
+use goko::plugins::calibration::{CalibrationMethod, DistanceCalibration};
 use goko::plugins::discrete::prelude::*;
 use goko::plugins::gaussians::*;
+use goko::plugins::masking::MaskingPlugin;
+use goko::plugins::partitions::PartitionPlugin;
+use goko::plugins::PluginRegistry;
 
 #[pyclass(unsendable)]
 pub struct CoverTree {
-    builder: Option<CoverTreeBuilder>,
-    temp_point_cloud: Option<Arc<DefaultLabeledCloud<L2>>>,
-    writer: Option<CoverTreeWriter<DefaultLabeledCloud<L2>>>,
+    builder: CoverTreeBuilder,
+    temp_point_cloud: Option<Arc<PyPointCloud>>,
+    writer: Option<CoverTreeWriter<PyPointCloud>>,
     metric: String,
+    metric_weights: WeightedL2,
+    accumulation: Accumulation,
+    normalization: Option<Normalization>,
+    nan_policy: Option<NanPolicy>,
+    collapse_duplicates: bool,
+    gaussian_estimator: GaussianEstimator,
+    progress_callback: Option<PyObject>,
+    // The dimension of the batches passed to `partial_fit` since the last `fit`
+    partial_dim: Option<usize>,
 }
 
 #[pymethods]
@@ -50,91 +81,505 @@ impl CoverTree {
     #[new]
     fn new() -> PyResult<CoverTree> {
         Ok(CoverTree {
-            builder: Some(CoverTreeBuilder::new()),
+            builder: CoverTreeBuilder::new(),
             temp_point_cloud: None,
             writer: None,
-            metric: "DefaultLabeledCloud<L2>".to_string(),
+            metric: "l2".to_string(),
+            metric_weights: WeightedL2::default(),
+            accumulation: Accumulation::F32,
+            normalization: None,
+            nan_policy: None,
+            collapse_duplicates: false,
+            gaussian_estimator: GaussianEstimator::Moments,
+            progress_callback: None,
+            partial_dim: None,
         })
     }
     pub fn set_scale_base(&mut self, x: f32) {
-        match &mut self.builder {
-            Some(builder) => builder.set_scale_base(x),
-            None => panic!("Set too late"),
-        };
+        self.builder.set_scale_base(x);
     }
     pub fn set_leaf_cutoff(&mut self, x: usize) {
-        match &mut self.builder {
-            Some(builder) => builder.set_leaf_cutoff(x),
-            None => panic!("Set too late"),
-        };
+        self.builder.set_leaf_cutoff(x);
     }
     pub fn set_min_res_index(&mut self, x: i32) {
-        match &mut self.builder {
-            Some(builder) => builder.set_min_res_index(x),
-            None => panic!("Set too late"),
-        };
+        self.builder.set_min_res_index(x);
     }
     pub fn set_use_singletons(&mut self, x: bool) {
-        match &mut self.builder {
-            Some(builder) => builder.set_use_singletons(x),
-            None => panic!("Set too late"),
-        };
+        self.builder.set_use_singletons(x);
+    }
+    /// Removes the nodes in the middle of chains of single children after each build. Queries
+    /// are unchanged, but the pruned tree skips scales along those chains.
+    pub fn set_prune_chains(&mut self, x: bool) {
+        self.builder.set_prune_chains(x);
     }
 
     pub fn set_verbosity(&mut self, x: u32) {
-        match &mut self.builder {
-            Some(builder) => builder.set_verbosity(x),
-            None => panic!("Set too late"),
-        };
+        self.builder.set_verbosity(x);
+    }
+
+    /// Seeds the build, so that fitting the same data again gives the same tree.
+    pub fn set_seed(&mut self, x: u64) {
+        self.builder.set_seed(x);
+    }
+
+    /// Calls `callback` with a dict of the build's progress every `interval` seconds during a fit:
+    /// `inserted_nodes`, `total_nodes` created so far, `indexed_points`, `point_count`,
+    /// `layer_counts` as `(scale_index, nodes)` pairs and `elapsed` seconds. The fit is cancelled
+    /// with a `ValueError` if the callback returns `False`, and with the callback's exception if it
+    /// raises one. A keyboard interrupt also cancels it. Pass `None` to remove the callback.
+    pub fn set_progress_callback(&mut self, callback: Option<PyObject>, interval: Option<f64>) {
+        self.progress_callback = callback;
+        if let Some(interval) = interval {
+            self.builder
+                .set_progress_interval(Duration::from_secs_f64(interval));
+        }
     }
 
     pub fn load_yaml_config(&mut self, file_name: String) -> PyResult<()> {
         let path = Path::new(&file_name);
-        let point_cloud = Arc::new(labeled_ram_from_yaml::<_, L2>(&path).unwrap());
-        let builder = CoverTreeBuilder::from_yaml(&path);
-        self.builder = Some(builder);
-        self.temp_point_cloud = Some(point_cloud);
+        let to_py_err = |e: PointCloudError| pyo3::exceptions::PyValueError::new_err(e.to_string());
+        self.metric_weights = weighted_l2_from_yaml(&path).map_err(to_py_err)?;
+        let metric = PyMetric::from_name(&self.metric, &self.metric_weights, self.accumulation)?;
+        let data = ram_from_yaml::<_, L2>(&path).map_err(to_py_err)?;
+        metric.check_dim(data.dim())?;
+        let labels = labels_from_yaml(&path).map_err(to_py_err)?;
+        self.builder = CoverTreeBuilder::from_yaml(&path);
+        let partitions = PartitionMap::unpartitioned(data.len());
+        self.temp_point_cloud = Some(Arc::new(SimpleLabeledCloud::new(
+            SimplePartitionedCloud::new(
+                SimpleMetaCloud::new(data.replace_metric(metric), MetadataMap::new()),
+                partitions,
+            )
+            .map_err(to_py_err)?,
+            labels.into(),
+        )));
+        Ok(())
+    }
+
+    /// Picks the metric the tree is built and queried with, one of `l1`, `l2`, `cosine`,
+    /// `bray_curtis`, `correlation`, `jensen_shannon` or `wasserstein`. Takes effect on the next
+    /// `fit` with data, or the next `load_yaml_config`.
+    pub fn set_metric(&mut self, metric_name: String) -> PyResult<()> {
+        PyMetric::from_name(&metric_name, &WeightedL2::default(), Accumulation::F32)?;
+        self.metric = metric_name.to_lowercase();
+        Ok(())
+    }
+
+    /// Weighs each dimension in the L2 distance, `sqrt(sum w_i (x_i - y_i)^2)`. Takes effect on
+    /// the next `fit` with data. Pass an empty list to go back to the unweighted distance.
+    pub fn set_metric_weights(&mut self, weights: Vec<f32>) -> PyResult<()> {
+        self.metric_weights = WeightedL2::new(weights)
+            .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))?;
+        Ok(())
+    }
+
+    /// Learns the L2 metric weights from labeled data, so points with the same label end up near
+    /// each other. Samples `pairs` pairs of points with the same label and `pairs` with different
+    /// labels, with the seed, and weighs each dimension by how much more the different pairs
+    /// spread along it. Negative labels are left out. Takes effect on the next `fit` with data,
+    /// and returns the learned weights.
+    pub fn learn_metric_weights(
+        &mut self,
+        data: &PyArray2<f32>,
+        labels: Vec<i64>,
+        pairs: Option<usize>,
+        seed: Option<u64>,
+    ) -> PyResult<Py<PyArray1<f32>>> {
+        let to_py_err = |e: PointCloudError| pyo3::exceptions::PyValueError::new_err(e.to_string());
+        let dim = data.shape()[1];
+        if labels.len() != data.shape()[0] {
+            return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "got {} labels for {} points",
+                labels.len(),
+                data.shape()[0]
+            )));
+        }
+        let labels: Vec<Option<i64>> = labels
+            .into_iter()
+            .map(|l| if l < 0 { None } else { Some(l) })
+            .collect();
+        let (similar, dissimilar) = metric_learning::sample_pairs(
+            &labels,
+            pairs.unwrap_or(1000),
+            seed.unwrap_or_else(rand::random),
+        );
+        let data = data.readonly();
+        self.metric_weights =
+            metric_learning::learn_diagonal(data.as_slice().unwrap(), dim, &similar, &dissimilar)
+                .map_err(to_py_err)?;
+        let gil = pyo3::Python::acquire_gil();
+        Ok(Array1::from(self.metric_weights.weights().to_vec())
+            .into_pyarray(gil.python())
+            .to_owned())
+    }
+
+    /// Picks how the l1 and l2 distances sum over the coordinates, `f32` for the fast SIMD
+    /// kernels, or `f64` or `kahan` to keep long dense vectors from losing small differences to
+    /// rounding. Takes effect on the next `fit` with data.
+    pub fn set_accumulation(&mut self, accumulation: String) -> PyResult<()> {
+        self.accumulation = Accumulation::from_name(&accumulation).ok_or_else(|| {
+            pyo3::exceptions::PyValueError::new_err(format!(
+                "unknown accumulation {:?}, expected f32, f64 or kahan",
+                accumulation
+            ))
+        })?;
+        Ok(())
+    }
+
+    /// Normalizes the data when it's fitted, `z_score`, `min_max` or `l2`, or `None` to leave it
+    /// as is. The normalization is fitted to the data and every query point is normalized the
+    /// same way before it's compared to the tree. Takes effect on the next `fit` with data. A yaml
+    /// config sets this with its own `normalization` key.
+    pub fn set_normalization(&mut self, normalization: Option<String>) -> PyResult<()> {
+        self.normalization = match normalization {
+            Some(name) => Some(name.to_lowercase().parse().map_err(|e: ParsingError| {
+                pyo3::exceptions::PyValueError::new_err(e.to_string())
+            })?),
+            None => None,
+        };
+        Ok(())
+    }
+
+    /// Handles NaNs in the data when it's fitted. `reject` makes them an error, `impute_mean`
+    /// fills them in with the mean of their dimension, here and in query points, and
+    /// `ignore_pairwise` keeps them for the `nan_euclidean` metric to skip. `None` leaves the
+    /// data as is, the build then fails if any distance is NaN. Takes effect on the next `fit`
+    /// with data, before the normalization.
+    pub fn set_nan_policy(&mut self, nan_policy: Option<String>) -> PyResult<()> {
+        self.nan_policy = match nan_policy {
+            Some(name) => Some(name.to_lowercase().parse().map_err(|e: ParsingError| {
+                pyo3::exceptions::PyValueError::new_err(e.to_string())
+            })?),
+            None => None,
+        };
+        Ok(())
+    }
+
+    /// Collapses identical points into one point that's counted as many times when the tree is
+    /// fitted. Points with different labels or partitions are kept apart. Queries return only
+    /// the first of the copies, but the node coverage, label summaries and the Dirichlet plugin
+    /// count all of them. Takes effect on the next `fit` with data.
+    pub fn set_collapse_duplicates(&mut self, x: bool) {
+        self.collapse_duplicates = x;
+    }
+
+    /// Picks how the node gaussians estimate location and scale, `moments` for the mean and
+    /// variance, `median` for the median and median absolute deviation, or `trimmed_mean` with
+    /// the fraction `trim` cut from each end. The robust estimators suit heavy tailed data. Takes
+    /// effect on the next fit.
+    pub fn set_gaussian_estimator(&mut self, estimator: String, trim: Option<f32>) -> PyResult<()> {
+        self.gaussian_estimator = match estimator.to_lowercase().as_str() {
+            "moments" => GaussianEstimator::Moments,
+            "median" => GaussianEstimator::Median,
+            "trimmed_mean" => GaussianEstimator::TrimmedMean(trim.unwrap_or(0.1)),
+            _ => {
+                return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                    "unknown estimator {:?}, expected moments, median or trimmed_mean",
+                    estimator
+                )))
+            }
+        };
         Ok(())
     }
 
-    pub fn set_metric(&mut self, metric_name: String) {
-        self.metric = metric_name;
+    /// Returns a new, unfitted tree with the same build parameters and metric as this one.
+    pub fn copy_unfitted(&self) -> CoverTree {
+        CoverTree {
+            builder: self.builder.clone(),
+            temp_point_cloud: self.temp_point_cloud.clone(),
+            writer: None,
+            metric: self.metric.clone(),
+            metric_weights: self.metric_weights.clone(),
+            accumulation: self.accumulation,
+            normalization: self.normalization,
+            nan_policy: self.nan_policy,
+            collapse_duplicates: self.collapse_duplicates,
+            gaussian_estimator: self.gaussian_estimator,
+            progress_callback: self.progress_callback.clone(),
+            partial_dim: None,
+        }
+    }
+
+    /// Drops the fitted tree so that its memory is reclaimed. Nodes, layers and trackers
+    /// handed out earlier keep their own reference until python releases them.
+    pub fn clear(&mut self) {
+        self.writer = None;
+        self.clear_partial_fit();
     }
 
+    /// Builds the tree, replacing any previous fit. The build parameters are kept, so this can
+    /// be called repeatedly with different data or after changing a parameter. The labels are
+    /// either an integer array or a list of strings, with `None` for unlabeled points. String
+    /// labels are kept as named categories, see `label_names`. The metadata is an optional list
+    /// of strings, like document ids or urls, returned by `knn_metadata` and
+    /// `Node.singletons_metadata`. The dimension names are optional, one per column of the data.
+    /// The partitions are an optional list of keys, like tenant ids, with `None` for points in no
+    /// partition, see `knn_in_partitions`. Returns the `build_report` of the fit.
     pub fn fit(
         &mut self,
         data: Option<&PyArray2<f32>>,
-        labels: Option<&PyArray1<i64>>,
-    ) -> PyResult<()> {
+        labels: Option<&PyAny>,
+        metadata: Option<Vec<Option<String>>>,
+        dim_names: Option<Vec<String>>,
+        partitions: Option<Vec<Option<String>>>,
+    ) -> PyResult<PyObject> {
+        // Release the old tree before we allocate the new one
+        self.writer = None;
+        self.clear_partial_fit();
         let point_cloud = if let Some(data) = data {
             let len = data.shape()[0];
             let data_dim = data.shape()[1];
-            let my_labels: Vec<i64> = match labels {
-                Some(labels) => Vec::from(labels.readonly().as_slice().unwrap()),
-                None => vec![0; len],
+            let my_labels = categorical_labels(labels, len)?;
+            let metadata = match metadata {
+                Some(metadata) => {
+                    if metadata.len() != len {
+                        return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                            "got {} metadata entries for {} points",
+                            metadata.len(),
+                            len
+                        )));
+                    }
+                    MetadataMap::from_values(metadata)
+                }
+                None => MetadataMap::new(),
             };
-            Arc::new(DefaultLabeledCloud::<L2>::new_simple(
+            self.point_cloud_from_parts(
                 Vec::from(data.readonly().as_slice().unwrap()),
                 data_dim,
                 my_labels,
-            ))
+                metadata,
+                dim_names,
+                partitions,
+            )?
         } else {
-            if let Some(point_cloud) = self.temp_point_cloud.take() {
-                point_cloud
+            if let Some(point_cloud) = self.temp_point_cloud.as_ref() {
+                Arc::clone(point_cloud)
             } else {
                 panic!("No known point_cloud");
             }
         };
 
-        let builder = self.builder.take();
-        self.writer = Some(builder.unwrap().build(point_cloud).unwrap());
-        let writer = self.writer.as_mut().unwrap();
-        writer.generate_summaries();
-        writer.add_plugin::<GokoDiagGaussian>(GokoDiagGaussian::singletons());
-        writer.add_plugin::<GokoDirichlet>(GokoDirichlet {});
+        self.build_writer(point_cloud)?;
+        self.build_report()
+    }
+
+    /// Saves the tree to `path`, with its build parameters and the parameters of its plugins,
+    /// like the calibration and masking. The data isn't saved, keep it next to the tree.
+    pub fn save(&self, path: String) -> PyResult<()> {
+        let writer = self.writer.as_ref().unwrap();
+        writer
+            .save(path)
+            .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))
+    }
+
+    /// Loads a tree saved with `save`, replacing any previous fit. The data and labels have to be
+    /// the ones the tree was fit on, in the same order. Without data this uses the data of the
+    /// current fit or config. The plugins are rebuilt from their saved parameters.
+    pub fn load(
+        &mut self,
+        path: String,
+        data: Option<&PyArray2<f32>>,
+        labels: Option<&PyAny>,
+    ) -> PyResult<()> {
+        let point_cloud = match data {
+            Some(data) => {
+                let len = data.shape()[0];
+                let data_dim = data.shape()[1];
+                let my_labels = categorical_labels(labels, len)?;
+                self.point_cloud_from_parts(
+                    Vec::from(data.readonly().as_slice().unwrap()),
+                    data_dim,
+                    my_labels,
+                    MetadataMap::new(),
+                    None,
+                    None,
+                )?
+            }
+            None => match (self.writer.as_ref(), self.temp_point_cloud.as_ref()) {
+                (Some(writer), _) => Arc::clone(writer.reader().point_cloud()),
+                (None, Some(point_cloud)) => Arc::clone(point_cloud),
+                (None, None) => {
+                    return Err(pyo3::exceptions::PyValueError::new_err(
+                        "loading a tree needs the data it was fit on",
+                    ))
+                }
+            },
+        };
+        let mut registry = PluginRegistry::default();
+        registry.register::<MaskingPlugin>();
+        self.writer = None;
+        self.clear_partial_fit();
+        let writer = CoverTreeWriter::load_file(path, point_cloud, &registry)
+            .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))?;
+        self.writer = Some(writer);
         Ok(())
     }
 
+    /// Builds the tree straight from numpy files on disk, without loading them into python first.
+    /// `path` is either a `.npy` of data, with the labels optionally in a second `.npy` at
+    /// `labels_path`, or a `.npz` holding named arrays. The `.npz` arrays are looked up as `data`
+    /// and `labels` unless other names are given, and its labels are optional too. Points without
+    /// labels get the label 0.
+    pub fn fit_npy(
+        &mut self,
+        path: String,
+        labels_path: Option<String>,
+        data_name: Option<String>,
+        labels_name: Option<String>,
+    ) -> PyResult<()> {
+        let to_py_err = |e: PointCloudError| pyo3::exceptions::PyValueError::new_err(e.to_string());
+        let is_npz = Path::new(&path)
+            .extension()
+            .map(|e| e == "npz")
+            .unwrap_or(false);
+        let (data, labels) = if is_npz {
+            let data_name = data_name.as_deref().unwrap_or("data");
+            let data = ram_from_npz::<_, L2>(&path, data_name).map_err(to_py_err)?;
+            let labels = match labels_name {
+                Some(labels_name) => Some(labels_from_npz(&path, &labels_name).map_err(to_py_err)?),
+                // The labels are optional if they weren't asked for by name
+                None => {
+                    let names = npz_array_names(&path).map_err(to_py_err)?;
+                    if names.iter().any(|n| n == "labels") {
+                        Some(labels_from_npz(&path, "labels").map_err(to_py_err)?)
+                    } else {
+                        None
+                    }
+                }
+            };
+            (data, labels)
+        } else {
+            let data = ram_from_npy::<_, L2>(&path).map_err(to_py_err)?;
+            let labels = match labels_path {
+                Some(labels_path) => Some(labels_from_npy(&labels_path).map_err(to_py_err)?),
+                None => None,
+            };
+            (data, labels)
+        };
+        let labels = match labels {
+            Some(labels) => {
+                if labels.len() != data.len() {
+                    return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                        "got {} labels for {} points",
+                        labels.len(),
+                        data.len()
+                    )));
+                }
+                labels
+            }
+            None => SmallIntLabels::new(vec![0; data.len()], None),
+        };
+        let metric = PyMetric::from_name(&self.metric, &self.metric_weights, self.accumulation)?;
+        metric.check_dim(data.dim())?;
+        let data = match self.nan_policy {
+            Some(policy) => data.with_nan_policy(policy).map_err(to_py_err)?,
+            None => data,
+        };
+        let mut data = match self.normalization {
+            Some(kind) => data.with_normalization(kind).map_err(to_py_err)?,
+            None => data,
+        };
+        if self.collapse_duplicates {
+            data.collapse_duplicates_by(|pi| labels.label(pi).ok().map(|l| l.copied()));
+        }
+
+        // Release the old tree before we allocate the new one
+        self.writer = None;
+        self.clear_partial_fit();
+        let partitions = PartitionMap::unpartitioned(data.len());
+        let point_cloud = Arc::new(SimpleLabeledCloud::new(
+            SimplePartitionedCloud::new(
+                SimpleMetaCloud::new(data.replace_metric(metric), MetadataMap::new()),
+                partitions,
+            )
+            .map_err(to_py_err)?,
+            labels.into(),
+        ));
+        self.build_writer(point_cloud)?;
+        Ok(())
+    }
+
+    /// Fits the tree a batch at a time, following scikit-learn's `partial_fit` convention for
+    /// out-of-core learning. The first call creates the tree and later calls add their points to
+    /// it, so the tree covers every batch since the last `fit` or `clear`. Points without labels
+    /// get the label 0.
+    ///
+    /// Later batches are inserted into the tree one point at a time, splitting leaves that grow
+    /// past the leaf cutoff. They're filled in and normalized with what was fitted on the first
+    /// batch, and duplicates are only collapsed within the first batch. Nodes, layers and trackers
+    /// from this tree must be released before adding a batch.
+    pub fn partial_fit(
+        &mut self,
+        data: &PyArray2<f32>,
+        labels: Option<&PyArray1<i64>>,
+    ) -> PyResult<()> {
+        let len = data.shape()[0];
+        let data_dim = data.shape()[1];
+        if let Some(dim) = self.partial_dim {
+            if dim != data_dim {
+                return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                    "expected data of dimension {}, got {}",
+                    dim, data_dim
+                )));
+            }
+        }
+        let my_labels: Vec<i64> = match labels {
+            Some(labels) => Vec::from(labels.readonly().as_slice().unwrap()),
+            None => vec![0; len],
+        };
+        if my_labels.len() != len {
+            return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "got {} labels for {} points",
+                my_labels.len(),
+                len
+            )));
+        }
+        let data = data.readonly();
+        let data = data.as_slice().unwrap();
+
+        if self.partial_dim.is_none() || self.writer.is_none() {
+            // Release the old tree before we allocate the new one
+            self.writer = None;
+            let point_cloud = self.point_cloud_from_parts(
+                data.to_vec(),
+                data_dim,
+                SmallIntLabels::new(my_labels, None).into(),
+                MetadataMap::new(),
+                None,
+                None,
+            )?;
+            self.build_writer(point_cloud)?;
+            self.partial_dim = Some(data_dim);
+            return Ok(());
+        }
+
+        // The loaded point cloud shares the tree's point cloud, let go of it while we insert.
+        let kept_point_cloud = self.temp_point_cloud.take().is_some();
+        let writer = self.writer.as_mut().unwrap();
+        let result = data
+            .chunks_exact(data_dim)
+            .zip(&my_labels)
+            .try_for_each(|(point, label)| writer.insert(point, Some(label)).map(|_| ()));
+        if kept_point_cloud {
+            self.temp_point_cloud = Some(Arc::clone(writer.reader().point_cloud()));
+        }
+        result.map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))
+    }
+
+    /// Corrects the labels of some points and updates the label summaries along their paths.
+    /// Nodes, layers and trackers from this tree must be released before calling this.
+    pub fn update_labels(&mut self, indexes: Vec<usize>, labels: &PyArray1<i64>) -> PyResult<()> {
+        let labels = Vec::from(labels.readonly().as_slice().unwrap());
+        // The loaded point cloud shares the tree's point cloud, let go of it while we edit.
+        let kept_point_cloud = self.temp_point_cloud.take().is_some();
+        let writer = self.writer.as_mut().unwrap();
+        let result = writer.update_labels(&indexes, &labels);
+        if kept_point_cloud {
+            self.temp_point_cloud = Some(Arc::clone(writer.reader().point_cloud()));
+        }
+        result.map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))
+    }
+
     /*
     pub fn attach_svds(&mut self, min_point_count: usize, max_point_count: usize, tau: f32) {
         let writer = self.writer.as_mut().unwrap();
@@ -146,6 +591,28 @@ impl CoverTree {
     }
     */
 
+    /// The names of the dimensions, from `fit` or the yaml config. `None` if they weren't named.
+    pub fn dim_names(&self) -> Option<Vec<String>> {
+        let reader = self.writer.as_ref().unwrap().reader();
+        reader
+            .parameters()
+            .point_cloud
+            .dim_names()
+            .map(|names| names.to_vec())
+    }
+
+    /// The names of the label categories, indexed by the label. Empty unless the tree was fit
+    /// with string labels.
+    pub fn label_names(&self) -> Vec<String> {
+        let reader = self.writer.as_ref().unwrap().reader();
+        let point_cloud = &reader.parameters().point_cloud;
+        let mut names = Vec::new();
+        while let Some(name) = point_cloud.label_name(&(names.len() as i64)) {
+            names.push(name.to_string());
+        }
+        names
+    }
+
     pub fn data_point(&self, point_index: usize) -> PyResult<Option<Py<PyArray1<f32>>>> {
         let reader = self.writer.as_ref().unwrap().reader();
         let dim = reader.parameters().point_cloud.dim();
@@ -221,6 +688,331 @@ impl CoverTree {
             .unwrap()
     }
 
+    /// `knn` as an awaitable, `await tree.knn_async(point, k)`. The query runs on the rayon pool
+    /// without the GIL so the event loop keeps serving while it does. Must be called from a
+    /// running event loop.
+    pub fn knn_async(&self, py: Python<'_>, point: &PyArray1<f32>, k: usize) -> PyResult<PyObject> {
+        let reader = self.writer.as_ref().unwrap().reader();
+        let point = point.readonly().as_slice()?.to_vec();
+        spawn_future(
+            py,
+            move || reader.knn(&point.as_slice(), k),
+            |py, neighbors| Ok(neighbors.into_py(py)),
+        )
+    }
+
+    /// Approximate `knn`, the `k`th neighbor found is at most `1 + epsilon` times farther than
+    /// the true one. Larger `epsilon` searches fewer nodes, `0` is exact.
+    pub fn knn_approx(
+        &self,
+        point: &PyArray1<f32>,
+        k: usize,
+        epsilon: f32,
+    ) -> PyResult<Vec<(f32, usize)>> {
+        let reader = self.writer.as_ref().unwrap().reader();
+        reader
+            .knn_approx(&point.readonly().as_slice().unwrap(), k, epsilon)
+            .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))
+    }
+
+    /// `knn_approx` that gathers `candidate_multiplier` times `k` candidates, 4 by default, and
+    /// keeps the `k` closest by the exact distance. This wins back most of the recall `epsilon`
+    /// gives up, at the cost of the larger search.
+    pub fn knn_reranked(
+        &self,
+        point: &PyArray1<f32>,
+        k: usize,
+        epsilon: f32,
+        candidate_multiplier: Option<usize>,
+    ) -> PyResult<Vec<(f32, usize)>> {
+        let reader = self.writer.as_ref().unwrap().reader();
+        let point = point.readonly();
+        let point = point.as_slice().unwrap();
+        reader
+            .knn_reranked(
+                &point,
+                reader.point_cloud().as_ref(),
+                &point,
+                k,
+                epsilon,
+                candidate_multiplier.unwrap_or(4),
+            )
+            .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))
+    }
+
+    /// The `k` nearest neighbors of each row of `points`, as a pair of `(n, k)` arrays of the
+    /// distances and the indexes. Rows are spread over all the cores and nearby rows share their
+    /// descent of the tree, which is much faster than calling `knn` row by row. If the tree has
+    /// fewer than `k` points the missing neighbors have a distance of `inf` and an index of -1.
+    pub fn knn_batch(
+        &self,
+        py: Python<'_>,
+        points: &PyArray2<f32>,
+        k: usize,
+    ) -> PyResult<(Py<PyArray2<f32>>, Py<PyArray2<i64>>)> {
+        let dim = points.shape()[1];
+        let data = points.readonly();
+        let rows: Vec<&[f32]> = data.as_slice()?.chunks_exact(dim).collect();
+        let reader = self.writer.as_ref().unwrap().reader();
+        let batch = py
+            .allow_threads(|| reader.knn_batch(&rows, k).map_err(|e| e.to_string()))
+            .map_err(pyo3::exceptions::PyValueError::new_err)?;
+
+        let mut distances = Array2::from_elem((batch.len(), k), f32::INFINITY);
+        let mut indexes = Array2::from_elem((batch.len(), k), -1i64);
+        for (i, (dists, pis)) in batch.iter().enumerate() {
+            for (j, (d, pi)) in dists.iter().zip(pis).enumerate() {
+                distances[[i, j]] = *d;
+                indexes[[i, j]] = *pi as i64;
+            }
+        }
+        Ok((
+            distances.into_pyarray(py).to_owned(),
+            indexes.into_pyarray(py).to_owned(),
+        ))
+    }
+
+    /// The kNN graph of the training set: the `k` nearest neighbors of every point in the tree,
+    /// not counting the point itself, as a pair of `(n, k)` arrays of the distances and the
+    /// indexes. Row `i` is point `i`. This walks the tree against itself, which is far faster
+    /// than a `knn_batch` of the training set. Deleted points, collapsed duplicates and missing
+    /// neighbors have a distance of `inf` and an index of -1.
+    pub fn all_knn(
+        &self,
+        py: Python<'_>,
+        k: usize,
+    ) -> PyResult<(Py<PyArray2<f32>>, Py<PyArray2<i64>>)> {
+        let reader = self.writer.as_ref().unwrap().reader();
+        let point_indexes = reader.point_cloud().reference_indexes();
+        let num_points = reader.point_cloud().len();
+        let graph = py
+            .allow_threads(|| reader.all_knn(k).map_err(|e| e.to_string()))
+            .map_err(pyo3::exceptions::PyValueError::new_err)?;
+
+        let mut distances = Array2::from_elem((num_points, k), f32::INFINITY);
+        let mut indexes = Array2::from_elem((num_points, k), -1i64);
+        for (i, (dists, pis)) in point_indexes.iter().zip(graph.iter()) {
+            for (j, (d, pi)) in dists.iter().zip(pis).enumerate() {
+                distances[[*i, j]] = *d;
+                indexes[[*i, j]] = *pi as i64;
+            }
+        }
+        Ok((
+            distances.into_pyarray(py).to_owned(),
+            indexes.into_pyarray(py).to_owned(),
+        ))
+    }
+
+    /// All the points within `radius` of the point, closest first, as `(distance, index)`.
+    pub fn range_query(&self, point: &PyArray1<f32>, radius: f32) -> PyResult<Vec<(f32, usize)>> {
+        let reader = self.writer.as_ref().unwrap().reader();
+        reader
+            .range(&point.readonly().as_slice().unwrap(), radius)
+            .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))
+    }
+
+    /// The `k` nearest neighbors with a bonus for sharing the label, as `(score, index)`. The
+    /// score is the distance, plus `weight` for points without the label. For string labels pass
+    /// the name's position in `label_names`.
+    pub fn knn_label_boosted(
+        &self,
+        point: &PyArray1<f32>,
+        k: usize,
+        label: i64,
+        weight: f32,
+    ) -> PyResult<Vec<(f32, usize)>> {
+        let reader = self.writer.as_ref().unwrap().reader();
+        reader
+            .knn_label_boosted(&point.readonly().as_slice().unwrap(), k, &label, weight)
+            .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))
+    }
+
+    /// The `k` nearest neighbors with one of the `labels`, as `(distance, index)`. Parts of the
+    /// tree without any of the labels are skipped. For string labels pass the names' positions
+    /// in `label_names`.
+    pub fn knn_with_labels(
+        &self,
+        point: &PyArray1<f32>,
+        k: usize,
+        labels: Vec<i64>,
+    ) -> PyResult<Vec<(f32, usize)>> {
+        let reader = self.writer.as_ref().unwrap().reader();
+        reader
+            .knn_with_labels(&point.readonly().as_slice().unwrap(), k, &labels)
+            .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))
+    }
+
+    /// Fits the map from knn distances to the probability that a neighbor shares the query's
+    /// label, on a labeled holdout set that isn't in the tree, and stores it with the tree.
+    /// `method` is `platt` or `isotonic`. Each holdout row's `k` nearest neighbors are the
+    /// samples. For string labels pass the names' positions in `label_names`.
+    pub fn calibrate(
+        &mut self,
+        points: &PyArray2<f32>,
+        labels: &PyArray1<i64>,
+        k: usize,
+        method: String,
+    ) -> PyResult<()> {
+        let method = match method.to_lowercase().as_str() {
+            "platt" => CalibrationMethod::Platt,
+            "isotonic" => CalibrationMethod::Isotonic,
+            _ => {
+                return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                    "unknown calibration method {:?}, expected platt or isotonic",
+                    method
+                )))
+            }
+        };
+        let dim = points.shape()[1];
+        let data = points.readonly().as_slice()?.to_vec();
+        let labels = labels.readonly().as_slice()?.to_vec();
+        let holdout = DefaultLabeledCloud::<L2>::new_simple(data, dim, labels);
+        let writer = self.writer.as_mut().unwrap();
+        let calibration = DistanceCalibration::fit(&writer.reader(), &holdout, k, method)
+            .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))?;
+        writer
+            .add_persistent_plugin(calibration)
+            .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))
+    }
+
+    /// The `k` nearest neighbors as `(probability, index)`, the calibrated probability that each
+    /// shares the point's label. Needs `calibrate` first.
+    pub fn knn_probabilities(
+        &self,
+        point: &PyArray1<f32>,
+        k: usize,
+    ) -> PyResult<Vec<(f32, usize)>> {
+        let reader = self.writer.as_ref().unwrap().reader();
+        reader
+            .knn_probabilities(&point.readonly().as_slice().unwrap(), k)
+            .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))
+    }
+
+    /// Predicts the point's label by a vote of its `k` nearest neighbors weighted by their
+    /// calibrated probabilities, as `(label, share of the vote)`. `None` if no neighbor has a
+    /// label. Needs `calibrate` first.
+    pub fn predict(&self, point: &PyArray1<f32>, k: usize) -> PyResult<Option<(i64, f32)>> {
+        let reader = self.writer.as_ref().unwrap().reader();
+        reader
+            .predict(&point.readonly().as_slice().unwrap(), k)
+            .map(|prediction| prediction.map(|(label, share)| (*label, share)))
+            .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))
+    }
+
+    /// Attaches the per-dimension bounds that speed up `knn_masked` when the mask is a small part
+    /// of the dimensions. This stores a float per dimension on every node.
+    pub fn attach_masking(&mut self) {
+        let writer = self.writer.as_mut().unwrap();
+        writer
+            .add_persistent_plugin(MaskingPlugin::default())
+            .unwrap();
+    }
+
+    /// The `k` nearest neighbors by the distance over the dimensions in `dims` only, with those
+    /// distances. Only the l1 and l2 metrics support this.
+    pub fn knn_masked(
+        &self,
+        point: &PyArray1<f32>,
+        k: usize,
+        dims: Vec<usize>,
+    ) -> PyResult<Vec<(f32, usize)>> {
+        let reader = self.writer.as_ref().unwrap().reader();
+        let point_cloud = reader.point_cloud();
+        if !point_cloud.metric().supports_masks() {
+            return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "the {} metric doesn't support masks, use l1 or l2",
+                self.metric
+            )));
+        }
+        let mask = DimMask::new(dims, point_cloud.dim())
+            .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))?;
+        reader
+            .knn_masked(&point.readonly().as_slice().unwrap(), k, &mask)
+            .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))
+    }
+
+    /// The `k` nearest neighbors among the points in any of the partitions, the keys passed to
+    /// `fit`. Unknown keys match nothing.
+    pub fn knn_in_partitions(
+        &self,
+        point: &PyArray1<f32>,
+        k: usize,
+        partitions: Vec<String>,
+    ) -> PyResult<Vec<(f32, usize)>> {
+        let reader = self.writer.as_ref().unwrap().reader();
+        reader
+            .knn_in_partitions(&point.readonly().as_slice().unwrap(), k, &partitions)
+            .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))
+    }
+
+    /// The `k` nearest neighbors as `(distance, index, metadata)`, the metadata passed to `fit`
+    /// or `None` for points without any.
+    pub fn knn_metadata(
+        &self,
+        point: &PyArray1<f32>,
+        k: usize,
+    ) -> Vec<(f32, usize, Option<String>)> {
+        let reader = self.writer.as_ref().unwrap().reader();
+        reader
+            .knn_with_metadata(&point.readonly().as_slice().unwrap(), k)
+            .unwrap()
+            .into_iter()
+            .map(|(d, i, m)| (d, i, m.cloned()))
+            .collect()
+    }
+
+    /// The `k` nearest neighbors as `(distance, index, label, address)`, with the label the point
+    /// was fit with and the address of the node it's in. String labels are given as their position
+    /// in `label_names`, unlabeled points as `None`.
+    pub fn knn_annotated(
+        &self,
+        point: &PyArray1<f32>,
+        k: usize,
+    ) -> PyResult<Vec<(f32, usize, Option<i64>, (i32, usize))>> {
+        let reader = self.writer.as_ref().unwrap().reader();
+        let neighbors = reader
+            .knn_annotated(&point.readonly().as_slice().unwrap(), k)
+            .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))?;
+        Ok(neighbors
+            .into_iter()
+            .map(|(d, i, label, address)| (d, i, label.copied(), address))
+            .collect())
+    }
+
+    /// The `k` nearest neighbors that aren't in `excluded`, for leaving out points temporarily
+    /// without editing the tree.
+    pub fn knn_excluding(
+        &self,
+        point: &PyArray1<f32>,
+        k: usize,
+        excluded: Vec<u32>,
+    ) -> PyResult<Vec<(f32, usize)>> {
+        let excluded: RoaringBitmap = excluded.into_iter().collect();
+        let reader = self.writer.as_ref().unwrap().reader();
+        reader
+            .knn_excluding(&point.readonly().as_slice().unwrap(), k, &excluded)
+            .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))
+    }
+
+    pub fn knn_within(&self, point: &PyArray1<f32>, k: usize, radius: f32) -> Vec<(f32, usize)> {
+        let reader = self.writer.as_ref().unwrap().reader();
+        reader
+            .knn_within(&point.readonly().as_slice().unwrap(), k, radius)
+            .unwrap()
+    }
+
+    pub fn knn_hinted(
+        &self,
+        point: &PyArray1<f32>,
+        k: usize,
+        hint: (i32, usize),
+    ) -> Vec<(f32, usize)> {
+        let reader = self.writer.as_ref().unwrap().reader();
+        reader
+            .knn_hinted(&point.readonly().as_slice().unwrap(), k, hint)
+            .unwrap()
+    }
+
     pub fn routing_knn(&self, point: &PyArray1<f32>, k: usize) -> Vec<(f32, usize)> {
         let reader = self.writer.as_ref().unwrap().reader();
         reader
@@ -228,11 +1020,296 @@ impl CoverTree {
             .unwrap()
     }
 
+    /// The `k` nearest nodes at `min_scale_index`, as `(distance, address)`, without reading anything below it.
+    pub fn knn_to_scale(
+        &self,
+        point: &PyArray1<f32>,
+        k: usize,
+        min_scale_index: i32,
+    ) -> PyResult<Vec<(f32, (i32, usize))>> {
+        let reader = self.writer.as_ref().unwrap().reader();
+        reader
+            .knn_to_scale(&point.readonly().as_slice().unwrap(), k, min_scale_index)
+            .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))
+    }
+
+    /// Checks the covering, separation and nesting invariants across the whole tree. Returns a description of
+    /// each violation, the list is empty if the tree is a legal cover tree.
+    pub fn validate(&self) -> PyResult<Vec<String>> {
+        let reader = self.writer.as_ref().unwrap().reader();
+        let report = reader
+            .validate()
+            .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))?;
+        Ok(report
+            .violations
+            .iter()
+            .map(|v| format!("{:?}", v))
+            .collect())
+    }
+
     pub fn known_path(&self, point_index: usize) -> Vec<(f32, (i32, usize))> {
         let reader = self.writer.as_ref().unwrap().reader();
         reader.known_path(point_index).unwrap()
     }
 
+    pub fn assignments(&self, scale_index: i32) -> Py<PyArray1<i64>> {
+        let reader = self.writer.as_ref().unwrap().reader();
+        let assignments = Array1::from(reader.assignments(scale_index));
+        let gil = pyo3::Python::acquire_gil();
+        let py = gil.python();
+        assignments.into_pyarray(py).to_owned()
+    }
+
+    pub fn compare_layer(&self, scale_index: i32, labels: &PyArray1<i64>) -> PyResult<PyObject> {
+        let reader = self.writer.as_ref().unwrap().reader();
+        let labels = labels.readonly();
+        let scores = goko::cluster_comparison::compare_layer(
+            &reader,
+            scale_index,
+            labels.as_slice().unwrap(),
+        )
+        .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))?;
+        let gil = pyo3::Python::acquire_gil();
+        let py = gil.python();
+        let dict = PyDict::new(py);
+        dict.set_item("count", scores.count)?;
+        dict.set_item("adjusted_rand_index", scores.adjusted_rand_index)?;
+        dict.set_item(
+            "normalized_mutual_information",
+            scores.normalized_mutual_information,
+        )?;
+        dict.set_item("homogeneity", scores.homogeneity)?;
+        dict.set_item("completeness", scores.completeness)?;
+        Ok(dict.into())
+    }
+
+    pub fn layer_quality(&self, scale_index: i32) -> PyResult<PyObject> {
+        let reader = self.writer.as_ref().unwrap().reader();
+        let quality = goko::cluster_quality::layer_quality(&reader, scale_index)
+            .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))?;
+        let gil = pyo3::Python::acquire_gil();
+        let py = gil.python();
+        let dict = PyDict::new(py);
+        dict.set_item("scale_index", quality.scale_index)?;
+        dict.set_item("cluster_count", quality.cluster_count)?;
+        dict.set_item("silhouette", quality.silhouette)?;
+        dict.set_item("davies_bouldin", quality.davies_bouldin)?;
+        Ok(dict.into())
+    }
+
+    /// Turns the latency histograms of `knn`, `path` (a dry insert), tracker pushes and inserts on
+    /// or off. They start off, and a new fit starts them over.
+    pub fn enable_latency_stats(&self, enabled: bool) {
+        let reader = self.writer.as_ref().unwrap().reader();
+        if enabled {
+            reader.latency_stats().enable();
+        } else {
+            reader.latency_stats().disable();
+        }
+    }
+
+    /// The latency percentiles of each operation in nanoseconds, keyed by the operation's name.
+    pub fn latency_stats(&self) -> PyResult<PyObject> {
+        let reader = self.writer.as_ref().unwrap().reader();
+        let gil = pyo3::Python::acquire_gil();
+        let py = gil.python();
+        let stats = PyDict::new(py);
+        for (op, summary) in reader.latency_stats().summaries() {
+            let dict = PyDict::new(py);
+            dict.set_item("count", summary.count)?;
+            dict.set_item("mean", summary.mean)?;
+            dict.set_item("p50", summary.p50)?;
+            dict.set_item("p99", summary.p99)?;
+            dict.set_item("p999", summary.p999)?;
+            dict.set_item("max", summary.max)?;
+            stats.set_item(op.name(), dict)?;
+        }
+        Ok(stats.into())
+    }
+
+    /// Clears the latency histograms.
+    pub fn reset_latency_stats(&self) {
+        let reader = self.writer.as_ref().unwrap().reader();
+        reader.latency_stats().reset();
+    }
+
+    /// The state of the tree for readiness checks: `generation`, the number of times the tree was
+    /// published, `point_count`, `memory_estimate` in bytes, `last_publish_ms` since the unix epoch,
+    /// `ready`, and `stale_plugins`, the type names of the plugins that weren't rebuilt since the
+    /// last edit.
+    pub fn health(&self) -> PyResult<PyObject> {
+        let reader = self.writer.as_ref().unwrap().reader();
+        let health = reader.health();
+        let gil = pyo3::Python::acquire_gil();
+        let py = gil.python();
+        let dict = PyDict::new(py);
+        dict.set_item("generation", health.generation)?;
+        dict.set_item("point_count", health.point_count)?;
+        dict.set_item("memory_estimate", health.memory_estimate)?;
+        dict.set_item("last_publish_ms", health.last_publish_ms)?;
+        dict.set_item("ready", health.is_ready())?;
+        dict.set_item(
+            "stale_plugins",
+            health.stale_plugins().collect::<Vec<&str>>(),
+        )?;
+        Ok(dict.into())
+    }
+
+    /// Where the time of the last fit went, in seconds: `nesting`, `insertion` and `finishing`
+    /// for the build itself, `summaries`, and `plugins` keyed by the plugin's type name. Also has
+    /// the `nodes` created, the `nodes_per_second` of the build and the `peak_memory` estimate of
+    /// the tree in bytes. `fit` returns this too.
+    pub fn build_report(&self) -> PyResult<PyObject> {
+        let report = self.writer.as_ref().unwrap().build_report();
+        let gil = pyo3::Python::acquire_gil();
+        let py = gil.python();
+        let dict = PyDict::new(py);
+        dict.set_item("nesting", report.nesting.as_secs_f64())?;
+        dict.set_item("insertion", report.insertion.as_secs_f64())?;
+        dict.set_item("finishing", report.finishing.as_secs_f64())?;
+        dict.set_item("summaries", report.summaries.as_secs_f64())?;
+        let plugins = PyDict::new(py);
+        for plugin in &report.plugins {
+            plugins.set_item(&plugin.name, plugin.time.as_secs_f64())?;
+        }
+        dict.set_item("plugins", plugins)?;
+        dict.set_item("build_time", report.build_time().as_secs_f64())?;
+        dict.set_item("total_time", report.total_time().as_secs_f64())?;
+        dict.set_item("nodes", report.nodes)?;
+        dict.set_item("nodes_per_second", report.nodes_per_second())?;
+        dict.set_item("peak_memory", report.peak_memory)?;
+        Ok(dict.into())
+    }
+
+    /// Writes the shape of the tree to `path` for a bug report: the parameters, the build report,
+    /// and each node's address, parent, children, radius and counts. No vectors, labels or
+    /// metadata are written, points only show up as indexes.
+    pub fn debug_bundle(&self, path: String) -> PyResult<()> {
+        let writer = self.writer.as_ref().unwrap();
+        DebugBundle::new(writer)
+            .save(path)
+            .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))
+    }
+
+    /// Loads a bundle written by `debug_bundle` and returns the problems found in the structure it
+    /// describes, empty for a sound tree.
+    #[staticmethod]
+    pub fn debug_bundle_issues(path: String) -> PyResult<Vec<String>> {
+        DebugBundle::load(path)
+            .map(|bundle| bundle.structural_issues())
+            .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))
+    }
+
+    /// Groups points within `radius` of each other. `keep` is `"first"`, the default, or `"centroid"`. Returns the
+    /// point kept for each group, and the members of every group that has more than one point, keyed by the point kept.
+    pub fn dedupe(&self, radius: f32, keep: Option<&str>) -> PyResult<(Vec<usize>, PyObject)> {
+        let keep = match keep.unwrap_or("first") {
+            "first" => goko::utils::DedupeKeep::First,
+            "centroid" => goko::utils::DedupeKeep::Centroid,
+            other => {
+                return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                    "keep has to be \"first\" or \"centroid\", not {:?}",
+                    other
+                )))
+            }
+        };
+        let reader = self.writer.as_ref().unwrap().reader();
+        let groups = goko::utils::dedupe(&reader, radius, keep)
+            .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))?;
+        let gil = pyo3::Python::acquire_gil();
+        let py = gil.python();
+        let duplicates = PyDict::new(py);
+        for group in groups.iter().filter(|g| g.members.len() > 1) {
+            duplicates.set_item(group.canonical, group.members.clone())?;
+        }
+        let canonical = groups.iter().map(|g| g.canonical).collect();
+        Ok((canonical, duplicates.into()))
+    }
+
+    /// Flattens the tree into a table of nodes and a table of parent to child edges. Each table is
+    /// a dict of equal length arrays, so it can be passed straight to `pandas.DataFrame`. The root's
+    /// parent columns are -1.
+    pub fn to_tables(&self) -> PyResult<(PyObject, PyObject)> {
+        let reader = self.writer.as_ref().unwrap().reader();
+        let node_count = reader.node_count();
+        let mut scale_indexes = Vec::with_capacity(node_count);
+        let mut center_indexes = Vec::with_capacity(node_count);
+        let mut parent_scale_indexes = Vec::with_capacity(node_count);
+        let mut parent_center_indexes = Vec::with_capacity(node_count);
+        let mut radii = Vec::with_capacity(node_count);
+        let mut coverage_counts = Vec::with_capacity(node_count);
+        let mut singleton_counts = Vec::with_capacity(node_count);
+        let mut children_counts = Vec::with_capacity(node_count);
+
+        let mut edge_parent_scale_indexes = Vec::with_capacity(node_count);
+        let mut edge_parent_center_indexes = Vec::with_capacity(node_count);
+        let mut edge_child_scale_indexes = Vec::with_capacity(node_count);
+        let mut edge_child_center_indexes = Vec::with_capacity(node_count);
+        let mut edge_nested = Vec::with_capacity(node_count);
+
+        for (scale_index, layer) in reader.layers() {
+            layer.for_each_node(|center_index, n| {
+                let (parent_scale_index, parent_center_index) = n
+                    .parent_address()
+                    .map(|(si, pi)| (si, pi as i64))
+                    .unwrap_or((-1, -1));
+                scale_indexes.push(scale_index);
+                center_indexes.push(*center_index as i64);
+                parent_scale_indexes.push(parent_scale_index);
+                parent_center_indexes.push(parent_center_index);
+                radii.push(n.radius());
+                coverage_counts.push(n.coverage_count() as i64);
+                singleton_counts.push(n.singletons_len() as i64);
+                children_counts.push(n.children_len() as i64);
+                if let Some((nested_scale, children)) = n.children() {
+                    let nested = (nested_scale, *center_index);
+                    for (child_scale, child_center) in std::iter::once(&nested).chain(children) {
+                        edge_parent_scale_indexes.push(scale_index);
+                        edge_parent_center_indexes.push(*center_index as i64);
+                        edge_child_scale_indexes.push(*child_scale);
+                        edge_child_center_indexes.push(*child_center as i64);
+                        edge_nested.push(*child_center == *center_index);
+                    }
+                }
+            });
+        }
+
+        let gil = pyo3::Python::acquire_gil();
+        let py = gil.python();
+        let nodes = PyDict::new(py);
+        nodes.set_item("scale_index", scale_indexes.into_pyarray(py))?;
+        nodes.set_item("center_index", center_indexes.into_pyarray(py))?;
+        nodes.set_item("parent_scale_index", parent_scale_indexes.into_pyarray(py))?;
+        nodes.set_item(
+            "parent_center_index",
+            parent_center_indexes.into_pyarray(py),
+        )?;
+        nodes.set_item("radius", radii.into_pyarray(py))?;
+        nodes.set_item("coverage_count", coverage_counts.into_pyarray(py))?;
+        nodes.set_item("singleton_count", singleton_counts.into_pyarray(py))?;
+        nodes.set_item("children_count", children_counts.into_pyarray(py))?;
+
+        let edges = PyDict::new(py);
+        edges.set_item(
+            "parent_scale_index",
+            edge_parent_scale_indexes.into_pyarray(py),
+        )?;
+        edges.set_item(
+            "parent_center_index",
+            edge_parent_center_indexes.into_pyarray(py),
+        )?;
+        edges.set_item(
+            "child_scale_index",
+            edge_child_scale_indexes.into_pyarray(py),
+        )?;
+        edges.set_item(
+            "child_center_index",
+            edge_child_center_indexes.into_pyarray(py),
+        )?;
+        edges.set_item("nested", edge_nested.into_pyarray(py))?;
+        Ok((nodes.into(), edges.into()))
+    }
+
     pub fn index_depths(&self, point_indexes: Vec<usize>, tau: Option<f32>) -> Vec<(usize, usize)> {
         let reader = self.writer.as_ref().unwrap().reader();
         let bulk = BulkInterface::new(reader);
@@ -293,6 +1370,42 @@ impl CoverTree {
         reader.path(&point.readonly().as_slice().unwrap()).unwrap()
     }
 
+    /// `path` that stops at `min_scale_index` instead of going down to a leaf.
+    pub fn path_to_scale(
+        &self,
+        point: &PyArray1<f32>,
+        min_scale_index: i32,
+    ) -> PyResult<Vec<(f32, (i32, usize))>> {
+        let reader = self.writer.as_ref().unwrap().reader();
+        reader
+            .path_to_scale(&point.readonly().as_slice().unwrap(), min_scale_index)
+            .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))
+    }
+
+    /// `path` with the details of each node, a dict per node with the `distance`, `address`,
+    /// `cover_radius`, `radius`, `coverage_count` and if the point was `inside` the covering radius.
+    pub fn path_detailed(&self, point: &PyArray1<f32>) -> PyResult<Vec<PyObject>> {
+        let reader = self.writer.as_ref().unwrap().reader();
+        let steps = reader
+            .path_detailed(&point.readonly().as_slice().unwrap())
+            .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))?;
+        let gil = pyo3::Python::acquire_gil();
+        let py = gil.python();
+        steps
+            .iter()
+            .map(|step| {
+                let dict = PyDict::new(py);
+                dict.set_item("distance", step.distance)?;
+                dict.set_item("address", step.address)?;
+                dict.set_item("cover_radius", step.cover_radius)?;
+                dict.set_item("radius", step.radius)?;
+                dict.set_item("coverage_count", step.coverage_count)?;
+                dict.set_item("inside", step.inside)?;
+                Ok(dict.into())
+            })
+            .collect()
+    }
+
     pub fn sample(&self) -> PyResult<(Py<PyArray1<f32>>, Option<PyObject>)> {
         let reader = self.writer.as_ref().unwrap().reader();
         let mut rng = SmallRng::from_entropy();
@@ -321,6 +1434,10 @@ impl CoverTree {
                 dict.set_item("errors", s.errors)?;
                 dict.set_item("nones", s.nones)?;
                 dict.set_item("items", s.summary.items.to_vec())?;
+                dict.set_item(
+                    "names",
+                    label_names(&reader.parameters().point_cloud, &s.summary),
+                )?;
                 Some(dict.into())
             }
             None => None,
@@ -328,25 +1445,82 @@ impl CoverTree {
         Ok((vec, summ))
     }
 
+    /// The addresses of the nodes whose gaussian is fit to points that fail any of the normality
+    /// checks, an absolute skewness or excess kurtosis above the limits in some coordinate, or
+    /// more than `max_outside_3_sigma` of a coordinate's values over 3 standard deviations out.
+    /// Nodes with fewer than `min_count` points are skipped, their statistics are too noisy.
+    pub fn gaussian_misfits(
+        &self,
+        max_abs_skewness: f32,
+        max_abs_excess_kurtosis: f32,
+        max_outside_3_sigma: f32,
+        min_count: Option<usize>,
+    ) -> Vec<(i32, usize)> {
+        let reader = self.writer.as_ref().unwrap().reader();
+        let min_count = min_count.unwrap_or(8);
+        let mut misfits = Vec::new();
+        for (_, layer) in reader.layers() {
+            layer.for_each_node(|_, n| {
+                let misfit = n.get_plugin_and::<GaussianFit, _, _>(|p| {
+                    p.count >= min_count
+                        && (p.max_abs_skewness() > max_abs_skewness
+                            || p.max_abs_excess_kurtosis() > max_abs_excess_kurtosis
+                            || p.max_outside_3_sigma() > max_outside_3_sigma)
+                });
+                if misfit == Some(true) {
+                    misfits.push(n.address());
+                }
+            });
+        }
+        misfits
+    }
+
+    /// A tracker that compares a window of the most recent points to the training set. With a
+    /// `partition` the prior only comes from that partition's points.
     pub fn kl_div_dirichlet(
         &self,
         size: u64,
         prior_weight: Option<f64>,
         observation_weight: Option<f64>,
-    ) -> PyBayesCategoricalTracker {
+        partition: Option<String>,
+    ) -> PyResult<PyBayesCategoricalTracker> {
         let writer = self.writer.as_ref().unwrap();
 
-        PyBayesCategoricalTracker {
-            hkl: BayesCategoricalTracker::new(
-                prior_weight.unwrap_or(1.0),
-                observation_weight.unwrap_or(1.0),
-                size as usize,
-                writer.reader(),
-            ),
+        let mut hkl = BayesCategoricalTracker::new(size as usize, writer.reader());
+        hkl.set_prior_weight(prior_weight.unwrap_or(1.0));
+        hkl.set_observation_weight(observation_weight.unwrap_or(1.0));
+        if let Some(partition) = partition {
+            hkl.set_partition(&partition)
+                .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))?;
+        }
+        Ok(PyBayesCategoricalTracker {
+            hkl,
             tree: writer.reader(),
+            pending: PendingPaths::default(),
+        })
+    }
+
+    /// A tracker that compares a test window of the most recent points to the reference window
+    /// of points before it, rather than to the training set.
+    pub fn two_window_tracker(
+        &self,
+        reference_size: usize,
+        test_size: usize,
+        prior_weight: Option<f64>,
+    ) -> PyTwoWindowTracker {
+        let writer = self.writer.as_ref().unwrap();
+
+        let mut hkl = TwoWindowTracker::new(reference_size, test_size, writer.reader());
+        hkl.set_prior_weight(prior_weight.unwrap_or(1.0));
+        PyTwoWindowTracker {
+            hkl,
+            tree: writer.reader(),
+            pending: PendingPaths::default(),
         }
     }
 
+    /// Trains a baseline for `kl_div_dirichlet` trackers. With a `partition` the sequences are
+    /// sampled from that partition's points and compared to its prior.
     pub fn kl_div_dirichlet_baseline(
         &self,
         sequence_len: usize,
@@ -354,7 +1528,8 @@ impl CoverTree {
         sample_rate: usize,
         prior_weight: Option<f64>,
         observation_weight: Option<f64>,
-    ) -> PyKLDivergenceBaseline {
+        partition: Option<String>,
+    ) -> PyResult<PyKLDivergenceBaseline> {
         let reader = self.writer.as_ref().unwrap().reader();
         let mut trainer = DirichletBaseline::default();
         trainer.set_prior_weight(prior_weight.unwrap_or(1.0));
@@ -362,7 +1537,156 @@ impl CoverTree {
         trainer.set_sequence_len(sequence_len);
         trainer.set_num_sequences(num_sequences);
         trainer.set_sample_rate(sample_rate);
-        let baseline = trainer.train(reader).unwrap();
-        PyKLDivergenceBaseline { baseline }
+        trainer.set_partition(partition);
+        let baseline = trainer
+            .train(reader)
+            .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))?;
+        Ok(PyKLDivergenceBaseline { baseline })
+    }
+}
+
+impl CoverTree {
+    fn point_cloud_from_parts(
+        &self,
+        data: Vec<f32>,
+        data_dim: usize,
+        labels: CategoricalLabels,
+        metadata: MetadataMap<String>,
+        dim_names: Option<Vec<String>>,
+        partitions: Option<Vec<Option<String>>>,
+    ) -> PyResult<Arc<PyPointCloud>> {
+        let metric = PyMetric::from_name(&self.metric, &self.metric_weights, self.accumulation)?;
+        metric.check_dim(data_dim)?;
+        let mut data = DataRam::with_metric(data, data_dim, metric).unwrap();
+        if let Some(names) = dim_names {
+            data.set_dim_names(names)
+                .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))?;
+        }
+        if let Some(policy) = self.nan_policy {
+            data.handle_missing_values(policy)
+                .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))?;
+        }
+        if let Some(kind) = self.normalization {
+            data.normalize(kind)
+                .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))?;
+        }
+        if self.collapse_duplicates {
+            data.collapse_duplicates_by(|pi| {
+                let partition = partitions
+                    .as_ref()
+                    .and_then(|keys| keys.get(pi).cloned().flatten());
+                labels.label(pi).ok().map(|l| (l.copied(), partition))
+            });
+        }
+        let partitions = match partitions {
+            Some(keys) => PartitionMap::from_keys(&keys),
+            None => PartitionMap::unpartitioned(data.len()),
+        };
+        let data = SimplePartitionedCloud::new(SimpleMetaCloud::new(data, metadata), partitions)
+            .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))?;
+        Ok(Arc::new(SimpleLabeledCloud::new(data, labels)))
+    }
+
+    fn build_writer(&mut self, point_cloud: Arc<PyPointCloud>) -> PyResult<()> {
+        let callback_error: Arc<Mutex<Option<PyErr>>> = Arc::new(Mutex::new(None));
+        let writer = match &self.progress_callback {
+            Some(callback) => {
+                let mut builder = self.builder.clone();
+                let token = CancelToken::new();
+                let callback = callback.clone();
+                let callback_token = token.clone();
+                let callback_error = Arc::clone(&callback_error);
+                builder
+                    .set_cancel_token(token)
+                    .set_progress_callback(move |progress| {
+                        let gil = pyo3::Python::acquire_gil();
+                        let py = gil.python();
+                        let result = progress_dict(py, progress)
+                            .and_then(|dict| callback.call1(py, (dict,)))
+                            .and_then(|result| {
+                                py.check_signals()?;
+                                Ok(result)
+                            });
+                        match result {
+                            Ok(result) => {
+                                if let Ok(false) = result.extract::<bool>(py) {
+                                    callback_token.cancel();
+                                }
+                            }
+                            Err(e) => {
+                                *callback_error.lock().unwrap() = Some(e);
+                                callback_token.cancel();
+                            }
+                        }
+                    });
+                builder.build(point_cloud)
+            }
+            None => self.builder.build(point_cloud),
+        };
+        if let Some(e) = callback_error.lock().unwrap().take() {
+            return Err(e);
+        }
+        self.writer =
+            Some(writer.map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))?);
+        let writer = self.writer.as_mut().unwrap();
+        writer.generate_summaries();
+        writer
+            .add_persistent_plugin(
+                GokoDiagGaussian::singletons().with_estimator(self.gaussian_estimator),
+            )
+            .unwrap();
+        writer
+            .add_persistent_plugin(GokoGaussianFit::singletons())
+            .unwrap();
+        writer.add_persistent_plugin(GokoDirichlet {}).unwrap();
+        writer
+            .add_persistent_plugin(PartitionPlugin::default())
+            .unwrap();
+        Ok(())
+    }
+
+    fn clear_partial_fit(&mut self) {
+        self.partial_dim = None;
+    }
+}
+
+/// The progress of a build as a dict, for the progress callback.
+fn progress_dict(py: Python, progress: &BuildProgress) -> PyResult<PyObject> {
+    let dict = PyDict::new(py);
+    dict.set_item("inserted_nodes", progress.inserted_nodes)?;
+    dict.set_item("total_nodes", progress.total_nodes)?;
+    dict.set_item("indexed_points", progress.indexed_points)?;
+    dict.set_item("point_count", progress.point_count)?;
+    dict.set_item("layer_counts", progress.layer_counts.clone())?;
+    dict.set_item("elapsed", progress.elapsed.as_secs_f64())?;
+    Ok(dict.into())
+}
+
+/// Reads labels passed from python, an integer array or a list of optional strings. Points get
+/// the label 0 if there are no labels.
+fn categorical_labels(labels: Option<&PyAny>, len: usize) -> PyResult<CategoricalLabels> {
+    let labels = match labels {
+        None => SmallIntLabels::new(vec![0; len], None).into(),
+        Some(labels) => match labels.extract::<&PyArray1<i64>>() {
+            Ok(labels) => {
+                SmallIntLabels::new(Vec::from(labels.readonly().as_slice().unwrap()), None).into()
+            }
+            Err(_) => {
+                let names: Vec<Option<String>> = labels.extract().map_err(|_| {
+                    pyo3::exceptions::PyValueError::new_err(
+                        "labels must be an integer array or a list of strings",
+                    )
+                })?;
+                CategoricalLabels::from_names(&names)
+            }
+        },
+    };
+    if labels.len() != len {
+        return Err(pyo3::exceptions::PyValueError::new_err(format!(
+            "got {} labels for {} points",
+            labels.len(),
+            len
+        )));
     }
+    Ok(labels)
 }
@@ -0,0 +1,48 @@
+use pointcloud::*;
+
+use goko::PartitionType;
+use serde::{Deserialize, Serialize};
+use crate::core::*;
+use goko::errors::GokoError;
+
+/// Send a `GET` request to `/` for this
+#[derive(Deserialize, Serialize, Clone, Copy)]
+pub struct ParametersRequest;
+
+/// Response to a parameters request
+#[derive(Deserialize, Serialize)]
+pub struct ParametersResponse {
+    /// See paper or main description, governs the number of children of each node. Higher is more.
+    pub scale_base: f32,
+    /// If a node covers less than or equal to this number of points, it becomes a leaf.
+    pub leaf_cutoff: usize,
+use pointcloud::*;
+
+use goko::health::TreeHealth;
+use serde::{Deserialize, Serialize};
+use crate::core::*;
+use goko::errors::GokoError;
+
+/// Send a `GET` request to `/healthz` for this
+#[derive(Deserialize, Serialize, Clone, Copy)]
+pub struct HealthRequest;
+
+/// Response to a health request. Over HTTP this comes back with a 503 status when the tree isn't ready.
+#[derive(Deserialize, Serialize)]
+pub struct HealthResponse {
+    /// If the tree has been published, covers some points and has no stale plugins
+    pub ready: bool,
+    /// See [`goko::health::TreeHealth`]
+    #[serde(flatten)]
+    pub health: TreeHealth,
+}
+
+impl HealthRequest {
+    pub fn process<D: PointCloud, T: Send + 'static>(self, reader: &mut CoreReader<D, T>) -> Result<HealthResponse, GokoError> {
+        let health = reader.tree.health();
+        Ok(HealthResponse {
+            ready: health.is_ready(),
+            health,
+        })
+    }
+}
@@ -2,16 +2,21 @@ use std::ops::Deref;
 use pointcloud::{PointCloud, SummaryCounter, Summary};
 use crate::errors::InternalServiceError;
 use crate::core::CoreReader;
+use crate::core::query_log::{QueryKind, QueryRecord};
+use log::warn;
+use std::time::Instant;
 
 use serde::{Deserialize, Serialize};
 //use std::convert::Infallible;
 
 mod parameters;
+mod health;
 mod path;
 mod knn;
 mod tracker;
 
 pub use parameters::*;
+pub use health::*;
 pub use path::*;
 pub use tracker::*;
 pub use knn::*;
@@ -23,6 +28,11 @@ pub enum GokoRequest<T> {
     /// 
     /// Response: [`ParametersResponse`]
     Parameters(ParametersRequest),
+    /// With the HTTP server, send a `GET` request to `/healthz` for this. Gate traffic on it, the status is 503 until
+    /// the tree is ready.
+    /// 
+    /// Response: [`HealthResponse`]
+    Health(HealthRequest),
     /// With the HTTP server, send a `GET` request to `/knn?k=5` with a set of features in the body for this query, 
     /// will return with the response with the nearest 5 routing nbrs. 
     /// 
@@ -85,6 +95,7 @@ pub enum TrackingRequestChoice<T> {
 #[derive(Deserialize, Serialize)]
 pub enum GokoResponse<L: Summary> {
     Parameters(ParametersResponse),
+    Health(HealthResponse),
     Knn(KnnResponse),
     RoutingKnn(RoutingKnnResponse),
     Path(PathResponse<L>),
@@ -122,20 +133,47 @@ pub struct NodeDistance<L: Summary + Clone> {
 }
 
 impl<D: PointCloud, P> CoreReader<D, P>
-where P: Deref<Target = D::Point> + Send + Sync + 'static {
+where P: Deref<Target = D::Point> + Serialize + Send + Sync + 'static {
+    fn log_query(&self, kind: QueryKind, point: &P, start: Instant, ok: bool) {
+        if let Some(query_log) = &self.query_log {
+            let record = QueryRecord::new(kind, point, start, ok);
+            if let Err(e) = query_log.lock().unwrap().write(&record) {
+                warn!("Unable to write to the query log: {}", e);
+            }
+        }
+    }
+
     pub async fn process(&mut self, request: GokoRequest<P>) -> Result<GokoResponse<D::LabelSummary>,InternalServiceError> {
+        // Held until the response is ready, so a retired catalog version isn't counted as drained under this query
+        let _lease = self.lease_tree();
         match request {
             GokoRequest::Parameters(p) => p.process(self).map(|p| GokoResponse::Parameters(p)).map_err(|e| e.into()),
-            GokoRequest::Knn(p) => p.process(self).map(|p| GokoResponse::Knn(p)).map_err(|e| e.into()),
-            GokoRequest::RoutingKnn(p) => p.process(self).map(|p| GokoResponse::RoutingKnn(p)).map_err(|e| e.into()),
-            GokoRequest::Path(p) => p.process(self).map(|p| GokoResponse::Path(p)).map_err(|e| e.into()),
+            GokoRequest::Health(p) => p.process(self).map(|p| GokoResponse::Health(p)).map_err(|e| e.into()),
+            GokoRequest::Knn(p) => {
+                let start = Instant::now();
+                let resp = p.process(self);
+                self.log_query(QueryKind::Knn { k: p.k }, &p.point, start, resp.is_ok());
+                resp.map(|p| GokoResponse::Knn(p)).map_err(|e| e.into())
+            },
+            GokoRequest::RoutingKnn(p) => {
+                let start = Instant::now();
+                let resp = p.process(self);
+                self.log_query(QueryKind::RoutingKnn { k: p.k }, &p.point, start, resp.is_ok());
+                resp.map(|p| GokoResponse::RoutingKnn(p)).map_err(|e| e.into())
+            },
+            GokoRequest::Path(p) => {
+                let start = Instant::now();
+                let resp = p.process(self);
+                self.log_query(QueryKind::Path, &p.point, start, resp.is_ok());
+                resp.map(|p| GokoResponse::Path(p)).map_err(|e| e.into())
+            },
             GokoRequest::Unknown(response_string, status) => {
                 Ok(GokoResponse::Unknown(response_string, status))
             },
             GokoRequest::Tracking(p) => {
                 if let Some(tracker_name) = &p.tracker_name {
                     if let TrackingRequestChoice::AddTracker(_) = p.request {
-                        self.trackers.write().await.entry(tracker_name.clone()).or_insert_with(|| TrackerWorker::operator(self.tree.clone()));
+                        self.trackers.write().await.entry(tracker_name.clone()).or_insert_with(|| TrackerWorker::operator(self.tracker_tree.clone()));
                     }
                     match self.trackers.read().await.get(tracker_name) {
                         Some(t) => t.message(p).await.map(|r| GokoResponse::Tracking(r)),
@@ -20,7 +20,7 @@ pub struct PathResponse<L: Summary> {
 }
 
 impl<T> PathRequest<T> {
-    pub fn process<D>(self, reader: &mut CoreReader<D, T>) -> Result<PathResponse<D::LabelSummary>, GokoError> 
+    pub fn process<D>(&self, reader: &mut CoreReader<D, T>) -> Result<PathResponse<D::LabelSummary>, GokoError> 
     where 
         D: PointCloud, 
         T: Deref<Target = D::Point> + Send + Sync,
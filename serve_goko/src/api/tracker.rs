@@ -1,6 +1,6 @@
 use pointcloud::*;
 use goko::{NodeAddress, CoverTreeReader};
-use goko::plugins::discrete::tracker::BayesCategoricalTracker;
+use goko::plugins::discrete::tracker::{BayesCategoricalTracker, EventId, ScaleKLDivergenceStats};
 use crate::core::internal_service::*;
 use goko::errors::GokoError;
 use std::ops::Deref;
@@ -13,11 +13,15 @@ use super::{TrackingRequest, TrackingRequestChoice, TrackingResponse};
 #[derive(Deserialize, Serialize)]
 pub struct TrackPointRequest<T> {
     pub point: T,
+    #[serde(default)]
+    pub event_id: Option<EventId>,
 }
 
 #[derive(Deserialize, Serialize)]
 pub struct TrackPathRequest {
     pub path:  Vec<(f32, NodeAddress)>,
+    #[serde(default)]
+    pub event_id: Option<EventId>,
 }
 
 #[derive(Deserialize, Serialize)]
@@ -48,6 +52,9 @@ pub struct CurrentStatsResponse {
     pub moment1_nz: f64,
     pub moment2_nz: f64,
     pub sequence_len: usize,
+    pub first_event_id: Option<EventId>,
+    pub last_event_id: Option<EventId>,
+    pub per_scale: Vec<ScaleKLDivergenceStats>,
 }
 
 
@@ -80,7 +87,7 @@ impl<D: PointCloud, T: Deref<Target = D::Point> + Send + Sync> InternalService<T
             TrackPoint(req) => {
                 let path = self.reader.path(&req.point)?;
                 for tracker in self.trackers.values_mut() {
-                    tracker.add_path(path.clone());
+                    tracker.add_path_with_id(path.clone(), req.event_id);
                 }
 
                 Ok(TrackingResponse::TrackPath(TrackPathResponse {
@@ -89,7 +96,7 @@ impl<D: PointCloud, T: Deref<Target = D::Point> + Send + Sync> InternalService<T
             }
             TrackPath(req) => {
                 for tracker in self.trackers.values_mut() {
-                    tracker.add_path(req.path.clone());
+                    tracker.add_path_with_id(req.path.clone(), req.event_id);
                 }
                 Ok(TrackingResponse::TrackPath(TrackPathResponse {
                     success: true,
@@ -119,6 +126,9 @@ impl<D: PointCloud, T: Deref<Target = D::Point> + Send + Sync> InternalService<T
                         moment1_nz: stats.moment1_nz,
                         moment2_nz: stats.moment2_nz,
                         sequence_len: stats.sequence_len,
+                        first_event_id: stats.first_event_id,
+                        last_event_id: stats.last_event_id,
+                        per_scale: stats.per_scale,
                     }))
                 } else {
                     Ok(TrackingResponse::Unknown(request.tracker_name.clone(),Some(req.window_size)))
@@ -0,0 +1,286 @@
+//! # Tree Catalog
+//!
+//! Holds named trees and swaps in new versions of them while the server is running, for indexes that are retrained
+//! periodically. A new version is loaded on a blocking thread, validated, and then replaces the old version all at
+//! once. Queries lease the version that's current when they start and keep it until they finish, so the old version
+//! is only dropped once the queries against it have drained.
+//!
+//! ```rust,ignore
+//! let catalog = Arc::new(TreeCatalog::new());
+//! catalog.insert("mnist", first_tree);
+//! let core = CoreWriter::from_catalog(Arc::clone(&catalog), "mnist")?;
+//! // Later, after retraining
+//! catalog.load_in_background("mnist", move || CoverTreeWriter::load_file(&path, point_cloud, &plugins), check_ready)?;
+//! ```
+
+use goko::errors::GokoResult;
+use goko::{CoverTreeReader, CoverTreeWriter};
+use log::{info, warn};
+use pointcloud::PointCloud;
+
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+
+use crate::errors::CatalogError;
+
+/// The default validation, the tree has to pass [`goko::health::TreeHealth::is_ready`].
+pub fn check_ready<D: PointCloud>(reader: &CoverTreeReader<D>) -> Result<(), String> {
+    let health = reader.health();
+    if health.is_ready() {
+        return Ok(());
+    }
+    let stale: Vec<&str> = health.stale_plugins().collect();
+    Err(format!(
+        "tree isn't ready, generation {}, {} points, stale plugins: {:?}",
+        health.generation, health.point_count, stale
+    ))
+}
+
+/// One version of a named tree in a [`TreeCatalog`].
+pub struct TreeVersion<D: PointCloud> {
+    name: String,
+    version: u64,
+    writer: Mutex<CoverTreeWriter<D>>,
+    in_flight: AtomicUsize,
+    retired: AtomicBool,
+}
+
+impl<D: PointCloud> TreeVersion<D> {
+    /// The name the tree is served under.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The version number, unique across the catalog and increasing with each insert or swap.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// A reader for this version.
+    pub fn reader(&self) -> CoverTreeReader<D> {
+        self.writer.lock().unwrap().reader()
+    }
+
+    /// The number of queries holding a lease on this version.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+
+    /// If a newer version has replaced this one, or it was removed from the catalog.
+    pub fn is_retired(&self) -> bool {
+        self.retired.load(Ordering::SeqCst)
+    }
+
+    /// Waits until no query holds a lease on this version, or the timeout passes. Returns if it drained.
+    pub async fn drain(&self, timeout: Duration) -> bool {
+        let start = Instant::now();
+        while self.in_flight() > 0 {
+            if start.elapsed() >= timeout {
+                return false;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        true
+    }
+}
+
+/// A query's hold on a [`TreeVersion`], the version counts as in flight until this is dropped.
+pub struct TreeLease<D: PointCloud> {
+    version: Arc<TreeVersion<D>>,
+    reader: CoverTreeReader<D>,
+}
+
+impl<D: PointCloud> TreeLease<D> {
+    fn new(version: Arc<TreeVersion<D>>) -> TreeLease<D> {
+        version.in_flight.fetch_add(1, Ordering::SeqCst);
+        let reader = version.reader();
+        TreeLease { version, reader }
+    }
+
+    /// The reader for the leased version.
+    pub fn reader(&self) -> &CoverTreeReader<D> {
+        &self.reader
+    }
+
+    /// The leased version.
+    pub fn version(&self) -> &Arc<TreeVersion<D>> {
+        &self.version
+    }
+}
+
+impl<D: PointCloud> Drop for TreeLease<D> {
+    fn drop(&mut self) {
+        self.version.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Named trees, each of which can be replaced by a new version while it's being queried. See the
+/// [module docs](self).
+pub struct TreeCatalog<D: PointCloud> {
+    trees: RwLock<HashMap<String, Arc<TreeVersion<D>>>>,
+    loading: Mutex<HashSet<String>>,
+    next_version: AtomicU64,
+    drain_timeout: Duration,
+}
+
+impl<D: PointCloud> Default for TreeCatalog<D> {
+    fn default() -> Self {
+        TreeCatalog::new()
+    }
+}
+
+impl<D: PointCloud> TreeCatalog<D> {
+    /// An empty catalog that waits up to 30 seconds for retired versions to drain.
+    pub fn new() -> TreeCatalog<D> {
+        TreeCatalog {
+            trees: RwLock::new(HashMap::new()),
+            loading: Mutex::new(HashSet::new()),
+            next_version: AtomicU64::new(1),
+            drain_timeout: Duration::from_secs(30),
+        }
+    }
+
+    /// Sets how long a background load waits for the version it replaced to drain. Queries that are still running
+    /// after this keep the old version alive until they finish, it's only logged.
+    pub fn with_drain_timeout(mut self, drain_timeout: Duration) -> Self {
+        self.drain_timeout = drain_timeout;
+        self
+    }
+
+    /// The names of the trees in the catalog.
+    pub fn names(&self) -> Vec<String> {
+        self.trees.read().unwrap().keys().cloned().collect()
+    }
+
+    /// The current version of a tree.
+    pub fn current(&self, name: &str) -> Option<Arc<TreeVersion<D>>> {
+        self.trees.read().unwrap().get(name).cloned()
+    }
+
+    /// Leases the current version of a tree for a query.
+    pub fn lease(&self, name: &str) -> Option<TreeLease<D>> {
+        self.current(name).map(TreeLease::new)
+    }
+
+    /// If a new version of the tree is being loaded in the background.
+    pub fn is_loading(&self, name: &str) -> bool {
+        self.loading.lock().unwrap().contains(name)
+    }
+
+    /// Puts a tree in the catalog without validating it, returning the version it replaced.
+    pub fn insert(&self, name: &str, writer: CoverTreeWriter<D>) -> Option<Arc<TreeVersion<D>>> {
+        self.install(self.new_version(name, writer))
+    }
+
+    fn new_version(&self, name: &str, writer: CoverTreeWriter<D>) -> Arc<TreeVersion<D>> {
+        Arc::new(TreeVersion {
+            name: name.to_string(),
+            version: self.next_version.fetch_add(1, Ordering::SeqCst),
+            writer: Mutex::new(writer),
+            in_flight: AtomicUsize::new(0),
+            retired: AtomicBool::new(false),
+        })
+    }
+
+    fn install(&self, version: Arc<TreeVersion<D>>) -> Option<Arc<TreeVersion<D>>> {
+        let old = self
+            .trees
+            .write()
+            .unwrap()
+            .insert(version.name.clone(), version);
+        if let Some(old) = &old {
+            old.retired.store(true, Ordering::SeqCst);
+        }
+        old
+    }
+
+    /// Removes a tree from the catalog. Queries that already leased it finish against it.
+    pub fn remove(&self, name: &str) -> Option<Arc<TreeVersion<D>>> {
+        let old = self.trees.write().unwrap().remove(name);
+        if let Some(old) = &old {
+            old.retired.store(true, Ordering::SeqCst);
+        }
+        old
+    }
+
+    /// Validates a tree and, if it passes, swaps it in as the new version of `name`. Returns the version it
+    /// replaced, which queries may still be holding, see [`TreeVersion::drain`].
+    pub fn swap<V>(
+        &self,
+        name: &str,
+        writer: CoverTreeWriter<D>,
+        validate: V,
+    ) -> Result<Option<Arc<TreeVersion<D>>>, CatalogError>
+    where
+        V: FnOnce(&CoverTreeReader<D>) -> Result<(), String>,
+    {
+        validate(&writer.reader()).map_err(CatalogError::Invalid)?;
+        Ok(self.insert(name, writer))
+    }
+
+    /// Loads a new version of `name` on a blocking thread, validates it, swaps it in and then waits for the old
+    /// version to drain. The handle resolves to the new version number. The current version keeps serving if the
+    /// load or the validation fails. Only one load per name can run at a time.
+    pub fn load_in_background<L, V>(
+        self: &Arc<Self>,
+        name: &str,
+        load: L,
+        validate: V,
+    ) -> Result<JoinHandle<Result<u64, CatalogError>>, CatalogError>
+    where
+        L: FnOnce() -> GokoResult<CoverTreeWriter<D>> + Send + 'static,
+        V: FnOnce(&CoverTreeReader<D>) -> Result<(), String> + Send + 'static,
+    {
+        if !self.loading.lock().unwrap().insert(name.to_string()) {
+            return Err(CatalogError::AlreadyLoading(name.to_string()));
+        }
+        let catalog = Arc::clone(self);
+        let name = name.to_string();
+        Ok(tokio::spawn(async move {
+            let result = catalog.load_and_swap(&name, load, validate).await;
+            catalog.loading.lock().unwrap().remove(&name);
+            if let Err(e) = &result {
+                warn!("Unable to load a new version of {}: {}", name, e);
+            }
+            result
+        }))
+    }
+
+    async fn load_and_swap<L, V>(
+        &self,
+        name: &str,
+        load: L,
+        validate: V,
+    ) -> Result<u64, CatalogError>
+    where
+        L: FnOnce() -> GokoResult<CoverTreeWriter<D>> + Send + 'static,
+        V: FnOnce(&CoverTreeReader<D>) -> Result<(), String> + Send + 'static,
+    {
+        let writer = tokio::task::spawn_blocking(move || {
+            let writer = load()?;
+            validate(&writer.reader()).map_err(CatalogError::Invalid)?;
+            Ok::<_, CatalogError>(writer)
+        })
+        .await
+        .map_err(|e| CatalogError::Join(e.to_string()))??;
+        let new = self.new_version(name, writer);
+        let version = new.version();
+        let old = self.install(new);
+        info!("Swapped in version {} of {}", version, name);
+        if let Some(old) = old {
+            if !old.drain(self.drain_timeout).await {
+                warn!(
+                    "Version {} of {} still has {} queries in flight after {:?}",
+                    old.version(),
+                    name,
+                    old.in_flight(),
+                    self.drain_timeout
+                );
+            }
+        }
+        Ok(version)
+    }
+}
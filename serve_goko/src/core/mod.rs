@@ -4,16 +4,34 @@ use std::sync::Arc;
 use tokio::sync::RwLock;
 use std::collections::HashMap;
 use std::ops::Deref;
+use std::io;
 
 pub(crate) mod internal_service;
+pub mod query_log;
+pub mod catalog;
+use query_log::{QueryLogConfig, QueryLogWriter};
+use catalog::{TreeCatalog, TreeLease, TreeVersion};
+use crate::errors::CatalogError;
 use internal_service::InternalServiceOperator;
 use crate::api::{TrackerWorker, TrackingRequest, TrackingResponse};
 
 
+/// Where a core gets its tree, either one it owns or the current version of a tree in a catalog.
+pub(crate) enum TreeSource<D: PointCloud> {
+    Owned(CoverTreeWriter<D>),
+    Catalog {
+        catalog: Arc<TreeCatalog<D>>,
+        name: String,
+        // The trackers are built on this version, so it's kept alive as long as the core is.
+        pinned: Arc<TreeVersion<D>>,
+    },
+}
+
 pub struct CoreWriter<D: PointCloud, T: Send + 'static> {
-    pub(crate) tree: CoverTreeWriter<D>,
+    pub(crate) tree: TreeSource<D>,
     pub(crate) trackers: Arc<RwLock<HashMap<String,InternalServiceOperator<TrackingRequest<T>, TrackingResponse>>>>,
     pub(crate) main_tracker: Arc<InternalServiceOperator<TrackingRequest<T>, TrackingResponse>>,
+    pub(crate) query_log: Option<Arc<std::sync::Mutex<QueryLogWriter>>>,
 }
 
 impl<D: PointCloud, T: Deref<Target = D::Point> + Send + Sync> CoreWriter<D,T> {
@@ -23,15 +41,59 @@ impl<D: PointCloud, T: Deref<Target = D::Point> + Send + Sync> CoreWriter<D,T> {
         CoreWriter {
             trackers,
             main_tracker,
-            tree: writer,
+            tree: TreeSource::Owned(writer),
+            query_log: None,
+        }
+    }
+
+    /// Serves the tree `name` from a catalog. Each query runs against the version that's current when it arrives,
+    /// so new versions swapped into the catalog are picked up without restarting the server. The trackers, including
+    /// ones added later, stay on the version that was current when this was made.
+    pub fn from_catalog(catalog: Arc<TreeCatalog<D>>, name: &str) -> Result<Self, CatalogError> {
+        let pinned = catalog.current(name).ok_or_else(|| CatalogError::UnknownTree(name.to_string()))?;
+        let trackers = Arc::new(RwLock::new(HashMap::new()));
+        let main_tracker = Arc::new(TrackerWorker::operator(pinned.reader()));
+        Ok(CoreWriter {
+            trackers,
+            main_tracker,
+            tree: TreeSource::Catalog {
+                catalog,
+                name: name.to_string(),
+                pinned,
+            },
+            query_log: None,
+        })
+    }
+
+    /// Logs every knn, routing knn and path query the readers answer, see [`query_log`].
+    pub fn with_query_log(mut self, config: QueryLogConfig) -> io::Result<Self> {
+        let writer = QueryLogWriter::new(config)?;
+        self.query_log = Some(Arc::new(std::sync::Mutex::new(writer)));
+        Ok(self)
+    }
+
+    /// Flushes the query log, if there is one.
+    pub fn flush_query_log(&self) -> io::Result<()> {
+        match &self.query_log {
+            Some(log) => log.lock().unwrap().flush(),
+            None => Ok(()),
         }
     }
 
     pub fn reader(&self) -> CoreReader<D,T> {
-        let tree = self.tree.reader();
+        let (tree, tracker_tree, catalog) = match &self.tree {
+            TreeSource::Owned(writer) => (writer.reader(), writer.reader(), None),
+            TreeSource::Catalog { catalog, name, pinned } => {
+                let tree = catalog.current(name).unwrap_or_else(|| Arc::clone(pinned)).reader();
+                (tree, pinned.reader(), Some((Arc::clone(catalog), name.clone())))
+            }
+        };
         CoreReader {
             trackers: Arc::clone(&self.trackers),
             main_tracker: Arc::clone(&self.main_tracker),
+            query_log: self.query_log.clone(),
+            catalog,
+            tracker_tree,
             tree,
         }
     }
@@ -41,5 +103,18 @@ pub struct CoreReader<D: PointCloud, T: Send + 'static> {
     pub(crate) tree: CoverTreeReader<D>,
     pub(crate) trackers: Arc<RwLock<HashMap<String,InternalServiceOperator<TrackingRequest<T>, TrackingResponse>>>>,
     pub(crate) main_tracker: Arc<InternalServiceOperator<TrackingRequest<T>, TrackingResponse>>,
+    pub(crate) query_log: Option<Arc<std::sync::Mutex<QueryLogWriter>>>,
+    pub(crate) catalog: Option<(Arc<TreeCatalog<D>>, String)>,
+    pub(crate) tracker_tree: CoverTreeReader<D>,
 }
 
+impl<D: PointCloud, T: Send + 'static> CoreReader<D,T> {
+    /// Moves this reader to the current version of its catalog tree, and holds that version until the lease is
+    /// dropped. Readers that don't come from a catalog keep their tree.
+    pub(crate) fn lease_tree(&mut self) -> Option<TreeLease<D>> {
+        let (catalog, name) = self.catalog.as_ref()?;
+        let lease = catalog.lease(name)?;
+        self.tree = lease.reader().clone();
+        Some(lease)
+    }
+}
@@ -0,0 +1,274 @@
+//! # Query Log
+//!
+//! Records the queries a server answers, with their parameters and latencies, so the traffic can be replayed against
+//! a new tree build for regression benchmarking. The log is binary, each record is a little endian `u32` length
+//! followed by the record in message pack. The log rotates like logrotate, the current file is `PREFIX.log`, the
+//! previous one is `PREFIX.log.1`, and so on up to `PREFIX.log.MAX_FILES`, after which the oldest is dropped.
+
+use goko::errors::GokoError;
+use goko::CoverTreeReader;
+use pointcloud::PointCloud;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// The query that was asked and its parameters, the point is kept in the record.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+pub enum QueryKind {
+    /// A `knn` query for `k` neighbors
+    Knn {
+        /// Number of neighbors asked for
+        k: usize,
+    },
+    /// A `routing_knn` query for `k` neighbors
+    RoutingKnn {
+        /// Number of neighbors asked for
+        k: usize,
+    },
+    /// A `path` query
+    Path,
+}
+
+/// A single logged query.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct QueryRecord<T> {
+    /// When the query was answered, in microseconds since the unix epoch
+    pub timestamp_micros: u64,
+    /// The query and its parameters
+    pub kind: QueryKind,
+    /// The query vector
+    pub point: T,
+    /// How long the tree took to answer, in microseconds
+    pub latency_micros: u64,
+    /// If the query was answered without an error
+    pub ok: bool,
+}
+
+impl<T> QueryRecord<T> {
+    /// Records a query that started at `start` and just finished.
+    pub fn new(kind: QueryKind, point: T, start: Instant, ok: bool) -> Self {
+        let timestamp_micros = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_micros() as u64)
+            .unwrap_or(0);
+        QueryRecord {
+            timestamp_micros,
+            kind,
+            point,
+            latency_micros: start.elapsed().as_micros() as u64,
+            ok,
+        }
+    }
+}
+
+/// Where the query log goes and when it rotates.
+#[derive(Clone, Debug)]
+pub struct QueryLogConfig {
+    /// The directory the log files are written to
+    pub directory: PathBuf,
+    /// The log files are named `PREFIX.log`, `PREFIX.log.1`, ...
+    pub prefix: String,
+    /// The current file is rotated once it's at least this large
+    pub max_file_bytes: u64,
+    /// The number of rotated files kept, not counting the current one
+    pub max_files: usize,
+}
+
+impl QueryLogConfig {
+    /// A log in `directory` that rotates every 64MB and keeps 8 old files.
+    pub fn new<P: AsRef<Path>>(directory: P, prefix: &str) -> QueryLogConfig {
+        QueryLogConfig {
+            directory: directory.as_ref().to_path_buf(),
+            prefix: prefix.to_string(),
+            max_file_bytes: 64 * 1024 * 1024,
+            max_files: 8,
+        }
+    }
+
+    /// The path of the current file for `rotation == 0`, and of the `rotation`th most recent rotated file
+    /// otherwise.
+    pub fn file_path(&self, rotation: usize) -> PathBuf {
+        if rotation == 0 {
+            self.directory.join(format!("{}.log", self.prefix))
+        } else {
+            self.directory
+                .join(format!("{}.log.{}", self.prefix, rotation))
+        }
+    }
+
+    /// Every record still on disk, oldest first.
+    pub fn load_all<T: DeserializeOwned>(&self) -> io::Result<Vec<QueryRecord<T>>> {
+        let mut records = Vec::new();
+        for rotation in (0..=self.max_files).rev() {
+            let path = self.file_path(rotation);
+            if path.exists() {
+                records.extend(read_query_log(&path)?);
+            }
+        }
+        Ok(records)
+    }
+}
+
+/// Appends records to the current log file, rotating it when it gets too large.
+pub struct QueryLogWriter {
+    config: QueryLogConfig,
+    file: BufWriter<File>,
+    bytes_written: u64,
+}
+
+impl QueryLogWriter {
+    /// Opens the current log file, appending to it if it's already there.
+    pub fn new(config: QueryLogConfig) -> io::Result<QueryLogWriter> {
+        fs::create_dir_all(&config.directory)?;
+        let (file, bytes_written) = open_append(&config.file_path(0))?;
+        Ok(QueryLogWriter {
+            config,
+            file,
+            bytes_written,
+        })
+    }
+
+    /// The configuration this writer was made with.
+    pub fn config(&self) -> &QueryLogConfig {
+        &self.config
+    }
+
+    /// Appends a record. This is buffered, call `flush` to be sure it's on disk.
+    pub fn write<T: Serialize>(&mut self, record: &QueryRecord<T>) -> io::Result<()> {
+        let bytes =
+            rmp_serde::to_vec(record).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        self.file.write_all(&(bytes.len() as u32).to_le_bytes())?;
+        self.file.write_all(&bytes)?;
+        self.bytes_written += 4 + bytes.len() as u64;
+        if self.bytes_written >= self.config.max_file_bytes {
+            self.rotate()?;
+        }
+        Ok(())
+    }
+
+    /// Flushes the buffered records to the current file.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+
+    /// Moves the current file to `PREFIX.log.1`, shifting the older ones back and dropping the oldest, then starts a
+    /// new current file.
+    pub fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.config.max_files == 0 {
+            fs::remove_file(self.config.file_path(0))?;
+        } else {
+            let oldest = self.config.file_path(self.config.max_files);
+            if oldest.exists() {
+                fs::remove_file(oldest)?;
+            }
+            for rotation in (0..self.config.max_files).rev() {
+                let path = self.config.file_path(rotation);
+                if path.exists() {
+                    fs::rename(path, self.config.file_path(rotation + 1))?;
+                }
+            }
+        }
+        let (file, bytes_written) = open_append(&self.config.file_path(0))?;
+        self.file = file;
+        self.bytes_written = bytes_written;
+        Ok(())
+    }
+}
+
+impl Drop for QueryLogWriter {
+    fn drop(&mut self) {
+        let _ = self.file.flush();
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<(BufWriter<File>, u64)> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let len = file.metadata()?.len();
+    Ok((BufWriter::new(file), len))
+}
+
+/// Reads the records of one log file. A record cut off at the end of the file, from a server that died mid write, is
+/// ignored.
+pub fn read_query_log<T: DeserializeOwned, P: AsRef<Path>>(
+    path: P,
+) -> io::Result<Vec<QueryRecord<T>>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut records = Vec::new();
+    let mut len_bytes = [0u8; 4];
+    let mut buffer = Vec::new();
+    loop {
+        match reader.read_exact(&mut len_bytes) {
+            Ok(()) => (),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        }
+        buffer.resize(u32::from_le_bytes(len_bytes) as usize, 0);
+        match reader.read_exact(&mut buffer) {
+            Ok(()) => (),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        }
+        let record = rmp_serde::from_read_ref(&buffer)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        records.push(record);
+    }
+    Ok(records)
+}
+
+/// The latency of a query when it was logged, and when it was replayed.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug)]
+pub struct ReplayTiming {
+    /// The query that was replayed
+    pub kind: QueryKind,
+    /// The logged latency, in microseconds
+    pub original_latency_micros: u64,
+    /// The latency against the new tree, in microseconds
+    pub replay_latency_micros: u64,
+    /// If the replayed query returned an error
+    pub ok: bool,
+}
+
+/// Replays captured traffic against a tree, one query at a time, timing each.
+pub fn replay<D, T>(records: &[QueryRecord<T>], tree: &CoverTreeReader<D>) -> Vec<ReplayTiming>
+where
+    D: PointCloud,
+    T: Deref<Target = D::Point> + Send + Sync,
+{
+    records
+        .iter()
+        .map(|record| {
+            let start = Instant::now();
+            let result: Result<(), GokoError> = match record.kind {
+                QueryKind::Knn { k } => tree.knn(&record.point, k).map(|_| ()),
+                QueryKind::RoutingKnn { k } => tree.routing_knn(&record.point, k).map(|_| ()),
+                QueryKind::Path => tree.path(&record.point).map(|_| ()),
+            };
+            ReplayTiming {
+                kind: record.kind,
+                original_latency_micros: record.latency_micros,
+                replay_latency_micros: start.elapsed().as_micros() as u64,
+                ok: result.is_ok(),
+            }
+        })
+        .collect()
+}
+
+/// The mean latency of the logged queries and of their replay.
+pub fn mean_latencies(timings: &[ReplayTiming]) -> (Duration, Duration) {
+    if timings.is_empty() {
+        return (Duration::default(), Duration::default());
+    }
+    let original: u64 = timings.iter().map(|t| t.original_latency_micros).sum();
+    let replayed: u64 = timings.iter().map(|t| t.replay_latency_micros).sum();
+    let n = timings.len() as u64;
+    (
+        Duration::from_micros(original / n),
+        Duration::from_micros(replayed / n),
+    )
+}
@@ -141,3 +141,56 @@ impl Error for GokoClientError {
         }
     }
 }
+
+/// Errors from loading and swapping trees in a [`crate::core::catalog::TreeCatalog`].
+pub enum CatalogError {
+    /// There's no tree with this name in the catalog
+    UnknownTree(String),
+    /// A new version of this tree is already being loaded
+    AlreadyLoading(String),
+    /// The new version failed to load
+    Load(GokoError),
+    /// The new version failed validation
+    Invalid(String),
+    /// The background load panicked or was cancelled
+    Join(String),
+}
+
+impl From<GokoError> for CatalogError {
+    fn from(e: GokoError) -> CatalogError {
+        CatalogError::Load(e)
+    }
+}
+
+impl fmt::Display for CatalogError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            CatalogError::UnknownTree(ref name) => write!(f, "No tree named {} in the catalog", name),
+            CatalogError::AlreadyLoading(ref name) => write!(f, "A new version of {} is already loading", name),
+            CatalogError::Load(ref se) => fmt::Display::fmt(se, f),
+            CatalogError::Invalid(ref se) => write!(f, "The new tree failed validation: {}", se),
+            CatalogError::Join(ref se) => write!(f, "The background load failed: {}", se),
+        }
+    }
+}
+
+impl fmt::Debug for CatalogError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            CatalogError::UnknownTree(ref se) => write!(f, "UnknownTree({:?})", se),
+            CatalogError::AlreadyLoading(ref se) => write!(f, "AlreadyLoading({:?})", se),
+            CatalogError::Load(ref se) => write!(f, "Load({:?})", se),
+            CatalogError::Invalid(ref se) => write!(f, "Invalid({:?})", se),
+            CatalogError::Join(ref se) => write!(f, "Join({:?})", se),
+        }
+    }
+}
+
+impl Error for CatalogError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            CatalogError::Load(ref se) => Some(se),
+            _ => None,
+        }
+    }
+}
@@ -68,10 +68,21 @@ fn parse_tracker_query(uri: &Uri) -> (Option<String>, Option<usize>) {
     (tracker_name, window_size)
 }
 
+fn parse_event_id_query(uri: &Uri) -> Option<u64> {
+    lazy_static! {
+        static ref RE_EVENT_ID: Regex = Regex::new(r"event_id=(?P<event_id>\d+)").unwrap();
+    }
+    match uri.query().map(|s| RE_EVENT_ID.captures(s)).flatten() {
+        Some(caps) => caps["event_id"].parse::<u64>().ok(),
+        None => None,
+    }
+}
+
 pub(crate) async fn parse_http<P: PointParser>(request: Request<Body>, parser: &mut PointBuffer<P>) -> Result<GokoRequest<P::Point>, GokoClientError> {
     match (request.method(), request.uri().path()) {
         // Serve some instructions at /
         (&Method::GET, "/") => Ok(GokoRequest::Parameters(ParametersRequest)),
+        (&Method::GET, "/healthz") => Ok(GokoRequest::Health(HealthRequest)),
         (&Method::GET, "/knn") => {
             let k = parse_knn_query(request.uri());
             let point = parser.point(request).await?;
@@ -107,10 +118,12 @@ pub(crate) async fn parse_http<P: PointParser>(request: Request<Body>, parser: &
         }
         (&Method::POST, "/track/point") => {
             let (tracker_name, _window_size) = parse_tracker_query(request.uri());
+            let event_id = parse_event_id_query(request.uri());
             let point = parser.point(request).await?;
             let request = TrackingRequestChoice::TrackPoint(
                 TrackPointRequest {
                     point,
+                    event_id,
                 }
             );
             let tracking_request = TrackingRequest {
@@ -145,6 +158,12 @@ pub(crate) fn into_http<L: Summary + Serialize>(response: GokoResponse<L>) -> Re
     let mut builder = http::response::Builder::new();
     let json_str = match response {
         GokoResponse::Parameters(p) => serde_json::to_string(&p).unwrap(),
+        GokoResponse::Health(p) => {
+            if !p.ready {
+                builder = builder.status(503);
+            }
+            serde_json::to_string(&p).unwrap()
+        }
         GokoResponse::Knn(p) => serde_json::to_string(&p).unwrap(),
         GokoResponse::RoutingKnn(p) => serde_json::to_string(&p).unwrap(),
         GokoResponse::Path(p) => serde_json::to_string(&p).unwrap(),